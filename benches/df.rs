@@ -74,6 +74,7 @@ cfg_if::cfg_if! {
                     let _ = black_box(deblock_scu_hor_chroma(
                         &mut None,
                         &mut plane.as_region_mut(),
+                        2,
                         qp,
                         1,
                         tbl,
@@ -122,6 +123,7 @@ cfg_if::cfg_if! {
                     let _ = black_box(deblock_scu_ver_chroma(
                         &mut None,
                         &mut plane.as_region_mut(),
+                        2,
                         qp,
                         1,
                         tbl,
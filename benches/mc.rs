@@ -58,6 +58,7 @@ cfg_if::cfg_if! {
                         &mut pred.data,
                         cuw as i16,
                         cuh as i16,
+                        false,
                     ));
                 })
             });
@@ -83,6 +84,7 @@ cfg_if::cfg_if! {
                         &mut pred.data,
                         cuw as i16,
                         cuh as i16,
+                        false,
                     ));
                 })
             });
@@ -108,6 +110,7 @@ cfg_if::cfg_if! {
                         &mut pred.data,
                         cuw as i16,
                         cuh as i16,
+                        false,
                     ));
                 })
             });
@@ -133,6 +136,7 @@ cfg_if::cfg_if! {
                         &mut pred.data,
                         cuw as i16,
                         cuh as i16,
+                        false,
                     ));
                 })
             });
@@ -158,6 +162,7 @@ cfg_if::cfg_if! {
                         &mut pred.data,
                         cuw as i16,
                         cuh as i16,
+                        false,
                     ));
                 })
             });
@@ -183,6 +188,7 @@ cfg_if::cfg_if! {
                         &mut pred.data,
                         cuw as i16,
                         cuh as i16,
+                        false,
                     ));
                 })
             });
@@ -208,6 +214,7 @@ cfg_if::cfg_if! {
                         &mut pred.data,
                         cuw as i16,
                         cuh as i16,
+                        false,
                     ));
                 })
             });
@@ -233,6 +240,7 @@ cfg_if::cfg_if! {
                         &mut pred.data,
                         cuw as i16,
                         cuh as i16,
+                        false,
                     ));
                 })
             });
@@ -0,0 +1,522 @@
+use super::api::frame::*;
+use super::def::*;
+use super::plane::*;
+use super::region::*;
+use super::tbl::*;
+use super::util::*;
+
+use std::cmp::*;
+
+/* thresholds of the activity classes */
+static evc_tbl_alf_th: [u8; 16] = [0, 1, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 3, 3, 4];
+/* filter transposition for the main and secondary directions */
+static evc_tbl_alf_transpose: [u8; 8] = [0, 1, 0, 2, 2, 3, 1, 3];
+
+/* coefficient of each tap of the 7x7 diamond for a transposition */
+pub(crate) static evc_tbl_alf_transpose7: [[usize; MAX_NUM_ALF_LUMA_COEFF]; 4] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
+    [9, 4, 10, 8, 1, 5, 11, 7, 3, 0, 2, 6, 12],
+    [0, 3, 2, 1, 8, 7, 6, 5, 4, 9, 10, 11, 12],
+    [9, 8, 10, 4, 3, 7, 11, 5, 1, 0, 2, 6, 12],
+];
+
+/* sample positions (dx, dy) of the taps of the diamonds, each tap but the
+center one weights the sample and its point reflection */
+pub(crate) static evc_tbl_alf_taps7: [(isize, isize); MAX_NUM_ALF_LUMA_COEFF - 1] = [
+    (0, 3),
+    (1, 2),
+    (0, 2),
+    (-1, 2),
+    (2, 1),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-2, 1),
+    (3, 0),
+    (2, 0),
+    (1, 0),
+];
+pub(crate) static evc_tbl_alf_taps5: [(isize, isize); MAX_NUM_ALF_CHROMA_COEFF - 1] =
+    [(0, 2), (1, 1), (0, 1), (-1, 1), (2, 0), (1, 0)];
+
+/* samples outside of the LCU the filters use */
+pub(crate) const ALF_PAD: usize = 3;
+/* precision of the filter coefficients */
+pub(crate) const ALF_SHIFT: usize = 9;
+/* classification block size */
+pub(crate) const ALF_CLS_SIZE: usize = 4;
+
+/*****************************************************************************
+ * filters applied to a picture, derived from the ALF parameters
+ *****************************************************************************/
+#[derive(Clone, Default)]
+pub(crate) struct EvcAlfFilter {
+    /* luma filter of each class, none if luma is not filtered */
+    pub(crate) luma: Option<[[i16; MAX_NUM_ALF_LUMA_COEFF]; MAX_NUM_ALF_CLASSES]>,
+    /* Cb and Cr filters, none if the component is not filtered */
+    pub(crate) chroma: [Option<[i16; MAX_NUM_ALF_CHROMA_COEFF]>; 2],
+}
+
+impl EvcAlfFilter {
+    pub(crate) fn new(sh: &EvcSh, aps: &[EvcAlfParam], chroma_format_idc: u8) -> Self {
+        let mut filter = EvcAlfFilter::default();
+        if !sh.alf_on {
+            return filter;
+        }
+
+        /* a luma filter which is not signalled leaves the samples unchanged */
+        let param = &aps[sh.aps_id_y as usize];
+        if param.luma_signal_flag {
+            filter.luma = Some(evc_alf_luma_coeff(param));
+        }
+
+        if chroma_format_idc != 0 {
+            for i in 0..2 {
+                if sh.alf_chroma_idc & (1 << i) != 0 {
+                    let aps_id = if i == 1 && chroma_format_idc == 3 {
+                        sh.aps_id_ch2
+                    } else {
+                        sh.aps_id_ch
+                    };
+                    filter.chroma[i] = Some(evc_alf_chroma_coeff(&aps[aps_id as usize]));
+                }
+            }
+        }
+
+        filter
+    }
+
+    pub(crate) fn is_on(&self) -> bool {
+        self.luma.is_some() || self.chroma[0].is_some() || self.chroma[1].is_some()
+    }
+}
+
+/* luma filter of each class: the signalled coefficients are added to the
+fixed filter of the class, the center coefficient is derived */
+pub(crate) fn evc_alf_luma_coeff(
+    param: &EvcAlfParam,
+) -> [[i16; MAX_NUM_ALF_LUMA_COEFF]; MAX_NUM_ALF_CLASSES] {
+    let mut coeff = param.luma_coeff;
+    if param.coeff_delta_pred_mode_flag {
+        for f in 1..param.num_luma_filters as usize {
+            for i in 0..MAX_NUM_ALF_LUMA_COEFF - 1 {
+                coeff[f * MAX_NUM_ALF_LUMA_COEFF + i] = coeff[f * MAX_NUM_ALF_LUMA_COEFF + i]
+                    .wrapping_add(coeff[(f - 1) * MAX_NUM_ALF_LUMA_COEFF + i]);
+            }
+        }
+    }
+
+    let pattern_to_large = if param.luma_filter_type {
+        &evc_tbl_alf_pattern_to_large7
+    } else {
+        &evc_tbl_alf_pattern_to_large5
+    };
+
+    let mut coeff_final = [[0i16; MAX_NUM_ALF_LUMA_COEFF]; MAX_NUM_ALF_CLASSES];
+    for (c, cf) in coeff_final.iter_mut().enumerate() {
+        let fixed = if param.fixed_filter_usage_flag[c] {
+            let idx = evc_tbl_alf_class_to_filter[c][param.fixed_filter_idx[c] as usize];
+            evc_tbl_alf_fixed_coeff[idx as usize]
+        } else {
+            [0; MAX_NUM_ALF_LUMA_COEFF]
+        };
+        let base = param.filter_coeff_delta_idx[c] as usize * MAX_NUM_ALF_LUMA_COEFF;
+
+        let mut sum = 0i32;
+        for i in 0..MAX_NUM_ALF_LUMA_COEFF - 1 {
+            let mut v = fixed[i] as i32;
+            if pattern_to_large[i] > 0 {
+                v += coeff[base + pattern_to_large[i] as usize - 1] as i32;
+            }
+            cf[i] = v as i16;
+            sum += cf[i] as i32;
+        }
+        cf[MAX_NUM_ALF_LUMA_COEFF - 1] = ((1 << ALF_SHIFT) - 2 * sum) as i16;
+    }
+
+    coeff_final
+}
+
+pub(crate) fn evc_alf_chroma_coeff(param: &EvcAlfParam) -> [i16; MAX_NUM_ALF_CHROMA_COEFF] {
+    let mut coeff = param.chroma_coeff;
+    let sum: i32 = coeff[..MAX_NUM_ALF_CHROMA_COEFF - 1]
+        .iter()
+        .map(|&v| v as i32)
+        .sum();
+    coeff[MAX_NUM_ALF_CHROMA_COEFF - 1] = ((1 << ALF_SHIFT) - 2 * sum) as i16;
+    coeff
+}
+
+/*****************************************************************************
+ * samples of an LCU with the samples around it the filters use
+ *****************************************************************************/
+pub(crate) struct EvcAlfBlock {
+    pub(crate) buf: Vec<pel>,
+    pub(crate) stride: usize,
+    /* LCU size in the component */
+    pub(crate) w: usize,
+    pub(crate) h: usize,
+}
+
+impl EvcAlfBlock {
+    /* sample at (x, y) relative to the top-left sample of the LCU */
+    #[inline(always)]
+    pub(crate) fn p(&self, x: isize, y: isize) -> i32 {
+        self.buf[((y + ALF_PAD as isize) as usize) * self.stride + (x + ALF_PAD as isize) as usize]
+            as i32
+    }
+
+    #[inline(always)]
+    fn set(&mut self, x: isize, y: isize, v: pel) {
+        self.buf
+            [((y + ALF_PAD as isize) as usize) * self.stride + (x + ALF_PAD as isize) as usize] = v;
+    }
+
+    /* the samples outside of the tile are replicated from its boundary, while
+    they are mirrored across the LCU at the edges which are not filtered
+    across */
+    fn new(
+        src: &PlaneRegion<'_, pel>,
+        x: usize,
+        y: usize,
+        w: usize,
+        h: usize,
+        tile: (usize, usize, usize, usize),
+        edge: (bool, bool, bool, bool),
+    ) -> Self {
+        let (tx0, ty0, tx1, ty1) = tile;
+        let (left, top, right, bottom) = edge;
+        let mut blk = EvcAlfBlock {
+            buf: vec![0; (w + 2 * ALF_PAD) * (h + 2 * ALF_PAD)],
+            stride: w + 2 * ALF_PAD,
+            w,
+            h,
+        };
+        let fetch = |i: isize, j: isize| -> pel {
+            let xx = EVC_CLIP3(tx0 as isize, tx1 as isize - 1, x as isize + i) as usize;
+            let yy = EVC_CLIP3(ty0 as isize, ty1 as isize - 1, y as isize + j) as usize;
+            src[yy][xx]
+        };
+        let (w, h, pad) = (w as isize, h as isize, ALF_PAD as isize);
+
+        for j in 0..h {
+            for i in 0..w {
+                blk.set(i, j, fetch(i, j));
+            }
+            for k in 1..=pad {
+                let v = if left {
+                    blk.p(k, j) as pel
+                } else {
+                    fetch(-k, j)
+                };
+                blk.set(-k, j, v);
+            }
+            for k in 0..pad {
+                let v = if right {
+                    blk.p(w - 2 - k, j) as pel
+                } else {
+                    fetch(w + k, j)
+                };
+                blk.set(w + k, j, v);
+            }
+        }
+        for k in 1..=pad {
+            for i in -pad..w + pad {
+                let v = if top {
+                    blk.p(i, k) as pel
+                } else {
+                    fetch(i, -k)
+                };
+                blk.set(i, -k, v);
+            }
+        }
+        for k in 0..pad {
+            for i in -pad..w + pad {
+                let v = if bottom {
+                    blk.p(i, h - 2 - k) as pel
+                } else {
+                    fetch(i, h + k)
+                };
+                blk.set(i, h + k, v);
+            }
+        }
+
+        blk
+    }
+
+    /* class and filter transposition of each 4x4 luma block, derived from the
+    laplacians of the 8x8 window around it */
+    pub(crate) fn classify(&self) -> Vec<(u8, u8)> {
+        let w_cls = (self.w + ALF_CLS_SIZE - 1) / ALF_CLS_SIZE;
+        let h_cls = (self.h + ALF_CLS_SIZE - 1) / ALF_CLS_SIZE;
+        let mut cls = Vec::with_capacity(w_cls * h_cls);
+
+        for by in 0..h_cls as isize {
+            for bx in 0..w_cls as isize {
+                let (mut sum_v, mut sum_h, mut sum_d0, mut sum_d1) = (0i32, 0i32, 0i32, 0i32);
+                for j in 0..8 {
+                    for i in 0..8 {
+                        let x = bx * ALF_CLS_SIZE as isize - 2 + i;
+                        let y = by * ALF_CLS_SIZE as isize - 2 + j;
+                        let c = self.p(x, y) << 1;
+                        sum_v += (c - self.p(x, y - 1) - self.p(x, y + 1)).abs();
+                        sum_h += (c - self.p(x - 1, y) - self.p(x + 1, y)).abs();
+                        sum_d0 += (c - self.p(x - 1, y - 1) - self.p(x + 1, y + 1)).abs();
+                        sum_d1 += (c - self.p(x - 1, y + 1) - self.p(x + 1, y - 1)).abs();
+                    }
+                }
+
+                let activity = EVC_CLIP3(0, 15, (sum_v + sum_h) >> (BIT_DEPTH - 2));
+                let mut class_idx = evc_tbl_alf_th[activity as usize];
+
+                let (hv1, hv0, dir_hv) = if sum_v > sum_h {
+                    (sum_v, sum_h, 1)
+                } else {
+                    (sum_h, sum_v, 3)
+                };
+                let (d1, d0, dir_d) = if sum_d0 > sum_d1 {
+                    (sum_d0, sum_d1, 0)
+                } else {
+                    (sum_d1, sum_d0, 2)
+                };
+                let (hvd1, hvd0, main_dir, secondary_dir) =
+                    if d1.wrapping_mul(hv0) > hv1.wrapping_mul(d0) {
+                        (d1, d0, dir_d, dir_hv)
+                    } else {
+                        (hv1, hv0, dir_hv, dir_d)
+                    };
+
+                let mut strength = 0;
+                if hvd1 > 2 * hvd0 {
+                    strength = 1;
+                }
+                if hvd1 * 2 > 9 * hvd0 {
+                    strength = 2;
+                }
+                if strength > 0 {
+                    class_idx += (((main_dir & 1) << 1) + strength) * 5;
+                }
+
+                let transpose_idx =
+                    evc_tbl_alf_transpose[main_dir as usize * 2 + (secondary_dir as usize >> 1)];
+                cls.push((class_idx, transpose_idx));
+            }
+        }
+
+        cls
+    }
+}
+
+/* visit the LCUs of a component tile by tile, with the samples of each LCU
+and the ones around it the filters use */
+pub(crate) fn evc_alf_for_each_lcu<F: FnMut(usize, usize, usize, &EvcAlfBlock)>(
+    src: &Plane<pel>,
+    tiles: &[EvcTile],
+    loop_filter_across_tiles_enabled_flag: bool,
+    w_lcu: u16,
+    mut f: F,
+) {
+    let (xdec, ydec) = (src.cfg.xdec, src.cfg.ydec);
+    let (w, h) = (src.cfg.width, src.cfg.height);
+    let (lcu_w, lcu_h) = (MAX_CU_SIZE >> xdec, MAX_CU_SIZE >> ydec);
+    let region = src.as_region();
+
+    for tile in tiles {
+        let tx0 = tile.x_lcu as usize * lcu_w;
+        let ty0 = tile.y_lcu as usize * lcu_h;
+        let tx1 = min((tile.x_lcu + tile.w_lcu) as usize * lcu_w, w);
+        let ty1 = min((tile.y_lcu + tile.h_lcu) as usize * lcu_h, h);
+
+        for y_lcu in tile.y_lcu..tile.y_lcu + tile.h_lcu {
+            for x_lcu in tile.x_lcu..tile.x_lcu + tile.w_lcu {
+                let x = x_lcu as usize * lcu_w;
+                let y = y_lcu as usize * lcu_h;
+                let bw = min(lcu_w, tx1 - x);
+                let bh = min(lcu_h, ty1 - y);
+
+                /* with filtering across the tiles, only the picture boundaries
+                are mirrored at the left and the top */
+                let edge = if loop_filter_across_tiles_enabled_flag {
+                    (x == 0, y == 0, false, false)
+                } else {
+                    (x == tx0, y == ty0, x + bw == tx1, y + bh == ty1)
+                };
+
+                let blk = EvcAlfBlock::new(&region, x, y, bw, bh, (tx0, ty0, tx1, ty1), edge);
+                let lcu_num = x_lcu as usize + y_lcu as usize * w_lcu as usize;
+                f(lcu_num, x, y, &blk);
+            }
+        }
+    }
+}
+
+/* filter the samples of an LCU, the class of each 4x4 block selects its luma
+filter */
+fn evc_alf_filter_blk(
+    dst: &mut PlaneRegionMut<'_, pel>,
+    blk: &EvcAlfBlock,
+    x: usize,
+    y: usize,
+    luma: Option<&[[i16; MAX_NUM_ALF_LUMA_COEFF]; MAX_NUM_ALF_CLASSES]>,
+    chroma: &[i16; MAX_NUM_ALF_CHROMA_COEFF],
+) {
+    let cls = luma.map(|_| blk.classify());
+    let w_cls = (blk.w + ALF_CLS_SIZE - 1) / ALF_CLS_SIZE;
+    let offset = 1 << (ALF_SHIFT - 1);
+    let max_val = (1 << BIT_DEPTH) - 1;
+
+    for j in 0..blk.h {
+        for i in 0..blk.w {
+            let (ii, jj) = (i as isize, j as isize);
+            let mut sum = 0i32;
+            if let (Some(luma), Some(cls)) = (luma, &cls) {
+                let (class_idx, transpose_idx) = cls[(j / ALF_CLS_SIZE) * w_cls + i / ALF_CLS_SIZE];
+                let coeff = &luma[class_idx as usize];
+                let perm = &evc_tbl_alf_transpose7[transpose_idx as usize];
+                for (k, &(dx, dy)) in evc_tbl_alf_taps7.iter().enumerate() {
+                    sum +=
+                        coeff[perm[k]] as i32 * (blk.p(ii + dx, jj + dy) + blk.p(ii - dx, jj - dy));
+                }
+                sum += coeff[perm[MAX_NUM_ALF_LUMA_COEFF - 1]] as i32 * blk.p(ii, jj);
+            } else {
+                for (k, &(dx, dy)) in evc_tbl_alf_taps5.iter().enumerate() {
+                    sum += chroma[k] as i32 * (blk.p(ii + dx, jj + dy) + blk.p(ii - dx, jj - dy));
+                }
+                sum += chroma[MAX_NUM_ALF_CHROMA_COEFF - 1] as i32 * blk.p(ii, jj);
+            }
+            dst[y + j][x + i] = EVC_CLIP3(0, max_val, (sum + offset) >> ALF_SHIFT) as pel;
+        }
+    }
+}
+
+/* adaptive loop filter of a deblocked picture. map_alf tells the components
+filtered in each LCU */
+pub(crate) fn evc_alf(
+    filter: &EvcAlfFilter,
+    frame: &mut Frame<pel>,
+    tiles: &[EvcTile],
+    loop_filter_across_tiles_enabled_flag: bool,
+    map_alf: &[[bool; N_C]],
+    w_lcu: u16,
+) {
+    for c in 0..N_C {
+        let chroma = if c == Y_C {
+            if filter.luma.is_none() {
+                continue;
+            }
+            [0; MAX_NUM_ALF_CHROMA_COEFF]
+        } else if let Some(chroma) = filter.chroma[c - 1] {
+            chroma
+        } else {
+            continue;
+        };
+        let luma = if c == Y_C { filter.luma.as_ref() } else { None };
+
+        let plane = &mut frame.planes[c];
+        let src = plane.clone();
+        let mut dst = plane.as_region_mut();
+        evc_alf_for_each_lcu(
+            &src,
+            tiles,
+            loop_filter_across_tiles_enabled_flag,
+            w_lcu,
+            |lcu_num, x, y, blk| {
+                if map_alf[lcu_num][c] {
+                    evc_alf_filter_blk(&mut dst, blk, x, y, luma, &chroma);
+                }
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::ChromaSampling;
+
+    fn test_param() -> EvcAlfParam {
+        let mut param = EvcAlfParam {
+            luma_signal_flag: true,
+            num_luma_filters: 2,
+            coeff_delta_pred_mode_flag: true,
+            fixed_filter_pattern: 2,
+            ..Default::default()
+        };
+        for c in 0..MAX_NUM_ALF_CLASSES {
+            param.filter_coeff_delta_idx[c] = (c % 2) as u8;
+            param.fixed_filter_usage_flag[c] = c % 3 == 0;
+            param.fixed_filter_idx[c] = (c % 16) as u8;
+        }
+        for (i, v) in param.luma_coeff[..2 * MAX_NUM_ALF_LUMA_COEFF]
+            .iter_mut()
+            .enumerate()
+        {
+            *v = (i % 7) as i16 - 3;
+        }
+        param.chroma_coeff = [-2, 5, 7, -1, 3, 11, 0];
+        param
+    }
+
+    #[test]
+    fn filters_keep_dc_gain() {
+        let param = test_param();
+        let luma = evc_alf_luma_coeff(&param);
+        for cf in &luma {
+            let sum: i32 = cf[..MAX_NUM_ALF_LUMA_COEFF - 1]
+                .iter()
+                .map(|&v| v as i32)
+                .sum();
+            assert_eq!(
+                2 * sum + cf[MAX_NUM_ALF_LUMA_COEFF - 1] as i32,
+                1 << ALF_SHIFT
+            );
+        }
+
+        /* the second filter is predicted from the first one */
+        let c = 1;
+        assert!(!param.fixed_filter_usage_flag[c]);
+        for i in 0..MAX_NUM_ALF_LUMA_COEFF - 1 {
+            let k = evc_tbl_alf_pattern_to_large7[i] as usize;
+            if k > 0 {
+                let coeff = &param.luma_coeff;
+                assert_eq!(
+                    luma[c][i],
+                    coeff[k - 1] + coeff[MAX_NUM_ALF_LUMA_COEFF + k - 1]
+                );
+            }
+        }
+
+        let chroma = evc_alf_chroma_coeff(&param);
+        assert_eq!(chroma[MAX_NUM_ALF_CHROMA_COEFF - 1], 512 - 2 * 23);
+    }
+
+    #[test]
+    fn flat_picture_unchanged() {
+        let (w, h) = (MAX_CU_SIZE + 16, MAX_CU_SIZE);
+        let mut frame = Frame::new(w, h, ChromaSampling::Cs420);
+        for plane in frame.planes.iter_mut() {
+            plane.data_origin_mut().fill(300);
+        }
+        let param = test_param();
+        let filter = EvcAlfFilter {
+            luma: Some(evc_alf_luma_coeff(&param)),
+            chroma: [Some(evc_alf_chroma_coeff(&param)); 2],
+        };
+        let tiles = [EvcTile {
+            x_lcu: 0,
+            y_lcu: 0,
+            w_lcu: 2,
+            h_lcu: 1,
+            f_lcu: 2,
+        }];
+
+        evc_alf(&filter, &mut frame, &tiles, false, &[[true; N_C]; 2], 2);
+        for plane in &frame.planes {
+            for y in 0..plane.cfg.height {
+                for x in 0..plane.cfg.width {
+                    assert_eq!(plane.p(x, y), 300);
+                }
+            }
+        }
+    }
+}
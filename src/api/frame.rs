@@ -309,6 +309,11 @@ pub struct Frame<T: Pixel> {
     pub planes: [Plane<T>; N_C],
     pub chroma_sampling: ChromaSampling,
     pub ts: u64,
+    /* encoder only: keep the frame as long-term reference picture */
+    pub long_term_ref: bool,
+    /* decoder only: LCUs of the frame have been concealed, because a slice
+    was corrupted or missing */
+    pub concealed: bool,
     pub crop_l: i16,
     pub crop_r: i16,
     pub crop_t: i16,
@@ -317,29 +322,32 @@ pub struct Frame<T: Pixel> {
 
 impl<T: Pixel> Frame<T> {
     pub fn new(width: usize, height: usize, chroma_sampling: ChromaSampling) -> Self {
-        //TODO: support Monochrome
+        let chroma_plane = || {
+            if chroma_sampling == ChromaSampling::Cs400 {
+                /* monochrome pictures carry no chroma samples */
+                Plane::new(0, 0, 1, 1, 0, 0)
+            } else {
+                let (xdec, ydec) = chroma_sampling.get_decimation();
+                Plane::new(
+                    (width + xdec) >> xdec,
+                    (height + ydec) >> ydec,
+                    xdec,
+                    ydec,
+                    PIC_PAD_SIZE_L >> xdec,
+                    PIC_PAD_SIZE_L >> ydec,
+                )
+            }
+        };
         Frame {
             planes: [
                 Plane::new(width, height, 0, 0, PIC_PAD_SIZE_L, PIC_PAD_SIZE_L),
-                Plane::new(
-                    (width + 1) >> 1,
-                    (height + 1) >> 1,
-                    1,
-                    1,
-                    PIC_PAD_SIZE_C,
-                    PIC_PAD_SIZE_C,
-                ),
-                Plane::new(
-                    (width + 1) >> 1,
-                    (height + 1) >> 1,
-                    1,
-                    1,
-                    PIC_PAD_SIZE_C,
-                    PIC_PAD_SIZE_C,
-                ),
+                chroma_plane(),
+                chroma_plane(),
             ],
             chroma_sampling,
             ts: 0,
+            long_term_ref: false,
+            concealed: false,
             crop_l: 0,
             crop_r: 0,
             crop_t: 0,
@@ -1,5 +1,4 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::{Arc, RwLock};
 use std::vec::Vec;
 use std::{cmp, fmt, io};
 
@@ -47,6 +46,10 @@ impl Default for EvcError {
 }
 
 pub const NALU_SIZE_FIELD_IN_BYTES: usize = 4;
+/* start code prefix of a NALU in an Annex-B byte stream */
+pub const NALU_START_CODE: [u8; 4] = [0, 0, 0, 1];
+/* byte inserted after two zero bytes to prevent start code emulation */
+pub const NALU_EMULATION_PREVENTION_BYTE: u8 = 0x03;
 
 #[allow(dead_code, non_camel_case_types)]
 #[derive(Debug, FromPrimitive, ToPrimitive, PartialEq, PartialOrd, Clone, Copy)]
@@ -177,13 +180,22 @@ pub struct EvcStat {
 
     pub ret: usize,
 
+    // decoder only
+    /* LCUs have been concealed since the previous NALU, because a slice was
+    corrupted or the last slices of a picture were missing. the pictures
+    decoded on worker threads only report it on Frame::concealed */
+    pub concealed: bool,
+    /* result of the decoded picture hash SEI check of the last decoded
+    picture: None if the NALU is not a picture hash SEI */
+    pub hash_match: Option<bool>,
+
     // encoder only
     /* encoded sei messages byte size */
     pub sei_size: usize,
     /* picture number increased whenever encoding a frame */
     /* quantization parameter used for encoding */
     pub qp: u8,
-    pub rec: Option<Rc<RefCell<Frame<pel>>>>,
+    pub rec: Option<Arc<RwLock<Frame<pel>>>>,
     pub psnr: Option<[f64; N_C]>,
 }
 
@@ -192,13 +204,18 @@ pub const MAX_NUM_ACTIVE_REF_FRAME: usize = 5;
 pub const MAX_NUM_RPLS: usize = 32;
 
 /* rpl structure */
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct EvcRpl {
     pub poc: usize,
     pub tid: usize,
     pub ref_pic_num: u8,
     pub ref_pic_active_num: u8,
-    pub ref_pics: [u8; MAX_NUM_REF_PICS],
+    /* POC of the current picture minus the POC of each reference picture,
+    or the POC LSBs of a long-term reference picture */
+    pub ref_pics: [i32; MAX_NUM_REF_PICS],
+    pub ref_pic_lt: [bool; MAX_NUM_REF_PICS],
+    /* number of POC LSBs identifying the long-term reference pictures */
+    pub lt_poc_lsb_len: u8,
     pub pic_type: u8,
 }
 
@@ -229,11 +246,16 @@ impl Default for EvcChromaTable {
     }
 }
 
+/// Frames and packets exchanged with a `Context`.
+///
+/// All variants are `Send`, so data can be handed to other threads. A
+/// `RefFrame` returned by `pull` stays valid while it is held: the codec
+/// gives its picture buffer a new frame instead of overwriting it.
 pub enum Data {
     Empty,
-    RefFrame(Rc<RefCell<Frame<pel>>>),
+    RefFrame(Arc<RwLock<Frame<pel>>>),
     Frame(Option<Frame<pel>>),
-    RefPacket(Rc<RefCell<Packet>>),
+    RefPacket(Arc<RwLock<Packet>>),
     Packet(Option<Packet>),
 }
 
@@ -287,6 +309,12 @@ impl ChromaSampling {
             Cs400 => (2, 2),
         }
     }
+
+    // Provides the shifts from the luma to the chroma dimensions in the horizontal and vertical axes.
+    pub fn get_decimation(self) -> (usize, usize) {
+        let (x, y) = self.sampling_period();
+        (x >> 1, y >> 1)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, FromPrimitive)]
@@ -386,11 +414,111 @@ pub enum InvalidConfig {
     #[error("Invalid Hierarchical GOP")]
     InvalidHierarchicalGOP,
 
+    /// The QP range of the bitrate mode is invalid.
+    #[error("invalid qp range {min}..={max} (expected <= 51)")]
+    InvalidQPRange {
+        /// The minimal QP.
+        min: u8,
+        /// The maximal QP.
+        max: u8,
+    },
+
+    /// The number of slices in a picture is invalid.
+    #[error("invalid number of slices in a picture {actual} (expected <= {max})")]
+    InvalidNumSlicesInPic {
+        /// The actual value.
+        actual: usize,
+        /// The maximal supported value, one slice per LCU row.
+        max: usize,
+    },
+
+    /// Long-term reference pictures are identified in the explicitly signalled
+    /// reference picture lists by their POC LSBs.
+    #[error("long-term reference pictures require rpl and pocs")]
+    InvalidLongTermRef,
+
     /// The rate control needs a target bitrate in order to produce results
     #[error("The rate control requires a target bitrate")]
     TargetBitrateNeeded,
 }
 
+/// Rate control mode of the encoder.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub enum RateControlMode {
+    /// Every picture is encoded with the fixed `qp` (plus hierarchical GOP offsets).
+    ConstantQP,
+    /// The QP is chosen per picture to hit `bitrate` on average.
+    AverageBitrate,
+    /// Like `AverageBitrate`, but also keeps a VBV buffer from under-flowing.
+    ConstantBitrate,
+}
+
+impl Default for RateControlMode {
+    fn default() -> Self {
+        RateControlMode::ConstantQP
+    }
+}
+
+/// Pass of a multi-pass rate control.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub enum RateControlPass {
+    /// Single pass encoding.
+    Single,
+    /// First pass: statistics are handed out by `Context::rc_receive_pass_data`.
+    First,
+    /// Second pass: statistics are fed by `Context::rc_send_pass_data`.
+    Second,
+}
+
+impl Default for RateControlPass {
+    fn default() -> Self {
+        RateControlPass::Single
+    }
+}
+
+/// Decoded picture hash carried in a SEI message after every picture.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub enum PictureHash {
+    /// No SEI message is written.
+    None,
+    /// MD5 of every plane, as written by the reference software.
+    MD5,
+    /// 16-bit CRC of every plane.
+    CRC,
+    /// 32-bit checksum of every plane.
+    Checksum,
+}
+
+impl Default for PictureHash {
+    fn default() -> Self {
+        PictureHash::None
+    }
+}
+
+impl PictureHash {
+    /* number of bytes of the hash of a plane */
+    pub(crate) fn size(self) -> usize {
+        match self {
+            PictureHash::None => 0,
+            PictureHash::MD5 => 16,
+            PictureHash::CRC => 2,
+            PictureHash::Checksum => 4,
+        }
+    }
+
+    pub(crate) fn from_size(size: usize) -> Self {
+        match size {
+            16 => PictureHash::MD5,
+            2 => PictureHash::CRC,
+            4 => PictureHash::Checksum,
+            _ => PictureHash::None,
+        }
+    }
+}
+
 // We add 1 to rdo_lookahead_frames in a bunch of places.
 pub(crate) const MAX_RDO_LOOKAHEAD_FRAMES: usize = usize::max_value() - 1;
 // Due to the math in RCState::new() regarding the reservoir frame delay.
@@ -426,6 +554,13 @@ pub struct EncoderConfig {
     pub max_qp: u8,
     // The target bitrate for the bitrate mode.
     pub bitrate: i32,
+    // The rate control mode.
+    pub rc_mode: RateControlMode,
+    // The VBV buffer size in bits for the bitrate mode
+    // (0: one second of `bitrate` in CBR, no VBV constraint in ABR).
+    pub vbv_buffer_size: i32,
+    // The pass of the two-pass rate control.
+    pub rc_pass: RateControlPass,
 
     pub cb_qp_offset: i8,
     pub cr_qp_offset: i8,
@@ -437,8 +572,59 @@ pub struct EncoderConfig {
     pub level: u8,
     pub enable_cip: bool,
     pub disable_dbf: bool,
+    // Binary and ternary splits of the coding tree (main profile).
+    pub btt: bool,
+    // Right-to-left split unit coding order (main profile).
+    pub suco: bool,
+    // Adaptive loop filter with the filters signalled in APS (main profile).
+    pub alf: bool,
+    // Advanced deblocking filter with slice offsets of its thresholds
+    // (main profile).
+    pub addb: bool,
+    // Merge and AMVP candidate lists of the advanced motion vector
+    // prediction (main profile).
+    pub admvp: bool,
+    // History-based motion vector prediction, requires admvp.
+    pub hmvp: bool,
+    // Affine motion compensation with affine merge and AMVP, requires admvp.
+    pub affine: bool,
+    // Decoder-side refinement of the motion of bi-predicted merge CUs,
+    // requires admvp.
+    pub dmvr: bool,
+    // Adaptive motion vector resolution of AMVP CUs from 1/4 to 4 samples,
+    // requires admvp.
+    pub amvr: bool,
+    // Merge with motion vector difference, requires admvp.
+    pub mmvd: bool,
+    // Extended intra prediction with 33 luma modes (main profile).
+    pub eipd: bool,
+    // Improved quantization and inverse transform (main profile).
+    pub iqt: bool,
+    // Adaptive DST-7/DCT-8 transform selection of intra and inter residuals,
+    // requires iqt.
+    pub ats: bool,
+    // Initialisation of the contexts from the slice QP and type (main
+    // profile).
+    pub cm_init: bool,
+    // Context-adaptive coding of the coefficients, requires cm_init (main
+    // profile).
+    pub adcc: bool,
+    // Reference picture lists signalled in the slice headers instead of
+    // derived from the temporal layers (main profile).
+    pub rpl: bool,
+    // POC LSBs signalled in the slice headers instead of derived from the
+    // sub-GOP structure (main profile).
+    pub pocs: bool,
+    // Long-term reference pictures, pinned with Frame::long_term_ref, requires
+    // rpl and pocs.
+    pub ltr: bool,
     pub num_slices_in_pic: usize,
     pub inter_slice_type: SliceType,
+    // Write start code prefixed NALUs (Annex-B byte stream) instead of
+    // length prefixed ones.
+    pub annexb: bool,
+    // Decoded picture hash written in a SEI message after every picture.
+    pub picture_hash: PictureHash,
 
     // Number of frames to read ahead for the RDO lookahead computation.
     pub rdo_lookahead_frames: usize,
@@ -463,7 +649,7 @@ impl EncoderConfig {
             return Err(InvalidHeight(config.height));
         }
 
-        if config.qp < MIN_QUANT || config.qp > MAX_QUANT {
+        if config.qp > MAX_QUANT {
             return Err(InvalidQP {
                 actual: config.qp,
                 max: MAX_QUANT,
@@ -531,12 +717,40 @@ impl EncoderConfig {
             }
         }
 
-        // TODO: add more validation
-        /*let rc = &self.rate_control;
+        /* every slice is a tile of one or more LCU rows */
+        let max_num_slices_in_pic = cmp::min(
+            (config.height + MAX_CU_SIZE - 1) / MAX_CU_SIZE,
+            MAX_NUM_TILES_ROW,
+        );
+        if config.num_slices_in_pic > max_num_slices_in_pic {
+            return Err(InvalidNumSlicesInPic {
+                actual: config.num_slices_in_pic,
+                max: max_num_slices_in_pic,
+            });
+        }
+
+        if config.ltr && !(config.rpl && config.pocs) {
+            return Err(InvalidLongTermRef);
+        }
 
-        if (rc.emit_pass_data || rc.summary.is_some()) && config.bitrate == 0 {
+        if config.rc_pass != RateControlPass::Single
+            && config.rc_mode == RateControlMode::ConstantQP
+        {
             return Err(TargetBitrateNeeded);
-        }*/
+        }
+        if config.rc_mode != RateControlMode::ConstantQP {
+            if config.bitrate <= 0 {
+                return Err(TargetBitrateNeeded);
+            }
+            if config.min_qp > config.max_qp || config.max_qp > MAX_QUANT {
+                return Err(InvalidQPRange {
+                    min: config.min_qp,
+                    max: config.max_qp,
+                });
+            }
+        }
+
+        // TODO: add more validation
 
         Ok(())
     }
@@ -546,6 +760,11 @@ impl EncoderConfig {
 #[derive(Clone, Copy, Debug, Default)]
 pub struct Config {
     /// The number of threads in the threadpool.
+    ///
+    /// The encoder encodes the slices of a picture on up to `threads` threads,
+    /// including the calling one.
+    /// The decoder decodes non-reference pictures on up to `threads - 1`
+    /// worker threads; 0 or 1 decodes everything on the calling thread.
     pub threads: usize,
 
     /// Encoder configuration (optional)
@@ -555,6 +774,7 @@ pub struct Config {
 pub struct DecoderContext(EvcdCtx);
 pub struct EncoderContext(EvceCtx);
 
+/// Encoder or decoder instance; `Send`, so it can be moved to a worker thread.
 pub enum Context {
     Invalid(InvalidConfig),
     Decoder(DecoderContext),
@@ -600,6 +820,7 @@ impl Context {
             Context::Decoder(ctx) => {
                 let mut stat = None;
                 let mut pull_frm = false;
+                let mut flush = false;
                 match ctx.0.decode_nalu() {
                     Ok(st) => {
                         pull_frm = st.fnum >= 0;
@@ -608,12 +829,17 @@ impl Context {
                     Err(err) => {
                         if err == EvcError::EVC_OK_FLUSH {
                             pull_frm = true;
+                            flush = true;
+                        } else {
+                            /* the NALU is dropped, decoding can go on with
+                            the next one */
+                            return Err(err);
                         }
                     }
                 }
 
                 if pull_frm {
-                    match ctx.0.pull_frm() {
+                    match ctx.0.pull_frm(flush) {
                         Ok(frame) => *data = Data::RefFrame(frame),
                         Err(err) => {
                             if err != EvcError::EVC_OK_OUTPUT_NOT_AVAILABLE {
@@ -650,4 +876,593 @@ impl Context {
             Context::Invalid(_) => Err(EvcError::EVC_ERR_UNSUPPORTED),
         }
     }
+
+    /// Returns the first pass statistics of the frames encoded since the last call.
+    pub fn rc_receive_pass_data(&mut self) -> Option<Vec<u8>> {
+        match self {
+            Context::Encoder(ctx) => ctx.0.rc_receive_pass_data(),
+            _ => None,
+        }
+    }
+
+    /// Feeds the first pass statistics to a second pass encoder before any frame is encoded.
+    pub fn rc_send_pass_data(&mut self, data: &[u8]) -> Result<(), EvcError> {
+        match self {
+            Context::Encoder(ctx) => ctx.0.rc_send_pass_data(data),
+            _ => Err(EvcError::EVC_ERR_UNSUPPORTED),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_send<T: Send>() {}
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn api_is_send_sync() {
+        assert_send::<Context>();
+        assert_send_sync::<Data>();
+        assert_send_sync::<EvcStat>();
+        assert_send_sync::<Frame<pel>>();
+        assert_send_sync::<Packet>();
+    }
+
+    fn cqp_config(qp: u8) -> EncoderConfig {
+        EncoderConfig {
+            width: 64,
+            height: 64,
+            time_base: Rational::new(1, 30),
+            max_key_frame_interval: 16,
+            ref_pic_gap_length: 1,
+            qp,
+            ..Default::default()
+        }
+    }
+
+    fn small_config() -> EncoderConfig {
+        EncoderConfig {
+            width: 64,
+            height: 64,
+            fps: 30,
+            bit_depth: 8,
+            inter_slice_type: SliceType::EVC_ST_B,
+            picture_hash: PictureHash::MD5,
+            ..cqp_config(32)
+        }
+    }
+
+    fn test_frame(cfg: &EncoderConfig, t: usize) -> Frame<pel> {
+        let mut frame = Frame::new(cfg.width, cfg.height, cfg.chroma_sampling);
+        /* monochrome frames have empty chroma planes */
+        let num_planes = if cfg.chroma_sampling == ChromaSampling::Cs400 {
+            1
+        } else {
+            N_C
+        };
+        for (c, plane) in frame.planes.iter_mut().take(num_planes).enumerate() {
+            let (w, h, stride) = (plane.cfg.width, plane.cfg.height, plane.cfg.stride);
+            for (y, row) in plane
+                .data_origin_mut()
+                .chunks_mut(stride)
+                .take(h)
+                .enumerate()
+            {
+                for (x, p) in row[..w].iter_mut().enumerate() {
+                    let v = (x * (c + t + 3) + y * (t + 5)) ^ (x * y >> (t % 3 + 2));
+                    *p = ((v & 0xff) << 2) as pel;
+                }
+            }
+        }
+        frame
+    }
+
+    /* encodes the frames and returns the NALUs and the reconstructed frames in
+    output order */
+    fn encode(cfg: EncoderConfig, frames: usize) -> (Vec<Vec<u8>>, Vec<Frame<pel>>) {
+        let (nalus, recs, _) = encode_with(cfg, frames, 1, None);
+        (nalus, recs)
+    }
+
+    /* like encode, on threads and with the frame long_term_ref pinned as
+    long-term reference picture. also returns the POC and the list 0 POCs of
+    every picture */
+    fn encode_with(
+        cfg: EncoderConfig,
+        frames: usize,
+        threads: usize,
+        long_term_ref: Option<usize>,
+    ) -> (Vec<Vec<u8>>, Vec<Frame<pel>>, Vec<(isize, Vec<isize>)>) {
+        let mut ctx = Context::new(&Config {
+            threads,
+            enc: Some(cfg),
+        });
+        let (mut nalus, mut recs, mut refs) = (vec![], vec![], vec![]);
+        let mut t = 0;
+        loop {
+            if t < frames {
+                let mut frame = test_frame(&cfg, t);
+                frame.ts = t as u64;
+                frame.long_term_ref = long_term_ref == Some(t);
+                assert!(ctx.push(&mut Data::Frame(Some(frame))).is_ok());
+                t += 1;
+            }
+            let mut data = Data::Empty;
+            match ctx.pull(&mut data) {
+                Ok(Some(stat)) => {
+                    if stat.fnum >= 0 {
+                        let num = stat.refpic_num[REFP_0] as usize;
+                        refs.push((stat.poc, stat.refpic[REFP_0][..num].to_vec()));
+                    }
+                    if let Some(rec) = stat.rec {
+                        recs.push(rec.read().unwrap().clone());
+                    }
+                    if let Data::RefPacket(pkt) = data {
+                        let pkt = pkt.read().unwrap();
+                        let mut bytes = &pkt.data[..];
+                        while !bytes.is_empty() {
+                            let len = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+                                as usize;
+                            nalus.push(bytes[4..4 + len].to_vec());
+                            bytes = &bytes[4 + len..];
+                        }
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    assert_eq!(err, EvcError::EVC_OK_NO_MORE_OUTPUT);
+                    break;
+                }
+            }
+        }
+        recs.sort_by_key(|f| f.ts);
+        (nalus, recs, refs)
+    }
+
+    /* decodes the NALUs and returns the decoded frames and the results of the
+    picture hash checks */
+    fn decode(nalus: Vec<Vec<u8>>, threads: usize) -> (Vec<Frame<pel>>, Vec<bool>) {
+        let mut ctx = Context::new(&Config { threads, enc: None });
+        let (mut frames, mut hashes) = (vec![], vec![]);
+        let mut nalus = nalus.into_iter();
+        loop {
+            if let Some(data) = nalus.next() {
+                let mut pkt = Data::Packet(Some(Packet { data, ts: 0 }));
+                assert!(ctx.push(&mut pkt).is_ok());
+            }
+            let mut data = Data::Empty;
+            match ctx.pull(&mut data) {
+                Ok(stat) => {
+                    if let Some(hash_match) = stat.and_then(|stat| stat.hash_match) {
+                        hashes.push(hash_match);
+                    }
+                    if let Data::RefFrame(frame) = data {
+                        frames.push(frame.read().unwrap().clone());
+                    }
+                }
+                Err(err) => {
+                    assert_eq!(err, EvcError::EVC_OK_NO_MORE_OUTPUT);
+                    break;
+                }
+            }
+        }
+        (frames, hashes)
+    }
+
+    fn assert_same_frames(a: &[Frame<pel>], b: &[Frame<pel>]) {
+        assert_eq!(a.len(), b.len());
+        for (fa, fb) in a.iter().zip(b.iter()) {
+            for (pa, pb) in fa.planes.iter().zip(fb.planes.iter()) {
+                assert_eq!(pa.cfg.width, pb.cfg.width);
+                assert_eq!(pa.cfg.height, pb.cfg.height);
+                for y in 0..pa.cfg.height {
+                    for x in 0..pa.cfg.width {
+                        assert_eq!(pa.p(x, y), pb.p(x, y), "sample ({}, {})", x, y);
+                    }
+                }
+            }
+        }
+    }
+
+    /* encodes the frames, checks that they decode to the reconstructed frames
+    and returns the NALUs and the reconstructed frames */
+    fn round_trip(cfg: EncoderConfig, frames: usize) -> (Vec<Vec<u8>>, Vec<Frame<pel>>) {
+        assert!(cfg.validate().is_ok());
+        let (nalus, recs) = encode(cfg, frames);
+        assert_eq!(recs.len(), frames);
+        /* pictures and slices are decoded on worker threads with threads > 1 */
+        for threads in [1, 4] {
+            let (decoded, hashes) = decode(nalus.clone(), threads);
+            assert_same_frames(&recs, &decoded);
+            assert!(decoded.iter().all(|frame| !frame.concealed));
+            if cfg.picture_hash != PictureHash::None {
+                assert_eq!(hashes.len(), frames);
+                assert!(hashes.iter().all(|&hash_match| hash_match));
+            }
+        }
+        (nalus, recs)
+    }
+
+    /* decodes the NALUs with the reference decoder of scripts/, when it can
+    run here, and checks that it reconstructs the same 10-bit samples */
+    fn assert_reference_decoding(name: &str, nalus: &[Vec<u8>], recs: &[Frame<pel>]) {
+        let decoder = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("scripts/evca_decoder");
+        if !cfg!(target_os = "linux") || !decoder.exists() {
+            return;
+        }
+
+        let tmp = std::env::temp_dir();
+        let id = format!("revc_{}_{}", name, std::process::id());
+        let (bitstream, output) = (tmp.join(id.clone() + ".evc"), tmp.join(id + ".yuv"));
+        let mut data = vec![];
+        for nalu in nalus {
+            data.extend_from_slice(&(nalu.len() as u32).to_le_bytes());
+            data.extend_from_slice(nalu);
+        }
+        std::fs::write(&bitstream, data).unwrap();
+        let status = std::process::Command::new(&decoder)
+            .arg("-i")
+            .arg(&bitstream)
+            .arg("-o")
+            .arg(&output)
+            .args(["--output_bit_depth", "10", "-s", "-v", "0"])
+            .status()
+            .unwrap();
+        let yuv = std::fs::read(&output).unwrap_or_default();
+        let _ = std::fs::remove_file(&bitstream);
+        let _ = std::fs::remove_file(&output);
+        assert!(status.success(), "{}: reference decoder failed", name);
+
+        let mut samples = yuv
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as pel);
+        for (i, rec) in recs.iter().enumerate() {
+            for (c, plane) in rec.planes.iter().enumerate() {
+                for y in 0..plane.cfg.height {
+                    for x in 0..plane.cfg.width {
+                        assert_eq!(
+                            samples.next(),
+                            Some(plane.p(x, y)),
+                            "{}: frame {} plane {} sample ({}, {})",
+                            name,
+                            i,
+                            c,
+                            x,
+                            y
+                        );
+                    }
+                }
+            }
+        }
+        assert_eq!(samples.next(), None, "{}: extra samples", name);
+    }
+
+    #[test]
+    fn cm_init_reference_decoding() {
+        let mut cfg = small_config();
+        cfg.cm_init = true;
+        let (nalus, recs) = round_trip(cfg, 4);
+        assert_reference_decoding("cm_init", &nalus, &recs);
+
+        cfg.admvp = true;
+        cfg.affine = true;
+        cfg.eipd = true;
+        let (nalus, recs) = round_trip(cfg, 4);
+        assert_reference_decoding("cm_init_admvp", &nalus, &recs);
+    }
+
+    #[test]
+    fn adcc_reference_decoding() {
+        let mut cfg = small_config();
+        cfg.adcc = true;
+        let (nalus, recs) = round_trip(cfg, 4);
+        assert_reference_decoding("adcc", &nalus, &recs);
+
+        cfg.qp = 12;
+        cfg.btt = true;
+        cfg.admvp = true;
+        let (nalus, recs) = round_trip(cfg, 4);
+        assert_reference_decoding("adcc_btt", &nalus, &recs);
+    }
+
+    #[test]
+    fn iqt_reference_decoding() {
+        let mut cfg = small_config();
+        cfg.iqt = true;
+        let (nalus, recs) = round_trip(cfg, 4);
+        assert_reference_decoding("iqt", &nalus, &recs);
+
+        cfg.qp = 12;
+        cfg.adcc = true;
+        cfg.btt = true;
+        let (nalus, recs) = round_trip(cfg, 4);
+        assert_reference_decoding("iqt_adcc", &nalus, &recs);
+    }
+
+    #[test]
+    fn ats_reference_decoding() {
+        let mut cfg = small_config();
+        cfg.ats = true;
+        let (nalus, recs) = round_trip(cfg, 4);
+        assert_reference_decoding("ats", &nalus, &recs);
+
+        cfg.cm_init = true;
+        cfg.btt = true;
+        cfg.admvp = true;
+        let (nalus, recs) = round_trip(cfg, 4);
+        assert_reference_decoding("ats_btt", &nalus, &recs);
+    }
+
+    #[test]
+    fn admvp_reference_decoding() {
+        for hmvp in [false, true] {
+            let mut cfg = small_config();
+            cfg.admvp = true;
+            cfg.hmvp = hmvp;
+            let (nalus, recs) = round_trip(cfg, 4);
+            assert_reference_decoding(&format!("admvp_{}", hmvp), &nalus, &recs);
+        }
+    }
+
+    #[test]
+    fn addb_reference_decoding() {
+        /* low delay and hierarchical B pictures */
+        for (max_b_frames, ref_pic_gap_length) in [(0, 1), (3, 0)] {
+            let mut cfg = small_config();
+            cfg.addb = true;
+            cfg.max_b_frames = max_b_frames;
+            cfg.ref_pic_gap_length = ref_pic_gap_length;
+            let (nalus, recs) = round_trip(cfg, 4);
+            assert_reference_decoding(&format!("addb_{}", max_b_frames), &nalus, &recs);
+        }
+
+        let mut cfg = small_config();
+        cfg.addb = true;
+        cfg.ats = true;
+        cfg.cb_qp_offset = -5;
+        cfg.cr_qp_offset = 6;
+        let (nalus, recs) = round_trip(cfg, 4);
+        assert_reference_decoding("addb_ats", &nalus, &recs);
+    }
+
+    #[test]
+    fn long_term_ref_decoding() {
+        /* low delay and hierarchical B pictures, the pinned picture has left
+        the short-term references of the last ones */
+        for (max_b_frames, ref_pic_gap_length, frames) in [(0, 1, 8), (3, 0, 21)] {
+            let mut cfg = small_config();
+            cfg.rpl = true;
+            cfg.pocs = true;
+            cfg.ltr = true;
+            cfg.max_b_frames = max_b_frames;
+            cfg.ref_pic_gap_length = ref_pic_gap_length;
+            assert!(cfg.validate().is_ok());
+            let (nalus, recs, refs) = encode_with(cfg, frames, 1, Some(0));
+            for (poc, l0) in &refs {
+                assert!(*poc == 0 || l0.contains(&0), "POC {} refers {:?}", poc, l0);
+            }
+
+            let (decoded, hashes) = decode(nalus.clone(), 1);
+            assert_same_frames(&recs, &decoded);
+            assert!(hashes.iter().all(|&hash_match| hash_match));
+            assert_reference_decoding(&format!("ltr_{}", max_b_frames), &nalus, &recs);
+        }
+    }
+
+    #[test]
+    fn concealed_frame() {
+        for num_slices_in_pic in [1, 2] {
+            let mut cfg = small_config();
+            cfg.height = 192;
+            cfg.num_slices_in_pic = num_slices_in_pic;
+            cfg.picture_hash = PictureHash::None;
+            let (mut nalus, _) = encode(cfg, 3);
+
+            /* the last slice of the stream loses its end */
+            let nalu_type = |nalu: &Vec<u8>| ((nalu[0] >> 1) & 0x3f) as i32 - 1;
+            let last_slice = nalus
+                .iter_mut()
+                .rev()
+                .find(|nalu| nalu_type(nalu) <= NaluType::EVC_IDR_NUT as i32)
+                .unwrap();
+            last_slice.truncate(8);
+
+            for threads in [1, 4] {
+                let (decoded, _) = decode(nalus.clone(), threads);
+                assert_eq!(decoded.len(), 3);
+                assert_eq!(decoded.iter().filter(|frame| frame.concealed).count(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn alf_reference_decoding() {
+        let mut cfg = small_config();
+        cfg.alf = true;
+        let (nalus, recs) = round_trip(cfg, 4);
+        let nalu_type = |nalu: &Vec<u8>| ((nalu[0] >> 1) & 0x3f) as i32 - 1;
+        assert!(nalus
+            .iter()
+            .any(|nalu| nalu_type(nalu) == NaluType::EVC_APS_NUT as i32));
+        assert_reference_decoding("alf", &nalus, &recs);
+    }
+
+    /* the bundled reference decoder only decodes 4:2:0, the other chroma
+    formats are checked against our own decoder */
+    #[test]
+    fn mono_round_trip() {
+        let mut cfg = small_config();
+        cfg.chroma_sampling = ChromaSampling::Cs400;
+        cfg.alf = true;
+        round_trip(cfg, 4);
+    }
+
+    #[test]
+    fn alf_422_round_trip() {
+        let mut cfg = small_config();
+        cfg.chroma_sampling = ChromaSampling::Cs422;
+        cfg.alf = true;
+        round_trip(cfg, 4);
+    }
+
+    #[test]
+    fn alf_444_round_trip() {
+        let mut cfg = small_config();
+        cfg.chroma_sampling = ChromaSampling::Cs444;
+        cfg.alf = true;
+        round_trip(cfg, 4);
+    }
+
+    #[test]
+    fn btt_reference_decoding() {
+        for cm_init in [false, true] {
+            let mut cfg = small_config();
+            cfg.btt = true;
+            cfg.cm_init = cm_init;
+            let (nalus, recs) = round_trip(cfg, 3);
+            assert_reference_decoding(&format!("btt_{}", cm_init), &nalus, &recs);
+        }
+    }
+
+    #[test]
+    fn btt_suco_reference_decoding() {
+        for cm_init in [false, true] {
+            let mut cfg = small_config();
+            cfg.btt = true;
+            cfg.suco = true;
+            cfg.admvp = true;
+            cfg.cm_init = cm_init;
+            let (nalus, recs) = round_trip(cfg, 3);
+            assert_reference_decoding(&format!("btt_suco_{}", cm_init), &nalus, &recs);
+        }
+    }
+
+    #[test]
+    fn amvr_reference_decoding() {
+        let mut cfg = small_config();
+        cfg.admvp = true;
+        cfg.amvr = true;
+        let (nalus, recs) = round_trip(cfg, 4);
+        assert_reference_decoding("amvr", &nalus, &recs);
+    }
+
+    #[test]
+    fn mmvd_reference_decoding() {
+        for inter_slice_type in [SliceType::EVC_ST_B, SliceType::EVC_ST_P] {
+            for dmvr in [false, true] {
+                let mut cfg = small_config();
+                cfg.inter_slice_type = inter_slice_type;
+                cfg.admvp = true;
+                cfg.mmvd = true;
+                cfg.dmvr = dmvr;
+                let (nalus, recs) = round_trip(cfg, 4);
+                assert_reference_decoding(
+                    &format!("mmvd_{:?}_{}", inter_slice_type, dmvr),
+                    &nalus,
+                    &recs,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn eipd_reference_decoding() {
+        let mut cfg = small_config();
+        cfg.eipd = true;
+        let (nalus, recs) = round_trip(cfg, 3);
+        assert_reference_decoding("eipd", &nalus, &recs);
+    }
+
+    #[test]
+    fn affine_reference_decoding() {
+        let mut cfg = small_config();
+        cfg.admvp = true;
+        cfg.affine = true;
+        let (nalus, recs) = round_trip(cfg, 4);
+        assert_reference_decoding("affine", &nalus, &recs);
+    }
+
+    #[test]
+    fn dmvr_reference_decoding() {
+        /* the refinement needs references on both sides of the picture */
+        let mut cfg = small_config();
+        cfg.max_b_frames = 3;
+        cfg.ref_pic_gap_length = 0;
+        cfg.admvp = true;
+        cfg.dmvr = true;
+        let (nalus, recs) = round_trip(cfg, 5);
+        assert_reference_decoding("dmvr", &nalus, &recs);
+    }
+
+    #[test]
+    fn rpl_reference_decoding() {
+        let mut cfg = small_config();
+        cfg.max_b_frames = 3;
+        cfg.ref_pic_gap_length = 0;
+        cfg.rpl = true;
+        let (nalus, recs) = round_trip(cfg, 5);
+        assert_reference_decoding("rpl", &nalus, &recs);
+    }
+
+    #[test]
+    fn pocs_reference_decoding() {
+        let mut cfg = small_config();
+        cfg.max_b_frames = 3;
+        cfg.ref_pic_gap_length = 0;
+        cfg.pocs = true;
+        let (nalus, recs) = round_trip(cfg, 5);
+        assert_reference_decoding("pocs", &nalus, &recs);
+    }
+
+    #[test]
+    fn multi_slice_abr_round_trip() {
+        let mut cfg = small_config();
+        cfg.height = 192;
+        cfg.num_slices_in_pic = 2;
+        cfg.rc_mode = RateControlMode::AverageBitrate;
+        cfg.bitrate = 50_000;
+        let (nalus, recs) = round_trip(cfg, 4);
+        assert_reference_decoding("multi_slice_abr", &nalus, &recs);
+
+        /* the QPs of the slices do not depend on the threads coding them */
+        let (threaded, _, _) = encode_with(cfg, 4, 2, None);
+        assert_eq!(nalus, threaded);
+    }
+
+    #[test]
+    fn validate_long_term_ref() {
+        let mut cfg = cqp_config(MAX_QUANT);
+        cfg.ltr = true;
+        assert_eq!(cfg.validate(), Err(InvalidConfig::InvalidLongTermRef));
+        cfg.rpl = true;
+        assert_eq!(cfg.validate(), Err(InvalidConfig::InvalidLongTermRef));
+        cfg.pocs = true;
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_qp_range() {
+        assert!(cqp_config(MIN_QUANT).validate().is_ok());
+        assert!(cqp_config(MAX_QUANT).validate().is_ok());
+        assert!(matches!(
+            cqp_config(MAX_QUANT + 1).validate(),
+            Err(InvalidConfig::InvalidQP { .. })
+        ));
+
+        let mut cfg = cqp_config(MAX_QUANT);
+        cfg.rc_mode = RateControlMode::AverageBitrate;
+        cfg.bitrate = 100_000;
+        cfg.max_qp = MAX_QUANT;
+        assert!(cfg.validate().is_ok());
+        cfg.min_qp = MIN_QUANT;
+        assert!(cfg.validate().is_ok());
+        cfg.min_qp = MAX_QUANT;
+        cfg.max_qp = MAX_QUANT - 1;
+        assert!(matches!(
+            cfg.validate(),
+            Err(InvalidConfig::InvalidQPRange { .. })
+        ));
+    }
 }
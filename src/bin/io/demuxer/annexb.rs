@@ -0,0 +1,80 @@
+use std::io;
+use std::io::{BufRead, Read};
+
+use super::*;
+use revc::api::*;
+
+pub struct AnnexBDemuxer {
+    reader: Box<dyn BufRead>,
+    /* the start code of the next NALU has already been consumed */
+    in_nalu: bool,
+}
+
+impl AnnexBDemuxer {
+    pub fn new(reader: Box<dyn BufRead>) -> io::Result<Box<dyn Demuxer>> {
+        Ok(Box::new(AnnexBDemuxer {
+            reader,
+            in_nalu: false,
+        }))
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let byte = match self.reader.fill_buf()?.first() {
+            Some(&byte) => byte,
+            None => return Ok(None),
+        };
+        self.reader.consume(1);
+        Ok(Some(byte))
+    }
+}
+
+impl Demuxer for AnnexBDemuxer {
+    fn read(&mut self) -> io::Result<Data> {
+        let mut zeros = 0;
+
+        /* skip leading zero bytes and the start code of the first NALU */
+        while !self.in_nalu {
+            match self.read_byte()? {
+                Some(0) => zeros += 1,
+                Some(1) if zeros >= 2 => self.in_nalu = true,
+                Some(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Missing start code in Annex-B byte stream",
+                    ))
+                }
+                None => return Err(io::ErrorKind::UnexpectedEof.into()),
+            }
+        }
+
+        /* the start code is kept so that the decoder removes the emulation
+        prevention bytes */
+        let mut data = NALU_START_CODE.to_vec();
+        zeros = 0;
+        loop {
+            match self.read_byte()? {
+                Some(0) => zeros += 1,
+                Some(1) if zeros >= 2 => break,
+                Some(byte) => {
+                    data.resize(data.len() + zeros, 0);
+                    data.push(byte);
+                    zeros = 0;
+                }
+                None => {
+                    self.in_nalu = false;
+                    break;
+                }
+            }
+        }
+
+        if data.len() == NALU_START_CODE.len() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        Ok(Data::Packet(Some(Packet { data, ts: 0 })))
+    }
+
+    fn info(&self) -> Option<VideoInfo> {
+        None
+    }
+}
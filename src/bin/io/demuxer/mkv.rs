@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+use std::io;
+use std::io::Read;
+
+use super::*;
+use crate::io::ebml::*;
+use crate::io::evcc::*;
+use revc::api::*;
+
+/* Matroska demuxer for the first EVC track. The parameter sets of the
+CodecPrivate are read first, then the NALUs of every block in stream order */
+pub struct MkvDemuxer {
+    data: Vec<u8>,
+    /* position of the next element in the Segment */
+    pos: usize,
+    track_number: u64,
+    config: EvcDecoderConfig,
+    timestamp_scale: u64,
+    cluster_ts: u64,
+    /* NALUs not yet handed to the decoder */
+    nalus: VecDeque<Packet>,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("mkv: {}", msg))
+}
+
+impl MkvDemuxer {
+    pub fn new(mut reader: Box<dyn Read>) -> io::Result<Box<dyn Demuxer>> {
+        let mut data = vec![];
+        reader.read_to_end(&mut data)?;
+
+        let (id, _, end) = read_element(&data, 0).ok_or_else(|| invalid("no EBML header"))?;
+        if id != EBML {
+            return Err(invalid("no EBML header"));
+        }
+        let (id, segment, _) = read_element(&data, end).ok_or_else(|| invalid("no Segment"))?;
+        if id != SEGMENT {
+            return Err(invalid("no Segment"));
+        }
+
+        let mut demuxer = MkvDemuxer {
+            data,
+            pos: segment,
+            track_number: 0,
+            config: EvcDecoderConfig::default(),
+            timestamp_scale: 1_000_000,
+            cluster_ts: 0,
+            nalus: VecDeque::new(),
+        };
+
+        /* the Info and Tracks precede the first Cluster */
+        while demuxer.track_number == 0 {
+            let (id, start, end) =
+                read_element(&demuxer.data, demuxer.pos).ok_or_else(|| invalid("no EVC track"))?;
+            match id {
+                INFO => {
+                    for (id, payload) in children(&demuxer.data[start..end]) {
+                        if id == TIMESTAMP_SCALE {
+                            demuxer.timestamp_scale = get_uint(payload);
+                        }
+                    }
+                }
+                TRACKS => demuxer.parse_tracks(start, end)?,
+                CLUSTER => return Err(invalid("no EVC track")),
+                _ => {}
+            }
+            demuxer.pos = end;
+        }
+
+        for nalu in demuxer.config.sps.iter().chain(demuxer.config.pps.iter()) {
+            demuxer.nalus.push_back(Packet {
+                data: nalu.clone(),
+                ts: 0,
+            });
+        }
+
+        Ok(Box::new(demuxer))
+    }
+
+    fn parse_tracks(&mut self, start: usize, end: usize) -> io::Result<()> {
+        for (id, entry) in children(&self.data[start..end]) {
+            if id != TRACK_ENTRY {
+                continue;
+            }
+            let mut track_number = 0;
+            let mut codec_id: &[u8] = &[];
+            let mut codec_private: &[u8] = &[];
+            for (id, payload) in children(entry) {
+                match id {
+                    TRACK_NUMBER => track_number = get_uint(payload),
+                    CODEC_ID => codec_id = payload,
+                    CODEC_PRIVATE => codec_private = payload,
+                    _ => {}
+                }
+            }
+            if codec_id == CODEC_ID_EVC.as_bytes() {
+                self.config = EvcDecoderConfig::parse(codec_private)?;
+                self.track_number = track_number;
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    /* queues the NALUs of a (Simple)Block payload of the EVC track */
+    fn parse_block(&mut self, block: &[u8]) -> io::Result<()> {
+        let (track_number, len) = read_size(block, 0).ok_or_else(|| invalid("invalid block"))?;
+        if track_number != Some(self.track_number) {
+            return Ok(());
+        }
+        let header = block
+            .get(len..len + 3)
+            .ok_or_else(|| invalid("invalid block"))?;
+        if header[2] & 0x06 != 0 {
+            return Err(invalid("laced blocks are not supported"));
+        }
+        let relative_ts = i16::from_be_bytes([header[0], header[1]]) as i64;
+        let ts = (self.cluster_ts as i64 + relative_ts).max(0) as u64;
+
+        for nalu in EvcDecoderConfig::split_sample(&block[len + 3..])? {
+            self.nalus.push_back(Packet {
+                data: nalu.to_vec(),
+                ts,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Demuxer for MkvDemuxer {
+    fn read(&mut self) -> io::Result<Data> {
+        while self.nalus.is_empty() {
+            let (id, start, end) = read_element(&self.data, self.pos)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+            /* descend into clusters, whose size may be unknown, and handle their
+            children as if they were in the Segment */
+            if id == CLUSTER {
+                self.pos = start;
+                continue;
+            }
+            self.pos = end;
+
+            match id {
+                TIMESTAMP => self.cluster_ts = get_uint(&self.data[start..end]),
+                SIMPLE_BLOCK => {
+                    let block = self.data[start..end].to_vec();
+                    self.parse_block(&block)?;
+                }
+                BLOCK_GROUP => {
+                    let block = children(&self.data[start..end])
+                        .find(|(id, _)| *id == BLOCK)
+                        .map(|(_, block)| block.to_vec());
+                    if let Some(block) = block {
+                        self.parse_block(&block)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Data::Packet(self.nalus.pop_front()))
+    }
+
+    fn info(&self) -> Option<VideoInfo> {
+        Some(VideoInfo {
+            width: self.config.width as usize,
+            height: self.config.height as usize,
+            bit_depth: self.config.bit_depth_luma_minus8 as usize + 8,
+            chroma_sampling: self.config.chroma_sampling(),
+            time_base: Rational::new(self.timestamp_scale, 1_000_000_000),
+        })
+    }
+}
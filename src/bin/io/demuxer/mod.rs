@@ -1,12 +1,18 @@
+mod annexb;
+mod mkv;
+mod mp4;
 mod nalu;
 mod y4m;
 mod yuv;
 
 use std::fs::File;
 use std::io;
-use std::io::Read;
+use std::io::{BufRead, BufReader, Read};
 use std::path::Path;
 
+use self::annexb::AnnexBDemuxer;
+use self::mkv::MkvDemuxer;
+use self::mp4::Mp4Demuxer;
 use self::nalu::NaluDemuxer;
 use self::y4m::Y4mDemuxer;
 use crate::io::demuxer::yuv::YuvDemuxer;
@@ -43,9 +49,13 @@ pub fn new(filename: &str, info: Option<VideoInfo>) -> io::Result<Box<dyn Demuxe
             Ok(Y4mDemuxer::new(filename)?)
         } else if ext == "yuv" {
             Ok(YuvDemuxer::new(filename, info)?)
+        } else if ext == "mp4" {
+            Mp4Demuxer::new(Box::new(File::open(filename)?))
+        } else if ext == "mkv" {
+            MkvDemuxer::new(Box::new(File::open(filename)?))
         } else {
             // .evc
-            Ok(NaluDemuxer::new(filename)?)
+            new_bitstream(filename)
         }
     } else {
         Err(io::Error::new(
@@ -54,3 +64,22 @@ pub fn new(filename: &str, info: Option<VideoInfo>) -> io::Result<Box<dyn Demuxe
         ))
     }
 }
+
+/* detects a start code (Annex-B) or a length prefixed (NALU) bitstream */
+fn new_bitstream(filename: &str) -> io::Result<Box<dyn Demuxer>> {
+    let reader: Box<dyn Read> = match filename {
+        "-" => Box::new(io::stdin()),
+        f => Box::new(File::open(&f)?),
+    };
+    let mut reader = BufReader::new(reader);
+
+    /* a length prefixed stream starts with the little endian size of the SPS,
+    which is far below 0x10000, so it cannot look like a start code */
+    let buf = reader.fill_buf()?;
+    let zeros = buf.iter().take_while(|&&byte| byte == 0).count();
+    if zeros >= 2 && buf.get(zeros) == Some(&1) {
+        AnnexBDemuxer::new(Box::new(reader))
+    } else {
+        NaluDemuxer::new(Box::new(reader))
+    }
+}
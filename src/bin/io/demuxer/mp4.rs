@@ -0,0 +1,251 @@
+use std::collections::VecDeque;
+use std::io;
+use std::io::Read;
+
+use super::*;
+use crate::io::evcc::*;
+use revc::api::*;
+
+/* ISO BMFF (MP4) demuxer for the first EVC track ('evc1' sample entry). The
+parameter sets of the evcC record are read first, then the NALUs of every
+sample in decoding order */
+pub struct Mp4Demuxer {
+    data: Vec<u8>,
+    config: EvcDecoderConfig,
+    timescale: u32,
+    /* (offset, size, composition time) of every sample in decoding order */
+    samples: VecDeque<(usize, usize, u64)>,
+    /* NALUs not yet handed to the decoder */
+    nalus: VecDeque<Packet>,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("mp4: {}", msg))
+}
+
+fn be(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0u64, |val, &b| (val << 8) | b as u64)
+}
+
+/* iterates over the (fourcc, payload) of the boxes in data */
+fn boxes(mut data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    std::iter::from_fn(move || {
+        if data.len() < 8 {
+            return None;
+        }
+        let (mut size, mut header) = (be(&data[0..4]) as usize, 8);
+        if size == 1 {
+            if data.len() < 16 {
+                return None;
+            }
+            size = be(&data[8..16]) as usize;
+            header = 16;
+        } else if size == 0 {
+            size = data.len();
+        }
+        if size < header || size > data.len() {
+            return None;
+        }
+        let item = (&data[4..8], &data[header..size]);
+        data = &data[size..];
+        Some(item)
+    })
+}
+
+fn find_box<'a>(data: &'a [u8], fourcc: &[u8; 4]) -> io::Result<&'a [u8]> {
+    boxes(data)
+        .find(|(t, _)| *t == fourcc)
+        .map(|(_, payload)| payload)
+        .ok_or_else(|| invalid(&format!("missing {} box", String::from_utf8_lossy(fourcc))))
+}
+
+/* payload of a full box after version and flags */
+fn find_full_box<'a>(data: &'a [u8], fourcc: &[u8; 4]) -> io::Result<(u8, &'a [u8])> {
+    let payload = find_box(data, fourcc)?;
+    if payload.len() < 4 {
+        return Err(invalid("truncated full box"));
+    }
+    Ok((payload[0], &payload[4..]))
+}
+
+/* the entries of a table box, each of entry_size bytes after the entry_count */
+fn table(payload: &[u8], entry_size: usize) -> io::Result<Vec<&[u8]>> {
+    if payload.len() < 4 {
+        return Err(invalid("truncated table"));
+    }
+    let count = be(&payload[0..4]) as usize;
+    let entries = &payload[4..];
+    if entries.len() < count * entry_size {
+        return Err(invalid("truncated table"));
+    }
+    Ok(entries.chunks(entry_size).take(count).collect())
+}
+
+impl Mp4Demuxer {
+    pub fn new(mut reader: Box<dyn Read>) -> io::Result<Box<dyn Demuxer>> {
+        let mut data = vec![];
+        reader.read_to_end(&mut data)?;
+
+        let (config, timescale, samples) = Mp4Demuxer::parse(&data)?;
+
+        let mut nalus = VecDeque::new();
+        for nalu in config.sps.iter().chain(config.pps.iter()) {
+            nalus.push_back(Packet {
+                data: nalu.clone(),
+                ts: 0,
+            });
+        }
+
+        Ok(Box::new(Mp4Demuxer {
+            data,
+            config,
+            timescale,
+            samples,
+            nalus,
+        }))
+    }
+
+    fn parse(data: &[u8]) -> io::Result<(EvcDecoderConfig, u32, VecDeque<(usize, usize, u64)>)> {
+        let moov = find_box(data, b"moov")?;
+        for (t, trak) in boxes(moov) {
+            if t != b"trak" {
+                continue;
+            }
+            let mdia = find_box(trak, b"mdia")?;
+            let stbl = find_box(find_box(mdia, b"minf")?, b"stbl")?;
+            let (_, stsd) = find_full_box(stbl, b"stsd")?;
+            let evc1 = match stsd
+                .get(4..)
+                .and_then(|e| boxes(e).find(|(t, _)| *t == b"evc1"))
+            {
+                Some((_, evc1)) => evc1,
+                None => continue,
+            };
+            /* the boxes of a visual sample entry follow 78 bytes of fields */
+            let config = EvcDecoderConfig::parse(find_box(
+                evc1.get(78..).ok_or_else(|| invalid("truncated evc1"))?,
+                b"evcC",
+            )?)?;
+
+            let (version, mdhd) = find_full_box(mdia, b"mdhd")?;
+            let timescale = if version == 1 {
+                be(mdhd.get(16..20).ok_or_else(|| invalid("truncated mdhd"))?)
+            } else {
+                be(mdhd.get(8..12).ok_or_else(|| invalid("truncated mdhd"))?)
+            } as u32;
+
+            return Ok((config, timescale, Mp4Demuxer::parse_samples(stbl)?));
+        }
+
+        Err(invalid("no evc1 track"))
+    }
+
+    fn parse_samples(stbl: &[u8]) -> io::Result<VecDeque<(usize, usize, u64)>> {
+        /* sample sizes */
+        let (_, stsz) = find_full_box(stbl, b"stsz")?;
+        let sizes: Vec<usize> = if stsz.len() >= 8 && be(&stsz[0..4]) != 0 {
+            vec![be(&stsz[0..4]) as usize; be(&stsz[4..8]) as usize]
+        } else {
+            table(stsz.get(4..).unwrap_or(&[]), 4)?
+                .iter()
+                .map(|e| be(e) as usize)
+                .collect()
+        };
+
+        /* chunk offsets */
+        let offsets: Vec<usize> = if let Ok((_, co64)) = find_full_box(stbl, b"co64") {
+            table(co64, 8)?.iter().map(|e| be(e) as usize).collect()
+        } else {
+            let (_, stco) = find_full_box(stbl, b"stco")?;
+            table(stco, 4)?.iter().map(|e| be(e) as usize).collect()
+        };
+
+        /* decoding times */
+        let (_, stts) = find_full_box(stbl, b"stts")?;
+        let mut dts = vec![];
+        let mut t = 0;
+        for e in table(stts, 8)? {
+            for _ in 0..be(&e[0..4]) {
+                dts.push(t);
+                t += be(&e[4..8]);
+            }
+        }
+
+        /* composition offsets */
+        let mut cts_offset = vec![];
+        if let Ok((version, ctts)) = find_full_box(stbl, b"ctts") {
+            for e in table(ctts, 8)? {
+                let offset = if version == 1 {
+                    be(&e[4..8]) as u32 as i32 as i64
+                } else {
+                    be(&e[4..8]) as i64
+                };
+                cts_offset.extend(std::iter::repeat(offset).take(be(&e[0..4]) as usize));
+            }
+        }
+
+        /* sample to chunk mapping */
+        let (_, stsc) = find_full_box(stbl, b"stsc")?;
+        let stsc: Vec<(usize, usize)> = table(stsc, 12)?
+            .iter()
+            .map(|e| (be(&e[0..4]) as usize, be(&e[4..8]) as usize))
+            .collect();
+
+        let mut samples = VecDeque::with_capacity(sizes.len());
+        let mut n = 0;
+        for (chunk, &chunk_offset) in offsets.iter().enumerate() {
+            let samples_per_chunk = stsc
+                .iter()
+                .rev()
+                .find(|(first_chunk, _)| *first_chunk <= chunk + 1)
+                .map(|(_, spc)| *spc)
+                .unwrap_or(0);
+            let mut offset = chunk_offset;
+            for _ in 0..samples_per_chunk {
+                if n >= sizes.len() {
+                    break;
+                }
+                let cts = dts.get(n).copied().unwrap_or(0) as i64
+                    + cts_offset.get(n).copied().unwrap_or(0);
+                samples.push_back((offset, sizes[n], cts.max(0) as u64));
+                offset += sizes[n];
+                n += 1;
+            }
+        }
+
+        Ok(samples)
+    }
+}
+
+impl Demuxer for Mp4Demuxer {
+    fn read(&mut self) -> io::Result<Data> {
+        while self.nalus.is_empty() {
+            let (offset, size, ts) = self
+                .samples
+                .pop_front()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+            let sample = self
+                .data
+                .get(offset..offset + size)
+                .ok_or_else(|| invalid("sample out of file"))?;
+            for nalu in EvcDecoderConfig::split_sample(sample)? {
+                self.nalus.push_back(Packet {
+                    data: nalu.to_vec(),
+                    ts,
+                });
+            }
+        }
+
+        Ok(Data::Packet(self.nalus.pop_front()))
+    }
+
+    fn info(&self) -> Option<VideoInfo> {
+        Some(VideoInfo {
+            width: self.config.width as usize,
+            height: self.config.height as usize,
+            bit_depth: self.config.bit_depth_luma_minus8 as usize + 8,
+            chroma_sampling: self.config.chroma_sampling(),
+            time_base: Rational::new(1, self.timescale as u64),
+        })
+    }
+}
@@ -1,4 +1,3 @@
-use std::fs::File;
 use std::io;
 use std::io::Read;
 
@@ -10,13 +9,8 @@ pub struct NaluDemuxer {
 }
 
 impl NaluDemuxer {
-    pub fn new(path: &str) -> io::Result<Box<dyn Demuxer>> {
-        Ok(Box::new(NaluDemuxer {
-            reader: match path {
-                "-" => Box::new(io::stdin()),
-                f => Box::new(File::open(&f).unwrap()),
-            },
-        }))
+    pub fn new(reader: Box<dyn Read>) -> io::Result<Box<dyn Demuxer>> {
+        Ok(Box::new(NaluDemuxer { reader }))
     }
 }
 
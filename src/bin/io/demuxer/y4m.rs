@@ -32,8 +32,8 @@ impl Demuxer for Y4mDemuxer {
         let bytes = self.reader.get_bytes_per_sample();
         let color_space = self.reader.get_colorspace();
         let chroma_sampling = map_y4m_color_space(color_space);
-        let (xdec, _) = chroma_sampling.sampling_period();
-        let chroma_width = (width + 1) >> xdec;
+        let (xdec, _) = chroma_sampling.get_decimation();
+        let chroma_width = (width + xdec) >> xdec;
         let frame = self
             .reader
             .read_frame()
@@ -41,12 +41,15 @@ impl Demuxer for Y4mDemuxer {
                 let mut f: Frame<u16> = Frame::new(width, height, chroma_sampling);
 
                 f.planes[0].copy_from_raw_u8(frame.get_y_plane(), width * bytes, bytes);
-                f.planes[1].copy_from_raw_u8(frame.get_u_plane(), chroma_width * bytes, bytes);
-                f.planes[2].copy_from_raw_u8(frame.get_v_plane(), chroma_width * bytes, bytes);
-
                 f.planes[0].conv_8b_to_16b(2);
-                f.planes[1].conv_8b_to_16b(2);
-                f.planes[2].conv_8b_to_16b(2);
+
+                if chroma_sampling != ChromaSampling::Cs400 {
+                    f.planes[1].copy_from_raw_u8(frame.get_u_plane(), chroma_width * bytes, bytes);
+                    f.planes[2].copy_from_raw_u8(frame.get_v_plane(), chroma_width * bytes, bytes);
+
+                    f.planes[1].conv_8b_to_16b(2);
+                    f.planes[2].conv_8b_to_16b(2);
+                }
 
                 f
             })
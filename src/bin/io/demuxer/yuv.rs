@@ -29,11 +29,15 @@ impl Demuxer for YuvDemuxer {
             let bytes_per_sample = if info.bit_depth > 8 { 2 } else { 1 };
             let pitch_y = info.width * bytes_per_sample;
             let height = info.height;
-            let chroma_sampling_period = info.chroma_sampling.sampling_period();
-            let (pitch_uv, height_uv) = (
-                (pitch_y * bytes_per_sample) / chroma_sampling_period.0,
-                height / chroma_sampling_period.1,
-            );
+            let (xdec, ydec) = info.chroma_sampling.get_decimation();
+            let (pitch_uv, height_uv) = if info.chroma_sampling == ChromaSampling::Cs400 {
+                (0, 0)
+            } else {
+                (
+                    ((info.width + xdec) >> xdec) * bytes_per_sample,
+                    (height + ydec) >> ydec,
+                )
+            };
 
             let (mut rec_y, mut rec_u, mut rec_v) = (
                 vec![128u8; pitch_y * height],
@@ -48,12 +52,15 @@ impl Demuxer for YuvDemuxer {
             let mut frame: Frame<u16> = Frame::new(info.width, info.height, info.chroma_sampling);
 
             frame.planes[0].copy_from_raw_u8(&rec_y, pitch_y, bytes_per_sample);
-            frame.planes[1].copy_from_raw_u8(&rec_u, pitch_uv, bytes_per_sample);
-            frame.planes[2].copy_from_raw_u8(&rec_v, pitch_uv, bytes_per_sample);
-
             frame.planes[0].conv_8b_to_16b(2);
-            frame.planes[1].conv_8b_to_16b(2);
-            frame.planes[2].conv_8b_to_16b(2);
+
+            if info.chroma_sampling != ChromaSampling::Cs400 {
+                frame.planes[1].copy_from_raw_u8(&rec_u, pitch_uv, bytes_per_sample);
+                frame.planes[2].copy_from_raw_u8(&rec_v, pitch_uv, bytes_per_sample);
+
+                frame.planes[1].conv_8b_to_16b(2);
+                frame.planes[2].conv_8b_to_16b(2);
+            }
 
             Ok(Data::Frame(Some(frame)))
         } else {
@@ -0,0 +1,233 @@
+/*****************************************************************************
+ * EBML elements of Matroska
+ *****************************************************************************/
+/* element IDs (including the VINT marker) */
+pub const EBML: u32 = 0x1A45DFA3;
+pub const EBML_VERSION: u32 = 0x4286;
+pub const EBML_READ_VERSION: u32 = 0x42F7;
+pub const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+pub const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+pub const DOC_TYPE: u32 = 0x4282;
+pub const DOC_TYPE_VERSION: u32 = 0x4287;
+pub const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+pub const SEGMENT: u32 = 0x18538067;
+pub const INFO: u32 = 0x1549A966;
+pub const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+pub const MUXING_APP: u32 = 0x4D80;
+pub const WRITING_APP: u32 = 0x5741;
+pub const TRACKS: u32 = 0x1654AE6B;
+pub const TRACK_ENTRY: u32 = 0xAE;
+pub const TRACK_NUMBER: u32 = 0xD7;
+pub const TRACK_UID: u32 = 0x73C5;
+pub const TRACK_TYPE: u32 = 0x83;
+pub const CODEC_ID: u32 = 0x86;
+pub const CODEC_PRIVATE: u32 = 0x63A2;
+pub const DEFAULT_DURATION: u32 = 0x23E383;
+pub const VIDEO: u32 = 0xE0;
+pub const PIXEL_WIDTH: u32 = 0xB0;
+pub const PIXEL_HEIGHT: u32 = 0xBA;
+pub const CLUSTER: u32 = 0x1F43B675;
+pub const TIMESTAMP: u32 = 0xE7;
+pub const SIMPLE_BLOCK: u32 = 0xA3;
+pub const BLOCK_GROUP: u32 = 0xA0;
+pub const BLOCK: u32 = 0xA1;
+
+/* CodecID of EVC, with the evcC record as CodecPrivate */
+pub const CODEC_ID_EVC: &str = "V_MPEG5/ISO/EVC";
+/* all ones size of an element written before its end is known */
+pub const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+
+pub fn put_id(buf: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count();
+    buf.extend_from_slice(&bytes[skip..]);
+}
+
+/* EBML variable size integer with the shortest length */
+pub fn put_size(buf: &mut Vec<u8>, size: u64) {
+    let mut len = 1;
+    /* all ones is reserved for unknown sizes */
+    while len < 8 && size >= (1 << (7 * len)) - 1 {
+        len += 1;
+    }
+    let val = size | (1 << (7 * len));
+    buf.extend_from_slice(&val.to_be_bytes()[8 - len..]);
+}
+
+pub fn put_binary(buf: &mut Vec<u8>, id: u32, data: &[u8]) {
+    put_id(buf, id);
+    put_size(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+pub fn put_uint(buf: &mut Vec<u8>, id: u32, val: u64) {
+    let bytes = val.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count().min(7);
+    put_binary(buf, id, &bytes[skip..]);
+}
+
+pub fn put_string(buf: &mut Vec<u8>, id: u32, val: &str) {
+    put_binary(buf, id, val.as_bytes());
+}
+
+pub fn put_element<F: FnOnce(&mut Vec<u8>)>(buf: &mut Vec<u8>, id: u32, f: F) {
+    let mut data = vec![];
+    f(&mut data);
+    put_binary(buf, id, &data);
+}
+
+/* reads an element ID at pos, returns it with its length */
+pub fn read_id(data: &[u8], pos: usize) -> Option<(u32, usize)> {
+    let first = *data.get(pos)?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 4 {
+        return None;
+    }
+    let bytes = data.get(pos..pos + len)?;
+    Some((
+        bytes.iter().fold(0u32, |val, &b| (val << 8) | b as u32),
+        len,
+    ))
+}
+
+/* reads an element size at pos, returns it (None if unknown) with its length */
+pub fn read_size(data: &[u8], pos: usize) -> Option<(Option<u64>, usize)> {
+    let first = *data.get(pos)?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 {
+        return None;
+    }
+    let bytes = data.get(pos..pos + len)?;
+    let mask = (1u64 << (7 * len)) - 1;
+    let val = bytes.iter().fold(0u64, |val, &b| (val << 8) | b as u64) & mask;
+    Some((if val == mask { None } else { Some(val) }, len))
+}
+
+/* reads the element at pos, returns (id, payload start, payload end); an
+unknown size extends to the end of data */
+pub fn read_element(data: &[u8], pos: usize) -> Option<(u32, usize, usize)> {
+    let (id, id_len) = read_id(data, pos)?;
+    let (size, size_len) = read_size(data, pos + id_len)?;
+    let start = pos + id_len + size_len;
+    let end = match size {
+        Some(size) => start.checked_add(size as usize)?,
+        None => data.len(),
+    };
+    if end > data.len() {
+        return None;
+    }
+    Some((id, start, end))
+}
+
+/* the child elements (id, payload) of a master element payload */
+pub fn children(data: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let (id, start, end) = read_element(data, pos)?;
+        pos = end;
+        Some((id, &data[start..end]))
+    })
+}
+
+pub fn get_uint(data: &[u8]) -> u64 {
+    data.iter().fold(0u64, |val, &b| (val << 8) | b as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn size(size: u64) -> Vec<u8> {
+        let mut buf = vec![];
+        put_size(&mut buf, size);
+        buf
+    }
+
+    #[test]
+    fn size_round_trip() {
+        for len in 1..=8 {
+            /* the largest value of every length, all ones being reserved */
+            let max = (1u64 << (7 * len)) - 2;
+            for &val in &[max >> 1, max] {
+                let buf = size(val);
+                assert_eq!(buf.len(), len, "size {}", val);
+                assert_eq!(read_size(&buf, 0), Some((Some(val), len)));
+            }
+            if len < 8 {
+                assert_eq!(size(max + 1).len(), len + 1);
+            }
+        }
+        assert_eq!(size(0), [0x80]);
+        assert_eq!(size(126), [0xFE]);
+        assert_eq!(size(127), [0x40, 0x7F]);
+        assert_eq!(
+            size((1 << 56) - 2),
+            [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE]
+        );
+    }
+
+    #[test]
+    fn read_size_unknown() {
+        assert_eq!(read_size(&UNKNOWN_SIZE, 0), Some((None, 8)));
+        /* all ones of any length is unknown */
+        assert_eq!(read_size(&[0xFF], 0), Some((None, 1)));
+        assert_eq!(read_size(&[0x7F, 0xFF], 0), Some((None, 2)));
+        /* longer than 8 bytes or truncated */
+        assert_eq!(read_size(&[0x00, 0xFF], 0), None);
+        assert_eq!(read_size(&UNKNOWN_SIZE[..7], 0), None);
+        assert_eq!(read_size(&[], 0), None);
+    }
+
+    #[test]
+    fn id_round_trip() {
+        for &id in &[TRACK_ENTRY, CODEC_PRIVATE, TIMESTAMP_SCALE, SEGMENT] {
+            let mut buf = vec![];
+            put_id(&mut buf, id);
+            assert_eq!(read_id(&buf, 0), Some((id, buf.len())));
+        }
+        assert_eq!(read_id(&[0x08, 0, 0, 0, 0], 0), None);
+        assert_eq!(read_id(&[0x1A, 0x45, 0xDF], 0), None);
+    }
+
+    #[test]
+    fn element_round_trip() {
+        let mut buf = vec![];
+        put_element(&mut buf, TRACK_ENTRY, |buf| {
+            put_uint(buf, TRACK_NUMBER, 1);
+            put_uint(buf, DEFAULT_DURATION, 33_333_333);
+            put_string(buf, CODEC_ID, CODEC_ID_EVC);
+            put_binary(buf, CODEC_PRIVATE, &[0x5A; 200]);
+        });
+
+        let (id, start, end) = read_element(&buf, 0).unwrap();
+        assert_eq!((id, end), (TRACK_ENTRY, buf.len()));
+        let children: Vec<_> = children(&buf[start..end]).collect();
+        assert_eq!(children.len(), 4);
+        assert_eq!(children[0], (TRACK_NUMBER, &[1u8][..]));
+        assert_eq!(get_uint(children[1].1), 33_333_333);
+        assert_eq!(children[2], (CODEC_ID, CODEC_ID_EVC.as_bytes()));
+        assert_eq!(children[3], (CODEC_PRIVATE, &[0x5A; 200][..]));
+
+        /* zero is written with one byte, truncated elements are rejected */
+        let mut zero = vec![];
+        put_uint(&mut zero, TRACK_NUMBER, 0);
+        assert_eq!(zero, [0xD7, 0x81, 0x00]);
+        assert_eq!(read_element(&buf[..buf.len() - 1], 0), None);
+    }
+
+    #[test]
+    fn element_unknown_size() {
+        let mut buf = vec![];
+        put_id(&mut buf, CLUSTER);
+        buf.extend_from_slice(&UNKNOWN_SIZE);
+        put_uint(&mut buf, TIMESTAMP, 40);
+
+        /* an unknown size extends to the end of data */
+        let (id, start, end) = read_element(&buf, 0).unwrap();
+        assert_eq!((id, start, end), (CLUSTER, 12, buf.len()));
+        assert_eq!(
+            children(&buf[start..end]).collect::<Vec<_>>(),
+            [(TIMESTAMP, &[40u8][..])]
+        );
+    }
+}
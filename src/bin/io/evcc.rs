@@ -0,0 +1,383 @@
+use std::io;
+
+use revc::api::*;
+
+/* size of the nalu header */
+const NALU_HEADER_SIZE: usize = 2;
+
+/* nal_unit_type of a NALU (without size or start code) */
+pub fn nalu_type(nalu: &[u8]) -> NaluType {
+    NaluType::from(((nalu[0] >> 1) & 0x3F).wrapping_sub(1))
+}
+
+/* splits a packet of 4-byte little endian length prefixed NALUs */
+pub fn split_nalus(data: &[u8]) -> io::Result<Vec<&[u8]>> {
+    let mut nalus = vec![];
+    let mut pos = 0;
+    while pos + NALU_SIZE_FIELD_IN_BYTES <= data.len() {
+        let size = (data[pos + 3] as usize) << 24
+            | (data[pos + 2] as usize) << 16
+            | (data[pos + 1] as usize) << 8
+            | data[pos] as usize;
+        pos += NALU_SIZE_FIELD_IN_BYTES;
+        if size < NALU_HEADER_SIZE || pos + size > data.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid NALU size (Annex-B packets are not supported in containers)",
+            ));
+        }
+        nalus.push(&data[pos..pos + size]);
+        pos += size;
+    }
+    Ok(nalus)
+}
+
+/* minimal bit reader for the leading SPS syntax elements */
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn read1(&mut self) -> io::Result<u32> {
+        let byte = self
+            .data
+            .get(self.pos >> 3)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Truncated SPS"))?;
+        let bit = (byte >> (7 - (self.pos & 7))) & 1;
+        self.pos += 1;
+        Ok(bit as u32)
+    }
+
+    fn read(&mut self, size: usize) -> io::Result<u32> {
+        let mut val = 0;
+        for _ in 0..size {
+            val = (val << 1) | self.read1()?;
+        }
+        Ok(val)
+    }
+
+    fn read_ue(&mut self) -> io::Result<u32> {
+        let mut len = 0;
+        while self.read1()? == 0 {
+            len += 1;
+            if len > 31 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid SPS"));
+            }
+        }
+        Ok((1 << len) - 1 + self.read(len)?)
+    }
+}
+
+/*****************************************************************************
+ * EVC decoder configuration record (ISO/IEC 14496-15 evcC), stored in the
+ * sample entry of MP4 and in the CodecPrivate of Matroska
+ *****************************************************************************/
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EvcDecoderConfig {
+    pub profile_idc: u8,
+    pub level_idc: u8,
+    pub toolset_idc_h: u32,
+    pub toolset_idc_l: u32,
+    pub chroma_format_idc: u8,
+    pub bit_depth_luma_minus8: u8,
+    pub bit_depth_chroma_minus8: u8,
+    pub width: u16,
+    pub height: u16,
+    /* parameter set NALUs (without size) */
+    pub sps: Vec<Vec<u8>>,
+    pub pps: Vec<Vec<u8>>,
+    /* no parameter set is carried in-band in the samples */
+    pub array_completeness: bool,
+}
+
+impl EvcDecoderConfig {
+    /* configuration version of the record */
+    const VERSION: u8 = 1;
+    /* samples carry 4-byte big endian NALU sizes */
+    const LENGTH_SIZE: usize = 4;
+
+    pub fn from_sps(sps: &[u8]) -> io::Result<Self> {
+        let mut br = BitReader {
+            data: sps,
+            pos: NALU_HEADER_SIZE << 3,
+        };
+
+        let _sps_seq_parameter_set_id = br.read_ue()?;
+        let profile_idc = br.read(8)? as u8;
+        let level_idc = br.read(8)? as u8;
+        let toolset_idc_h = br.read(32)?;
+        let toolset_idc_l = br.read(32)?;
+        let chroma_format_idc = br.read_ue()? as u8;
+        let width = br.read_ue()? as u16;
+        let height = br.read_ue()? as u16;
+        let bit_depth_luma_minus8 = br.read_ue()? as u8;
+        let bit_depth_chroma_minus8 = br.read_ue()? as u8;
+
+        Ok(EvcDecoderConfig {
+            profile_idc,
+            level_idc,
+            toolset_idc_h,
+            toolset_idc_l,
+            chroma_format_idc,
+            bit_depth_luma_minus8,
+            bit_depth_chroma_minus8,
+            width,
+            height,
+            sps: vec![sps.to_vec()],
+            pps: vec![],
+            array_completeness: true,
+        })
+    }
+
+    /* whether a parameter set of the stream is carried in-band: only the
+    repetitions of the record are dropped, until a parameter set of the record
+    is replaced in-band, which makes the arrays incomplete */
+    pub fn in_band(&mut self, nalu: &[u8]) -> bool {
+        let nalus = match nalu_type(nalu) {
+            NaluType::EVC_SPS_NUT => &self.sps,
+            NaluType::EVC_PPS_NUT => &self.pps,
+            _ => return true,
+        };
+        if self.array_completeness && nalus.iter().any(|n| n[..] == *nalu) {
+            return false;
+        }
+        self.array_completeness = false;
+        true
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        let mut buf = vec![EvcDecoderConfig::VERSION, self.profile_idc, self.level_idc];
+        buf.extend_from_slice(&self.toolset_idc_h.to_be_bytes());
+        buf.extend_from_slice(&self.toolset_idc_l.to_be_bytes());
+        buf.push(
+            (self.chroma_format_idc & 0x3) << 6
+                | (self.bit_depth_luma_minus8 & 0x7) << 3
+                | (self.bit_depth_chroma_minus8 & 0x7),
+        );
+        buf.extend_from_slice(&self.width.to_be_bytes());
+        buf.extend_from_slice(&self.height.to_be_bytes());
+        buf.push(0xFC | (EvcDecoderConfig::LENGTH_SIZE - 1) as u8);
+
+        let arrays = [
+            (NaluType::EVC_SPS_NUT, &self.sps),
+            (NaluType::EVC_PPS_NUT, &self.pps),
+        ];
+        buf.push(arrays.iter().filter(|(_, nalus)| !nalus.is_empty()).count() as u8);
+        for (nal_unit_type, nalus) in arrays.iter().filter(|(_, nalus)| !nalus.is_empty()) {
+            buf.push((self.array_completeness as u8) << 7 | *nal_unit_type as u8);
+            buf.extend_from_slice(&(nalus.len() as u16).to_be_bytes());
+            for nalu in nalus.iter() {
+                buf.extend_from_slice(&(nalu.len() as u16).to_be_bytes());
+                buf.extend_from_slice(nalu);
+            }
+        }
+
+        buf
+    }
+
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid evcC record");
+        let get = |pos: usize, len: usize| data.get(pos..pos + len).ok_or_else(invalid);
+        let be = |bytes: &[u8]| bytes.iter().fold(0u32, |val, &b| (val << 8) | b as u32);
+
+        if get(0, 1)?[0] != EvcDecoderConfig::VERSION {
+            return Err(invalid());
+        }
+        let fmt = get(11, 1)?[0];
+        if (get(16, 1)?[0] & 0x3) as usize != EvcDecoderConfig::LENGTH_SIZE - 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Only 4-byte NALU sizes are supported",
+            ));
+        }
+
+        let mut config = EvcDecoderConfig {
+            profile_idc: get(1, 1)?[0],
+            level_idc: get(2, 1)?[0],
+            toolset_idc_h: be(get(3, 4)?),
+            toolset_idc_l: be(get(7, 4)?),
+            chroma_format_idc: fmt >> 6,
+            bit_depth_luma_minus8: (fmt >> 3) & 0x7,
+            bit_depth_chroma_minus8: fmt & 0x7,
+            width: be(get(12, 2)?) as u16,
+            height: be(get(14, 2)?) as u16,
+            sps: vec![],
+            pps: vec![],
+            array_completeness: true,
+        };
+
+        let num_arrays = get(17, 1)?[0];
+        let mut pos = 18;
+        for _ in 0..num_arrays {
+            let nal_unit_type = NaluType::from(get(pos, 1)?[0] & 0x3F);
+            config.array_completeness &= get(pos, 1)?[0] & 0x80 != 0;
+            let num_nalus = be(get(pos + 1, 2)?);
+            pos += 3;
+            for _ in 0..num_nalus {
+                let size = be(get(pos, 2)?) as usize;
+                let nalu = get(pos + 2, size)?.to_vec();
+                pos += 2 + size;
+                match nal_unit_type {
+                    NaluType::EVC_SPS_NUT => config.sps.push(nalu),
+                    NaluType::EVC_PPS_NUT => config.pps.push(nalu),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /* size prefixed NALUs of a sample, in decoding order */
+    pub fn split_sample(sample: &[u8]) -> io::Result<Vec<&[u8]>> {
+        let mut nalus = vec![];
+        let mut pos = 0;
+        while pos + EvcDecoderConfig::LENGTH_SIZE <= sample.len() {
+            let size = sample[pos..pos + EvcDecoderConfig::LENGTH_SIZE]
+                .iter()
+                .fold(0usize, |val, &b| (val << 8) | b as usize);
+            pos += EvcDecoderConfig::LENGTH_SIZE;
+            if pos + size > sample.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid NALU size in sample",
+                ));
+            }
+            nalus.push(&sample[pos..pos + size]);
+            pos += size;
+        }
+        Ok(nalus)
+    }
+
+    /* appends a NALU with its size prefix to a sample */
+    pub fn append_to_sample(sample: &mut Vec<u8>, nalu: &[u8]) {
+        sample.extend_from_slice(&(nalu.len() as u32).to_be_bytes());
+        sample.extend_from_slice(nalu);
+    }
+
+    pub fn chroma_sampling(&self) -> ChromaSampling {
+        ChromaSampling::from(self.chroma_format_idc)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use std::sync::{Arc, RwLock};
+
+    /* NALU header of a parameter set, nal_unit_type_plus1 in the first byte */
+    pub fn nalu(nal_unit_type: NaluType, payload: &[u8]) -> Vec<u8> {
+        let mut nalu = vec![(nal_unit_type as u8 + 1) << 1, 0];
+        nalu.extend_from_slice(payload);
+        nalu
+    }
+
+    /* packet of length prefixed NALUs, as the encoder outputs them */
+    pub fn packet(nalus: &[Vec<u8>], ts: u64) -> Data {
+        let mut data = vec![];
+        for nalu in nalus {
+            data.extend_from_slice(&(nalu.len() as u32).to_le_bytes());
+            data.extend_from_slice(nalu);
+        }
+        Data::RefPacket(Arc::new(RwLock::new(Packet { data, ts })))
+    }
+
+    /* SPS with the leading syntax elements read by the record */
+    pub fn sps(chroma_format_idc: u32, width: u32, height: u32) -> Vec<u8> {
+        let mut bits = String::new();
+        let ue = |bits: &mut String, val: u32| {
+            let code = format!("{:b}", val + 1);
+            bits.push_str(&"0".repeat(code.len() - 1));
+            bits.push_str(&code);
+        };
+        ue(&mut bits, 0); // sps_seq_parameter_set_id
+        bits.push_str(&format!("{:08b}{:08b}", 1, 153)); // profile_idc, level_idc
+        bits.push_str(&format!("{:032b}{:032b}", 0x12345678, 0x9ABCDEF0u32));
+        ue(&mut bits, chroma_format_idc);
+        ue(&mut bits, width);
+        ue(&mut bits, height);
+        ue(&mut bits, 2); // bit_depth_luma_minus8
+        ue(&mut bits, 2); // bit_depth_chroma_minus8
+        bits.push('1');
+        while bits.len() % 8 != 0 {
+            bits.push('0');
+        }
+        let payload: Vec<u8> = (0..bits.len() / 8)
+            .map(|i| u8::from_str_radix(&bits[i * 8..i * 8 + 8], 2).unwrap())
+            .collect();
+        nalu(NaluType::EVC_SPS_NUT, &payload)
+    }
+
+    #[test]
+    fn from_sps() {
+        let config = EvcDecoderConfig::from_sps(&sps(3, 1920, 1080)).unwrap();
+        assert_eq!(config.profile_idc, 1);
+        assert_eq!(config.level_idc, 153);
+        assert_eq!(config.toolset_idc_h, 0x12345678);
+        assert_eq!(config.toolset_idc_l, 0x9ABCDEF0);
+        assert_eq!(config.chroma_sampling(), ChromaSampling::Cs444);
+        assert_eq!((config.width, config.height), (1920, 1080));
+        assert_eq!(config.bit_depth_luma_minus8, 2);
+        assert_eq!(config.bit_depth_chroma_minus8, 2);
+        assert!(config.array_completeness);
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let mut config = EvcDecoderConfig::from_sps(&sps(1, 352, 288)).unwrap();
+        config.pps.push(nalu(NaluType::EVC_PPS_NUT, &[0x12, 0x34]));
+        let record = config.write();
+        assert_eq!(EvcDecoderConfig::parse(&record).unwrap(), config);
+
+        /* without any PPS */
+        config.pps.clear();
+        assert_eq!(EvcDecoderConfig::parse(&config.write()).unwrap(), config);
+
+        /* truncated records and other NALU sizes are rejected */
+        assert!(EvcDecoderConfig::parse(&record[..record.len() - 1]).is_err());
+        let mut record = record;
+        record[16] = 0xFC | 1;
+        assert!(EvcDecoderConfig::parse(&record).is_err());
+    }
+
+    #[test]
+    fn in_band_parameter_sets() {
+        let sps0 = sps(1, 352, 288);
+        let pps0 = nalu(NaluType::EVC_PPS_NUT, &[0x12, 0x34]);
+        let pps1 = nalu(NaluType::EVC_PPS_NUT, &[0x56, 0x78]);
+        let mut config = EvcDecoderConfig::from_sps(&sps0).unwrap();
+        config.pps.push(pps0.clone());
+
+        /* repetitions of the record are dropped */
+        assert!(!config.in_band(&sps0));
+        assert!(!config.in_band(&pps0));
+        assert!(config.in_band(&nalu(NaluType::EVC_IDR_NUT, &[0x80])));
+        assert!(config.array_completeness);
+
+        /* a new parameter set is carried in-band, and the record ones after it */
+        assert!(config.in_band(&pps1));
+        assert!(!config.array_completeness);
+        assert!(config.in_band(&pps0));
+        assert!(config.in_band(&sps0));
+
+        let parsed = EvcDecoderConfig::parse(&config.write()).unwrap();
+        assert!(!parsed.array_completeness);
+        assert_eq!(parsed, config);
+    }
+
+    #[test]
+    fn sample_round_trip() {
+        let nalus = [
+            nalu(NaluType::EVC_IDR_NUT, &[0x80; 300]),
+            nalu(NaluType::EVC_SEI_NUT, &[0x05]),
+        ];
+        let mut sample = vec![];
+        for nalu in nalus.iter() {
+            EvcDecoderConfig::append_to_sample(&mut sample, nalu);
+        }
+        assert_eq!(&sample[..4], &[0, 0, 1, 46]);
+        assert_eq!(EvcDecoderConfig::split_sample(&sample).unwrap(), nalus);
+        assert!(EvcDecoderConfig::split_sample(&sample[..sample.len() - 1]).is_err());
+    }
+}
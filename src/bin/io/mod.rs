@@ -4,6 +4,8 @@ use std::io;
 use revc::api::{frame::Frame, Packet};
 
 pub mod demuxer;
+pub mod ebml;
+pub mod evcc;
 pub mod muxer;
 
 /* clipping within min and max */
@@ -0,0 +1,256 @@
+use super::Muxer;
+use crate::io::ebml::*;
+use crate::io::evcc::*;
+use crate::Data;
+
+use std::fs::File;
+use std::io;
+use std::io::Write;
+
+use revc::api::*;
+
+/* timestamps are in milliseconds */
+const TIMESTAMP_SCALE_NS: u64 = 1_000_000;
+const TRACK_TYPE_VIDEO: u64 = 1;
+const EVC_TRACK_NUMBER: u64 = 1;
+
+/* Matroska muxer with a single EVC track. The Segment has an unknown size so
+that clusters are written as soon as they are complete; a cluster starts at
+every key frame. The parameter sets following the ones of the CodecPrivate are
+carried in-band */
+pub struct MkvMuxer {
+    writer: Box<dyn Write>,
+    config: Option<EvcDecoderConfig>,
+    header_written: bool,
+    cluster: Vec<u8>,
+    cluster_ts: i64,
+}
+
+impl MkvMuxer {
+    pub fn new(path: &str) -> io::Result<Box<dyn Muxer>> {
+        Ok(Box::new(MkvMuxer {
+            writer: match path {
+                "-" => Box::new(io::stdout()),
+                f => Box::new(File::create(&f)?),
+            },
+            config: None,
+            header_written: false,
+            cluster: vec![],
+            cluster_ts: 0,
+        }))
+    }
+
+    fn write_header(&mut self, frame_rate: Rational) -> io::Result<()> {
+        let config = self
+            .config
+            .as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No SPS for MkvMuxer"))?;
+        /* the CodecPrivate is written before the parameter sets that may follow
+        in-band are known */
+        let record = EvcDecoderConfig {
+            array_completeness: false,
+            ..config.clone()
+        };
+
+        let mut buf = vec![];
+        put_element(&mut buf, EBML, |buf| {
+            put_uint(buf, EBML_VERSION, 1);
+            put_uint(buf, EBML_READ_VERSION, 1);
+            put_uint(buf, EBML_MAX_ID_LENGTH, 4);
+            put_uint(buf, EBML_MAX_SIZE_LENGTH, 8);
+            put_string(buf, DOC_TYPE, "matroska");
+            put_uint(buf, DOC_TYPE_VERSION, 4);
+            put_uint(buf, DOC_TYPE_READ_VERSION, 2);
+        });
+
+        put_id(&mut buf, SEGMENT);
+        buf.extend_from_slice(&UNKNOWN_SIZE);
+
+        put_element(&mut buf, INFO, |buf| {
+            put_uint(buf, TIMESTAMP_SCALE, TIMESTAMP_SCALE_NS);
+            put_string(buf, MUXING_APP, "revc");
+            put_string(buf, WRITING_APP, "revce");
+        });
+        put_element(&mut buf, TRACKS, |buf| {
+            put_element(buf, TRACK_ENTRY, |buf| {
+                put_uint(buf, TRACK_NUMBER, EVC_TRACK_NUMBER);
+                put_uint(buf, TRACK_UID, EVC_TRACK_NUMBER);
+                put_uint(buf, TRACK_TYPE, TRACK_TYPE_VIDEO);
+                put_string(buf, CODEC_ID, CODEC_ID_EVC);
+                put_binary(buf, CODEC_PRIVATE, &record.write());
+                if frame_rate.num != 0 {
+                    put_uint(
+                        buf,
+                        DEFAULT_DURATION,
+                        frame_rate.den * 1_000_000_000 / frame_rate.num,
+                    );
+                }
+                put_element(buf, VIDEO, |buf| {
+                    put_uint(buf, PIXEL_WIDTH, config.width as u64);
+                    put_uint(buf, PIXEL_HEIGHT, config.height as u64);
+                });
+            });
+        });
+
+        self.writer.write_all(&buf)?;
+        self.header_written = true;
+
+        Ok(())
+    }
+
+    fn write_cluster(&mut self) -> io::Result<()> {
+        if !self.cluster.is_empty() {
+            let mut buf = vec![];
+            put_element(&mut buf, CLUSTER, |buf| {
+                put_uint(buf, TIMESTAMP, self.cluster_ts as u64);
+                buf.extend_from_slice(&self.cluster);
+            });
+            self.writer.write_all(&buf)?;
+            self.cluster.clear();
+        }
+        Ok(())
+    }
+}
+
+impl Muxer for MkvMuxer {
+    fn write(&mut self, data: Data, bitdepth: u8, frame_rate: Rational) -> io::Result<()> {
+        if let Data::RefPacket(pkt_data) = data {
+            let pkt = pkt_data.read().unwrap();
+
+            let mut frame = vec![];
+            let mut is_key = false;
+            for nalu in split_nalus(&pkt.data)? {
+                match (nalu_type(nalu), &mut self.config) {
+                    (NaluType::EVC_SPS_NUT, None) => {
+                        self.config = Some(EvcDecoderConfig::from_sps(nalu)?);
+                    }
+                    (NaluType::EVC_PPS_NUT, Some(config))
+                        if !self.header_written && config.pps.is_empty() =>
+                    {
+                        config.pps.push(nalu.to_vec());
+                    }
+                    (nal_unit_type, config) => {
+                        if config.as_mut().map_or(true, |config| config.in_band(nalu)) {
+                            is_key |= nal_unit_type == NaluType::EVC_IDR_NUT;
+                            EvcDecoderConfig::append_to_sample(&mut frame, nalu);
+                        }
+                    }
+                }
+            }
+
+            if !self.header_written {
+                self.write_header(frame_rate)?;
+            }
+            if frame.is_empty() {
+                return Ok(());
+            }
+
+            let ts = if frame_rate.num != 0 {
+                (pkt.ts * frame_rate.den * 1_000_000_000 / frame_rate.num / TIMESTAMP_SCALE_NS)
+                    as i64
+            } else {
+                0
+            };
+            let relative_ts = ts - self.cluster_ts;
+            if is_key
+                || self.cluster.is_empty()
+                || relative_ts < i16::MIN as i64
+                || relative_ts > i16::MAX as i64
+            {
+                self.write_cluster()?;
+                self.cluster_ts = ts;
+            }
+
+            let mut block = vec![0x80 | EVC_TRACK_NUMBER as u8];
+            block.extend_from_slice(&((ts - self.cluster_ts) as i16).to_be_bytes());
+            /* no lacing */
+            block.push(if is_key { 0x80 } else { 0x00 });
+            block.extend_from_slice(&frame);
+            put_binary(&mut self.cluster, SIMPLE_BLOCK, &block);
+
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid Packet Data for MkvMuxer",
+            ))
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_cluster()?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::demuxer;
+    use crate::io::evcc::test::*;
+
+    #[test]
+    fn mux_demux() {
+        let sps0 = sps(2, 64, 48);
+        let pps0 = nalu(NaluType::EVC_PPS_NUT, &[0x12, 0x34]);
+        let pps1 = nalu(NaluType::EVC_PPS_NUT, &[0x56, 0x78]);
+        let idr = nalu(NaluType::EVC_IDR_NUT, &[0x80; 200]);
+        let slice = nalu(NaluType::EVC_NONIDR_NUT, &[0x40; 20]);
+        let frame_rate = Rational::new(25, 1);
+
+        let path = std::env::temp_dir().join(format!("revc-mkv-{}.mkv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut muxer = MkvMuxer::new(path).unwrap();
+        muxer
+            .write(
+                packet(&[sps0.clone(), pps0.clone(), idr.clone()], 0),
+                8,
+                frame_rate,
+            )
+            .unwrap();
+        muxer
+            .write(packet(&[slice.clone()], 1), 8, frame_rate)
+            .unwrap();
+        /* a repeated SPS is dropped, a new PPS is carried in-band */
+        muxer
+            .write(
+                packet(&[sps0.clone(), pps1.clone(), idr.clone()], 2),
+                8,
+                frame_rate,
+            )
+            .unwrap();
+        muxer.flush().unwrap();
+        drop(muxer);
+
+        let mut demuxer = demuxer::new(path, None).unwrap();
+        let info = demuxer.info().unwrap();
+        assert_eq!((info.width, info.height), (64, 48));
+        assert_eq!(info.chroma_sampling, ChromaSampling::Cs422);
+        let mut nalus = vec![];
+        while let Ok(Data::Packet(Some(pkt))) = demuxer.read() {
+            nalus.push((pkt.data, pkt.ts));
+        }
+        std::fs::remove_file(path).unwrap();
+
+        /* timestamps are in milliseconds */
+        assert_eq!(
+            nalus,
+            [
+                (sps0, 0),
+                (pps0, 0),
+                (idr.clone(), 0),
+                (slice, 40),
+                (pps1, 80),
+                (idr, 80)
+            ]
+        );
+    }
+
+    #[test]
+    fn create_error() {
+        let path = std::env::temp_dir()
+            .join("revc-missing-dir")
+            .join("out.mkv");
+        assert!(MkvMuxer::new(path.to_str().unwrap()).is_err());
+    }
+}
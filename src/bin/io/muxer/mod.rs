@@ -1,3 +1,5 @@
+mod mkv;
+mod mp4;
 mod nalu;
 mod y4m;
 mod yuv;
@@ -5,6 +7,8 @@ mod yuv;
 use std::io;
 use std::path::Path;
 
+use self::mkv::MkvMuxer;
+use self::mp4::Mp4Muxer;
 use self::y4m::Y4mMuxer;
 use self::yuv::YuvMuxer;
 use crate::io::muxer::nalu::NaluMuxer;
@@ -12,6 +16,19 @@ use revc::api::*;
 
 pub trait Muxer {
     fn write(&mut self, data: Data, bitdepth: u8, frame_rate: Rational) -> io::Result<()>;
+
+    /* finishes the output after the last write */
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/* containers carry length prefixed NALUs in their samples */
+pub fn is_container(filename: &str) -> bool {
+    match Path::new(filename).extension() {
+        Some(ext) => ext == "mp4" || ext == "mkv",
+        None => false,
+    }
 }
 
 pub fn new(filename: &str) -> io::Result<Box<dyn Muxer>> {
@@ -20,6 +37,10 @@ pub fn new(filename: &str) -> io::Result<Box<dyn Muxer>> {
             Ok(Y4mMuxer::new(filename))
         } else if ext == "yuv" {
             Ok(YuvMuxer::new(filename))
+        } else if ext == "mp4" {
+            Mp4Muxer::new(filename)
+        } else if ext == "mkv" {
+            MkvMuxer::new(filename)
         } else {
             // .evc
             Ok(NaluMuxer::new(filename))
@@ -0,0 +1,402 @@
+use super::Muxer;
+use crate::io::evcc::*;
+use crate::Data;
+
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+use revc::api::*;
+
+/* an encoded sample in decoding order */
+struct Mp4Sample {
+    size: u32,
+    /* presentation time in frames */
+    ts: u64,
+    is_sync: bool,
+}
+
+/* ISO BMFF (MP4) muxer with a single EVC track ('evc1' sample entry). The
+samples are written to the mdat box as they come and the moov box follows on
+flush, when the size of the mdat box is patched. The parameter sets following
+the ones of the evcC record are carried in-band */
+pub struct Mp4Muxer {
+    writer: BufWriter<File>,
+    config: Option<EvcDecoderConfig>,
+    samples: Vec<Mp4Sample>,
+    frame_rate: Rational,
+    /* position of the mdat box and size of the box with the samples so far */
+    mdat_offset: u64,
+    mdat_size: u64,
+}
+
+impl Mp4Muxer {
+    pub fn new(path: &str) -> io::Result<Box<dyn Muxer>> {
+        if path == "-" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "MP4 output must be a file",
+            ));
+        }
+        Ok(Box::new(Mp4Muxer {
+            writer: BufWriter::new(File::create(&path)?),
+            config: None,
+            samples: vec![],
+            frame_rate: Rational::new(30, 1),
+            mdat_offset: 0,
+            mdat_size: 0,
+        }))
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let mut header = vec![];
+        write_box(&mut header, b"ftyp", |buf| {
+            buf.extend_from_slice(b"isom");
+            put_u32(buf, 0x200);
+            buf.extend_from_slice(b"isomiso6mp41evc1");
+        });
+        self.mdat_offset = header.len() as u64;
+        self.mdat_size = 16;
+
+        /* 64-bit largesize mdat header, the size is known on flush */
+        put_u32(&mut header, 1);
+        header.extend_from_slice(b"mdat");
+        header.extend_from_slice(&self.mdat_size.to_be_bytes());
+
+        self.writer.write_all(&header)
+    }
+
+    fn write_moov(&self, buf: &mut Vec<u8>, config: &EvcDecoderConfig, mdat_offset: u64) {
+        let timescale = self.frame_rate.num as u32;
+        let delta = self.frame_rate.den as u32;
+        let duration = self.samples.len() as u32 * delta;
+
+        /* the decoding time of a sample is its index; pictures displayed before
+        they are decoded shift all presentation times by a delay */
+        let delay = self
+            .samples
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (i as i64 - s.ts as i64).max(0))
+            .max()
+            .unwrap_or(0) as u32;
+        let ctts: Vec<u32> = self
+            .samples
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.ts as i64 + delay as i64 - i as i64) as u32 * delta)
+            .collect();
+
+        write_box(buf, b"moov", |buf| {
+            write_full_box(buf, b"mvhd", 0, 0, |buf| {
+                put_u32(buf, 0); // creation_time
+                put_u32(buf, 0); // modification_time
+                put_u32(buf, timescale);
+                put_u32(buf, duration);
+                put_u32(buf, 0x00010000); // rate
+                put_u16(buf, 0x0100); // volume
+                buf.extend_from_slice(&[0; 10]);
+                put_matrix(buf);
+                buf.extend_from_slice(&[0; 24]);
+                put_u32(buf, 2); // next_track_ID
+            });
+            write_box(buf, b"trak", |buf| {
+                /* track_enabled | track_in_movie */
+                write_full_box(buf, b"tkhd", 0, 3, |buf| {
+                    put_u32(buf, 0); // creation_time
+                    put_u32(buf, 0); // modification_time
+                    put_u32(buf, 1); // track_ID
+                    put_u32(buf, 0);
+                    put_u32(buf, duration);
+                    buf.extend_from_slice(&[0; 8]);
+                    put_u16(buf, 0); // layer
+                    put_u16(buf, 0); // alternate_group
+                    put_u16(buf, 0); // volume
+                    put_u16(buf, 0);
+                    put_matrix(buf);
+                    put_u32(buf, (config.width as u32) << 16);
+                    put_u32(buf, (config.height as u32) << 16);
+                });
+                if delay > 0 {
+                    write_box(buf, b"edts", |buf| {
+                        write_full_box(buf, b"elst", 0, 0, |buf| {
+                            put_u32(buf, 1); // entry_count
+                            put_u32(buf, duration); // segment_duration
+                            put_u32(buf, delay * delta); // media_time
+                            put_u32(buf, 0x00010000); // media_rate
+                        });
+                    });
+                }
+                write_box(buf, b"mdia", |buf| {
+                    write_full_box(buf, b"mdhd", 0, 0, |buf| {
+                        put_u32(buf, 0); // creation_time
+                        put_u32(buf, 0); // modification_time
+                        put_u32(buf, timescale);
+                        put_u32(buf, duration);
+                        put_u16(buf, 0x55C4); // language: und
+                        put_u16(buf, 0);
+                    });
+                    write_full_box(buf, b"hdlr", 0, 0, |buf| {
+                        put_u32(buf, 0);
+                        buf.extend_from_slice(b"vide");
+                        buf.extend_from_slice(&[0; 12]);
+                        buf.extend_from_slice(b"VideoHandler\0");
+                    });
+                    write_box(buf, b"minf", |buf| {
+                        write_full_box(buf, b"vmhd", 0, 1, |buf| {
+                            buf.extend_from_slice(&[0; 8]);
+                        });
+                        write_box(buf, b"dinf", |buf| {
+                            write_full_box(buf, b"dref", 0, 0, |buf| {
+                                put_u32(buf, 1);
+                                /* media data is in the same file */
+                                write_full_box(buf, b"url ", 0, 1, |_| {});
+                            });
+                        });
+                        self.write_stbl(buf, config, &ctts, mdat_offset);
+                    });
+                });
+            });
+        });
+    }
+
+    fn write_stbl(
+        &self,
+        buf: &mut Vec<u8>,
+        config: &EvcDecoderConfig,
+        ctts: &[u32],
+        mdat_offset: u64,
+    ) {
+        write_box(buf, b"stbl", |buf| {
+            write_full_box(buf, b"stsd", 0, 0, |buf| {
+                put_u32(buf, 1);
+                write_box(buf, b"evc1", |buf| {
+                    buf.extend_from_slice(&[0; 6]);
+                    put_u16(buf, 1); // data_reference_index
+                    buf.extend_from_slice(&[0; 16]);
+                    put_u16(buf, config.width);
+                    put_u16(buf, config.height);
+                    put_u32(buf, 0x00480000); // horizresolution: 72 dpi
+                    put_u32(buf, 0x00480000); // vertresolution: 72 dpi
+                    put_u32(buf, 0);
+                    put_u16(buf, 1); // frame_count
+                    buf.extend_from_slice(&[0; 32]); // compressorname
+                    put_u16(buf, 0x0018); // depth
+                    put_u16(buf, 0xFFFF); // pre_defined = -1
+                    write_box(buf, b"evcC", |buf| buf.extend_from_slice(&config.write()));
+                });
+            });
+            write_full_box(buf, b"stts", 0, 0, |buf| {
+                put_u32(buf, 1);
+                put_u32(buf, self.samples.len() as u32);
+                put_u32(buf, self.frame_rate.den as u32);
+            });
+            if ctts.iter().any(|&offset| offset != 0) {
+                write_full_box(buf, b"ctts", 0, 0, |buf| {
+                    put_u32(buf, ctts.len() as u32);
+                    for &offset in ctts {
+                        put_u32(buf, 1);
+                        put_u32(buf, offset);
+                    }
+                });
+            }
+            if self.samples.iter().any(|s| !s.is_sync) {
+                write_full_box(buf, b"stss", 0, 0, |buf| {
+                    let sync: Vec<u32> = (1..=self.samples.len() as u32)
+                        .filter(|&i| self.samples[i as usize - 1].is_sync)
+                        .collect();
+                    put_u32(buf, sync.len() as u32);
+                    for i in sync {
+                        put_u32(buf, i);
+                    }
+                });
+            }
+            /* all samples are in a single chunk */
+            write_full_box(buf, b"stsc", 0, 0, |buf| {
+                put_u32(buf, 1);
+                put_u32(buf, 1); // first_chunk
+                put_u32(buf, self.samples.len() as u32); // samples_per_chunk
+                put_u32(buf, 1); // sample_description_index
+            });
+            write_full_box(buf, b"stsz", 0, 0, |buf| {
+                put_u32(buf, 0);
+                put_u32(buf, self.samples.len() as u32);
+                for s in &self.samples {
+                    put_u32(buf, s.size);
+                }
+            });
+            write_full_box(buf, b"co64", 0, 0, |buf| {
+                put_u32(buf, 1);
+                buf.extend_from_slice(&mdat_offset.to_be_bytes());
+            });
+        });
+    }
+}
+
+impl Muxer for Mp4Muxer {
+    fn write(&mut self, data: Data, bitdepth: u8, frame_rate: Rational) -> io::Result<()> {
+        if let Data::RefPacket(pkt_data) = data {
+            let pkt = pkt_data.read().unwrap();
+            self.frame_rate = frame_rate;
+
+            let mut sample = vec![];
+            let mut is_sync = false;
+            for nalu in split_nalus(&pkt.data)? {
+                match (nalu_type(nalu), &mut self.config) {
+                    (NaluType::EVC_SPS_NUT, None) => {
+                        self.config = Some(EvcDecoderConfig::from_sps(nalu)?);
+                        self.write_header()?;
+                    }
+                    (NaluType::EVC_PPS_NUT, Some(config)) if config.pps.is_empty() => {
+                        config.pps.push(nalu.to_vec());
+                    }
+                    (_, None) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "No SPS for Mp4Muxer",
+                        ))
+                    }
+                    (nal_unit_type, Some(config)) => {
+                        if config.in_band(nalu) {
+                            is_sync |= nal_unit_type == NaluType::EVC_IDR_NUT;
+                            EvcDecoderConfig::append_to_sample(&mut sample, nalu);
+                        }
+                    }
+                }
+            }
+
+            if !sample.is_empty() {
+                self.writer.write_all(&sample)?;
+                self.mdat_size += sample.len() as u64;
+                self.samples.push(Mp4Sample {
+                    size: sample.len() as u32,
+                    ts: pkt.ts,
+                    is_sync,
+                });
+            }
+
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid Packet Data for Mp4Muxer",
+            ))
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let config = self
+            .config
+            .take()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No SPS for Mp4Muxer"))?;
+
+        self.writer.seek(SeekFrom::Start(self.mdat_offset + 8))?;
+        self.writer.write_all(&self.mdat_size.to_be_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+
+        let mut moov = vec![];
+        self.write_moov(&mut moov, &config, self.mdat_offset + 16);
+        self.writer.write_all(&moov)?;
+        self.samples.clear();
+
+        self.writer.flush()
+    }
+}
+
+fn put_u16(buf: &mut Vec<u8>, val: u16) {
+    buf.extend_from_slice(&val.to_be_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, val: u32) {
+    buf.extend_from_slice(&val.to_be_bytes());
+}
+
+/* unity transformation matrix */
+fn put_matrix(buf: &mut Vec<u8>) {
+    for &val in &[0x00010000, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000] {
+        put_u32(buf, val);
+    }
+}
+
+fn write_box<F: FnOnce(&mut Vec<u8>)>(buf: &mut Vec<u8>, fourcc: &[u8; 4], f: F) {
+    let start = buf.len();
+    put_u32(buf, 0);
+    buf.extend_from_slice(fourcc);
+    f(buf);
+    let size = (buf.len() - start) as u32;
+    buf[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+fn write_full_box<F: FnOnce(&mut Vec<u8>)>(
+    buf: &mut Vec<u8>,
+    fourcc: &[u8; 4],
+    version: u8,
+    flags: u32,
+    f: F,
+) {
+    write_box(buf, fourcc, |buf| {
+        put_u32(buf, (version as u32) << 24 | flags);
+        f(buf);
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::demuxer;
+    use crate::io::evcc::test::*;
+
+    #[test]
+    fn mux_demux() {
+        let sps0 = sps(1, 64, 48);
+        let pps0 = nalu(NaluType::EVC_PPS_NUT, &[0x12, 0x34]);
+        let pps1 = nalu(NaluType::EVC_PPS_NUT, &[0x56, 0x78]);
+        let idr = nalu(NaluType::EVC_IDR_NUT, &[0x80; 100]);
+        let slice = nalu(NaluType::EVC_NONIDR_NUT, &[0x40; 20]);
+        let frame_rate = Rational::new(30, 1);
+
+        let path = std::env::temp_dir().join(format!("revc-mp4-{}.mp4", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut muxer = Mp4Muxer::new(path).unwrap();
+        muxer
+            .write(
+                packet(&[sps0.clone(), pps0.clone(), idr.clone()], 0),
+                8,
+                frame_rate,
+            )
+            .unwrap();
+        muxer
+            .write(packet(&[slice.clone()], 1), 8, frame_rate)
+            .unwrap();
+        /* a repeated SPS is dropped, a new PPS is carried in-band */
+        muxer
+            .write(
+                packet(&[sps0.clone(), pps1.clone(), idr.clone()], 2),
+                8,
+                frame_rate,
+            )
+            .unwrap();
+        muxer.flush().unwrap();
+
+        let mut demuxer = demuxer::new(path, None).unwrap();
+        let info = demuxer.info().unwrap();
+        assert_eq!((info.width, info.height), (64, 48));
+        let mut nalus = vec![];
+        while let Ok(Data::Packet(Some(pkt))) = demuxer.read() {
+            nalus.push((pkt.data, pkt.ts));
+        }
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            nalus,
+            [
+                (sps0, 0),
+                (pps0, 0),
+                (idr.clone(), 0),
+                (slice, 1),
+                (pps1, 2),
+                (idr, 2)
+            ]
+        );
+    }
+}
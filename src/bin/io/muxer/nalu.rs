@@ -28,8 +28,8 @@ impl NaluMuxer {
 impl Muxer for NaluMuxer {
     fn write(&mut self, data: Data, bitdepth: u8, frame_rate: Rational) -> io::Result<()> {
         if let Data::RefPacket(pkt_data) = data {
-            let pkt = pkt_data.borrow();
-            self.writer.write(&pkt.data)?;
+            let pkt = pkt_data.read().unwrap();
+            self.writer.write_all(&pkt.data)?;
 
             Ok(())
         } else {
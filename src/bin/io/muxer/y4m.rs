@@ -8,7 +8,7 @@ use std::io::Write;
 use std::slice;
 
 use revc::api::frame::*;
-use revc::api::Rational;
+use revc::api::{ChromaSampling, Rational};
 
 pub enum Y4mMuxer {
     writer(Option<Box<dyn Write>>),
@@ -29,7 +29,7 @@ impl Muxer for Y4mMuxer {
         if let Y4mMuxer::writer(writer) = self {
             if let Some(writer) = writer.take() {
                 if let Data::RefFrame(frame) = &data {
-                    let f = frame.borrow();
+                    let f = frame.read().unwrap();
                     let width = f.planes[0].cfg.width;
                     let height = f.planes[0].cfg.height;
                     *self = Y4mMuxer::encoder(
@@ -38,6 +38,7 @@ impl Muxer for Y4mMuxer {
                             height,
                             y4m::Ratio::new(frame_rate.num as usize, frame_rate.den as usize),
                         )
+                        .with_colorspace(map_chroma_sampling(f.chroma_sampling))
                         .write_header(writer)
                         .map_err(|e| map_y4m_error(e))?,
                     );
@@ -46,15 +47,18 @@ impl Muxer for Y4mMuxer {
         }
 
         if let (Data::RefFrame(frame), Y4mMuxer::encoder(encoder)) = (&data, self) {
-            let f = frame.borrow();
+            let f = frame.read().unwrap();
             let bytes_per_sample = if bit_depth > 8 { 2 } else { 1 };
             let pitch_y = f.planes[0].cfg.width * bytes_per_sample;
             let height = f.planes[0].cfg.height;
-            let chroma_sampling_period = f.chroma_sampling.sampling_period();
-            let (pitch_uv, height_uv) = (
-                (pitch_y * bytes_per_sample) / chroma_sampling_period.0,
-                height / chroma_sampling_period.1,
-            );
+            let (pitch_uv, height_uv) = if f.chroma_sampling == ChromaSampling::Cs400 {
+                (0, 0)
+            } else {
+                (
+                    f.planes[1].cfg.width * bytes_per_sample,
+                    f.planes[1].cfg.height,
+                )
+            };
 
             let (mut rec_y, mut rec_u, mut rec_v) = (
                 vec![128u8; pitch_y * height],
@@ -89,46 +93,49 @@ impl Muxer for Y4mMuxer {
                     );
                 }
             }
-            for (line, line_out) in f.planes[1]
-                .data_origin()
-                .chunks(stride_u)
-                .zip(rec_u.chunks_mut(pitch_uv))
-            {
-                if bit_depth > 8 {
-                    unsafe {
-                        line_out.copy_from_slice(slice::from_raw_parts::<u8>(
-                            line.as_ptr() as *const u8,
-                            pitch_uv,
-                        ));
+            /* monochrome pictures carry no chroma planes */
+            if f.chroma_sampling != ChromaSampling::Cs400 {
+                for (line, line_out) in f.planes[1]
+                    .data_origin()
+                    .chunks(stride_u)
+                    .zip(rec_u.chunks_mut(pitch_uv))
+                {
+                    if bit_depth > 8 {
+                        unsafe {
+                            line_out.copy_from_slice(slice::from_raw_parts::<u8>(
+                                line.as_ptr() as *const u8,
+                                pitch_uv,
+                            ));
+                        }
+                    } else {
+                        line_out.copy_from_slice(
+                            &line
+                                .iter()
+                                .map(|&v| u8::cast_from(IFVCA_CLIP(0, 255, (v + 2) >> 2)))
+                                .collect::<Vec<u8>>()[..pitch_uv],
+                        );
                     }
-                } else {
-                    line_out.copy_from_slice(
-                        &line
-                            .iter()
-                            .map(|&v| u8::cast_from(IFVCA_CLIP(0, 255, (v + 2) >> 2)))
-                            .collect::<Vec<u8>>()[..pitch_uv],
-                    );
                 }
-            }
-            for (line, line_out) in f.planes[2]
-                .data_origin()
-                .chunks(stride_v)
-                .zip(rec_v.chunks_mut(pitch_uv))
-            {
-                if bit_depth > 8 {
-                    unsafe {
-                        line_out.copy_from_slice(slice::from_raw_parts::<u8>(
-                            line.as_ptr() as *const u8,
-                            pitch_uv,
-                        ));
+                for (line, line_out) in f.planes[2]
+                    .data_origin()
+                    .chunks(stride_v)
+                    .zip(rec_v.chunks_mut(pitch_uv))
+                {
+                    if bit_depth > 8 {
+                        unsafe {
+                            line_out.copy_from_slice(slice::from_raw_parts::<u8>(
+                                line.as_ptr() as *const u8,
+                                pitch_uv,
+                            ));
+                        }
+                    } else {
+                        line_out.copy_from_slice(
+                            &line
+                                .iter()
+                                .map(|&v| u8::cast_from(IFVCA_CLIP(0, 255, (v + 2) >> 2)))
+                                .collect::<Vec<u8>>()[..pitch_uv],
+                        );
                     }
-                } else {
-                    line_out.copy_from_slice(
-                        &line
-                            .iter()
-                            .map(|&v| u8::cast_from(IFVCA_CLIP(0, 255, (v + 2) >> 2)))
-                            .collect::<Vec<u8>>()[..pitch_uv],
-                    );
                 }
             }
 
@@ -146,3 +153,14 @@ impl Muxer for Y4mMuxer {
         }
     }
 }
+
+fn map_chroma_sampling(chroma_sampling: ChromaSampling) -> y4m::Colorspace {
+    use y4m::Colorspace::*;
+    use ChromaSampling::*;
+    match chroma_sampling {
+        Cs400 => Cmono,
+        Cs420 => C420,
+        Cs422 => C422,
+        Cs444 => C444,
+    }
+}
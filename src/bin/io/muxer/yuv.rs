@@ -7,7 +7,7 @@ use std::io::Write;
 use std::slice;
 
 use revc::api::frame::*;
-use revc::api::Rational;
+use revc::api::{ChromaSampling, Rational};
 
 pub struct YuvMuxer {
     writer: Box<dyn Write>,
@@ -27,15 +27,18 @@ impl YuvMuxer {
 impl Muxer for YuvMuxer {
     fn write(&mut self, data: Data, bitdepth: u8, frame_rate: Rational) -> io::Result<()> {
         if let Data::RefFrame(frame) = &data {
-            let f = frame.borrow();
+            let f = frame.read().unwrap();
             let bytes_per_sample = if bitdepth > 8 { 2 } else { 1 };
             let pitch_y = f.planes[0].cfg.width * bytes_per_sample;
             let height = f.planes[0].cfg.height;
-            let chroma_sampling_period = f.chroma_sampling.sampling_period();
-            let (pitch_uv, height_uv) = (
-                (pitch_y * bytes_per_sample) / chroma_sampling_period.0,
-                height / chroma_sampling_period.1,
-            );
+            let (pitch_uv, height_uv) = if f.chroma_sampling == ChromaSampling::Cs400 {
+                (0, 0)
+            } else {
+                (
+                    f.planes[1].cfg.width * bytes_per_sample,
+                    f.planes[1].cfg.height,
+                )
+            };
 
             let (mut rec_y, mut rec_u, mut rec_v) = (
                 vec![128u8; pitch_y * height],
@@ -70,46 +73,49 @@ impl Muxer for YuvMuxer {
                     );
                 }
             }
-            for (line, line_out) in f.planes[1]
-                .data_origin()
-                .chunks(stride_u)
-                .zip(rec_u.chunks_mut(pitch_uv))
-            {
-                if bitdepth > 8 {
-                    unsafe {
-                        line_out.copy_from_slice(slice::from_raw_parts::<u8>(
-                            line.as_ptr() as *const u8,
-                            pitch_uv,
-                        ));
+            /* monochrome pictures carry no chroma planes */
+            if f.chroma_sampling != ChromaSampling::Cs400 {
+                for (line, line_out) in f.planes[1]
+                    .data_origin()
+                    .chunks(stride_u)
+                    .zip(rec_u.chunks_mut(pitch_uv))
+                {
+                    if bitdepth > 8 {
+                        unsafe {
+                            line_out.copy_from_slice(slice::from_raw_parts::<u8>(
+                                line.as_ptr() as *const u8,
+                                pitch_uv,
+                            ));
+                        }
+                    } else {
+                        line_out.copy_from_slice(
+                            &line
+                                .iter()
+                                .map(|&v| u8::cast_from(IFVCA_CLIP(0, 255, (v + 2) >> 2)))
+                                .collect::<Vec<u8>>()[..pitch_uv],
+                        );
                     }
-                } else {
-                    line_out.copy_from_slice(
-                        &line
-                            .iter()
-                            .map(|&v| u8::cast_from(IFVCA_CLIP(0, 255, (v + 2) >> 2)))
-                            .collect::<Vec<u8>>()[..pitch_uv],
-                    );
                 }
-            }
-            for (line, line_out) in f.planes[2]
-                .data_origin()
-                .chunks(stride_v)
-                .zip(rec_v.chunks_mut(pitch_uv))
-            {
-                if bitdepth > 8 {
-                    unsafe {
-                        line_out.copy_from_slice(slice::from_raw_parts::<u8>(
-                            line.as_ptr() as *const u8,
-                            pitch_uv,
-                        ));
+                for (line, line_out) in f.planes[2]
+                    .data_origin()
+                    .chunks(stride_v)
+                    .zip(rec_v.chunks_mut(pitch_uv))
+                {
+                    if bitdepth > 8 {
+                        unsafe {
+                            line_out.copy_from_slice(slice::from_raw_parts::<u8>(
+                                line.as_ptr() as *const u8,
+                                pitch_uv,
+                            ));
+                        }
+                    } else {
+                        line_out.copy_from_slice(
+                            &line
+                                .iter()
+                                .map(|&v| u8::cast_from(IFVCA_CLIP(0, 255, (v + 2) >> 2)))
+                                .collect::<Vec<u8>>()[..pitch_uv],
+                        );
                     }
-                } else {
-                    line_out.copy_from_slice(
-                        &line
-                            .iter()
-                            .map(|&v| u8::cast_from(IFVCA_CLIP(0, 255, (v + 2) >> 2)))
-                            .collect::<Vec<u8>>()[..pitch_uv],
-                    );
                 }
             }
 
@@ -124,6 +124,14 @@ fn print_stat(stat: &EvcStat, bs_cnt: usize) {
         eprint!("Adaptation Parameter Set ({} bytes)", stat.bytes);
     } else if stat.nalu_type == NaluType::EVC_SEI_NUT {
         eprint!("SEI message ({} bytes)", stat.bytes);
+        match stat.hash_match {
+            Some(true) => eprint!(": picture hash check OK"),
+            Some(false) => eprint!(": picture hash mismatch!"),
+            None => {}
+        }
+    }
+    if stat.concealed {
+        eprint!(" concealed");
     }
     eprint!("\n");
 }
@@ -171,6 +179,7 @@ fn main() -> std::io::Result<()> {
     let mut pic_ocnt: usize = 0;
     let mut clk_tot = 0;
     let mut bs_cnt = 0;
+    let mut hash_mismatch_cnt = 0;
     let mut w = 0;
     let mut h = 0;
 
@@ -219,13 +228,19 @@ fn main() -> std::io::Result<()> {
                     if cli.verbose {
                         print_stat(&stat, bs_cnt);
                     }
+                    if stat.hash_match == Some(false) {
+                        hash_mismatch_cnt += 1;
+                    }
                     bs_cnt += 1;
                 }
 
                 let has_frame = if let Data::RefFrame(frame) = &data {
-                    let f = frame.borrow();
+                    let f = frame.read().unwrap();
                     w = f.planes[0].cfg.width;
                     h = f.planes[0].cfg.height;
+                    if cli.verbose && f.concealed {
+                        eprint!("output frame {} concealed\n", pic_ocnt);
+                    }
                     true
                 } else {
                     false
@@ -241,6 +256,12 @@ fn main() -> std::io::Result<()> {
                     if cli.verbose {
                         eprint!("bumping process completed\n");
                     }
+                } else if state == EvcdState::STATE_DECODING {
+                    /* the NALU is dropped, what it should have decoded is
+                    concealed by the decoder */
+                    eprint!("Decoding error = {:?}\n", err);
+                    bs_cnt += 1;
+                    continue;
                 } else {
                     eprint!("failed to pull the decoded frame\n");
                 }
@@ -253,5 +274,12 @@ fn main() -> std::io::Result<()> {
         print_summary(w, h, bs_cnt, pic_ocnt, clk_tot);
     }
 
+    if hash_mismatch_cnt > 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} decoded picture hash mismatches", hash_mismatch_cnt),
+        ));
+    }
+
     Ok(())
 }
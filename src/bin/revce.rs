@@ -8,11 +8,10 @@ mod io;
 
 use clap::{App, AppSettings, Arg, ArgMatches};
 
-use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::prelude::*;
-use std::rc::Rc;
+use std::sync::{Arc, RwLock};
 use std::time::Instant;
 
 use io::demuxer::VideoInfo;
@@ -25,9 +24,11 @@ struct CLISettings {
     demuxer: Box<dyn demuxer::Demuxer>,
     muxer: Box<dyn muxer::Muxer>,
     rec: Option<Box<dyn muxer::Muxer>>,
+    stats: Option<String>,
     enc: EncoderConfig,
     frames: usize,
     skip: usize,
+    ltr_frame: Option<usize>,
     verbose: bool,
     threads: usize,
     bitdepth: u8,
@@ -103,7 +104,12 @@ fn parse_config(matches: &ArgMatches<'_>) -> std::io::Result<EncoderConfig> {
         .unwrap_or("8")
         .parse()
         .unwrap();
-    cfg.chroma_sampling = ChromaSampling::Cs420;
+    cfg.chroma_sampling = match matches.value_of("CHROMA_FORMAT").unwrap() {
+        "400" => ChromaSampling::Cs400,
+        "420" => ChromaSampling::Cs420,
+        "422" => ChromaSampling::Cs422,
+        _ => ChromaSampling::Cs444,
+    };
     cfg.min_key_frame_interval = min_interval;
     // Map an input value of 0 to an infinite interval
     cfg.max_key_frame_interval = max_interval;
@@ -112,6 +118,24 @@ fn parse_config(matches: &ArgMatches<'_>) -> std::io::Result<EncoderConfig> {
     cfg.max_qp = matches.value_of("MAXQP").unwrap_or("0").parse().unwrap();
     cfg.min_qp = matches.value_of("MINQP").unwrap_or("0").parse().unwrap();
     cfg.bitrate = bitrate.checked_mul(1000).expect("Bitrate too high");
+    cfg.rc_mode = if bitrate == 0 {
+        RateControlMode::ConstantQP
+    } else {
+        match matches.value_of("RC_MODE").unwrap_or("abr") {
+            "cbr" => RateControlMode::ConstantBitrate,
+            _ => RateControlMode::AverageBitrate,
+        }
+    };
+    cfg.rc_pass = match matches.value_of("PASS") {
+        Some("1") => RateControlPass::First,
+        Some("2") => RateControlPass::Second,
+        _ => RateControlPass::Single,
+    };
+    cfg.vbv_buffer_size = matches
+        .value_of_int("VBV_BUFSIZE")
+        .unwrap_or(Ok(0))?
+        .checked_mul(1000)
+        .expect("VBV buffer size too high");
 
     cfg.cb_qp_offset = matches
         .value_of("CB_QP_OFFSET")
@@ -146,11 +170,40 @@ fn parse_config(matches: &ArgMatches<'_>) -> std::io::Result<EncoderConfig> {
     cfg.disable_hgop = matches.is_present("DISABLE_HGOP");
     cfg.enable_cip = matches.is_present("ENABLE_CIP");
     cfg.disable_dbf = matches.is_present("DISABLE_DBF");
+    cfg.btt = matches.is_present("BTT");
+    cfg.suco = matches.is_present("SUCO");
+    cfg.alf = matches.is_present("ALF");
+    cfg.addb = matches.is_present("ADDB");
+    cfg.hmvp = matches.is_present("HMVP");
+    cfg.affine = matches.is_present("AFFINE");
+    cfg.dmvr = matches.is_present("DMVR");
+    cfg.amvr = matches.is_present("AMVR");
+    cfg.mmvd = matches.is_present("MMVD");
+    cfg.admvp =
+        matches.is_present("ADMVP") || cfg.hmvp || cfg.affine || cfg.dmvr || cfg.amvr || cfg.mmvd;
+    cfg.eipd = matches.is_present("EIPD");
+    cfg.ats = matches.is_present("ATS");
+    cfg.iqt = matches.is_present("IQT") || cfg.ats;
+    cfg.adcc = matches.is_present("ADCC");
+    cfg.cm_init = matches.is_present("CM_INIT") || cfg.adcc;
+    cfg.ltr = matches.is_present("LTR_FRAME");
+    cfg.rpl = matches.is_present("RPL") || cfg.ltr;
+    cfg.pocs = matches.is_present("POCS") || cfg.ltr;
     cfg.num_slices_in_pic = matches
         .value_of("NUM_SLICES_IN_PIC")
         .unwrap_or("1")
         .parse()
         .unwrap();
+    cfg.annexb = matches.is_present("ANNEXB");
+    cfg.picture_hash = if matches.is_present("SIGNATURES") {
+        match matches.value_of("SIGNATURES").unwrap_or("md5") {
+            "crc" => PictureHash::CRC,
+            "checksum" => PictureHash::Checksum,
+            _ => PictureHash::MD5,
+        }
+    } else {
+        PictureHash::None
+    };
     cfg.inter_slice_type = if matches
         .value_of("INTER_SLICE_TYPE")
         .unwrap_or("0")
@@ -231,6 +284,14 @@ fn parse_cli() -> std::io::Result<CLISettings> {
                 .takes_value(true)
                 .default_value("8"),
         )
+        .arg(
+            Arg::with_name("CHROMA_FORMAT")
+                .help("chroma format of raw input video (400, 420, 422, 444)")
+                .long("chroma-format")
+                .takes_value(true)
+                .possible_values(&["400", "420", "422", "444"])
+                .default_value("420"),
+        )
         .arg(
             Arg::with_name("BITRATE")
                 .help("Bitrate (kbps)")
@@ -238,6 +299,38 @@ fn parse_cli() -> std::io::Result<CLISettings> {
                 .long("bitrate")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("RC_MODE")
+                .help("Rate control mode in bitrate mode")
+                .long("rc-mode")
+                .takes_value(true)
+                .possible_values(&["abr", "cbr"])
+                .default_value("abr"),
+        )
+        .arg(
+            Arg::with_name("VBV_BUFSIZE")
+                .help(
+                    "VBV buffer size (kbits) in bitrate mode, 0: one second for cbr, none for abr",
+                )
+                .long("vbv-bufsize")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("PASS")
+                .help(
+                    "Pass of the two-pass encoding in bitrate mode (1: write stats, 2: read stats)",
+                )
+                .long("pass")
+                .takes_value(true)
+                .possible_values(&["1", "2"])
+                .requires("STATS"),
+        )
+        .arg(
+            Arg::with_name("STATS")
+                .help("file name of the two-pass statistics")
+                .long("stats")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("MINQP")
                 .help("Minimum quantizer (0-51) to use in bitrate mode")
@@ -337,7 +430,10 @@ fn parse_cli() -> std::io::Result<CLISettings> {
             Arg::with_name("SIGNATURES")
                 .help("embed picture signature (HASH) for conformance checking in decoding")
                 .short("s")
-                .long("signature"),
+                .long("signature")
+                .takes_value(true)
+                .min_values(0)
+                .possible_values(&["md5", "crc", "checksum"]),
         )
         .arg(
             Arg::with_name("REF_PIC_GAP_LENGTH")
@@ -381,11 +477,109 @@ fn parse_cli() -> std::io::Result<CLISettings> {
         )
         .arg(
             Arg::with_name("NUM_SLICES_IN_PIC")
-                .help("Number of slices in the pic")
+                .help("Number of slices in the pic, encoded in parallel with --threads")
                 .long("num_slices_in_pic")
                 .takes_value(true)
                 .default_value("1"),
         )
+        .arg(
+            Arg::with_name("BTT")
+                .help("Enable binary and ternary tree splits of the coding tree")
+                .long("btt"),
+        )
+        .arg(
+            Arg::with_name("SUCO")
+                .help("Enable the split unit coding order (right-to-left coding of split blocks)")
+                .long("suco"),
+        )
+        .arg(
+            Arg::with_name("ALF")
+                .help("Enable the adaptive loop filter")
+                .long("alf"),
+        )
+        .arg(
+            Arg::with_name("ADDB")
+                .help("Enable the advanced deblocking filter")
+                .long("addb"),
+        )
+        .arg(
+            Arg::with_name("ADMVP")
+                .help(
+                    "Enable the advanced motion vector prediction (merge and AMVP candidate lists)",
+                )
+                .long("admvp"),
+        )
+        .arg(
+            Arg::with_name("HMVP")
+                .help("Enable the history-based motion vector prediction (implies --admvp)")
+                .long("hmvp"),
+        )
+        .arg(
+            Arg::with_name("AFFINE")
+                .help("Enable the affine motion compensation (implies --admvp)")
+                .long("affine"),
+        )
+        .arg(
+            Arg::with_name("DMVR")
+                .help("Enable the decoder-side motion vector refinement (implies --admvp)")
+                .long("dmvr"),
+        )
+        .arg(
+            Arg::with_name("AMVR")
+                .help("Enable the adaptive motion vector resolution (implies --admvp)")
+                .long("amvr"),
+        )
+        .arg(
+            Arg::with_name("MMVD")
+                .help("Enable the merge with motion vector difference (implies --admvp)")
+                .long("mmvd"),
+        )
+        .arg(
+            Arg::with_name("EIPD")
+                .help("Enable the extended intra prediction modes")
+                .long("eipd"),
+        )
+        .arg(
+            Arg::with_name("IQT")
+                .help("Enable the improved quantization and inverse transform")
+                .long("iqt"),
+        )
+        .arg(
+            Arg::with_name("ATS")
+                .help("Enable the adaptive transform selection (implies --iqt)")
+                .long("ats"),
+        )
+        .arg(
+            Arg::with_name("CM_INIT")
+                .help("Initialise the contexts from the slice QP and type")
+                .long("cm_init"),
+        )
+        .arg(
+            Arg::with_name("ADCC")
+                .help("Enable the advanced coefficient coding (implies --cm_init)")
+                .long("adcc"),
+        )
+        .arg(
+            Arg::with_name("RPL")
+                .help("Signal the reference picture lists explicitly in the slice headers")
+                .long("rpl"),
+        )
+        .arg(
+            Arg::with_name("POCS")
+                .help("Signal the POC LSBs in the slice headers")
+                .long("pocs"),
+        )
+        .arg(
+            Arg::with_name("LTR_FRAME")
+                .help("Keep the n-th frame as long-term reference picture (implies rpl and pocs)")
+                .long("ltr_frame")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ANNEXB")
+                .help("Write an Annex-B byte stream (start code prefixed NALUs)")
+                .long("annexb"),
+        )
         .arg(
             Arg::with_name("INTER_SLICE_TYPE")
                 .help("Inter slice type (0: SLICE_B 1: SLICE_P)")
@@ -413,6 +607,13 @@ fn parse_cli() -> std::io::Result<CLISettings> {
     };
 
     let enc = parse_config(&matches)?;
+    let output = matches.value_of("OUTPUT").unwrap();
+    if enc.annexb && muxer::is_container(output) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Annex-B byte streams can't be written to MP4 or Matroska containers",
+        ));
+    }
     let info = Some(VideoInfo {
         width: enc.width,
         height: enc.height,
@@ -423,11 +624,15 @@ fn parse_cli() -> std::io::Result<CLISettings> {
 
     Ok(CLISettings {
         demuxer: demuxer::new(matches.value_of("INPUT").unwrap(), info)?,
-        muxer: muxer::new(matches.value_of("OUTPUT").unwrap())?,
+        muxer: muxer::new(output)?,
         rec,
+        stats: matches.value_of("STATS").map(|s| s.to_owned()),
         enc,
         frames: matches.value_of("FRAMES").unwrap().parse().unwrap(),
         skip: matches.value_of("SKIP").unwrap().parse().unwrap(),
+        ltr_frame: matches
+            .value_of("LTR_FRAME")
+            .map(|v| v.parse().expect("LTR frame must be an integer")),
         verbose: matches.is_present("VERBOSE"),
         threads: matches
             .value_of("THREADS")
@@ -442,7 +647,7 @@ fn parse_cli() -> std::io::Result<CLISettings> {
 
 fn write_rec_frames(
     cli: &mut CLISettings,
-    map_rec: &mut BTreeMap<u64, Option<Rc<RefCell<Frame<u16>>>>>,
+    map_rec: &mut BTreeMap<u64, Option<Arc<RwLock<Frame<u16>>>>>,
     pic_ocnt: &mut u64,
 ) -> std::io::Result<()> {
     for (ts, rec) in map_rec.iter_mut() {
@@ -487,7 +692,26 @@ fn print_config(cli: &CLISettings) {
         "\tintra picture period     = {}\n",
         cli.enc.max_key_frame_interval
     );
-    eprint!("\tQP                       = {}\n", cli.enc.qp);
+    match cli.enc.rc_mode {
+        RateControlMode::ConstantQP => {
+            eprint!("\tQP                       = {}\n", cli.enc.qp);
+        }
+        rc_mode => {
+            eprint!(
+                "\trate control             = {}\n",
+                if rc_mode == RateControlMode::ConstantBitrate {
+                    "CBR"
+                } else {
+                    "ABR"
+                }
+            );
+            eprint!("\tbitrate(kbps)            = {}\n", cli.enc.bitrate / 1000);
+            eprint!(
+                "\tQP range                 = {}..{}\n",
+                cli.enc.min_qp, cli.enc.max_qp
+            );
+        }
+    }
 
     eprint!("\tframes                   = {}\n", cli.frames);
     eprint!(
@@ -511,6 +735,63 @@ fn print_config(cli: &CLISettings) {
             "disabled"
         }
     );
+    if cli.enc.btt {
+        eprint!("\tbinary/ternary splits    = enabled\n");
+    }
+    if cli.enc.suco {
+        eprint!("\tsplit unit coding order  = enabled\n");
+    }
+    if cli.enc.alf {
+        eprint!("\tadaptive loop filter     = enabled\n");
+    }
+    if cli.enc.addb {
+        eprint!("\tadvanced deblocking      = enabled\n");
+    }
+    if cli.enc.admvp {
+        eprint!("\tadvanced mv prediction   = enabled\n");
+    }
+    if cli.enc.hmvp {
+        eprint!("\thistory-based mvp        = enabled\n");
+    }
+    if cli.enc.affine {
+        eprint!("\taffine motion            = enabled\n");
+    }
+    if cli.enc.dmvr {
+        eprint!("\tdecoder-side mv refine   = enabled\n");
+    }
+    if cli.enc.amvr {
+        eprint!("\tadaptive mv resolution   = enabled\n");
+    }
+    if cli.enc.mmvd {
+        eprint!("\tmerge with mvd           = enabled\n");
+    }
+    if cli.enc.eipd {
+        eprint!("\textended intra modes     = enabled\n");
+    }
+    if cli.enc.iqt {
+        eprint!("\timproved quantization    = enabled\n");
+    }
+    if cli.enc.ats {
+        eprint!("\tadaptive transforms      = enabled\n");
+    }
+    if cli.enc.cm_init {
+        eprint!("\tcontext initialisation   = enabled\n");
+    }
+    if cli.enc.adcc {
+        eprint!("\tadvanced coef coding     = enabled\n");
+    }
+    if cli.enc.rpl {
+        eprint!("\treference picture lists  = enabled\n");
+    }
+    if cli.enc.pocs {
+        eprint!("\tPOC LSB signalling       = enabled\n");
+    }
+    if let Some(ltr_frame) = cli.ltr_frame {
+        eprint!("\tlong-term reference      = frame {}\n", ltr_frame);
+    }
+    if cli.enc.picture_hash != PictureHash::None {
+        eprint!("\tpicture signature        = {:?}\n", cli.enc.picture_hash);
+    }
 }
 
 fn print_stat_init() {
@@ -631,6 +912,24 @@ fn main() -> std::io::Result<()> {
         return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, *err));
     }
 
+    let mut stats_file = None;
+    if let Some(stats) = &cli.stats {
+        match cli.enc.rc_pass {
+            RateControlPass::First => stats_file = Some(File::create(stats)?),
+            RateControlPass::Second => {
+                let mut data = vec![];
+                File::open(stats)?.read_to_end(&mut data)?;
+                if let Err(err) = ctx.rc_send_pass_data(&data) {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("Invalid two-pass statistics: {:?}", err),
+                    ));
+                }
+            }
+            RateControlPass::Single => {}
+        }
+    }
+
     for _ in 0..cli.skip {
         match cli.demuxer.read() {
             Ok(f) => f,
@@ -666,6 +965,7 @@ fn main() -> std::io::Result<()> {
                         if let Data::Frame(frame) = &mut data {
                             if let Some(frm) = frame {
                                 frm.ts = pic_icnt as u64;
+                                frm.long_term_ref = cli.ltr_frame == Some(pic_icnt);
                             }
                         }
                         let start = Instant::now();
@@ -705,7 +1005,7 @@ fn main() -> std::io::Result<()> {
                         print_stat(&stat, duration.as_millis() as usize);
                     }
                     if let Some(rec_frame) = stat.rec {
-                        let ts = rec_frame.borrow().ts;
+                        let ts = rec_frame.read().unwrap().ts;
                         map_rec.insert(ts, Some(rec_frame));
                     }
                     byte_tot += stat.bytes;
@@ -723,6 +1023,10 @@ fn main() -> std::io::Result<()> {
                 }
 
                 write_rec_frames(&mut cli, &mut map_rec, &mut pic_ocnt)?;
+
+                if let (Some(f), Some(pass_data)) = (&mut stats_file, ctx.rc_receive_pass_data()) {
+                    f.write_all(&pass_data)?;
+                }
             }
             Err(err) => {
                 if err == EvcError::EVC_OK_NO_MORE_OUTPUT {
@@ -737,6 +1041,8 @@ fn main() -> std::io::Result<()> {
         }
     }
 
+    cli.muxer.flush()?;
+
     if cli.verbose {
         print_summary(cli.enc.fps, byte_tot, pic_icnt, clk_tot, &psnr_tot);
     }
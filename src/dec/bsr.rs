@@ -51,7 +51,14 @@ impl EvcdBsr {
         self.cur as isize - (self.leftbits >> 3)
     }
 
-    pub(crate) fn new(pkt: Packet) -> Self {
+    pub(crate) fn new(mut pkt: Packet) -> Self {
+        /* a NALU header never starts with a zero byte, so a leading start
+        code marks an Annex-B NALU whose emulation prevention bytes must be
+        removed before parsing */
+        if let Some(pos) = EvcdBsr::start_code_len(&pkt.data) {
+            pkt.data = EvcdBsr::remove_emulation_prevention(&pkt.data[pos..]);
+        }
+
         EvcdBsr {
             code: 0,
             leftbits: 0,
@@ -61,6 +68,30 @@ impl EvcdBsr {
         }
     }
 
+    /* length of the start code (with leading zero bytes) at the beginning of data */
+    fn start_code_len(data: &[u8]) -> Option<usize> {
+        let zeros = data.iter().take_while(|&&byte| byte == 0).count();
+        if zeros >= 2 && data.get(zeros) == Some(&1) {
+            Some(zeros + 1)
+        } else {
+            None
+        }
+    }
+
+    fn remove_emulation_prevention(data: &[u8]) -> Vec<u8> {
+        let mut rbsp = Vec::with_capacity(data.len());
+        let mut zeros = 0;
+        for &byte in data {
+            if zeros >= 2 && byte == NALU_EMULATION_PREVENTION_BYTE {
+                zeros = 0;
+                continue;
+            }
+            rbsp.push(byte);
+            zeros = if byte == 0 { zeros + 1 } else { 0 };
+        }
+        rbsp
+    }
+
     pub(crate) fn flush(&mut self, mut byte: isize) -> Result<(), EvcError> {
         let mut shift: i32 = 24;
         let mut code: u32 = 0;
@@ -221,3 +252,52 @@ impl EvcdBsr {
         Ok(val)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rbsp(data: &[u8]) -> Vec<u8> {
+        EvcdBsr::new(Packet {
+            data: data.to_vec(),
+            ts: 0,
+        })
+        .pkt
+        .data
+    }
+
+    #[test]
+    fn emulation_prevention_remove() {
+        assert_eq!(rbsp(&[0, 0, 0, 1, 0x12, 0x34]), [0x12, 0x34]);
+        assert_eq!(rbsp(&[0, 0, 1, 0x12, 0x34]), [0x12, 0x34]);
+        for byte in 0..=3 {
+            assert_eq!(
+                rbsp(&[0, 0, 0, 1, 0x12, 0, 0, 0x03, byte, 0x34]),
+                [0x12, 0, 0, byte, 0x34]
+            );
+        }
+        assert_eq!(
+            rbsp(&[0, 0, 0, 1, 0x12, 0, 0, 0x03, 0, 0, 0x34]),
+            [0x12, 0, 0, 0, 0, 0x34]
+        );
+        /* a 0x03 after a single zero byte is data */
+        assert_eq!(
+            rbsp(&[0, 0, 0, 1, 0x12, 0, 0x03, 0x34]),
+            [0x12, 0, 0x03, 0x34]
+        );
+        /* length prefixed NALUs are left untouched */
+        assert_eq!(rbsp(&[0x12, 0, 0, 0x03, 0x01]), [0x12, 0, 0, 0x03, 0x01]);
+    }
+
+    #[test]
+    fn emulation_prevention_trailing_zeros() {
+        assert_eq!(rbsp(&[0, 0, 0, 1, 0x12, 0]), [0x12, 0]);
+        assert_eq!(rbsp(&[0, 0, 0, 1, 0x12, 0, 0x03]), [0x12, 0, 0x03]);
+        assert_eq!(rbsp(&[0, 0, 0, 1, 0x12, 0, 0, 0x03]), [0x12, 0, 0]);
+        assert_eq!(rbsp(&[0, 0, 0, 1, 0x12, 0, 0, 0x03, 0]), [0x12, 0, 0, 0]);
+        assert_eq!(
+            rbsp(&[0, 0, 0, 1, 0x12, 0, 0, 0x03, 0, 0, 0x03]),
+            [0x12, 0, 0, 0, 0]
+        );
+    }
+}
@@ -1,7 +1,9 @@
 use super::bsr::*;
 use super::sbac::EvcdSbac;
 use super::{EvcdCore, EvcdCtx};
-use crate::api::{EvcError, NaluType, SliceType};
+use crate::api::{
+    EvcError, EvcRpl, NaluType, PictureHash, SliceType, MAX_NUM_REF_PICS, MAX_NUM_RPLS,
+};
 use crate::def::*;
 use crate::ipred::*;
 use crate::itdq::*;
@@ -12,8 +14,7 @@ use crate::tbl::*;
 use crate::tracer::*;
 use crate::util::*;
 
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::{Arc, RwLock};
 
 use log::*;
 
@@ -26,7 +27,9 @@ pub(crate) fn evcd_eco_nalu(bs: &mut EvcdBsr, nalu: &mut EvcNalu) -> Result<(),
         return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
     }
 
-    nalu.nal_unit_type = (bs.read(6, Some("nalu->nal_unit_type_plus1"))? as u8 - 1).into();
+    nalu.nal_unit_type = (bs.read(6, Some("nalu->nal_unit_type_plus1"))? as u8)
+        .wrapping_sub(1)
+        .into();
     nalu.nuh_temporal_id = bs.read(3, Some("nalu->nuh_temporal_id"))? as u8;
     nalu.nuh_reserved_zero_5bits = bs.read(5, Some("nalu->nuh_reserved_zero_5bits"))? as u8;
 
@@ -60,11 +63,65 @@ pub(crate) fn evcd_eco_sps(bs: &mut EvcdBsr, sps: &mut EvcSps) -> Result<(), Evc
     sps.bit_depth_luma_minus8 = bs.read_ue(Some("sps->bit_depth_luma_minus8"))? as u8;
     sps.bit_depth_chroma_minus8 = bs.read_ue(Some("sps->bit_depth_chroma_minus8"))? as u8;
     sps.sps_btt_flag = bs.read1(Some("sps->sps_btt_flag"))? != 0;
+    if sps.sps_btt_flag {
+        sps.log2_ctu_size_minus5 = bs.read_ue(Some("sps->log2_ctu_size_minus5"))? as u8;
+        sps.log2_min_cb_size_minus2 = bs.read_ue(Some("sps->log2_min_cb_size_minus2"))? as u8;
+        sps.log2_diff_ctu_max_14_cb_size =
+            bs.read_ue(Some("sps->log2_diff_ctu_max_14_cb_size"))? as u8;
+        sps.log2_diff_ctu_max_tt_cb_size =
+            bs.read_ue(Some("sps->log2_diff_ctu_max_tt_cb_size"))? as u8;
+        sps.log2_diff_min_cb_min_tt_cb_size_minus2 =
+            bs.read_ue(Some("sps->log2_diff_min_cb_min_tt_cb_size_minus2"))? as u8;
+        if sps.log2_ctu_size_minus5 + 5 != MAX_CU_LOG2 as u8 {
+            /* only 64x64 CTUs are supported */
+            return Err(EvcError::EVC_ERR_UNSUPPORTED);
+        }
+        if sps.log2_min_cb_size_minus2 + 2 > MAX_CU_LOG2 as u8
+            || sps.log2_diff_ctu_max_14_cb_size > (MAX_CU_LOG2 - MIN_CU_LOG2) as u8
+            || sps.log2_diff_ctu_max_tt_cb_size > (MAX_CU_LOG2 - MIN_CU_LOG2) as u8
+        {
+            error!("malformed bitstream: invalid block sizes in SPS");
+            return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+        }
+    }
     sps.sps_suco_flag = bs.read1(Some("sps->sps_suco_flag"))? != 0;
+    if sps.sps_suco_flag {
+        sps.log2_diff_ctu_size_max_suco_cb_size =
+            bs.read_ue(Some("sps->log2_diff_ctu_size_max_suco_cb_size"))? as u8;
+        sps.log2_diff_max_suco_min_suco_cb_size =
+            bs.read_ue(Some("sps->log2_diff_max_suco_min_suco_cb_size"))? as u8;
+        if sps.log2_diff_ctu_size_max_suco_cb_size > (MAX_CU_LOG2 - MIN_CU_LOG2) as u8
+            || sps.log2_diff_max_suco_min_suco_cb_size > (MAX_CU_LOG2 - MIN_CU_LOG2) as u8
+        {
+            error!("malformed bitstream: invalid suco block sizes in SPS");
+            return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+        }
+    }
     sps.tool_admvp = bs.read1(Some("sps->tool_admvp"))? != 0;
+    if sps.tool_admvp {
+        sps.tool_affine = bs.read1(Some("sps->tool_affine"))? != 0;
+        sps.tool_amvr = bs.read1(Some("sps->tool_amvr"))? != 0;
+        sps.tool_dmvr = bs.read1(Some("sps->tool_dmvr"))? != 0;
+        sps.tool_mmvd = bs.read1(Some("sps->tool_mmvd"))? != 0;
+        sps.tool_hmvp = bs.read1(Some("sps->tool_hmvp"))? != 0;
+    }
     sps.tool_eipd = bs.read1(Some("sps->tool_eipd"))? != 0;
+    if sps.tool_eipd {
+        sps.ibc_flag = bs.read1(Some("sps->ibc_flag"))? != 0;
+        if sps.ibc_flag {
+            sps.ibc_log_max_size =
+                bs.read_ue(Some("sps->log2_max_ibc_cand_size_minus2"))? as u8 + 2;
+            return Err(EvcError::EVC_ERR_UNSUPPORTED);
+        }
+    }
     sps.tool_cm_init = bs.read1(Some("sps->tool_cm_init"))? != 0;
+    if sps.tool_cm_init {
+        sps.tool_adcc = bs.read1(Some("sps->tool_adcc"))? != 0;
+    }
     sps.tool_iqt = bs.read1(Some("sps->tool_iqt"))? != 0;
+    if sps.tool_iqt {
+        sps.tool_ats = bs.read1(Some("sps->tool_ats"))? != 0;
+    }
     sps.tool_addb = bs.read1(Some("sps->tool_addb"))? != 0;
     sps.tool_alf = bs.read1(Some("sps->tool_alf"))? != 0;
     sps.tool_htdf = bs.read1(Some("sps->tool_htdf"))? != 0;
@@ -72,6 +129,13 @@ pub(crate) fn evcd_eco_sps(bs: &mut EvcdBsr, sps: &mut EvcSps) -> Result<(), Evc
     sps.tool_pocs = bs.read1(Some("sps->tool_pocs"))? != 0;
     sps.dquant_flag = bs.read1(Some("sps->dquant_flag"))? != 0;
     sps.tool_dra = bs.read1(Some("sps->tool_dra"))? != 0;
+    if sps.tool_pocs {
+        let log2_max_poc_lsb_minus4 = bs.read_ue(Some("sps->log2_max_pic_order_cnt_lsb_minus4"))?;
+        if log2_max_poc_lsb_minus4 > 12 {
+            return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+        }
+        sps.log2_max_pic_order_cnt_lsb_minus4 = log2_max_poc_lsb_minus4 as u8;
+    }
     if !sps.tool_rpl || !sps.tool_pocs {
         sps.log2_sub_gop_length = bs.read_ue(Some("sps->log2_sub_gop_length"))? as u8;
         if sps.log2_sub_gop_length == 0 {
@@ -80,6 +144,43 @@ pub(crate) fn evcd_eco_sps(bs: &mut EvcdBsr, sps: &mut EvcSps) -> Result<(), Evc
     }
     if !sps.tool_rpl {
         sps.max_num_ref_pics = bs.read_ue(Some("sps->max_num_ref_pics"))? as u8;
+    } else {
+        sps.sps_max_dec_pic_buffering_minus1 =
+            bs.read_ue(Some("sps->sps_max_dec_pic_buffering_minus1"))? as u8;
+        sps.long_term_ref_pics_flag = bs.read1(Some("sps->long_term_ref_pics_flag"))? != 0;
+        let log2_max_poc_lsb = sps.log2_max_pic_order_cnt_lsb_minus4 + 4;
+        sps.rpl1_same_as_rpl0_flag = bs.read1(Some("sps->rpl1_same_as_rpl0_flag"))? != 0;
+        let num_rpls = bs.read_ue(Some("sps->num_ref_pic_lists_in_sps0"))?;
+        if num_rpls > MAX_NUM_RPLS as u32 {
+            return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+        }
+        sps.num_ref_pic_lists_in_sps0 = num_rpls as u8;
+        for i in 0..sps.num_ref_pic_lists_in_sps0 as usize {
+            evcd_eco_rpl(
+                bs,
+                &mut sps.rpls_l0[i],
+                sps.long_term_ref_pics_flag,
+                log2_max_poc_lsb,
+            )?;
+        }
+        if !sps.rpl1_same_as_rpl0_flag {
+            let num_rpls = bs.read_ue(Some("sps->num_ref_pic_lists_in_sps1"))?;
+            if num_rpls > MAX_NUM_RPLS as u32 {
+                return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+            }
+            sps.num_ref_pic_lists_in_sps1 = num_rpls as u8;
+            for i in 0..sps.num_ref_pic_lists_in_sps1 as usize {
+                evcd_eco_rpl(
+                    bs,
+                    &mut sps.rpls_l1[i],
+                    sps.long_term_ref_pics_flag,
+                    log2_max_poc_lsb,
+                )?;
+            }
+        } else {
+            sps.num_ref_pic_lists_in_sps1 = sps.num_ref_pic_lists_in_sps0;
+            sps.rpls_l1.clone_from(&sps.rpls_l0);
+        }
     }
 
     sps.picture_cropping_flag = bs.read1(Some("sps->picture_cropping_flag"))? != 0;
@@ -138,6 +239,41 @@ pub(crate) fn evcd_eco_sps(bs: &mut EvcdBsr, sps: &mut EvcSps) -> Result<(), Evc
     Ok(())
 }
 
+/* reference picture list structure: the POC differences of the short-term
+entries are coded as a chain, each one relative to the previous short-term
+entry, the long-term entries by their POC LSBs */
+fn evcd_eco_rpl(
+    bs: &mut EvcdBsr,
+    rpl: &mut EvcRpl,
+    long_term_ref_pics_flag: bool,
+    lt_poc_lsb_len: u8,
+) -> Result<(), EvcError> {
+    let ref_pic_num = bs.read_ue(Some("rpl->ref_pic_num"))?;
+    if ref_pic_num > MAX_NUM_REF_PICS as u32 {
+        return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+    }
+    rpl.ref_pic_num = ref_pic_num as u8;
+    rpl.lt_poc_lsb_len = lt_poc_lsb_len;
+
+    let mut delta_poc = 0;
+    for i in 0..rpl.ref_pic_num as usize {
+        rpl.ref_pic_lt[i] = long_term_ref_pics_flag && bs.read1(Some("rpl->st_ref_pic_flag"))? == 0;
+        if rpl.ref_pic_lt[i] {
+            rpl.ref_pics[i] = bs.read(lt_poc_lsb_len as isize, Some("rpl->poc_lsb_lt"))? as i32;
+            continue;
+        }
+
+        let mut delta = bs.read_ue(Some("rpl->abs_delta_poc_st"))? as i32;
+        if delta != 0 && bs.read1(Some("rpl->strp_entry_sign_flag"))? != 0 {
+            delta = -delta;
+        }
+        delta_poc += delta;
+        rpl.ref_pics[i] = delta_poc;
+    }
+
+    Ok(())
+}
+
 pub(crate) fn evcd_eco_pps(bs: &mut EvcdBsr, pps: &mut EvcPps) -> Result<(), EvcError> {
     EVC_TRACE(&mut bs.tracer, "***********************************\n");
     EVC_TRACE(&mut bs.tracer, "************ PPS Start ************\n");
@@ -148,10 +284,36 @@ pub(crate) fn evcd_eco_pps(bs: &mut EvcdBsr, pps: &mut EvcPps) -> Result<(), Evc
         bs.read_ue(Some("pps->num_ref_idx_default_active_minus1[0]"))? as u8;
     pps.num_ref_idx_default_active_minus1[1] =
         bs.read_ue(Some("pps->num_ref_idx_default_active_minus1[1]"))? as u8;
-    pps.additional_lt_poc_lsb_len = bs.read_ue(Some("pps->additional_lt_poc_lsb_len"))? as u8;
+    let additional_lt_poc_lsb_len = bs.read_ue(Some("pps->additional_lt_poc_lsb_len"))?;
+    if additional_lt_poc_lsb_len > 16 {
+        return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+    }
+    pps.additional_lt_poc_lsb_len = additional_lt_poc_lsb_len as u8;
     pps.rpl1_idx_present_flag = bs.read1(Some("pps->rpl1_idx_present_flag"))? != 0;
     pps.single_tile_in_pic_flag = bs.read1(Some("pps->single_tile_in_pic_flag"))? != 0;
-    assert_eq!(pps.single_tile_in_pic_flag, true);
+    if !pps.single_tile_in_pic_flag {
+        pps.num_tile_columns_minus1 = bs.read_ue(Some("pps->num_tile_columns_minus1"))? as u8;
+        pps.num_tile_rows_minus1 = bs.read_ue(Some("pps->num_tile_rows_minus1"))? as u8;
+        if pps.num_tile_columns_minus1 as usize >= MAX_NUM_TILES_COL
+            || pps.num_tile_rows_minus1 as usize >= MAX_NUM_TILES_ROW
+        {
+            return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+        }
+        pps.uniform_tile_spacing_flag = bs.read1(Some("pps->uniform_tile_spacing_flag"))? != 0;
+        if !pps.uniform_tile_spacing_flag {
+            for i in 0..pps.num_tile_columns_minus1 as usize {
+                pps.tile_column_width_minus1[i] =
+                    bs.read_ue(Some("pps->tile_column_width_minus1"))? as u16;
+            }
+            for i in 0..pps.num_tile_rows_minus1 as usize {
+                pps.tile_row_height_minus1[i] =
+                    bs.read_ue(Some("pps->tile_row_height_minus1"))? as u16;
+            }
+        }
+        pps.loop_filter_across_tiles_enabled_flag =
+            bs.read1(Some("pps->loop_filter_across_tiles_enabled_flag"))? != 0;
+        pps.tile_offset_len_minus1 = bs.read_ue(Some("pps->tile_offset_len_minus1"))? as u8;
+    }
 
     pps.tile_id_len_minus1 = bs.read_ue(Some("pps->tile_id_len_minus1"))? as u8;
     pps.explicit_tile_id_flag = bs.read1(Some("pps->explicit_tile_id_flag"))? != 0;
@@ -179,8 +341,179 @@ pub(crate) fn evcd_eco_pps(bs: &mut EvcdBsr, pps: &mut EvcPps) -> Result<(), Evc
     Ok(())
 }
 
+/* exp-golomb code of an ALF coefficient, the suffix is read LSB first */
+fn evcd_eco_alf_golomb(bs: &mut EvcdBsr, k: u8) -> Result<i16, EvcError> {
+    let mut prefix = 0;
+    while bs.read1(Some("alf_coeff_prefix"))? != 0 {
+        prefix += 1;
+        if prefix > 16 {
+            return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+        }
+    }
+
+    let mut val = prefix << k;
+    for i in 0..k {
+        if bs.read1(Some("alf_coeff_suffix"))? != 0 {
+            val += 1 << i;
+        }
+    }
+    if val != 0 && bs.read1(Some("alf_coeff_sign"))? == 0 {
+        val = -val;
+    }
+
+    Ok(val as i16)
+}
+
+fn evcd_eco_alf_filter(
+    bs: &mut EvcdBsr,
+    alf: &mut EvcAlfParam,
+    is_chroma: bool,
+) -> Result<(), EvcError> {
+    if !is_chroma {
+        alf.coeff_delta_flag = bs.read1(Some("alf_coefficients_delta_flag"))? != 0;
+        alf.coeff_delta_pred_mode_flag = if !alf.coeff_delta_flag && alf.num_luma_filters > 1 {
+            bs.read1(Some("coeff_delta_pred_mode_flag"))? != 0
+        } else {
+            false
+        };
+    }
+
+    let (golomb_idx, max_golomb_idx, num_coeff) = if !is_chroma && alf.luma_filter_type {
+        (&evc_tbl_alf_golomb_idx7[..], 3, MAX_NUM_ALF_LUMA_COEFF)
+    } else {
+        (&evc_tbl_alf_golomb_idx5[..], 2, MAX_NUM_ALF_CHROMA_COEFF)
+    };
+
+    let mut k_min = bs.read_ue(Some("min_golomb_order"))? + 1;
+    let mut k_min_tab = [0u8; 3];
+    for k in &mut k_min_tab[..max_golomb_idx] {
+        k_min += bs.read1(Some("golomb_order_increase_flag"))?;
+        if k_min > 16 {
+            return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+        }
+        *k = k_min as u8;
+    }
+
+    if is_chroma {
+        for i in 0..num_coeff - 1 {
+            alf.chroma_coeff[i] = evcd_eco_alf_golomb(bs, k_min_tab[golomb_idx[i] as usize])?;
+        }
+        return Ok(());
+    }
+
+    let num_filters = alf.num_luma_filters as usize;
+    if alf.coeff_delta_flag {
+        for f in 0..num_filters {
+            alf.filter_coeff_flag[f] = bs.read1(Some("filter_coefficient_flag"))? != 0;
+        }
+    }
+    for f in 0..num_filters {
+        let coeff = &mut alf.luma_coeff[f * MAX_NUM_ALF_LUMA_COEFF..][..MAX_NUM_ALF_LUMA_COEFF];
+        if alf.coeff_delta_flag && !alf.filter_coeff_flag[f] {
+            coeff.fill(0);
+            continue;
+        }
+        for i in 0..num_coeff - 1 {
+            coeff[i] = evcd_eco_alf_golomb(bs, k_min_tab[golomb_idx[i] as usize])?;
+        }
+    }
+
+    Ok(())
+}
+
+fn evcd_eco_alf_aps_param(bs: &mut EvcdBsr, alf: &mut EvcAlfParam) -> Result<(), EvcError> {
+    alf.luma_signal_flag = bs.read1(Some("alf_luma_filter_signal_flag"))? != 0;
+    alf.chroma_signal_flag = bs.read1(Some("alf_chroma_filter_signal_flag"))? != 0;
+
+    if alf.luma_signal_flag {
+        let num_luma_filters = bs.read_ue(Some("alf_luma_num_filters_signalled_minus1"))? + 1;
+        if num_luma_filters as usize > MAX_NUM_ALF_CLASSES {
+            error!("malformed bitstream: invalid number of ALF luma filters");
+            return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+        }
+        alf.num_luma_filters = num_luma_filters as u8;
+        alf.luma_filter_type = bs.read1(Some("alf_luma_type_flag"))? != 0;
+
+        if num_luma_filters > 1 {
+            let len = (32 - (num_luma_filters - 1).leading_zeros()) as isize;
+            for i in 0..MAX_NUM_ALF_CLASSES {
+                let idx = bs.read(len, Some("alf_luma_coeff_delta_idx"))?;
+                if idx >= num_luma_filters {
+                    return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+                }
+                alf.filter_coeff_delta_idx[i] = idx as u8;
+            }
+        }
+
+        /* fixed_filter_pattern, a unary code followed by its sign */
+        let mut pattern = 0;
+        while bs.read1(Some("fixed_filter_pattern"))? != 0 {
+            pattern += 1;
+            if pattern > 2 {
+                return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+            }
+        }
+        if pattern > 0 && bs.read1(Some("fixed_filter_pattern_sign"))? == 0 {
+            return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+        }
+        alf.fixed_filter_pattern = pattern;
+
+        for i in 0..MAX_NUM_ALF_CLASSES {
+            alf.fixed_filter_usage_flag[i] = match pattern {
+                0 => false,
+                1 => true,
+                _ => bs.read1(Some("fixed_filter_usage_flag"))? != 0,
+            };
+        }
+        for i in 0..MAX_NUM_ALF_CLASSES {
+            if alf.fixed_filter_usage_flag[i] {
+                alf.fixed_filter_idx[i] = bs.read(4, Some("fixed_filter_idx"))? as u8;
+            }
+        }
+
+        evcd_eco_alf_filter(bs, alf, false)?;
+    }
+
+    if alf.chroma_signal_flag {
+        evcd_eco_alf_filter(bs, alf, true)?;
+    }
+
+    Ok(())
+}
+
+/* parse an adaptation parameter set, returns its id and the ALF parameters */
+pub(crate) fn evcd_eco_aps(bs: &mut EvcdBsr) -> Result<(usize, EvcAlfParam), EvcError> {
+    EVC_TRACE(&mut bs.tracer, "***********************************\n");
+    EVC_TRACE(&mut bs.tracer, "************ APS Start ************\n");
+
+    let aps_id = bs.read(5, Some("aps->aps_id"))? as usize;
+    let aps_params_type = bs.read(3, Some("aps->aps_params_type"))?;
+    if aps_params_type != ALF_APS_TYPE {
+        return Err(EvcError::EVC_ERR_UNSUPPORTED);
+    }
+
+    let mut alf = EvcAlfParam::default();
+    evcd_eco_alf_aps_param(bs, &mut alf)?;
+
+    let aps_extension_flag = bs.read1(Some("aps->aps_extension_flag"))? != 0;
+    if aps_extension_flag {
+        return Err(EvcError::EVC_ERR_UNSUPPORTED);
+    }
+
+    /* byte align */
+    while !bs.is_byte_aligned() {
+        bs.read1(Some("t0"))?;
+    }
+
+    EVC_TRACE(&mut bs.tracer, "************ APS End   ************\n");
+    EVC_TRACE(&mut bs.tracer, "***********************************\n");
+
+    Ok((aps_id, alf))
+}
+
 pub(crate) fn evcd_eco_sh(
     bs: &mut EvcdBsr,
+    sps: &EvcSps,
     pps: &EvcPps,
     sh: &mut EvcSh,
     nalu_type: NaluType,
@@ -189,11 +522,139 @@ pub(crate) fn evcd_eco_sh(
     EVC_TRACE(&mut bs.tracer, "************ SH  Start ************\n");
 
     sh.slice_pic_parameter_set_id = bs.read_ue(Some("sh->slice_pic_parameter_set_id"))? as u8;
+    if !pps.single_tile_in_pic_flag {
+        sh.single_tile_in_slice_flag = bs.read1(Some("sh->single_tile_in_slice_flag"))? != 0;
+        sh.first_tile_id = bs.read(
+            pps.tile_id_len_minus1 as isize + 1,
+            Some("sh->first_tile_id"),
+        )? as u16;
+    } else {
+        sh.single_tile_in_slice_flag = true;
+        sh.first_tile_id = 0;
+    }
+    if !sh.single_tile_in_slice_flag {
+        sh.arbitrary_slice_flag = if pps.arbitrary_slice_present_flag {
+            bs.read1(Some("sh->arbitrary_slice_flag"))? != 0
+        } else {
+            false
+        };
+        if sh.arbitrary_slice_flag {
+            return Err(EvcError::EVC_ERR_UNSUPPORTED);
+        }
+        sh.last_tile_id = bs.read(
+            pps.tile_id_len_minus1 as isize + 1,
+            Some("sh->last_tile_id"),
+        )? as u16;
+        if sh.last_tile_id != sh.first_tile_id {
+            /* slices of several tiles need entry points */
+            return Err(EvcError::EVC_ERR_UNSUPPORTED);
+        }
+    } else {
+        sh.arbitrary_slice_flag = false;
+        sh.last_tile_id = sh.first_tile_id;
+    }
     sh.slice_type = (bs.read_ue(Some("sh->slice_type"))? as u8).into();
 
     if nalu_type == NaluType::EVC_IDR_NUT {
         sh.no_output_of_prior_pics_flag = bs.read1(Some("sh->no_output_of_prior_pics_flag"))? != 0;
-    } else {
+    }
+
+    sh.mmvd_group_enable_flag = false;
+    if sps.tool_mmvd
+        && (sh.slice_type == SliceType::EVC_ST_B || sh.slice_type == SliceType::EVC_ST_P)
+    {
+        sh.mmvd_group_enable_flag = bs.read1(Some("sh->mmvd_group_enable_flag"))? != 0;
+    }
+
+    sh.alf_on = false;
+    sh.alf_map_flag = false;
+    sh.alf_chroma_idc = 0;
+    sh.alf_chroma_map_flag = false;
+    sh.alf_chroma2_map_flag = false;
+    if sps.tool_alf {
+        sh.alf_on = bs.read1(Some("sh->alf_on"))? != 0;
+        if sh.alf_on {
+            sh.aps_id_y = bs.read(5, Some("sh->aps_id_y"))? as u8;
+            sh.alf_map_flag = bs.read1(Some("sh->alf_map_flag"))? != 0;
+            sh.alf_chroma_idc = bs.read(2, Some("sh->alf_chroma_idc"))? as u8;
+            if sh.alf_chroma_idc != 0 && (sps.chroma_format_idc == 1 || sps.chroma_format_idc == 2)
+            {
+                sh.aps_id_ch = bs.read(5, Some("sh->aps_id_ch"))? as u8;
+            }
+            if sps.chroma_format_idc == 3 {
+                if sh.alf_chroma_idc & 1 != 0 {
+                    sh.aps_id_ch = bs.read(5, Some("sh->aps_id_ch"))? as u8;
+                    sh.alf_chroma_map_flag = bs.read1(Some("sh->alf_chroma_map_flag"))? != 0;
+                }
+                if sh.alf_chroma_idc & 2 != 0 {
+                    sh.aps_id_ch2 = bs.read(5, Some("sh->aps_id_ch2"))? as u8;
+                    sh.alf_chroma2_map_flag = bs.read1(Some("sh->alf_chroma2_map_flag"))? != 0;
+                }
+            }
+        }
+    }
+
+    if nalu_type != NaluType::EVC_IDR_NUT {
+        if sps.tool_pocs {
+            sh.poc_lsb = bs.read(
+                sps.log2_max_pic_order_cnt_lsb_minus4 as isize + 4,
+                Some("sh->poc_lsb"),
+            )? as i32;
+        }
+        if sps.tool_rpl {
+            let num_rpls = [sps.num_ref_pic_lists_in_sps0, sps.num_ref_pic_lists_in_sps1];
+            for lidx in 0..REFP_NUM {
+                /* the list 1 follows the list 0 when its index is not present */
+                let idx_present = lidx == REFP_0 || pps.rpl1_idx_present_flag;
+                sh.ref_pic_list_sps_flag[lidx] = if num_rpls[lidx] > 0 && idx_present {
+                    bs.read1(Some("sh->ref_pic_list_sps_flag"))?
+                } else if num_rpls[lidx] > 0 {
+                    sh.ref_pic_list_sps_flag[REFP_0]
+                } else {
+                    0
+                };
+
+                let rpl_idx = if sh.ref_pic_list_sps_flag[lidx] != 0 {
+                    let rpl_idx = if num_rpls[lidx] > 1 && idx_present {
+                        bs.read_ue(Some("sh->rpl_idx"))? as isize
+                    } else if num_rpls[lidx] > 1 {
+                        sh.rpl_l0_idx
+                    } else {
+                        0
+                    };
+                    if rpl_idx >= num_rpls[lidx] as isize {
+                        return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+                    }
+                    rpl_idx
+                } else {
+                    -1
+                };
+
+                let rpl = if lidx == REFP_0 {
+                    sh.rpl_l0_idx = rpl_idx;
+                    &mut sh.rpl_l0
+                } else {
+                    sh.rpl_l1_idx = rpl_idx;
+                    &mut sh.rpl_l1
+                };
+                if rpl_idx >= 0 {
+                    let rpls = if lidx == REFP_0 {
+                        &sps.rpls_l0
+                    } else {
+                        &sps.rpls_l1
+                    };
+                    rpl.clone_from(&rpls[rpl_idx as usize]);
+                } else {
+                    evcd_eco_rpl(
+                        bs,
+                        rpl,
+                        sps.long_term_ref_pics_flag,
+                        sps.log2_max_pic_order_cnt_lsb_minus4 + 4 + pps.additional_lt_poc_lsb_len,
+                    )?;
+                }
+            }
+        }
+
         if sh.slice_type == SliceType::EVC_ST_P || sh.slice_type == SliceType::EVC_ST_B {
             sh.num_ref_idx_active_override_flag =
                 bs.read1(Some("sh->num_ref_idx_active_override_flag"))? != 0;
@@ -208,12 +669,43 @@ pub(crate) fn evcd_eco_sh(
                 sh.rpl_l0.ref_pic_active_num = pps.num_ref_idx_default_active_minus1[REFP_0] + 1;
                 sh.rpl_l1.ref_pic_active_num = pps.num_ref_idx_default_active_minus1[REFP_1] + 1;
             }
+
+            sh.temporal_mvp_asigned_flag = false;
+            sh.collocated_from_list_idx = 0;
+            sh.collocated_from_ref_idx = 0;
+            sh.collocated_mvp_source_list_idx = 0;
+            if sps.tool_admvp {
+                sh.temporal_mvp_asigned_flag =
+                    bs.read1(Some("sh->temporal_mvp_asigned_flag"))? != 0;
+                if sh.temporal_mvp_asigned_flag {
+                    if sh.slice_type == SliceType::EVC_ST_B {
+                        sh.collocated_from_list_idx =
+                            bs.read1(Some("sh->collocated_from_list_idx"))? as u8;
+                        sh.collocated_mvp_source_list_idx =
+                            bs.read1(Some("sh->collocated_mvp_source_list_idx"))? as u8;
+                    }
+                    sh.collocated_from_ref_idx =
+                        bs.read1(Some("sh->collocated_from_ref_idx"))? as u8;
+                }
+            }
         }
     }
 
     sh.deblocking_filter_on = bs.read1(Some("sh->deblocking_filter_on"))? != 0;
+    sh.sh_deblock_alpha_offset = 0;
+    sh.sh_deblock_beta_offset = 0;
+    if sh.deblocking_filter_on && sps.tool_addb {
+        sh.sh_deblock_alpha_offset = bs.read_se(Some("sh->sh_deblock_alpha_offset"))? as i8;
+        sh.sh_deblock_beta_offset = bs.read_se(Some("sh->sh_deblock_beta_offset"))? as i8;
+        if sh.sh_deblock_alpha_offset.abs() > DBF_ADDB_MAX_OFFSET
+            || sh.sh_deblock_beta_offset.abs() > DBF_ADDB_MAX_OFFSET
+        {
+            error!("malformed bitstream: deblocking offsets should be in the range of -12 to 12\n");
+            return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+        }
+    }
     sh.qp = bs.read(6, Some("sh->qp"))? as u8;
-    if sh.qp < 0 || sh.qp > 51 {
+    if sh.qp > 51 {
         error!("malformed bitstream: slice_qp should be in the range of 0 to 51\n");
         return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
     }
@@ -238,6 +730,41 @@ pub(crate) fn evcd_eco_sh(
     Ok(())
 }
 
+/* parse a SEI message, returns the decoded picture hash it carries. other
+payload types are skipped */
+pub(crate) fn evcd_eco_sei(
+    bs: &mut EvcdBsr,
+    chroma_format_idc: u8,
+) -> Result<Option<(PictureHash, [[u8; 16]; N_C])>, EvcError> {
+    let payload_type = bs.read(8, Some("payload_type"))?;
+    let payload_size = bs.read(8, Some("payload_size"))? as usize;
+
+    if payload_type != EVC_UD_PIC_SIGNATURE {
+        return Ok(None);
+    }
+
+    /* payload_size is the hash size of a plane */
+    let hash = PictureHash::from_size(payload_size);
+    if hash == PictureHash::None {
+        error!(
+            "malformed bitstream: invalid picture signature size {}",
+            payload_size
+        );
+        return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+    }
+
+    /* a monochrome picture only has the signature of the luma plane */
+    let num_comp = if chroma_format_idc == 0 { 1 } else { N_C };
+    let mut signature = [[0u8; 16]; N_C];
+    for sign in &mut signature[..num_comp] {
+        for byte in &mut sign[..payload_size] {
+            *byte = bs.read(8, Some("pic_sign"))? as u8;
+        }
+    }
+
+    Ok(Some((hash, signature)))
+}
+
 pub(crate) fn evcd_eco_tile_end_flag(
     bs: &mut EvcdBsr,
     sbac: &mut EvcdSbac,
@@ -249,35 +776,166 @@ pub(crate) fn evcd_eco_split_mode(
     bs: &mut EvcdBsr,
     sbac: &mut EvcdSbac,
     sbac_ctx: &mut EvcSbacCtx,
+    x: u16,
+    y: u16,
     cuw: u16,
     cuh: u16,
+    w: u16,
+    h: u16,
+    w_scu: u16,
+    map_scu: &[MCU],
+    sps_btt_flag: bool,
+    split_tbl: &EvcSplitTbl,
+    mode_cons: ModeCons,
+    sps_cm_init_flag: bool,
 ) -> Result<SplitMode, EvcError> {
     if cuw < 8 && cuh < 8 {
-        Ok(SplitMode::NO_SPLIT)
-    } else {
+        return Ok(SplitMode::NO_SPLIT);
+    }
+
+    if !sps_btt_flag {
         /* split_cu_flag */
         let bin = sbac.decode_bin(bs, &mut sbac_ctx.split_cu_flag[0])?;
 
-        if bin != 0 {
+        return if bin != 0 {
             Ok(SplitMode::SPLIT_QUAD)
         } else {
             Ok(SplitMode::NO_SPLIT)
-        }
+        };
+    }
+
+    let mut split_allow = [false; MAX_SPLIT_NUM];
+    evc_check_split_mode(
+        &mut split_allow,
+        CONV_LOG2(cuw as usize),
+        CONV_LOG2(cuh as usize),
+        false,
+        false,
+        false,
+        MAX_CU_LOG2 as u8,
+        x,
+        y,
+        w,
+        h,
+        sps_btt_flag,
+        split_tbl,
+        mode_cons,
+    );
+    let allow_bi_ver = split_allow[SplitMode::SPLIT_BI_VER as usize];
+    let allow_bi_hor = split_allow[SplitMode::SPLIT_BI_HOR as usize];
+    let allow_tri_ver = split_allow[SplitMode::SPLIT_TRI_VER as usize];
+    let allow_tri_hor = split_allow[SplitMode::SPLIT_TRI_HOR as usize];
+    let allow_ver = allow_bi_ver || allow_tri_ver;
+    let allow_hor = allow_bi_hor || allow_tri_hor;
+
+    if !allow_ver && !allow_hor {
+        return Ok(SplitMode::NO_SPLIT);
+    }
+
+    let log2_cuw = CONV_LOG2(cuw as usize);
+    let log2_cuh = CONV_LOG2(cuh as usize);
+
+    /* btt_split_flag */
+    let ctx =
+        evc_get_ctx_btt_split_flag(x, y, log2_cuw, log2_cuh, w_scu, map_scu, sps_cm_init_flag);
+    if sbac.decode_bin(bs, &mut sbac_ctx.btt_split_flag[ctx])? == 0 {
+        return Ok(SplitMode::NO_SPLIT);
     }
+
+    /* btt_split_dir */
+    let vertical = if allow_ver && allow_hor {
+        let ctx = evc_get_ctx_btt_split_dir(log2_cuw, log2_cuh, sps_cm_init_flag);
+        sbac.decode_bin(bs, &mut sbac_ctx.btt_split_dir[ctx])? != 0
+    } else {
+        allow_ver
+    };
+
+    /* btt_split_type, which has a single context */
+    let (allow_bi, allow_tri) = if vertical {
+        (allow_bi_ver, allow_tri_ver)
+    } else {
+        (allow_bi_hor, allow_tri_hor)
+    };
+    let tri = if allow_bi && allow_tri {
+        sbac.decode_bin(bs, &mut sbac_ctx.btt_split_type[0])? != 0
+    } else {
+        allow_tri
+    };
+
+    Ok(match (vertical, tri) {
+        (true, false) => SplitMode::SPLIT_BI_VER,
+        (false, false) => SplitMode::SPLIT_BI_HOR,
+        (true, true) => SplitMode::SPLIT_TRI_VER,
+        (false, true) => SplitMode::SPLIT_TRI_HOR,
+    })
+}
+
+pub(crate) fn evcd_eco_alf_ctb_flag(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+) -> Result<bool, EvcError> {
+    let alf_ctb_flag = sbac.decode_bin(bs, &mut sbac_ctx.alf_ctb_flag[0])? != 0;
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "alf ctb flag ");
+    EVC_TRACE(&mut bs.tracer, alf_ctb_flag as u32);
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    Ok(alf_ctb_flag)
+}
+
+pub(crate) fn evcd_eco_suco_flag(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    log2_cuw: u8,
+    log2_cuh: u8,
+    sps_cm_init_flag: bool,
+) -> Result<bool, EvcError> {
+    let ctx = evc_get_ctx_suco_flag(log2_cuw, log2_cuh, sps_cm_init_flag);
+    let suco_flag = sbac.decode_bin(bs, &mut sbac_ctx.suco_flag[ctx])? != 0;
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "suco flag ");
+    EVC_TRACE(&mut bs.tracer, suco_flag as u32);
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    Ok(suco_flag)
+}
+
+pub(crate) fn evcd_eco_mode_constr(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+) -> Result<ModeCons, EvcError> {
+    let mode_cons = if sbac.decode_bin(bs, &mut sbac_ctx.mode_cons[0])? != 0 {
+        ModeCons::eOnlyIntra
+    } else {
+        ModeCons::eOnlyInter
+    };
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "mode_constr ");
+    EVC_TRACE(&mut bs.tracer, (mode_cons == ModeCons::eOnlyIntra) as u8);
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    Ok(mode_cons)
 }
 
 pub(crate) fn evcd_eco_cu_skip_flag(
     bs: &mut EvcdBsr,
     sbac: &mut EvcdSbac,
     sbac_ctx: &mut EvcSbacCtx,
+    ctx: usize,
 ) -> Result<u32, EvcError> {
-    let cu_skip_flag = sbac.decode_bin(bs, &mut sbac_ctx.skip_flag[0])?; /* cu_skip_flag */
+    let cu_skip_flag = sbac.decode_bin(bs, &mut sbac_ctx.skip_flag[ctx])?; /* cu_skip_flag */
 
     EVC_TRACE_COUNTER(&mut bs.tracer);
     EVC_TRACE(&mut bs.tracer, "skip flag ");
     EVC_TRACE(&mut bs.tracer, cu_skip_flag);
     EVC_TRACE(&mut bs.tracer, " ctx ");
-    EVC_TRACE(&mut bs.tracer, 0);
+    EVC_TRACE(&mut bs.tracer, ctx);
     EVC_TRACE(&mut bs.tracer, " \n");
 
     Ok(cu_skip_flag)
@@ -287,10 +945,11 @@ pub(crate) fn evcd_eco_pred_mode(
     bs: &mut EvcdBsr,
     sbac: &mut EvcdSbac,
     sbac_ctx: &mut EvcSbacCtx,
-    slice_type: SliceType,
+    mode_cons: ModeCons,
+    ctx: usize,
 ) -> Result<PredMode, EvcError> {
-    if slice_type != SliceType::EVC_ST_I {
-        let pred_mode = if sbac.decode_bin(bs, &mut sbac_ctx.pred_mode[0])? != 0 {
+    if mode_cons == ModeCons::eAll {
+        let pred_mode = if sbac.decode_bin(bs, &mut sbac_ctx.pred_mode[ctx])? != 0 {
             PredMode::MODE_INTRA
         } else {
             PredMode::MODE_INTER
@@ -302,6 +961,8 @@ pub(crate) fn evcd_eco_pred_mode(
         EVC_TRACE(&mut bs.tracer, " \n");
 
         Ok(pred_mode)
+    } else if mode_cons == ModeCons::eOnlyInter {
+        Ok(PredMode::MODE_INTER)
     } else {
         Ok(PredMode::MODE_INTRA)
     }
@@ -324,6 +985,130 @@ pub(crate) fn evcd_eco_mvp_idx(
     Ok(idx)
 }
 
+pub(crate) fn evcd_eco_merge_idx(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+) -> Result<u8, EvcError> {
+    let idx = sbac.read_truncate_unary_sym(
+        bs,
+        &mut sbac_ctx.merge_idx,
+        NUM_CTX_MERGE_IDX as u32,
+        MAX_NUM_MVP as u32,
+    )? as u8;
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "merge idx ");
+    EVC_TRACE(&mut bs.tracer, idx);
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    Ok(idx)
+}
+
+pub(crate) fn evcd_eco_mvr_idx(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+) -> Result<u8, EvcError> {
+    let mvr_idx = sbac.read_truncate_unary_sym(
+        bs,
+        &mut sbac_ctx.mvr_idx,
+        NUM_CTX_MVR_IDX as u32,
+        MAX_NUM_MVR as u32,
+    )? as u8;
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "mvr idx ");
+    EVC_TRACE(&mut bs.tracer, mvr_idx);
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    Ok(mvr_idx)
+}
+
+pub(crate) fn evcd_eco_mmvd_flag(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+) -> Result<bool, EvcError> {
+    let mmvd_flag = sbac.decode_bin(bs, &mut sbac_ctx.mmvd_flag[0])? != 0;
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "mmvd flag ");
+    EVC_TRACE(&mut bs.tracer, if mmvd_flag { 1 } else { 0 });
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    Ok(mmvd_flag)
+}
+
+/* distance group, base merge candidate, offset distance and offset direction
+of MMVD, returned as a single packed index */
+pub(crate) fn evcd_eco_mmvd_idx(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    num_groups: usize,
+) -> Result<u16, EvcError> {
+    let group_idx = if num_groups > 1 {
+        sbac.read_truncate_unary_sym(
+            bs,
+            &mut sbac_ctx.mmvd_group_idx,
+            NUM_CTX_MMVD_GROUP_IDX as u32,
+            num_groups as u32,
+        )?
+    } else {
+        0
+    };
+    let merge_idx = sbac.read_truncate_unary_sym(
+        bs,
+        &mut sbac_ctx.mmvd_merge_idx,
+        NUM_CTX_MMVD_MERGE_IDX as u32,
+        MMVD_BASE_NUM as u32,
+    )?;
+    let dist_idx = sbac.read_truncate_unary_sym(
+        bs,
+        &mut sbac_ctx.mmvd_dist_idx,
+        NUM_CTX_MMVD_DIST_IDX as u32,
+        MMVD_DIST_NUM as u32,
+    )?;
+    let mut dir_idx = sbac.decode_bin(bs, &mut sbac_ctx.mmvd_direction_idx[0])? << 1;
+    dir_idx |= sbac.decode_bin(bs, &mut sbac_ctx.mmvd_direction_idx[1])?;
+
+    let mmvd_idx = evc_mmvd_idx(
+        group_idx as usize,
+        merge_idx as usize,
+        dist_idx as usize,
+        dir_idx as usize,
+    );
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "mmvd idx ");
+    EVC_TRACE(&mut bs.tracer, mmvd_idx);
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    Ok(mmvd_idx)
+}
+
+pub(crate) fn evcd_eco_bi_idx(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+) -> Result<u8, EvcError> {
+    let bi_idx = if sbac.decode_bin(bs, &mut sbac_ctx.bi_idx[0])? != 0 {
+        BI_NORMAL
+    } else if sbac.decode_bin(bs, &mut sbac_ctx.bi_idx[1])? != 0 {
+        BI_FL0
+    } else {
+        BI_FL1
+    };
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "bi_idx ");
+    EVC_TRACE(&mut bs.tracer, bi_idx);
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    Ok(bi_idx)
+}
+
 pub(crate) fn evcd_eco_abs_mvd(
     bs: &mut EvcdBsr,
     sbac: &mut EvcdSbac,
@@ -364,6 +1149,7 @@ pub(crate) fn evcd_eco_get_mvd(
     sbac: &mut EvcdSbac,
     sbac_ctx: &mut EvcSbacCtx,
     mvd: &mut [i16],
+    mvr_idx: u8,
 ) -> Result<(), EvcError> {
     /* MV_X */
     let mut t16 = evcd_eco_abs_mvd(bs, sbac, &mut sbac_ctx.mvd[0])? as i16;
@@ -397,6 +1183,10 @@ pub(crate) fn evcd_eco_get_mvd(
         }
     }
 
+    /* the difference is signalled in units of the motion vector resolution */
+    mvd[MV_X] = mvd[MV_X].wrapping_shl(mvr_idx as u32);
+    mvd[MV_Y] = mvd[MV_Y].wrapping_shl(mvr_idx as u32);
+
     EVC_TRACE_COUNTER(&mut bs.tracer);
     EVC_TRACE(&mut bs.tracer, "mvd x ");
     EVC_TRACE(&mut bs.tracer, mvd[MV_X]);
@@ -419,11 +1209,113 @@ pub(crate) fn evcd_eco_direct_mode_flag(
     };
 
     EVC_TRACE_COUNTER(&mut bs.tracer);
-    EVC_TRACE(&mut bs.tracer, "direct_mode_flag ");
-    EVC_TRACE(&mut bs.tracer, inter_dir as u8);
+    EVC_TRACE(&mut bs.tracer, "direct_mode_flag ");
+    EVC_TRACE(&mut bs.tracer, inter_dir as u8);
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    Ok(inter_dir)
+}
+
+pub(crate) fn evcd_eco_merge_mode_flag(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+) -> Result<bool, EvcError> {
+    let merge_mode_flag = sbac.decode_bin(bs, &mut sbac_ctx.merge_mode_flag[0])? != 0;
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "merge_mode_flag ");
+    EVC_TRACE(&mut bs.tracer, if merge_mode_flag { 1 } else { 0 });
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    Ok(merge_mode_flag)
+}
+
+pub(crate) fn evcd_eco_affine_flag(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    ctx: usize,
+) -> Result<bool, EvcError> {
+    let affine_flag = sbac.decode_bin(bs, &mut sbac_ctx.affine_flag[ctx])? != 0;
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "affine flag ");
+    EVC_TRACE(&mut bs.tracer, if affine_flag { 1 } else { 0 });
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    Ok(affine_flag)
+}
+
+pub(crate) fn evcd_eco_affine_mode(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+) -> Result<u8, EvcError> {
+    let affine_mode = sbac.decode_bin(bs, &mut sbac_ctx.affine_mode[0])? as u8;
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "affine mode ");
+    EVC_TRACE(&mut bs.tracer, affine_mode);
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    Ok(affine_mode)
+}
+
+pub(crate) fn evcd_eco_affine_mrg_idx(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+) -> Result<u8, EvcError> {
+    let idx = sbac.read_truncate_unary_sym(
+        bs,
+        &mut sbac_ctx.affine_mrg,
+        AFF_MAX_CAND as u32,
+        AFF_MAX_CAND as u32,
+    )? as u8;
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "affine merge idx ");
+    EVC_TRACE(&mut bs.tracer, idx);
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    Ok(idx)
+}
+
+pub(crate) fn evcd_eco_affine_mvp_idx(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+) -> Result<u8, EvcError> {
+    let idx = sbac.read_truncate_unary_sym(
+        bs,
+        &mut sbac_ctx.affine_mvp_idx,
+        NUM_CTX_AFFINE_MVP_IDX as u32,
+        AFF_MAX_NUM_MVP as u32,
+    )? as u8;
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "affine mvp idx ");
+    EVC_TRACE(&mut bs.tracer, idx);
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    Ok(idx)
+}
+
+pub(crate) fn evcd_eco_affine_mvd_flag(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    refi: usize,
+) -> Result<bool, EvcError> {
+    let mvd_flag = sbac.decode_bin(bs, &mut sbac_ctx.affine_mvd_flag[refi])? != 0;
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "affine mvd flag ");
+    EVC_TRACE(&mut bs.tracer, if mvd_flag { 1 } else { 0 });
     EVC_TRACE(&mut bs.tracer, " \n");
 
-    Ok(inter_dir)
+    Ok(mvd_flag)
 }
 
 pub(crate) fn evcd_eco_inter_pred_idc(
@@ -431,9 +1323,12 @@ pub(crate) fn evcd_eco_inter_pred_idc(
     sbac: &mut EvcdSbac,
     sbac_ctx: &mut EvcSbacCtx,
     slice_type: SliceType,
+    cuw: u16,
+    cuh: u16,
+    admvp: bool,
 ) -> Result<InterPredDir, EvcError> {
     let mut tmp = true;
-    if check_bi_applicability(slice_type) {
+    if check_bi_applicability(slice_type, cuw as usize, cuh as usize, admvp) {
         tmp = sbac.decode_bin(bs, &mut sbac_ctx.inter_dir[0])? != 0;
     }
 
@@ -502,7 +1397,7 @@ pub(crate) fn evcd_eco_intra_dir_b(
     //#if TRACE_ADDITIONAL_FLAGS
     //    EVC_TRACE_STR("mpm list: ");
     //#endif
-    for i in 0..IntraPredDir::IPD_CNT_B as usize {
+    for i in 0..IPD_CNT_B {
         if t0 == mpm[i] as u32 {
             ipm = i;
         }
@@ -517,6 +1412,67 @@ pub(crate) fn evcd_eco_intra_dir_b(
     Ok(ipm as u8)
 }
 
+pub(crate) fn evcd_eco_intra_dir(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    mpm: &[u8],
+) -> Result<u8, EvcError> {
+    let idx = if sbac.decode_bin(bs, &mut sbac_ctx.intra_luma_pred_mpm_flag[0])? != 0 {
+        sbac.decode_bin(bs, &mut sbac_ctx.intra_luma_pred_mpm_idx[0])?
+    } else if sbac.decode_bin_ep(bs)? != 0 {
+        IPD_MPM_CNT as u32 + sbac.decode_bins_ep(bs, 3)?
+    } else {
+        /* truncated binary code of the remaining modes */
+        let num_rem = (IPD_CNT - IPD_MPM_CNT - IPD_PIMS_CNT) as u32;
+        let k = 31 - num_rem.leading_zeros();
+        let u = (1 << (k + 1)) - num_rem;
+        let mut rem = sbac.decode_bins_ep(bs, k as usize)?;
+        if rem >= u {
+            rem = ((rem << 1) | sbac.decode_bin_ep(bs)?) - u;
+        }
+        (IPD_MPM_CNT + IPD_PIMS_CNT) as u32 + rem
+    };
+    if idx as usize >= IPD_CNT {
+        return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+    }
+    let ipm = mpm[idx as usize];
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "ipm Y ");
+    EVC_TRACE(&mut bs.tracer, ipm);
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    Ok(ipm)
+}
+
+pub(crate) fn evcd_eco_intra_dir_c(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    ipm_l: IntraPredDir,
+) -> Result<u8, EvcError> {
+    let mut ipm = IntraPredDir::IPD_DM_C.0 as u8;
+    if sbac.decode_bin(bs, &mut sbac_ctx.intra_dir_c[0])? == 0 {
+        /* the chroma mode predicting the same as DM is not signalled */
+        let ipm_dup = evc_ipred_conv_l2c(ipm_l);
+        ipm = sbac.read_unary_sym_ep(bs, IPD_CHROMA_CNT as u32 - 1)? as u8 + 1;
+        if ipm_dup != IntraPredDir::IPD_INVALID && ipm >= ipm_dup.0 as u8 {
+            ipm += 1;
+        }
+        if ipm as usize >= IPD_CHROMA_CNT {
+            return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+        }
+    }
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "ipm UV ");
+    EVC_TRACE(&mut bs.tracer, ipm);
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    Ok(ipm)
+}
+
 pub(crate) fn evcd_eco_cbf(
     bs: &mut EvcdBsr,
     sbac: &mut EvcdSbac,
@@ -527,6 +1483,7 @@ pub(crate) fn evcd_eco_cbf(
     is_sub: bool,
     sub_pos: u8,
     cbf_all: &mut bool,
+    chroma_format_idc: u8,
 ) -> Result<(), EvcError> {
     /* decode allcbf */
     if pred_mode != PredMode::MODE_INTRA {
@@ -551,17 +1508,23 @@ pub(crate) fn evcd_eco_cbf(
             }
         }
 
-        cbf[U_C] = sbac.decode_bin(bs, &mut sbac_ctx.cbf_cb[0])? != 0;
-        EVC_TRACE_COUNTER(&mut bs.tracer);
-        EVC_TRACE(&mut bs.tracer, "cbf U ");
-        EVC_TRACE(&mut bs.tracer, cbf[U_C] as u8);
-        EVC_TRACE(&mut bs.tracer, " \n");
+        /* monochrome pictures have no chroma residual */
+        if chroma_format_idc != 0 {
+            cbf[U_C] = sbac.decode_bin(bs, &mut sbac_ctx.cbf_cb[0])? != 0;
+            EVC_TRACE_COUNTER(&mut bs.tracer);
+            EVC_TRACE(&mut bs.tracer, "cbf U ");
+            EVC_TRACE(&mut bs.tracer, cbf[U_C] as u8);
+            EVC_TRACE(&mut bs.tracer, " \n");
 
-        cbf[V_C] = sbac.decode_bin(bs, &mut sbac_ctx.cbf_cr[0])? != 0;
-        EVC_TRACE_COUNTER(&mut bs.tracer);
-        EVC_TRACE(&mut bs.tracer, "cbf V ");
-        EVC_TRACE(&mut bs.tracer, cbf[V_C] as u8);
-        EVC_TRACE(&mut bs.tracer, " \n");
+            cbf[V_C] = sbac.decode_bin(bs, &mut sbac_ctx.cbf_cr[0])? != 0;
+            EVC_TRACE_COUNTER(&mut bs.tracer);
+            EVC_TRACE(&mut bs.tracer, "cbf V ");
+            EVC_TRACE(&mut bs.tracer, cbf[V_C] as u8);
+            EVC_TRACE(&mut bs.tracer, " \n");
+        } else {
+            cbf[U_C] = false;
+            cbf[V_C] = false;
+        }
 
         if cbf[U_C] == false && cbf[V_C] == false && !is_sub {
             cbf[Y_C] = true;
@@ -573,17 +1536,23 @@ pub(crate) fn evcd_eco_cbf(
             EVC_TRACE(&mut bs.tracer, " \n");
         }
     } else {
-        cbf[U_C] = sbac.decode_bin(bs, &mut sbac_ctx.cbf_cb[0])? != 0;
-        EVC_TRACE_COUNTER(&mut bs.tracer);
-        EVC_TRACE(&mut bs.tracer, "cbf U ");
-        EVC_TRACE(&mut bs.tracer, cbf[U_C] as u8);
-        EVC_TRACE(&mut bs.tracer, " \n");
+        /* monochrome pictures have no chroma residual */
+        if chroma_format_idc != 0 {
+            cbf[U_C] = sbac.decode_bin(bs, &mut sbac_ctx.cbf_cb[0])? != 0;
+            EVC_TRACE_COUNTER(&mut bs.tracer);
+            EVC_TRACE(&mut bs.tracer, "cbf U ");
+            EVC_TRACE(&mut bs.tracer, cbf[U_C] as u8);
+            EVC_TRACE(&mut bs.tracer, " \n");
 
-        cbf[V_C] = sbac.decode_bin(bs, &mut sbac_ctx.cbf_cr[0])? != 0;
-        EVC_TRACE_COUNTER(&mut bs.tracer);
-        EVC_TRACE(&mut bs.tracer, "cbf V ");
-        EVC_TRACE(&mut bs.tracer, cbf[V_C] as u8);
-        EVC_TRACE(&mut bs.tracer, " \n");
+            cbf[V_C] = sbac.decode_bin(bs, &mut sbac_ctx.cbf_cr[0])? != 0;
+            EVC_TRACE_COUNTER(&mut bs.tracer);
+            EVC_TRACE(&mut bs.tracer, "cbf V ");
+            EVC_TRACE(&mut bs.tracer, cbf[V_C] as u8);
+            EVC_TRACE(&mut bs.tracer, " \n");
+        } else {
+            cbf[U_C] = false;
+            cbf[V_C] = false;
+        }
 
         cbf[Y_C] = sbac.decode_bin(bs, &mut sbac_ctx.cbf_luma[0])? != 0;
         EVC_TRACE_COUNTER(&mut bs.tracer);
@@ -595,6 +1564,85 @@ pub(crate) fn evcd_eco_cbf(
     Ok(())
 }
 
+pub(crate) fn evcd_eco_ats_intra_cu(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+) -> Result<(bool, u8), EvcError> {
+    let ats_intra_cu = sbac.decode_bin_ep(bs)? != 0;
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "ats intra CU ");
+    EVC_TRACE(&mut bs.tracer, ats_intra_cu as u8);
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    let mut ats_mode = 0;
+    if ats_intra_cu {
+        ats_mode = (sbac.decode_bin(bs, &mut sbac_ctx.ats_mode[0])? << 1) as u8;
+        ats_mode |= sbac.decode_bin(bs, &mut sbac_ctx.ats_mode[0])? as u8;
+        EVC_TRACE_COUNTER(&mut bs.tracer);
+        EVC_TRACE(&mut bs.tracer, "ats intra mode ");
+        EVC_TRACE(&mut bs.tracer, ats_mode);
+        EVC_TRACE(&mut bs.tracer, " \n");
+    }
+
+    Ok((ats_intra_cu, ats_mode))
+}
+
+pub(crate) fn evcd_eco_ats_inter_info(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    log2_cuw: u8,
+    log2_cuh: u8,
+    sps_cm_init_flag: bool,
+) -> Result<u8, EvcError> {
+    let ctx = if sps_cm_init_flag && log2_cuw + log2_cuh < 8 {
+        1
+    } else {
+        0
+    };
+    let mut ats_inter_info = 0;
+
+    if sbac.decode_bin(bs, &mut sbac_ctx.ats_inter_flag[ctx])? != 0 {
+        let is_quad = if evc_check_ats_inter_idx(ATS_INTER_QUAD_VER, log2_cuw, log2_cuh)
+            || evc_check_ats_inter_idx(ATS_INTER_QUAD_HOR, log2_cuw, log2_cuh)
+        {
+            sbac.decode_bin(bs, &mut sbac_ctx.ats_inter_quad_flag[0])? != 0
+        } else {
+            false
+        };
+
+        let (idx_ver, idx_hor) = if is_quad {
+            (ATS_INTER_QUAD_VER, ATS_INTER_QUAD_HOR)
+        } else {
+            (ATS_INTER_HALF_VER, ATS_INTER_HALF_HOR)
+        };
+        let ver_allowed = evc_check_ats_inter_idx(idx_ver, log2_cuw, log2_cuh);
+        let is_hor = if ver_allowed && evc_check_ats_inter_idx(idx_hor, log2_cuw, log2_cuh) {
+            let ctx = if !sps_cm_init_flag || log2_cuw == log2_cuh {
+                0
+            } else if log2_cuw < log2_cuh {
+                1
+            } else {
+                2
+            };
+            sbac.decode_bin(bs, &mut sbac_ctx.ats_inter_hor_flag[ctx])? != 0
+        } else {
+            !ver_allowed
+        };
+
+        let pos = sbac.decode_bin(bs, &mut sbac_ctx.ats_inter_pos_flag[0])? as u8;
+        ats_inter_info = if is_hor { idx_hor } else { idx_ver } | (pos << 4);
+    }
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "ats inter info ");
+    EVC_TRACE(&mut bs.tracer, ats_inter_info);
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    Ok(ats_inter_info)
+}
+
 pub(crate) fn evcd_eco_dqp(
     bs: &mut EvcdBsr,
     sbac: &mut EvcdSbac,
@@ -623,8 +1671,23 @@ pub(crate) fn evcd_eco_xcoef(
     log2_w: u8,
     log2_h: u8,
     ch_type: usize,
+    sps_cm_init_flag: bool,
+    sps_adcc_flag: bool,
 ) -> Result<(), EvcError> {
-    evcd_eco_run_length_cc(bs, sbac, sbac_ctx, coef, log2_w, log2_h, ch_type)?;
+    if sps_adcc_flag {
+        evcd_eco_adcc(bs, sbac, sbac_ctx, coef, log2_w, log2_h, ch_type)?;
+    } else {
+        evcd_eco_run_length_cc(
+            bs,
+            sbac,
+            sbac_ctx,
+            coef,
+            log2_w,
+            log2_h,
+            ch_type,
+            sps_cm_init_flag,
+        )?;
+    }
 
     TRACE_COEF(
         &mut bs.tracer,
@@ -645,8 +1708,9 @@ pub(crate) fn evcd_eco_run_length_cc(
     log2_w: u8,
     log2_h: u8,
     ch_type: usize,
+    sps_cm_init_flag: bool,
 ) -> Result<(), EvcError> {
-    let scanp = &evc_scan_tbl[log2_w as usize - 1];
+    let scanp = &evc_scan_tbl[log2_w as usize - 1][log2_h as usize - 1];
     let num_coeff = 1 << (log2_w + log2_h) as u32;
     let mut scan_pos_offset = 0;
     let mut prev_level = 6;
@@ -654,10 +1718,21 @@ pub(crate) fn evcd_eco_run_length_cc(
 
     let mut last_flag = false;
     while !last_flag {
-        let t0 = if ch_type == Y_C { 0 } else { 2 };
+        /* with cm_init, the contexts also depend on the previous level */
+        let t0 = if sps_cm_init_flag {
+            (prev_level.min(6) as usize - 1) * 2 + if ch_type == Y_C { 0 } else { 12 }
+        } else if ch_type == Y_C {
+            0
+        } else {
+            2
+        };
 
         /* Run parsing */
         let run = sbac.read_unary_sym(bs, &mut sbac_ctx.run[t0..], 2)?;
+        if scan_pos_offset + run >= num_coeff {
+            error!("malformed bitstream: coefficient run out of the block\n");
+            return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+        }
         for i in scan_pos_offset..scan_pos_offset + run {
             coef[scanp[i as usize] as usize] = 0;
         }
@@ -699,6 +1774,213 @@ pub(crate) fn evcd_eco_run_length_cc(
     Ok(())
 }
 
+fn evcd_eco_last_sig_coeff_pos(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    models: &mut [SBAC_CTX_MODEL],
+    log2_size: u8,
+    ch_type: usize,
+) -> Result<usize, EvcError> {
+    let (offset, shift) = evc_get_ctx_last_pos_xy(log2_size, ch_type);
+    let max_group = evc_tbl_group_idx[(1 << log2_size) - 1] as usize;
+
+    let mut group = 0;
+    while group < max_group && sbac.decode_bin(bs, &mut models[offset + (group >> shift)])? != 0 {
+        group += 1;
+    }
+
+    Ok(group)
+}
+
+fn evcd_eco_last_sig_coeff_suffix(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    group: usize,
+) -> Result<usize, EvcError> {
+    let mut pos = evc_tbl_min_in_group[group] as usize;
+    if group > 3 {
+        pos += sbac.decode_bins_ep(bs, (group >> 1) - 1)? as usize;
+    }
+
+    Ok(pos)
+}
+
+/* level remainder of the advanced coefficient coding, binarized by a Rice code
+escaping to an Exp-Golomb code */
+fn evcd_eco_coef_remain(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    rparam: u32,
+) -> Result<u32, EvcError> {
+    let range = evc_tbl_go_rice_range[rparam as usize] as u32;
+    let mut prefix = 0;
+    while prefix < 32 && sbac.decode_bin_ep(bs)? != 0 {
+        prefix += 1;
+    }
+
+    if prefix < range {
+        let code = sbac.decode_bins_ep(bs, rparam as usize)?;
+        Ok((prefix << rparam) + code)
+    } else {
+        let length = prefix - range + rparam;
+        if length > 16 {
+            error!("malformed bitstream: coefficient level remainder too long\n");
+            return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+        }
+        let code = sbac.decode_bins_ep(bs, length as usize)?;
+        Ok((((1 << (prefix - range)) + range - 1) << rparam) + code)
+    }
+}
+
+/* the coefficients are coded backwards in groups of 16 scan positions: the
+significance flags, the greater-than flags of the first 8 significant levels,
+the remainders and the signs */
+pub(crate) fn evcd_eco_adcc(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    coef: &mut [i16],
+    log2_w: u8,
+    log2_h: u8,
+    ch_type: usize,
+) -> Result<(), EvcError> {
+    let scanp = &evc_scan_tbl[log2_w as usize - 1][log2_h as usize - 1];
+    let inv_scanp = &evc_inv_scan_tbl[log2_w as usize - 1][log2_h as usize - 1];
+    let sig_set = evc_get_sig_coeff_set(log2_w, log2_h, ch_type);
+    let ctx_gtx_offset = if ch_type == Y_C { 0 } else { NUM_CTX_GTX_LUMA };
+
+    let group_x = evcd_eco_last_sig_coeff_pos(
+        bs,
+        sbac,
+        &mut sbac_ctx.last_sig_coeff_x_prefix,
+        log2_w,
+        ch_type,
+    )?;
+    let group_y = evcd_eco_last_sig_coeff_pos(
+        bs,
+        sbac,
+        &mut sbac_ctx.last_sig_coeff_y_prefix,
+        log2_h,
+        ch_type,
+    )?;
+    let last_x = evcd_eco_last_sig_coeff_suffix(bs, sbac, group_x)?;
+    let last_y = evcd_eco_last_sig_coeff_suffix(bs, sbac, group_y)?;
+    if last_x >= (1 << log2_w) || last_y >= (1 << log2_h) {
+        error!("malformed bitstream: last significant coefficient out of the block\n");
+        return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+    }
+    let last_scan_pos = inv_scanp[(last_y << log2_w) + last_x] as usize;
+
+    /* the contexts are derived from the levels decoded so far */
+    for c in coef[..1 << (log2_w + log2_h)].iter_mut() {
+        *c = 0;
+    }
+
+    let mut start = last_scan_pos & !15;
+    let mut end = last_scan_pos;
+    loop {
+        let mut pos = [0usize; 16];
+        let mut abs_level = [0u32; 16];
+        let mut num_nz = 0;
+
+        for scan_pos in (start..=end).rev() {
+            let blk_pos = scanp[scan_pos] as usize;
+            let sig = if scan_pos == last_scan_pos {
+                1
+            } else {
+                let ctx = evc_get_ctx_sig_coeff_inc(coef, blk_pos, log2_w, log2_h, ch_type);
+                sbac.decode_bin(bs, &mut sbac_ctx.sig_coeff_flag[sig_set][ctx])?
+            };
+            if sig != 0 {
+                coef[blk_pos] = 1;
+                pos[num_nz] = blk_pos;
+                abs_level[num_nz] = 1;
+                num_nz += 1;
+            }
+        }
+
+        if num_nz > 0 {
+            let mut first_c2 = None;
+            let mut escape = false;
+            for idx in 0..std::cmp::min(num_nz, NUM_CTX_GTX_COEF) {
+                let blk_pos = pos[idx];
+                let ctx = if blk_pos == scanp[last_scan_pos] as usize {
+                    0
+                } else {
+                    evc_get_ctx_gtx_inc(coef, blk_pos, log2_w, log2_h, ch_type, 1)
+                };
+                let bin = sbac.decode_bin(
+                    bs,
+                    &mut sbac_ctx.coeff_abs_level_greaterAB_flag[ctx_gtx_offset + ctx],
+                )?;
+                coef[blk_pos] += bin as i16;
+                abs_level[idx] = 1 + bin;
+                if bin != 0 {
+                    if first_c2.is_none() {
+                        first_c2 = Some(idx);
+                    } else {
+                        escape = true;
+                    }
+                }
+            }
+
+            if let Some(idx) = first_c2 {
+                let blk_pos = pos[idx];
+                let ctx = if blk_pos == scanp[last_scan_pos] as usize {
+                    0
+                } else {
+                    evc_get_ctx_gtx_inc(coef, blk_pos, log2_w, log2_h, ch_type, 2)
+                };
+                let bin = sbac.decode_bin(
+                    bs,
+                    &mut sbac_ctx.coeff_abs_level_greaterAB_flag[ctx_gtx_offset + ctx],
+                )?;
+                coef[blk_pos] += bin as i16;
+                abs_level[idx] = 2 + bin;
+                escape |= bin != 0;
+            }
+
+            if escape || num_nz > NUM_CTX_GTX_COEF {
+                let mut c2_flag = 1;
+                for idx in 0..num_nz {
+                    let blk_pos = pos[idx];
+                    let base = if idx < NUM_CTX_GTX_COEF {
+                        2 + c2_flag
+                    } else {
+                        1
+                    };
+                    if abs_level[idx] == base {
+                        let rparam = evc_get_rice_para(coef, blk_pos, log2_w, log2_h, base);
+                        abs_level[idx] = base + evcd_eco_coef_remain(bs, sbac, rparam)?;
+                        if abs_level[idx] > 32767 {
+                            error!("malformed bitstream: coefficient level out of range\n");
+                            return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+                        }
+                        coef[blk_pos] = abs_level[idx] as i16;
+                    }
+                    if abs_level[idx] >= 2 {
+                        c2_flag = 0;
+                    }
+                }
+            }
+
+            for idx in 0..num_nz {
+                if sbac.decode_bin_ep(bs)? != 0 {
+                    coef[pos[idx]] = -(abs_level[idx] as i16);
+                }
+            }
+        }
+
+        if start == 0 {
+            break;
+        }
+        end = start - 1;
+        start -= 16;
+    }
+
+    Ok(())
+}
+
 fn evcd_eco_coef(
     bs: &mut EvcdBsr,
     sbac: &mut EvcdSbac,
@@ -711,9 +1993,15 @@ fn evcd_eco_coef(
     cu_qp_delta_code: u8,
     sh_qp_u_offset: i8,
     sh_qp_v_offset: i8,
+    sps_admvp_flag: bool,
+    sps_ats_flag: bool,
+    sps_cm_init_flag: bool,
+    sps_adcc_flag: bool,
+    chroma_format_idc: u8,
 ) -> Result<(), EvcError> {
     let mut cbf = [false; N_C];
-    let mut b_no_cbf = false;
+    /* a merge CU always has a residual, so the all-zero flag is not coded */
+    let b_no_cbf = sps_admvp_flag && core.inter_dir == InterPredDir::PRED_DIR;
 
     let mut tmp_coef = [0; N_C];
     let is_sub = false;
@@ -730,6 +2018,7 @@ fn evcd_eco_coef(
             is_sub,
             0,
             &mut cbf_all,
+            chroma_format_idc,
         )?;
     } else {
         cbf[Y_C] = false;
@@ -750,6 +2039,28 @@ fn evcd_eco_coef(
     core.qp = GET_QP(core.qp as i8, dqp) as u8;
     core.qp_y = GET_LUMA_QP(core.qp as i8) as u8;
 
+    if sps_ats_flag
+        && core.pred_mode == PredMode::MODE_INTRA
+        && cbf[Y_C]
+        && log2_cuw as usize <= MAX_TR_LOG2_ATS
+        && log2_cuh as usize <= MAX_TR_LOG2_ATS
+    {
+        let (ats_intra_cu, ats_mode) = evcd_eco_ats_intra_cu(bs, sbac, sbac_ctx)?;
+        core.ats_intra_cu = ats_intra_cu;
+        core.ats_mode = ats_mode;
+    }
+
+    /* the sub-block transform of an inter residual */
+    if sps_ats_flag
+        && core.pred_mode != PredMode::MODE_INTRA
+        && (cbf[Y_C] || cbf[U_C] || cbf[V_C])
+        && (evc_check_ats_inter_idx(ATS_INTER_HALF_VER, log2_cuw, log2_cuh)
+            || evc_check_ats_inter_idx(ATS_INTER_HALF_HOR, log2_cuw, log2_cuh))
+    {
+        core.ats_inter_info =
+            evcd_eco_ats_inter_info(bs, sbac, sbac_ctx, log2_cuw, log2_cuh, sps_cm_init_flag)?;
+    }
+
     let qp_i_cb = EVC_CLIP3(
         -6 * (BIT_DEPTH as i8 - 8),
         57,
@@ -769,15 +2080,26 @@ fn evcd_eco_coef(
 
     for c in 0..N_C {
         if cbf[c] {
-            let chroma = if c > 0 { 1 } else { 0 };
+            let (w_shift, h_shift) = if c > 0 {
+                (
+                    GET_CHROMA_W_SHIFT(chroma_format_idc) as u8,
+                    GET_CHROMA_H_SHIFT(chroma_format_idc) as u8,
+                )
+            } else {
+                (0, 0)
+            };
+            let (_, _, log2_w, log2_h) =
+                evc_get_ats_inter_trs(core.ats_inter_info, log2_cuw - w_shift, log2_cuh - h_shift);
             evcd_eco_xcoef(
                 bs,
                 sbac,
                 sbac_ctx,
                 &mut core.coef.data[tbl_cu_dim_offset[c]..],
-                log2_cuw - chroma,
-                log2_cuh - chroma,
+                log2_w,
+                log2_h,
                 c,
+                sps_cm_init_flag,
+                sps_adcc_flag,
             )?;
 
             tmp_coef[c] += 1;
@@ -793,6 +2115,23 @@ fn evcd_eco_coef(
     Ok(())
 }
 
+/* MMVD index of a skip or merge CU, whose base candidate is also kept as the
+merge index */
+fn evcd_eco_mmvd(
+    bs: &mut EvcdBsr,
+    sbac: &mut EvcdSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    core: &mut EvcdCore,
+    num_groups: usize,
+) -> Result<(), EvcError> {
+    core.mmvd_idx = evcd_eco_mmvd_idx(bs, sbac, sbac_ctx, num_groups)?;
+    let (_, merge_idx, _, _) = evc_mmvd_idx_split(core.mmvd_idx);
+    core.mvp_idx[REFP_0] = merge_idx as u8;
+    core.mvp_idx[REFP_1] = merge_idx as u8;
+
+    Ok(())
+}
+
 fn evcd_eco_cu(
     bs: &mut EvcdBsr,
     sbac: &mut EvcdSbac,
@@ -805,7 +2144,7 @@ fn evcd_eco_cu(
     w_scu: u16,
     map_scu: &[MCU],
     map_ipm: &[IntraPredDir],
-    dpm: &Option<EvcPm>,
+    num_refp: &[u8; REFP_NUM],
     sps_dquant_flag: bool,
     pps_cu_qp_delta_enabled_flag: bool,
     cu_qp_delta_code: u8,
@@ -813,6 +2152,17 @@ fn evcd_eco_cu(
     sh_qp: u8,
     sh_qp_u_offset: i8,
     sh_qp_v_offset: i8,
+    sps_admvp_flag: bool,
+    sps_eipd_flag: bool,
+    sps_affine_flag: bool,
+    sps_amvr_flag: bool,
+    sps_mmvd_flag: bool,
+    sh_mmvd_group_enable_flag: bool,
+    sps_ats_flag: bool,
+    sps_cm_init_flag: bool,
+    sps_adcc_flag: bool,
+    chroma_format_idc: u8,
+    mode_cons: ModeCons,
 ) -> Result<(), EvcError> {
     //CU position X in a frame in SCU unit
     let x_scu = PEL2SCU(x as usize) as u16;
@@ -832,18 +2182,31 @@ fn evcd_eco_cu(
     core.mvp_idx[REFP_0] = 0;
     core.mvp_idx[REFP_1] = 0;
     core.inter_dir = InterPredDir::PRED_L0;
+    core.bi_idx = BI_NON;
+    core.affine_flag = 0;
+    core.mvr_idx = 0;
+    core.mmvd_flag = false;
+    core.mmvd_idx = 0;
+    core.ats_intra_cu = false;
+    core.ats_mode = 0;
+    core.ats_inter_info = 0;
     for i in 0..REFP_NUM {
         for j in 0..MV_D {
             core.mvd[i][j] = 0;
         }
     }
+    core.affine_mvd = [[[0; MV_D]; VER_NUM]; REFP_NUM];
 
     let cuw = 1 << log2_cuw;
     let cuh = 1 << log2_cuh;
+    let mmvd_num_groups =
+        evc_mmvd_num_groups(sh_mmvd_group_enable_flag, cuw as usize, cuh as usize);
+    let ctx_flags =
+        evc_get_ctx_some_flags(x_scu, y_scu, cuw, cuh, w_scu, map_scu, sps_cm_init_flag);
 
-    if sh_slice_type != SliceType::EVC_ST_I {
+    if mode_cons != ModeCons::eOnlyIntra {
         /* CU skip flag */
-        let cu_skip_flag = evcd_eco_cu_skip_flag(bs, sbac, sbac_ctx)?;
+        let cu_skip_flag = evcd_eco_cu_skip_flag(bs, sbac, sbac_ctx, ctx_flags[CNID_SKIP_FLAG])?;
         if cu_skip_flag != 0 {
             core.pred_mode = PredMode::MODE_SKIP;
         }
@@ -851,9 +2214,30 @@ fn evcd_eco_cu(
 
     /* parse prediction info */
     if core.pred_mode == PredMode::MODE_SKIP {
-        core.mvp_idx[REFP_0] = evcd_eco_mvp_idx(bs, sbac, sbac_ctx)?;
-        if sh_slice_type == SliceType::EVC_ST_B {
-            core.mvp_idx[REFP_1] = evcd_eco_mvp_idx(bs, sbac, sbac_ctx)?;
+        if sps_mmvd_flag {
+            core.mmvd_flag = evcd_eco_mmvd_flag(bs, sbac, sbac_ctx)?;
+        }
+
+        if !core.mmvd_flag && sps_affine_flag && cuw >= 8 && cuh >= 8 {
+            core.affine_flag =
+                evcd_eco_affine_flag(bs, sbac, sbac_ctx, ctx_flags[CNID_AFFN_FLAG])? as u8;
+        }
+
+        if core.mmvd_flag {
+            evcd_eco_mmvd(bs, sbac, sbac_ctx, core, mmvd_num_groups)?;
+        } else if core.affine_flag != 0 {
+            let affine_mrg_idx = evcd_eco_affine_mrg_idx(bs, sbac, sbac_ctx)?;
+            core.mvp_idx[REFP_0] = affine_mrg_idx;
+            core.mvp_idx[REFP_1] = affine_mrg_idx;
+        } else if sps_admvp_flag {
+            let merge_idx = evcd_eco_merge_idx(bs, sbac, sbac_ctx)?;
+            core.mvp_idx[REFP_0] = merge_idx;
+            core.mvp_idx[REFP_1] = merge_idx;
+        } else {
+            core.mvp_idx[REFP_0] = evcd_eco_mvp_idx(bs, sbac, sbac_ctx)?;
+            if sh_slice_type == SliceType::EVC_ST_B {
+                core.mvp_idx[REFP_1] = evcd_eco_mvp_idx(bs, sbac, sbac_ctx)?;
+            }
         }
 
         core.is_coef[Y_C] = false;
@@ -880,37 +2264,143 @@ fn evcd_eco_cu(
             [(EVC_TBL_CHROMA_QP_OFFSET + qp_i_cr) as usize]
             + (6 * (BIT_DEPTH - 8)) as i8) as u8;
     } else {
-        core.pred_mode = evcd_eco_pred_mode(bs, sbac, sbac_ctx, sh_slice_type)?;
+        core.pred_mode =
+            evcd_eco_pred_mode(bs, sbac, sbac_ctx, mode_cons, ctx_flags[CNID_PRED_MODE])?;
+
+        if core.pred_mode == PredMode::MODE_INTER && sps_admvp_flag {
+            if sps_amvr_flag {
+                core.mvr_idx = evcd_eco_mvr_idx(bs, sbac, sbac_ctx)?;
+            }
+
+            /* merge and affine CUs use the quarter sample resolution */
+            if core.mvr_idx == 0 && evcd_eco_merge_mode_flag(bs, sbac, sbac_ctx)? {
+                core.inter_dir = InterPredDir::PRED_DIR;
+                if sps_mmvd_flag {
+                    core.mmvd_flag = evcd_eco_mmvd_flag(bs, sbac, sbac_ctx)?;
+                }
+                if !core.mmvd_flag && sps_affine_flag && cuw >= 8 && cuh >= 8 {
+                    core.affine_flag =
+                        evcd_eco_affine_flag(bs, sbac, sbac_ctx, ctx_flags[CNID_AFFN_FLAG])? as u8;
+                }
+
+                if core.mmvd_flag {
+                    evcd_eco_mmvd(bs, sbac, sbac_ctx, core, mmvd_num_groups)?;
+                } else {
+                    let merge_idx = if core.affine_flag != 0 {
+                        evcd_eco_affine_mrg_idx(bs, sbac, sbac_ctx)?
+                    } else {
+                        evcd_eco_merge_idx(bs, sbac, sbac_ctx)?
+                    };
+                    core.mvp_idx[REFP_0] = merge_idx;
+                    core.mvp_idx[REFP_1] = merge_idx;
+                }
+            } else {
+                /* inter_pred_idc */
+                core.inter_dir =
+                    evcd_eco_inter_pred_idc(bs, sbac, sbac_ctx, sh_slice_type, cuw, cuh, true)?;
+                if core.mvr_idx == 0 && sps_affine_flag && cuw >= 16 && cuh >= 16 {
+                    core.affine_flag =
+                        evcd_eco_affine_flag(bs, sbac, sbac_ctx, ctx_flags[CNID_AFFN_FLAG])? as u8;
+                }
 
-        if core.pred_mode == PredMode::MODE_INTER {
+                if core.affine_flag != 0 {
+                    /* 1: 4-parameter model, 2: 6-parameter model */
+                    core.affine_flag += evcd_eco_affine_mode(bs, sbac, sbac_ctx)?;
+
+                    for inter_dir_idx in 0..2 {
+                        /* 0: forward, 1: backward */
+                        if (((core.inter_dir as usize + 1) >> inter_dir_idx) & 1) != 0 {
+                            core.refi[inter_dir_idx] =
+                                evcd_eco_refi(bs, sbac, sbac_ctx, num_refp[inter_dir_idx])? as i8;
+                            core.mvp_idx[inter_dir_idx] =
+                                evcd_eco_affine_mvp_idx(bs, sbac, sbac_ctx)?;
+                            if !evcd_eco_affine_mvd_flag(bs, sbac, sbac_ctx, inter_dir_idx)? {
+                                for vertex in 0..=core.affine_flag as usize {
+                                    evcd_eco_get_mvd(
+                                        bs,
+                                        sbac,
+                                        sbac_ctx,
+                                        &mut core.affine_mvd[inter_dir_idx][vertex],
+                                        0,
+                                    )?;
+                                }
+                            }
+                        }
+                    }
+                } else {
+                    if core.inter_dir == InterPredDir::PRED_BI {
+                        core.bi_idx = evcd_eco_bi_idx(bs, sbac, sbac_ctx)?;
+                    }
+
+                    for inter_dir_idx in 0..2 {
+                        /* 0: forward, 1: backward */
+                        if (((core.inter_dir as usize + 1) >> inter_dir_idx) & 1) != 0 {
+                            if core.bi_idx != BI_FL0 && core.bi_idx != BI_FL1 {
+                                core.refi[inter_dir_idx] =
+                                    evcd_eco_refi(bs, sbac, sbac_ctx, num_refp[inter_dir_idx])?
+                                        as i8;
+                            }
+                            /* the motion vector difference of one list may be inferred to be 0 */
+                            if core.bi_idx != BI_FL0 + inter_dir_idx as u8 {
+                                evcd_eco_get_mvd(
+                                    bs,
+                                    sbac,
+                                    sbac_ctx,
+                                    &mut core.mvd[inter_dir_idx],
+                                    core.mvr_idx,
+                                )?;
+                            }
+                        }
+                    }
+                }
+            }
+        } else if core.pred_mode == PredMode::MODE_INTER {
             //TODO: bugfix? missing SLICE_TYPE==B for direct_mode_flag?
             core.inter_dir = evcd_eco_direct_mode_flag(bs, sbac, sbac_ctx)?;
 
             if core.inter_dir != InterPredDir::PRED_DIR {
                 /* inter_pred_idc */
-                core.inter_dir = evcd_eco_inter_pred_idc(bs, sbac, sbac_ctx, sh_slice_type)?;
+                core.inter_dir =
+                    evcd_eco_inter_pred_idc(bs, sbac, sbac_ctx, sh_slice_type, cuw, cuh, false)?;
 
                 for inter_dir_idx in 0..2 {
                     /* 0: forward, 1: backward */
                     if (((core.inter_dir as usize + 1) >> inter_dir_idx) & 1) != 0 {
-                        core.refi[inter_dir_idx] = evcd_eco_refi(
-                            bs,
-                            sbac,
-                            sbac_ctx,
-                            dpm.as_ref().unwrap().num_refp[inter_dir_idx],
-                        )? as i8;
+                        core.refi[inter_dir_idx] =
+                            evcd_eco_refi(bs, sbac, sbac_ctx, num_refp[inter_dir_idx])? as i8;
                         core.mvp_idx[inter_dir_idx] = evcd_eco_mvp_idx(bs, sbac, sbac_ctx)?;
-                        evcd_eco_get_mvd(bs, sbac, sbac_ctx, &mut core.mvd[inter_dir_idx])?;
+                        evcd_eco_get_mvd(bs, sbac, sbac_ctx, &mut core.mvd[inter_dir_idx], 0)?;
                     }
                 }
             }
         } else if core.pred_mode == PredMode::MODE_INTRA {
-            let mpm_b_list = evc_get_mpm_b(x_scu, y_scu, map_scu, map_ipm, scup, w_scu);
+            if sps_eipd_flag {
+                let mut mpm_list = [0u8; IPD_CNT];
+                evc_get_mpm(
+                    x_scu,
+                    y_scu,
+                    cuw as u16,
+                    map_scu,
+                    map_ipm,
+                    scup,
+                    w_scu,
+                    &mut mpm_list,
+                );
 
-            let mut luma_ipm = IntraPredDir::IPD_DC_B;
-            core.ipm[0] = evcd_eco_intra_dir_b(bs, sbac, sbac_ctx, mpm_b_list)?.into();
-            luma_ipm = core.ipm[0];
-            core.ipm[1] = luma_ipm;
+                core.ipm[0] = evcd_eco_intra_dir(bs, sbac, sbac_ctx, &mpm_list)?.into();
+                core.ipm[1] = if chroma_format_idc != 0 {
+                    evcd_eco_intra_dir_c(bs, sbac, sbac_ctx, core.ipm[0])?.into()
+                } else {
+                    IntraPredDir::IPD_DM_C
+                };
+            } else {
+                let mpm_b_list = evc_get_mpm_b(x_scu, y_scu, map_scu, map_ipm, scup, w_scu);
+
+                let mut luma_ipm = IntraPredDir::IPD_DC_B;
+                core.ipm[0] = evcd_eco_intra_dir_b(bs, sbac, sbac_ctx, mpm_b_list)?.into();
+                luma_ipm = core.ipm[0];
+                core.ipm[1] = luma_ipm;
+            }
 
             core.refi[REFP_0] = REFI_INVALID;
             core.refi[REFP_1] = REFI_INVALID;
@@ -919,18 +2409,20 @@ fn evcd_eco_cu(
             core.mv[REFP_1][MV_X] = 0;
             core.mv[REFP_1][MV_Y] = 0;
         } else {
-            evc_assert_rv(false, EvcError::EVC_ERR_MALFORMED_BITSTREAM)?;
+            return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
         }
 
         /* clear coefficient buffer */
         let cuwh = (cuw * cuh) as usize;
+        let cuwh_c =
+            cuwh >> (GET_CHROMA_W_SHIFT(chroma_format_idc) + GET_CHROMA_H_SHIFT(chroma_format_idc));
         for v in &mut core.coef.data[tbl_cu_dim_offset[Y_C]..tbl_cu_dim_offset[Y_C] + cuwh] {
             *v = 0;
         }
-        for v in &mut core.coef.data[tbl_cu_dim_offset[U_C]..tbl_cu_dim_offset[U_C] + (cuwh >> 2)] {
+        for v in &mut core.coef.data[tbl_cu_dim_offset[U_C]..tbl_cu_dim_offset[U_C] + cuwh_c] {
             *v = 0;
         }
-        for v in &mut core.coef.data[tbl_cu_dim_offset[V_C]..tbl_cu_dim_offset[V_C] + (cuwh >> 2)] {
+        for v in &mut core.coef.data[tbl_cu_dim_offset[V_C]..tbl_cu_dim_offset[V_C] + cuwh_c] {
             *v = 0;
         }
 
@@ -947,6 +2439,11 @@ fn evcd_eco_cu(
             cu_qp_delta_code,
             sh_qp_u_offset,
             sh_qp_v_offset,
+            sps_admvp_flag,
+            sps_ats_flag,
+            sps_cm_init_flag,
+            sps_adcc_flag,
+            chroma_format_idc,
         )?;
     }
 
@@ -966,24 +2463,40 @@ pub(crate) fn evcd_eco_unit(
     h_scu: u16,
     w: u16,
     h: u16,
-    map_mv: &Option<Rc<RefCell<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    map_mv: &Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    map_refined_mv: &Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    map_refi: &Option<Arc<RwLock<Vec<[i8; REFP_NUM]>>>>,
     refp: &Vec<Vec<EvcRefP>>,
     map_scu: &[MCU],
     map_ipm: &[IntraPredDir],
-    dpm: &Option<EvcPm>,
+    map_affine: &[u32],
+    num_refp: &[u8; REFP_NUM],
     poc_val: i32,
-    pic: &Option<Rc<RefCell<EvcPic>>>,
+    pic: &Option<Arc<RwLock<EvcPic>>>,
     sps_dquant_flag: bool,
     pps_cu_qp_delta_enabled_flag: bool,
     cu_qp_delta_code: u8,
     pps_constrained_intra_pred_flag: bool,
-    sh_slice_type: SliceType,
-    sh_qp: u8,
-    sh_qp_u_offset: i8,
-    sh_qp_v_offset: i8,
+    sh: &EvcSh,
+    sps_admvp_flag: bool,
+    sps_hmvp_flag: bool,
+    sps_eipd_flag: bool,
+    sps_affine_flag: bool,
+    sps_dmvr_flag: bool,
+    sps_amvr_flag: bool,
+    sps_mmvd_flag: bool,
+    sps_iqt_flag: bool,
+    sps_ats_flag: bool,
+    sps_cm_init_flag: bool,
+    sps_adcc_flag: bool,
+    chroma_format_idc: u8,
+    mode_cons: ModeCons,
 ) -> Result<(), EvcError> {
+    let sh_slice_type = sh.slice_type;
     let cuw = 1 << log2_cuw;
     let cuh = 1 << log2_cuh;
+    let w_shift = GET_CHROMA_W_SHIFT(chroma_format_idc);
+    let h_shift = GET_CHROMA_H_SHIFT(chroma_format_idc);
 
     //CU position X in a frame in SCU unit
     let x_scu = PEL2SCU(x as usize) as u16;
@@ -1020,14 +2533,25 @@ pub(crate) fn evcd_eco_unit(
         w_scu,
         map_scu,
         map_ipm,
-        dpm,
+        num_refp,
         sps_dquant_flag,
         pps_cu_qp_delta_enabled_flag,
         cu_qp_delta_code,
         sh_slice_type,
-        sh_qp,
-        sh_qp_u_offset,
-        sh_qp_v_offset,
+        sh.qp,
+        sh.qp_u_offset,
+        sh.qp_v_offset,
+        sps_admvp_flag,
+        sps_eipd_flag,
+        sps_affine_flag,
+        sps_amvr_flag,
+        sps_mmvd_flag,
+        sh.mmvd_group_enable_flag,
+        sps_ats_flag,
+        sps_cm_init_flag,
+        sps_adcc_flag,
+        chroma_format_idc,
+        mode_cons,
     )?;
 
     /* inverse transform and dequantization */
@@ -1041,6 +2565,11 @@ pub(crate) fn evcd_eco_unit(
             core.qp_u,
             core.qp_v,
             &core.is_coef,
+            sps_iqt_flag,
+            core.ats_intra_cu,
+            core.ats_mode,
+            core.ats_inter_info,
+            chroma_format_idc,
         );
     }
 
@@ -1056,7 +2585,56 @@ pub(crate) fn evcd_eco_unit(
             cuh as usize,
             map_scu,
         );
-        if core.pred_mode == PredMode::MODE_SKIP {
+        let avail_lr = evc_check_nev_avail(x_scu, y_scu, cuw as u16, w_scu, map_scu);
+        if core.affine_flag != 0
+            && (core.pred_mode == PredMode::MODE_SKIP || core.inter_dir == InterPredDir::PRED_DIR)
+        {
+            evcd_get_affine_merge_motion(
+                core, cuw, cuh, w_scu, h_scu, scup, avail_lr, map_mv, map_refi, refp, map_scu,
+                map_affine, poc_val, sh,
+            );
+        } else if core.affine_flag != 0 {
+            evcd_get_affine_amvp_motion(
+                core, cuw, cuh, w_scu, h_scu, scup, map_mv, map_refi, map_scu, map_affine,
+            );
+        } else if sps_admvp_flag
+            && (core.pred_mode == PredMode::MODE_SKIP || core.inter_dir == InterPredDir::PRED_DIR)
+        {
+            evcd_get_merge_motion(
+                core,
+                cuw,
+                cuh,
+                w_scu,
+                h_scu,
+                scup,
+                avail_lr,
+                map_mv,
+                map_refined_mv,
+                map_refi,
+                refp,
+                map_scu,
+                num_refp,
+                poc_val,
+                sh,
+            );
+        } else if sps_admvp_flag {
+            evcd_get_amvp_motion(
+                core,
+                cuw,
+                cuh,
+                w_scu,
+                h_scu,
+                scup,
+                avail_lr,
+                map_mv,
+                map_refi,
+                refp,
+                map_scu,
+                num_refp,
+                poc_val,
+                sps_hmvp_flag,
+            );
+        } else if core.pred_mode == PredMode::MODE_SKIP {
             evcd_get_skip_motion(
                 core,
                 cuw,
@@ -1099,20 +2677,48 @@ pub(crate) fn evcd_eco_unit(
         EVC_TRACE(&mut bs.tracer, " )\n");
 
         let (pred0, pred1) = core.pred.split_at_mut(1);
-        evc_mc2(
-            x as i16,
-            y as i16,
-            w as i16,
-            h as i16,
-            cuw as i16,
-            cuh as i16,
-            &core.refi,
-            &core.mv,
-            refp,
-            &mut pred0[0].data,
-            &mut pred1[0].data,
-        );
+        if core.affine_flag != 0 {
+            core.dmvr_flag = false;
+            evc_affine_mc2(
+                x as i16,
+                y as i16,
+                w as i16,
+                h as i16,
+                cuw as i16,
+                cuh as i16,
+                &core.refi,
+                &core.affine_mv,
+                refp,
+                &mut pred0[0].data,
+                &mut pred1[0].data,
+                core.affine_flag as usize + 1,
+            );
+        } else {
+            /* merge and skip CUs without MMVD are refined on the decoder side */
+            let apply_dmvr = sps_dmvr_flag
+                && !core.mmvd_flag
+                && (core.pred_mode == PredMode::MODE_SKIP
+                    || core.inter_dir == InterPredDir::PRED_DIR);
+            core.dmvr_flag = evc_mc2(
+                x as i16,
+                y as i16,
+                w as i16,
+                h as i16,
+                cuw as i16,
+                cuh as i16,
+                &core.refi,
+                &core.mv,
+                refp,
+                &mut pred0[0].data,
+                &mut pred1[0].data,
+                poc_val as u32,
+                apply_dmvr,
+                &mut core.dmvr_mv,
+                sps_admvp_flag,
+            );
+        }
     } else {
+        core.dmvr_flag = false;
         let avail_cu = evc_get_avail_intra(
             x_scu as usize,
             y_scu as usize,
@@ -1137,43 +2743,91 @@ pub(crate) fn evcd_eco_unit(
             map_scu,
             pic,
             pps_constrained_intra_pred_flag,
+            sps_eipd_flag,
+            chroma_format_idc,
         );
 
         EVC_TRACE_COUNTER(&mut bs.tracer);
         EVC_TRACE(&mut bs.tracer, "Intra: ");
-        EVC_TRACE(&mut bs.tracer, core.ipm[0] as isize);
+        EVC_TRACE(&mut bs.tracer, core.ipm[0].0 as isize);
         EVC_TRACE(&mut bs.tracer, " , ");
-        EVC_TRACE(&mut bs.tracer, core.ipm[1] as isize);
+        EVC_TRACE(&mut bs.tracer, core.ipm[1].0 as isize);
         EVC_TRACE(&mut bs.tracer, " \n");
 
-        evc_ipred_b(
-            &core.nb.data[tbl_nb_siz_offset[Y_C]..tbl_nb_siz_offset[Y_C] + (cuh << 1) as usize],
-            core.nb.data[tbl_nb_siz_offset[Y_C] + (cuh << 1) as usize],
-            &core.nb.data[tbl_nb_siz_offset[Y_C] + (cuh << 1) as usize + 1..],
-            &mut core.pred[0].data[tbl_cu_dim_offset[Y_C]..],
-            core.ipm[0],
-            cuw as usize,
-            cuh as usize,
-        );
-
-        evc_ipred_b(
-            &core.nb.data[tbl_nb_siz_offset[U_C]..tbl_nb_siz_offset[U_C] + cuh as usize],
-            core.nb.data[tbl_nb_siz_offset[U_C] + cuh as usize],
-            &core.nb.data[tbl_nb_siz_offset[U_C] + cuh as usize + 1..],
-            &mut core.pred[0].data[tbl_cu_dim_offset[U_C]..],
-            core.ipm[1],
-            cuw as usize >> 1,
-            cuh as usize >> 1,
-        );
-        evc_ipred_b(
-            &core.nb.data[tbl_nb_siz_offset[V_C]..tbl_nb_siz_offset[V_C] + cuh as usize],
-            core.nb.data[tbl_nb_siz_offset[V_C] + cuh as usize],
-            &core.nb.data[tbl_nb_siz_offset[V_C] + cuh as usize + 1..],
-            &mut core.pred[0].data[tbl_cu_dim_offset[V_C]..],
-            core.ipm[1],
-            cuw as usize >> 1,
-            cuh as usize >> 1,
-        );
+        if sps_eipd_flag {
+            let avail_lr = evc_check_nev_avail(x_scu, y_scu, cuw as u16, w_scu, map_scu);
+            evc_ipred(
+                &core.nb_lines.data[Y_C],
+                avail_lr,
+                &mut core.pred[0].data[tbl_cu_dim_offset[Y_C]..],
+                core.ipm[0],
+                cuw as usize,
+                cuh as usize,
+            );
+        }
+        if sps_eipd_flag && chroma_format_idc != 0 {
+            let avail_lr = evc_check_nev_avail(x_scu, y_scu, cuw as u16, w_scu, map_scu);
+            evc_ipred_uv(
+                &core.nb_lines.data[U_C],
+                avail_lr,
+                &mut core.pred[0].data[tbl_cu_dim_offset[U_C]..],
+                core.ipm[1],
+                core.ipm[0],
+                cuw as usize >> w_shift,
+                cuh as usize >> h_shift,
+            );
+            evc_ipred_uv(
+                &core.nb_lines.data[V_C],
+                avail_lr,
+                &mut core.pred[0].data[tbl_cu_dim_offset[V_C]..],
+                core.ipm[1],
+                core.ipm[0],
+                cuw as usize >> w_shift,
+                cuh as usize >> h_shift,
+            );
+        }
+        if !sps_eipd_flag {
+            evc_ipred_b(
+                &core.nb.data
+                    [tbl_nb_siz_offset[Y_C]..tbl_nb_siz_offset[Y_C] + (cuw + cuh) as usize],
+                core.nb.data[tbl_nb_siz_offset[Y_C] + (cuw + cuh) as usize],
+                &core.nb.data[tbl_nb_siz_offset[Y_C] + (cuw + cuh) as usize + 1..],
+                &mut core.pred[0].data[tbl_cu_dim_offset[Y_C]..],
+                core.ipm[0],
+                cuw as usize,
+                cuh as usize,
+            );
+        }
+        if !sps_eipd_flag && chroma_format_idc != 0 {
+            evc_ipred_b(
+                &core.nb.data[tbl_nb_siz_offset[U_C]
+                    ..tbl_nb_siz_offset[U_C]
+                        + ((cuw as usize >> w_shift) + (cuh as usize >> h_shift))],
+                core.nb.data[tbl_nb_siz_offset[U_C]
+                    + ((cuw as usize >> w_shift) + (cuh as usize >> h_shift))],
+                &core.nb.data[tbl_nb_siz_offset[U_C]
+                    + ((cuw as usize >> w_shift) + (cuh as usize >> h_shift))
+                    + 1..],
+                &mut core.pred[0].data[tbl_cu_dim_offset[U_C]..],
+                core.ipm[1],
+                cuw as usize >> w_shift,
+                cuh as usize >> h_shift,
+            );
+            evc_ipred_b(
+                &core.nb.data[tbl_nb_siz_offset[V_C]
+                    ..tbl_nb_siz_offset[V_C]
+                        + ((cuw as usize >> w_shift) + (cuh as usize >> h_shift))],
+                core.nb.data[tbl_nb_siz_offset[V_C]
+                    + ((cuw as usize >> w_shift) + (cuh as usize >> h_shift))],
+                &core.nb.data[tbl_nb_siz_offset[V_C]
+                    + ((cuw as usize >> w_shift) + (cuh as usize >> h_shift))
+                    + 1..],
+                &mut core.pred[0].data[tbl_cu_dim_offset[V_C]..],
+                core.ipm[1],
+                cuw as usize >> w_shift,
+                cuh as usize >> h_shift,
+            );
+        }
     }
 
     TRACE_PRED(
@@ -1186,15 +2840,15 @@ pub(crate) fn evcd_eco_unit(
     TRACE_PRED(
         &mut bs.tracer,
         U_C,
-        cuw as usize >> 1,
-        cuh as usize >> 1,
+        cuw as usize >> w_shift,
+        cuh as usize >> h_shift,
         &core.pred[0].data[tbl_cu_dim_offset[U_C]..],
     );
     TRACE_PRED(
         &mut bs.tracer,
         V_C,
-        cuw as usize >> 1,
-        cuh as usize >> 1,
+        cuw as usize >> w_shift,
+        cuh as usize >> h_shift,
         &core.pred[0].data[tbl_cu_dim_offset[V_C]..],
     );
 
@@ -1209,7 +2863,8 @@ pub(crate) fn evcd_eco_unit(
             &core.coef.data,
             &core.pred[0].data,
             &core.is_coef,
-            &mut pic.borrow().frame.borrow_mut().planes,
+            &mut pic.read().unwrap().frame.write().unwrap().planes,
+            chroma_format_idc,
         );
     }
 
@@ -1223,14 +2878,14 @@ fn evcd_get_skip_motion(
     w_scu: u16,
     scup: u32,
     avail_cu: u16,
-    map_mv: &Option<Rc<RefCell<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    map_mv: &Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
     refp: &Vec<Vec<EvcRefP>>,
     sh_slice_type: SliceType,
 ) {
     let mut srefi = [[0i8; MAX_NUM_MVP]; REFP_NUM];
     let mut smvp = [[[0i16; MV_D]; MAX_NUM_MVP]; REFP_NUM];
 
-    let map_mv = map_mv.as_ref().unwrap().borrow();
+    let map_mv = map_mv.as_ref().unwrap().read().unwrap();
 
     evc_get_motion(
         scup as usize,
@@ -1281,13 +2936,13 @@ fn evcd_get_inter_motion(
     w_scu: u16,
     scup: u32,
     avail_cu: u16,
-    map_mv: &Option<Rc<RefCell<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    map_mv: &Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
     refp: &Vec<Vec<EvcRefP>>,
 ) {
     let mut mvp = [[0i16; MV_D]; MAX_NUM_MVP];
     let mut refi = [0i8; MAX_NUM_MVP];
 
-    let map_mv = map_mv.as_ref().unwrap().borrow();
+    let map_mv = map_mv.as_ref().unwrap().read().unwrap();
 
     for inter_dir_idx in 0..2 {
         /* 0: forward, 1: backward */
@@ -1316,6 +2971,269 @@ fn evcd_get_inter_motion(
     }
 }
 
+fn evcd_get_merge_motion(
+    core: &mut EvcdCore,
+    cuw: u8,
+    cuh: u8,
+    w_scu: u16,
+    h_scu: u16,
+    scup: u32,
+    avail_lr: u16,
+    map_mv: &Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    map_refined_mv: &Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    map_refi: &Option<Arc<RwLock<Vec<[i8; REFP_NUM]>>>>,
+    refp: &Vec<Vec<EvcRefP>>,
+    map_scu: &[MCU],
+    num_refp: &[u8; REFP_NUM],
+    poc_val: i32,
+    sh: &EvcSh,
+) {
+    let mut srefi = [[0i8; MAX_NUM_MVP]; REFP_NUM];
+    let mut smvp = [[[0i16; MV_D]; MAX_NUM_MVP]; REFP_NUM];
+
+    /* the base candidates of MMVD are derived from the refined motion */
+    let map_mv = if core.mmvd_flag {
+        map_refined_mv
+    } else {
+        map_mv
+    };
+    let map_mv = map_mv.as_ref().unwrap().read().unwrap();
+    let map_refi = map_refi.as_ref().unwrap().read().unwrap();
+
+    evc_get_motion_merge_main(
+        poc_val,
+        sh.slice_type,
+        scup as usize,
+        &*map_refi,
+        &*map_mv,
+        refp,
+        cuw as usize,
+        cuh as usize,
+        w_scu as usize,
+        h_scu as usize,
+        &mut srefi,
+        &mut smvp,
+        map_scu,
+        avail_lr,
+        &core.history_buffer,
+        sh,
+        MAX_CU_LOG2 as u8,
+    );
+
+    for lidx in 0..REFP_NUM {
+        core.refi[lidx] = srefi[lidx][core.mvp_idx[lidx] as usize];
+        core.mv[lidx] = smvp[lidx][core.mvp_idx[lidx] as usize];
+    }
+
+    if sh.slice_type == SliceType::EVC_ST_P {
+        core.refi[REFP_1] = REFI_INVALID;
+        core.mv[REFP_1][MV_X] = 0;
+        core.mv[REFP_1][MV_Y] = 0;
+    }
+
+    if core.mmvd_flag {
+        evc_get_mmvd_motion(
+            poc_val,
+            sh.slice_type,
+            refp,
+            num_refp,
+            cuw as usize,
+            cuh as usize,
+            &srefi,
+            &smvp,
+            core.mmvd_idx,
+            &mut core.refi,
+            &mut core.mv,
+        );
+    }
+}
+
+fn evcd_get_amvp_motion(
+    core: &mut EvcdCore,
+    cuw: u8,
+    cuh: u8,
+    w_scu: u16,
+    h_scu: u16,
+    scup: u32,
+    avail_lr: u16,
+    map_mv: &Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    map_refi: &Option<Arc<RwLock<Vec<[i8; REFP_NUM]>>>>,
+    refp: &Vec<Vec<EvcRefP>>,
+    map_scu: &[MCU],
+    num_refp: &[u8; REFP_NUM],
+    poc_val: i32,
+    sps_hmvp_flag: bool,
+) {
+    let map_mv = map_mv.as_ref().unwrap().read().unwrap();
+    let map_refi = map_refi.as_ref().unwrap().read().unwrap();
+
+    for inter_dir_idx in 0..2 {
+        /* 0: forward, 1: backward */
+        if (((core.inter_dir as usize + 1) >> inter_dir_idx) & 1) != 0 {
+            /* the reference index of a list without mvd is not signalled */
+            if core.bi_idx == BI_FL0 || core.bi_idx == BI_FL1 {
+                core.refi[inter_dir_idx] = evc_get_first_refi(
+                    scup as usize,
+                    inter_dir_idx,
+                    &*map_refi,
+                    &*map_mv,
+                    cuw as usize,
+                    cuh as usize,
+                    w_scu as usize,
+                    h_scu as usize,
+                    map_scu,
+                    core.mvr_idx,
+                    avail_lr,
+                    &core.history_buffer,
+                    sps_hmvp_flag,
+                );
+            }
+
+            let mut mvp = [0i16; MV_D];
+            evc_get_motion_from_mvr(
+                core.mvr_idx,
+                poc_val,
+                scup as usize,
+                inter_dir_idx,
+                core.refi[inter_dir_idx],
+                num_refp[inter_dir_idx],
+                &*map_mv,
+                &*map_refi,
+                refp,
+                cuw as usize,
+                cuh as usize,
+                w_scu as usize,
+                h_scu as usize,
+                &mut mvp,
+                map_scu,
+                avail_lr,
+                &core.history_buffer,
+                sps_hmvp_flag,
+            );
+            core.mvp_idx[inter_dir_idx] = 0;
+
+            core.mv[inter_dir_idx][MV_X] = mvp[MV_X].wrapping_add(core.mvd[inter_dir_idx][MV_X]);
+            core.mv[inter_dir_idx][MV_Y] = mvp[MV_Y].wrapping_add(core.mvd[inter_dir_idx][MV_Y]);
+        } else {
+            core.refi[inter_dir_idx] = REFI_INVALID;
+            core.mv[inter_dir_idx][MV_X] = 0;
+            core.mv[inter_dir_idx][MV_Y] = 0;
+        }
+    }
+}
+
+fn evcd_get_affine_merge_motion(
+    core: &mut EvcdCore,
+    cuw: u8,
+    cuh: u8,
+    w_scu: u16,
+    h_scu: u16,
+    scup: u32,
+    avail_lr: u16,
+    map_mv: &Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    map_refi: &Option<Arc<RwLock<Vec<[i8; REFP_NUM]>>>>,
+    refp: &Vec<Vec<EvcRefP>>,
+    map_scu: &[MCU],
+    map_affine: &[u32],
+    poc_val: i32,
+    sh: &EvcSh,
+) {
+    let mut mrg_refi = [[REFI_INVALID; REFP_NUM]; AFF_MAX_CAND];
+    let mut mrg_mv = [[[[0i16; MV_D]; VER_NUM]; REFP_NUM]; AFF_MAX_CAND];
+
+    let map_mv = map_mv.as_ref().unwrap().read().unwrap();
+    let map_refi = map_refi.as_ref().unwrap().read().unwrap();
+
+    let cp_num = evc_get_affine_merge_candidate(
+        poc_val,
+        sh.slice_type,
+        scup as usize,
+        &*map_refi,
+        &*map_mv,
+        refp,
+        cuw as usize,
+        cuh as usize,
+        w_scu as usize,
+        h_scu as usize,
+        avail_lr,
+        &mut mrg_refi,
+        &mut mrg_mv,
+        map_scu,
+        map_affine,
+        MAX_CU_LOG2 as u8,
+        sh,
+    );
+
+    let mrg_idx = core.mvp_idx[REFP_0] as usize;
+    core.affine_flag = cp_num[mrg_idx] as u8 - 1;
+
+    for lidx in 0..REFP_NUM {
+        core.refi[lidx] = mrg_refi[mrg_idx][lidx];
+        if REFI_IS_VALID(core.refi[lidx]) {
+            core.affine_mv[lidx] = mrg_mv[mrg_idx][lidx];
+        } else {
+            core.refi[lidx] = REFI_INVALID;
+            core.affine_mv[lidx] = [[0; MV_D]; VER_NUM];
+        }
+    }
+}
+
+fn evcd_get_affine_amvp_motion(
+    core: &mut EvcdCore,
+    cuw: u8,
+    cuh: u8,
+    w_scu: u16,
+    h_scu: u16,
+    scup: u32,
+    map_mv: &Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    map_refi: &Option<Arc<RwLock<Vec<[i8; REFP_NUM]>>>>,
+    map_scu: &[MCU],
+    map_affine: &[u32],
+) {
+    let map_mv = map_mv.as_ref().unwrap().read().unwrap();
+    let map_refi = map_refi.as_ref().unwrap().read().unwrap();
+    let vertex_num = core.affine_flag as usize + 1;
+
+    for inter_dir_idx in 0..2 {
+        /* 0: forward, 1: backward */
+        if (((core.inter_dir as usize + 1) >> inter_dir_idx) & 1) != 0 {
+            let mut mvp = [[[0i16; MV_D]; VER_NUM]; AFF_MAX_NUM_MVP];
+            evc_get_affine_motion_scaling(
+                scup as usize,
+                inter_dir_idx,
+                core.refi[inter_dir_idx],
+                &*map_mv,
+                &*map_refi,
+                cuw as usize,
+                cuh as usize,
+                w_scu as usize,
+                h_scu as usize,
+                vertex_num,
+                &mut mvp,
+                map_scu,
+                map_affine,
+                MAX_CU_LOG2 as u8,
+            );
+
+            /* the mvd of the first vertex is a predictor for the other ones */
+            let mvp = &mvp[core.mvp_idx[inter_dir_idx] as usize];
+            let mvd = &core.affine_mvd[inter_dir_idx];
+            for vertex in 0..vertex_num {
+                for c in 0..MV_D {
+                    let mut mv = mvp[vertex][c].wrapping_add(mvd[vertex][c]);
+                    if vertex != 0 {
+                        mv = mv.wrapping_add(mvd[0][c]);
+                    }
+                    core.affine_mv[inter_dir_idx][vertex][c] = mv;
+                }
+            }
+        } else {
+            core.refi[inter_dir_idx] = REFI_INVALID;
+            core.affine_mv[inter_dir_idx] = [[0; MV_D]; VER_NUM];
+        }
+    }
+}
+
 fn evcd_get_nbr_yuv(
     core: &mut EvcdCore,
     mut x: u16,
@@ -1327,68 +3245,136 @@ fn evcd_get_nbr_yuv(
     scup: u32,
     avail_cu: u16,
     map_scu: &[MCU],
-    pic: &Option<Rc<RefCell<EvcPic>>>,
+    pic: &Option<Arc<RwLock<EvcPic>>>,
     pps_constrained_intra_pred_flag: bool,
+    sps_eipd_flag: bool,
+    chroma_format_idc: u8,
 ) {
     if let Some(pic) = &pic {
-        let frame = &pic.borrow().frame;
-        let planes = &frame.borrow().planes;
+        let frame = &pic.read().unwrap().frame;
+        let planes = &frame.read().unwrap().planes;
 
         /* Y */
-        evc_get_nbr_b(
-            x as usize,
-            y as usize,
-            cuw as usize,
-            cuh as usize,
-            &planes[Y_C].as_region(),
-            avail_cu,
-            &mut core.nb.data[tbl_nb_siz_offset[Y_C]..],
-            scup as usize,
-            map_scu,
-            w_scu as usize,
-            h_scu as usize,
-            Y_C,
-            pps_constrained_intra_pred_flag,
-        );
+        if sps_eipd_flag {
+            evc_get_nbr(
+                x as usize,
+                y as usize,
+                cuw as usize,
+                cuh as usize,
+                &planes[Y_C].as_region(),
+                avail_cu,
+                &mut core.nb_lines.data[Y_C],
+                scup as usize,
+                map_scu,
+                w_scu as usize,
+                h_scu as usize,
+                Y_C,
+                pps_constrained_intra_pred_flag,
+                chroma_format_idc,
+            );
+        } else {
+            evc_get_nbr_b(
+                x as usize,
+                y as usize,
+                cuw as usize,
+                cuh as usize,
+                &planes[Y_C].as_region(),
+                avail_cu,
+                &mut core.nb.data[tbl_nb_siz_offset[Y_C]..],
+                scup as usize,
+                map_scu,
+                w_scu as usize,
+                h_scu as usize,
+                Y_C,
+                pps_constrained_intra_pred_flag,
+                chroma_format_idc,
+            );
+        }
+
+        if chroma_format_idc == 0 {
+            return;
+        }
 
-        cuw >>= 1;
-        cuh >>= 1;
-        x >>= 1;
-        y >>= 1;
+        let w_shift = GET_CHROMA_W_SHIFT(chroma_format_idc);
+        let h_shift = GET_CHROMA_H_SHIFT(chroma_format_idc);
+        cuw >>= w_shift;
+        cuh >>= h_shift;
+        x >>= w_shift;
+        y >>= h_shift;
 
         /* U */
-        evc_get_nbr_b(
-            x as usize,
-            y as usize,
-            cuw as usize,
-            cuh as usize,
-            &planes[U_C].as_region(),
-            avail_cu,
-            &mut core.nb.data[tbl_nb_siz_offset[U_C]..],
-            scup as usize,
-            map_scu,
-            w_scu as usize,
-            h_scu as usize,
-            U_C,
-            pps_constrained_intra_pred_flag,
-        );
+        if sps_eipd_flag {
+            evc_get_nbr(
+                x as usize,
+                y as usize,
+                cuw as usize,
+                cuh as usize,
+                &planes[U_C].as_region(),
+                avail_cu,
+                &mut core.nb_lines.data[U_C],
+                scup as usize,
+                map_scu,
+                w_scu as usize,
+                h_scu as usize,
+                U_C,
+                pps_constrained_intra_pred_flag,
+                chroma_format_idc,
+            );
+        } else {
+            evc_get_nbr_b(
+                x as usize,
+                y as usize,
+                cuw as usize,
+                cuh as usize,
+                &planes[U_C].as_region(),
+                avail_cu,
+                &mut core.nb.data[tbl_nb_siz_offset[U_C]..],
+                scup as usize,
+                map_scu,
+                w_scu as usize,
+                h_scu as usize,
+                U_C,
+                pps_constrained_intra_pred_flag,
+                chroma_format_idc,
+            );
+        }
 
         /* V */
-        evc_get_nbr_b(
-            x as usize,
-            y as usize,
-            cuw as usize,
-            cuh as usize,
-            &planes[V_C].as_region(),
-            avail_cu,
-            &mut core.nb.data[tbl_nb_siz_offset[V_C]..],
-            scup as usize,
-            map_scu,
-            w_scu as usize,
-            h_scu as usize,
-            V_C,
-            pps_constrained_intra_pred_flag,
-        );
+        if sps_eipd_flag {
+            evc_get_nbr(
+                x as usize,
+                y as usize,
+                cuw as usize,
+                cuh as usize,
+                &planes[V_C].as_region(),
+                avail_cu,
+                &mut core.nb_lines.data[V_C],
+                scup as usize,
+                map_scu,
+                w_scu as usize,
+                h_scu as usize,
+                V_C,
+                pps_constrained_intra_pred_flag,
+                chroma_format_idc,
+            );
+        } else {
+            evc_get_nbr_b(
+                x as usize,
+                y as usize,
+                cuw as usize,
+                cuh as usize,
+                &planes[V_C].as_region(),
+                avail_cu,
+                &mut core.nb.data[tbl_nb_siz_offset[V_C]..],
+                scup as usize,
+                map_scu,
+                w_scu as usize,
+                h_scu as usize,
+                V_C,
+                pps_constrained_intra_pred_flag,
+                chroma_format_idc,
+            );
+        }
     }
 }
 
@@ -1401,10 +3387,12 @@ pub(crate) fn evcd_set_dec_info(
     w_scu: usize,
     pps_cu_qp_delta_enabled_flag: bool,
     slice_num: u16,
-    map_refi: &mut Option<Rc<RefCell<Vec<[i8; REFP_NUM]>>>>,
-    map_mv: &mut Option<Rc<RefCell<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    map_refi: &mut Option<Arc<RwLock<Vec<[i8; REFP_NUM]>>>>,
+    map_mv: &mut Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    map_unrefined_mv: &mut Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
     map_scu: &mut [MCU],
     map_ipm: &mut [IntraPredDir],
+    map_affine: &mut [u32],
 ) {
     //CU position X in a frame in SCU unit
     let x_scu = PEL2SCU(x as usize) as usize;
@@ -1421,14 +3409,22 @@ pub(crate) fn evcd_set_dec_info(
         0
     };
 
-    if let (Some(map_refi), Some(map_mv)) = (map_refi, map_mv) {
-        let (mut refis, mut mvs) = (map_refi.borrow_mut(), map_mv.borrow_mut());
+    if let (Some(map_refi), Some(map_mv), Some(map_unrefined_mv)) =
+        (map_refi, map_mv, map_unrefined_mv)
+    {
+        let (mut refis, mut mvs, mut umvs) = (
+            map_refi.write().unwrap(),
+            map_mv.write().unwrap(),
+            map_unrefined_mv.write().unwrap(),
+        );
 
         for i in 0..h_cu {
             let map_scu = &mut map_scu[scup + i * w_scu..];
             let map_ipm = &mut map_ipm[scup + i * w_scu..];
+            let map_affine = &mut map_affine[scup + i * w_scu..];
             let refi = &mut refis[scup + i * w_scu..];
             let mv = &mut mvs[scup + i * w_scu..];
+            let umv = &mut umvs[scup + i * w_scu..];
 
             for j in 0..w_cu {
                 if core.pred_mode == PredMode::MODE_SKIP {
@@ -1441,21 +3437,61 @@ pub(crate) fn evcd_set_dec_info(
                 } else {
                     map_scu[j].CLR_CBFL();
                 }
+                if core.ats_inter_info != 0 {
+                    map_scu[j].SET_ATS_INTER();
+                } else {
+                    map_scu[j].CLR_ATS_INTER();
+                }
 
                 if pps_cu_qp_delta_enabled_flag {
                     map_scu[j].RESET_QP();
                 }
                 map_scu[j].SET_IF_COD_SN_QP(flag, slice_num as u32, core.qp);
+                map_scu[j].SET_AFF(core.affine_flag as u32);
+                map_scu[j].SET_LOGW_LOGH(log2_cuw, log2_cuh);
 
                 map_ipm[j] = core.ipm[0];
+                map_affine[j] = evc_affine_map_info(log2_cuw, log2_cuh, j, i);
 
                 refi[j][REFP_0] = core.refi[REFP_0];
                 refi[j][REFP_1] = core.refi[REFP_1];
-                mv[j][REFP_0][MV_X] = core.mv[REFP_0][MV_X];
-                mv[j][REFP_0][MV_Y] = core.mv[REFP_0][MV_Y];
-                mv[j][REFP_1][MV_X] = core.mv[REFP_1][MV_X];
-                mv[j][REFP_1][MV_Y] = core.mv[REFP_1][MV_Y];
+                /* the refined motion is used for temporal prediction,
+                the signalled one for spatial prediction and deblocking */
+                mv[j] = if core.dmvr_flag {
+                    core.dmvr_mv[i * w_cu + j]
+                } else {
+                    core.mv
+                };
+                umv[j] = core.mv;
+            }
+        }
+
+        if core.affine_flag != 0 {
+            evc_set_affine_mvf(
+                scup,
+                1 << log2_cuw,
+                1 << log2_cuh,
+                w_scu,
+                &core.refi,
+                &core.affine_mv,
+                core.affine_flag as usize + 1,
+                &mut mvs,
+            );
+            for i in 0..h_cu {
+                let pos = scup + i * w_scu;
+                umvs[pos..pos + w_cu].copy_from_slice(&mvs[pos..pos + w_cu]);
             }
         }
+        core.mv = mvs[scup];
+    }
+
+    if core.is_coef[Y_C] && core.ats_inter_info != 0 {
+        evc_set_cu_cbf_flags(
+            core.ats_inter_info,
+            log2_cuw,
+            log2_cuh,
+            &mut map_scu[scup..],
+            w_scu,
+        );
     }
 }
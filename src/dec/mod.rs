@@ -1,3 +1,4 @@
+use super::alf::*;
 use super::api::frame::*;
 use super::api::*;
 use super::def::*;
@@ -11,8 +12,10 @@ use super::tbl::*;
 use super::tracer::*;
 use super::util::*;
 
-use std::cell::RefCell;
-use std::rc::Rc;
+use crate::region::Area;
+use log::*;
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
 
 mod bsr;
 mod eco;
@@ -37,11 +40,13 @@ pub(crate) struct EvcdCore {
     //lft_pel: Vec<Vec<pel>>, //[N_C][MAX_CU_SIZE]
 
     /* coefficient buffer of current CU */
-    coef: Aligned<[i16; MAX_CU_DIM + (MAX_CU_DIM >> 1)]>, //[N_C][MAX_CU_DIM]
+    coef: Aligned<[i16; MAX_CU_DIM * N_C]>, //[N_C][MAX_CU_DIM]
     /* pred buffer of current CU:  [1] is used for bi-pred. */
-    pred: [Aligned<[pel; MAX_CU_DIM + (MAX_CU_DIM >> 1)]>; 2], //[2][N_C][MAX_CU_DIM]
+    pred: [Aligned<[pel; MAX_CU_DIM * N_C]>; 2], //[2][N_C][MAX_CU_DIM]
     /* neighbor pixel buffer for intra prediction: left*2 + top_left + top*2 */
-    nb: Aligned<[pel; (MAX_CU_SIZE << 3) + 3 + 1]>, // [N_C][MAX_CU_SIZE*4+1];
+    nb: Aligned<[pel; ((MAX_CU_SIZE << 2) + 1) * N_C + 1]>, // [N_C][MAX_CU_SIZE*4+1];
+    /* neighbor pixel lines for intra prediction of main profile */
+    nb_lines: Aligned<[EvcNbLines; N_C]>,
 
     /* prediction mode of current CU: INTRA, INTER, ... */
     pred_mode: PredMode,
@@ -57,6 +62,30 @@ pub(crate) struct EvcdCore {
     mv: [[i16; MV_D]; REFP_NUM],
     mvp_idx: [u8; REFP_NUM],
     mvd: [[i16; MV_D]; REFP_NUM],
+    /* bi-prediction index of advanced motion vector prediction */
+    bi_idx: u8,
+    /* motion vector resolution index of current CU */
+    mvr_idx: u8,
+    /* whether a skip or merge CU uses MMVD, and its packed index */
+    mmvd_flag: bool,
+    mmvd_idx: u16,
+    /* adaptive transform selection of an intra CU and its horizontal and vertical
+    transforms packed as (h << 1) | v */
+    ats_intra_cu: bool,
+    ats_mode: u8,
+    /* sub-block transform of an inter CU, packed as idx | (pos << 4) */
+    ats_inter_info: u8,
+    /* affine flag: 0 for translational, 1 for 4-parameter, 2 for 6-parameter */
+    affine_flag: u8,
+    /* control point motion vectors of current affine CU */
+    affine_mv: [[[i16; MV_D]; VER_NUM]; REFP_NUM],
+    affine_mvd: [[[i16; MV_D]; VER_NUM]; REFP_NUM],
+    /* motion of the previously decoded CUs */
+    history_buffer: EvcHistoryBuffer,
+    /* whether the motion of current CU is refined by DMVR */
+    dmvr_flag: bool,
+    /* refined motion vectors of every 4x4 block of current CU */
+    dmvr_mv: Vec<[[i16; MV_D]; REFP_NUM]>,
 
     /* is there coefficient? */
     is_coef: [bool; N_C],
@@ -72,19 +101,20 @@ pub(crate) struct EvcdCore {
     /************** current LCU *************/
     /* split mode map for current LCU */
     split_mode: LcuSplitMode,
+    /* split unit coding order map for current LCU */
+    suco_flag: LcuSucoFlag,
 
     evc_tbl_qp_chroma_dynamic_ext: Vec<Vec<i8>>, // [[i8; MAX_QP_TABLE_SIZE_EXT]; 2],
 }
 
 /******************************************************************************
- * CONTEXT used for decoding process.
+ * TASK used for decoding a picture.
  *
- * All have to be stored are in this structure.
+ * Everything the slice decoding, deblocking and padding of a picture touch,
+ * so that a non-reference picture, or a slice of a picture made of several
+ * tiles, can be handed over to a worker thread.
  *****************************************************************************/
-pub(crate) struct EvcdCtx {
-    /* input packet */
-    pkt: Option<Packet>,
-
+pub(crate) struct EvcdTask {
     /* CORE information used for fast operation */
     core: EvcdCore,
 
@@ -93,12 +123,20 @@ pub(crate) struct EvcdCtx {
     map_scu: Vec<MCU>,
     /* LCU split information */
     map_split: Vec<LcuSplitMode>,
+    /* LCU split unit coding order information */
+    map_suco: Vec<LcuSucoFlag>,
     /* decoded motion vector for every blocks */
-    map_mv: Option<Rc<RefCell<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    map_mv: Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    /* motion vectors before decoder-side refinement */
+    map_unrefined_mv: Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
     /* reference frame indices */
-    map_refi: Option<Rc<RefCell<Vec<[i8; REFP_NUM]>>>>,
+    map_refi: Option<Arc<RwLock<Vec<[i8; REFP_NUM]>>>>,
     /* intra prediction modes */
     map_ipm: Vec<IntraPredDir>,
+    /* affine model size and position of the CU covering every block */
+    map_affine: Vec<u32>,
+    /* components filtered by the adaptive loop filter in each LCU */
+    map_alf: Vec<[bool; N_C]>,
 
     /* *******************************************************************/
     /* current decoding bitstream */
@@ -107,32 +145,50 @@ pub(crate) struct EvcdCtx {
     sbac_dec: EvcdSbac,
     sbac_ctx: EvcSbacCtx,
 
-    /* current nalu header */
-    nalu: EvcNalu,
     /* current slice header */
     sh: EvcSh,
-    /* decoded picture buffer management */
-    dpm: Option<EvcPm>,
-    /* sequence parameter set */
-    sps: EvcSps,
-    /* picture parameter set */
-    pps: EvcPps,
+    /* adaptive loop filter of the picture */
+    alf: EvcAlfFilter,
     /* current decoded (decoding) picture buffer */
-    pic: Option<Rc<RefCell<EvcPic>>>,
+    pic: Option<Arc<RwLock<EvcPic>>>,
+    /* picture the tile is decoded to, when the slice is decoded concurrently
+    with the first one */
+    pic_tile: Option<Arc<RwLock<EvcPic>>>,
+    /* reference picture (0: forward, 1: backward) */
+    refp: Vec<Vec<EvcRefP>>, //[[EvcRefP; REFP_NUM]; MAX_NUM_REF_PICS],
+    /* number of reference pictures */
+    num_refp: [u8; REFP_NUM],
+
+    /* SPS/PPS flags used while decoding CUs */
+    sps_btt_flag: bool,
+    sps_suco_flag: bool,
+    sps_admvp_flag: bool,
+    sps_hmvp_flag: bool,
+    sps_eipd_flag: bool,
+    sps_affine_flag: bool,
+    sps_dmvr_flag: bool,
+    sps_amvr_flag: bool,
+    sps_mmvd_flag: bool,
+    sps_addb_flag: bool,
+    sps_iqt_flag: bool,
+    sps_ats_flag: bool,
+    sps_cm_init_flag: bool,
+    sps_adcc_flag: bool,
+    chroma_format_idc: u8,
+    log2_diff_ctu_size_max_suco_cb_size: u8,
+    log2_diff_max_suco_min_suco_cb_size: u8,
+    sps_dquant_flag: bool,
+    pps_cu_qp_delta_enabled_flag: bool,
+    pps_cu_qp_delta_area: u8,
+    pps_constrained_intra_pred_flag: bool,
+    pps_loop_filter_across_tiles_enabled_flag: bool,
+    /* block sizes allowed by the binary and ternary splits */
+    split_tbl: EvcSplitTbl,
+
     /* decoding picture width */
     w: u16,
     /* decoding picture height */
     h: u16,
-    /* decoding chroma sampling */
-    cs: ChromaSampling,
-
-    /**************************************************************************/
-    /* current slice number, which is increased whenever decoding a slice.
-    when receiving a slice for new picture, this value is set to zero.
-    this value can be used for distinguishing b/w slices */
-    slice_num: u16,
-    /* last coded intra picture's picture order count */
-    last_intra_poc: i32,
     /* picture width in LCU unit */
     w_lcu: u16,
     /* picture height in LCU unit */
@@ -146,21 +202,25 @@ pub(crate) struct EvcdCtx {
     /* picture size in SCU unit (= w_scu * h_scu) */
     f_scu: u32,
     /* the picture order count value */
-    poc: EvcPoc,
-    /* the number of currently decoded pictures */
-    pic_cnt: u32,
-    /* flag whether current picture is referenced picture or not */
-    slice_ref_flag: bool,
-    /* distance between ref pics in addition to closest ref ref pic in LD*/
-    ref_pic_gap_length: u32,
-    /* reference picture (0: forward, 1: backward) */
-    refp: Vec<Vec<EvcRefP>>, //[[EvcRefP; REFP_NUM]; MAX_NUM_REF_PICS],
-    /* flag to indicate opl decoder output */
+    poc_val: i32,
+    /* current slice number */
+    slice_num: u16,
+    /* tile covered by the current slice */
+    tile: EvcTile,
+    /* tiles of the picture */
+    tiles: Vec<EvcTile>,
+    /* tile index of each LCU, if tile boundaries are not deblocked */
+    map_tidx: Option<Vec<u16>>,
+    /* number of LCUs left to decode in the picture */
     num_ctb: u32,
+    /* reference picture the LCUs which cannot be decoded are copied from */
+    pic_conceal: Option<Arc<RwLock<EvcPic>>>,
+    /* whether LCUs have been concealed since the last report */
+    concealed: bool,
 }
 
-impl EvcdCtx {
-    pub(crate) fn new(_cfg: &Config) -> Self {
+impl EvcdTask {
+    fn new() -> Self {
         let mut refp = Vec::with_capacity(MAX_NUM_REF_PICS);
         for _ in 0..MAX_NUM_REF_PICS {
             let mut refp1d = Vec::with_capacity(REFP_NUM);
@@ -170,164 +230,70 @@ impl EvcdCtx {
             refp.push(refp1d);
         }
 
-        EvcdCtx {
-            pkt: None,
-
-            /* CORE information used for fast operation */
+        EvcdTask {
             core: EvcdCore::default(),
 
-            /* MAPS *******************************************************************/
-            /* SCU map for CU information */
             map_scu: vec![],
-            /* LCU split information */
             map_split: vec![],
-            /* decoded motion vector for every blocks */
+            map_suco: vec![],
             map_mv: None,
-            /* reference frame indices */
+            map_unrefined_mv: None,
             map_refi: None,
-            /* intra prediction modes */
             map_ipm: vec![],
+            map_affine: vec![],
+            map_alf: vec![],
 
-            /**************************************************************************/
-            /* current decoding bitstream */
             bs: EvcdBsr::default(),
-            /* SBAC */
             sbac_dec: EvcdSbac::default(),
             sbac_ctx: EvcSbacCtx::default(),
 
-            /* current nalu header */
-            nalu: EvcNalu::default(),
-            /* current slice header */
             sh: EvcSh::default(),
-            /* decoded picture buffer management */
-            dpm: None,
-            /* sequence parameter set */
-            sps: EvcSps::default(),
-            /* picture parameter set */
-            pps: EvcPps::default(),
-            /* current decoded (decoding) picture buffer */
+            alf: EvcAlfFilter::default(),
             pic: None,
-            /* decoding picture width */
+            pic_tile: None,
+            refp,
+            num_refp: [0; REFP_NUM],
+
+            sps_btt_flag: false,
+            sps_suco_flag: false,
+            sps_admvp_flag: false,
+            sps_hmvp_flag: false,
+            sps_eipd_flag: false,
+            sps_affine_flag: false,
+            sps_dmvr_flag: false,
+            sps_amvr_flag: false,
+            sps_mmvd_flag: false,
+            sps_addb_flag: false,
+            sps_iqt_flag: false,
+            sps_ats_flag: false,
+            sps_cm_init_flag: false,
+            sps_adcc_flag: false,
+            chroma_format_idc: 0,
+            log2_diff_ctu_size_max_suco_cb_size: 0,
+            log2_diff_max_suco_min_suco_cb_size: 0,
+            sps_dquant_flag: false,
+            pps_cu_qp_delta_enabled_flag: false,
+            pps_cu_qp_delta_area: 0,
+            pps_constrained_intra_pred_flag: false,
+            pps_loop_filter_across_tiles_enabled_flag: false,
+            split_tbl: EvcSplitTbl::default(),
+
             w: 0,
-            /* decoding picture height */
             h: 0,
-            cs: ChromaSampling::Cs400,
-
-            /**************************************************************************/
-            /* current slice number, which is increased whenever decoding a slice.
-            when receiving a slice for new picture, this value is set to zero.
-            this value can be used for distinguishing b/w slices */
-            slice_num: 0,
-            /* last coded intra picture's picture order count */
-            last_intra_poc: 0,
-            /* picture width in LCU unit */
             w_lcu: 0,
-            /* picture height in LCU unit */
             h_lcu: 0,
-            /* picture size in LCU unit (= w_lcu * h_lcu) */
             f_lcu: 0,
-            /* picture width in SCU unit */
             w_scu: 0,
-            /* picture height in SCU unit */
             h_scu: 0,
-            /* picture size in SCU unit (= w_scu * h_scu) */
             f_scu: 0,
-            /* the picture order count value */
-            poc: EvcPoc::default(),
-            /* the number of currently decoded pictures */
-            pic_cnt: 0,
-            /* flag whether current picture is refecened picture or not */
-            slice_ref_flag: false,
-            /* distance between ref pics in addition to closest ref ref pic in LD*/
-            ref_pic_gap_length: 0,
-            /* reference picture (0: foward, 1: backward) */
-            refp,
-            /* flag to indicate opl decoder output */
+            poc_val: 0,
+            slice_num: 0,
+            tile: EvcTile::default(),
+            tiles: vec![],
+            map_tidx: None,
             num_ctb: 0,
-        }
-    }
-
-    fn sequence_init(&mut self) -> Result<(), EvcError> {
-        if self.sps.pic_width_in_luma_samples != self.w
-            || self.sps.pic_height_in_luma_samples != self.h
-        {
-            /* resolution was changed */
-            self.w = self.sps.pic_width_in_luma_samples;
-            self.h = self.sps.pic_height_in_luma_samples;
-            self.cs = self.sps.chroma_format_idc.into();
-            assert_eq!(self.sps.sps_btt_flag, false);
-        }
-
-        self.w_lcu = (self.w + (MAX_CU_SIZE as u16 - 1)) / MAX_CU_SIZE as u16;
-        self.h_lcu = (self.h + (MAX_CU_SIZE as u16 - 1)) / MAX_CU_SIZE as u16;
-        self.f_lcu = (self.w_lcu * self.h_lcu) as u32;
-        self.w_scu = (self.w + ((1 << MIN_CU_LOG2) - 1) as u16) >> MIN_CU_LOG2 as u16;
-        self.h_scu = (self.h + ((1 << MIN_CU_LOG2) - 1) as u16) >> MIN_CU_LOG2 as u16;
-        self.f_scu = (self.w_scu * self.h_scu) as u32;
-
-        // Top/Left Line Buffer
-        /*self.core.top_mcu = vec![MCU::default(); self.w_scu as usize];
-        self.core.top_pel = vec![
-            vec![0; self.w as usize],
-            vec![0; (self.w >> 1) as usize],
-            vec![0; (self.w >> 1) as usize],
-        ];
-        self.core.lft_mcu = vec![MCU::default(); MAX_CU_SIZE / MIN_CU_SIZE];
-        self.core.lft_pel = vec![
-            vec![0; MAX_CU_SIZE],
-            vec![0; MAX_CU_SIZE >> 1],
-            vec![0; MAX_CU_SIZE >> 1],
-        ];*/
-
-        /* alloc SCU map */
-        self.map_scu = vec![MCU::default(); self.f_scu as usize];
-
-        /* alloc map for CU split flag */
-        self.map_split = vec![LcuSplitMode::default(); self.f_lcu as usize];
-
-        /* alloc map for intra prediction mode */
-        self.map_ipm = vec![IntraPredDir::default(); self.f_scu as usize];
-
-        /* initialize reference picture manager */
-        self.ref_pic_gap_length = (1 << self.sps.log2_ref_pic_gap_length) as u32;
-
-        /* initialize decode picture manager */
-        let mut dpm = EvcPm::new(self.w as usize, self.h as usize, self.cs);
-        dpm.evc_picman_init(MAX_PB_SIZE as u8, MAX_NUM_REF_PICS as u8)?;
-        self.dpm = Some(dpm);
-
-        if self.sps.chroma_qp_table_struct.chroma_qp_table_present_flag {
-            self.core.evc_tbl_qp_chroma_dynamic_ext =
-                evc_derived_chroma_qp_mapping_tables(&self.sps.chroma_qp_table_struct);
-        } else {
-            self.core.evc_tbl_qp_chroma_dynamic_ext = vec![];
-            self.core
-                .evc_tbl_qp_chroma_dynamic_ext
-                .push(evc_tbl_qp_chroma_ajudst_base.to_vec());
-            self.core
-                .evc_tbl_qp_chroma_dynamic_ext
-                .push(evc_tbl_qp_chroma_ajudst_base.to_vec());
-        }
-        Ok(())
-    }
-
-    fn slice_init(&mut self) {
-        self.core.qp = self.sh.qp;
-        self.core.qp_y = self.sh.qp + (6 * (BIT_DEPTH - 8)) as u8;
-        self.core.qp_u = (self.core.evc_tbl_qp_chroma_dynamic_ext[0]
-            [(EVC_TBL_CHROMA_QP_OFFSET + self.sh.qp_u as i8) as usize]
-            + (6 * (BIT_DEPTH - 8)) as i8) as u8;
-        self.core.qp_v = (self.core.evc_tbl_qp_chroma_dynamic_ext[1]
-            [(EVC_TBL_CHROMA_QP_OFFSET + self.sh.qp_v as i8) as usize]
-            + (6 * (BIT_DEPTH - 8)) as i8) as u8;
-
-        /* clear maps */
-        for i in 0..self.f_scu as usize {
-            self.map_scu[i] = MCU::default();
-        }
-
-        if self.sh.slice_type == SliceType::EVC_ST_I {
-            self.last_intra_poc = self.poc.poc_val;
+            pic_conceal: None,
+            concealed: false,
         }
     }
 
@@ -339,7 +305,9 @@ impl EvcdCtx {
         log2_cuh: u8,
         cup: u16,
         cud: u16,
+        parent_suco: bool,
         mut cu_qp_delta_code: u8,
+        mode_cons: ModeCons,
     ) -> Result<(), EvcError> {
         let core = &mut self.core;
         let bs = &mut self.bs;
@@ -348,64 +316,75 @@ impl EvcdCtx {
 
         let cuw = 1u16 << log2_cuw;
         let cuh = 1u16 << log2_cuh;
+        let min_cuwh = 1u16 << self.split_tbl[BLOCK_11][IDX_MIN];
         let mut split_mode = SplitMode::NO_SPLIT;
-        if cuw > MIN_CU_SIZE as u16 || cuh > MIN_CU_SIZE as u16 {
-            if x + cuw <= self.w && y + cuh <= self.h {
-                split_mode = evcd_eco_split_mode(bs, sbac, sbac_ctx, cuw, cuh)?;
-                EVC_TRACE_COUNTER(&mut bs.tracer);
-                EVC_TRACE(&mut bs.tracer, "x pos ");
-                EVC_TRACE(&mut bs.tracer, x);
-                EVC_TRACE(&mut bs.tracer, " y pos ");
-                EVC_TRACE(&mut bs.tracer, y);
-                EVC_TRACE(&mut bs.tracer, " width ");
-                EVC_TRACE(&mut bs.tracer, cuw);
-                EVC_TRACE(&mut bs.tracer, " height ");
-                EVC_TRACE(&mut bs.tracer, cuh);
-                EVC_TRACE(&mut bs.tracer, " depth ");
-                EVC_TRACE(&mut bs.tracer, cud);
-                EVC_TRACE(&mut bs.tracer, " split mode ");
-                EVC_TRACE(
-                    &mut bs.tracer,
-                    if split_mode == SplitMode::NO_SPLIT {
-                        0
-                    } else {
-                        5
-                    },
-                );
-                EVC_TRACE(&mut bs.tracer, " \n");
+        if cuw > min_cuwh || cuh > min_cuwh {
+            if (x + cuw <= self.w && y + cuh <= self.h) || !self.sps_btt_flag {
+                split_mode = evcd_eco_split_mode(
+                    bs,
+                    sbac,
+                    sbac_ctx,
+                    x,
+                    y,
+                    cuw,
+                    cuh,
+                    self.w,
+                    self.h,
+                    self.w_scu,
+                    &self.map_scu,
+                    self.sps_btt_flag,
+                    &self.split_tbl,
+                    mode_cons,
+                    self.sps_cm_init_flag,
+                )?;
             } else {
-                split_mode = evcd_eco_split_mode(bs, sbac, sbac_ctx, cuw, cuh)?;
-                EVC_TRACE_COUNTER(&mut bs.tracer);
-                EVC_TRACE(&mut bs.tracer, "x pos ");
-                EVC_TRACE(&mut bs.tracer, x);
-                EVC_TRACE(&mut bs.tracer, " y pos ");
-                EVC_TRACE(&mut bs.tracer, y);
-                EVC_TRACE(&mut bs.tracer, " width ");
-                EVC_TRACE(&mut bs.tracer, cuw);
-                EVC_TRACE(&mut bs.tracer, " height ");
-                EVC_TRACE(&mut bs.tracer, cuh);
-                EVC_TRACE(&mut bs.tracer, " depth ");
-                EVC_TRACE(&mut bs.tracer, cud);
-                EVC_TRACE(&mut bs.tracer, " split mode ");
-                EVC_TRACE(
-                    &mut bs.tracer,
-                    if split_mode == SplitMode::NO_SPLIT {
-                        0
-                    } else {
-                        5
-                    },
+                split_mode = evc_boundary_split_mode(
+                    x,
+                    y,
+                    log2_cuw,
+                    log2_cuh,
+                    MAX_CU_LOG2 as u8,
+                    self.w,
+                    self.h,
+                    &self.split_tbl,
                 );
-                EVC_TRACE(&mut bs.tracer, " \n");
             }
-        } else {
-            split_mode = SplitMode::NO_SPLIT;
+            EVC_TRACE_COUNTER(&mut bs.tracer);
+            EVC_TRACE(&mut bs.tracer, "x pos ");
+            EVC_TRACE(&mut bs.tracer, x);
+            EVC_TRACE(&mut bs.tracer, " y pos ");
+            EVC_TRACE(&mut bs.tracer, y);
+            EVC_TRACE(&mut bs.tracer, " width ");
+            EVC_TRACE(&mut bs.tracer, cuw);
+            EVC_TRACE(&mut bs.tracer, " height ");
+            EVC_TRACE(&mut bs.tracer, cuh);
+            EVC_TRACE(&mut bs.tracer, " depth ");
+            EVC_TRACE(&mut bs.tracer, cud);
+            EVC_TRACE(&mut bs.tracer, " split mode ");
+            EVC_TRACE(&mut bs.tracer, split_mode as u32);
+            EVC_TRACE(&mut bs.tracer, " \n");
         }
 
-        if self.pps.cu_qp_delta_enabled_flag && self.sps.dquant_flag {
+        if split_mode == SplitMode::NO_SPLIT
+            && (cuw > min_cuwh || cuh > min_cuwh)
+            && (x + cuw > self.w || y + cuh > self.h)
+        {
+            error!("malformed bitstream: CU across the picture boundary\n");
+            return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+        }
+
+        if self.pps_cu_qp_delta_enabled_flag && self.sps_dquant_flag {
+            let log2_cuwh = log2_cuw + log2_cuh;
             if split_mode == SplitMode::NO_SPLIT
-                && (log2_cuh + log2_cuw >= self.pps.cu_qp_delta_area)
+                && log2_cuwh >= self.pps_cu_qp_delta_area
+                && cu_qp_delta_code != 2
             {
-                cu_qp_delta_code = 1;
+                cu_qp_delta_code = if log2_cuw == 7 || log2_cuh == 7 { 2 } else { 1 };
+                core.cu_qp_delta_is_coded = false;
+            } else if (split_mode.is_tt() && log2_cuwh == self.pps_cu_qp_delta_area + 1)
+                || (log2_cuwh == self.pps_cu_qp_delta_area && cu_qp_delta_code != 2)
+            {
+                cu_qp_delta_code = 2;
                 core.cu_qp_delta_is_coded = false;
             }
         }
@@ -420,7 +399,61 @@ impl EvcdCtx {
             MAX_CU_SIZE as u16,
         );
 
+        let boundary = !(x + cuw <= self.w && y + cuh <= self.h);
+        let suco_flag = if self.sps_suco_flag
+            && evc_check_suco_cond(
+                cuw,
+                cuh,
+                split_mode,
+                boundary,
+                MAX_CU_LOG2 as u8,
+                self.log2_diff_ctu_size_max_suco_cb_size,
+                self.log2_diff_max_suco_min_suco_cb_size,
+            ) {
+            evcd_eco_suco_flag(
+                bs,
+                sbac,
+                sbac_ctx,
+                log2_cuw,
+                log2_cuh,
+                self.sps_cm_init_flag,
+            )?
+        } else {
+            parent_suco
+        };
+        evc_set_suco_flag(
+            &mut core.suco_flag,
+            suco_flag,
+            cud,
+            cup,
+            cuw,
+            cuh,
+            MAX_CU_SIZE as u16,
+        );
+
         if split_mode != SplitMode::NO_SPLIT {
+            let mut mode_cons = mode_cons;
+            if self.sps_btt_flag
+                && self.sps_admvp_flag
+                && mode_cons == ModeCons::eAll
+                && !evc_is_chroma_split_allowed(cuw, cuh, split_mode)
+            {
+                /* the parts of a split with too small chroma blocks share
+                one prediction mode */
+                mode_cons = if self.sh.slice_type == SliceType::EVC_ST_I
+                    || evc_get_mode_cons_by_split(split_mode, cuw, cuh) == ModeCons::eOnlyIntra
+                {
+                    ModeCons::eOnlyIntra
+                } else {
+                    evcd_eco_mode_constr(bs, sbac, sbac_ctx)?
+                };
+                if mode_cons == ModeCons::eOnlyIntra {
+                    /* intra parts are coded with separate luma and chroma trees */
+                    error!("unsupported bitstream: local dual tree\n");
+                    return Err(EvcError::EVC_ERR_UNSUPPORTED);
+                }
+            }
+
             let split_struct = evc_split_get_part_structure(
                 split_mode,
                 x,
@@ -432,7 +465,9 @@ impl EvcdCtx {
                 (MAX_CU_LOG2 - MIN_CU_LOG2) as u8,
             );
 
-            for cur_part_num in 0..split_struct.part_count {
+            let suco_order = evc_split_get_suco_order(suco_flag, split_mode);
+            for part_num in 0..split_struct.part_count {
+                let cur_part_num = suco_order[part_num];
                 let log2_sub_cuw = split_struct.log_cuw[cur_part_num];
                 let log2_sub_cuh = split_struct.log_cuh[cur_part_num];
                 let x_pos = split_struct.x_pos[cur_part_num];
@@ -446,11 +481,24 @@ impl EvcdCtx {
                         log2_sub_cuh,
                         split_struct.cup[cur_part_num],
                         split_struct.cud[cur_part_num],
+                        suco_flag,
                         cu_qp_delta_code,
+                        mode_cons,
                     )?;
                 }
             }
         } else {
+            /* 4x4 CUs of the main profile can only be intra predicted */
+            let mode_cons = if self.sh.slice_type == SliceType::EVC_ST_I
+                || (self.sps_admvp_flag
+                    && log2_cuw == MIN_CU_LOG2 as u8
+                    && log2_cuh == MIN_CU_LOG2 as u8)
+            {
+                ModeCons::eOnlyIntra
+            } else {
+                mode_cons
+            };
+
             evcd_eco_unit(
                 bs,
                 sbac,
@@ -464,21 +512,34 @@ impl EvcdCtx {
                 self.h_scu,
                 self.w,
                 self.h,
+                &self.map_unrefined_mv,
                 &self.map_mv,
+                &self.map_refi,
                 &self.refp,
                 &self.map_scu,
                 &self.map_ipm,
-                &self.dpm,
-                self.poc.poc_val,
+                &self.map_affine,
+                &self.num_refp,
+                self.poc_val,
                 &self.pic,
-                self.sps.dquant_flag,
-                self.pps.cu_qp_delta_enabled_flag,
+                self.sps_dquant_flag,
+                self.pps_cu_qp_delta_enabled_flag,
                 cu_qp_delta_code,
-                self.pps.constrained_intra_pred_flag,
-                self.sh.slice_type,
-                self.sh.qp,
-                self.sh.qp_u_offset,
-                self.sh.qp_v_offset,
+                self.pps_constrained_intra_pred_flag,
+                &self.sh,
+                self.sps_admvp_flag,
+                self.sps_hmvp_flag,
+                self.sps_eipd_flag,
+                self.sps_affine_flag,
+                self.sps_dmvr_flag,
+                self.sps_amvr_flag,
+                self.sps_mmvd_flag,
+                self.sps_iqt_flag,
+                self.sps_ats_flag,
+                self.sps_cm_init_flag,
+                self.sps_adcc_flag,
+                self.chroma_format_idc,
+                mode_cons,
             )?;
 
             evcd_set_dec_info(
@@ -488,13 +549,35 @@ impl EvcdCtx {
                 log2_cuw,
                 log2_cuh,
                 self.w_scu as usize,
-                self.pps.cu_qp_delta_enabled_flag,
+                self.pps_cu_qp_delta_enabled_flag,
                 self.slice_num,
                 &mut self.map_refi,
                 &mut self.map_mv,
+                &mut self.map_unrefined_mv,
                 &mut self.map_scu,
                 &mut self.map_ipm,
+                &mut self.map_affine,
             );
+
+            if self.sps_hmvp_flag && core.pred_mode != PredMode::MODE_INTRA {
+                if core.affine_flag != 0 {
+                    /* affine CUs contribute the motion at their centre */
+                    let vertex_num = core.affine_flag as usize + 1;
+                    let (cuw, cuh) = (1 << log2_cuw, 1 << log2_cuh);
+                    let mut mv = [[0; MV_D]; REFP_NUM];
+                    let mut refi = [REFI_INVALID; REFP_NUM];
+                    for lidx in 0..REFP_NUM {
+                        if REFI_IS_VALID(core.refi[lidx]) {
+                            mv[lidx] =
+                                evc_affine_center_mv(&core.affine_mv[lidx], cuw, cuh, vertex_num);
+                            refi[lidx] = core.refi[lidx];
+                        }
+                    }
+                    core.history_buffer.update(&mv, &refi);
+                } else {
+                    core.history_buffer.update(&core.mv, &core.refi);
+                }
+            }
         }
 
         Ok(())
@@ -502,22 +585,59 @@ impl EvcdCtx {
 
     fn decode_slice(&mut self) -> Result<(), EvcError> {
         // Initialize CABAC at each tile
-        self.sbac_dec.reset(&mut self.bs, &mut self.sbac_ctx);
+        self.sbac_dec.reset(
+            &mut self.bs,
+            &mut self.sbac_ctx,
+            self.sh.slice_type,
+            self.sh.qp,
+            self.sps_cm_init_flag,
+        )?;
+
+        /* as in the reference decoder, the LCUs outside of the last slice of
+        the picture are all filtered */
+        for flags in &mut self.map_alf {
+            *flags = [true; N_C];
+        }
 
-        //TODO: move x_lcu/y_lcu=0 to pic init
-        let mut x_lcu = 0; //entry point lcu's x location
-        let mut y_lcu = 0; // entry point lcu's y location
-        while self.num_ctb > 0 {
+        let mut x_lcu = self.tile.x_lcu; //entry point lcu's x location
+        let mut y_lcu = self.tile.y_lcu; // entry point lcu's y location
+        let mut num_ctb = self.tile.f_lcu;
+        while num_ctb > 0 {
             let lcu_num = x_lcu + y_lcu * self.w_lcu; // Init the first lcu_num in tile
 
             //LCU decoding with in a tile
-            evc_assert_rv((lcu_num as u32) < self.f_lcu, EvcError::EVC_ERR_UNEXPECTED)?;
+            if lcu_num as u32 >= self.f_lcu {
+                return Err(EvcError::EVC_ERR_UNEXPECTED);
+            }
+
+            /* the history of motion restarts at each LCU row of a tile */
+            if x_lcu == self.tile.x_lcu {
+                self.core.history_buffer.reset();
+            }
 
             // invoke coding_tree() recursion
             for i in 0..NUM_CU_DEPTH {
                 for j in 0..BlockShape::NUM_BLOCK_SHAPE as usize {
                     for k in 0..MAX_CU_CNT_IN_LCU {
                         self.core.split_mode.data[i][j][k] = SplitMode::NO_SPLIT;
+                        self.core.suco_flag.data[i][j][k] = false;
+                    }
+                }
+            }
+
+            if self.sh.alf_on {
+                let map_flags = [
+                    self.sh.alf_map_flag,
+                    self.sh.alf_chroma_map_flag,
+                    self.sh.alf_chroma2_map_flag,
+                ];
+                for c in 0..N_C {
+                    if map_flags[c] {
+                        self.map_alf[lcu_num as usize][c] = evcd_eco_alf_ctb_flag(
+                            &mut self.bs,
+                            &mut self.sbac_dec,
+                            &mut self.sbac_ctx,
+                        )?;
                     }
                 }
             }
@@ -529,19 +649,23 @@ impl EvcdCtx {
                 MAX_CU_LOG2 as u8,
                 0,
                 0,
+                false,
                 0,
+                ModeCons::eAll,
             )?;
             // set split flags to map
             self.map_split[lcu_num as usize].clone_from(&self.core.split_mode);
+            self.map_suco[lcu_num as usize].clone_from(&self.core.suco_flag);
 
             self.num_ctb -= 1;
-            // read end_of_picture_flag
-            if self.num_ctb == 0 {
+            num_ctb -= 1;
+            // read end_of_tile_one_bit
+            if num_ctb == 0 {
                 evcd_eco_tile_end_flag(&mut self.bs, &mut self.sbac_dec)?;
             } else {
                 x_lcu += 1;
-                if x_lcu >= self.w_lcu {
-                    x_lcu = 0;
+                if x_lcu >= self.tile.x_lcu + self.tile.w_lcu {
+                    x_lcu = self.tile.x_lcu;
                     y_lcu += 1;
                 }
             }
@@ -551,180 +675,1176 @@ impl EvcdCtx {
         Ok(())
     }
 
-    fn make_stat(&mut self, btype: NaluType) -> EvcStat {
-        let mut stat = EvcStat {
-            nalu_type: btype,
-            stype: SliceType::EVC_ST_I,
-            fnum: -1,
-            bytes: NALU_SIZE_FIELD_IN_BYTES + self.bs.get_read_byte() as usize,
-            ..Default::default()
-        };
+    /* split map of a concealed LCU: only the CUs crossing the picture
+    boundary are split */
+    fn conceal_tree(
+        &self,
+        split_mode_buf: &mut LcuSplitMode,
+        x: u16,
+        y: u16,
+        log2_cuw: u8,
+        log2_cuh: u8,
+        cup: u16,
+        cud: u16,
+    ) {
+        let cuw = 1u16 << log2_cuw;
+        let cuh = 1u16 << log2_cuh;
+        if (cuw > MIN_CU_SIZE as u16 || cuh > MIN_CU_SIZE as u16)
+            && (x + cuw > self.w || y + cuh > self.h)
+        {
+            let split_mode = if self.sps_btt_flag {
+                evc_boundary_split_mode(
+                    x,
+                    y,
+                    log2_cuw,
+                    log2_cuh,
+                    MAX_CU_LOG2 as u8,
+                    self.w,
+                    self.h,
+                    &self.split_tbl,
+                )
+            } else {
+                SplitMode::SPLIT_QUAD
+            };
+            if split_mode == SplitMode::NO_SPLIT {
+                return;
+            }
 
-        if btype < NaluType::EVC_SPS_NUT {
-            stat.fnum = self.pic_cnt as isize;
-            stat.stype = self.sh.slice_type;
+            evc_set_split_mode(
+                split_mode_buf,
+                split_mode,
+                cud,
+                cup,
+                cuw,
+                cuh,
+                MAX_CU_SIZE as u16,
+            );
 
-            /* increase decoded picture count */
-            self.pic_cnt += 1;
-            stat.poc = self.poc.poc_val as isize;
-            stat.tid = self.nalu.nuh_temporal_id as isize;
+            let split_struct = evc_split_get_part_structure(
+                split_mode,
+                x,
+                y,
+                cuw,
+                cuh,
+                cup,
+                cud,
+                (MAX_CU_LOG2 - MIN_CU_LOG2) as u8,
+            );
+            for part_num in 0..split_struct.part_count {
+                let x_pos = split_struct.x_pos[part_num];
+                let y_pos = split_struct.y_pos[part_num];
+                if x_pos < self.w && y_pos < self.h {
+                    self.conceal_tree(
+                        split_mode_buf,
+                        x_pos,
+                        y_pos,
+                        split_struct.log_cuw[part_num],
+                        split_struct.log_cuh[part_num],
+                        split_struct.cup[part_num],
+                        split_struct.cud[part_num],
+                    );
+                }
+            }
+        }
+    }
 
-            for i in 0..2 {
-                stat.refpic_num[i] = self.dpm.as_ref().unwrap().num_refp[i];
-                for j in 0..stat.refpic_num[i] as usize {
-                    stat.refpic[i][j] = self.refp[j][i].poc as isize;
+    /* conceal an LCU by copying the co-located samples of the closest
+    reference picture (mid-gray without any), it is then handled as an intra
+    CU without motion */
+    fn conceal_lcu(&mut self, x_lcu: u16, y_lcu: u16) {
+        let lcu_num = x_lcu as usize + y_lcu as usize * self.w_lcu as usize;
+        let x = x_lcu << MAX_CU_LOG2;
+        let y = y_lcu << MAX_CU_LOG2;
+        let w = std::cmp::min(MAX_CU_SIZE as u16, self.w - x) as usize;
+        let h = std::cmp::min(MAX_CU_SIZE as u16, self.h - y) as usize;
+
+        if let Some(pic) = &self.pic {
+            let p = pic.read().unwrap();
+            let mut frame = p.frame.write().unwrap();
+            let ref_pic = self.pic_conceal.as_ref().map(|r| r.read().unwrap());
+            let ref_frame = ref_pic.as_ref().map(|r| r.frame.read().unwrap());
+            let num_comp = if frame.chroma_sampling == ChromaSampling::Cs400 {
+                1
+            } else {
+                N_C
+            };
+            for c in 0..num_comp {
+                let plane = &mut frame.planes[c];
+                let (xdec, ydec) = (plane.cfg.xdec, plane.cfg.ydec);
+                let (x0, y0) = (x as usize >> xdec, y as usize >> ydec);
+                let (w0, h0) = (w >> xdec, h >> ydec);
+                let dst = &mut plane.as_region_mut();
+                if let Some(ref_frame) = &ref_frame {
+                    let src = &ref_frame.planes[c].as_region();
+                    for j in y0..y0 + h0 {
+                        dst[j][x0..x0 + w0].copy_from_slice(&src[j][x0..x0 + w0]);
+                    }
+                } else {
+                    for j in y0..y0 + h0 {
+                        for v in &mut dst[j][x0..x0 + w0] {
+                            *v = 1 << (BIT_DEPTH - 1);
+                        }
+                    }
                 }
             }
         }
 
-        stat
+        let mut split_mode = LcuSplitMode::default();
+        self.conceal_tree(
+            &mut split_mode,
+            x,
+            y,
+            MAX_CU_LOG2 as u8,
+            MAX_CU_LOG2 as u8,
+            0,
+            0,
+        );
+        self.map_split[lcu_num] = split_mode;
+        self.map_suco[lcu_num] = LcuSucoFlag::default();
+
+        let x_scu = PEL2SCU(x as usize) as usize;
+        let y_scu = PEL2SCU(y as usize) as usize;
+        let w_scu = (w + MIN_CU_SIZE - 1) >> MIN_CU_LOG2;
+        let h_scu = (h + MIN_CU_SIZE - 1) >> MIN_CU_LOG2;
+        if let (Some(map_refi), Some(map_mv), Some(map_unrefined_mv)) =
+            (&self.map_refi, &self.map_mv, &self.map_unrefined_mv)
+        {
+            let (mut refis, mut mvs, mut umvs) = (
+                map_refi.write().unwrap(),
+                map_mv.write().unwrap(),
+                map_unrefined_mv.write().unwrap(),
+            );
+            for j in y_scu..y_scu + h_scu {
+                for i in x_scu..x_scu + w_scu {
+                    let scup = i + j * self.w_scu as usize;
+                    self.map_scu[scup] = MCU::default();
+                    self.map_scu[scup].SET_IF_COD_SN_QP(1, self.slice_num as u32, self.sh.qp);
+                    self.map_ipm[scup] = IntraPredDir::IPD_DC_B;
+                    self.map_affine[scup] = 0;
+                    refis[scup] = [REFI_INVALID; REFP_NUM];
+                    mvs[scup] = [[0; MV_D]; REFP_NUM];
+                    umvs[scup] = [[0; MV_D]; REFP_NUM];
+                }
+            }
+        }
     }
 
-    pub(crate) fn push_pkt(&mut self, pkt: &mut Option<Packet>) -> Result<(), EvcError> {
-        self.pkt = pkt.take();
-        Ok(())
+    /* conceal the LCUs of a tile which follow the first decoded ones */
+    fn conceal_tile(&mut self, tile: &EvcTile, decoded: u32) {
+        for n in decoded..tile.f_lcu {
+            let x_lcu = tile.x_lcu + (n % tile.w_lcu as u32) as u16;
+            let y_lcu = tile.y_lcu + (n / tile.w_lcu as u32) as u16;
+            self.conceal_lcu(x_lcu, y_lcu);
+            self.num_ctb -= 1;
+        }
+        self.concealed = true;
     }
 
-    pub(crate) fn decode_nalu(&mut self) -> Result<EvcStat, EvcError> {
-        if self.pkt.is_none() {
-            return Err(EvcError::EVC_OK_FLUSH);
+    /* decode slice data, then deblock and pad the picture once it is complete */
+    fn decode(&mut self) -> Result<(), EvcError> {
+        self.decode_tile();
+
+        if self.num_ctb > 0 {
+            /* the other tiles of the picture are still to come */
+            return Ok(());
         }
 
-        let pkt = self.pkt.take().ok_or(EvcError::EVC_ERR_EMPTY_PACKET)?;
+        self.finish();
 
-        /* bitstream reader initialization */
-        self.bs = EvcdBsr::new(pkt);
+        Ok(())
+    }
+
+    /* decode the slice data of the tile, the LCUs which cannot be decoded are
+    concealed */
+    fn decode_tile(&mut self) {
+        let num_ctb = self.num_ctb;
+        let tile = self.tile;
+
+        /* decode slice layer */
+        if self.sh.slice_type != SliceType::EVC_ST_I && self.num_refp[REFP_0] == 0 {
+            /* the reference pictures of the slice are missing */
+            self.conceal_tile(&tile, 0);
+        } else if self.tile.f_lcu < self.f_lcu {
+            /* the other tiles of the picture are not available to the slice,
+            their SCUs are restored for the deblocking filter afterwards */
+            let mut map_scu = self.map_scu.clone();
+            evc_set_scu_outside_tile(&mut self.map_scu, &self.tile, self.w_scu, self.h_scu);
+            if self.decode_slice().is_err() {
+                self.conceal_tile(&tile, num_ctb - self.num_ctb);
+            }
+
+            let (x_scu, y_scu, w, h) = evc_tile_scu_rect(&self.tile, self.w_scu, self.h_scu);
+            for j in y_scu..y_scu + h {
+                let idx = j * self.w_scu as usize + x_scu;
+                map_scu[idx..idx + w].copy_from_slice(&self.map_scu[idx..idx + w]);
+            }
+            self.map_scu = map_scu;
+        } else if self.decode_slice().is_err() {
+            self.conceal_tile(&tile, num_ctb - self.num_ctb);
+        }
+    }
+
+    /* merge the tile decoded by a slice task into the picture */
+    fn slice_merge(&mut self, task: &EvcdTask) {
+        let tile = task.tile;
+        let (x_scu, y_scu, w_scu, h_scu) = evc_tile_scu_rect(&tile, self.w_scu, self.h_scu);
+
+        for j in y_scu..y_scu + h_scu {
+            let idx = j * self.w_scu as usize + x_scu;
+            self.map_scu[idx..idx + w_scu].copy_from_slice(&task.map_scu[idx..idx + w_scu]);
+        }
+        for j in tile.y_lcu..tile.y_lcu + tile.h_lcu {
+            for i in tile.x_lcu..tile.x_lcu + tile.w_lcu {
+                let lcu_num = (j * self.w_lcu + i) as usize;
+                self.map_split[lcu_num].clone_from(&task.map_split[lcu_num]);
+                self.map_suco[lcu_num].clone_from(&task.map_suco[lcu_num]);
+            }
+        }
+        /* every slice sets the ALF flags of the LCUs outside of it, so the
+        flags of the last slice are kept */
+        self.map_alf.copy_from_slice(&task.map_alf);
+
+        /* every slice refines motion into a map of its own */
+        if let (Some(dst), Some(src)) = (&self.map_unrefined_mv, &task.map_unrefined_mv) {
+            let (mut dst, src) = (dst.write().unwrap(), src.read().unwrap());
+            for j in y_scu..y_scu + h_scu {
+                let idx = j * self.w_scu as usize + x_scu;
+                dst[idx..idx + w_scu].copy_from_slice(&src[idx..idx + w_scu]);
+            }
+        }
+
+        let (dst, src) = match (&self.pic, &task.pic) {
+            (Some(dst), Some(src)) if !Arc::ptr_eq(dst, src) => (dst, src),
+            _ => return,
+        };
+        let (dst, src) = (dst.read().unwrap(), src.read().unwrap());
+
+        {
+            let (mut dst_refi, mut dst_mv) =
+                (dst.map_refi.write().unwrap(), dst.map_mv.write().unwrap());
+            let (src_refi, src_mv) = (src.map_refi.read().unwrap(), src.map_mv.read().unwrap());
+            for j in y_scu..y_scu + h_scu {
+                let idx = j * self.w_scu as usize + x_scu;
+                dst_refi[idx..idx + w_scu].copy_from_slice(&src_refi[idx..idx + w_scu]);
+                dst_mv[idx..idx + w_scu].copy_from_slice(&src_mv[idx..idx + w_scu]);
+            }
+        }
+
+        let (dst_planes, src_planes) = (
+            &mut dst.frame.write().unwrap().planes,
+            &src.frame.read().unwrap().planes,
+        );
+        let x = (tile.x_lcu as usize) << MAX_CU_LOG2;
+        let y = (tile.y_lcu as usize) << MAX_CU_LOG2;
+        let w = std::cmp::min((tile.w_lcu as usize) << MAX_CU_LOG2, self.w as usize - x);
+        let h = std::cmp::min((tile.h_lcu as usize) << MAX_CU_LOG2, self.h as usize - y);
+        let num_comp = if self.chroma_format_idc == 0 { 1 } else { N_C };
+        for c in 0..num_comp {
+            let (xdec, ydec) = (dst_planes[c].cfg.xdec, dst_planes[c].cfg.ydec);
+            let area = Area::Rect {
+                x: (x >> xdec) as isize,
+                y: (y >> ydec) as isize,
+                width: w >> xdec,
+                height: h >> ydec,
+            };
+            let src_region = src_planes[c].region(area);
+            let mut dst_region = dst_planes[c].region_mut(area);
+            for j in 0..h >> ydec {
+                dst_region[j].copy_from_slice(&src_region[j]);
+            }
+        }
+    }
+
+    /* deblock, filter and pad the complete picture */
+    fn finish(&mut self) {
+        /* deblocking filter */
+        if self.sh.deblocking_filter_on {
+            evc_deblock(
+                self.sh.qp_u_offset,
+                self.sh.qp_v_offset,
+                self.sps_addb_flag,
+                self.sh.sh_deblock_alpha_offset,
+                self.sh.sh_deblock_beta_offset,
+                self.w_lcu,
+                self.h_lcu,
+                self.w_scu,
+                self.h_scu,
+                self.w,
+                self.h,
+                &mut self.bs.tracer,
+                &self.pic,
+                &mut self.map_scu,
+                &self.map_split,
+                &self.map_suco,
+                &self.map_unrefined_mv,
+                &self.map_refi,
+                &self.refp,
+                self.map_tidx.as_deref(),
+                &self.core.evc_tbl_qp_chroma_dynamic_ext,
+            );
+        }
+
+        /* adaptive loop filter */
+        if self.alf.is_on() {
+            if let Some(pic) = &self.pic {
+                let frame = &pic.read().unwrap().frame;
+                evc_alf(
+                    &self.alf,
+                    &mut frame.write().unwrap(),
+                    &self.tiles,
+                    self.pps_loop_filter_across_tiles_enabled_flag,
+                    &self.map_alf,
+                    self.w_lcu,
+                );
+            }
+        }
+
+        /* expand pixels to padding area */
+        if let Some(pic) = &self.pic {
+            let frame = &pic.read().unwrap().frame;
+            frame.write().unwrap().pad();
+        }
+    }
+}
+
+/* non-reference picture being decoded on a worker thread */
+struct EvcdJob {
+    /* picture written by the worker */
+    pic: Arc<RwLock<EvcPic>>,
+    /* reference pictures read by the worker */
+    refs: Vec<Arc<RwLock<EvcPic>>>,
+    handle: JoinHandle<(EvcdTask, Result<(), EvcError>)>,
+}
+
+/* slice of the current picture being decoded on a worker thread */
+struct EvcdSliceJob {
+    /* tile decoded by the worker */
+    tile: EvcTile,
+    handle: JoinHandle<EvcdTask>,
+}
+
+/* report the concealment of a picture on its frame */
+fn evcd_set_concealed(pic: &Option<Arc<RwLock<EvcPic>>>) {
+    if let Some(pic) = pic {
+        pic.read().unwrap().frame.write().unwrap().concealed = true;
+    }
+}
+
+/******************************************************************************
+ * CONTEXT used for decoding process.
+ *
+ * All have to be stored are in this structure.
+ *****************************************************************************/
+pub(crate) struct EvcdCtx {
+    /* input packet */
+    pkt: Option<Packet>,
+
+    /* task of the picture currently being parsed */
+    task: EvcdTask,
+    /* idle tasks, ready to be reused for the next pictures */
+    tasks: Vec<EvcdTask>,
+    /* pictures being decoded on worker threads, in decoding order */
+    jobs: Vec<EvcdJob>,
+    /* slices of the current picture being decoded on worker threads, in
+    decoding order */
+    slice_jobs: Vec<EvcdSliceJob>,
+    /* maximum number of threads decoding pictures, including the caller */
+    threads: usize,
+
+    /* *******************************************************************/
+    /* current decoding bitstream */
+    bs: EvcdBsr,
+
+    /* current nalu header */
+    nalu: EvcNalu,
+    /* decoded picture buffer management */
+    dpm: Option<EvcPm>,
+    /* sequence parameter set */
+    sps: EvcSps,
+    /* picture parameter set */
+    pps: EvcPps,
+    /* tiles of a picture */
+    tiles: Vec<EvcTile>,
+    /* tile index of each LCU, if tile boundaries are not deblocked */
+    map_tidx: Option<Vec<u16>>,
+    /* tiles of the current picture which have been decoded */
+    tile_decoded: Vec<bool>,
+    /* ALF parameters of the adaptation parameter sets */
+    aps: Vec<EvcAlfParam>,
+    /* LCUs have been concealed since the last decoded NALU */
+    concealed: bool,
+    /* the last picture started, whose hash a picture signature SEI carries */
+    pic_last: Option<Arc<RwLock<EvcPic>>>,
+    /* decoding picture width */
+    w: u16,
+    /* decoding picture height */
+    h: u16,
+    /* decoding chroma sampling */
+    cs: ChromaSampling,
+
+    /**************************************************************************/
+    /* current slice number, which is increased whenever decoding a slice.
+    when receiving a slice for new picture, this value is set to zero.
+    this value can be used for distinguishing b/w slices */
+    slice_num: u16,
+    /* last coded intra picture's picture order count */
+    last_intra_poc: i32,
+    /* picture width in LCU unit */
+    w_lcu: u16,
+    /* picture height in LCU unit */
+    h_lcu: u16,
+    /* picture size in LCU unit (= w_lcu * h_lcu) */
+    f_lcu: u32,
+    /* picture width in SCU unit */
+    w_scu: u16,
+    /* picture height in SCU unit */
+    h_scu: u16,
+    /* picture size in SCU unit (= w_scu * h_scu) */
+    f_scu: u32,
+    /* the picture order count value */
+    poc: EvcPoc,
+    /* the number of currently decoded pictures */
+    pic_cnt: u32,
+    /* flag whether current picture is referenced picture or not */
+    slice_ref_flag: bool,
+    /* distance between ref pics in addition to closest ref ref pic in LD*/
+    ref_pic_gap_length: u32,
+    /* chroma QP mapping tables of the sequence */
+    evc_tbl_qp_chroma_dynamic_ext: Vec<Vec<i8>>,
+}
+
+impl EvcdCtx {
+    pub(crate) fn new(cfg: &Config) -> Self {
+        EvcdCtx {
+            pkt: None,
+
+            task: EvcdTask::new(),
+            tasks: vec![],
+            jobs: vec![],
+            slice_jobs: vec![],
+            /* trace output has to follow decoding order */
+            threads: if cfg!(feature = "trace") {
+                1
+            } else {
+                std::cmp::max(cfg.threads, 1)
+            },
+
+            /**************************************************************************/
+            /* current decoding bitstream */
+            bs: EvcdBsr::default(),
+
+            /* current nalu header */
+            nalu: EvcNalu::default(),
+            /* decoded picture buffer management */
+            dpm: None,
+            /* sequence parameter set */
+            sps: EvcSps::default(),
+            /* picture parameter set */
+            pps: EvcPps::default(),
+            tiles: vec![],
+            map_tidx: None,
+            tile_decoded: vec![],
+            aps: vec![EvcAlfParam::default(); APS_MAX_NUM],
+            concealed: false,
+            pic_last: None,
+            /* decoding picture width */
+            w: 0,
+            /* decoding picture height */
+            h: 0,
+            cs: ChromaSampling::Cs400,
+
+            /**************************************************************************/
+            /* current slice number, which is increased whenever decoding a slice.
+            when receiving a slice for new picture, this value is set to zero.
+            this value can be used for distinguishing b/w slices */
+            slice_num: 0,
+            /* last coded intra picture's picture order count */
+            last_intra_poc: 0,
+            /* picture width in LCU unit */
+            w_lcu: 0,
+            /* picture height in LCU unit */
+            h_lcu: 0,
+            /* picture size in LCU unit (= w_lcu * h_lcu) */
+            f_lcu: 0,
+            /* picture width in SCU unit */
+            w_scu: 0,
+            /* picture height in SCU unit */
+            h_scu: 0,
+            /* picture size in SCU unit (= w_scu * h_scu) */
+            f_scu: 0,
+            /* the picture order count value */
+            poc: EvcPoc::default(),
+            /* the number of currently decoded pictures */
+            pic_cnt: 0,
+            /* flag whether current picture is refecened picture or not */
+            slice_ref_flag: false,
+            /* distance between ref pics in addition to closest ref ref pic in LD*/
+            ref_pic_gap_length: 0,
+            evc_tbl_qp_chroma_dynamic_ext: vec![],
+        }
+    }
+
+    fn sequence_init(&mut self) -> Result<(), EvcError> {
+        /* pictures of the previous sequence have to be completed first */
+        self.wait_jobs();
+
+        if self.sps.pic_width_in_luma_samples != self.w
+            || self.sps.pic_height_in_luma_samples != self.h
+        {
+            /* resolution was changed */
+            self.w = self.sps.pic_width_in_luma_samples;
+            self.h = self.sps.pic_height_in_luma_samples;
+            self.cs = self.sps.chroma_format_idc.into();
+        }
+
+        self.w_lcu = (self.w + (MAX_CU_SIZE as u16 - 1)) / MAX_CU_SIZE as u16;
+        self.h_lcu = (self.h + (MAX_CU_SIZE as u16 - 1)) / MAX_CU_SIZE as u16;
+        self.f_lcu = (self.w_lcu * self.h_lcu) as u32;
+        self.w_scu = (self.w + ((1 << MIN_CU_LOG2) - 1) as u16) >> MIN_CU_LOG2 as u16;
+        self.h_scu = (self.h + ((1 << MIN_CU_LOG2) - 1) as u16) >> MIN_CU_LOG2 as u16;
+        self.f_scu = (self.w_scu * self.h_scu) as u32;
+
+        /* initialize reference picture manager */
+        self.ref_pic_gap_length = (1 << self.sps.log2_ref_pic_gap_length) as u32;
+
+        /* initialize decode picture manager */
+        let mut dpm = EvcPm::new(self.w as usize, self.h as usize, self.cs);
+        dpm.evc_picman_init(MAX_PB_SIZE as u8, MAX_NUM_REF_PICS as u8)?;
+        self.dpm = Some(dpm);
+
+        if self.sps.chroma_qp_table_struct.chroma_qp_table_present_flag {
+            self.evc_tbl_qp_chroma_dynamic_ext =
+                evc_derived_chroma_qp_mapping_tables(&self.sps.chroma_qp_table_struct);
+        } else {
+            self.evc_tbl_qp_chroma_dynamic_ext =
+                evc_default_chroma_qp_mapping_tables(self.sps.chroma_format_idc, self.sps.tool_iqt);
+        }
+
+        /* tasks are sized for the sequence */
+        self.tasks.clear();
+        let mut task = EvcdTask::new();
+        self.task_init(&mut task);
+        self.task = task;
+
+        Ok(())
+    }
+
+    fn task_init(&self, task: &mut EvcdTask) {
+        task.w = self.w;
+        task.h = self.h;
+        task.w_lcu = self.w_lcu;
+        task.h_lcu = self.h_lcu;
+        task.f_lcu = self.f_lcu;
+        task.w_scu = self.w_scu;
+        task.h_scu = self.h_scu;
+        task.f_scu = self.f_scu;
+        task.sps_btt_flag = self.sps.sps_btt_flag;
+        task.sps_suco_flag = self.sps.sps_suco_flag;
+        task.sps_admvp_flag = self.sps.tool_admvp;
+        task.sps_hmvp_flag = self.sps.tool_hmvp;
+        task.sps_eipd_flag = self.sps.tool_eipd;
+        task.sps_affine_flag = self.sps.tool_affine;
+        task.sps_dmvr_flag = self.sps.tool_dmvr;
+        task.sps_amvr_flag = self.sps.tool_amvr;
+        task.sps_mmvd_flag = self.sps.tool_mmvd;
+        task.sps_addb_flag = self.sps.tool_addb;
+        task.sps_iqt_flag = self.sps.tool_iqt;
+        task.sps_ats_flag = self.sps.tool_ats;
+        task.sps_cm_init_flag = self.sps.tool_cm_init;
+        task.sps_adcc_flag = self.sps.tool_adcc;
+        task.chroma_format_idc = self.sps.chroma_format_idc;
+        task.log2_diff_ctu_size_max_suco_cb_size = self.sps.log2_diff_ctu_size_max_suco_cb_size;
+        task.log2_diff_max_suco_min_suco_cb_size = self.sps.log2_diff_max_suco_min_suco_cb_size;
+        task.split_tbl = evc_set_split_tbl(&self.sps);
+
+        /* alloc SCU map */
+        task.map_scu = vec![MCU::default(); self.f_scu as usize];
+
+        /* alloc map for CU split flag */
+        task.map_split = vec![LcuSplitMode::default(); self.f_lcu as usize];
+        task.map_suco = vec![LcuSucoFlag::default(); self.f_lcu as usize];
+
+        /* alloc map for intra prediction mode */
+        task.map_ipm = vec![IntraPredDir::default(); self.f_scu as usize];
+
+        /* alloc map for affine model information */
+        task.map_affine = vec![0; self.f_scu as usize];
+
+        /* alloc map for the motion vectors before DMVR */
+        task.map_unrefined_mv = Some(Arc::new(RwLock::new(vec![
+            [[0; MV_D]; REFP_NUM];
+            self.f_scu as usize
+        ])));
+
+        /* alloc map for ALF flags */
+        task.map_alf = vec![[true; N_C]; self.f_lcu as usize];
+
+        task.core.evc_tbl_qp_chroma_dynamic_ext = self.evc_tbl_qp_chroma_dynamic_ext.clone();
+        task.core.dmvr_mv = vec![[[0; MV_D]; REFP_NUM]; MAX_CU_CNT_IN_LCU];
+    }
+
+    fn slice_init(&mut self, first_slice: bool) {
+        let (core, sh) = (&mut self.task.core, &self.task.sh);
+        core.qp = sh.qp;
+        core.qp_y = sh.qp + (6 * (BIT_DEPTH - 8)) as u8;
+        core.qp_u = (core.evc_tbl_qp_chroma_dynamic_ext[0]
+            [(EVC_TBL_CHROMA_QP_OFFSET + sh.qp_u as i8) as usize]
+            + (6 * (BIT_DEPTH - 8)) as i8) as u8;
+        core.qp_v = (core.evc_tbl_qp_chroma_dynamic_ext[1]
+            [(EVC_TBL_CHROMA_QP_OFFSET + sh.qp_v as i8) as usize]
+            + (6 * (BIT_DEPTH - 8)) as i8) as u8;
+
+        /* clear maps */
+        if first_slice {
+            for i in 0..self.f_scu as usize {
+                self.task.map_scu[i] = MCU::default();
+            }
+        }
+
+        if self.task.sh.slice_type == SliceType::EVC_ST_I {
+            self.last_intra_poc = self.poc.poc_val;
+        }
+    }
+
+    fn make_stat(&mut self, btype: NaluType, bytes: usize) -> EvcStat {
+        let mut stat = EvcStat {
+            nalu_type: btype,
+            stype: SliceType::EVC_ST_I,
+            fnum: -1,
+            bytes: NALU_SIZE_FIELD_IN_BYTES + bytes,
+            ..Default::default()
+        };
+
+        if btype < NaluType::EVC_SPS_NUT {
+            stat.fnum = self.pic_cnt as isize;
+            stat.stype = self.task.sh.slice_type;
+
+            /* increase decoded picture count with the last slice of the picture */
+            if self.task.num_ctb == self.task.tile.f_lcu {
+                self.pic_cnt += 1;
+            }
+            stat.poc = self.poc.poc_val as isize;
+            stat.tid = self.nalu.nuh_temporal_id as isize;
+
+            for i in 0..2 {
+                stat.refpic_num[i] = self.dpm.as_ref().unwrap().num_refp[i];
+                for j in 0..stat.refpic_num[i] as usize {
+                    stat.refpic[i][j] = self.task.refp[j][i].poc as isize;
+                }
+            }
+        }
+
+        stat
+    }
+
+    /* wait for the worker at position idx and recycle its task. the NALU being
+    decoded is unrelated to the picture, so a failure of the worker is reported
+    on the frame of the picture only */
+    fn wait_job(&mut self, idx: usize) {
+        let job = self.jobs.remove(idx);
+        let concealed = match job.handle.join() {
+            Ok((mut task, ret)) => {
+                let concealed = std::mem::take(&mut task.concealed) || ret.is_err();
+                self.tasks.push(task);
+                concealed
+            }
+            /* the task is lost with the worker */
+            Err(_) => true,
+        };
+        if concealed {
+            evcd_set_concealed(&Some(job.pic));
+        }
+    }
+
+    fn wait_jobs(&mut self) {
+        while !self.jobs.is_empty() {
+            self.wait_job(0);
+        }
+    }
+
+    /* a picture which is no longer used for reference can be recycled by the
+    DPB, so the workers still reading it have to be completed first */
+    fn wait_jobs_on_unused_refs(&mut self) {
+        let mut i = 0;
+        while i < self.jobs.len() {
+            if self.jobs[i]
+                .refs
+                .iter()
+                .any(|pic| !pic.read().unwrap().is_ref)
+            {
+                self.wait_job(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /* move the current non-reference picture to a worker thread */
+    fn spawn_job(&mut self) {
+        if self.jobs.len() + 1 >= self.threads {
+            self.wait_job(0);
+        }
+
+        let mut task = self.tasks.pop().unwrap_or_else(|| {
+            let mut task = EvcdTask::new();
+            self.task_init(&mut task);
+            task
+        });
+        std::mem::swap(&mut task, &mut self.task);
+
+        let pic = Arc::clone(task.pic.as_ref().unwrap());
+        let mut refs = vec![];
+        for lidx in 0..REFP_NUM {
+            for i in 0..task.num_refp[lidx] as usize {
+                if let Some(ref_pic) = &task.refp[i][lidx].pic {
+                    refs.push(Arc::clone(ref_pic));
+                }
+            }
+        }
+        if let Some(ref_pic) = &task.pic_conceal {
+            refs.push(Arc::clone(ref_pic));
+        }
+
+        let handle = thread::spawn(move || {
+            let ret = task.decode();
+            (task, ret)
+        });
+        self.jobs.push(EvcdJob { pic, refs, handle });
+    }
+
+    /* hand over everything the current slice needs to the task decoding it */
+    fn slice_job_init(&mut self, task: &mut EvcdTask) {
+        let pic_task = &mut self.task;
+
+        task.sh.clone_from(&pic_task.sh);
+        task.tile = pic_task.tile;
+        task.num_ctb = pic_task.tile.f_lcu;
+        task.slice_num = pic_task.slice_num;
+        task.poc_val = pic_task.poc_val;
+        task.bs = std::mem::take(&mut pic_task.bs);
+        task.core.qp = pic_task.core.qp;
+        task.core.qp_y = pic_task.core.qp_y;
+        task.core.qp_u = pic_task.core.qp_u;
+        task.core.qp_v = pic_task.core.qp_v;
+
+        for i in 0..MAX_NUM_REF_PICS {
+            for j in 0..REFP_NUM {
+                task.refp[i][j].clone_from(&pic_task.refp[i][j]);
+            }
+        }
+        task.num_refp = pic_task.num_refp;
+        task.pic_conceal.clone_from(&pic_task.pic_conceal);
+
+        task.sps_dquant_flag = pic_task.sps_dquant_flag;
+        task.pps_cu_qp_delta_enabled_flag = pic_task.pps_cu_qp_delta_enabled_flag;
+        task.pps_cu_qp_delta_area = pic_task.pps_cu_qp_delta_area;
+        task.pps_constrained_intra_pred_flag = pic_task.pps_constrained_intra_pred_flag;
+        task.pps_loop_filter_across_tiles_enabled_flag =
+            pic_task.pps_loop_filter_across_tiles_enabled_flag;
+        task.alf.clone_from(&pic_task.alf);
+        task.tiles.clone_from(&pic_task.tiles);
+        task.map_tidx.clone_from(&pic_task.map_tidx);
+        task.map_scu.copy_from_slice(&pic_task.map_scu);
+
+        /* the first slice is decoded into the picture directly, the others
+        into a private picture merged after decoding */
+        if pic_task.slice_num == 0 {
+            task.pic.clone_from(&pic_task.pic);
+            task.map_refi.clone_from(&pic_task.map_refi);
+            task.map_mv.clone_from(&pic_task.map_mv);
+        } else {
+            if task.pic_tile.is_none() {
+                task.pic_tile = Some(Arc::new(RwLock::new(EvcPic::new(
+                    self.w as usize,
+                    self.h as usize,
+                    self.cs,
+                ))));
+            }
+            task.pic.clone_from(&task.pic_tile);
+            if let Some(pic) = &task.pic_tile {
+                let p = pic.read().unwrap();
+                task.map_refi = Some(Arc::clone(&p.map_refi));
+                task.map_mv = Some(Arc::clone(&p.map_mv));
+            }
+        }
+    }
+
+    /* move the current slice to a worker thread, the tiles of a picture being
+    independent of each other */
+    fn spawn_slice_job(&mut self) {
+        if self.jobs.len() + self.slice_jobs.len() + 1 >= self.threads {
+            if self.slice_jobs.is_empty() {
+                self.wait_job(0);
+            } else {
+                self.wait_slice_job();
+            }
+        }
+
+        let mut task = self.tasks.pop().unwrap_or_else(|| {
+            let mut task = EvcdTask::new();
+            self.task_init(&mut task);
+            task
+        });
+        self.slice_job_init(&mut task);
+
+        /* the LCUs of the tile are decoded or concealed by the worker */
+        let tile = task.tile;
+        self.task.num_ctb -= tile.f_lcu;
+
+        let handle = thread::spawn(move || {
+            task.decode_tile();
+            task
+        });
+        self.slice_jobs.push(EvcdSliceJob { tile, handle });
+    }
+
+    /* wait for the oldest slice worker and merge its tile into the picture */
+    fn wait_slice_job(&mut self) {
+        let job = self.slice_jobs.remove(0);
+        let concealed = match job.handle.join() {
+            Ok(mut task) => {
+                self.task.slice_merge(&task);
+                let concealed = std::mem::take(&mut task.concealed);
+                self.tasks.push(task);
+                concealed
+            }
+            Err(_) => {
+                /* the task is lost with the worker, so the tile is concealed
+                in the picture, its LCUs being accounted for again */
+                self.task.num_ctb += job.tile.f_lcu;
+                self.task.conceal_tile(&job.tile, 0);
+                true
+            }
+        };
+        if concealed {
+            self.concealed = true;
+            evcd_set_concealed(&self.task.pic);
+        }
+    }
+
+    fn wait_slice_jobs(&mut self) {
+        while !self.slice_jobs.is_empty() {
+            self.wait_slice_job();
+        }
+    }
+
+    /* put the decoded picture to the DPB */
+    fn put_pic(&mut self) {
+        self.dpm.as_mut().unwrap().evc_picman_put_pic(
+            &self.task.pic,
+            self.nalu.nal_unit_type == NaluType::EVC_IDR_NUT,
+            self.poc.poc_val as u32,
+            self.nalu.nuh_temporal_id,
+            true,
+            &mut self.task.refp,
+            self.slice_ref_flag,
+            self.sps.tool_rpl,
+            self.ref_pic_gap_length,
+        );
+    }
+
+    /* complete the current picture when its last slices are missing: the
+    tiles which have not been decoded are concealed */
+    fn conceal_pic(&mut self) {
+        self.wait_slice_jobs();
+        for (i, tile) in self.tiles.iter().enumerate() {
+            if !self.tile_decoded[i] {
+                self.task.conceal_tile(tile, 0);
+            }
+        }
+        self.task.finish();
+        self.concealed = true;
+        evcd_set_concealed(&self.task.pic);
+        self.pic_cnt += 1;
+
+        /* put concealed picture to DPB */
+        self.put_pic();
+    }
+
+    /* compare the hash of the last decoded picture with a picture signature */
+    fn check_signature(
+        &mut self,
+        hash: PictureHash,
+        signature: &[[u8; 16]; N_C],
+    ) -> Result<bool, EvcError> {
+        let pic = match &self.pic_last {
+            Some(pic) => Arc::clone(pic),
+            None => return Ok(false),
+        };
+
+        /* the picture may still be under decoding on a worker thread */
+        if let Some(idx) = self.jobs.iter().position(|job| Arc::ptr_eq(&job.pic, &pic)) {
+            self.wait_job(idx);
+        }
+
+        let mut p = pic.write().unwrap();
+        let digest = evc_picbuf_signature(&p.frame.read().unwrap(), hash);
+        p.digest = digest;
+
+        Ok(digest == *signature)
+    }
+
+    pub(crate) fn push_pkt(&mut self, pkt: &mut Option<Packet>) -> Result<(), EvcError> {
+        self.pkt = pkt.take();
+        Ok(())
+    }
+
+    pub(crate) fn decode_nalu(&mut self) -> Result<EvcStat, EvcError> {
+        if self.pkt.is_none() {
+            if self.task.num_ctb > 0 {
+                /* the last slices of the stream are missing */
+                let mut stat = EvcStat {
+                    nalu_type: self.nalu.nal_unit_type,
+                    stype: self.task.sh.slice_type,
+                    fnum: self.pic_cnt as isize,
+                    poc: self.poc.poc_val as isize,
+                    tid: self.nalu.nuh_temporal_id as isize,
+                    ..Default::default()
+                };
+                self.conceal_pic();
+                stat.concealed = std::mem::take(&mut self.concealed);
+                return Ok(stat);
+            }
+            return Err(EvcError::EVC_OK_FLUSH);
+        }
+
+        let pkt = self.pkt.take().ok_or(EvcError::EVC_ERR_EMPTY_PACKET)?;
+        let bytes = pkt.data.len();
+
+        /* bitstream reader initialization */
+        self.bs = EvcdBsr::new(pkt);
 
         /* parse nalu header */
-        evcd_eco_nalu(&mut self.bs, &mut self.nalu)?;
+        let mut nalu = EvcNalu::default();
+        evcd_eco_nalu(&mut self.bs, &mut nalu)?;
+
+        let nalu_type = nalu.nal_unit_type;
+        if (nalu_type == NaluType::EVC_SPS_NUT
+            || nalu_type == NaluType::EVC_PPS_NUT
+            || nalu_type == NaluType::EVC_APS_NUT)
+            && self.task.num_ctb > 0
+        {
+            /* parameter sets never come in between the slices of a picture, so
+            the last slices of the current picture are missing */
+            self.conceal_pic();
+        }
 
-        let nalu_type = self.nalu.nal_unit_type;
-        if nalu_type == NaluType::EVC_SPS_NUT {
+        let mut stat = if nalu_type == NaluType::EVC_SPS_NUT {
             evcd_eco_sps(&mut self.bs, &mut self.sps)?;
 
             self.sequence_init()?;
+
+            self.make_stat(nalu_type, bytes)
         } else if nalu_type == NaluType::EVC_PPS_NUT {
             evcd_eco_pps(&mut self.bs, &mut self.pps)?;
+            self.tiles = evc_tile_init(&self.pps, self.w_lcu, self.h_lcu);
+            self.map_tidx = evc_tile_map_tidx(&self.pps, &self.tiles, self.w_lcu, self.h_lcu);
+            self.tile_decoded = vec![false; self.tiles.len()];
+
+            self.make_stat(nalu_type, bytes)
+        } else if nalu_type == NaluType::EVC_APS_NUT {
+            let (aps_id, alf) = evcd_eco_aps(&mut self.bs)?;
+            self.aps[aps_id] = alf;
+
+            self.make_stat(nalu_type, bytes)
         } else if nalu_type < NaluType::EVC_SPS_NUT {
-            /* decode slice header */
-            self.sh.num_ctb = self.f_lcu as u16;
+            /* decode slice header. a slice with a corrupted header is dropped,
+            its tile is concealed when the picture is completed */
+            let mut sh = self.task.sh.clone();
+            sh.num_ctb = self.f_lcu as u16;
 
-            evcd_eco_sh(&mut self.bs, &self.pps, &mut self.sh, nalu_type)?;
+            evcd_eco_sh(&mut self.bs, &self.sps, &self.pps, &mut sh, nalu_type)?;
 
-            if self.num_ctb == 0 {
-                self.num_ctb = self.f_lcu;
+            let tile_idx = sh.first_tile_id as usize;
+            if tile_idx >= self.tiles.len() {
+                return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+            }
+
+            if self.task.num_ctb > 0
+                && (self.tile_decoded[tile_idx]
+                    || nalu_type != self.nalu.nal_unit_type
+                    || nalu.nuh_temporal_id != self.nalu.nuh_temporal_id)
+            {
+                /* the slice belongs to the next picture, so the last slices of
+                the current picture are missing */
+                self.conceal_pic();
             }
 
+            /* the first slice of a picture finds no LCU left to decode */
+            let first_slice = self.task.num_ctb == 0;
+
+            self.nalu = nalu;
+            self.task.sh = sh;
+            self.task.tile = self.tiles[tile_idx];
+
             /* POC derivation process */
-            assert_eq!(self.sps.tool_pocs, false);
-            if !self.sps.tool_pocs {
-                //sps_pocs_flag == 0
+            if first_slice {
+                /* the sub-GOP is not signalled with both the RPLs and the POC
+                LSBs, the pictures are then kept by the RPLs only */
+                self.slice_ref_flag = (self.sps.tool_rpl && self.sps.tool_pocs)
+                    || self.nalu.nuh_temporal_id == 0
+                    || self.nalu.nuh_temporal_id < self.sps.log2_sub_gop_length;
                 if nalu_type == NaluType::EVC_IDR_NUT {
-                    self.sh.poc_lsb = 0;
+                    self.task.sh.poc_lsb = 0;
                     self.poc.prev_doc_offset = -1;
                     self.poc.prev_poc_val = 0;
-                    self.slice_ref_flag = (self.nalu.nuh_temporal_id == 0
-                        || self.nalu.nuh_temporal_id < self.sps.log2_sub_gop_length);
                     self.poc.poc_val = 0;
+                } else if self.sps.tool_pocs {
+                    evc_poc_lsb_derivation(
+                        &self.sps,
+                        self.nalu.nuh_temporal_id,
+                        self.task.sh.poc_lsb,
+                        &mut self.poc,
+                    );
                 } else {
-                    self.slice_ref_flag = (self.nalu.nuh_temporal_id == 0
-                        || self.nalu.nuh_temporal_id < self.sps.log2_sub_gop_length);
                     evc_poc_derivation(&self.sps, self.nalu.nuh_temporal_id, &mut self.poc);
-                    self.sh.poc_lsb = self.poc.poc_val;
+                    self.task.sh.poc_lsb = self.poc.poc_val;
                 }
             }
 
-            self.slice_init();
+            self.slice_init(first_slice);
 
-            if self.num_ctb == 0 {
-                self.num_ctb = self.f_lcu;
+            if first_slice {
+                self.task.num_ctb = self.f_lcu;
                 self.slice_num = 0;
+                self.tile_decoded.fill(false);
             } else {
                 self.slice_num += 1;
             }
+            self.tile_decoded[tile_idx] = true;
+
+            /* initialize reference pictures, the slice is concealed if they are
+            missing */
+            let dpm = self.dpm.as_mut().unwrap();
+            let refp_init = if self.sps.tool_rpl {
+                if first_slice && nalu_type != NaluType::EVC_IDR_NUT {
+                    dpm.evc_picman_refpic_marking(&self.task.sh, self.poc.poc_val as u32);
+                }
+                dpm.evc_picman_refp_rpl_based_init(
+                    &self.task.sh,
+                    self.poc.poc_val as u32,
+                    &mut self.task.refp,
+                )
+            } else {
+                dpm.evc_picman_refp_init(
+                    self.sps.max_num_ref_pics,
+                    self.task.sh.slice_type,
+                    self.poc.poc_val as u32,
+                    self.nalu.nuh_temporal_id,
+                    self.last_intra_poc,
+                    &mut self.task.refp,
+                )
+            };
+            let num_refp = match refp_init {
+                Ok(_) => self.dpm.as_ref().unwrap().num_refp,
+                Err(_) => [0; REFP_NUM],
+            };
+
+            if first_slice {
+                self.wait_jobs_on_unused_refs();
 
-            /* initialize reference pictures */
-            self.dpm.as_mut().unwrap().evc_picman_refp_init(
-                self.sps.max_num_ref_pics,
-                self.sh.slice_type,
-                self.poc.poc_val as u32,
-                self.nalu.nuh_temporal_id,
-                self.last_intra_poc,
-                &mut self.refp,
-            );
-
-            if self.num_ctb == self.f_lcu {
                 /* get available frame buffer for decoded image */
-                self.pic = self.dpm.as_mut().unwrap().evc_picman_get_empty_pic()?;
+                self.task.pic = self.dpm.as_mut().unwrap().evc_picman_get_empty_pic()?;
+                self.pic_last.clone_from(&self.task.pic);
 
                 /* get available frame buffer for decoded image */
-                if let Some(pic) = &self.pic {
-                    let p = pic.borrow();
-                    self.map_refi = Some(Rc::clone(&p.map_refi));
-                    self.map_mv = Some(Rc::clone(&p.map_mv));
+                if let Some(pic) = &self.task.pic {
+                    let p = pic.read().unwrap();
+                    self.task.map_refi = Some(Arc::clone(&p.map_refi));
+                    self.task.map_mv = Some(Arc::clone(&p.map_mv));
+                    p.frame.write().unwrap().concealed = false;
                 }
-            }
-
-            /* decode slice layer */
-            self.decode_slice()?;
 
-            /* deblocking filter */
-            if self.sh.deblocking_filter_on {
-                evc_deblock(
-                    self.sh.qp_u_offset,
-                    self.sh.qp_v_offset,
-                    self.w_lcu,
-                    self.h_lcu,
-                    self.w_scu,
-                    self.h_scu,
-                    self.w,
-                    self.h,
-                    &mut self.bs.tracer,
-                    &self.pic,
-                    &mut self.map_scu,
-                    &self.map_split,
-                    &self.map_mv,
-                    &self.map_refi,
-                    &self.core.evc_tbl_qp_chroma_dynamic_ext,
-                );
+                self.task.pic_conceal = self
+                    .dpm
+                    .as_ref()
+                    .unwrap()
+                    .evc_picman_closest_ref_pic(self.poc.poc_val as u32);
             }
 
-            if self.num_ctb == 0 {
-                /* expand pixels to padding area */
-                if let Some(pic) = &self.pic {
-                    let frame = &pic.borrow().frame;
-                    frame.borrow_mut().pad();
+            let task = &mut self.task;
+            task.num_refp = num_refp;
+            task.sps_dquant_flag = self.sps.dquant_flag;
+            task.pps_cu_qp_delta_enabled_flag = self.pps.cu_qp_delta_enabled_flag;
+            task.pps_cu_qp_delta_area = self.pps.cu_qp_delta_area;
+            task.pps_constrained_intra_pred_flag = self.pps.constrained_intra_pred_flag;
+            task.pps_loop_filter_across_tiles_enabled_flag =
+                self.pps.loop_filter_across_tiles_enabled_flag;
+            task.alf = EvcAlfFilter::new(&task.sh, &self.aps, self.sps.chroma_format_idc);
+            task.tiles.clone_from(&self.tiles);
+            task.map_tidx.clone_from(&self.map_tidx);
+            task.poc_val = self.poc.poc_val;
+            task.slice_num = self.slice_num;
+            task.bs = std::mem::take(&mut self.bs);
+
+            let stat = self.make_stat(nalu_type, bytes);
+
+            if self.threads > 1 && !self.pps.single_tile_in_pic_flag {
+                /* the slices are decoded while the following ones are parsed,
+                the picture is completed with its last slice */
+                self.spawn_slice_job();
+
+                if self.task.num_ctb == 0 {
+                    self.wait_slice_jobs();
+                    self.task.finish();
+
+                    /* put decoded picture to DPB */
+                    self.put_pic();
                 }
+            } else if self.threads > 1 && !self.slice_ref_flag {
+                /* nothing refers to a non-reference picture, so it can be
+                decoded while the following pictures are parsed */
+                self.put_pic();
 
-                /* put decoded picture to DPB */
-                self.dpm.as_mut().unwrap().evc_picman_put_pic(
-                    &self.pic,
-                    self.nalu.nal_unit_type == NaluType::EVC_IDR_NUT,
-                    self.poc.poc_val as u32,
-                    self.nalu.nuh_temporal_id,
-                    true,
-                    &mut self.refp,
-                    self.slice_ref_flag,
-                    self.ref_pic_gap_length,
-                );
+                self.spawn_job();
+            } else {
+                self.task.decode()?;
+                if std::mem::take(&mut self.task.concealed) {
+                    self.concealed = true;
+                    evcd_set_concealed(&self.task.pic);
+                }
+
+                if self.task.num_ctb == 0 {
+                    /* put decoded picture to DPB */
+                    self.put_pic();
+                }
             }
+
+            stat
         } else if nalu_type == NaluType::EVC_SEI_NUT {
+            let mut stat = self.make_stat(nalu_type, bytes);
+
+            if let Some((hash, signature)) = evcd_eco_sei(&mut self.bs, self.sps.chroma_format_idc)?
+            {
+                if self.task.num_ctb > 0 {
+                    /* the picture signature follows the last slice of the
+                    picture, so the last slices are missing */
+                    self.conceal_pic();
+                }
+                stat.hash_match = Some(self.check_signature(hash, &signature)?);
+            }
+
+            stat
         } else {
             return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
-        }
+        };
 
-        let mut stat = self.make_stat(nalu_type);
-        if self.num_ctb > 0 {
+        if self.task.num_ctb > 0 {
             stat.fnum = -1;
         }
+        stat.concealed = std::mem::take(&mut self.concealed);
 
         Ok(stat)
     }
 
-    pub(crate) fn pull_frm(&mut self) -> Result<Rc<RefCell<Frame<pel>>>, EvcError> {
-        let pic = self.dpm.as_mut().unwrap().evc_picman_out_pic()?;
+    pub(crate) fn pull_frm(&mut self, flush: bool) -> Result<Arc<RwLock<Frame<pel>>>, EvcError> {
+        let pic = self.dpm.as_mut().unwrap().evc_picman_out_pic(flush)?;
         if let Some(p) = &pic {
-            Ok(Rc::clone(&p.borrow().frame))
+            /* the picture may still be under decoding on a worker thread */
+            if let Some(idx) = self.jobs.iter().position(|job| Arc::ptr_eq(&job.pic, p)) {
+                self.wait_job(idx);
+            }
+            Ok(Arc::clone(&p.read().unwrap().frame))
         } else {
             Err(EvcError::EVC_OK_OUTPUT_NOT_AVAILABLE)
         }
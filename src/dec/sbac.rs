@@ -2,6 +2,7 @@ use super::bsr::*;
 use crate::api::*;
 use crate::def::*;
 use crate::tracer::*;
+use crate::util::*;
 
 /*****************************************************************************
  * SBAC structure
@@ -17,6 +18,9 @@ impl EvcdSbac {
         &mut self,
         bs: &mut EvcdBsr,
         sbac_ctx: &mut EvcSbacCtx,
+        slice_type: SliceType,
+        slice_qp: u8,
+        sps_cm_init_flag: bool,
     ) -> Result<(), EvcError> {
         /* Initialization of the internal variables */
         self.range = 16384;
@@ -27,6 +31,21 @@ impl EvcdSbac {
         }
 
         /* Initialization of the context models */
+        for i in 0..NUM_CTX_BTT_SPLIT_FLAG {
+            sbac_ctx.btt_split_flag[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_BTT_SPLIT_DIR {
+            sbac_ctx.btt_split_dir[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_BTT_SPLIT_TYPE {
+            sbac_ctx.btt_split_type[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_SUCO_FLAG {
+            sbac_ctx.suco_flag[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_MODE_CONS {
+            sbac_ctx.mode_cons[i] = PROB_INIT;
+        }
         for i in 0..NUM_CTX_SPLIT_CU_FLAG {
             sbac_ctx.split_cu_flag[i] = PROB_INIT;
         }
@@ -63,9 +82,27 @@ impl EvcdSbac {
         for i in 0..NUM_CTX_INTRA_PRED_MODE {
             sbac_ctx.intra_dir[i] = PROB_INIT;
         }
+        for i in 0..NUM_CTX_INTRA_PRED_MODE_C {
+            sbac_ctx.intra_dir_c[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_INTRA_LUMA_PRED_MPM_FLAG {
+            sbac_ctx.intra_luma_pred_mpm_flag[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_INTRA_LUMA_PRED_MPM_IDX {
+            sbac_ctx.intra_luma_pred_mpm_idx[i] = PROB_INIT;
+        }
         for i in 0..NUM_CTX_MVP_IDX {
             sbac_ctx.mvp_idx[i] = PROB_INIT;
         }
+        for i in 0..NUM_CTX_MERGE_MODE_FLAG {
+            sbac_ctx.merge_mode_flag[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_MERGE_IDX {
+            sbac_ctx.merge_idx[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_BI_IDX {
+            sbac_ctx.bi_idx[i] = PROB_INIT;
+        }
         for i in 0..NUM_CTX_MVD {
             sbac_ctx.mvd[i] = PROB_INIT;
         }
@@ -75,10 +112,77 @@ impl EvcdSbac {
         for i in 0..NUM_CTX_DELTA_QP {
             sbac_ctx.delta_qp[i] = PROB_INIT;
         }
+        for i in 0..NUM_CTX_ALF_CTB_FLAG {
+            sbac_ctx.alf_ctb_flag[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_AFFINE_FLAG {
+            sbac_ctx.affine_flag[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_AFFINE_MODE {
+            sbac_ctx.affine_mode[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_AFFINE_MRG {
+            sbac_ctx.affine_mrg[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_AFFINE_MVP_IDX {
+            sbac_ctx.affine_mvp_idx[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_AFFINE_MVD_FLAG {
+            sbac_ctx.affine_mvd_flag[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_MVR_IDX {
+            sbac_ctx.mvr_idx[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_MMVD_FLAG {
+            sbac_ctx.mmvd_flag[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_MMVD_GROUP_IDX {
+            sbac_ctx.mmvd_group_idx[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_MMVD_MERGE_IDX {
+            sbac_ctx.mmvd_merge_idx[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_MMVD_DIST_IDX {
+            sbac_ctx.mmvd_dist_idx[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_MMVD_DIRECTION_IDX {
+            sbac_ctx.mmvd_direction_idx[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_ATS_MODE_FLAG {
+            sbac_ctx.ats_mode[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_ATS_INTER_FLAG {
+            sbac_ctx.ats_inter_flag[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_ATS_INTER_QUAD_FLAG {
+            sbac_ctx.ats_inter_quad_flag[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_ATS_INTER_HOR_FLAG {
+            sbac_ctx.ats_inter_hor_flag[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_ATS_INTER_POS_FLAG {
+            sbac_ctx.ats_inter_pos_flag[i] = PROB_INIT;
+        }
+        for i in 0..NUM_CTX_LAST_SIG_COEFF {
+            sbac_ctx.last_sig_coeff_x_prefix[i] = PROB_INIT;
+            sbac_ctx.last_sig_coeff_y_prefix[i] = PROB_INIT;
+        }
+        for set in 0..NUM_SIG_COEFF_SET {
+            for i in 0..NUM_CTX_SIG_COEFF_FLAG {
+                sbac_ctx.sig_coeff_flag[set][i] = PROB_INIT;
+            }
+        }
+        for i in 0..NUM_CTX_GTX {
+            sbac_ctx.coeff_abs_level_greaterAB_flag[i] = PROB_INIT;
+        }
         for i in 0..NUM_CTX_SKIP_FLAG {
             sbac_ctx.skip_flag[i] = PROB_INIT;
         }
 
+        if sps_cm_init_flag {
+            evc_eco_sbac_ctx_init(sbac_ctx, slice_type, slice_qp);
+        }
+
         Ok(())
     }
 
@@ -146,8 +250,9 @@ impl EvcdSbac {
         self.range -= 1;
         if self.value >= self.range {
             while !bs.is_byte_aligned() {
-                let t0 = bs.read1(None)?;
-                evc_assert_rv(t0 == 0, EvcError::EVC_ERR_MALFORMED_BITSTREAM)?;
+                if bs.read1(None)? != 0 {
+                    return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+                }
             }
             Ok(1)
         } else {
@@ -160,6 +265,45 @@ impl EvcdSbac {
         }
     }
 
+    pub(crate) fn decode_bins_ep(
+        &mut self,
+        bs: &mut EvcdBsr,
+        num_bin: usize,
+    ) -> Result<u32, EvcError> {
+        let mut value = 0;
+        for _ in 0..num_bin {
+            value = (value << 1) | self.decode_bin_ep(bs)?;
+        }
+
+        Ok(value)
+    }
+
+    pub(crate) fn read_unary_sym_ep(
+        &mut self,
+        bs: &mut EvcdBsr,
+        max_val: u32,
+    ) -> Result<u32, EvcError> {
+        let mut symbol = self.decode_bin_ep(bs)?;
+
+        if symbol == 0 {
+            return Ok(symbol);
+        }
+
+        symbol = 0;
+        let mut t32u = 1;
+        let mut counter = 1;
+        while t32u != 0 && counter < max_val {
+            t32u = self.decode_bin_ep(bs)?;
+            symbol += 1;
+            counter += 1;
+        }
+        if t32u != 0 {
+            symbol += 1;
+        }
+
+        Ok(symbol)
+    }
+
     pub(crate) fn read_unary_sym(
         &mut self,
         bs: &mut EvcdBsr,
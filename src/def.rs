@@ -16,6 +16,10 @@ pub(crate) fn evc_assert_rv(x: bool, r: EvcError) -> Result<(), EvcError> {
 /********* Conditional tools definition ********/
 pub type pel = u16;
 
+/* profiles */
+pub(crate) const PROFILE_BASELINE: u8 = 0;
+pub(crate) const PROFILE_MAIN: u8 = 1;
+
 /* number of picture order count lsb bit */
 pub(crate) const POC_LSB_BIT: usize = (11);
 
@@ -77,9 +81,94 @@ pub(crate) fn PEL2SCU(p: usize) -> usize {
 
 pub(crate) const PIC_PAD_SIZE_L: usize = (MAX_CU_SIZE + 16);
 pub(crate) const PIC_PAD_SIZE_C: usize = (PIC_PAD_SIZE_L >> 1);
+/* padding bounding the collocated motion vectors, that of a 128x128 largest
+coding unit whatever the coding unit size of the profile */
+pub(crate) const MV_CLIP_PAD_SIZE: usize = (1 << 7) + 16;
+
+/* shifts from the luma to the chroma width and height of a chroma format */
+#[inline]
+pub(crate) fn GET_CHROMA_W_SHIFT(chroma_format_idc: u8) -> usize {
+    ChromaSampling::from(chroma_format_idc).get_decimation().0
+}
+#[inline]
+pub(crate) fn GET_CHROMA_H_SHIFT(chroma_format_idc: u8) -> usize {
+    ChromaSampling::from(chroma_format_idc).get_decimation().1
+}
 
 /* number of MVP candidates */
-pub(crate) const MAX_NUM_MVP: usize = 4;
+pub(crate) const MAX_NUM_MVP: usize = 6;
+/* number of merge candidates for CUs of 32 samples or less */
+pub(crate) const MAX_NUM_MVP_SMALL_CU: usize = 4;
+
+/* history-based motion vector prediction */
+pub(crate) const ALLOWED_CHECKED_NUM: usize = 23;
+pub(crate) const ALLOWED_CHECKED_NUM_SMALL_CU: usize = 15;
+pub(crate) const ALLOWED_CHECKED_AMVP_NUM: usize = 4;
+/* number of spatial neighbours checked for merge and AMVP candidates */
+pub(crate) const MAX_NUM_POSSIBLE_SCAND: usize = 5;
+
+/* affine motion model */
+/* maximum number of control points */
+pub(crate) const VER_NUM: usize = 3;
+/* number of affine merge candidates */
+pub(crate) const AFF_MAX_CAND: usize = 5;
+/* number of affine AMVP candidates */
+pub(crate) const AFF_MAX_NUM_MVP: usize = 2;
+
+/* bi-prediction index for advanced motion vector prediction */
+pub(crate) const BI_NON: u8 = 0;
+pub(crate) const BI_NORMAL: u8 = 1;
+pub(crate) const BI_FL0: u8 = 2;
+pub(crate) const BI_FL1: u8 = 3;
+
+/* adaptive motion vector resolution: 1/4, 1/2, 1, 2 and 4 sample precision */
+pub(crate) const MAX_NUM_MVR: usize = 5;
+
+/* merge with motion vector difference */
+/* number of merge candidates used as a base motion */
+pub(crate) const MMVD_BASE_NUM: usize = 4;
+/* number of offset distances, from 1/4 to 32 samples */
+pub(crate) const MMVD_DIST_NUM: usize = 8;
+/* number of offset directions: +x, -x, +y, -y */
+pub(crate) const MMVD_DIR_NUM: usize = 4;
+/* number of groups of base predictions (bi-prediction, then each list); only
+the first one is used unless mmvd_group_enable_flag is set in the slice header */
+pub(crate) const MMVD_GRP_NUM: usize = 3;
+/* CUs up to this number of samples only use the first group, which is then
+uni-predicted */
+pub(crate) const MMVD_GRP_MIN_CU_SIZE: usize = 32;
+pub(crate) const MMVD_MAX_IDX: usize = MMVD_GRP_NUM * MMVD_BASE_NUM * MMVD_DIST_NUM * MMVD_DIR_NUM;
+
+/* boundary strength of the advanced deblocking filter */
+pub(crate) const DBF_ADDB_BS_INTRA_STRONG: u8 = 4;
+pub(crate) const DBF_ADDB_BS_INTRA: u8 = 3;
+pub(crate) const DBF_ADDB_BS_CODED: u8 = 2;
+pub(crate) const DBF_ADDB_BS_MV: u8 = 1;
+pub(crate) const DBF_ADDB_BS_OTHERS: u8 = 0;
+/* range of the slice offsets of the advanced deblocking filter */
+pub(crate) const DBF_ADDB_MAX_OFFSET: i8 = 12;
+
+/* transform types of the adaptive transform selection */
+pub(crate) const DCT2: u8 = 0xff;
+pub(crate) const DST7: u8 = 0;
+pub(crate) const DCT8: u8 = 1;
+pub(crate) const NUM_TRANS_TYPE: usize = 2;
+/* largest side of a block coded with the adaptive transform selection */
+pub(crate) const MAX_TR_LOG2_ATS: usize = 5;
+/* sub-block modes of the inter adaptive transform selection, packed with the
+sub-block position as ats_inter_info = idx | (pos << 4) */
+pub(crate) const ATS_INTER_NONE: u8 = 0;
+pub(crate) const ATS_INTER_HALF_VER: u8 = 1;
+pub(crate) const ATS_INTER_HALF_HOR: u8 = 2;
+pub(crate) const ATS_INTER_QUAD_VER: u8 = 3;
+pub(crate) const ATS_INTER_QUAD_HOR: u8 = 4;
+pub(crate) const NUM_ATS_INTER_IDX: u8 = 5;
+
+/* advanced coefficient coding */
+/* prefix groups of the last significant position, up to 64 */
+pub(crate) const NUM_LAST_POS_GROUP: usize = 12;
+/* coefficients of a coefficient group coded with the greater-than flags */
+pub(crate) const NUM_CTX_GTX_COEF: usize = 8;
 
 pub(crate) const COEF_SCAN_ZIGZAG: usize = 0;
 
@@ -97,6 +186,13 @@ pub(crate) const MAX_PB_SIZE: usize = MAX_NUM_REF_PICS + EXTRA_FRAME + DRA_FRAME
 pub(crate) const MAX_NUM_TILES_ROW: usize = 22;
 pub(crate) const MAX_NUM_TILES_COL: usize = 20;
 
+/* adaptive loop filter */
+pub(crate) const APS_MAX_NUM: usize = 32;
+pub(crate) const MAX_NUM_ALF_CLASSES: usize = 25;
+pub(crate) const MAX_NUM_ALF_LUMA_COEFF: usize = 13;
+pub(crate) const MAX_NUM_ALF_CHROMA_COEFF: usize = 7;
+pub(crate) const ALF_APS_TYPE: u32 = 0;
+
 /* Neighboring block availability flag bits */
 pub(crate) const AVAIL_BIT_UP: u16 = 0;
 pub(crate) const AVAIL_BIT_LE: u16 = 1;
@@ -189,7 +285,23 @@ pub(crate) enum InterPredDir {
     PRED_SKIP = 3,
     /* inter pred direction, look both list0, list1 side */
     PRED_DIR = 4,
-    PRED_NUM = 5,
+    /* affine inter pred with 4-parameter model, look list0 side */
+    AFF_L0 = 5,
+    /* affine inter pred with 4-parameter model, look list1 side */
+    AFF_L1 = 6,
+    /* affine inter pred with 4-parameter model, look both list0, list1 side */
+    AFF_BI = 7,
+    /* affine merge without residual */
+    AFF_SKIP = 8,
+    /* affine merge with residual */
+    AFF_DIR = 9,
+    /* affine inter pred with 6-parameter model, look list0 side */
+    AFF_6_L0 = 10,
+    /* affine inter pred with 6-parameter model, look list1 side */
+    AFF_6_L1 = 11,
+    /* affine inter pred with 6-parameter model, look both list0, list1 side */
+    AFF_6_BI = 12,
+    PRED_NUM = 13,
 }
 
 impl Default for InterPredDir {
@@ -202,16 +314,47 @@ impl Default for InterPredDir {
  * intra prediction direction
  *****************************************************************************/
 pub(crate) const IPD_RDO_CNT: usize = 5;
-
-#[derive(Clone, Copy, PartialEq)]
-pub(crate) enum IntraPredDir {
-    IPD_INVALID = -1,
-    IPD_DC_B = 0,
-    IPD_HOR_B = 1, /* Luma, Horizontal */
-    IPD_VER_B = 2, /* Luma, Vertical */
-    IPD_UL_B = 3,
-    IPD_UR_B = 4,
-    IPD_CNT_B = 5,
+/* number of luma modes of the baseline profile */
+pub(crate) const IPD_CNT_B: usize = 5;
+/* number of luma modes of the main profile with eipd */
+pub(crate) const IPD_CNT: usize = 33;
+/* number of chroma modes of the main profile with eipd */
+pub(crate) const IPD_CHROMA_CNT: usize = 5;
+/* number of most probable modes and of the secondary (pims) modes */
+pub(crate) const IPD_MPM_CNT: usize = 2;
+pub(crate) const IPD_PIMS_CNT: usize = 8;
+
+/* the mode numbers of the baseline and of the main profile overlap, so the
+direction is kept as a plain number with named constants */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct IntraPredDir(pub(crate) i8);
+
+impl IntraPredDir {
+    pub(crate) const IPD_INVALID: IntraPredDir = IntraPredDir(-1);
+
+    /* baseline profile */
+    pub(crate) const IPD_DC_B: IntraPredDir = IntraPredDir(0);
+    pub(crate) const IPD_HOR_B: IntraPredDir = IntraPredDir(1); /* Luma, Horizontal */
+    pub(crate) const IPD_VER_B: IntraPredDir = IntraPredDir(2); /* Luma, Vertical */
+    pub(crate) const IPD_UL_B: IntraPredDir = IntraPredDir(3);
+    pub(crate) const IPD_UR_B: IntraPredDir = IntraPredDir(4);
+
+    /* main profile with eipd, the modes from 3 to 32 are angular */
+    pub(crate) const IPD_DC: IntraPredDir = IntraPredDir(0);
+    pub(crate) const IPD_PLN: IntraPredDir = IntraPredDir(1); /* Luma, Planar */
+    pub(crate) const IPD_BI: IntraPredDir = IntraPredDir(2); /* Luma, Bilinear */
+    pub(crate) const IPD_DIA_L: IntraPredDir = IntraPredDir(6); /* Luma, Left diagonal */
+    pub(crate) const IPD_VER: IntraPredDir = IntraPredDir(12); /* Luma, Vertical */
+    pub(crate) const IPD_DIA_R: IntraPredDir = IntraPredDir(18); /* Luma, Right diagonal */
+    pub(crate) const IPD_HOR: IntraPredDir = IntraPredDir(24); /* Luma, Horizontal */
+    pub(crate) const IPD_DIA_U: IntraPredDir = IntraPredDir(30); /* Luma, Up diagonal */
+
+    /* chroma of the main profile with eipd */
+    pub(crate) const IPD_DM_C: IntraPredDir = IntraPredDir(0); /* Chroma, DM */
+    pub(crate) const IPD_BI_C: IntraPredDir = IntraPredDir(1); /* Chroma, Bilinear */
+    pub(crate) const IPD_DC_C: IntraPredDir = IntraPredDir(2); /* Chroma, DC */
+    pub(crate) const IPD_HOR_C: IntraPredDir = IntraPredDir(3); /* Chroma, Horizontal */
+    pub(crate) const IPD_VER_C: IntraPredDir = IntraPredDir(4); /* Chroma, Vertical */
 }
 
 impl Default for IntraPredDir {
@@ -222,16 +365,7 @@ impl Default for IntraPredDir {
 
 impl From<u8> for IntraPredDir {
     fn from(val: u8) -> Self {
-        use self::IntraPredDir::*;
-        match val {
-            0 => IPD_DC_B,
-            1 => IPD_HOR_B,
-            2 => IPD_VER_B,
-            3 => IPD_UL_B,
-            4 => IPD_UR_B,
-            5 => IPD_CNT_B,
-            _ => IPD_INVALID,
-        }
+        IntraPredDir(val as i8)
     }
 }
 
@@ -239,14 +373,16 @@ impl From<u8> for IntraPredDir {
 * macros for CU map
 
 - [ 0: 6] : SN: slice number (0 ~ 128)
-- [ 7:14] : reserved
+- [ 7: 7] : reserved
+- [ 8: 9] : AFF: affine motion model (0: none, 1: 4-parameter, 2: 6-parameter)
+- [10:10] : ATS_INTER: inter CU coded with the adaptive transform selection
+- [11:14] : reserved
 - [15:15] : IF: 1 -> intra CU, 0 -> inter CU
 - [16:22] : QP
 - [23:23] : SF: skip mode flag
 - [24:24] : CBFL: luma cbf
-- [25:25] : reserved
-- [26:26] : reserved
-- [27:30] : reserved
+- [25:27] : LOGW: log2 of CU width
+- [28:30] : LOGH: log2 of CU height
 - [31:31] : COD: 0 -> no encoded/decoded CU, 1 -> encoded/decoded CU
 *****************************************************************************/
 #[derive(Default, Clone, Copy)]
@@ -334,6 +470,56 @@ impl MCU {
         self.0 = self.0 & (!(1 << 24))
     }
 
+    /* set affine motion model to map */
+    #[inline]
+    pub(crate) fn SET_AFF(&mut self, aff: u32) {
+        self.0 = (self.0 & 0xFFFFFCFF) | ((aff & 0x03) << 8);
+    }
+    /* get affine motion model from map */
+    #[inline]
+    pub(crate) fn GET_AFF(&self) -> u32 {
+        (self.0 >> 8) & 0x03
+    }
+    /* clear affine motion model in map */
+    #[inline]
+    pub(crate) fn CLR_AFF(&mut self) {
+        self.0 = self.0 & 0xFFFFFCFF;
+    }
+
+    /* set inter ATS flag to map */
+    #[inline]
+    pub(crate) fn SET_ATS_INTER(&mut self) {
+        self.0 = self.0 | (1 << 10);
+    }
+    /* get inter ATS flag from map */
+    #[inline]
+    pub(crate) fn GET_ATS_INTER(&self) -> u32 {
+        (self.0 >> 10) & 1
+    }
+    /* clear inter ATS flag in map */
+    #[inline]
+    pub(crate) fn CLR_ATS_INTER(&mut self) {
+        self.0 = self.0 & (!(1 << 10));
+    }
+
+    /* set log2 of CU width and height to map */
+    #[inline]
+    pub(crate) fn SET_LOGW_LOGH(&mut self, log2_cuw: u8, log2_cuh: u8) {
+        self.0 = (self.0 & 0x81FFFFFF)
+            | ((log2_cuw as u32 & 0x07) << 25)
+            | ((log2_cuh as u32 & 0x07) << 28);
+    }
+    /* get log2 of CU width from map */
+    #[inline]
+    pub(crate) fn GET_LOGW(&self) -> u8 {
+        ((self.0 >> 25) & 0x07) as u8
+    }
+    /* get log2 of CU height from map */
+    #[inline]
+    pub(crate) fn GET_LOGH(&self) -> u8 {
+        ((self.0 >> 28) & 0x07) as u8
+    }
+
     /* set encoded/decoded CU to map */
     #[inline]
     pub(crate) fn SET_COD(&mut self) {
@@ -385,6 +571,10 @@ impl EvcNalu {
     }
 }
 
+/* SEI payload type of the decoded picture hash (user data in the reference
+software) */
+pub(crate) const EVC_UD_PIC_SIGNATURE: u32 = 0x10;
+
 pub(crate) const EXTENDED_SAR: usize = 255;
 pub(crate) const NUM_CPB: usize = 32;
 
@@ -471,6 +661,8 @@ pub(crate) struct EvcSps {
     pub(crate) log2_diff_ctu_max_14_cb_size: u8,
     pub(crate) log2_diff_ctu_max_tt_cb_size: u8,
     pub(crate) log2_diff_min_cb_min_tt_cb_size_minus2: u8,
+    pub(crate) log2_diff_ctu_size_max_suco_cb_size: u8,
+    pub(crate) log2_diff_max_suco_min_suco_cb_size: u8,
 
     pub(crate) tool_amvr: bool,
     pub(crate) tool_mmvd: bool,
@@ -482,6 +674,8 @@ pub(crate) struct EvcSps {
     pub(crate) tool_admvp: bool,
     pub(crate) tool_hmvp: bool,
     pub(crate) tool_eipd: bool,
+    pub(crate) ibc_flag: bool,
+    pub(crate) ibc_log_max_size: u8,
     pub(crate) tool_iqt: bool,
     pub(crate) tool_cm_init: bool,
     pub(crate) tool_ats: bool,
@@ -489,10 +683,20 @@ pub(crate) struct EvcSps {
     pub(crate) tool_pocs: bool,
     pub(crate) tool_adcc: bool,
 
+    pub(crate) log2_max_pic_order_cnt_lsb_minus4: u8,
     pub(crate) log2_sub_gop_length: u8,
     pub(crate) log2_ref_pic_gap_length: u8,
     pub(crate) max_num_ref_pics: u8,
 
+    /* reference picture lists signalled in the SPS, selected by the slices */
+    pub(crate) sps_max_dec_pic_buffering_minus1: u8,
+    pub(crate) long_term_ref_pics_flag: bool,
+    pub(crate) rpl1_same_as_rpl0_flag: bool,
+    pub(crate) num_ref_pic_lists_in_sps0: u8,
+    pub(crate) num_ref_pic_lists_in_sps1: u8,
+    pub(crate) rpls_l0: [EvcRpl; MAX_NUM_RPLS],
+    pub(crate) rpls_l1: [EvcRpl; MAX_NUM_RPLS],
+
     pub(crate) picture_cropping_flag: bool,
     pub(crate) picture_crop_left_offset: u16,
     pub(crate) picture_crop_right_offset: u16,
@@ -519,6 +723,13 @@ pub(crate) struct EvcPps {
     pub(crate) additional_lt_poc_lsb_len: u8,
     pub(crate) rpl1_idx_present_flag: bool,
     pub(crate) single_tile_in_pic_flag: bool,
+    pub(crate) num_tile_columns_minus1: u8,
+    pub(crate) num_tile_rows_minus1: u8,
+    pub(crate) uniform_tile_spacing_flag: bool,
+    pub(crate) tile_column_width_minus1: [u16; MAX_NUM_TILES_COL],
+    pub(crate) tile_row_height_minus1: [u16; MAX_NUM_TILES_ROW],
+    pub(crate) loop_filter_across_tiles_enabled_flag: bool,
+    pub(crate) tile_offset_len_minus1: u8,
     pub(crate) tile_id_len_minus1: u8,
     pub(crate) explicit_tile_id_flag: bool,
     pub(crate) pic_dra_enabled_flag: bool,
@@ -528,15 +739,71 @@ pub(crate) struct EvcPps {
     pub(crate) cu_qp_delta_area: u8,
 }
 
+/*****************************************************************************
+ * adaptive loop filter parameters, carried by an adaptation parameter set
+ *****************************************************************************/
+#[derive(Clone)]
+pub(crate) struct EvcAlfParam {
+    pub(crate) luma_signal_flag: bool,
+    pub(crate) chroma_signal_flag: bool,
+    pub(crate) num_luma_filters: u8,
+    /* true: 7x7 diamond, false: 5x5 diamond */
+    pub(crate) luma_filter_type: bool,
+    pub(crate) filter_coeff_delta_idx: [u8; MAX_NUM_ALF_CLASSES],
+    /* 0: no fixed filter, 1: fixed filters for all classes, 2: signalled */
+    pub(crate) fixed_filter_pattern: u8,
+    pub(crate) fixed_filter_usage_flag: [bool; MAX_NUM_ALF_CLASSES],
+    pub(crate) fixed_filter_idx: [u8; MAX_NUM_ALF_CLASSES],
+    pub(crate) coeff_delta_flag: bool,
+    pub(crate) coeff_delta_pred_mode_flag: bool,
+    pub(crate) filter_coeff_flag: [bool; MAX_NUM_ALF_CLASSES],
+    pub(crate) luma_coeff: [i16; MAX_NUM_ALF_CLASSES * MAX_NUM_ALF_LUMA_COEFF],
+    pub(crate) chroma_coeff: [i16; MAX_NUM_ALF_CHROMA_COEFF],
+}
+
+impl Default for EvcAlfParam {
+    fn default() -> Self {
+        EvcAlfParam {
+            luma_signal_flag: false,
+            chroma_signal_flag: false,
+            num_luma_filters: 1,
+            luma_filter_type: true,
+            filter_coeff_delta_idx: [0; MAX_NUM_ALF_CLASSES],
+            fixed_filter_pattern: 0,
+            fixed_filter_usage_flag: [false; MAX_NUM_ALF_CLASSES],
+            fixed_filter_idx: [0; MAX_NUM_ALF_CLASSES],
+            coeff_delta_flag: false,
+            coeff_delta_pred_mode_flag: false,
+            filter_coeff_flag: [true; MAX_NUM_ALF_CLASSES],
+            luma_coeff: [0; MAX_NUM_ALF_CLASSES * MAX_NUM_ALF_LUMA_COEFF],
+            chroma_coeff: [0; MAX_NUM_ALF_CHROMA_COEFF],
+        }
+    }
+}
+
 /*****************************************************************************
  * slice header
  *****************************************************************************/
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub(crate) struct EvcSh {
     pub(crate) slice_pic_parameter_set_id: u8,
+    pub(crate) single_tile_in_slice_flag: bool,
+    pub(crate) first_tile_id: u16,
+    pub(crate) arbitrary_slice_flag: bool,
+    pub(crate) last_tile_id: u16,
     pub(crate) slice_type: SliceType,
     pub(crate) no_output_of_prior_pics_flag: bool,
 
+    /* adaptive loop filter */
+    pub(crate) alf_on: bool,
+    pub(crate) aps_id_y: u8,
+    pub(crate) alf_map_flag: bool,
+    pub(crate) alf_chroma_idc: u8,
+    pub(crate) aps_id_ch: u8,
+    pub(crate) alf_chroma_map_flag: bool,
+    pub(crate) aps_id_ch2: u8,
+    pub(crate) alf_chroma2_map_flag: bool,
+
     pub(crate) poc_lsb: i32,
 
     /*   HLS_RPL */
@@ -548,7 +815,20 @@ pub(crate) struct EvcSh {
     pub(crate) rpl_l1: EvcRpl,
 
     pub(crate) num_ref_idx_active_override_flag: bool,
+
+    /* collocated picture for temporal motion vector prediction */
+    pub(crate) temporal_mvp_asigned_flag: bool,
+    pub(crate) collocated_from_list_idx: u8,
+    pub(crate) collocated_from_ref_idx: u8,
+    pub(crate) collocated_mvp_source_list_idx: u8,
+
+    /* larger MMVD offset distances allowed for the CUs of the slice */
+    pub(crate) mmvd_group_enable_flag: bool,
+
     pub(crate) deblocking_filter_on: bool,
+    /* tc and beta offsets of the advanced deblocking filter */
+    pub(crate) sh_deblock_alpha_offset: i8,
+    pub(crate) sh_deblock_beta_offset: i8,
 
     pub(crate) qp: u8,
     pub(crate) qp_u: u8,
@@ -564,6 +844,67 @@ pub(crate) struct EvcSh {
     pub(crate) num_ctb: u16,
 }
 
+/*****************************************************************************
+ * tile position and size in LCU unit
+ *****************************************************************************/
+#[derive(Default, Clone, Copy)]
+pub(crate) struct EvcTile {
+    /* column of the top-left LCU */
+    pub(crate) x_lcu: u16,
+    /* row of the top-left LCU */
+    pub(crate) y_lcu: u16,
+    /* tile width in LCU unit */
+    pub(crate) w_lcu: u16,
+    /* tile height in LCU unit */
+    pub(crate) h_lcu: u16,
+    /* tile size in LCU unit (= w_lcu * h_lcu) */
+    pub(crate) f_lcu: u32,
+}
+
+/*****************************************************************************
+ * history buffer of motion of previously coded CUs (HMVP)
+ *****************************************************************************/
+#[derive(Clone, Copy)]
+pub(crate) struct EvcHistoryBuffer {
+    pub(crate) history_mv_table: [[[i16; MV_D]; REFP_NUM]; ALLOWED_CHECKED_NUM],
+    pub(crate) history_refi_table: [[i8; REFP_NUM]; ALLOWED_CHECKED_NUM],
+    pub(crate) currCnt: usize,
+    pub(crate) m_maxCnt: usize,
+}
+
+impl Default for EvcHistoryBuffer {
+    fn default() -> Self {
+        EvcHistoryBuffer {
+            history_mv_table: [[[0; MV_D]; REFP_NUM]; ALLOWED_CHECKED_NUM],
+            history_refi_table: [[REFI_INVALID; REFP_NUM]; ALLOWED_CHECKED_NUM],
+            currCnt: 0,
+            m_maxCnt: ALLOWED_CHECKED_NUM,
+        }
+    }
+}
+
+impl EvcHistoryBuffer {
+    pub(crate) fn reset(&mut self) {
+        *self = EvcHistoryBuffer::default();
+    }
+
+    /* append the motion of a coded CU, dropping the oldest entry when full */
+    pub(crate) fn update(&mut self, mv: &[[i16; MV_D]; REFP_NUM], refi: &[i8; REFP_NUM]) {
+        if self.currCnt == self.m_maxCnt {
+            for i in 1..self.currCnt {
+                self.history_mv_table[i - 1] = self.history_mv_table[i];
+                self.history_refi_table[i - 1] = self.history_refi_table[i];
+            }
+            self.history_mv_table[self.currCnt - 1] = *mv;
+            self.history_refi_table[self.currCnt - 1] = *refi;
+        } else {
+            self.history_mv_table[self.currCnt] = *mv;
+            self.history_refi_table[self.currCnt] = *refi;
+            self.currCnt += 1;
+        }
+    }
+}
+
 /*****************************************************************************/
 #[derive(Default)]
 pub(crate) struct EvcPoc {
@@ -581,49 +922,104 @@ pub(crate) struct EvcPoc {
 #[derive(PartialEq, Clone, Copy)]
 pub(crate) enum SplitMode {
     NO_SPLIT = 0,
-    SPLIT_QUAD = 1,
+    SPLIT_BI_VER = 1,
+    SPLIT_BI_HOR = 2,
+    SPLIT_TRI_VER = 3,
+    SPLIT_TRI_HOR = 4,
+    SPLIT_QUAD = 5,
+}
+pub(crate) const MAX_SPLIT_NUM: usize = 6;
+
+impl From<usize> for SplitMode {
+    fn from(val: usize) -> Self {
+        use self::SplitMode::*;
+        match val {
+            1 => SPLIT_BI_VER,
+            2 => SPLIT_BI_HOR,
+            3 => SPLIT_TRI_VER,
+            4 => SPLIT_TRI_HOR,
+            5 => SPLIT_QUAD,
+            _ => NO_SPLIT,
+        }
+    }
 }
-pub(crate) const MAX_SPLIT_NUM: usize = 2;
 
 impl SplitMode {
     #[inline]
     pub(crate) fn part_count(&self) -> usize {
-        if self == &SplitMode::NO_SPLIT {
-            0
-        } else {
-            4
+        match self {
+            SplitMode::NO_SPLIT => 0,
+            SplitMode::SPLIT_BI_VER | SplitMode::SPLIT_BI_HOR => 2,
+            SplitMode::SPLIT_TRI_VER | SplitMode::SPLIT_TRI_HOR => 3,
+            SplitMode::SPLIT_QUAD => 4,
         }
     }
 
     #[inline]
-    pub(crate) fn part_size(&self, length: usize) -> usize {
-        if self == &SplitMode::NO_SPLIT {
-            length
-        } else {
-            length >> 1
+    pub(crate) fn part_size(&self, part_idx: usize, length: usize) -> usize {
+        match self {
+            SplitMode::NO_SPLIT => length,
+            SplitMode::SPLIT_TRI_VER | SplitMode::SPLIT_TRI_HOR if part_idx != 1 => length >> 2,
+            _ => length >> 1,
         }
     }
 
     #[inline]
-    pub(crate) fn part_size_idx(&self, length_idx: usize) -> usize {
-        if self == &SplitMode::NO_SPLIT {
-            length_idx
-        } else {
-            length_idx - 1
+    pub(crate) fn part_size_idx(&self, part_idx: usize, length_idx: usize) -> usize {
+        match self {
+            SplitMode::NO_SPLIT => length_idx,
+            SplitMode::SPLIT_TRI_VER | SplitMode::SPLIT_TRI_HOR if part_idx != 1 => length_idx - 2,
+            _ => length_idx - 1,
         }
     }
 
+    #[inline]
+    pub(crate) fn is_vertical(&self) -> bool {
+        !(self == &SplitMode::SPLIT_BI_HOR || self == &SplitMode::SPLIT_TRI_HOR)
+    }
+
+    #[inline]
+    pub(crate) fn is_bt(&self) -> bool {
+        self == &SplitMode::SPLIT_BI_VER || self == &SplitMode::SPLIT_BI_HOR
+    }
+
+    #[inline]
+    pub(crate) fn is_tt(&self) -> bool {
+        self == &SplitMode::SPLIT_TRI_VER || self == &SplitMode::SPLIT_TRI_HOR
+    }
+
     /* Partitioning (START) */
     #[inline]
     pub(crate) fn inc_qt_depth(&self, qtd: u8) -> u8 {
-        if self == &SplitMode::NO_SPLIT {
+        if self == &SplitMode::SPLIT_QUAD {
+            qtd + 1
+        } else {
             qtd
+        }
+    }
+
+    #[inline]
+    pub(crate) fn inc_btt_depth(&self, bttd: u8) -> u8 {
+        if self == &SplitMode::NO_SPLIT || self == &SplitMode::SPLIT_QUAD {
+            bttd
         } else {
-            qtd + 1
+            bttd + 1
         }
     }
 }
 
+/* block ratios limited by the SPS, for the split table */
+pub(crate) const BLOCK_11: usize = 0;
+pub(crate) const BLOCK_12: usize = 1;
+pub(crate) const BLOCK_14: usize = 2;
+pub(crate) const BLOCK_TT: usize = 3;
+pub(crate) const SPLIT_CHECK_NUM: usize = 4;
+
+/* log2 of the largest and the smallest long side of a block of each ratio */
+pub(crate) const IDX_MAX: usize = 0;
+pub(crate) const IDX_MIN: usize = 1;
+pub(crate) type EvcSplitTbl = [[u8; 2]; SPLIT_CHECK_NUM];
+
 pub(crate) enum SplitDir {
     SPLIT_VER = 0,
     SPLIT_HOR = 1,
@@ -638,12 +1034,19 @@ pub(crate) enum BlockShape {
     NUM_BLOCK_SHAPE = 5,
 }
 
+#[derive(Clone, Copy, PartialEq)]
 pub(crate) enum ModeCons {
     eOnlyIntra,
     eOnlyInter,
     eAll,
 }
 
+impl Default for ModeCons {
+    fn default() -> Self {
+        ModeCons::eAll
+    }
+}
+
 pub(crate) type SBAC_CTX_MODEL = u16;
 
 /* CABAC (START) */
@@ -665,13 +1068,58 @@ pub(crate) const NUM_CTX_INTER_PRED_IDC: usize = 2;
 pub(crate) const NUM_CTX_DIRECT_MODE_FLAG: usize = 1;
 pub(crate) const NUM_CTX_REF_IDX: usize = 2;
 pub(crate) const NUM_CTX_MVP_IDX: usize = 3;
+pub(crate) const NUM_CTX_MERGE_MODE_FLAG: usize = 1;
+pub(crate) const NUM_CTX_MERGE_IDX: usize = 5;
+pub(crate) const NUM_CTX_BI_IDX: usize = 2;
 pub(crate) const NUM_CTX_MVD: usize = 1;
 pub(crate) const NUM_CTX_INTRA_PRED_MODE: usize = 2;
+pub(crate) const NUM_CTX_INTRA_PRED_MODE_C: usize = 1;
+pub(crate) const NUM_CTX_INTRA_LUMA_PRED_MPM_FLAG: usize = 1;
+pub(crate) const NUM_CTX_INTRA_LUMA_PRED_MPM_IDX: usize = 1;
 pub(crate) const NUM_CTX_CC_RUN: usize = 24;
 pub(crate) const NUM_CTX_CC_LAST: usize = 2;
 pub(crate) const NUM_CTX_CC_LEVEL: usize = 24;
+pub(crate) const NUM_CTX_BTT_SPLIT_FLAG: usize = 15;
+pub(crate) const NUM_CTX_BTT_SPLIT_DIR: usize = 5;
+pub(crate) const NUM_CTX_BTT_SPLIT_TYPE: usize = 1;
+pub(crate) const NUM_CTX_SUCO_FLAG: usize = 14;
+pub(crate) const NUM_CTX_MODE_CONS: usize = 3;
 pub(crate) const NUM_CTX_SPLIT_CU_FLAG: usize = 1;
 pub(crate) const NUM_CTX_DELTA_QP: usize = 1;
+pub(crate) const NUM_CTX_ALF_CTB_FLAG: usize = 1;
+pub(crate) const NUM_CTX_AFFINE_FLAG: usize = 2;
+pub(crate) const NUM_CTX_AFFINE_MODE: usize = 1;
+pub(crate) const NUM_CTX_AFFINE_MRG: usize = AFF_MAX_CAND;
+pub(crate) const NUM_CTX_AFFINE_MVP_IDX: usize = AFF_MAX_NUM_MVP - 1;
+pub(crate) const NUM_CTX_AFFINE_MVD_FLAG: usize = 2;
+pub(crate) const NUM_CTX_MVR_IDX: usize = MAX_NUM_MVR - 1;
+pub(crate) const NUM_CTX_MMVD_FLAG: usize = 1;
+pub(crate) const NUM_CTX_MMVD_GROUP_IDX: usize = MMVD_GRP_NUM - 1;
+pub(crate) const NUM_CTX_MMVD_MERGE_IDX: usize = MMVD_BASE_NUM - 1;
+pub(crate) const NUM_CTX_MMVD_DIST_IDX: usize = MMVD_DIST_NUM - 1;
+pub(crate) const NUM_CTX_MMVD_DIRECTION_IDX: usize = 2;
+pub(crate) const NUM_CTX_ATS_MODE_FLAG: usize = 1;
+pub(crate) const NUM_CTX_ATS_INTER_FLAG: usize = 2;
+pub(crate) const NUM_CTX_ATS_INTER_QUAD_FLAG: usize = 1;
+pub(crate) const NUM_CTX_ATS_INTER_HOR_FLAG: usize = 3;
+pub(crate) const NUM_CTX_ATS_INTER_POS_FLAG: usize = 1;
+/* luma contexts first, then chroma */
+pub(crate) const NUM_CTX_LAST_SIG_COEFF_LUMA: usize = 18;
+pub(crate) const NUM_CTX_LAST_SIG_COEFF_CHROMA: usize = 3;
+pub(crate) const NUM_CTX_LAST_SIG_COEFF: usize =
+    NUM_CTX_LAST_SIG_COEFF_LUMA + NUM_CTX_LAST_SIG_COEFF_CHROMA;
+/* one set of sig_coeff_flag contexts per luma transform size class, then a
+chroma set which only uses the first 8 contexts */
+pub(crate) const NUM_CTX_SIG_COEFF_FLAG: usize = 13;
+pub(crate) const NUM_SIG_COEFF_SET: usize = 4;
+pub(crate) const NUM_CTX_GTX_LUMA: usize = 13;
+pub(crate) const NUM_CTX_GTX: usize = NUM_CTX_GTX_LUMA + 5;
+
+/* flags whose context is derived from the neighbouring CUs */
+pub(crate) const CNID_SKIP_FLAG: usize = 0;
+pub(crate) const CNID_PRED_MODE: usize = 1;
+pub(crate) const CNID_AFFN_FLAG: usize = 2;
+pub(crate) const NUM_CNID: usize = 3;
 
 /* context models for arithemetic coding */
 #[derive(Default, Copy, Clone)]
@@ -686,13 +1134,45 @@ pub(crate) struct EvcSbacCtx {
     pub(crate) direct_mode_flag: [SBAC_CTX_MODEL; NUM_CTX_DIRECT_MODE_FLAG],
     pub(crate) refi: [SBAC_CTX_MODEL; NUM_CTX_REF_IDX],
     pub(crate) mvp_idx: [SBAC_CTX_MODEL; NUM_CTX_MVP_IDX],
+    pub(crate) merge_mode_flag: [SBAC_CTX_MODEL; NUM_CTX_MERGE_MODE_FLAG],
+    pub(crate) merge_idx: [SBAC_CTX_MODEL; NUM_CTX_MERGE_IDX],
+    pub(crate) bi_idx: [SBAC_CTX_MODEL; NUM_CTX_BI_IDX],
     pub(crate) mvd: [SBAC_CTX_MODEL; NUM_CTX_MVD],
     pub(crate) intra_dir: [SBAC_CTX_MODEL; NUM_CTX_INTRA_PRED_MODE],
+    pub(crate) intra_dir_c: [SBAC_CTX_MODEL; NUM_CTX_INTRA_PRED_MODE_C],
+    pub(crate) intra_luma_pred_mpm_flag: [SBAC_CTX_MODEL; NUM_CTX_INTRA_LUMA_PRED_MPM_FLAG],
+    pub(crate) intra_luma_pred_mpm_idx: [SBAC_CTX_MODEL; NUM_CTX_INTRA_LUMA_PRED_MPM_IDX],
     pub(crate) run: [SBAC_CTX_MODEL; NUM_CTX_CC_RUN],
     pub(crate) last: [SBAC_CTX_MODEL; NUM_CTX_CC_LAST],
     pub(crate) level: [SBAC_CTX_MODEL; NUM_CTX_CC_LEVEL],
+    pub(crate) btt_split_flag: [SBAC_CTX_MODEL; NUM_CTX_BTT_SPLIT_FLAG],
+    pub(crate) btt_split_dir: [SBAC_CTX_MODEL; NUM_CTX_BTT_SPLIT_DIR],
+    pub(crate) btt_split_type: [SBAC_CTX_MODEL; NUM_CTX_BTT_SPLIT_TYPE],
+    pub(crate) suco_flag: [SBAC_CTX_MODEL; NUM_CTX_SUCO_FLAG],
+    pub(crate) mode_cons: [SBAC_CTX_MODEL; NUM_CTX_MODE_CONS],
     pub(crate) split_cu_flag: [SBAC_CTX_MODEL; NUM_CTX_SPLIT_CU_FLAG],
     pub(crate) delta_qp: [SBAC_CTX_MODEL; NUM_CTX_DELTA_QP],
+    pub(crate) alf_ctb_flag: [SBAC_CTX_MODEL; NUM_CTX_ALF_CTB_FLAG],
+    pub(crate) affine_flag: [SBAC_CTX_MODEL; NUM_CTX_AFFINE_FLAG],
+    pub(crate) affine_mode: [SBAC_CTX_MODEL; NUM_CTX_AFFINE_MODE],
+    pub(crate) affine_mrg: [SBAC_CTX_MODEL; NUM_CTX_AFFINE_MRG],
+    pub(crate) affine_mvp_idx: [SBAC_CTX_MODEL; NUM_CTX_AFFINE_MVP_IDX],
+    pub(crate) affine_mvd_flag: [SBAC_CTX_MODEL; NUM_CTX_AFFINE_MVD_FLAG],
+    pub(crate) mvr_idx: [SBAC_CTX_MODEL; NUM_CTX_MVR_IDX],
+    pub(crate) mmvd_flag: [SBAC_CTX_MODEL; NUM_CTX_MMVD_FLAG],
+    pub(crate) mmvd_group_idx: [SBAC_CTX_MODEL; NUM_CTX_MMVD_GROUP_IDX],
+    pub(crate) mmvd_merge_idx: [SBAC_CTX_MODEL; NUM_CTX_MMVD_MERGE_IDX],
+    pub(crate) mmvd_dist_idx: [SBAC_CTX_MODEL; NUM_CTX_MMVD_DIST_IDX],
+    pub(crate) mmvd_direction_idx: [SBAC_CTX_MODEL; NUM_CTX_MMVD_DIRECTION_IDX],
+    pub(crate) ats_mode: [SBAC_CTX_MODEL; NUM_CTX_ATS_MODE_FLAG],
+    pub(crate) ats_inter_flag: [SBAC_CTX_MODEL; NUM_CTX_ATS_INTER_FLAG],
+    pub(crate) ats_inter_quad_flag: [SBAC_CTX_MODEL; NUM_CTX_ATS_INTER_QUAD_FLAG],
+    pub(crate) ats_inter_hor_flag: [SBAC_CTX_MODEL; NUM_CTX_ATS_INTER_HOR_FLAG],
+    pub(crate) ats_inter_pos_flag: [SBAC_CTX_MODEL; NUM_CTX_ATS_INTER_POS_FLAG],
+    pub(crate) last_sig_coeff_x_prefix: [SBAC_CTX_MODEL; NUM_CTX_LAST_SIG_COEFF],
+    pub(crate) last_sig_coeff_y_prefix: [SBAC_CTX_MODEL; NUM_CTX_LAST_SIG_COEFF],
+    pub(crate) sig_coeff_flag: [[SBAC_CTX_MODEL; NUM_CTX_SIG_COEFF_FLAG]; NUM_SIG_COEFF_SET],
+    pub(crate) coeff_abs_level_greaterAB_flag: [SBAC_CTX_MODEL; NUM_CTX_GTX],
 }
 
 pub(crate) const QUANT_SHIFT: usize = 14;
@@ -717,6 +1197,23 @@ impl Default for LcuSplitMode {
     }
 }
 
+/* split unit coding order flags of an LCU, laid out like LcuSplitMode */
+#[derive(Clone)]
+pub(crate) struct LcuSucoFlag {
+    pub(crate) data: Vec<Vec<Vec<bool>>>,
+}
+
+impl Default for LcuSucoFlag {
+    fn default() -> Self {
+        LcuSucoFlag {
+            data: vec![
+                vec![vec![false; MAX_CU_CNT_IN_LCU]; BlockShape::NUM_BLOCK_SHAPE as usize];
+                NUM_CU_DEPTH
+            ],
+        }
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct CUBuffer<T: Default + Copy> {
     pub(crate) data: Vec<Vec<T>>,
@@ -727,8 +1224,8 @@ impl<T: Default + Copy> Default for CUBuffer<T> {
         CUBuffer {
             data: vec![
                 vec![T::default(); MAX_CU_DIM],
-                vec![T::default(); MAX_CU_DIM >> 2],
-                vec![T::default(); MAX_CU_DIM >> 2],
+                vec![T::default(); MAX_CU_DIM],
+                vec![T::default(); MAX_CU_DIM],
             ],
         }
     }
@@ -6,13 +6,16 @@ use super::tbl::*;
 use super::tracer::*;
 use super::util::*;
 
-use std::cell::RefCell;
+use crate::api::ChromaSampling;
 use std::cmp::*;
-use std::rc::Rc;
+use std::sync::{Arc, RwLock};
 
 pub(crate) fn evc_deblock(
     sh_qp_u_offset: i8,
     sh_qp_v_offset: i8,
+    addb: bool,
+    sh_alpha_offset: i8,
+    sh_beta_offset: i8,
     w_lcu: u16,
     h_lcu: u16,
     w_scu: u16,
@@ -20,15 +23,18 @@ pub(crate) fn evc_deblock(
     w: u16,
     h: u16,
     tracer: &mut Option<Tracer>,
-    pic: &Option<Rc<RefCell<EvcPic>>>,
+    pic: &Option<Arc<RwLock<EvcPic>>>,
     map_scu: &mut [MCU],
     map_split: &[LcuSplitMode],
-    map_mv: &Option<Rc<RefCell<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
-    map_refi: &Option<Rc<RefCell<Vec<[i8; REFP_NUM]>>>>,
+    map_suco: &[LcuSucoFlag],
+    map_mv: &Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    map_refi: &Option<Arc<RwLock<Vec<[i8; REFP_NUM]>>>>,
+    refp: &[Vec<EvcRefP>],
+    map_tidx: Option<&[u16]>,
     evc_tbl_qp_chroma_dynamic_ext: &Vec<Vec<i8>>,
 ) {
     if let Some(pic) = pic {
-        let mut p = pic.borrow_mut();
+        let mut p = pic.write().unwrap();
         p.pic_qp_u_offset = sh_qp_u_offset;
         p.pic_qp_v_offset = sh_qp_v_offset;
     }
@@ -61,6 +67,9 @@ pub(crate) fn evc_deblock(
                 0,
                 0,
                 false, /*horizontal filtering of vertical edge*/
+                addb,
+                sh_alpha_offset,
+                sh_beta_offset,
                 w_lcu,
                 w_scu,
                 w,
@@ -69,8 +78,11 @@ pub(crate) fn evc_deblock(
                 pic,
                 map_scu,
                 map_split,
+                map_suco,
                 map_mv,
                 map_refi,
+                refp,
+                map_tidx,
                 evc_tbl_qp_chroma_dynamic_ext,
             );
         }
@@ -93,6 +105,9 @@ pub(crate) fn evc_deblock(
                 0,
                 0,
                 true, /*vertical filtering of horizontal edge*/
+                addb,
+                sh_alpha_offset,
+                sh_beta_offset,
                 w_lcu,
                 w_scu,
                 w,
@@ -101,8 +116,11 @@ pub(crate) fn evc_deblock(
                 pic,
                 map_scu,
                 map_split,
+                map_suco,
                 map_mv,
                 map_refi,
+                refp,
+                map_tidx,
                 evc_tbl_qp_chroma_dynamic_ext,
             );
         }
@@ -117,16 +135,22 @@ fn evc_deblock_tree(
     cud: u16,
     cup: u16,
     is_hor_edge: bool,
+    addb: bool,
+    sh_alpha_offset: i8,
+    sh_beta_offset: i8,
     w_lcu: u16,
     w_scu: u16,
     w: u16,
     h: u16,
     tracer: &mut Option<Tracer>,
-    pic: &Option<Rc<RefCell<EvcPic>>>,
+    pic: &Option<Arc<RwLock<EvcPic>>>,
     map_scu: &mut [MCU],
     map_split: &[LcuSplitMode],
-    map_mv: &Option<Rc<RefCell<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
-    map_refi: &Option<Rc<RefCell<Vec<[i8; REFP_NUM]>>>>,
+    map_suco: &[LcuSucoFlag],
+    map_mv: &Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    map_refi: &Option<Arc<RwLock<Vec<[i8; REFP_NUM]>>>>,
+    refp: &[Vec<EvcRefP>],
+    map_tidx: Option<&[u16]>,
     evc_tbl_qp_chroma_dynamic_ext: &Vec<Vec<i8>>,
 ) {
     let lcu_num = (x >> MAX_CU_LOG2) + (y >> MAX_CU_LOG2) * w_lcu;
@@ -163,9 +187,19 @@ fn evc_deblock_tree(
             (MAX_CU_LOG2 - MIN_CU_LOG2) as u8,
         );
 
+        let suco_flag = evc_get_suco_flag(
+            cud,
+            cup,
+            cuw,
+            cuh,
+            MAX_CU_SIZE as u16,
+            &map_suco[lcu_num as usize],
+        );
+        let suco_order = evc_split_get_suco_order(suco_flag, split_mode);
+
         // In base profile we have small chroma blocks
         for part_num in 0..split_struct.part_count {
-            let cur_part_num = part_num;
+            let cur_part_num = suco_order[part_num];
             let sub_cuw = split_struct.width[cur_part_num];
             let sub_cuh = split_struct.height[cur_part_num];
             let x_pos = split_struct.x_pos[cur_part_num];
@@ -180,6 +214,9 @@ fn evc_deblock_tree(
                     split_struct.cud[cur_part_num],
                     split_struct.cup[cur_part_num],
                     is_hor_edge,
+                    addb,
+                    sh_alpha_offset,
+                    sh_beta_offset,
                     w_lcu,
                     w_scu,
                     w,
@@ -188,102 +225,143 @@ fn evc_deblock_tree(
                     pic,
                     map_scu,
                     map_split,
+                    map_suco,
                     map_mv,
                     map_refi,
+                    refp,
+                    map_tidx,
                     evc_tbl_qp_chroma_dynamic_ext,
                 );
             }
         }
     } else if let (Some(pic), Some(map_refi), Some(map_mv)) = (pic, map_refi, map_mv) {
+        /* edges on tile boundaries are filtered only across tiles */
+        let filter_top = y == 0 || evc_same_tile(map_tidx, w_lcu, x, y, x, y - 1);
+        let filter_left = x == 0 || evc_same_tile(map_tidx, w_lcu, x, y, x - 1, y);
+        let filter_right = x + cuw >= w || evc_same_tile(map_tidx, w_lcu, x, y, x + cuw, y);
+
         // deblock
         if is_hor_edge {
             if cuh > MAX_TR_SIZE as u16 {
                 evc_deblock_cu_hor(
                     tracer,
-                    &*pic.borrow(),
+                    &*pic.read().unwrap(),
                     x as usize,
                     y as usize,
                     cuw as usize,
                     cuh as usize >> 1,
                     map_scu,
-                    &*map_refi.borrow(),
-                    &*map_mv.borrow(),
+                    &*map_refi.read().unwrap(),
+                    &*map_mv.read().unwrap(),
+                    refp,
                     w_scu as usize,
                     evc_tbl_qp_chroma_dynamic_ext,
+                    addb,
+                    sh_alpha_offset,
+                    sh_beta_offset,
+                    filter_top,
                 );
 
                 evc_deblock_cu_hor(
                     tracer,
-                    &*pic.borrow(),
+                    &*pic.read().unwrap(),
                     x as usize,
                     y as usize + MAX_TR_SIZE,
                     cuw as usize,
                     cuh as usize >> 1,
                     map_scu,
-                    &*map_refi.borrow(),
-                    &*map_mv.borrow(),
+                    &*map_refi.read().unwrap(),
+                    &*map_mv.read().unwrap(),
+                    refp,
                     w_scu as usize,
                     evc_tbl_qp_chroma_dynamic_ext,
+                    addb,
+                    sh_alpha_offset,
+                    sh_beta_offset,
+                    true,
                 );
             } else {
                 evc_deblock_cu_hor(
                     tracer,
-                    &*pic.borrow(),
+                    &*pic.read().unwrap(),
                     x as usize,
                     y as usize,
                     cuw as usize,
                     cuh as usize,
                     map_scu,
-                    &*map_refi.borrow(),
-                    &*map_mv.borrow(),
+                    &*map_refi.read().unwrap(),
+                    &*map_mv.read().unwrap(),
+                    refp,
                     w_scu as usize,
                     evc_tbl_qp_chroma_dynamic_ext,
+                    addb,
+                    sh_alpha_offset,
+                    sh_beta_offset,
+                    filter_top,
                 );
             }
         } else {
             if cuw > MAX_TR_SIZE as u16 {
                 evc_deblock_cu_ver(
                     tracer,
-                    &*pic.borrow(),
+                    &*pic.read().unwrap(),
                     x as usize,
                     y as usize,
                     cuw as usize >> 1,
                     cuh as usize,
                     map_scu,
-                    &*map_refi.borrow(),
-                    &*map_mv.borrow(),
+                    &*map_refi.read().unwrap(),
+                    &*map_mv.read().unwrap(),
+                    refp,
                     w_scu as usize,
                     evc_tbl_qp_chroma_dynamic_ext,
+                    addb,
+                    sh_alpha_offset,
+                    sh_beta_offset,
                     w as usize,
+                    filter_left,
+                    true,
                 );
                 evc_deblock_cu_ver(
                     tracer,
-                    &*pic.borrow(),
+                    &*pic.read().unwrap(),
                     x as usize + MAX_TR_SIZE,
                     y as usize,
                     cuw as usize >> 1,
                     cuh as usize,
                     map_scu,
-                    &*map_refi.borrow(),
-                    &*map_mv.borrow(),
+                    &*map_refi.read().unwrap(),
+                    &*map_mv.read().unwrap(),
+                    refp,
                     w_scu as usize,
                     evc_tbl_qp_chroma_dynamic_ext,
+                    addb,
+                    sh_alpha_offset,
+                    sh_beta_offset,
                     w as usize,
+                    true,
+                    filter_right,
                 );
             } else {
                 evc_deblock_cu_ver(
                     tracer,
-                    &*pic.borrow(),
+                    &*pic.read().unwrap(),
                     x as usize,
                     y as usize,
                     cuw as usize,
                     cuh as usize,
                     map_scu,
-                    &*map_refi.borrow(),
-                    &*map_mv.borrow(),
+                    &*map_refi.read().unwrap(),
+                    &*map_mv.read().unwrap(),
+                    refp,
                     w_scu as usize,
                     evc_tbl_qp_chroma_dynamic_ext,
+                    addb,
+                    sh_alpha_offset,
+                    sh_beta_offset,
                     w as usize,
+                    filter_left,
+                    filter_right,
                 );
             }
         }
@@ -300,18 +378,47 @@ pub(crate) fn evc_deblock_cu_hor(
     map_scu: &mut [MCU],
     map_refi: &Vec<[i8; REFP_NUM]>,
     map_mv: &Vec<[[i16; MV_D]; REFP_NUM]>,
+    refp: &[Vec<EvcRefP>],
     w_scu: usize,
     evc_tbl_qp_chroma_dynamic_ext: &Vec<Vec<i8>>,
+    addb: bool,
+    sh_alpha_offset: i8,
+    sh_beta_offset: i8,
+    filter_top: bool,
 ) {
     let w = cuw >> MIN_CU_LOG2;
     let h = cuh >> MIN_CU_LOG2;
     let offset = (x_pel >> MIN_CU_LOG2) + (y_pel >> MIN_CU_LOG2) * w_scu;
 
-    /* horizontal filtering */
-    if y_pel > 0 {
-        let planes = &mut pic.frame.borrow_mut().planes;
+    /* horizontal filtering, only on the 8x8 grid with the advanced filter */
+    if y_pel > 0 && filter_top && (!addb || y_pel & 7 == 0) {
+        let mut frame = pic.frame.write().unwrap();
+        let chroma_sampling = frame.chroma_sampling;
+        let chroma = chroma_sampling != ChromaSampling::Cs400;
+        let (w_shift, h_shift) = chroma_sampling.get_decimation();
+        let planes = &mut frame.planes;
 
         for i in 0..w {
+            if addb {
+                evc_deblock_addb_scu(
+                    tracer,
+                    planes,
+                    pic,
+                    x_pel + (i << MIN_CU_LOG2),
+                    y_pel,
+                    true,
+                    [map_scu[offset + i], map_scu[offset + i - w_scu]],
+                    [&map_refi[offset + i], &map_refi[offset + i - w_scu]],
+                    [&map_mv[offset + i], &map_mv[offset + i - w_scu]],
+                    refp,
+                    sh_alpha_offset,
+                    sh_beta_offset,
+                    evc_tbl_qp_chroma_dynamic_ext,
+                    chroma_sampling,
+                );
+                continue;
+            }
+
             let tbl_qp_to_st = evc_get_tbl_qp_to_st(
                 map_scu[offset + i],
                 map_scu[offset + i - w_scu],
@@ -338,6 +445,10 @@ pub(crate) fn evc_deblock_cu_hor(
                 tbl_qp_to_st,
             );
 
+            if !chroma {
+                continue;
+            }
+
             let qp_u = EVC_CLIP3(
                 -6 * (BIT_DEPTH as i8 - 8),
                 57,
@@ -349,15 +460,16 @@ pub(crate) fn evc_deblock_cu_hor(
                 qp as i8 + pic.pic_qp_v_offset,
             );
             let area_chroma = Area::Rect {
-                x: (x_pel as isize + t) >> 1,
-                y: (y_pel as isize >> 1) - 2,
-                width: MIN_CU_SIZE >> 1,
+                x: (x_pel as isize + t) >> w_shift,
+                y: (y_pel as isize >> h_shift) - 2,
+                width: MIN_CU_SIZE >> w_shift,
                 height: MIN_CU_SIZE,
             };
 
             deblock_scu_hor_chroma(
                 tracer,
                 &mut planes[U_C].as_region_mut().subregion_mut(area_chroma),
+                MIN_CU_SIZE >> w_shift,
                 evc_tbl_qp_chroma_dynamic_ext[0][(EVC_TBL_CHROMA_QP_OFFSET + qp_u) as usize]
                     as usize,
                 U_C,
@@ -366,6 +478,7 @@ pub(crate) fn evc_deblock_cu_hor(
             deblock_scu_hor_chroma(
                 tracer,
                 &mut planes[V_C].as_region_mut().subregion_mut(area_chroma),
+                MIN_CU_SIZE >> w_shift,
                 evc_tbl_qp_chroma_dynamic_ext[1][(EVC_TBL_CHROMA_QP_OFFSET + qp_v) as usize]
                     as usize,
                 V_C,
@@ -391,18 +504,57 @@ pub(crate) fn evc_deblock_cu_ver(
     map_scu: &mut [MCU],
     map_refi: &Vec<[i8; REFP_NUM]>,
     map_mv: &Vec<[[i16; MV_D]; REFP_NUM]>,
+    refp: &[Vec<EvcRefP>],
     w_scu: usize,
     evc_tbl_qp_chroma_dynamic_ext: &Vec<Vec<i8>>,
+    addb: bool,
+    sh_alpha_offset: i8,
+    sh_beta_offset: i8,
     pic_w: usize,
+    filter_left: bool,
+    filter_right: bool,
 ) {
     let w = cuw >> MIN_CU_LOG2;
     let h = cuh >> MIN_CU_LOG2;
     let offset = (x_pel >> MIN_CU_LOG2) + (y_pel >> MIN_CU_LOG2) * w_scu;
-    let planes = &mut pic.frame.borrow_mut().planes;
-
-    /* vertical filtering */
-    if x_pel > 0 && map_scu[offset - 1].GET_COD() != 0 {
+    let mut frame = pic.frame.write().unwrap();
+    let chroma_sampling = frame.chroma_sampling;
+    let chroma = chroma_sampling != ChromaSampling::Cs400;
+    let (w_shift, h_shift) = chroma_sampling.get_decimation();
+    let planes = &mut frame.planes;
+
+    /* vertical filtering, only on the 8x8 grid with the advanced filter */
+    if x_pel > 0 && filter_left && map_scu[offset - 1].GET_COD() != 0 && (!addb || x_pel & 7 == 0) {
         for j in 0..h {
+            if addb {
+                evc_deblock_addb_scu(
+                    tracer,
+                    planes,
+                    pic,
+                    x_pel,
+                    y_pel + (j << MIN_CU_LOG2),
+                    false,
+                    [
+                        map_scu[offset + j * w_scu + 0],
+                        map_scu[offset + j * w_scu - 1],
+                    ],
+                    [
+                        &map_refi[offset + j * w_scu + 0],
+                        &map_refi[offset + j * w_scu - 1],
+                    ],
+                    [
+                        &map_mv[offset + j * w_scu + 0],
+                        &map_mv[offset + j * w_scu - 1],
+                    ],
+                    refp,
+                    sh_alpha_offset,
+                    sh_beta_offset,
+                    evc_tbl_qp_chroma_dynamic_ext,
+                    chroma_sampling,
+                );
+                continue;
+            }
+
             let tbl_qp_to_st = evc_get_tbl_qp_to_st(
                 map_scu[offset + j * w_scu + 0],
                 map_scu[offset + j * w_scu - 1],
@@ -429,6 +581,10 @@ pub(crate) fn evc_deblock_cu_ver(
                 tbl_qp_to_st,
             );
 
+            if !chroma {
+                continue;
+            }
+
             let qp_u = EVC_CLIP3(
                 -6 * (BIT_DEPTH as i8 - 8),
                 57,
@@ -440,15 +596,16 @@ pub(crate) fn evc_deblock_cu_ver(
                 qp as i8 + pic.pic_qp_v_offset,
             );
             let area_chroma = Area::Rect {
-                x: (x_pel as isize >> 1) - 2,
-                y: (y_pel as isize + t) >> 1,
+                x: (x_pel as isize >> w_shift) - 2,
+                y: (y_pel as isize + t) >> h_shift,
                 width: MIN_CU_SIZE,
-                height: MIN_CU_SIZE >> 1,
+                height: MIN_CU_SIZE >> h_shift,
             };
 
             deblock_scu_ver_chroma(
                 tracer,
                 &mut planes[U_C].as_region_mut().subregion_mut(area_chroma),
+                MIN_CU_SIZE >> h_shift,
                 evc_tbl_qp_chroma_dynamic_ext[0][(EVC_TBL_CHROMA_QP_OFFSET + qp_u) as usize]
                     as usize,
                 U_C,
@@ -457,6 +614,7 @@ pub(crate) fn evc_deblock_cu_ver(
             deblock_scu_ver_chroma(
                 tracer,
                 &mut planes[V_C].as_region_mut().subregion_mut(area_chroma),
+                MIN_CU_SIZE >> h_shift,
                 evc_tbl_qp_chroma_dynamic_ext[0][(EVC_TBL_CHROMA_QP_OFFSET + qp_v) as usize]
                     as usize,
                 V_C,
@@ -465,8 +623,41 @@ pub(crate) fn evc_deblock_cu_ver(
         }
     }
 
-    if x_pel + cuw < pic_w && map_scu[offset + w].GET_COD() != 0 {
+    if x_pel + cuw < pic_w
+        && filter_right
+        && map_scu[offset + w].GET_COD() != 0
+        && (!addb || (x_pel + cuw) & 7 == 0)
+    {
         for j in 0..h {
+            if addb {
+                evc_deblock_addb_scu(
+                    tracer,
+                    planes,
+                    pic,
+                    x_pel + cuw,
+                    y_pel + (j << MIN_CU_LOG2),
+                    false,
+                    [
+                        map_scu[offset + j * w_scu + w],
+                        map_scu[offset + j * w_scu + w - 1],
+                    ],
+                    [
+                        &map_refi[offset + j * w_scu + w],
+                        &map_refi[offset + j * w_scu + w - 1],
+                    ],
+                    [
+                        &map_mv[offset + j * w_scu + w],
+                        &map_mv[offset + j * w_scu + w - 1],
+                    ],
+                    refp,
+                    sh_alpha_offset,
+                    sh_beta_offset,
+                    evc_tbl_qp_chroma_dynamic_ext,
+                    chroma_sampling,
+                );
+                continue;
+            }
+
             let tbl_qp_to_st = evc_get_tbl_qp_to_st(
                 map_scu[offset + j * w_scu + w],
                 map_scu[offset + j * w_scu + w - 1],
@@ -493,6 +684,10 @@ pub(crate) fn evc_deblock_cu_ver(
                 tbl_qp_to_st,
             );
 
+            if !chroma {
+                continue;
+            }
+
             let qp_u = EVC_CLIP3(
                 -6 * (BIT_DEPTH as i8 - 8),
                 57,
@@ -504,15 +699,16 @@ pub(crate) fn evc_deblock_cu_ver(
                 qp as i8 + pic.pic_qp_v_offset,
             );
             let area_chroma = Area::Rect {
-                x: ((x_pel as isize + cuw as isize) >> 1) - 2,
-                y: (y_pel as isize + t) >> 1,
+                x: ((x_pel as isize + cuw as isize) >> w_shift) - 2,
+                y: (y_pel as isize + t) >> h_shift,
                 width: MIN_CU_SIZE,
-                height: MIN_CU_SIZE >> 1,
+                height: MIN_CU_SIZE >> h_shift,
             };
 
             deblock_scu_ver_chroma(
                 tracer,
                 &mut planes[U_C].as_region_mut().subregion_mut(area_chroma),
+                MIN_CU_SIZE >> h_shift,
                 evc_tbl_qp_chroma_dynamic_ext[0][(EVC_TBL_CHROMA_QP_OFFSET + qp_u) as usize]
                     as usize,
                 U_C,
@@ -521,6 +717,7 @@ pub(crate) fn evc_deblock_cu_ver(
             deblock_scu_ver_chroma(
                 tracer,
                 &mut planes[V_C].as_region_mut().subregion_mut(area_chroma),
+                MIN_CU_SIZE >> h_shift,
                 evc_tbl_qp_chroma_dynamic_ext[0][(EVC_TBL_CHROMA_QP_OFFSET + qp_v) as usize]
                     as usize,
                 V_C,
@@ -536,6 +733,18 @@ pub(crate) fn evc_deblock_cu_ver(
     }
 }
 
+/* check whether two pixel positions are in the same tile, which is always the
+case when the deblocking filter runs across tile boundaries */
+fn evc_same_tile(map_tidx: Option<&[u16]>, w_lcu: u16, x0: u16, y0: u16, x1: u16, y1: u16) -> bool {
+    if let Some(map_tidx) = map_tidx {
+        let lcu0 = (x0 >> MAX_CU_LOG2) + (y0 >> MAX_CU_LOG2) * w_lcu;
+        let lcu1 = (x1 >> MAX_CU_LOG2) + (y1 >> MAX_CU_LOG2) * w_lcu;
+        map_tidx[lcu0 as usize] == map_tidx[lcu1 as usize]
+    } else {
+        true
+    }
+}
+
 fn evc_get_tbl_qp_to_st(
     mcu0: MCU,
     mcu1: MCU,
@@ -553,57 +762,361 @@ fn evc_get_tbl_qp_to_st(
     //} //else if mcu0.GET_IBC() || mcu1.GET_IBC() {
     //    idx = 2;
     } else {
-        let mut mv0_l0 = [mv0[REFP_0][MV_X], mv0[REFP_0][MV_Y]];
-        let mut mv0_l1 = [mv0[REFP_1][MV_X], mv0[REFP_1][MV_Y]];
-        let mut mv1_l0 = [mv1[REFP_0][MV_X], mv1[REFP_0][MV_Y]];
-        let mut mv1_l1 = [mv1[REFP_1][MV_X], mv1[REFP_1][MV_Y]];
-
-        if !REFI_IS_VALID(refi0[REFP_0]) {
-            mv0_l0[0] = 0;
-            mv0_l0[1] = 0;
+        idx = if evc_is_motion_diff(refi0, refi1, mv0, mv1) {
+            2
+        } else {
+            3
+        };
+    }
+
+    return &evc_tbl_df_st[idx];
+}
+
+/* whether two blocks are predicted from different reference pictures or
+with motion vectors differing by an integer sample or more */
+fn evc_is_motion_diff(
+    refi0: &[i8],
+    refi1: &[i8],
+    mv0: &[[i16; MV_D]; REFP_NUM],
+    mv1: &[[i16; MV_D]; REFP_NUM],
+) -> bool {
+    let get_mv = |refi: &[i8], mv: &[[i16; MV_D]; REFP_NUM], lidx: usize| {
+        if REFI_IS_VALID(refi[lidx]) {
+            mv[lidx]
+        } else {
+            [0; MV_D]
         }
+    };
+    let mv_diff = |a: [i16; MV_D], b: [i16; MV_D]| {
+        (a[MV_X] - b[MV_X]).abs() >= 4 || (a[MV_Y] - b[MV_Y]).abs() >= 4
+    };
+    let (mv0_l0, mv0_l1) = (get_mv(refi0, mv0, REFP_0), get_mv(refi0, mv0, REFP_1));
+    let (mv1_l0, mv1_l1) = (get_mv(refi1, mv1, REFP_0), get_mv(refi1, mv1, REFP_1));
+
+    if (refi0[REFP_0] == refi1[REFP_0]) && (refi0[REFP_1] == refi1[REFP_1]) {
+        mv_diff(mv0_l0, mv1_l0) || mv_diff(mv0_l1, mv1_l1)
+    } else if (refi0[REFP_0] == refi1[REFP_1]) && (refi0[REFP_1] == refi1[REFP_0]) {
+        mv_diff(mv0_l0, mv1_l1) || mv_diff(mv0_l1, mv1_l0)
+    } else {
+        true
+    }
+}
 
-        if !REFI_IS_VALID(refi0[REFP_1]) {
-            mv0_l1[0] = 0;
-            mv0_l1[1] = 0;
+/* boundary strength of the advanced deblocking filter, intra boundaries on
+the 64x64 grid are filtered with the strong filter */
+fn evc_get_addb_bs(
+    mcu0: MCU,
+    mcu1: MCU,
+    refi0: &[i8],
+    refi1: &[i8],
+    mv0: &[[i16; MV_D]; REFP_NUM],
+    mv1: &[[i16; MV_D]; REFP_NUM],
+    refp: &[Vec<EvcRefP>],
+    edge: usize,
+) -> u8 {
+    if mcu0.GET_IF() != 0 || mcu1.GET_IF() != 0 {
+        if edge & (MAX_CU_SIZE - 1) == 0 {
+            DBF_ADDB_BS_INTRA_STRONG
+        } else {
+            DBF_ADDB_BS_INTRA
+        }
+    } else if mcu0.GET_ATS_INTER() != 0 || mcu1.GET_ATS_INTER() != 0 {
+        DBF_ADDB_BS_CODED
+    } else if mcu0.GET_CBFL() == 1 || mcu1.GET_CBFL() == 1 {
+        DBF_ADDB_BS_CODED
+    } else if evc_is_addb_motion_diff(refi0, refi1, mv0, mv1, refp) {
+        DBF_ADDB_BS_MV
+    } else {
+        DBF_ADDB_BS_OTHERS
+    }
+}
+
+/* whether two blocks are predicted from different reference pictures or with
+motion vectors differing by an integer sample or more, the pictures rather than
+the reference indices are compared. blocks predicted twice from the same
+picture are compared in both pairings of their motion vectors */
+fn evc_is_addb_motion_diff(
+    refi0: &[i8],
+    refi1: &[i8],
+    mv0: &[[i16; MV_D]; REFP_NUM],
+    mv1: &[[i16; MV_D]; REFP_NUM],
+    refp: &[Vec<EvcRefP>],
+) -> bool {
+    let get_pic = |refi: &[i8], lidx: usize| {
+        if REFI_IS_VALID(refi[lidx]) {
+            refp[refi[lidx] as usize][lidx]
+                .pic
+                .as_ref()
+                .map(Arc::as_ptr)
+        } else {
+            None
         }
+    };
+    let get_mv = |refi: &[i8], mv: &[[i16; MV_D]; REFP_NUM], lidx: usize| {
+        if REFI_IS_VALID(refi[lidx]) {
+            mv[lidx]
+        } else {
+            [0; MV_D]
+        }
+    };
+    let mv_diff = |a: [i16; MV_D], b: [i16; MV_D]| {
+        (a[MV_X] - b[MV_X]).abs() >= 4 || (a[MV_Y] - b[MV_Y]).abs() >= 4
+    };
+    let (pic0_l0, pic0_l1) = (get_pic(refi0, REFP_0), get_pic(refi0, REFP_1));
+    let (pic1_l0, pic1_l1) = (get_pic(refi1, REFP_0), get_pic(refi1, REFP_1));
+    let (mv0_l0, mv0_l1) = (get_mv(refi0, mv0, REFP_0), get_mv(refi0, mv0, REFP_1));
+    let (mv1_l0, mv1_l1) = (get_mv(refi1, mv1, REFP_0), get_mv(refi1, mv1, REFP_1));
+    let straight = || mv_diff(mv0_l0, mv1_l0) || mv_diff(mv0_l1, mv1_l1);
+    let cross = || mv_diff(mv0_l0, mv1_l1) || mv_diff(mv0_l1, mv1_l0);
+
+    if pic0_l0 == pic1_l0 && pic0_l1 == pic1_l1 {
+        if pic0_l0 != pic0_l1 {
+            straight()
+        } else {
+            straight() || cross()
+        }
+    } else if pic0_l0 == pic1_l1 && pic0_l1 == pic1_l0 {
+        cross()
+    } else {
+        true
+    }
+}
+
+/* alpha, beta and the clipping value of the advanced deblocking filter. the
+slice offsets are added as unsigned bytes like in the reference decoder, so a
+negative offset selects the last entry of the tables */
+fn evc_get_addb_alpha_beta_c0(
+    qp: i32,
+    bs: u8,
+    sh_alpha_offset: i8,
+    sh_beta_offset: i8,
+) -> (i16, i16, i16) {
+    let max_idx = evc_tbl_addb_alpha.len() - 1;
+    let idx_a = min(max_idx, qp as u8 as usize + sh_alpha_offset as u8 as usize);
+    let idx_b = min(max_idx, qp as u8 as usize + sh_beta_offset as u8 as usize);
+
+    (
+        (evc_tbl_addb_alpha[idx_a] as i16) << 2,
+        (evc_tbl_addb_beta[idx_b] as i16) << 2,
+        (evc_tbl_addb_clip[idx_a][bs as usize] as i16) << 1,
+    )
+}
 
-        if !REFI_IS_VALID(refi1[REFP_0]) {
-            mv1_l0[0] = 0;
-            mv1_l0[1] = 0;
+/* advanced deblocking across the top (is_hor_edge) or the left edge of a 4x4
+block at (x, y) */
+fn evc_deblock_addb_scu(
+    tracer: &mut Option<Tracer>,
+    planes: &mut [Plane<pel>; N_C],
+    pic: &EvcPic,
+    x: usize,
+    y: usize,
+    is_hor_edge: bool,
+    mcu: [MCU; 2],
+    refi: [&[i8; REFP_NUM]; 2],
+    mv: [&[[i16; MV_D]; REFP_NUM]; 2],
+    refp: &[Vec<EvcRefP>],
+    sh_alpha_offset: i8,
+    sh_beta_offset: i8,
+    evc_tbl_qp_chroma_dynamic_ext: &Vec<Vec<i8>>,
+    chroma_sampling: ChromaSampling,
+) {
+    let bs = evc_get_addb_bs(
+        mcu[0],
+        mcu[1],
+        refi[0],
+        refi[1],
+        mv[0],
+        mv[1],
+        refp,
+        if is_hor_edge { y } else { x },
+    );
+    if bs == DBF_ADDB_BS_OTHERS {
+        return;
+    }
+
+    let qp = ((mcu[0].GET_QP() + mcu[1].GET_QP() + 1) >> 1) as i8;
+    let (alpha, beta, c0) =
+        evc_get_addb_alpha_beta_c0(qp as i32, bs, sh_alpha_offset, sh_beta_offset);
+    let area_luma = if is_hor_edge {
+        Area::Rect {
+            x: x as isize,
+            y: y as isize - 4,
+            width: MIN_CU_SIZE,
+            height: 8,
+        }
+    } else {
+        Area::Rect {
+            x: x as isize - 4,
+            y: y as isize,
+            width: 8,
+            height: MIN_CU_SIZE,
         }
+    };
+    deblock_addb_scu_luma(
+        tracer,
+        &mut planes[Y_C].as_region_mut().subregion_mut(area_luma),
+        is_hor_edge,
+        bs,
+        alpha,
+        beta,
+        c0,
+    );
+
+    if chroma_sampling == ChromaSampling::Cs400 {
+        return;
+    }
+    let (w_shift, h_shift) = chroma_sampling.get_decimation();
+    for (ch_type, qp_offset) in [(U_C, pic.pic_qp_u_offset), (V_C, pic.pic_qp_v_offset)] {
+        let qp_c = EVC_CLIP3(-6 * (BIT_DEPTH as i8 - 8), 57, qp + qp_offset);
+        let qp_c =
+            evc_tbl_qp_chroma_dynamic_ext[ch_type - 1][(EVC_TBL_CHROMA_QP_OFFSET + qp_c) as usize];
+        let (alpha, beta, c0) =
+            evc_get_addb_alpha_beta_c0(qp_c as i32, bs, sh_alpha_offset, sh_beta_offset);
+        let (area_chroma, size) = if is_hor_edge {
+            (
+                Area::Rect {
+                    x: x as isize >> w_shift,
+                    y: (y as isize >> h_shift) - 2,
+                    width: MIN_CU_SIZE >> w_shift,
+                    height: 4,
+                },
+                MIN_CU_SIZE >> w_shift,
+            )
+        } else {
+            (
+                Area::Rect {
+                    x: (x as isize >> w_shift) - 2,
+                    y: y as isize >> h_shift,
+                    width: 4,
+                    height: MIN_CU_SIZE >> h_shift,
+                },
+                MIN_CU_SIZE >> h_shift,
+            )
+        };
+        deblock_addb_scu_chroma(
+            tracer,
+            &mut planes[ch_type].as_region_mut().subregion_mut(area_chroma),
+            size,
+            ch_type,
+            is_hor_edge,
+            bs,
+            alpha,
+            beta,
+            c0 + 2,
+        );
+    }
+}
 
-        if !REFI_IS_VALID(refi1[REFP_1]) {
-            mv1_l1[0] = 0;
-            mv1_l1[1] = 0;
+/* luma samples p3..p0 and q0..q3 of each of the lines across a 4x4 block
+edge, the lines with a step below alpha and flat sides below beta are filtered
+with the normal filter or, on the strong intra boundaries, smoothed */
+fn deblock_addb_scu_luma(
+    tracer: &mut Option<Tracer>,
+    buf: &mut PlaneRegionMut<'_, pel>,
+    is_hor_edge: bool,
+    bs: u8,
+    alpha: i16,
+    beta: i16,
+    c0: i16,
+) {
+    for k in 0..MIN_CU_SIZE {
+        let mut l = [0i16; 8];
+        for (n, s) in l.iter_mut().enumerate() {
+            *s = if is_hor_edge { buf[n][k] } else { buf[k][n] } as i16;
         }
 
-        if (refi0[REFP_0] == refi1[REFP_0]) && (refi0[REFP_1] == refi1[REFP_1]) {
-            idx = if (mv0_l0[MV_X] - mv1_l0[MV_X]).abs() >= 4
-                || (mv0_l0[MV_Y] - mv1_l0[MV_Y]).abs() >= 4
-                || (mv0_l1[MV_X] - mv1_l1[MV_X]).abs() >= 4
-                || (mv0_l1[MV_Y] - mv1_l1[MV_Y]).abs() >= 4
-            {
-                2
+        let [p3, p2, p1, p0, q0, q1, q2, q3] = l;
+        if (p0 - q0).abs() >= alpha || (p1 - p0).abs() >= beta || (q1 - q0).abs() >= beta {
+            continue;
+        }
+        let filter_p = (p2 - p0).abs() < beta;
+        let filter_q = (q2 - q0).abs() < beta;
+
+        if bs == DBF_ADDB_BS_INTRA_STRONG {
+            let small_gap = (p0 - q0).abs() <= (alpha >> 2) + 1;
+            if filter_p && small_gap {
+                l[1] = (2 * p3 + 3 * p2 + p1 + p0 + q0 + 4) >> 3;
+                l[2] = (p2 + p1 + p0 + q0 + 2) >> 2;
+                l[3] = (p2 + 2 * p1 + 2 * p0 + 2 * q0 + q1 + 4) >> 3;
             } else {
-                3
-            };
-        } else if (refi0[REFP_0] == refi1[REFP_1]) && (refi0[REFP_1] == refi1[REFP_0]) {
-            idx = if (mv0_l0[MV_X] - mv1_l1[MV_X]).abs() >= 4
-                || (mv0_l0[MV_Y] - mv1_l1[MV_Y]).abs() >= 4
-                || (mv0_l1[MV_X] - mv1_l0[MV_X]).abs() >= 4
-                || (mv0_l1[MV_Y] - mv1_l0[MV_Y]).abs() >= 4
-            {
-                2
+                l[3] = (2 * p1 + p0 + q1 + 2) >> 2;
+            }
+            if filter_q && small_gap {
+                l[4] = (p1 + 2 * p0 + 2 * q0 + 2 * q1 + q2 + 4) >> 3;
+                l[5] = (p0 + q0 + q1 + q2 + 2) >> 2;
+                l[6] = (2 * q3 + 3 * q2 + q1 + q0 + p0 + 4) >> 3;
             } else {
-                3
-            };
+                l[4] = (2 * q1 + q0 + p1 + 2) >> 2;
+            }
         } else {
-            idx = 2;
+            let c = c0 + 2 * (filter_p as i16 + filter_q as i16);
+            let delta = EVC_CLIP3(-c, c, (((q0 - p0) << 2) + p1 - q1 + 4) >> 3);
+            l[3] = p0 + delta;
+            l[4] = q0 - delta;
+            if filter_p {
+                l[2] = p1 + EVC_CLIP3(-c0, c0, (3 * (p2 + p0 + q0) - 8 * p1 - q1) >> 4);
+            }
+            if filter_q {
+                l[5] = q1 + EVC_CLIP3(-c0, c0, (3 * (q2 + q0 + p0) - 8 * q1 - p1) >> 4);
+            }
+        }
+
+        for (n, &s) in l.iter().enumerate() {
+            let s = EVC_CLIP3(0, MAX_SAMPLE_VAL_I16, s) as pel;
+            if is_hor_edge {
+                buf[n][k] = s;
+            } else {
+                buf[k][n] = s;
+            }
         }
     }
+    TRACE_DBF(tracer, Y_C, MIN_CU_SIZE, 8, is_hor_edge, buf);
+}
 
-    return &evc_tbl_df_st[idx];
+/* chroma samples p0 and q0 of each of the lines across the edge */
+fn deblock_addb_scu_chroma(
+    tracer: &mut Option<Tracer>,
+    buf: &mut PlaneRegionMut<'_, pel>,
+    size: usize,
+    ch_type: usize,
+    is_hor_edge: bool,
+    bs: u8,
+    alpha: i16,
+    beta: i16,
+    c: i16,
+) {
+    for k in 0..size {
+        let get = |buf: &PlaneRegionMut<'_, pel>, n: usize| {
+            if is_hor_edge {
+                buf[n][k] as i16
+            } else {
+                buf[k][n] as i16
+            }
+        };
+        let (p1, p0, q0, q1) = (get(buf, 0), get(buf, 1), get(buf, 2), get(buf, 3));
+        if (p0 - q0).abs() >= alpha || (p1 - p0).abs() >= beta || (q1 - q0).abs() >= beta {
+            continue;
+        }
+
+        let (p0, q0) = if bs == DBF_ADDB_BS_INTRA_STRONG {
+            ((2 * p1 + p0 + q1 + 2) >> 2, (2 * q1 + q0 + p1 + 2) >> 2)
+        } else {
+            let delta = EVC_CLIP3(-c, c, (((q0 - p0) << 2) + p1 - q1 + 4) >> 3);
+            (p0 + delta, q0 - delta)
+        };
+        let (p0, q0) = (
+            EVC_CLIP3(0, MAX_SAMPLE_VAL_I16, p0) as pel,
+            EVC_CLIP3(0, MAX_SAMPLE_VAL_I16, q0) as pel,
+        );
+        if is_hor_edge {
+            buf[1][k] = p0;
+            buf[2][k] = q0;
+        } else {
+            buf[k][1] = p0;
+            buf[k][2] = q0;
+        }
+    }
+    TRACE_DBF(tracer, ch_type, size, 4, is_hor_edge, buf);
 }
 
 //TODO: evc_mc_l should be private, but in order to be visible for benchmark,
@@ -646,7 +1159,7 @@ pub fn deblock_scu_hor_luma(
             buf[2][i] = EVC_CLIP3(0, MAX_SAMPLE_VAL_I16, C) as pel;
             buf[3][i] = EVC_CLIP3(0, MAX_SAMPLE_VAL_I16, D) as pel;
         }
-        TRACE_DBF(tracer, ch_type, MIN_CU_SIZE, true, buf);
+        TRACE_DBF(tracer, ch_type, MIN_CU_SIZE, 4, true, buf);
     }
 }
 
@@ -655,13 +1168,14 @@ pub fn deblock_scu_hor_luma(
 pub fn deblock_scu_hor_chroma(
     tracer: &mut Option<Tracer>,
     buf: &mut PlaneRegionMut<'_, pel>,
+    size: usize,
     qp: usize,
     ch_type: usize,
     tbl_qp_to_st: &[u8],
 ) {
     let st = (tbl_qp_to_st[qp] as i16) << (BIT_DEPTH - 8);
     if st != 0 {
-        for i in 0..MIN_CU_SIZE >> 1 {
+        for i in 0..size {
             let (mut A, mut B, mut C, mut D) = (
                 buf[0][i] as i16,
                 buf[1][i] as i16,
@@ -684,7 +1198,7 @@ pub fn deblock_scu_hor_chroma(
             buf[1][i] = EVC_CLIP3(0, MAX_SAMPLE_VAL_I16, B) as pel;
             buf[2][i] = EVC_CLIP3(0, MAX_SAMPLE_VAL_I16, C) as pel;
         }
-        TRACE_DBF(tracer, ch_type, MIN_CU_SIZE >> 1, true, buf);
+        TRACE_DBF(tracer, ch_type, size, 4, true, buf);
     }
 }
 
@@ -729,7 +1243,7 @@ pub fn deblock_scu_ver_luma(
             buf[j][2] = EVC_CLIP3(0, MAX_SAMPLE_VAL_I16, C) as pel;
             buf[j][3] = EVC_CLIP3(0, MAX_SAMPLE_VAL_I16, D) as pel;
         }
-        TRACE_DBF(tracer, ch_type, MIN_CU_SIZE, false, buf);
+        TRACE_DBF(tracer, ch_type, MIN_CU_SIZE, 4, false, buf);
     }
 }
 
@@ -738,6 +1252,7 @@ pub fn deblock_scu_ver_luma(
 pub fn deblock_scu_ver_chroma(
     tracer: &mut Option<Tracer>,
     buf: &mut PlaneRegionMut<'_, pel>,
+    size: usize,
     qp: usize,
     ch_type: usize,
     tbl_qp_to_st: &[u8],
@@ -745,7 +1260,7 @@ pub fn deblock_scu_ver_chroma(
     let st = (tbl_qp_to_st[qp] as i16) << (BIT_DEPTH - 8);
 
     if st != 0 {
-        for j in 0..MIN_CU_SIZE >> 1 {
+        for j in 0..size {
             let (mut A, mut B, mut C, mut D) = (
                 buf[j][0] as i16,
                 buf[j][1] as i16,
@@ -768,6 +1283,58 @@ pub fn deblock_scu_ver_chroma(
             buf[j][1] = EVC_CLIP3(0, MAX_SAMPLE_VAL_I16, B) as pel;
             buf[j][2] = EVC_CLIP3(0, MAX_SAMPLE_VAL_I16, C) as pel;
         }
-        TRACE_DBF(tracer, ch_type, MIN_CU_SIZE >> 1, false, buf);
+        TRACE_DBF(tracer, ch_type, size, 4, false, buf);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn addb_alpha_beta_c0() {
+        assert_eq!(
+            evc_get_addb_alpha_beta_c0(32, DBF_ADDB_BS_CODED, 0, 0),
+            (32 << 2, 9 << 2, 2 << 1)
+        );
+        /* a negative offset wraps to a large index and selects the last entry */
+        assert_eq!(
+            evc_get_addb_alpha_beta_c0(32, DBF_ADDB_BS_CODED, 2, -2),
+            (40 << 2, 18 << 2, 2 << 1)
+        );
+    }
+
+    #[test]
+    fn addb_luma_step() {
+        let (alpha, beta, c0) = evc_get_addb_alpha_beta_c0(32, DBF_ADDB_BS_CODED, 0, 0);
+
+        let mut plane = Plane::wrap([[100, 100, 100, 100, 110, 110, 110, 110]; 4].concat(), 8);
+        deblock_addb_scu_luma(
+            &mut None,
+            &mut plane.as_region_mut(),
+            false,
+            DBF_ADDB_BS_CODED,
+            alpha,
+            beta,
+            c0,
+        );
+        assert_eq!(
+            plane.data_origin(),
+            [[100, 100, 101, 104, 106, 108, 110, 110]; 4].concat()
+        );
+
+        /* a step of alpha or more is an edge of the picture content */
+        let edge = [[100; 16], [100 + alpha as pel; 16]].concat();
+        let mut plane = Plane::wrap(edge.clone(), 4);
+        deblock_addb_scu_luma(
+            &mut None,
+            &mut plane.as_region_mut(),
+            true,
+            DBF_ADDB_BS_CODED,
+            alpha,
+            beta,
+            c0,
+        );
+        assert_eq!(plane.data_origin(), edge);
     }
 }
@@ -0,0 +1,518 @@
+use super::*;
+use crate::alf::*;
+use crate::plane::*;
+
+/* number of taps of the luma filter without the center one, the chroma
+filter uses the first ones */
+const ALF_NUM_TAPS: usize = MAX_NUM_ALF_LUMA_COEFF - 1;
+/* bits of the APS NALU and of the slice header fields, whatever the filters */
+const ALF_OVERHEAD_BITS: f64 = 80.0;
+/* approximate bits of an ALF CTU flag */
+const ALF_CTB_FLAG_BITS: f64 = 1.0;
+/* largest magnitude of a signalled coefficient */
+const ALF_MAX_COEFF: i32 = (1 << ALF_SHIFT) - 1;
+
+/* the distortions of the statistics are in 10 bits samples while the mode
+decision measures them in 8 bits ones */
+#[inline]
+fn alf_dist(d: f64) -> f64 {
+    d / (1 << ((BIT_DEPTH - 8) << 1)) as f64
+}
+
+/*****************************************************************************
+ * least squares statistics of a filter: correlation of the tap features, their
+ * correlation with the target and the energy of the target. The feature of a
+ * tap is the sum of its two samples minus twice the center sample, the target
+ * is the original sample minus the center sample, so that the filter output
+ * minus the center sample is the dot product of the coefficients with the
+ * features.
+ *****************************************************************************/
+#[derive(Clone, Copy, Default)]
+struct EvceAlfCov {
+    e: [[f64; ALF_NUM_TAPS]; ALF_NUM_TAPS],
+    y: [f64; ALF_NUM_TAPS],
+    pix_acc: f64,
+}
+
+impl EvceAlfCov {
+    fn add(&mut self, other: &EvceAlfCov) {
+        for i in 0..ALF_NUM_TAPS {
+            for j in 0..ALF_NUM_TAPS {
+                self.e[i][j] += other.e[i][j];
+            }
+            self.y[i] += other.y[i];
+        }
+        self.pix_acc += other.pix_acc;
+    }
+
+    /* only the upper triangle of the correlation is accumulated */
+    #[inline(always)]
+    fn accumulate(&mut self, feat: &[f64], target: f64) {
+        for i in 0..feat.len() {
+            for j in i..feat.len() {
+                self.e[i][j] += feat[i] * feat[j];
+            }
+            self.y[i] += feat[i] * target;
+        }
+        self.pix_acc += target * target;
+    }
+
+    fn symmetrize(&mut self) {
+        for i in 0..ALF_NUM_TAPS {
+            for j in 0..i {
+                self.e[i][j] = self.e[j][i];
+            }
+        }
+    }
+
+    /* coefficients minimizing the squared error, by gaussian elimination */
+    fn solve(&self, n: usize) -> [f64; ALF_NUM_TAPS] {
+        let mut a = self.e;
+        let mut b = self.y;
+        let mut c = [0f64; ALF_NUM_TAPS];
+
+        for i in 0..n {
+            let mut pivot = i;
+            for k in i + 1..n {
+                if a[k][i].abs() > a[pivot][i].abs() {
+                    pivot = k;
+                }
+            }
+            if a[pivot][i].abs() < 1e-9 {
+                continue;
+            }
+            a.swap(i, pivot);
+            b.swap(i, pivot);
+            for k in i + 1..n {
+                let r = a[k][i] / a[i][i];
+                for j in i..n {
+                    a[k][j] -= r * a[i][j];
+                }
+                b[k] -= r * b[i];
+            }
+        }
+        for i in (0..n).rev() {
+            if a[i][i].abs() < 1e-9 {
+                continue;
+            }
+            let mut v = b[i];
+            for j in i + 1..n {
+                v -= a[i][j] * c[j];
+            }
+            c[i] = v / a[i][i];
+        }
+
+        c
+    }
+
+    /* reduction of the squared error by a filter */
+    fn gain(&self, c: &[f64], n: usize) -> f64 {
+        let mut gain = 0.0;
+        for i in 0..n {
+            let mut ec = 0.0;
+            for j in 0..n {
+                ec += self.e[i][j] * c[j];
+            }
+            gain += c[i] * (2.0 * self.y[i] - ec);
+        }
+        gain
+    }
+
+    fn gain_quant(&self, coeff: &[i16], n: usize) -> f64 {
+        let mut c = [0f64; ALF_NUM_TAPS];
+        for i in 0..n {
+            c[i] = coeff[i] as f64 / (1 << ALF_SHIFT) as f64;
+        }
+        self.gain(&c, n)
+    }
+}
+
+fn evce_alf_quant(c: &[f64], n: usize) -> [i16; ALF_NUM_TAPS] {
+    let mut coeff = [0i16; ALF_NUM_TAPS];
+    for i in 0..n {
+        coeff[i] = EVC_CLIP3(
+            -ALF_MAX_COEFF,
+            ALF_MAX_COEFF,
+            (c[i] * (1 << ALF_SHIFT) as f64).round() as i32,
+        ) as i16;
+    }
+    coeff
+}
+
+/* statistics of each LCU of a component, per class for luma */
+fn evce_alf_stats(
+    org: &Plane<pel>,
+    rec: &Plane<pel>,
+    tiles: &[EvcTile],
+    loop_filter_across_tiles_enabled_flag: bool,
+    w_lcu: u16,
+    f_lcu: usize,
+    is_luma: bool,
+) -> Vec<Vec<EvceAlfCov>> {
+    let num_cls = if is_luma { MAX_NUM_ALF_CLASSES } else { 1 };
+    let mut stats = vec![vec![EvceAlfCov::default(); num_cls]; f_lcu];
+    let org = org.as_region();
+
+    evc_alf_for_each_lcu(
+        rec,
+        tiles,
+        loop_filter_across_tiles_enabled_flag,
+        w_lcu,
+        |lcu_num, x, y, blk| {
+            let stat = &mut stats[lcu_num];
+            let cls = if is_luma { blk.classify() } else { vec![] };
+            let w_cls = (blk.w + ALF_CLS_SIZE - 1) / ALF_CLS_SIZE;
+            let mut feat = [0f64; ALF_NUM_TAPS];
+
+            for j in 0..blk.h {
+                for i in 0..blk.w {
+                    let (ii, jj) = (i as isize, j as isize);
+                    let x0 = blk.p(ii, jj);
+                    let target = (org[y + j][x + i] as i32 - x0) as f64;
+                    let (cov, n) = if is_luma {
+                        let (class_idx, transpose_idx) =
+                            cls[(j / ALF_CLS_SIZE) * w_cls + i / ALF_CLS_SIZE];
+                        let perm = &evc_tbl_alf_transpose7[transpose_idx as usize];
+                        for (k, &(dx, dy)) in evc_tbl_alf_taps7.iter().enumerate() {
+                            feat[perm[k]] =
+                                (blk.p(ii + dx, jj + dy) + blk.p(ii - dx, jj - dy) - 2 * x0) as f64;
+                        }
+                        (&mut stat[class_idx as usize], ALF_NUM_TAPS)
+                    } else {
+                        for (k, &(dx, dy)) in evc_tbl_alf_taps5.iter().enumerate() {
+                            feat[k] =
+                                (blk.p(ii + dx, jj + dy) + blk.p(ii - dx, jj - dy) - 2 * x0) as f64;
+                        }
+                        (&mut stat[0], MAX_NUM_ALF_CHROMA_COEFF - 1)
+                    };
+                    cov.accumulate(&feat[..n], target);
+                }
+            }
+            for cov in stat.iter_mut() {
+                cov.symmetrize();
+            }
+        },
+    );
+
+    stats
+}
+
+#[inline]
+fn evce_alf_ue_bits(val: u32) -> u32 {
+    2 * (32 - (val + 1).leading_zeros()) - 1
+}
+
+#[inline]
+fn evce_alf_golomb_bits(coeff: i16, k: u8) -> u32 {
+    let val = coeff.unsigned_abs() as u32;
+    (val >> k) + 1 + k as u32 + (val != 0) as u32
+}
+
+/* golomb orders of the coefficients minimizing their bits, with these bits */
+fn evce_alf_golomb_order(coeff: &[[i16; ALF_NUM_TAPS]], is_luma: bool) -> ([u8; 3], u32) {
+    let (golomb_idx, max_golomb_idx, num_coeff) = if is_luma {
+        (&evc_tbl_alf_golomb_idx7[..], 3, MAX_NUM_ALF_LUMA_COEFF)
+    } else {
+        (&evc_tbl_alf_golomb_idx5[..], 2, MAX_NUM_ALF_CHROMA_COEFF)
+    };
+
+    let mut best = ([1u8; 3], u32::MAX);
+    for k_start in 1..=10u8 {
+        for incr in 0..1u8 << max_golomb_idx {
+            let mut k_min_tab = [0u8; 3];
+            let mut k_min = k_start;
+            for (idx, k) in k_min_tab[..max_golomb_idx].iter_mut().enumerate() {
+                k_min += (incr >> idx) & 1;
+                *k = k_min;
+            }
+
+            let mut bits = evce_alf_ue_bits(k_start as u32 - 1) + max_golomb_idx as u32;
+            for c in coeff {
+                for i in 0..num_coeff - 1 {
+                    bits += evce_alf_golomb_bits(c[i], k_min_tab[golomb_idx[i] as usize]);
+                }
+            }
+            if bits < best.1 {
+                best = (k_min_tab, bits);
+            }
+        }
+    }
+
+    best
+}
+
+/* luma filters of a picture: the filters, the filter of each class and the
+golomb orders of the coefficients */
+#[derive(Clone)]
+struct EvceAlfLuma {
+    coeff: Vec<[i16; ALF_NUM_TAPS]>,
+    filter_idx: [u8; MAX_NUM_ALF_CLASSES],
+    k_min_tab: [u8; 3],
+    bits: u32,
+}
+
+impl EvceAlfLuma {
+    /* a single filter leaving the samples unchanged */
+    fn identity() -> Self {
+        let coeff = vec![[0; ALF_NUM_TAPS]];
+        let (k_min_tab, bits) = evce_alf_golomb_order(&coeff, true);
+        EvceAlfLuma {
+            coeff,
+            filter_idx: [0; MAX_NUM_ALF_CLASSES],
+            k_min_tab,
+            bits: evce_alf_luma_bits(1, bits),
+        }
+    }
+
+    fn gain(&self, stat: &[EvceAlfCov]) -> f64 {
+        stat.iter()
+            .enumerate()
+            .map(|(c, cov)| cov.gain_quant(&self.coeff[self.filter_idx[c] as usize], ALF_NUM_TAPS))
+            .sum()
+    }
+}
+
+/* bits of the luma parameters, with the bits of the coefficients */
+fn evce_alf_luma_bits(num_filters: usize, coeff_bits: u32) -> u32 {
+    let mut bits = evce_alf_ue_bits(num_filters as u32 - 1) + 1;
+    if num_filters > 1 {
+        bits += MAX_NUM_ALF_CLASSES as u32 * (32 - (num_filters as u32 - 1).leading_zeros());
+        bits += 1; /* coeff_delta_pred_mode_flag */
+    }
+    bits + 2 /* fixed_filter_pattern and alf_coefficients_delta_flag */ + coeff_bits
+}
+
+/* derive the luma filters by merging the classes greedily, the number of
+filters is the one with the lowest rate-distortion cost */
+fn evce_alf_luma_design(stat: &[EvceAlfCov], lambda: f64) -> (EvceAlfLuma, f64) {
+    let err = |cov: &EvceAlfCov| -> f64 {
+        let c = cov.solve(ALF_NUM_TAPS);
+        cov.pix_acc - cov.gain(&c, ALF_NUM_TAPS)
+    };
+
+    let mut groups: Vec<(Vec<usize>, EvceAlfCov, f64)> = stat
+        .iter()
+        .enumerate()
+        .map(|(c, cov)| (vec![c], *cov, err(cov)))
+        .collect();
+
+    let mut best: Option<(EvceAlfLuma, f64)> = None;
+    loop {
+        /* rate-distortion cost of the current classes merging */
+        let mut luma = EvceAlfLuma {
+            coeff: Vec::with_capacity(groups.len()),
+            filter_idx: [0; MAX_NUM_ALF_CLASSES],
+            k_min_tab: [1; 3],
+            bits: 0,
+        };
+        let mut gain = 0.0;
+        for (f, (classes, cov, _)) in groups.iter().enumerate() {
+            let coeff = evce_alf_quant(&cov.solve(ALF_NUM_TAPS), ALF_NUM_TAPS);
+            gain += cov.gain_quant(&coeff, ALF_NUM_TAPS);
+            luma.coeff.push(coeff);
+            for &c in classes {
+                luma.filter_idx[c] = f as u8;
+            }
+        }
+        let (k_min_tab, coeff_bits) = evce_alf_golomb_order(&luma.coeff, true);
+        luma.k_min_tab = k_min_tab;
+        luma.bits = evce_alf_luma_bits(groups.len(), coeff_bits);
+
+        let cost = -alf_dist(gain) + lambda * luma.bits as f64;
+        if best.as_ref().map_or(true, |b| cost < b.1) {
+            best = Some((luma, cost));
+        }
+
+        if groups.len() == 1 {
+            break;
+        }
+
+        /* merge the two groups of classes increasing the error the least */
+        let mut merge = (0, 1, f64::MAX, EvceAlfCov::default(), 0.0);
+        for a in 0..groups.len() {
+            for b in a + 1..groups.len() {
+                let mut cov = groups[a].1;
+                cov.add(&groups[b].1);
+                let e = err(&cov);
+                let delta = e - groups[a].2 - groups[b].2;
+                if delta < merge.2 {
+                    merge = (a, b, delta, cov, e);
+                }
+            }
+        }
+        let (a, b, _, cov, e) = merge;
+        let (classes, _, _) = groups.remove(b);
+        groups[a].0.extend(classes);
+        groups[a].1 = cov;
+        groups[a].2 = e;
+    }
+
+    best.unwrap()
+}
+
+/* ALF parameters of a picture, with the LCUs filtered */
+pub(crate) struct EvceAlf {
+    pub(crate) param: EvcAlfParam,
+    /* golomb orders of the luma and chroma coefficients */
+    pub(crate) k_min_tab: [[u8; 3]; 2],
+    pub(crate) chroma_idc: u8,
+    pub(crate) map_flag: bool,
+    pub(crate) map_alf: Vec<[bool; N_C]>,
+}
+
+impl EvceAlf {
+    pub(crate) fn filter(&self) -> EvcAlfFilter {
+        let mut filter = EvcAlfFilter {
+            luma: Some(evc_alf_luma_coeff(&self.param)),
+            ..Default::default()
+        };
+        for i in 0..2 {
+            if self.chroma_idc & (1 << i) != 0 {
+                filter.chroma[i] = Some(evc_alf_chroma_coeff(&self.param));
+            }
+        }
+        filter
+    }
+}
+
+/* estimate the ALF parameters of a deblocked picture, none if filtering does
+not pay off. The CTU flags are only used with a single slice, as the flags
+of a slice are reset by the next one */
+pub(crate) fn evce_alf_estimate(
+    org: &Frame<pel>,
+    rec: &Frame<pel>,
+    tiles: &[EvcTile],
+    loop_filter_across_tiles_enabled_flag: bool,
+    w_lcu: u16,
+    f_lcu: usize,
+    lambda: f64,
+    dist_chroma_weight: &[f64; 2],
+) -> Option<EvceAlf> {
+    let use_map = tiles.len() == 1;
+
+    /* luma */
+    let stats = evce_alf_stats(
+        &org.planes[Y_C],
+        &rec.planes[Y_C],
+        tiles,
+        loop_filter_across_tiles_enabled_flag,
+        w_lcu,
+        f_lcu,
+        true,
+    );
+    let mut stat_pic = vec![EvceAlfCov::default(); MAX_NUM_ALF_CLASSES];
+    for stat in &stats {
+        for (c, cov) in stat.iter().enumerate() {
+            stat_pic[c].add(cov);
+        }
+    }
+
+    let (mut luma, mut luma_cost) = evce_alf_luma_design(&stat_pic, lambda);
+    let mut map_luma = vec![true; f_lcu];
+
+    if use_map {
+        /* switch off the LCUs not gaining from the filters and derive the
+        filters again from the remaining ones */
+        let mut map = vec![true; f_lcu];
+        let mut design = luma.clone();
+        for _ in 0..2 {
+            let mut gain = 0.0;
+            for (lcu_num, stat) in stats.iter().enumerate() {
+                let g = alf_dist(design.gain(stat));
+                map[lcu_num] = g > lambda * ALF_CTB_FLAG_BITS;
+                if map[lcu_num] {
+                    gain += g;
+                }
+            }
+            let cost = -gain + lambda * (design.bits as f64 + f_lcu as f64 * ALF_CTB_FLAG_BITS);
+            if cost < luma_cost {
+                luma = design.clone();
+                luma_cost = cost;
+                map_luma.clone_from(&map);
+            }
+
+            let mut stat_on = vec![EvceAlfCov::default(); MAX_NUM_ALF_CLASSES];
+            for (stat, _) in stats.iter().zip(&map).filter(|(_, &on)| on) {
+                for (c, cov) in stat.iter().enumerate() {
+                    stat_on[c].add(cov);
+                }
+            }
+            design = evce_alf_luma_design(&stat_on, lambda).0;
+        }
+    }
+
+    /* without luma filtering, an identity filter is signalled for chroma */
+    let mut map_flag = map_luma.iter().any(|&on| !on);
+    if luma_cost >= 0.0 {
+        luma = EvceAlfLuma::identity();
+        luma_cost = lambda * luma.bits as f64;
+        map_flag = false;
+        map_luma.fill(true);
+    }
+
+    /* chroma, a filter shared by the Cb and Cr components */
+    let n = MAX_NUM_ALF_CHROMA_COEFF - 1;
+    let mut chroma_coeff = [0; ALF_NUM_TAPS];
+    let mut k_min_chroma = [0; 3];
+    let mut chroma_idc = 0;
+    let mut chroma_cost = 0.0;
+    if org.chroma_sampling != ChromaSampling::Cs400 {
+        let mut stat_ch = [EvceAlfCov::default(); 2];
+        let mut stat_chroma = EvceAlfCov::default();
+        for (i, stat) in stat_ch.iter_mut().enumerate() {
+            for lcu_stat in evce_alf_stats(
+                &org.planes[U_C + i],
+                &rec.planes[U_C + i],
+                tiles,
+                loop_filter_across_tiles_enabled_flag,
+                w_lcu,
+                f_lcu,
+                false,
+            ) {
+                stat.add(&lcu_stat[0]);
+            }
+            stat_chroma.add(stat);
+        }
+        chroma_coeff = evce_alf_quant(&stat_chroma.solve(n), n);
+        let (k_min, chroma_bits) = evce_alf_golomb_order(&[chroma_coeff], false);
+        k_min_chroma = k_min;
+
+        let mut chroma_gain = 0.0;
+        for i in 0..2 {
+            let g = dist_chroma_weight[i] * alf_dist(stat_ch[i].gain_quant(&chroma_coeff, n));
+            if g > 0.0 {
+                chroma_idc |= 1 << i;
+                chroma_gain += g;
+            }
+        }
+        chroma_cost = -chroma_gain + lambda * chroma_bits as f64;
+        if chroma_idc == 0 || chroma_cost >= 0.0 {
+            chroma_idc = 0;
+            chroma_cost = 0.0;
+        }
+    }
+
+    if luma_cost + chroma_cost + lambda * ALF_OVERHEAD_BITS >= 0.0 {
+        return None;
+    }
+
+    let mut param = EvcAlfParam {
+        luma_signal_flag: true,
+        chroma_signal_flag: chroma_idc != 0,
+        num_luma_filters: luma.coeff.len() as u8,
+        filter_coeff_delta_idx: luma.filter_idx,
+        ..Default::default()
+    };
+    for (f, coeff) in luma.coeff.iter().enumerate() {
+        param.luma_coeff[f * MAX_NUM_ALF_LUMA_COEFF..][..ALF_NUM_TAPS].copy_from_slice(coeff);
+    }
+    param.chroma_coeff[..n].copy_from_slice(&chroma_coeff[..n]);
+
+    Some(EvceAlf {
+        param,
+        k_min_tab: [luma.k_min_tab, k_min_chroma],
+        chroma_idc,
+        map_flag,
+        map_alf: map_luma.iter().map(|&on| [on, true, true]).collect(),
+    })
+}
@@ -10,11 +10,20 @@ pub(crate) struct EvceBsw {
     leftbits: isize,
     /* buffer */
     pub(crate) pkt: Option<Packet>,
+    /* write start code prefixed NALUs with emulation prevention (Annex-B) */
+    pub(crate) annexb: bool,
     /* tracer */
     pub(crate) tracer: Option<Tracer>,
 }
 
 impl EvceBsw {
+    pub(crate) fn new(annexb: bool) -> Self {
+        EvceBsw {
+            annexb,
+            ..Default::default()
+        }
+    }
+
     /* is bitstream byte aligned? */
     #[inline]
     pub(crate) fn IS_BYTE_ALIGN(&self) -> bool {
@@ -66,6 +75,11 @@ impl EvceBsw {
     }
 
     pub(crate) fn write_nalu_size(&mut self) {
+        if self.annexb {
+            self.write_start_code();
+            return;
+        }
+
         let size = self.GET_WRITE_BYTE() - 4;
 
         if let Some(pkt) = &mut self.pkt {
@@ -76,6 +90,31 @@ impl EvceBsw {
         }
     }
 
+    /* replace the nalu size by a start code and insert emulation prevention
+    bytes, so that the payload never contains a start code */
+    fn write_start_code(&mut self) {
+        if let Some(pkt) = &mut self.pkt {
+            let mut data = Vec::with_capacity(pkt.data.len() + (pkt.data.len() >> 6));
+            data.extend_from_slice(&NALU_START_CODE);
+
+            let mut zeros = 0;
+            for &byte in &pkt.data[4..] {
+                if zeros >= 2 && byte <= 0x03 {
+                    data.push(NALU_EMULATION_PREVENTION_BYTE);
+                    zeros = 0;
+                }
+                data.push(byte);
+                zeros = if byte == 0 { zeros + 1 } else { 0 };
+            }
+            /* trailing zeros would merge with the next start code */
+            if zeros >= 2 {
+                data.push(NALU_EMULATION_PREVENTION_BYTE);
+            }
+
+            pkt.data = data;
+        }
+    }
+
     pub(crate) fn write1(&mut self, val: u32, name: Option<&str>) {
         if let Some(name) = name {
             EVC_TRACE(&mut self.tracer, name);
@@ -162,3 +201,51 @@ impl EvceBsw {
         self.write_ue(v as u32, None);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn annexb(payload: &[u8]) -> Vec<u8> {
+        let mut bs = EvceBsw::new(true);
+        bs.init();
+        if let Some(pkt) = &mut bs.pkt {
+            pkt.data = vec![0; 4];
+            pkt.data.extend_from_slice(payload);
+        }
+        bs.write_nalu_size();
+        bs.pkt.unwrap().data
+    }
+
+    #[test]
+    fn emulation_prevention_insert() {
+        assert_eq!(annexb(&[0x12, 0x34]), [0, 0, 0, 1, 0x12, 0x34]);
+        for byte in 0..=3 {
+            assert_eq!(
+                annexb(&[0x12, 0, 0, byte, 0x34]),
+                [0, 0, 0, 1, 0x12, 0, 0, 0x03, byte, 0x34]
+            );
+        }
+        assert_eq!(annexb(&[0x12, 0, 0, 0x04]), [0, 0, 0, 1, 0x12, 0, 0, 0x04]);
+        /* the zero counter restarts after an inserted byte */
+        assert_eq!(
+            annexb(&[0x12, 0, 0, 0, 0, 0x34]),
+            [0, 0, 0, 1, 0x12, 0, 0, 0x03, 0, 0, 0x34]
+        );
+        assert_eq!(
+            annexb(&[0x12, 0, 0x01, 0, 0x34]),
+            [0, 0, 0, 1, 0x12, 0, 0x01, 0, 0x34]
+        );
+    }
+
+    #[test]
+    fn emulation_prevention_trailing_zeros() {
+        assert_eq!(annexb(&[0x12, 0]), [0, 0, 0, 1, 0x12, 0]);
+        assert_eq!(annexb(&[0x12, 0, 0]), [0, 0, 0, 1, 0x12, 0, 0, 0x03]);
+        assert_eq!(annexb(&[0x12, 0, 0, 0]), [0, 0, 0, 1, 0x12, 0, 0, 0x03, 0]);
+        assert_eq!(
+            annexb(&[0x12, 0, 0, 0, 0]),
+            [0, 0, 0, 1, 0x12, 0, 0, 0x03, 0, 0, 0x03]
+        );
+    }
+}
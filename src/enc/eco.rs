@@ -3,6 +3,7 @@ use super::sbac::*;
 use super::util::*;
 use crate::api::*;
 use crate::def::*;
+use crate::ipred::*;
 use crate::tbl::*;
 use crate::tracer::*;
 use crate::util::*;
@@ -66,11 +67,65 @@ pub(crate) fn evce_eco_sps(bs: &mut EvceBsw, sps: &EvcSps) {
         Some("sps->bit_depth_chroma_minus8"),
     );
     bs.write1(sps.sps_btt_flag as u32, Some("sps->sps_btt_flag"));
+    if sps.sps_btt_flag {
+        bs.write_ue(
+            sps.log2_ctu_size_minus5 as u32,
+            Some("sps->log2_ctu_size_minus5"),
+        );
+        bs.write_ue(
+            sps.log2_min_cb_size_minus2 as u32,
+            Some("sps->log2_min_cb_size_minus2"),
+        );
+        bs.write_ue(
+            sps.log2_diff_ctu_max_14_cb_size as u32,
+            Some("sps->log2_diff_ctu_max_14_cb_size"),
+        );
+        bs.write_ue(
+            sps.log2_diff_ctu_max_tt_cb_size as u32,
+            Some("sps->log2_diff_ctu_max_tt_cb_size"),
+        );
+        bs.write_ue(
+            sps.log2_diff_min_cb_min_tt_cb_size_minus2 as u32,
+            Some("sps->log2_diff_min_cb_min_tt_cb_size_minus2"),
+        );
+    }
     bs.write1(sps.sps_suco_flag as u32, Some("sps->sps_suco_flag"));
+    if sps.sps_suco_flag {
+        bs.write_ue(
+            sps.log2_diff_ctu_size_max_suco_cb_size as u32,
+            Some("sps->log2_diff_ctu_size_max_suco_cb_size"),
+        );
+        bs.write_ue(
+            sps.log2_diff_max_suco_min_suco_cb_size as u32,
+            Some("sps->log2_diff_max_suco_min_suco_cb_size"),
+        );
+    }
     bs.write1(sps.tool_admvp as u32, Some("sps->tool_admvp"));
+    if sps.tool_admvp {
+        bs.write1(sps.tool_affine as u32, Some("sps->tool_affine"));
+        bs.write1(sps.tool_amvr as u32, Some("sps->tool_amvr"));
+        bs.write1(sps.tool_dmvr as u32, Some("sps->tool_dmvr"));
+        bs.write1(sps.tool_mmvd as u32, Some("sps->tool_mmvd"));
+        bs.write1(sps.tool_hmvp as u32, Some("sps->tool_hmvp"));
+    }
     bs.write1(sps.tool_eipd as u32, Some("sps->tool_eipd"));
+    if sps.tool_eipd {
+        bs.write1(sps.ibc_flag as u32, Some("sps->ibc_flag"));
+        if sps.ibc_flag {
+            bs.write_ue(
+                sps.ibc_log_max_size as u32 - 2,
+                Some("sps->log2_max_ibc_cand_size_minus2"),
+            );
+        }
+    }
     bs.write1(sps.tool_cm_init as u32, Some("sps->tool_cm_init"));
+    if sps.tool_cm_init {
+        bs.write1(sps.tool_adcc as u32, Some("sps->tool_adcc"));
+    }
     bs.write1(sps.tool_iqt as u32, Some("sps->tool_iqt"));
+    if sps.tool_iqt {
+        bs.write1(sps.tool_ats as u32, Some("sps->tool_ats"));
+    }
     bs.write1(sps.tool_addb as u32, Some("sps->tool_addb"));
     bs.write1(sps.tool_alf as u32, Some("sps->tool_alf"));
     bs.write1(sps.tool_htdf as u32, Some("sps->tool_htdf"));
@@ -78,6 +133,12 @@ pub(crate) fn evce_eco_sps(bs: &mut EvceBsw, sps: &EvcSps) {
     bs.write1(sps.tool_pocs as u32, Some("sps->tool_pocs"));
     bs.write1(sps.dquant_flag as u32, Some("sps->dquant_flag"));
     bs.write1(sps.tool_dra as u32, Some("sps->tool_dra"));
+    if sps.tool_pocs {
+        bs.write_ue(
+            sps.log2_max_pic_order_cnt_lsb_minus4 as u32,
+            Some("sps->log2_max_pic_order_cnt_lsb_minus4"),
+        );
+    }
     if !sps.tool_rpl || !sps.tool_pocs {
         bs.write_ue(
             sps.log2_sub_gop_length as u32,
@@ -92,6 +153,35 @@ pub(crate) fn evce_eco_sps(bs: &mut EvceBsw, sps: &EvcSps) {
     }
     if !sps.tool_rpl {
         bs.write_ue(sps.max_num_ref_pics as u32, Some("sps->max_num_ref_pics"));
+    } else {
+        bs.write_ue(
+            sps.sps_max_dec_pic_buffering_minus1 as u32,
+            Some("sps->sps_max_dec_pic_buffering_minus1"),
+        );
+        bs.write1(
+            sps.long_term_ref_pics_flag as u32,
+            Some("sps->long_term_ref_pics_flag"),
+        );
+        bs.write1(
+            sps.rpl1_same_as_rpl0_flag as u32,
+            Some("sps->rpl1_same_as_rpl0_flag"),
+        );
+        bs.write_ue(
+            sps.num_ref_pic_lists_in_sps0 as u32,
+            Some("sps->num_ref_pic_lists_in_sps0"),
+        );
+        for i in 0..sps.num_ref_pic_lists_in_sps0 as usize {
+            evce_eco_rpl(bs, &sps.rpls_l0[i], sps.long_term_ref_pics_flag);
+        }
+        if !sps.rpl1_same_as_rpl0_flag {
+            bs.write_ue(
+                sps.num_ref_pic_lists_in_sps1 as u32,
+                Some("sps->num_ref_pic_lists_in_sps1"),
+            );
+            for i in 0..sps.num_ref_pic_lists_in_sps1 as usize {
+                evce_eco_rpl(bs, &sps.rpls_l1[i], sps.long_term_ref_pics_flag);
+            }
+        }
     }
 
     bs.write1(
@@ -170,6 +260,35 @@ pub(crate) fn evce_eco_sps(bs: &mut EvceBsw, sps: &EvcSps) {
     EVC_TRACE(&mut bs.tracer, "***********************************\n");
 }
 
+/* reference picture list structure: the POC differences of the short-term
+entries are coded as a chain, each one relative to the previous short-term
+entry, the long-term entries by their POC LSBs */
+fn evce_eco_rpl(bs: &mut EvceBsw, rpl: &EvcRpl, long_term_ref_pics_flag: bool) {
+    bs.write_ue(rpl.ref_pic_num as u32, Some("rpl->ref_pic_num"));
+
+    let mut delta_poc = 0;
+    for i in 0..rpl.ref_pic_num as usize {
+        if long_term_ref_pics_flag {
+            bs.write1(!rpl.ref_pic_lt[i] as u32, Some("rpl->st_ref_pic_flag"));
+        }
+        if rpl.ref_pic_lt[i] {
+            bs.write(
+                rpl.ref_pics[i] as u32,
+                rpl.lt_poc_lsb_len as isize,
+                Some("rpl->poc_lsb_lt"),
+            );
+            continue;
+        }
+
+        let delta = rpl.ref_pics[i] - delta_poc;
+        bs.write_ue(delta.unsigned_abs(), Some("rpl->abs_delta_poc_st"));
+        if delta != 0 {
+            bs.write1((delta < 0) as u32, Some("rpl->strp_entry_sign_flag"));
+        }
+        delta_poc = rpl.ref_pics[i];
+    }
+}
+
 pub(crate) fn evce_eco_pps(bs: &mut EvceBsw, pps: &EvcPps) {
     EVC_TRACE(&mut bs.tracer, "***********************************\n");
     EVC_TRACE(&mut bs.tracer, "************ PPS Start ************\n");
@@ -202,6 +321,42 @@ pub(crate) fn evce_eco_pps(bs: &mut EvceBsw, pps: &EvcPps) {
         pps.single_tile_in_pic_flag as u32,
         Some("pps->single_tile_in_pic_flag"),
     );
+    if !pps.single_tile_in_pic_flag {
+        bs.write_ue(
+            pps.num_tile_columns_minus1 as u32,
+            Some("pps->num_tile_columns_minus1"),
+        );
+        bs.write_ue(
+            pps.num_tile_rows_minus1 as u32,
+            Some("pps->num_tile_rows_minus1"),
+        );
+        bs.write1(
+            pps.uniform_tile_spacing_flag as u32,
+            Some("pps->uniform_tile_spacing_flag"),
+        );
+        if !pps.uniform_tile_spacing_flag {
+            for i in 0..pps.num_tile_columns_minus1 as usize {
+                bs.write_ue(
+                    pps.tile_column_width_minus1[i] as u32,
+                    Some("pps->tile_column_width_minus1"),
+                );
+            }
+            for i in 0..pps.num_tile_rows_minus1 as usize {
+                bs.write_ue(
+                    pps.tile_row_height_minus1[i] as u32,
+                    Some("pps->tile_row_height_minus1"),
+                );
+            }
+        }
+        bs.write1(
+            pps.loop_filter_across_tiles_enabled_flag as u32,
+            Some("pps->loop_filter_across_tiles_enabled_flag"),
+        );
+        bs.write_ue(
+            pps.tile_offset_len_minus1 as u32,
+            Some("pps->tile_offset_len_minus1"),
+        );
+    }
 
     bs.write_ue(
         pps.tile_id_len_minus1 as u32,
@@ -245,7 +400,189 @@ pub(crate) fn evce_eco_pps(bs: &mut EvceBsw, pps: &EvcPps) {
     EVC_TRACE(&mut bs.tracer, "***********************************\n");
 }
 
-pub(crate) fn evce_eco_sh(bs: &mut EvceBsw, sh: &EvcSh, nalu_type: NaluType) {
+pub(crate) fn evce_eco_sei(
+    bs: &mut EvceBsw,
+    hash: PictureHash,
+    signature: &[[u8; 16]; N_C],
+    chroma_format_idc: u8,
+) {
+    /* should be aligned before adding user data */
+    assert!(bs.IS_BYTE_ALIGN());
+
+    /* picture signature, payload_size is the hash size of a plane */
+    bs.write(EVC_UD_PIC_SIGNATURE, 8, Some("payload_type"));
+    bs.write(hash.size() as u32, 8, Some("payload_size"));
+    /* a monochrome picture only has the signature of the luma plane */
+    let num_comp = if chroma_format_idc == 0 { 1 } else { N_C };
+    for sign in &signature[..num_comp] {
+        for &byte in &sign[..hash.size()] {
+            bs.write(byte as u32, 8, Some("pic_sign"));
+        }
+    }
+}
+
+/* exp-golomb code of an ALF coefficient, the suffix is written LSB first */
+fn evce_eco_alf_golomb(bs: &mut EvceBsw, coeff: i16, k: u8) {
+    let val = coeff.unsigned_abs() as u32;
+    for _ in 0..val >> k {
+        bs.write1(1, Some("alf_coeff_prefix"));
+    }
+    bs.write1(0, Some("alf_coeff_prefix"));
+    for i in 0..k {
+        bs.write1((val >> i) & 1, Some("alf_coeff_suffix"));
+    }
+    if val != 0 {
+        bs.write1((coeff > 0) as u32, Some("alf_coeff_sign"));
+    }
+}
+
+fn evce_eco_alf_filter(bs: &mut EvceBsw, alf: &EvcAlfParam, is_chroma: bool, k_min_tab: &[u8; 3]) {
+    if !is_chroma {
+        bs.write1(
+            alf.coeff_delta_flag as u32,
+            Some("alf_coefficients_delta_flag"),
+        );
+        if !alf.coeff_delta_flag && alf.num_luma_filters > 1 {
+            bs.write1(
+                alf.coeff_delta_pred_mode_flag as u32,
+                Some("coeff_delta_pred_mode_flag"),
+            );
+        }
+    }
+
+    let (golomb_idx, max_golomb_idx, num_coeff) = if !is_chroma && alf.luma_filter_type {
+        (&evc_tbl_alf_golomb_idx7[..], 3, MAX_NUM_ALF_LUMA_COEFF)
+    } else {
+        (&evc_tbl_alf_golomb_idx5[..], 2, MAX_NUM_ALF_CHROMA_COEFF)
+    };
+
+    bs.write_ue(k_min_tab[0] as u32 - 1, Some("min_golomb_order"));
+    let mut k_min = k_min_tab[0];
+    for &k in &k_min_tab[..max_golomb_idx] {
+        bs.write1((k - k_min) as u32, Some("golomb_order_increase_flag"));
+        k_min = k;
+    }
+
+    if is_chroma {
+        for i in 0..num_coeff - 1 {
+            evce_eco_alf_golomb(bs, alf.chroma_coeff[i], k_min_tab[golomb_idx[i] as usize]);
+        }
+        return;
+    }
+
+    let num_filters = alf.num_luma_filters as usize;
+    if alf.coeff_delta_flag {
+        for f in 0..num_filters {
+            bs.write1(
+                alf.filter_coeff_flag[f] as u32,
+                Some("filter_coefficient_flag"),
+            );
+        }
+    }
+    for f in 0..num_filters {
+        if alf.coeff_delta_flag && !alf.filter_coeff_flag[f] {
+            continue;
+        }
+        for i in 0..num_coeff - 1 {
+            evce_eco_alf_golomb(
+                bs,
+                alf.luma_coeff[f * MAX_NUM_ALF_LUMA_COEFF + i],
+                k_min_tab[golomb_idx[i] as usize],
+            );
+        }
+    }
+}
+
+/* write an adaptation parameter set carrying ALF parameters, k_min_tab are
+the golomb orders of the luma and chroma coefficients */
+pub(crate) fn evce_eco_aps(
+    bs: &mut EvceBsw,
+    aps_id: u8,
+    alf: &EvcAlfParam,
+    k_min_tab: &[[u8; 3]; 2],
+) {
+    EVC_TRACE(&mut bs.tracer, "***********************************\n");
+    EVC_TRACE(&mut bs.tracer, "************ APS Start ************\n");
+
+    bs.write(aps_id as u32, 5, Some("aps->aps_id"));
+    bs.write(ALF_APS_TYPE, 3, Some("aps->aps_params_type"));
+
+    bs.write1(
+        alf.luma_signal_flag as u32,
+        Some("alf_luma_filter_signal_flag"),
+    );
+    bs.write1(
+        alf.chroma_signal_flag as u32,
+        Some("alf_chroma_filter_signal_flag"),
+    );
+
+    if alf.luma_signal_flag {
+        let num_luma_filters = alf.num_luma_filters as u32;
+        bs.write_ue(
+            num_luma_filters - 1,
+            Some("alf_luma_num_filters_signalled_minus1"),
+        );
+        bs.write1(alf.luma_filter_type as u32, Some("alf_luma_type_flag"));
+
+        if num_luma_filters > 1 {
+            let len = (32 - (num_luma_filters - 1).leading_zeros()) as isize;
+            for i in 0..MAX_NUM_ALF_CLASSES {
+                bs.write(
+                    alf.filter_coeff_delta_idx[i] as u32,
+                    len,
+                    Some("alf_luma_coeff_delta_idx"),
+                );
+            }
+        }
+
+        /* fixed_filter_pattern, a unary code followed by its sign */
+        for _ in 0..alf.fixed_filter_pattern {
+            bs.write1(1, Some("fixed_filter_pattern"));
+        }
+        bs.write1(0, Some("fixed_filter_pattern"));
+        if alf.fixed_filter_pattern > 0 {
+            bs.write1(1, Some("fixed_filter_pattern_sign"));
+        }
+
+        if alf.fixed_filter_pattern == 2 {
+            for i in 0..MAX_NUM_ALF_CLASSES {
+                bs.write1(
+                    alf.fixed_filter_usage_flag[i] as u32,
+                    Some("fixed_filter_usage_flag"),
+                );
+            }
+        }
+        for i in 0..MAX_NUM_ALF_CLASSES {
+            if alf.fixed_filter_usage_flag[i] {
+                bs.write(alf.fixed_filter_idx[i] as u32, 4, Some("fixed_filter_idx"));
+            }
+        }
+
+        evce_eco_alf_filter(bs, alf, false, &k_min_tab[0]);
+    }
+
+    if alf.chroma_signal_flag {
+        evce_eco_alf_filter(bs, alf, true, &k_min_tab[1]);
+    }
+
+    bs.write1(0, Some("aps->aps_extension_flag"));
+
+    /* byte align */
+    while !bs.IS_BYTE_ALIGN() {
+        bs.write1(0, Some("t0"));
+    }
+
+    EVC_TRACE(&mut bs.tracer, "************ APS End   ************\n");
+    EVC_TRACE(&mut bs.tracer, "***********************************\n");
+}
+
+pub(crate) fn evce_eco_sh(
+    bs: &mut EvceBsw,
+    sps: &EvcSps,
+    pps: &EvcPps,
+    sh: &EvcSh,
+    nalu_type: NaluType,
+) {
     EVC_TRACE(&mut bs.tracer, "***********************************\n");
     EVC_TRACE(&mut bs.tracer, "************ SH  Start ************\n");
 
@@ -253,6 +590,31 @@ pub(crate) fn evce_eco_sh(bs: &mut EvceBsw, sh: &EvcSh, nalu_type: NaluType) {
         sh.slice_pic_parameter_set_id as u32,
         Some("sh->slice_pic_parameter_set_id"),
     );
+    if !pps.single_tile_in_pic_flag {
+        bs.write1(
+            sh.single_tile_in_slice_flag as u32,
+            Some("sh->single_tile_in_slice_flag"),
+        );
+        bs.write(
+            sh.first_tile_id as u32,
+            pps.tile_id_len_minus1 as isize + 1,
+            Some("sh->first_tile_id"),
+        );
+    }
+    if !sh.single_tile_in_slice_flag {
+        if pps.arbitrary_slice_present_flag {
+            bs.write1(
+                sh.arbitrary_slice_flag as u32,
+                Some("sh->arbitrary_slice_flag"),
+            );
+        }
+        /* each slice holds exactly one tile, so there are no entry points */
+        bs.write(
+            sh.last_tile_id as u32,
+            pps.tile_id_len_minus1 as isize + 1,
+            Some("sh->last_tile_id"),
+        );
+    }
     bs.write_ue(sh.slice_type as u32, Some("sh->slice_type"));
 
     if nalu_type == NaluType::EVC_IDR_NUT {
@@ -260,7 +622,87 @@ pub(crate) fn evce_eco_sh(bs: &mut EvceBsw, sh: &EvcSh, nalu_type: NaluType) {
             sh.no_output_of_prior_pics_flag as u32,
             Some("sh->no_output_of_prior_pics_flag"),
         );
-    } else {
+    }
+
+    if sps.tool_mmvd
+        && (sh.slice_type == SliceType::EVC_ST_B || sh.slice_type == SliceType::EVC_ST_P)
+    {
+        bs.write1(
+            sh.mmvd_group_enable_flag as u32,
+            Some("sh->mmvd_group_enable_flag"),
+        );
+    }
+
+    if sps.tool_alf {
+        bs.write1(sh.alf_on as u32, Some("sh->alf_on"));
+        if sh.alf_on {
+            bs.write(sh.aps_id_y as u32, 5, Some("sh->aps_id_y"));
+            bs.write1(sh.alf_map_flag as u32, Some("sh->alf_map_flag"));
+            bs.write(sh.alf_chroma_idc as u32, 2, Some("sh->alf_chroma_idc"));
+            if sh.alf_chroma_idc != 0 && (sps.chroma_format_idc == 1 || sps.chroma_format_idc == 2)
+            {
+                bs.write(sh.aps_id_ch as u32, 5, Some("sh->aps_id_ch"));
+            }
+            if sps.chroma_format_idc == 3 {
+                if sh.alf_chroma_idc & 1 != 0 {
+                    bs.write(sh.aps_id_ch as u32, 5, Some("sh->aps_id_ch"));
+                    bs.write1(
+                        sh.alf_chroma_map_flag as u32,
+                        Some("sh->alf_chroma_map_flag"),
+                    );
+                }
+                if sh.alf_chroma_idc & 2 != 0 {
+                    bs.write(sh.aps_id_ch2 as u32, 5, Some("sh->aps_id_ch2"));
+                    bs.write1(
+                        sh.alf_chroma2_map_flag as u32,
+                        Some("sh->alf_chroma2_map_flag"),
+                    );
+                }
+            }
+        }
+    }
+
+    if nalu_type != NaluType::EVC_IDR_NUT {
+        if sps.tool_pocs {
+            bs.write(
+                sh.poc_lsb as u32,
+                sps.log2_max_pic_order_cnt_lsb_minus4 as isize + 4,
+                Some("sh->poc_lsb"),
+            );
+        }
+        if sps.tool_rpl {
+            let num_rpls = [sps.num_ref_pic_lists_in_sps0, sps.num_ref_pic_lists_in_sps1];
+            for lidx in 0..REFP_NUM {
+                let idx_present = lidx == REFP_0 || pps.rpl1_idx_present_flag;
+                if num_rpls[lidx] > 0 && idx_present {
+                    bs.write1(
+                        sh.ref_pic_list_sps_flag[lidx],
+                        Some("sh->ref_pic_list_sps_flag"),
+                    );
+                }
+                if sh.ref_pic_list_sps_flag[lidx] != 0 {
+                    if num_rpls[lidx] > 1 && idx_present {
+                        let rpl_idx = if lidx == REFP_0 {
+                            sh.rpl_l0_idx
+                        } else {
+                            sh.rpl_l1_idx
+                        };
+                        bs.write_ue(rpl_idx as u32, Some("sh->rpl_idx"));
+                    }
+                } else {
+                    evce_eco_rpl(
+                        bs,
+                        if lidx == REFP_0 {
+                            &sh.rpl_l0
+                        } else {
+                            &sh.rpl_l1
+                        },
+                        sps.long_term_ref_pics_flag,
+                    );
+                }
+            }
+        }
+
         if sh.slice_type == SliceType::EVC_ST_P || sh.slice_type == SliceType::EVC_ST_B {
             bs.write1(
                 sh.num_ref_idx_active_override_flag as u32,
@@ -280,6 +722,29 @@ pub(crate) fn evce_eco_sh(bs: &mut EvceBsw, sh: &EvcSh, nalu_type: NaluType) {
                     );
                 }
             }
+
+            if sps.tool_admvp {
+                bs.write1(
+                    sh.temporal_mvp_asigned_flag as u32,
+                    Some("sh->temporal_mvp_asigned_flag"),
+                );
+                if sh.temporal_mvp_asigned_flag {
+                    if sh.slice_type == SliceType::EVC_ST_B {
+                        bs.write1(
+                            sh.collocated_from_list_idx as u32,
+                            Some("sh->collocated_from_list_idx"),
+                        );
+                        bs.write1(
+                            sh.collocated_mvp_source_list_idx as u32,
+                            Some("sh->collocated_mvp_source_list_idx"),
+                        );
+                    }
+                    bs.write1(
+                        sh.collocated_from_ref_idx as u32,
+                        Some("sh->collocated_from_ref_idx"),
+                    );
+                }
+            }
         }
     }
 
@@ -287,6 +752,16 @@ pub(crate) fn evce_eco_sh(bs: &mut EvceBsw, sh: &EvcSh, nalu_type: NaluType) {
         sh.deblocking_filter_on as u32,
         Some("sh->deblocking_filter_on"),
     );
+    if sh.deblocking_filter_on && sps.tool_addb {
+        bs.write_se(
+            sh.sh_deblock_alpha_offset as i32,
+            Some("sh->sh_deblock_alpha_offset"),
+        );
+        bs.write_se(
+            sh.sh_deblock_beta_offset as i32,
+            Some("sh->sh_deblock_beta_offset"),
+        );
+    }
     bs.write(sh.qp as u32, 6, Some("sh->qp"));
     bs.write_se(sh.qp_u_offset as i32, Some("sh->qp_u_offset"));
     bs.write_se(sh.qp_v_offset as i32, Some("sh->qp_v_offset"));
@@ -316,6 +791,14 @@ pub(crate) fn evce_eco_split_mode(
     cuh: u16,
     lcu_s: u16,
     split_mode_buf: &LcuSplitMode,
+    w: u16,
+    h: u16,
+    w_scu: u16,
+    map_scu: &[MCU],
+    sps_btt_flag: bool,
+    split_tbl: &EvcSplitTbl,
+    mode_cons: ModeCons,
+    sps_cm_init_flag: bool,
 ) {
     if cuw < 8 && cuh < 8 {
         return;
@@ -324,15 +807,89 @@ pub(crate) fn evce_eco_split_mode(
     //evc_assert(evce_check_luma(c, core));
     let split_mode = evc_get_split_mode(cud, cup, cuw, cuh, lcu_s, split_mode_buf);
 
-    sbac.encode_bin(
-        bs,
-        &mut sbac_ctx.split_cu_flag[0],
+    if !sps_btt_flag {
+        sbac.encode_bin(
+            bs,
+            &mut sbac_ctx.split_cu_flag[0],
+            if split_mode != SplitMode::NO_SPLIT {
+                1
+            } else {
+                0
+            },
+        ); /* split_cu_flag */
+    } else {
+        let mut split_allow = [false; MAX_SPLIT_NUM];
+        evc_check_split_mode(
+            &mut split_allow,
+            CONV_LOG2(cuw as usize),
+            CONV_LOG2(cuh as usize),
+            false,
+            false,
+            false,
+            MAX_CU_LOG2 as u8,
+            x_pel,
+            y_pel,
+            w,
+            h,
+            sps_btt_flag,
+            split_tbl,
+            mode_cons,
+        );
+        let allow_ver = split_allow[SplitMode::SPLIT_BI_VER as usize]
+            || split_allow[SplitMode::SPLIT_TRI_VER as usize];
+        let allow_hor = split_allow[SplitMode::SPLIT_BI_HOR as usize]
+            || split_allow[SplitMode::SPLIT_TRI_HOR as usize];
+
+        if !allow_ver && !allow_hor {
+            return;
+        }
+
+        let log2_cuw = CONV_LOG2(cuw as usize);
+        let log2_cuh = CONV_LOG2(cuh as usize);
+
+        let ctx = evc_get_ctx_btt_split_flag(
+            x_pel,
+            y_pel,
+            log2_cuw,
+            log2_cuh,
+            w_scu,
+            map_scu,
+            sps_cm_init_flag,
+        );
+        sbac.encode_bin(
+            bs,
+            &mut sbac_ctx.btt_split_flag[ctx],
+            if split_mode != SplitMode::NO_SPLIT {
+                1
+            } else {
+                0
+            },
+        ); /* btt_split_flag */
+
         if split_mode != SplitMode::NO_SPLIT {
-            1
-        } else {
-            0
-        },
-    ); /* split_cu_flag */
+            if allow_ver && allow_hor {
+                let ctx = evc_get_ctx_btt_split_dir(log2_cuw, log2_cuh, sps_cm_init_flag);
+                sbac.encode_bin(
+                    bs,
+                    &mut sbac_ctx.btt_split_dir[ctx],
+                    split_mode.is_vertical() as u32,
+                ); /* btt_split_dir */
+            }
+
+            let (bi, tri) = if split_mode.is_vertical() {
+                (SplitMode::SPLIT_BI_VER, SplitMode::SPLIT_TRI_VER)
+            } else {
+                (SplitMode::SPLIT_BI_HOR, SplitMode::SPLIT_TRI_HOR)
+            };
+            if split_allow[bi as usize] && split_allow[tri as usize] {
+                sbac.encode_bin(
+                    bs,
+                    &mut sbac_ctx.btt_split_type[0],
+                    split_mode.is_tt() as u32,
+                ); /* btt_split_type */
+            }
+        }
+    }
 
     EVC_TRACE_COUNTER(&mut bs.tracer);
     EVC_TRACE(&mut bs.tracer, "x pos ");
@@ -352,14 +909,54 @@ pub(crate) fn evce_eco_split_mode(
     EVC_TRACE(&mut bs.tracer, " depth ");
     EVC_TRACE(&mut bs.tracer, cud);
     EVC_TRACE(&mut bs.tracer, " split mode ");
-    EVC_TRACE(
-        &mut bs.tracer,
-        if split_mode == SplitMode::NO_SPLIT {
-            0
-        } else {
-            5
-        },
-    );
+    EVC_TRACE(&mut bs.tracer, split_mode as u32);
+    EVC_TRACE(&mut bs.tracer, " \n");
+}
+
+pub(crate) fn evce_eco_alf_ctb_flag(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    flag: bool,
+) {
+    sbac.encode_bin(bs, &mut sbac_ctx.alf_ctb_flag[0], flag as u32);
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "alf ctb flag ");
+    EVC_TRACE(&mut bs.tracer, flag as u32);
+    EVC_TRACE(&mut bs.tracer, " \n");
+}
+
+pub(crate) fn evce_eco_suco_flag(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    log2_cuw: u8,
+    log2_cuh: u8,
+    sps_cm_init_flag: bool,
+    suco_flag: bool,
+) {
+    let ctx = evc_get_ctx_suco_flag(log2_cuw, log2_cuh, sps_cm_init_flag);
+    sbac.encode_bin(bs, &mut sbac_ctx.suco_flag[ctx], suco_flag as u32);
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "suco flag ");
+    EVC_TRACE(&mut bs.tracer, suco_flag as u32);
+    EVC_TRACE(&mut bs.tracer, " \n");
+}
+
+pub(crate) fn evce_eco_mode_constr(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    mode_cons: ModeCons,
+) {
+    let flag = (mode_cons == ModeCons::eOnlyIntra) as u32;
+    sbac.encode_bin(bs, &mut sbac_ctx.mode_cons[0], flag);
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "mode_constr ");
+    EVC_TRACE(&mut bs.tracer, flag);
     EVC_TRACE(&mut bs.tracer, " \n");
 }
 
@@ -378,8 +975,72 @@ pub(crate) fn evce_eco_intra_dir_b(
         {
             EVC_TRACE_INT(mpm[i]);
         }
-    #endif*/
-    EVC_TRACE(&mut bs.tracer, "ipm Y ");
+    #endif*/
+    EVC_TRACE(&mut bs.tracer, "ipm Y ");
+    EVC_TRACE(&mut bs.tracer, ipm);
+    EVC_TRACE(&mut bs.tracer, " \n");
+}
+
+pub(crate) fn evce_eco_intra_dir(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    ipm: u8,
+    mpm: &[u8],
+) {
+    let idx = mpm.iter().position(|&m| m == ipm).unwrap();
+    if idx < IPD_MPM_CNT {
+        sbac.encode_bin(bs, &mut sbac_ctx.intra_luma_pred_mpm_flag[0], 1);
+        sbac.encode_bin(bs, &mut sbac_ctx.intra_luma_pred_mpm_idx[0], idx as u32);
+    } else if idx < IPD_MPM_CNT + IPD_PIMS_CNT {
+        sbac.encode_bin(bs, &mut sbac_ctx.intra_luma_pred_mpm_flag[0], 0);
+        sbac.encode_bin_ep(bs, 1);
+        sbac.encode_bins_ep(bs, (idx - IPD_MPM_CNT) as u32, 3);
+    } else {
+        sbac.encode_bin(bs, &mut sbac_ctx.intra_luma_pred_mpm_flag[0], 0);
+        sbac.encode_bin_ep(bs, 0);
+
+        /* truncated binary code of the remaining modes */
+        let rem = (idx - IPD_MPM_CNT - IPD_PIMS_CNT) as u32;
+        let num_rem = (IPD_CNT - IPD_MPM_CNT - IPD_PIMS_CNT) as u32;
+        let k = 31 - num_rem.leading_zeros();
+        let u = (1 << (k + 1)) - num_rem;
+        if rem < u {
+            sbac.encode_bins_ep(bs, rem, k as isize);
+        } else {
+            sbac.encode_bins_ep(bs, rem + u, k as isize + 1);
+        }
+    }
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "ipm Y ");
+    EVC_TRACE(&mut bs.tracer, ipm);
+    EVC_TRACE(&mut bs.tracer, " \n");
+}
+
+pub(crate) fn evce_eco_intra_dir_c(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    ipm: u8,
+    ipm_l: IntraPredDir,
+) {
+    if ipm == IntraPredDir::IPD_DM_C.0 as u8 {
+        sbac.encode_bin(bs, &mut sbac_ctx.intra_dir_c[0], 1);
+    } else {
+        sbac.encode_bin(bs, &mut sbac_ctx.intra_dir_c[0], 0);
+
+        /* the chroma mode predicting the same as DM is not signalled */
+        let ipm_dup = evc_ipred_conv_l2c(ipm_l);
+        let mut remain = ipm as u32 - 1;
+        if ipm_dup != IntraPredDir::IPD_INVALID && ipm > ipm_dup.0 as u8 {
+            remain -= 1;
+        }
+        sbac.write_unary_sym_ep(bs, remain, IPD_CHROMA_CNT as u32 - 1);
+    }
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "ipm UV ");
     EVC_TRACE(&mut bs.tracer, ipm);
     EVC_TRACE(&mut bs.tracer, " \n");
 }
@@ -389,10 +1050,11 @@ pub(crate) fn evce_eco_pred_mode(
     sbac: &mut EvceSbac,
     sbac_ctx: &mut EvcSbacCtx,
     pred_mode: PredMode,
+    ctx: usize,
 ) {
     sbac.encode_bin(
         bs,
-        &mut sbac_ctx.pred_mode[0],
+        &mut sbac_ctx.pred_mode[ctx],
         if pred_mode == PredMode::MODE_INTRA {
             1
         } else {
@@ -424,12 +1086,15 @@ pub(crate) fn evce_eco_cbf(
     b_no_cbf: bool,
     cbf_all: u16,
     run: &[bool],
+    chroma_format_idc: u8,
 ) {
+    let run_all = (run[Y_C] as u8 + run[U_C] as u8 + run[V_C] as u8) == 3;
+
     /* code allcbf */
     if pred_mode != PredMode::MODE_INTRA {
         if b_no_cbf {
             assert_ne!(cbf_all, 0);
-        } else if (run[Y_C] as u8 + run[U_C] as u8 + run[V_C] as u8) == 3 {
+        } else if run_all {
             // not count bits of root_cbf when checking each component
 
             if cbf_all == 0 {
@@ -451,14 +1116,15 @@ pub(crate) fn evce_eco_cbf(
             }
         }
 
-        if run[U_C] {
+        /* monochrome pictures have no chroma residual */
+        if run[U_C] && chroma_format_idc != 0 {
             sbac.encode_bin(bs, &mut sbac_ctx.cbf_cb[0], cbf_u as u32);
             EVC_TRACE_COUNTER(&mut bs.tracer);
             EVC_TRACE(&mut bs.tracer, "cbf U ");
             EVC_TRACE(&mut bs.tracer, cbf_u as u8);
             EVC_TRACE(&mut bs.tracer, " \n");
         }
-        if run[V_C] {
+        if run[V_C] && chroma_format_idc != 0 {
             sbac.encode_bin(bs, &mut sbac_ctx.cbf_cr[0], cbf_v as u32);
             EVC_TRACE_COUNTER(&mut bs.tracer);
             EVC_TRACE(&mut bs.tracer, "cbf V ");
@@ -474,14 +1140,15 @@ pub(crate) fn evce_eco_cbf(
             EVC_TRACE(&mut bs.tracer, " \n");
         }
     } else {
-        if run[U_C] {
+        /* monochrome pictures have no chroma residual */
+        if run[U_C] && chroma_format_idc != 0 {
             sbac.encode_bin(bs, &mut sbac_ctx.cbf_cb[0], cbf_u as u32);
             EVC_TRACE_COUNTER(&mut bs.tracer);
             EVC_TRACE(&mut bs.tracer, "cbf U ");
             EVC_TRACE(&mut bs.tracer, cbf_u as u8);
             EVC_TRACE(&mut bs.tracer, " \n");
         }
-        if run[V_C] {
+        if run[V_C] && chroma_format_idc != 0 {
             sbac.encode_bin(bs, &mut sbac_ctx.cbf_cr[0], cbf_v as u32);
             EVC_TRACE_COUNTER(&mut bs.tracer);
             EVC_TRACE(&mut bs.tracer, "cbf V ");
@@ -498,6 +1165,84 @@ pub(crate) fn evce_eco_cbf(
     }
 }
 
+pub(crate) fn evce_eco_ats_intra_cu(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    ats_intra_cu: bool,
+    ats_mode: u8,
+) {
+    sbac.encode_bin_ep(bs, ats_intra_cu as u32);
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "ats intra CU ");
+    EVC_TRACE(&mut bs.tracer, ats_intra_cu as u8);
+    EVC_TRACE(&mut bs.tracer, " \n");
+
+    if ats_intra_cu {
+        sbac.encode_bin(bs, &mut sbac_ctx.ats_mode[0], (ats_mode >> 1) as u32);
+        sbac.encode_bin(bs, &mut sbac_ctx.ats_mode[0], (ats_mode & 1) as u32);
+        EVC_TRACE_COUNTER(&mut bs.tracer);
+        EVC_TRACE(&mut bs.tracer, "ats intra mode ");
+        EVC_TRACE(&mut bs.tracer, ats_mode);
+        EVC_TRACE(&mut bs.tracer, " \n");
+    }
+}
+
+pub(crate) fn evce_eco_ats_inter_info(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    log2_cuw: u8,
+    log2_cuh: u8,
+    ats_inter_info: u8,
+    sps_cm_init_flag: bool,
+) {
+    let idx = ats_inter_info & 0xf;
+    let pos = ats_inter_info >> 4;
+    let ctx = if sps_cm_init_flag && log2_cuw + log2_cuh < 8 {
+        1
+    } else {
+        0
+    };
+
+    sbac.encode_bin(bs, &mut sbac_ctx.ats_inter_flag[ctx], (idx != 0) as u32);
+    if idx != 0 {
+        let is_quad = idx == ATS_INTER_QUAD_VER || idx == ATS_INTER_QUAD_HOR;
+        let is_hor = idx == ATS_INTER_HALF_HOR || idx == ATS_INTER_QUAD_HOR;
+
+        if evc_check_ats_inter_idx(ATS_INTER_QUAD_VER, log2_cuw, log2_cuh)
+            || evc_check_ats_inter_idx(ATS_INTER_QUAD_HOR, log2_cuw, log2_cuh)
+        {
+            sbac.encode_bin(bs, &mut sbac_ctx.ats_inter_quad_flag[0], is_quad as u32);
+        }
+
+        let (idx_ver, idx_hor) = if is_quad {
+            (ATS_INTER_QUAD_VER, ATS_INTER_QUAD_HOR)
+        } else {
+            (ATS_INTER_HALF_VER, ATS_INTER_HALF_HOR)
+        };
+        if evc_check_ats_inter_idx(idx_ver, log2_cuw, log2_cuh)
+            && evc_check_ats_inter_idx(idx_hor, log2_cuw, log2_cuh)
+        {
+            let ctx = if !sps_cm_init_flag || log2_cuw == log2_cuh {
+                0
+            } else if log2_cuw < log2_cuh {
+                1
+            } else {
+                2
+            };
+            sbac.encode_bin(bs, &mut sbac_ctx.ats_inter_hor_flag[ctx], is_hor as u32);
+        }
+
+        sbac.encode_bin(bs, &mut sbac_ctx.ats_inter_pos_flag[0], pos as u32);
+    }
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "ats inter info ");
+    EVC_TRACE(&mut bs.tracer, ats_inter_info);
+    EVC_TRACE(&mut bs.tracer, " \n");
+}
+
 pub(crate) fn evce_eco_dqp(
     bs: &mut EvceBsw,
     sbac: &mut EvceSbac,
@@ -535,9 +1280,10 @@ pub(crate) fn evce_eco_run_length_cc(
     log2_h: u8,
     mut num_sig: u16,
     ch_type: usize,
+    sps_cm_init_flag: bool,
 ) {
     let mut ctx_last = 0;
-    let scanp = &evc_scan_tbl[log2_w as usize - 1];
+    let scanp = &evc_scan_tbl[log2_w as usize - 1][log2_h as usize - 1];
     let num_coeff = (1 << (log2_w + log2_h)) as usize;
     let mut run = 0;
     let mut prev_level = 6;
@@ -547,7 +1293,14 @@ pub(crate) fn evce_eco_run_length_cc(
         if coef_cur != 0 {
             let level = coef_cur.abs() as u32;
             let sign = if coef_cur > 0 { 0 } else { 1 };
-            let t0 = if ch_type == Y_C { 0 } else { 2 };
+            /* with cm_init, the contexts also depend on the previous level */
+            let t0 = if sps_cm_init_flag {
+                (prev_level.min(6) as usize - 1) * 2 + if ch_type == Y_C { 0 } else { 12 }
+            } else if ch_type == Y_C {
+                0
+            } else {
+                2
+            };
 
             /* Run coding */
             sbac.write_unary_sym(bs, &mut sbac_ctx.run[t0..], run, 2);
@@ -590,6 +1343,202 @@ pub(crate) fn evce_eco_run_length_cc(
      */
 }
 
+fn evce_eco_last_sig_coeff_pos(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    models: &mut [SBAC_CTX_MODEL],
+    pos: usize,
+    log2_size: u8,
+    ch_type: usize,
+) {
+    let (offset, shift) = evc_get_ctx_last_pos_xy(log2_size, ch_type);
+    let group = evc_tbl_group_idx[pos] as usize;
+    let max_group = evc_tbl_group_idx[(1 << log2_size) - 1] as usize;
+
+    for i in 0..group {
+        sbac.encode_bin(bs, &mut models[offset + (i >> shift)], 1);
+    }
+    if group < max_group {
+        sbac.encode_bin(bs, &mut models[offset + (group >> shift)], 0);
+    }
+}
+
+fn evce_eco_last_sig_coeff_suffix(bs: &mut EvceBsw, sbac: &mut EvceSbac, pos: usize) {
+    let group = evc_tbl_group_idx[pos] as usize;
+    if group > 3 {
+        let suffix = pos as u32 - evc_tbl_min_in_group[group] as u32;
+        sbac.encode_bins_ep(bs, suffix, ((group >> 1) - 1) as isize);
+    }
+}
+
+/* level remainder of the advanced coefficient coding, binarized by a Rice code
+escaping to an Exp-Golomb code */
+fn evce_eco_coef_remain(bs: &mut EvceBsw, sbac: &mut EvceSbac, symbol: u32, rparam: u32) {
+    let range = evc_tbl_go_rice_range[rparam as usize] as u32;
+    if symbol < (range << rparam) {
+        let prefix = symbol >> rparam;
+        sbac.encode_bins_ep(bs, (1 << (prefix + 1)) - 2, (prefix + 1) as isize);
+        sbac.encode_bins_ep(bs, symbol % (1 << rparam), rparam as isize);
+    } else {
+        let symbol = symbol - ((range - 1) << rparam);
+        let length = 31 - (symbol >> rparam).leading_zeros();
+        let prefix_len = range + length + 1;
+        sbac.encode_bins_ep(bs, (1 << prefix_len) - 2, prefix_len as isize);
+        sbac.encode_bins_ep(
+            bs,
+            symbol - (1 << (length + rparam)),
+            (length + rparam) as isize,
+        );
+    }
+}
+
+/* the coefficients are coded backwards in groups of 16 scan positions: the
+significance flags, the greater-than flags of the first 8 significant levels,
+the remainders and the signs. the contexts only depend on coefficients that
+follow in the scan, so they are derived from the final levels */
+pub(crate) fn evce_eco_adcc(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    coef: &[i16],
+    log2_w: u8,
+    log2_h: u8,
+    ch_type: usize,
+) {
+    let scanp = &evc_scan_tbl[log2_w as usize - 1][log2_h as usize - 1];
+    let num_coeff = (1 << (log2_w + log2_h)) as usize;
+    let w_mask = (1 << log2_w) - 1;
+    let sig_set = evc_get_sig_coeff_set(log2_w, log2_h, ch_type);
+    let ctx_gtx_offset = if ch_type == Y_C { 0 } else { NUM_CTX_GTX_LUMA };
+
+    let last_scan_pos = match (0..num_coeff)
+        .rev()
+        .find(|&scan_pos| coef[scanp[scan_pos] as usize] != 0)
+    {
+        Some(scan_pos) => scan_pos,
+        None => return,
+    };
+    let last_blk_pos = scanp[last_scan_pos] as usize;
+    let last_x = last_blk_pos & w_mask;
+    let last_y = last_blk_pos >> log2_w;
+
+    evce_eco_last_sig_coeff_pos(
+        bs,
+        sbac,
+        &mut sbac_ctx.last_sig_coeff_x_prefix,
+        last_x,
+        log2_w,
+        ch_type,
+    );
+    evce_eco_last_sig_coeff_pos(
+        bs,
+        sbac,
+        &mut sbac_ctx.last_sig_coeff_y_prefix,
+        last_y,
+        log2_h,
+        ch_type,
+    );
+    evce_eco_last_sig_coeff_suffix(bs, sbac, last_x);
+    evce_eco_last_sig_coeff_suffix(bs, sbac, last_y);
+
+    let mut start = last_scan_pos & !15;
+    let mut end = last_scan_pos;
+    loop {
+        let mut pos = [0usize; 16];
+        /* levels known to the decoder after the greater-than flags */
+        let mut abs_level = [0u32; 16];
+        let mut num_nz = 0;
+
+        for scan_pos in (start..=end).rev() {
+            let blk_pos = scanp[scan_pos] as usize;
+            let sig = coef[blk_pos] != 0;
+            if scan_pos != last_scan_pos {
+                let ctx = evc_get_ctx_sig_coeff_inc(coef, blk_pos, log2_w, log2_h, ch_type);
+                sbac.encode_bin(bs, &mut sbac_ctx.sig_coeff_flag[sig_set][ctx], sig as u32);
+            }
+            if sig {
+                pos[num_nz] = blk_pos;
+                abs_level[num_nz] = 1;
+                num_nz += 1;
+            }
+        }
+
+        if num_nz > 0 {
+            let mut first_c2 = None;
+            let mut escape = false;
+            for idx in 0..std::cmp::min(num_nz, NUM_CTX_GTX_COEF) {
+                let blk_pos = pos[idx];
+                let ctx = if blk_pos == last_blk_pos {
+                    0
+                } else {
+                    evc_get_ctx_gtx_inc(coef, blk_pos, log2_w, log2_h, ch_type, 1)
+                };
+                let bin = (coef[blk_pos].unsigned_abs() > 1) as u32;
+                sbac.encode_bin(
+                    bs,
+                    &mut sbac_ctx.coeff_abs_level_greaterAB_flag[ctx_gtx_offset + ctx],
+                    bin,
+                );
+                abs_level[idx] = 1 + bin;
+                if bin != 0 {
+                    if first_c2.is_none() {
+                        first_c2 = Some(idx);
+                    } else {
+                        escape = true;
+                    }
+                }
+            }
+
+            if let Some(idx) = first_c2 {
+                let blk_pos = pos[idx];
+                let ctx = if blk_pos == last_blk_pos {
+                    0
+                } else {
+                    evc_get_ctx_gtx_inc(coef, blk_pos, log2_w, log2_h, ch_type, 2)
+                };
+                let bin = (coef[blk_pos].unsigned_abs() > 2) as u32;
+                sbac.encode_bin(
+                    bs,
+                    &mut sbac_ctx.coeff_abs_level_greaterAB_flag[ctx_gtx_offset + ctx],
+                    bin,
+                );
+                abs_level[idx] = 2 + bin;
+                escape |= bin != 0;
+            }
+
+            if escape || num_nz > NUM_CTX_GTX_COEF {
+                let mut c2_flag = 1;
+                for idx in 0..num_nz {
+                    let blk_pos = pos[idx];
+                    let base = if idx < NUM_CTX_GTX_COEF {
+                        2 + c2_flag
+                    } else {
+                        1
+                    };
+                    if abs_level[idx] == base {
+                        let rparam = evc_get_rice_para(coef, blk_pos, log2_w, log2_h, base);
+                        abs_level[idx] = coef[blk_pos].unsigned_abs() as u32;
+                        evce_eco_coef_remain(bs, sbac, abs_level[idx] - base, rparam);
+                    }
+                    if abs_level[idx] >= 2 {
+                        c2_flag = 0;
+                    }
+                }
+            }
+
+            for idx in 0..num_nz {
+                sbac.encode_bin_ep(bs, (coef[pos[idx]] < 0) as u32);
+            }
+        }
+
+        if start == 0 {
+            break;
+        }
+        end = start - 1;
+        start -= 16;
+    }
+}
+
 pub(crate) fn evce_eco_xcoef(
     bs: &mut EvceBsw,
     sbac: &mut EvceSbac,
@@ -599,8 +1548,24 @@ pub(crate) fn evce_eco_xcoef(
     log2_h: u8,
     num_sig: u16,
     ch_type: usize,
+    sps_cm_init_flag: bool,
+    sps_adcc_flag: bool,
 ) {
-    evce_eco_run_length_cc(bs, sbac, sbac_ctx, coef, log2_w, log2_h, num_sig, ch_type);
+    if sps_adcc_flag {
+        evce_eco_adcc(bs, sbac, sbac_ctx, coef, log2_w, log2_h, ch_type);
+    } else {
+        evce_eco_run_length_cc(
+            bs,
+            sbac,
+            sbac_ctx,
+            coef,
+            log2_w,
+            log2_h,
+            num_sig,
+            ch_type,
+            sps_cm_init_flag,
+        );
+    }
 
     TRACE_COEF(
         &mut bs.tracer,
@@ -619,6 +1584,7 @@ pub(crate) fn coef_rect_to_series(
     mut y: u16,
     mut cuw: u16,
     mut cuh: u16,
+    chroma_format_idc: u8,
 ) {
     let max_cuwh = 1u16 << log2_max_cuwh;
 
@@ -633,12 +1599,15 @@ pub(crate) fn coef_rect_to_series(
         sidx += max_cuwh as usize;
     }
 
-    x >>= 1;
-    y >>= 1;
-    cuw >>= 1;
-    cuh >>= 1;
+    let w_shift = GET_CHROMA_W_SHIFT(chroma_format_idc);
+    let h_shift = GET_CHROMA_H_SHIFT(chroma_format_idc);
+    x >>= w_shift;
+    y >>= h_shift;
+    cuw >>= w_shift;
+    cuh >>= h_shift;
 
-    sidx = ((x & ((max_cuwh >> 1) - 1)) + ((y & ((max_cuwh >> 1) - 1)) << (log2_max_cuwh - 1)))
+    sidx = ((x & ((max_cuwh >> w_shift) - 1))
+        + ((y & ((max_cuwh >> h_shift) - 1)) << (log2_max_cuwh as usize - w_shift)))
         as usize;
     didx = 0;
 
@@ -648,7 +1617,7 @@ pub(crate) fn coef_rect_to_series(
             coef_dst.data[V_C][didx] = coef_src[V_C][sidx + i];
             didx += 1;
         }
-        sidx += (max_cuwh >> 1) as usize;
+        sidx += (max_cuwh >> w_shift) as usize;
     }
 }
 
@@ -657,14 +1626,15 @@ pub(crate) fn evce_eco_skip_flag(
     sbac: &mut EvceSbac,
     sbac_ctx: &mut EvcSbacCtx,
     flag: u32,
+    ctx: usize,
 ) {
-    sbac.encode_bin(bs, &mut sbac_ctx.skip_flag[0], flag);
+    sbac.encode_bin(bs, &mut sbac_ctx.skip_flag[ctx], flag);
 
     EVC_TRACE_COUNTER(&mut bs.tracer);
     EVC_TRACE(&mut bs.tracer, "skip flag ");
     EVC_TRACE(&mut bs.tracer, flag);
     EVC_TRACE(&mut bs.tracer, " ctx ");
-    EVC_TRACE(&mut bs.tracer, 0);
+    EVC_TRACE(&mut bs.tracer, ctx);
     EVC_TRACE(&mut bs.tracer, " \n");
 }
 
@@ -682,6 +1652,240 @@ pub(crate) fn evce_eco_mvp_idx(
     EVC_TRACE(&mut bs.tracer, " \n");
 }
 
+pub(crate) fn evce_eco_merge_idx(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    merge_idx: u32,
+) {
+    sbac.write_truncate_unary_sym(bs, &mut sbac_ctx.merge_idx, merge_idx, MAX_NUM_MVP as u32);
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "merge idx ");
+    EVC_TRACE(&mut bs.tracer, merge_idx);
+    EVC_TRACE(&mut bs.tracer, " \n");
+}
+
+pub(crate) fn evce_eco_mvr_idx(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    mvr_idx: u32,
+) {
+    sbac.write_truncate_unary_sym(bs, &mut sbac_ctx.mvr_idx, mvr_idx, MAX_NUM_MVR as u32);
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "mvr idx ");
+    EVC_TRACE(&mut bs.tracer, mvr_idx);
+    EVC_TRACE(&mut bs.tracer, " \n");
+}
+
+pub(crate) fn evce_eco_mmvd_flag(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    flag: u32,
+) {
+    sbac.encode_bin(bs, &mut sbac_ctx.mmvd_flag[0], flag);
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "mmvd flag ");
+    EVC_TRACE(&mut bs.tracer, flag);
+    EVC_TRACE(&mut bs.tracer, " \n");
+}
+
+pub(crate) fn evce_eco_mmvd_idx(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    mmvd_idx: u16,
+    num_groups: usize,
+) {
+    let (group_idx, merge_idx, dist_idx, dir_idx) = evc_mmvd_idx_split(mmvd_idx);
+
+    if num_groups > 1 {
+        sbac.write_truncate_unary_sym(
+            bs,
+            &mut sbac_ctx.mmvd_group_idx,
+            group_idx as u32,
+            num_groups as u32,
+        );
+    }
+    sbac.write_truncate_unary_sym(
+        bs,
+        &mut sbac_ctx.mmvd_merge_idx,
+        merge_idx as u32,
+        MMVD_BASE_NUM as u32,
+    );
+    sbac.write_truncate_unary_sym(
+        bs,
+        &mut sbac_ctx.mmvd_dist_idx,
+        dist_idx as u32,
+        MMVD_DIST_NUM as u32,
+    );
+    sbac.encode_bin(
+        bs,
+        &mut sbac_ctx.mmvd_direction_idx[0],
+        (dir_idx >> 1) as u32,
+    );
+    sbac.encode_bin(
+        bs,
+        &mut sbac_ctx.mmvd_direction_idx[1],
+        (dir_idx & 1) as u32,
+    );
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "mmvd idx ");
+    EVC_TRACE(&mut bs.tracer, mmvd_idx);
+    EVC_TRACE(&mut bs.tracer, " \n");
+}
+
+pub(crate) fn evce_eco_merge_mode_flag(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    merge_mode_flag: u32,
+) {
+    sbac.encode_bin(bs, &mut sbac_ctx.merge_mode_flag[0], merge_mode_flag);
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "merge_mode_flag ");
+    EVC_TRACE(&mut bs.tracer, merge_mode_flag);
+    EVC_TRACE(&mut bs.tracer, " \n");
+}
+
+pub(crate) fn evce_eco_affine_flag(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    flag: u32,
+    ctx: usize,
+) {
+    sbac.encode_bin(bs, &mut sbac_ctx.affine_flag[ctx], flag);
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "affine flag ");
+    EVC_TRACE(&mut bs.tracer, flag);
+    EVC_TRACE(&mut bs.tracer, " \n");
+}
+
+pub(crate) fn evce_eco_affine_mode(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    flag: u32,
+) {
+    sbac.encode_bin(bs, &mut sbac_ctx.affine_mode[0], flag);
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "affine mode ");
+    EVC_TRACE(&mut bs.tracer, flag);
+    EVC_TRACE(&mut bs.tracer, " \n");
+}
+
+pub(crate) fn evce_eco_affine_mrg_idx(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    affine_mrg_idx: u32,
+) {
+    sbac.write_truncate_unary_sym(
+        bs,
+        &mut sbac_ctx.affine_mrg,
+        affine_mrg_idx,
+        AFF_MAX_CAND as u32,
+    );
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "affine merge idx ");
+    EVC_TRACE(&mut bs.tracer, affine_mrg_idx);
+    EVC_TRACE(&mut bs.tracer, " \n");
+}
+
+pub(crate) fn evce_eco_affine_mvp_idx(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    mvp_idx: u32,
+) {
+    sbac.write_truncate_unary_sym(
+        bs,
+        &mut sbac_ctx.affine_mvp_idx,
+        mvp_idx,
+        AFF_MAX_NUM_MVP as u32,
+    );
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "affine mvp idx ");
+    EVC_TRACE(&mut bs.tracer, mvp_idx);
+    EVC_TRACE(&mut bs.tracer, " \n");
+}
+
+pub(crate) fn evce_eco_affine_mvd_flag(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    flag: u32,
+    refi: usize,
+) {
+    sbac.encode_bin(bs, &mut sbac_ctx.affine_mvd_flag[refi], flag);
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "affine mvd flag ");
+    EVC_TRACE(&mut bs.tracer, flag);
+    EVC_TRACE(&mut bs.tracer, " \n");
+}
+
+/* affine motion of one list: reference index, predictor index and the
+mvds of the control points */
+pub(crate) fn evce_eco_affine_mvd(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    lidx: usize,
+    num_refp: u8,
+    refi: i8,
+    mvp_idx: u8,
+    mvd: &[[i16; MV_D]; VER_NUM],
+    vertex_num: usize,
+) {
+    evce_eco_refi(bs, sbac, sbac_ctx, num_refp, refi);
+    evce_eco_affine_mvp_idx(bs, sbac, sbac_ctx, mvp_idx as u32);
+
+    let zero_mvd = mvd[..vertex_num]
+        .iter()
+        .all(|v| v[MV_X] == 0 && v[MV_Y] == 0);
+    evce_eco_affine_mvd_flag(bs, sbac, sbac_ctx, zero_mvd as u32, lidx);
+    if !zero_mvd {
+        for vertex in 0..vertex_num {
+            evce_eco_mvd(bs, sbac, sbac_ctx, &mvd[vertex], 0);
+        }
+    }
+}
+
+pub(crate) fn evce_eco_bi_idx(
+    bs: &mut EvceBsw,
+    sbac: &mut EvceSbac,
+    sbac_ctx: &mut EvcSbacCtx,
+    bi_idx: u8,
+) {
+    if bi_idx == BI_NORMAL {
+        sbac.encode_bin(bs, &mut sbac_ctx.bi_idx[0], 1);
+    } else {
+        sbac.encode_bin(bs, &mut sbac_ctx.bi_idx[0], 0);
+        sbac.encode_bin(
+            bs,
+            &mut sbac_ctx.bi_idx[1],
+            if bi_idx == BI_FL0 { 1 } else { 0 },
+        );
+    }
+
+    EVC_TRACE_COUNTER(&mut bs.tracer);
+    EVC_TRACE(&mut bs.tracer, "bi_idx ");
+    EVC_TRACE(&mut bs.tracer, bi_idx);
+    EVC_TRACE(&mut bs.tracer, " \n");
+}
+
 pub(crate) fn evce_eco_direct_mode_flag(
     bs: &mut EvceBsw,
     sbac: &mut EvceSbac,
@@ -709,13 +1913,17 @@ pub(crate) fn evce_eco_inter_pred_idc(
     sbac_ctx: &mut EvcSbacCtx,
     refi: &[i8],
     slice_type: SliceType,
+    cuw: u16,
+    cuh: u16,
+    admvp: bool,
 ) {
+    let bi_allowed = check_bi_applicability(slice_type, cuw as usize, cuh as usize, admvp);
     if REFI_IS_VALID(refi[REFP_0]) && REFI_IS_VALID(refi[REFP_1]) {
         /* PRED_BI */
-        assert!(check_bi_applicability(slice_type));
+        assert!(bi_allowed);
         sbac.encode_bin(bs, &mut sbac_ctx.inter_dir[0], 0);
     } else {
-        if check_bi_applicability(slice_type) {
+        if bi_allowed {
             sbac.encode_bin(bs, &mut sbac_ctx.inter_dir[0], 1);
         }
 
@@ -810,13 +2018,17 @@ pub(crate) fn evce_eco_mvd(
     sbac: &mut EvceSbac,
     sbac_ctx: &mut EvcSbacCtx,
     mvd: &[i16],
+    mvr_idx: u8,
 ) {
+    /* the difference is signalled in units of the motion vector resolution */
+    let mvd_x = mvd[MV_X] >> mvr_idx;
+    let mvd_y = mvd[MV_Y] >> mvr_idx;
     let mut t0 = 0;
 
-    let mut mv = mvd[MV_X];
-    if mvd[MV_X] < 0 {
+    let mut mv = mvd_x;
+    if mvd_x < 0 {
         t0 = 1;
-        mv = -mvd[MV_X];
+        mv = -mvd_x;
     }
     evce_eco_abs_mvd(bs, sbac, &mut sbac_ctx.mvd[0], mv as u32);
 
@@ -825,10 +2037,10 @@ pub(crate) fn evce_eco_mvd(
     }
 
     t0 = 0;
-    mv = mvd[MV_Y];
-    if mvd[MV_Y] < 0 {
+    mv = mvd_y;
+    if mvd_y < 0 {
         t0 = 1;
-        mv = -mvd[MV_Y];
+        mv = -mvd_y;
     }
 
     evce_eco_abs_mvd(bs, sbac, &mut sbac_ctx.mvd[0], mv as u32);
@@ -863,6 +2075,13 @@ pub(crate) fn evce_eco_coef(
     core_cu_qp_delta_code: u8,
     core_cu_qp_delta_is_coded: &mut bool,
     core_qp_prev_eco: &mut u8,
+    sps_ats_flag: bool,
+    sps_cm_init_flag: bool,
+    sps_adcc_flag: bool,
+    chroma_format_idc: u8,
+    ats_intra_cu: bool,
+    ats_mode: u8,
+    ats_inter_info: u8,
 ) {
     let run_stats = evc_get_run(run_stats);
     let run = [
@@ -891,7 +2110,9 @@ pub(crate) fn evce_eco_coef(
         b_no_cbf,
         cbf_all,
         &run,
+        chroma_format_idc,
     );
+
     if pps_cu_qp_delta_enabled_flag && enc_dqp {
         let cbf_for_dqp = nnz[Y_C] != 0 || nnz[U_C] != 0 || nnz[V_C] != 0;
         if ((!sps_dquant_flag || (core_cu_qp_delta_code == 1 && !(*core_cu_qp_delta_is_coded)))
@@ -904,18 +2125,58 @@ pub(crate) fn evce_eco_coef(
         }
     }
 
+    if sps_ats_flag
+        && pred_mode == PredMode::MODE_INTRA
+        && run[Y_C]
+        && nnz[Y_C] != 0
+        && log2_cuw as usize <= MAX_TR_LOG2_ATS
+        && log2_cuh as usize <= MAX_TR_LOG2_ATS
+    {
+        evce_eco_ats_intra_cu(bs, sbac, sbac_ctx, ats_intra_cu, ats_mode);
+    }
+
+    /* the sub-block transform of an inter residual */
+    if sps_ats_flag
+        && pred_mode != PredMode::MODE_INTRA
+        && (b_no_cbf || run.iter().all(|&r| r))
+        && cbf_all != 0
+        && (evc_check_ats_inter_idx(ATS_INTER_HALF_VER, log2_cuw, log2_cuh)
+            || evc_check_ats_inter_idx(ATS_INTER_HALF_HOR, log2_cuw, log2_cuh))
+    {
+        evce_eco_ats_inter_info(
+            bs,
+            sbac,
+            sbac_ctx,
+            log2_cuw,
+            log2_cuh,
+            ats_inter_info,
+            sps_cm_init_flag,
+        );
+    }
+
     for c in 0..N_C {
         if nnz[c] != 0 && run[c] {
-            let chroma = if c > 0 { 1 } else { 0 };
+            let (w_shift, h_shift) = if c > 0 {
+                (
+                    GET_CHROMA_W_SHIFT(chroma_format_idc) as u8,
+                    GET_CHROMA_H_SHIFT(chroma_format_idc) as u8,
+                )
+            } else {
+                (0, 0)
+            };
+            let (_, _, log2_w, log2_h) =
+                evc_get_ats_inter_trs(ats_inter_info, log2_cuw - w_shift, log2_cuh - h_shift);
             evce_eco_xcoef(
                 bs,
                 sbac,
                 sbac_ctx,
                 &coef.data[c],
-                log2_cuw - chroma,
-                log2_cuh - chroma,
+                log2_w,
+                log2_h,
                 nnz[c],
                 c,
+                sps_cm_init_flag,
+                sps_adcc_flag,
             );
         }
     }
@@ -11,6 +11,12 @@ const RASTER_SEARCH_THD: i16 = 5;
 const REFINE_SEARCH_THD: i16 = 0;
 const BI_STEP: i16 = 5;
 
+/* maximum number of gradient steps of the affine motion estimation */
+const AFF_ITER_NUM: usize = 5;
+/* control point motion vectors are searched in this range (quarter-pel) */
+const AFF_ME_MV_MAX: i32 = (1 << 13) - 1;
+const AFF_ME_MV_MIN: i32 = -(1 << 13);
+
 static test_pos: [[i16; 2]; 9] = [
     [0, 0],
     [-1, -1],
@@ -152,7 +158,8 @@ impl EvcePInter {
             }
         }
 
-        if self.me_level > ME_LEV_IPEL {
+        /* sub-pel positions are only searched for quarter- and half-pel resolution */
+        if self.me_level > ME_LEV_IPEL && self.me_mvr_idx < 2 {
             /* sub-pel ME */
             cost = self.me_spel_pattern(
                 x, y, log2_cuw, log2_cuh, refi, lidx, &gmvp, &mv, &mut mvt, bi, refp,
@@ -183,6 +190,16 @@ impl EvcePInter {
             }
         }
 
+        /* coarser than integer-pel resolution: snap the integer-pel result */
+        if self.me_mvr_idx > 2 {
+            let mv_ipel = [mv[MV_X], mv[MV_Y]];
+            evc_round_mv_to_mvr(mv, self.me_mvr_idx);
+            if mv[MV_X] != mv_ipel[MV_X] || mv[MV_Y] != mv_ipel[MV_Y] {
+                cost_best =
+                    self.me_ipel_cost(x, y, log2_cuw, log2_cuh, refi, lidx, &gmvp, mv, bi, refp);
+            }
+        }
+
         TRACE_ME(
             tracer, x, y, log2_cuw, log2_cuh, refi, lidx, mvp, mv, bi, cost_best, false,
         );
@@ -321,6 +338,7 @@ impl EvcePInter {
                                 (mv_y << 2) - gmvp[MV_Y],
                                 self.num_refp,
                                 refi,
+                                self.me_mvr_idx,
                             );
 
                             if bi != 0 {
@@ -333,8 +351,8 @@ impl EvcePInter {
                             if bi != 0 {
                                 /* get sad */
                                 if let Some(pic_r) = &refp[refi as usize][lidx].pic {
-                                    let frame_r = &pic_r.borrow().frame;
-                                    let plane_r = &frame_r.borrow().planes[Y_C];
+                                    let frame_r = &pic_r.read().unwrap().frame;
+                                    let plane_r = &frame_r.read().unwrap().planes[Y_C];
                                     cost += evce_sad_bi_16b(
                                         mv_x,
                                         mv_y,
@@ -349,11 +367,13 @@ impl EvcePInter {
                                 if let (Some(pic_o), Some(pic_r)) =
                                     (&self.pic_o, &refp[refi as usize][lidx].pic)
                                 {
-                                    let (frame_o, frame_r) =
-                                        (&pic_o.borrow().frame, &pic_r.borrow().frame);
+                                    let (frame_o, frame_r) = (
+                                        &pic_o.read().unwrap().frame,
+                                        &pic_r.read().unwrap().frame,
+                                    );
                                     let (plane_o, plane_r) = (
-                                        &frame_o.borrow().planes[Y_C],
-                                        &frame_r.borrow().planes[Y_C],
+                                        &frame_o.read().unwrap().planes[Y_C],
+                                        &frame_r.read().unwrap().planes[Y_C],
                                     );
                                     cost += evce_sad_16b(
                                         x as usize,
@@ -417,6 +437,7 @@ impl EvcePInter {
                             (mv_y << 2) - gmvp[MV_Y],
                             self.num_refp,
                             refi,
+                            self.me_mvr_idx,
                         );
 
                         if bi != 0 {
@@ -429,8 +450,8 @@ impl EvcePInter {
                         if bi != 0 {
                             /* get sad */
                             if let Some(pic_r) = &refp[refi as usize][lidx].pic {
-                                let frame_r = &pic_r.borrow().frame;
-                                let plane_r = &frame_r.borrow().planes[Y_C];
+                                let frame_r = &pic_r.read().unwrap().frame;
+                                let plane_r = &frame_r.read().unwrap().planes[Y_C];
                                 cost += evce_sad_bi_16b(
                                     mv_x,
                                     mv_y,
@@ -446,9 +467,11 @@ impl EvcePInter {
                                 (&self.pic_o, &refp[refi as usize][lidx].pic)
                             {
                                 let (frame_o, frame_r) =
-                                    (&pic_o.borrow().frame, &pic_r.borrow().frame);
-                                let (plane_o, plane_r) =
-                                    (&frame_o.borrow().planes[Y_C], &frame_r.borrow().planes[Y_C]);
+                                    (&pic_o.read().unwrap().frame, &pic_r.read().unwrap().frame);
+                                let (plane_o, plane_r) = (
+                                    &frame_o.read().unwrap().planes[Y_C],
+                                    &frame_r.read().unwrap().planes[Y_C],
+                                );
                                 cost += evce_sad_16b(
                                     x as usize,
                                     y as usize,
@@ -532,6 +555,7 @@ impl EvcePInter {
                     (mv_y << 2) - gmvp[MV_Y],
                     self.num_refp,
                     refi,
+                    self.me_mvr_idx,
                 );
 
                 /* get MVD cost_best */
@@ -539,9 +563,12 @@ impl EvcePInter {
 
                 /* get sad */
                 if let (Some(pic_o), Some(pic_r)) = (&self.pic_o, &refp[refi as usize][lidx].pic) {
-                    let (frame_o, frame_r) = (&pic_o.borrow().frame, &pic_r.borrow().frame);
-                    let (plane_o, plane_r) =
-                        (&frame_o.borrow().planes[Y_C], &frame_r.borrow().planes[Y_C]);
+                    let (frame_o, frame_r) =
+                        (&pic_o.read().unwrap().frame, &pic_r.read().unwrap().frame);
+                    let (plane_o, plane_r) = (
+                        &frame_o.read().unwrap().planes[Y_C],
+                        &frame_r.read().unwrap().planes[Y_C],
+                    );
                     cost += evce_sad_16b(
                         x as usize,
                         y as usize,
@@ -589,6 +616,7 @@ impl EvcePInter {
                         (mv_y << 2) - gmvp[MV_Y],
                         self.num_refp,
                         refi,
+                        self.me_mvr_idx,
                     );
 
                     /* get MVD cost_best */
@@ -598,9 +626,12 @@ impl EvcePInter {
                     if let (Some(pic_o), Some(pic_r)) =
                         (&self.pic_o, &refp[refi as usize][lidx].pic)
                     {
-                        let (frame_o, frame_r) = (&pic_o.borrow().frame, &pic_r.borrow().frame);
-                        let (plane_o, plane_r) =
-                            (&frame_o.borrow().planes[Y_C], &frame_r.borrow().planes[Y_C]);
+                        let (frame_o, frame_r) =
+                            (&pic_o.read().unwrap().frame, &pic_r.read().unwrap().frame);
+                        let (plane_o, plane_r) = (
+                            &frame_o.read().unwrap().planes[Y_C],
+                            &frame_r.read().unwrap().planes[Y_C],
+                        );
                         cost += evce_sad_16b(
                             x as usize,
                             y as usize,
@@ -670,8 +701,13 @@ impl EvcePInter {
             let mv_y = cy + tbl_search_pattern_hpel_partial[i as usize][1] as i16;
 
             /* get MVD bits */
-            let mut mv_bits =
-                get_mv_bits(mv_x - gmvp[MV_X], mv_y - gmvp[MV_Y], self.num_refp, refi);
+            let mut mv_bits = get_mv_bits(
+                mv_x - gmvp[MV_X],
+                mv_y - gmvp[MV_Y],
+                self.num_refp,
+                refi,
+                self.me_mvr_idx,
+            );
 
             if bi != 0 {
                 mv_bits += self.mot_bits[lidx_r];
@@ -682,8 +718,8 @@ impl EvcePInter {
 
             /* get the interpolated(predicted) image */
             if let Some(pic_r) = &refp[refi as usize][lidx].pic {
-                let frame_r = &pic_r.borrow().frame;
-                let plane_r = &frame_r.borrow().planes[Y_C];
+                let frame_r = &pic_r.read().unwrap().frame;
+                let plane_r = &frame_r.read().unwrap().planes[Y_C];
                 evc_mc_l(
                     mv_x,
                     mv_y,
@@ -693,6 +729,7 @@ impl EvcePInter {
                     &mut self.pred_buf.data[Y_C],
                     cuw as i16,
                     cuh as i16,
+                    self.admvp,
                 );
             }
 
@@ -703,8 +740,8 @@ impl EvcePInter {
             } else {
                 /* get sad */
                 if let Some(pic_o) = &self.pic_o {
-                    let frame_o = &pic_o.borrow().frame;
-                    let plane_o = &frame_o.borrow().planes[Y_C];
+                    let frame_o = &pic_o.read().unwrap().frame;
+                    let plane_o = &frame_o.read().unwrap().planes[Y_C];
                     cost += evce_sad_16i(
                         x as usize,
                         y as usize,
@@ -726,7 +763,7 @@ impl EvcePInter {
 
         /* search upto qpel-level from here*/
         /* search of small diamond pattern */
-        if self.me_level > ME_LEV_HPEL {
+        if self.me_level > ME_LEV_HPEL && self.me_mvr_idx == 0 {
             /* make MV to be absolute coordinate */
             cx = mv[MV_X] + (x << 2);
             cy = mv[MV_Y] + (y << 2);
@@ -736,8 +773,13 @@ impl EvcePInter {
                 let mv_y = cy + tbl_search_pattern_qpel_8point[i as usize][1] as i16;
 
                 /* get MVD bits */
-                let mut mv_bits =
-                    get_mv_bits(mv_x - gmvp[MV_X], mv_y - gmvp[MV_Y], self.num_refp, refi);
+                let mut mv_bits = get_mv_bits(
+                    mv_x - gmvp[MV_X],
+                    mv_y - gmvp[MV_Y],
+                    self.num_refp,
+                    refi,
+                    self.me_mvr_idx,
+                );
 
                 if bi != 0 {
                     mv_bits += self.mot_bits[lidx_r];
@@ -748,8 +790,8 @@ impl EvcePInter {
 
                 /* get the interpolated(predicted) image */
                 if let Some(pic_r) = &refp[refi as usize][lidx].pic {
-                    let frame_r = &pic_r.borrow().frame;
-                    let plane_r = &frame_r.borrow().planes[Y_C];
+                    let frame_r = &pic_r.read().unwrap().frame;
+                    let plane_r = &frame_r.read().unwrap().planes[Y_C];
                     evc_mc_l(
                         mv_x,
                         mv_y,
@@ -759,6 +801,7 @@ impl EvcePInter {
                         &mut self.pred_buf.data[Y_C],
                         cuw as i16,
                         cuh as i16,
+                        self.admvp,
                     );
                 }
 
@@ -770,8 +813,8 @@ impl EvcePInter {
                 } else {
                     /* get sad */
                     if let Some(pic_o) = &self.pic_o {
-                        let frame_o = &pic_o.borrow().frame;
-                        let plane_o = &frame_o.borrow().planes[Y_C];
+                        let frame_o = &pic_o.read().unwrap().frame;
+                        let plane_o = &frame_o.read().unwrap().planes[Y_C];
                         cost += evce_sad_16i(
                             x as usize,
                             y as usize,
@@ -800,6 +843,85 @@ impl EvcePInter {
         cost_best
     }
 
+    /* cost of a single integer-pel motion vector */
+    fn me_ipel_cost(
+        &mut self,
+        x: i16,
+        y: i16,
+        log2_cuw: usize,
+        log2_cuh: usize,
+        refi: i8,
+        lidx: usize,
+        gmvp: &[i16],
+        mv: &[i16],
+        bi: u8,
+        refp: &Vec<Vec<EvcRefP>>,
+    ) -> u32 {
+        let lidx_r = if lidx == REFP_0 { REFP_1 } else { REFP_0 };
+        let cuw = 1usize << log2_cuw;
+        let cuh = 1usize << log2_cuh;
+
+        /* make MV to be global coordinate */
+        let mv_x = mv[MV_X] + (x << 2);
+        let mv_y = mv[MV_Y] + (y << 2);
+
+        /* get MVD bits */
+        let mut mv_bits = get_mv_bits(
+            mv_x - gmvp[MV_X],
+            mv_y - gmvp[MV_Y],
+            self.num_refp,
+            refi,
+            self.me_mvr_idx,
+        );
+
+        if bi != 0 {
+            mv_bits += self.mot_bits[lidx_r];
+        }
+
+        /* get MVD cost */
+        let mut cost = MV_COST(self.lambda_mv, mv_bits);
+
+        /* get the predicted image */
+        if let Some(pic_r) = &refp[refi as usize][lidx].pic {
+            let frame_r = &pic_r.read().unwrap().frame;
+            let plane_r = &frame_r.read().unwrap().planes[Y_C];
+            evc_mc_l(
+                mv_x,
+                mv_y,
+                &plane_r,
+                mv_x,
+                mv_y,
+                &mut self.pred_buf.data[Y_C],
+                cuw as i16,
+                cuh as i16,
+                self.admvp,
+            );
+        }
+
+        if bi != 0 {
+            /* get sad */
+            cost +=
+                evce_sad_bi_16i(cuw, cuh, &self.org_bi.data[Y_C], &self.pred_buf.data[Y_C]) >> 1;
+        } else {
+            /* get sad */
+            if let Some(pic_o) = &self.pic_o {
+                let frame_o = &pic_o.read().unwrap().frame;
+                let plane_o = &frame_o.read().unwrap().planes[Y_C];
+                cost += evce_sad_16i(
+                    x as usize,
+                    y as usize,
+                    cuw,
+                    cuh,
+                    &plane_o.as_region(),
+                    &self.pred_buf.data[Y_C],
+                );
+            }
+            self.mot_bits[lidx] = mv_bits;
+        }
+
+        cost
+    }
+
     fn me_ipel_refinement(
         &mut self,
         x: i16,
@@ -846,6 +968,7 @@ impl EvcePInter {
                     (mv_y << 2) - gmvp[MV_Y],
                     self.num_refp,
                     refi,
+                    self.me_mvr_idx,
                 );
 
                 if bi != 0 {
@@ -858,8 +981,8 @@ impl EvcePInter {
                 if bi != 0 {
                     /* get sad */
                     if let Some(pic_r) = &refp[refi as usize][lidx].pic {
-                        let frame_r = &pic_r.borrow().frame;
-                        let plane_r = &frame_r.borrow().planes[Y_C];
+                        let frame_r = &pic_r.read().unwrap().frame;
+                        let plane_r = &frame_r.read().unwrap().planes[Y_C];
                         cost += evce_sad_bi_16b(
                             mv_x,
                             mv_y,
@@ -874,9 +997,12 @@ impl EvcePInter {
                     if let (Some(pic_o), Some(pic_r)) =
                         (&self.pic_o, &refp[refi as usize][lidx].pic)
                     {
-                        let (frame_o, frame_r) = (&pic_o.borrow().frame, &pic_r.borrow().frame);
-                        let (plane_o, plane_r) =
-                            (&frame_o.borrow().planes[Y_C], &frame_r.borrow().planes[Y_C]);
+                        let (frame_o, frame_r) =
+                            (&pic_o.read().unwrap().frame, &pic_r.read().unwrap().frame);
+                        let (plane_o, plane_r) = (
+                            &frame_o.read().unwrap().planes[Y_C],
+                            &frame_r.read().unwrap().planes[Y_C],
+                        );
                         cost += evce_sad_16b(
                             x as usize,
                             y as usize,
@@ -910,4 +1036,244 @@ impl EvcePInter {
 
         cost_best
     }
+
+    /* affine motion estimation of one list: the parameters of the model are
+    refined by the optical flow equation on the current prediction, followed
+    by a quarter-pel refinement of each control point */
+    pub(crate) fn pinter_affine_me_gradient(
+        &mut self,
+        x: i16,
+        y: i16,
+        log2_cuw: usize,
+        log2_cuh: usize,
+        refi: i8,
+        lidx: usize,
+        mvp: &[[i16; MV_D]; VER_NUM],
+        mv: &mut [[i16; MV_D]; VER_NUM],
+        vertex_num: usize,
+        refp: &Vec<Vec<EvcRefP>>,
+    ) -> u32 {
+        let cuw = 1 << log2_cuw;
+        let cuh = 1 << log2_cuh;
+
+        for v in mv.iter_mut() {
+            v[MV_X] = EVC_CLIP3(AFF_ME_MV_MIN, AFF_ME_MV_MAX, v[MV_X] as i32) as i16;
+            v[MV_Y] = EVC_CLIP3(AFF_ME_MV_MIN, AFF_ME_MV_MAX, v[MV_Y] as i32) as i16;
+        }
+        let mut cost_best =
+            self.affine_me_cost(x, y, cuw, cuh, refi, lidx, mvp, mv, vertex_num, refp);
+
+        for _ in 0..AFF_ITER_NUM {
+            let dmv = match self.affine_me_delta(x as usize, y as usize, cuw, cuh, vertex_num) {
+                Some(dmv) => dmv,
+                None => break,
+            };
+            if dmv[..vertex_num]
+                .iter()
+                .all(|d| d[MV_X] == 0 && d[MV_Y] == 0)
+            {
+                break;
+            }
+
+            let mut mvt = *mv;
+            for vertex in 0..vertex_num {
+                for c in 0..MV_D {
+                    mvt[vertex][c] = EVC_CLIP3(
+                        AFF_ME_MV_MIN,
+                        AFF_ME_MV_MAX,
+                        mv[vertex][c] as i32 + dmv[vertex][c] as i32,
+                    ) as i16;
+                }
+            }
+
+            let cost = self.affine_me_cost(x, y, cuw, cuh, refi, lidx, mvp, &mvt, vertex_num, refp);
+            if cost < cost_best {
+                cost_best = cost;
+                *mv = mvt;
+            } else {
+                break;
+            }
+        }
+
+        for vertex in 0..vertex_num {
+            let mvc = *mv;
+            for pos in &tbl_search_pattern_qpel_8point[..4] {
+                let mut mvt = mvc;
+                mvt[vertex][MV_X] = EVC_CLIP3(
+                    AFF_ME_MV_MIN,
+                    AFF_ME_MV_MAX,
+                    mvc[vertex][MV_X] as i32 + pos[0] as i32,
+                ) as i16;
+                mvt[vertex][MV_Y] = EVC_CLIP3(
+                    AFF_ME_MV_MIN,
+                    AFF_ME_MV_MAX,
+                    mvc[vertex][MV_Y] as i32 + pos[1] as i32,
+                ) as i16;
+
+                let cost =
+                    self.affine_me_cost(x, y, cuw, cuh, refi, lidx, mvp, &mvt, vertex_num, refp);
+                if cost < cost_best {
+                    cost_best = cost;
+                    *mv = mvt;
+                }
+            }
+        }
+
+        cost_best
+    }
+
+    /* luma SATD of the affine prediction plus the cost of the control point
+    mvds, the prediction is left in pred[PRED_NUM] */
+    pub(crate) fn affine_me_cost(
+        &mut self,
+        x: i16,
+        y: i16,
+        cuw: usize,
+        cuh: usize,
+        refi: i8,
+        lidx: usize,
+        mvp: &[[i16; MV_D]; VER_NUM],
+        mv: &[[i16; MV_D]; VER_NUM],
+        vertex_num: usize,
+        refp: &Vec<Vec<EvcRefP>>,
+    ) -> u32 {
+        let mut refi_l = [REFI_INVALID; REFP_NUM];
+        let mut mv_l = [[[0i16; MV_D]; VER_NUM]; REFP_NUM];
+        refi_l[lidx] = refi;
+        mv_l[lidx] = *mv;
+
+        evc_affine_mc(
+            x,
+            y,
+            self.pic_w,
+            self.pic_h,
+            cuw as i16,
+            cuh as i16,
+            &refi_l,
+            &mv_l,
+            refp,
+            &mut self.pred[InterPredDir::PRED_NUM as usize],
+            vertex_num,
+        );
+
+        let mv_bits = get_affine_mv_bits(mv, mvp, vertex_num, self.num_refp, refi);
+        let mut cost = MV_COST(self.lambda_mv, mv_bits);
+
+        if let Some(pic_o) = &self.pic_o {
+            let frame_o = &pic_o.read().unwrap().frame;
+            let plane_o = &frame_o.read().unwrap().planes[Y_C];
+            cost += evce_satd_16b(
+                x as usize,
+                y as usize,
+                cuw,
+                cuh,
+                &plane_o.as_region(),
+                &self.pred[InterPredDir::PRED_NUM as usize][0].data[Y_C],
+            );
+        }
+
+        cost
+    }
+
+    /* least squares solution of the control point mv update from the
+    gradient of the prediction in pred[PRED_NUM] and the prediction error */
+    fn affine_me_delta(
+        &self,
+        x: usize,
+        y: usize,
+        cuw: usize,
+        cuh: usize,
+        vertex_num: usize,
+    ) -> Option<[[i16; MV_D]; VER_NUM]> {
+        let pred = &self.pred[InterPredDir::PRED_NUM as usize][0].data[Y_C];
+        let n = vertex_num << 1;
+        let mut a = [[0f64; 7]; 6];
+
+        let pic_o = self.pic_o.as_ref()?;
+        let frame_o = &pic_o.read().unwrap().frame;
+        let plane_o = &frame_o.read().unwrap().planes[Y_C];
+        let org = plane_o.as_region();
+
+        let p = |i: usize, j: usize| pred[j * cuw + i] as f64;
+        for j in 1..cuh - 1 {
+            for i in 1..cuw - 1 {
+                let gx = (p(i + 1, j - 1) + 2.0 * p(i + 1, j) + p(i + 1, j + 1)
+                    - p(i - 1, j - 1)
+                    - 2.0 * p(i - 1, j)
+                    - p(i - 1, j + 1))
+                    / 8.0;
+                let gy = (p(i - 1, j + 1) + 2.0 * p(i, j + 1) + p(i + 1, j + 1)
+                    - p(i - 1, j - 1)
+                    - 2.0 * p(i, j - 1)
+                    - p(i + 1, j - 1))
+                    / 8.0;
+                let err = org[y + j][x + i] as f64 - p(i, j);
+
+                /* the motion is constant inside a 4x4 sub-block */
+                let cx = (((i >> 2) << 2) + 2) as f64;
+                let cy = (((j >> 2) << 2) + 2) as f64;
+                let c = if vertex_num == 2 {
+                    [gx, gx * cx + gy * cy, gy, gy * cx - gx * cy, 0.0, 0.0]
+                } else {
+                    [gx, gx * cx, gy, gy * cx, gx * cy, gy * cy]
+                };
+
+                for r in 0..n {
+                    for k in 0..n {
+                        a[r][k] += c[r] * c[k];
+                    }
+                    a[r][n] += c[r] * err;
+                }
+            }
+        }
+
+        let para = solve_linear_equation(&mut a, n)?;
+        let qpel = |v: f64| (v * 4.0).round().max(-256.0).min(256.0) as i16;
+        let (w, h) = (cuw as f64, cuh as f64);
+        let mut dmv = [[0i16; MV_D]; VER_NUM];
+        dmv[0] = [qpel(para[0]), qpel(para[2])];
+        if vertex_num == 2 {
+            dmv[1] = [qpel(para[0] + para[1] * w), qpel(para[2] + para[3] * w)];
+        } else {
+            dmv[1] = [qpel(para[0] + para[1] * w), qpel(para[2] + para[3] * w)];
+            dmv[2] = [qpel(para[0] + para[4] * h), qpel(para[2] + para[5] * h)];
+        }
+
+        Some(dmv)
+    }
+}
+
+/* gaussian elimination with partial pivoting of an augmented n x (n + 1)
+matrix */
+fn solve_linear_equation(a: &mut [[f64; 7]; 6], n: usize) -> Option<[f64; 6]> {
+    for i in 0..n {
+        let mut pivot = i;
+        for r in i + 1..n {
+            if a[r][i].abs() > a[pivot][i].abs() {
+                pivot = r;
+            }
+        }
+        if a[pivot][i].abs() < 1e-6 {
+            return None;
+        }
+        a.swap(i, pivot);
+
+        for r in i + 1..n {
+            let f = a[r][i] / a[i][i];
+            for c in i..=n {
+                a[r][c] -= f * a[i][c];
+            }
+        }
+    }
+
+    let mut x = [0f64; 6];
+    for i in (0..n).rev() {
+        let mut s = a[i][n];
+        for c in i + 1..n {
+            s -= a[i][c] * x[c];
+        }
+        x[i] = s / a[i][i];
+    }
+
+    Some(x)
 }
@@ -1,15 +1,18 @@
+pub(crate) mod alf;
 pub(crate) mod bsw;
 pub(crate) mod eco;
 pub(crate) mod me;
 pub(crate) mod mode;
 pub(crate) mod pinter;
 pub(crate) mod pintra;
+pub(crate) mod rc;
 pub(crate) mod sad;
 pub(crate) mod sbac;
 pub(crate) mod tbl;
 pub(crate) mod tq;
 pub(crate) mod util;
 
+use super::alf::*;
 use super::api::frame::*;
 use super::api::*;
 use super::def::*;
@@ -20,19 +23,22 @@ use super::tbl::*;
 use super::tracer::*;
 use super::util::*;
 
+use self::alf::*;
 use bsw::*;
 use eco::*;
 use mode::*;
 use pinter::*;
 use pintra::*;
+use rc::*;
+use sad::*;
 use sbac::*;
 use tbl::*;
 use util::*;
 
+use crate::region::Area;
 use crate::tracer::{Tracer, OPEN_TRACE};
-use std::cell::RefCell;
-use std::rc::Rc;
-use thiserror::private::DisplayAsDisplay;
+use std::sync::{Arc, RwLock};
+use std::thread;
 
 /* support RDOQ */
 pub(crate) const SCALE_BITS: usize = 15; /* Inherited from TMuC, pressumably for fractional bit estimates in RDOQ */
@@ -97,19 +103,106 @@ pub(crate) const LIST_NUM: usize = 1;
 pub(crate) const ORG_MAX_NUM_MVP: u8 = 4;
 
 /*****************************************************************************
- * bi-prediction type
+ * bi-prediction type, following BI_NON ~ BI_FL1
  *****************************************************************************/
-pub(crate) const BI_NON: u8 = 0;
-pub(crate) const BI_NORMAL: u8 = 1;
 pub(crate) const BI_ITER: u8 = 4;
 
+/* derive the slice QP from the base QP and the slice depth */
+pub(crate) fn evce_slice_qp(param: &EncoderConfig, base_qp: u8, slice_depth: u8) -> u8 {
+    let qp_adapt_param = if param.max_b_frames == 0 {
+        if param.max_key_frame_interval == 1 {
+            &qp_adapt_param_ai
+        } else {
+            &qp_adapt_param_ld
+        }
+    } else {
+        &qp_adapt_param_ra
+    };
+
+    let mut qp = base_qp as i8; //EVC_CLIP3(0, MAX_QUANT, (self.param.qp_incread_frame != 0 && (int)(self.poc.poc_val) >= self.param.qp_incread_frame) ? self.qp + 1.0 : self.qp);
+
+    if !param.disable_hgop {
+        qp += qp_adapt_param[slice_depth as usize].qp_offset_layer;
+        let dqp_offset = qp as f64 * qp_adapt_param[slice_depth as usize].qp_offset_model_scale
+            + qp_adapt_param[slice_depth as usize].qp_offset_model_offset
+            + 0.5;
+
+        let qp_offset = EVC_CLIP3(0.0, 3.0, dqp_offset).floor() as i8;
+        qp += qp_offset;
+    }
+
+    EVC_CLIP3(0, MAX_QUANT as i8, qp) as u8
+}
+
+/* derive the chroma QPs of the slice and the lambdas from the slice QP */
+fn evce_set_slice_qp(
+    sh: &mut EvcSh,
+    qp: u8,
+    evc_tbl_qp_chroma_dynamic_ext: &[Vec<i8>],
+    lambda: &mut [f64; 3],
+    sqrt_lambda: &mut [f64; 3],
+    dist_chroma_weight: &mut [f64; 2],
+) {
+    sh.qp = qp;
+    sh.qp_u = EVC_CLIP3(-6 * (BIT_DEPTH as i8 - 8), 57, sh.qp as i8 + sh.qp_u_offset) as u8;
+    sh.qp_v = EVC_CLIP3(-6 * (BIT_DEPTH as i8 - 8), 57, sh.qp as i8 + sh.qp_v_offset) as u8;
+
+    let qp_l_i = sh.qp as i8;
+    lambda[0] = 0.57 * (2.0f64).powf((qp_l_i - 12) as f64 / 3.0);
+    let qp_c_i =
+        evc_tbl_qp_chroma_dynamic_ext[0][EVC_TBL_CHROMA_QP_OFFSET as usize + sh.qp_u as usize];
+    dist_chroma_weight[0] = (2.0f64).powf((qp_l_i - qp_c_i) as f64 / 3.0);
+    let qp_c_i =
+        evc_tbl_qp_chroma_dynamic_ext[1][EVC_TBL_CHROMA_QP_OFFSET as usize + sh.qp_v as usize];
+    dist_chroma_weight[1] = (2.0f64).powf((qp_l_i - qp_c_i) as f64 / 3.0);
+    lambda[1] = lambda[0] / dist_chroma_weight[0];
+    lambda[2] = lambda[0] / dist_chroma_weight[1];
+    sqrt_lambda[0] = lambda[0].sqrt();
+    sqrt_lambda[1] = lambda[1].sqrt();
+    sqrt_lambda[2] = lambda[2].sqrt();
+}
+
+/* encode consecutive slices of a picture */
+fn evce_encode_slices(
+    tasks: &mut [EvceTask],
+    sps: &EvcSps,
+    pps: &EvcPps,
+    nalu: &EvcNalu,
+    mut tracer: Option<Tracer>,
+) -> Option<Tracer> {
+    for task in tasks.iter_mut() {
+        task.bs.tracer = tracer.take();
+        task.encode_slice(sps, pps, nalu);
+        tracer = task.bs.tracer.take();
+    }
+    tracer
+}
+
+/* reset the motion of a picture before it is encoded */
+fn evce_clear_motion_maps(
+    map_refi: &RwLock<Vec<[i8; REFP_NUM]>>,
+    map_mv: &RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>,
+) {
+    let (mut map_refi, mut map_mv) = (map_refi.write().unwrap(), map_mv.write().unwrap());
+    for v in &mut *map_refi {
+        v[REFP_0] = -1;
+        v[REFP_1] = -1;
+    }
+    for v in &mut *map_mv {
+        v[REFP_0][MV_X] = 0;
+        v[REFP_0][MV_Y] = 0;
+        v[REFP_1][MV_X] = 0;
+        v[REFP_1][MV_Y] = 0;
+    }
+}
+
 /*****************************************************************************
  * original picture buffer structure
  *****************************************************************************/
 #[derive(Default)]
 pub(crate) struct EvcePicOrg {
     /* original picture store */
-    pic: Rc<RefCell<EvcPic>>,
+    pic: Arc<RwLock<EvcPic>>,
     /* input picture count */
     pic_icnt: usize,
     /* be used for encoding input */
@@ -124,6 +217,26 @@ pub(crate) struct EvceDQP {
     cu_qp_delta_code: u8,
 }
 
+/* split decisions of a block position kept within an LCU for the save & load technique */
+#[derive(Copy, Clone)]
+pub(crate) struct EvceBefData {
+    visit: bool,
+    nosplit: u8,
+    split: u8,
+    split_cost: [f64; MAX_SPLIT_NUM],
+}
+
+impl Default for EvceBefData {
+    fn default() -> Self {
+        EvceBefData {
+            visit: false,
+            nosplit: 0,
+            split: 0,
+            split_cost: [MAX_COST; MAX_SPLIT_NUM],
+        }
+    }
+}
+
 #[derive(Default)]
 pub(crate) struct EvceRdoqEst {
     cbf_all: [i64; 2],
@@ -133,6 +246,10 @@ pub(crate) struct EvceRdoqEst {
     run: [[i32; 2]; NUM_CTX_CC_RUN],
     level: [[i32; 2]; NUM_CTX_CC_LEVEL],
     last: [[i32; 2]; NUM_CTX_CC_LAST],
+    sig_coeff: [[[i32; 2]; NUM_CTX_SIG_COEFF_FLAG]; NUM_SIG_COEFF_SET],
+    gtx: [[i32; 2]; NUM_CTX_GTX],
+    last_sig_coeff_x: [[i32; 2]; NUM_CTX_LAST_SIG_COEFF],
+    last_sig_coeff_y: [[i32; 2]; NUM_CTX_LAST_SIG_COEFF],
 }
 
 /*****************************************************************************
@@ -145,12 +262,15 @@ pub(crate) struct EvceCore {
     /* temporary coefficient buffer */
     ctmp: CUBuffer<i16>, //[[i16;MAX_CU_DIM];N_C]
     /* neighbor pixel buffer for intra prediction */
-    nb: Aligned<[pel; (MAX_CU_SIZE << 3) + 3 + 1]>, // [N_C][MAX_CU_SIZE*4+1];
+    nb: Aligned<[pel; ((MAX_CU_SIZE << 2) + 1) * N_C + 1]>, // [N_C][MAX_CU_SIZE*4+1];
+    /* neighbor pixel lines for intra prediction of main profile */
+    nb_lines: Aligned<[EvcNbLines; N_C]>,
 
     /* CU data for RDO */
     cu_data_best: Vec<Vec<EvceCUData>>, //[[EvceCUData; MAX_CU_DEPTH]; MAX_CU_DEPTH],
     cu_data_temp: Vec<Vec<EvceCUData>>, //[[EvceCUData; MAX_CU_DEPTH]; MAX_CU_DEPTH],
     dqp_data: Vec<Vec<EvceDQP>>,        //[[EvceDQP; MAX_CU_DEPTH]; MAX_CU_DEPTH],
+    bef_data: Vec<Vec<Vec<EvceBefData>>>, //[[[EvceBefData; MAX_CU_CNT_IN_LCU]; MAX_CU_DEPTH]; MAX_CU_DEPTH],
 
     /* current encoding LCU number */
     lcu_num: u16,
@@ -192,10 +312,17 @@ pub(crate) struct EvceCore {
     avail_cu: u16,
     /* Left, right availability of current CU */
     avail_lr: u16,
+    /* contexts of the flags derived from the neighbour CUs */
+    ctx_flags: [usize; NUM_CNID],
+    /* motion of previously coded CUs for HMVP */
+    history_buffer: EvcHistoryBuffer,
     /* CU mode */
     cu_mode: PredMode,
+    /* prediction modes allowed for current CU */
+    mode_cons: ModeCons,
     /* intra prediction mode */
     mpm_b_list: &'static [u8],
+    mpm_list: Vec<u8>,
     ipm: [IntraPredDir; 2],
     /* skip flag for MODE_INTER */
     skip_flag: bool,
@@ -210,6 +337,11 @@ pub(crate) struct EvceCore {
     log2_cuh: u8,
     /* number of non-zero coefficient */
     nnz: [u16; N_C],
+    /* adaptive transform selection of current CU: the intra flag with its
+    transforms packed as (h << 1) | v, and the inter sub-block transform */
+    ats_intra_cu: bool,
+    ats_mode: u8,
+    ats_inter_info: u8,
 
     /* bitstream structure for RDO */
     bs_temp: EvceBsw,
@@ -248,42 +380,37 @@ pub(crate) struct EvceCore {
     evc_tbl_qp_chroma_dynamic_ext: Vec<Vec<i8>>, // [[i8; MAX_QP_TABLE_SIZE_EXT]; 2],
 }
 impl EvceCore {
-    pub(crate) fn new() -> Self {
-        let mut evc_tbl_qp_chroma_dynamic_ext = vec![];
-        /*if sps.chroma_qp_table_struct.chroma_qp_table_present_flag {
-            evc_derived_chroma_qp_mapping_tables(
-                &sps.chroma_qp_table_struct,
-                &mut core.evc_tbl_qp_chroma_dynamic_ext,
-            );
-        } else*/
-        {
-            evc_tbl_qp_chroma_dynamic_ext.push(evc_tbl_qp_chroma_ajudst_base.to_vec());
-            evc_tbl_qp_chroma_dynamic_ext.push(evc_tbl_qp_chroma_ajudst_base.to_vec());
-        }
+    pub(crate) fn new(chroma_format_idc: u8, iqt: bool) -> Self {
+        let evc_tbl_qp_chroma_dynamic_ext =
+            evc_default_chroma_qp_mapping_tables(chroma_format_idc, iqt);
 
         let mut cu_data_best = Vec::with_capacity(MAX_CU_DEPTH);
         let mut cu_data_temp = Vec::with_capacity(MAX_CU_DEPTH);
         let mut dqp_data = Vec::with_capacity(MAX_CU_DEPTH);
         let mut dqp_curr_best = Vec::with_capacity(MAX_CU_DEPTH);
         let mut dqp_next_best = Vec::with_capacity(MAX_CU_DEPTH);
+        let mut bef_data = Vec::with_capacity(MAX_CU_DEPTH);
         for i in 0..MAX_CU_DEPTH {
             let mut best = Vec::with_capacity(MAX_CU_DEPTH);
             let mut temp = Vec::with_capacity(MAX_CU_DEPTH);
             let mut data = Vec::with_capacity(MAX_CU_DEPTH);
             let mut curr = Vec::with_capacity(MAX_CU_DEPTH);
             let mut next = Vec::with_capacity(MAX_CU_DEPTH);
+            let mut bef = Vec::with_capacity(MAX_CU_DEPTH);
             for j in 0..MAX_CU_DEPTH {
                 best.push(EvceCUData::new(i as u8, j as u8));
                 temp.push(EvceCUData::new(i as u8, j as u8));
                 data.push(EvceDQP::default());
                 curr.push(EvceDQP::default());
                 next.push(EvceDQP::default());
+                bef.push(vec![EvceBefData::default(); MAX_CU_CNT_IN_LCU]);
             }
             cu_data_best.push(best);
             cu_data_temp.push(temp);
             dqp_data.push(data);
             dqp_curr_best.push(curr);
             dqp_next_best.push(next);
+            bef_data.push(bef);
         }
 
         EvceCore {
@@ -292,12 +419,14 @@ impl EvceCore {
             dqp_data,
             dqp_curr_best,
             dqp_next_best,
+            bef_data,
 
             delta_dist: [0; N_C],
             dist_nofilt: [0; N_C],
             dist_filter: [0; N_C],
 
             evc_tbl_qp_chroma_dynamic_ext,
+            mpm_list: vec![0; IPD_CNT],
             ..Default::default()
         }
     }
@@ -311,6 +440,121 @@ impl EvceCore {
     }
 }
 
+/******************************************************************************
+ * TASK used for encoding a slice.
+ *
+ * Each slice covers one tile and is encoded into its own maps and bitstream,
+ * so that the slices of a picture can be handed over to worker threads.
+ *****************************************************************************/
+pub(crate) struct EvceTask {
+    /* address of core structure */
+    core: EvceCore,
+
+    /* address indicating current encoding, list0, list1 and original pictures */
+    pic: Vec<Option<Arc<RwLock<EvcPic>>>>, /* the last one is for original */
+    /* picture the tile is reconstructed to, when the slice is not the first one */
+    pic_tile: Option<Arc<RwLock<EvcPic>>>,
+    /* reference picture (0: foward, 1: backward) */
+    refp: Vec<Vec<EvcRefP>>,
+    /* number of reference pictures */
+    num_refp: [u8; REFP_NUM],
+    /* SBAC */
+    sbac_enc: EvceSbac,
+    sbac_ctx: EvcSbacCtx,
+    /* bitstream structure of the slice */
+    bs: EvceBsw,
+    /* slice header */
+    sh: EvcSh,
+    /* tile covered by the slice */
+    tile: EvcTile,
+
+    /* SPS/PPS flags used while encoding CUs */
+    sps_btt_flag: bool,
+    sps_suco_flag: bool,
+    sps_admvp_flag: bool,
+    sps_hmvp_flag: bool,
+    sps_eipd_flag: bool,
+    sps_affine_flag: bool,
+    sps_dmvr_flag: bool,
+    sps_amvr_flag: bool,
+    sps_mmvd_flag: bool,
+    sps_addb_flag: bool,
+    sps_iqt_flag: bool,
+    sps_ats_flag: bool,
+    sps_cm_init_flag: bool,
+    sps_adcc_flag: bool,
+    /* 0 for monochrome pictures, which carry no chroma */
+    chroma_format_idc: u8,
+    log2_diff_ctu_size_max_suco_cb_size: u8,
+    log2_diff_max_suco_min_suco_cb_size: u8,
+    sps_dquant_flag: bool,
+    pps_cu_qp_delta_enabled_flag: bool,
+    pps_cu_qp_delta_area: u8,
+    pps_constrained_intra_pred_flag: bool,
+    /* block sizes allowed by the binary and ternary splits */
+    split_tbl: EvcSplitTbl,
+
+    /* picture order count value */
+    poc_val: i32,
+    /* quantization value of current encoding slice */
+    qp: u8,
+    /* current encoding slice number in one picture */
+    slice_num: usize,
+    /* current slice type */
+    slice_type: SliceType,
+    gop_size: usize,
+    /* encoding picture width */
+    w: u16,
+    /* encoding picture height */
+    h: u16,
+    /* maximum CU width and height */
+    max_cuwh: u16,
+    /* log2 of maximum CU width and height */
+    log2_max_cuwh: u8,
+    /* minimum CU width and height */
+    min_cuwh: u16,
+    /* picture width in LCU unit */
+    w_lcu: u16,
+    /* picture width in SCU unit */
+    w_scu: u16,
+    /* picture height in SCU unit */
+    h_scu: u16,
+    /* log2 of SCU count in a LCU row */
+    log2_culine: u8,
+    /* mode decision structure */
+    mode: EvceMode,
+    /* intra prediction analysis */
+    pintra: EvcePIntra,
+    /* inter prediction analysis */
+    pinter: EvcePInter,
+    /* MAPS *******************************************************************/
+    /* cu data for current LCU */
+    map_cu_data: Vec<EvceCUData>,
+    /* CU map (width in SCU x height in SCU) of raster scan order in a frame */
+    map_scu: Vec<MCU>,
+    /* LCU split information */
+    map_split: Vec<LcuSplitMode>, // for deblocking
+    /* LCU split unit coding order information */
+    map_suco: Vec<LcuSucoFlag>, // for deblocking
+    /* map for encoded motion vectors in SCU */
+    map_mv: Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    /* map for motion vectors before decoder-side refinement */
+    map_unrefined_mv: Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    /* map for reference indices */
+    map_refi: Option<Arc<RwLock<Vec<[i8; REFP_NUM]>>>>,
+    /* map for intra pred mode */
+    map_ipm: Vec<IntraPredDir>,
+    /* map for affine model size and position of the CUs */
+    map_affine: Vec<u32>,
+
+    map_depth: Vec<i8>,
+
+    pic_dbk: Option<Arc<RwLock<EvcPic>>>, //one picture that arranges cu pixels and neighboring pixels for deblocking (just to match the interface of deblocking functions)
+    lambda: [f64; 3],
+    sqrt_lambda: [f64; 3],
+    dist_chroma_weight: [f64; 2],
+}
+
 /******************************************************************************
  * CONTEXT used for encoding process.
  *
@@ -331,19 +575,24 @@ pub(crate) struct EvceCtx {
     gop_size: usize,
 
     sps_pps_once: bool,
-
-    /* address of core structure */
-    core: EvceCore,
+    /* identifier of the next APS */
+    aps_id: u8,
+
+    /* one task per slice of a picture */
+    tasks: Vec<EvceTask>,
+    /* maximum number of threads encoding slices, including the caller */
+    threads: usize,
+    /* tiles of a picture, one for each slice */
+    tiles: Vec<EvcTile>,
+    /* tile index of each LCU, if tile boundaries are not deblocked */
+    map_tidx: Option<Vec<u16>>,
 
     /* address indicating current encoding, list0, list1 and original pictures */
-    pic: Vec<Option<Rc<RefCell<EvcPic>>>>, /* the last one is for original */
+    pic: Vec<Option<Arc<RwLock<EvcPic>>>>, /* the last one is for original */
     /* reference picture (0: foward, 1: backward) */
-    refp: Vec<Vec<EvcRefP>>, // Rc<RefCell<Vec<Vec<EvcRefP>>>>  refp[MAX_NUM_REF_PICS][REFP_NUM];
+    refp: Vec<Vec<EvcRefP>>, // Arc<RwLock<Vec<Vec<EvcRefP>>>>  refp[MAX_NUM_REF_PICS][REFP_NUM];
     /* encoding parameter */
     param: EncoderConfig,
-    /* SBAC */
-    sbac_enc: EvceSbac,
-    sbac_ctx: EvcSbacCtx,
     /* debug tracer */
     tracer: Option<Tracer>,
     /* bitstream structure */
@@ -361,6 +610,8 @@ pub(crate) struct EvceCtx {
     sh: EvcSh,
     /* reference picture manager */
     rpm: EvcPm,
+    /* rate control */
+    rc: EvceRc,
     /* quantization value of current encoding slice */
     qp: u8,
     /* encoding picture width */
@@ -391,80 +642,52 @@ pub(crate) struct EvceCtx {
     force_ignored_cnt: usize,
     /* initial frame return number(delayed input count) due to B picture or Forecast */
     frm_rnum: isize,
-    /* current encoding slice number in one picture */
-    slice_num: usize,
-    /* first mb number of current encoding slice in one picture */
-    sl_first_mb: i32,
     /* current slice type */
     slice_type: SliceType,
     /* slice depth for current picture */
     slice_depth: u8,
     /* flag whether current picture is refecened picture or not */
     slice_ref_flag: bool,
+    /* the current picture is kept as long-term reference picture */
+    lt_ref_pin: bool,
     /* distance between ref pics in addition to closest ref ref pic in LD*/
     ref_pic_gap_length: u32,
-    /* maximum CU depth */
-    max_cud: u8,
 
     /* last coded intra picture's picture order count */
     last_intra_poc: i32,
-    /* maximum CU width and height */
-    max_cuwh: u16,
     /* log2 of maximum CU width and height */
     log2_max_cuwh: u8,
-    /* minimum CU width and height */
-    min_cuwh: u16,
-    /* log2 of minimum CU width and height */
-    log2_min_cuwh: u8,
-    /* total count of remained LCU for encoding one picture. if a picture is
-    encoded properly, this value should reach to zero */
-    lcu_cnt: u32,
     /* picture width in LCU unit */
     w_lcu: u16,
     /* picture height in LCU unit */
     h_lcu: u16,
-    /* picture size in LCU unit (= w_lcu * h_lcu) */
-    f_lcu: u32,
     /* picture width in SCU unit */
     w_scu: u16,
     /* picture height in SCU unit */
     h_scu: u16,
-    /* picture size in SCU unit (= w_scu * h_scu) */
-    f_scu: u32,
-    /* log2 of SCU count in a LCU row */
-    log2_culine: u8,
-    /* log2 of SCU count in a LCU (== log2_culine * 2) */
-    log2_cudim: u8,
-    /* mode decision structure */
-    mode: EvceMode,
-    /* intra prediction analysis */
-    pintra: EvcePIntra,
-    /* inter prediction analysis */
-    pinter: EvcePInter,
-    /* MAPS *******************************************************************/
-    /* cu data for current LCU */
-    map_cu_data: Vec<EvceCUData>,
+    /* MAPS of the picture, merged from the slices for deblocking ************/
     /* CU map (width in SCU x height in SCU) of raster scan order in a frame */
     map_scu: Vec<MCU>,
     /* LCU split information */
-    map_split: Vec<LcuSplitMode>, // for deblocking
+    map_split: Vec<LcuSplitMode>,
+    /* LCU split unit coding order information */
+    map_suco: Vec<LcuSucoFlag>,
     /* map for encoded motion vectors in SCU */
-    map_mv: Option<Rc<RefCell<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    map_mv: Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    /* map for motion vectors before decoder-side refinement */
+    map_unrefined_mv: Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
     /* map for reference indices */
-    map_refi: Option<Rc<RefCell<Vec<[i8; REFP_NUM]>>>>,
-    /* map for intra pred mode */
-    map_ipm: Vec<IntraPredDir>,
-
-    map_depth: Vec<i8>,
+    map_refi: Option<Arc<RwLock<Vec<[i8; REFP_NUM]>>>>,
+    /* chroma QP mapping tables of the sequence */
+    evc_tbl_qp_chroma_dynamic_ext: Vec<Vec<i8>>,
 
-    pic_dbk: Option<Rc<RefCell<EvcPic>>>, //one picture that arranges cu pixels and neighboring pixels for deblocking (just to match the interface of deblocking functions)
     lambda: [f64; 3],
     sqrt_lambda: [f64; 3],
     dist_chroma_weight: [f64; 2],
 }
 
-impl EvceCtx {
-    pub(crate) fn new(cfg: &Config) -> Self {
+impl EvceTask {
+    fn new(param: &EncoderConfig) -> Self {
         let mut refp = Vec::with_capacity(MAX_NUM_REF_PICS);
         for _ in 0..MAX_NUM_REF_PICS {
             let mut refp1d = Vec::with_capacity(REFP_NUM);
@@ -474,18 +697,11 @@ impl EvceCtx {
             refp.push(refp1d);
         }
 
-        let core = EvceCore::new();
-
-        let param = cfg.enc.unwrap();
-
         let w = param.width as u16;
         let h = param.height as u16;
-        let f = w as u32 * h as u32;
         let max_cuwh = 64;
         let min_cuwh = 1 << 2;
-        let log2_min_cuwh = 2;
         let log2_max_cuwh = 6;
-        let max_cud = log2_max_cuwh - MIN_CU_LOG2 as u8;
         let w_lcu = (w + max_cuwh - 1) >> 6;
         let h_lcu = (h + max_cuwh - 1) >> 6;
         let f_lcu = w_lcu as u32 * h_lcu as u32;
@@ -493,23 +709,128 @@ impl EvceCtx {
         let h_scu = (h + ((1 << MIN_CU_LOG2) - 1)) >> MIN_CU_LOG2;
         let f_scu = w_scu as u32 * h_scu as u32;
         let log2_culine = log2_max_cuwh - MIN_CU_LOG2 as u8;
-        let log2_cudim = log2_culine << 1;
 
         /*  allocate CU data map*/
         let mut map_cu_data = Vec::with_capacity(f_lcu as usize);
         for _ in 0..f_lcu as usize {
-            let mut cu_data = EvceCUData::new(
+            map_cu_data.push(EvceCUData::new(
                 log2_max_cuwh - MIN_CU_LOG2 as u8,
                 log2_max_cuwh - MIN_CU_LOG2 as u8,
-            );
-            map_cu_data.push(cu_data);
+            ));
+        }
+
+        EvceTask {
+            core: EvceCore::new(param.chroma_sampling as u8, param.iqt || param.ats),
+
+            pic: vec![None; PIC_D + 1],
+            pic_tile: None,
+            refp,
+            num_refp: [0; REFP_NUM],
+            sbac_enc: EvceSbac::default(),
+            sbac_ctx: EvcSbacCtx::default(),
+            bs: EvceBsw::new(param.annexb),
+            sh: EvcSh::default(),
+            tile: EvcTile::default(),
+
+            sps_btt_flag: false,
+            sps_suco_flag: false,
+            sps_admvp_flag: false,
+            sps_hmvp_flag: false,
+            sps_eipd_flag: false,
+            sps_affine_flag: false,
+            sps_dmvr_flag: false,
+            sps_amvr_flag: false,
+            sps_mmvd_flag: false,
+            sps_addb_flag: false,
+            sps_iqt_flag: false,
+            sps_ats_flag: false,
+            sps_cm_init_flag: false,
+            sps_adcc_flag: false,
+            chroma_format_idc: 0,
+            log2_diff_ctu_size_max_suco_cb_size: 0,
+            log2_diff_max_suco_min_suco_cb_size: 0,
+            sps_dquant_flag: false,
+            pps_cu_qp_delta_enabled_flag: false,
+            pps_cu_qp_delta_area: 0,
+            pps_constrained_intra_pred_flag: false,
+            split_tbl: EvcSplitTbl::default(),
+
+            poc_val: 0,
+            qp: param.qp,
+            slice_num: 0,
+            slice_type: SliceType::default(),
+            gop_size: param.max_b_frames as usize + 1,
+            w,
+            h,
+            max_cuwh,
+            log2_max_cuwh,
+            min_cuwh,
+            w_lcu,
+            w_scu,
+            h_scu,
+            log2_culine,
+            mode: EvceMode::default(),
+            pintra: EvcePIntra::default(),
+            pinter: EvcePInter::new(w, h, param.max_b_frames),
+
+            /* allocate maps */
+            map_cu_data,
+            map_scu: vec![MCU::default(); f_scu as usize],
+            map_split: vec![LcuSplitMode::default(); f_lcu as usize],
+            map_suco: vec![LcuSucoFlag::default(); f_lcu as usize],
+            map_mv: None,
+            map_unrefined_mv: Some(Arc::new(RwLock::new(vec![
+                [[0; MV_D]; REFP_NUM];
+                f_scu as usize
+            ]))),
+            map_refi: None,
+            map_ipm: vec![IntraPredDir::default(); f_scu as usize],
+            map_affine: vec![0; f_scu as usize],
+            map_depth: vec![-1; f_scu as usize],
+
+            pic_dbk: None,
+            lambda: [0.0; 3],
+            sqrt_lambda: [0.0; 3],
+            dist_chroma_weight: [0.0; 2],
+        }
+    }
+}
+
+impl EvceCtx {
+    pub(crate) fn new(cfg: &Config) -> Self {
+        let mut refp = Vec::with_capacity(MAX_NUM_REF_PICS);
+        for _ in 0..MAX_NUM_REF_PICS {
+            let mut refp1d = Vec::with_capacity(REFP_NUM);
+            for _ in 0..REFP_NUM {
+                refp1d.push(EvcRefP::new());
+            }
+            refp.push(refp1d);
         }
 
+        let param = cfg.enc.unwrap();
+
+        let w = param.width as u16;
+        let h = param.height as u16;
+        let f = w as u32 * h as u32;
+        let max_cuwh = 64;
+        let log2_max_cuwh = 6;
+        let w_lcu = (w + max_cuwh - 1) >> 6;
+        let h_lcu = (h + max_cuwh - 1) >> 6;
+        let f_lcu = w_lcu as u32 * h_lcu as u32;
+        let w_scu = (w + ((1 << MIN_CU_LOG2) - 1)) >> MIN_CU_LOG2;
+        let h_scu = (h + ((1 << MIN_CU_LOG2) - 1)) >> MIN_CU_LOG2;
+        let f_scu = w_scu as u32 * h_scu as u32;
+
         /* allocate maps */
         let map_scu = vec![MCU::default(); f_scu as usize];
         let map_split = vec![LcuSplitMode::default(); f_lcu as usize];
-        let map_ipm = vec![IntraPredDir::default(); f_scu as usize];
-        let map_depth = vec![-1; f_scu as usize];
+        let map_suco = vec![LcuSucoFlag::default(); f_lcu as usize];
+
+        let num_slices_in_pic = std::cmp::max(param.num_slices_in_pic, 1);
+        let mut tasks = Vec::with_capacity(num_slices_in_pic);
+        for _ in 0..num_slices_in_pic {
+            tasks.push(EvceTask::new(&param));
+        }
 
         let pico_max_cnt = 1 + ((param.max_b_frames as usize) << 1);
         /* initialize decode picture manager */
@@ -536,17 +857,25 @@ impl EvceCtx {
             gop_size: param.max_b_frames as usize + 1,
 
             sps_pps_once: false,
-            core,
+            aps_id: 0,
+
+            tasks,
+            /* trace output has to follow encoding order */
+            threads: if cfg!(feature = "trace") {
+                1
+            } else {
+                std::cmp::max(cfg.threads, 1)
+            },
+            tiles: vec![],
+            map_tidx: None,
+
             pic: vec![None; PIC_D + 1],
 
             refp,
             param,
-            /* SBAC */
-            sbac_enc: EvceSbac::default(),
-            sbac_ctx: EvcSbacCtx::default(),
             tracer: OPEN_TRACE(true),
             /* bitstream structure */
-            bs: EvceBsw::default(),
+            bs: EvceBsw::new(param.annexb),
             /* sequnce parameter set */
             sps: EvcSps::default(),
             /* picture parameter set */
@@ -560,6 +889,8 @@ impl EvceCtx {
             sh: EvcSh::default(),
             /* reference picture manager */
             rpm,
+            /* rate control */
+            rc: EvceRc::new(&param, param.max_b_frames as usize + 1),
             /* quantization value of current encoding slice */
             qp: param.qp,
             /* encoding picture width */
@@ -590,70 +921,45 @@ impl EvceCtx {
             force_ignored_cnt: 0,
             /* initial frame return number(delayed input count) due to B picture or Forecast */
             frm_rnum: param.max_b_frames as isize,
-            /* current encoding slice number in one picture */
-            slice_num: 0,
-            /* first mb number of current encoding slice in one picture */
-            sl_first_mb: 0,
             /* current slice type */
             slice_type: SliceType::default(),
             /* slice depth for current picture */
             slice_depth: 0,
             /* flag whether current picture is refecened picture or not */
             slice_ref_flag: false,
+            lt_ref_pin: false,
             /* distance between ref pics in addition to closest ref ref pic in LD*/
             ref_pic_gap_length: 0,
-            /* maximum CU depth */
-            max_cud,
 
             /* last coded intra picture's picture order count */
             last_intra_poc: 0,
-            /* maximum CU width and height */
-            max_cuwh,
             /* log2 of maximum CU width and height */
             log2_max_cuwh,
-            /* minimum CU width and height */
-            min_cuwh,
-            /* log2 of minimum CU width and height */
-            log2_min_cuwh,
-            /* total count of remained LCU for encoding one picture. if a picture is
-            encoded properly, this value should reach to zero */
-            lcu_cnt: 0,
             /* picture width in LCU unit */
             w_lcu,
             /* picture height in LCU unit */
             h_lcu,
-            /* picture size in LCU unit (= w_lcu * h_lcu) */
-            f_lcu,
             /* picture width in SCU unit */
             w_scu,
             /* picture height in SCU unit */
             h_scu,
-            /* picture size in SCU unit (= w_scu * h_scu) */
-            f_scu,
-            /* log2 of SCU count in a LCU row */
-            log2_culine,
-            /* log2 of SCU count in a LCU (== log2_culine * 2) */
-            log2_cudim,
-            /* mode decision structure */
-            mode: EvceMode::default(),
-            /* intra prediction analysis */
-            pintra: EvcePIntra::default(),
-            /* inter prediction analysis */
-            pinter: EvcePInter::new(w, h, param.max_b_frames),
             /* MAPS *******************************************************************/
-            /* cu data for current LCU */
-            map_cu_data,
-            /* CU map (width in SCU x height in SCU) of raster scan order in a frame */
             map_scu,
             map_split,
+            map_suco,
             /* map for encoded motion vectors in SCU */
             map_mv: None,
+            /* map for motion vectors before decoder-side refinement */
+            map_unrefined_mv: Some(Arc::new(RwLock::new(vec![
+                [[0; MV_D]; REFP_NUM];
+                f_scu as usize
+            ]))),
             /* map for reference indices */
             map_refi: None,
-            /* map for intra pred mode */
-            map_ipm,
-            map_depth,
-            pic_dbk: None, //one picture that arranges cu pixels and neighboring pixels for deblocking (just to match the interface of deblocking functions)
+            evc_tbl_qp_chroma_dynamic_ext: evc_default_chroma_qp_mapping_tables(
+                param.chroma_sampling as u8,
+                param.iqt || param.ats,
+            ),
             lambda: [0.0; 3],
             sqrt_lambda: [0.0; 3],
             dist_chroma_weight: [0.0; 2],
@@ -677,9 +983,9 @@ impl EvceCtx {
                 pico.pic_icnt = self.pic_icnt as usize;
                 pico.is_used = true;
 
-                pico.pic.borrow_mut().frame = Rc::new(RefCell::new(f));
+                pico.pic.write().unwrap().frame = Arc::new(RwLock::new(f));
 
-                self.pic[PIC_IDX_ORIG] = Some(Rc::clone(&pico.pic));
+                self.pic[PIC_IDX_ORIG] = Some(Arc::clone(&pico.pic));
             }
         }
 
@@ -708,16 +1014,24 @@ impl EvceCtx {
         self.evce_enc_pic_finish()
     }
 
-    pub(crate) fn pull_pkt(&mut self) -> Result<Rc<RefCell<Packet>>, EvcError> {
+    pub(crate) fn pull_pkt(&mut self) -> Result<Arc<RwLock<Packet>>, EvcError> {
         let pkt = self.pkt.take();
         self.pkt = Some(Packet::default());
         if let Some(data) = pkt {
-            Ok(Rc::new(RefCell::new(data)))
+            Ok(Arc::new(RwLock::new(data)))
         } else {
             Err(EvcError::EVC_ERR_EMPTY_PACKET)
         }
     }
 
+    pub(crate) fn rc_receive_pass_data(&mut self) -> Option<Vec<u8>> {
+        self.rc.receive_pass_data()
+    }
+
+    pub(crate) fn rc_send_pass_data(&mut self, data: &[u8]) -> Result<(), EvcError> {
+        self.rc.send_pass_data(data)
+    }
+
     fn check_frame_delay(&self) -> Result<(), EvcError> {
         if self.pic_icnt < self.frm_rnum {
             Err(EvcError::EVC_OK_OUTPUT_NOT_AVAILABLE)
@@ -747,14 +1061,12 @@ impl EvceCtx {
     fn evce_enc_pic_prepare(&mut self) -> Result<(), EvcError> {
         //evc_assert_rv(PIC_ORIG(ctx) != NULL, EVC_ERR_UNEXPECTED);
 
-        self.qp = self.param.qp;
-
         self.pic[PIC_IDX_CURR] = self.rpm.evc_picman_get_empty_pic()?;
         if let Some(pic) = &self.pic[PIC_IDX_CURR] {
             {
-                let p = pic.borrow();
-                self.map_refi = Some(Rc::clone(&p.map_refi));
-                self.map_mv = Some(Rc::clone(&p.map_mv));
+                let p = pic.read().unwrap();
+                self.map_refi = Some(Arc::clone(&p.map_refi));
+                self.map_mv = Some(Arc::clone(&p.map_mv));
             }
 
             /*if self.sps.picture_cropping_flag {
@@ -765,18 +1077,22 @@ impl EvceCtx {
                 PIC_CURR(ctx)->imgb->crop_b = self.sps.picture_crop_bottom_offset;
             }*/
 
-            self.pic[PIC_IDX_MODE] = Some(Rc::clone(pic));
+            self.pic[PIC_IDX_MODE] = Some(Arc::clone(pic));
         }
 
-        if self.pic_dbk.is_none() {
-            self.pic_dbk = Some(Rc::new(RefCell::new(EvcPic::new(
-                self.w as usize,
-                self.h as usize,
-                self.param.chroma_sampling,
-            ))));
+        self.decide_slice_type();
+
+        /* a pinned frame is kept as long-term reference picture */
+        self.lt_ref_pin = self.param.ltr
+            && self.pic[PIC_IDX_ORIG].as_ref().map_or(false, |pic| {
+                pic.read().unwrap().frame.read().unwrap().long_term_ref
+            });
+        if self.lt_ref_pin {
+            self.slice_ref_flag = true;
         }
 
-        self.decide_slice_type();
+        /* pick the base QP of the picture */
+        self.qp = self.rc.frame_start(self.slice_depth);
 
         if self.slice_type == SliceType::EVC_ST_I {
             if !self.sps_pps_once {
@@ -788,30 +1104,12 @@ impl EvceCtx {
             }
         }
 
-        self.lcu_cnt = self.f_lcu;
-        self.slice_num = 0;
-
         if self.slice_type == SliceType::EVC_ST_I {
             self.last_intra_poc = self.poc.poc_val;
         }
 
         if let (Some(map_refi), Some(map_mv)) = (&mut self.map_refi, &mut self.map_mv) {
-            let (mut map_refi, mut map_mv) = (map_refi.borrow_mut(), map_mv.borrow_mut());
-            for v in &mut *map_refi {
-                v[REFP_0] = -1;
-                v[REFP_1] = -1;
-            }
-            for v in &mut *map_mv {
-                v[REFP_0][MV_X] = 0;
-                v[REFP_0][MV_Y] = 0;
-                v[REFP_1][MV_X] = 0;
-                v[REFP_1][MV_Y] = 0;
-            }
-        }
-
-        /* clear map */
-        for v in &mut self.map_scu {
-            *v = MCU::default();
+            evce_clear_motion_maps(map_refi, map_mv);
         }
 
         //TODO: support MULTIPLE_NAL?
@@ -820,212 +1118,452 @@ impl EvceCtx {
     }
 
     fn evce_enc_pic(&mut self) -> Result<(), EvcError> {
-        let num_slice_in_pic = self.param.num_slices_in_pic;
+        if self.poc.poc_val > self.last_intra_poc {
+            self.last_intra_poc = i32::MAX;
+        }
+        if self.slice_type == SliceType::EVC_ST_I {
+            self.last_intra_poc = self.poc.poc_val;
+        }
 
-        /* initialize bitstream container */
-        self.bs.init();
-        self.bs.tracer = self.tracer.take();
+        /* initialize reference pictures */
+        self.rpm.evc_picman_refp_init(
+            self.sps.max_num_ref_pics,
+            self.slice_type,
+            self.poc.poc_val as u32,
+            self.nalu.nuh_temporal_id,
+            self.last_intra_poc,
+            &mut self.refp,
+        );
 
-        for slice_num in 0..num_slice_in_pic {
-            self.slice_num = slice_num;
+        /* Set nalu header */
+        self.nalu.set_nalu(
+            if self.pic_cnt == 0
+                || (self.slice_type == SliceType::EVC_ST_I && self.param.closed_gop)
+            {
+                NaluType::EVC_IDR_NUT
+            } else {
+                NaluType::EVC_NONIDR_NUT
+            },
+            self.nalu.nuh_temporal_id,
+        );
 
-            if self.poc.poc_val > self.last_intra_poc {
-                self.last_intra_poc = i32::MAX;
-            }
-            if self.slice_type == SliceType::EVC_ST_I {
-                self.last_intra_poc = self.poc.poc_val;
-            }
+        self.set_sh();
 
-            /* initialize reference pictures */
-            self.rpm.evc_picman_refp_init(
-                self.sps.max_num_ref_pics,
-                self.slice_type,
-                self.poc.poc_val as u32,
-                self.nalu.nuh_temporal_id,
-                self.last_intra_poc,
-                &mut self.refp,
-            );
+        let rewrite_slices = self.sps.tool_alf || self.sps.tool_addb;
+        let mut tasks = std::mem::take(&mut self.tasks);
+        let num_slices = tasks.len();
+        for (slice_num, task) in tasks.iter_mut().enumerate() {
+            self.slice_init(task, slice_num);
+            task.slice_qp(self.rc.slice_start(num_slices, slice_num));
+        }
 
-            /* initialize mode decision for frame encoding */
-            self.mode_init_frame();
+        /* the slices of a picture are independent of each other */
+        let threads = std::cmp::min(self.threads, num_slices);
+        if threads > 1 {
+            let (sps, pps, nalu) = (&self.sps, &self.pps, &self.nalu);
+            let chunk_size = (num_slices + threads - 1) / threads;
+            thread::scope(|s| {
+                let mut chunks = tasks.chunks_mut(chunk_size);
+                let first = chunks.next();
+                for chunk in chunks {
+                    s.spawn(move || evce_encode_slices(chunk, sps, pps, nalu, None));
+                }
+                if let Some(chunk) = first {
+                    evce_encode_slices(chunk, sps, pps, nalu, None);
+                }
+            });
+        } else {
+            /* with ALF or ADDB, only the slices written again are traced */
+            let tracer = if rewrite_slices {
+                None
+            } else {
+                self.tracer.take()
+            };
+            let tracer = evce_encode_slices(&mut tasks, &self.sps, &self.pps, &self.nalu, tracer);
+            if !rewrite_slices {
+                self.tracer = tracer;
+            }
+        }
 
-            /* mode analyze frame */
-            self.mode_analyze_frame();
+        self.rc.slice_end(
+            tasks
+                .iter()
+                .map(|task| (task.bs.GET_WRITE_BYTE() << 3) as f64)
+                .collect(),
+        );
+        for task in &tasks {
+            self.slice_merge(task);
+        }
 
-            /* slice layer encoding loop */
-            {
-                let core = &mut self.core;
-                core.x_lcu = 0;
-                core.y_lcu = 0;
-                core.x_pel = 0;
-                core.y_pel = 0;
-                core.lcu_num = 0;
+        /* deblocking filter */
+        if self.sh.deblocking_filter_on {
+            if self.sps.tool_addb {
+                self.evce_addb_offsets();
             }
-            self.lcu_cnt = self.f_lcu;
-
-            /* Set nalu header */
-            self.nalu.set_nalu(
-                if self.pic_cnt == 0
-                    || (self.slice_type == SliceType::EVC_ST_I && self.param.closed_gop)
-                {
-                    NaluType::EVC_IDR_NUT
-                } else {
-                    NaluType::EVC_NONIDR_NUT
-                },
-                self.nalu.nuh_temporal_id,
-            );
-
-            /* Encode nalu header */
-            evce_eco_nalu(&mut self.bs, &self.nalu);
+            self.evce_deblock();
+        }
 
-            self.set_sh();
-            /* Encode slice header */
-            evce_eco_sh(&mut self.bs, &self.sh, self.nalu.nal_unit_type);
+        /* adaptive loop filter */
+        let alf = if self.sps.tool_alf {
+            self.evce_alf_picture()
+        } else {
+            None
+        };
+        if let Some(alf) = &alf {
+            let aps_id = self.aps_id;
+            self.aps_id = (self.aps_id + 1) % APS_MAX_NUM as u8;
+            self.evce_encode_aps(aps_id, alf);
+
+            self.sh.alf_on = true;
+            self.sh.aps_id_y = aps_id;
+            self.sh.alf_map_flag = alf.map_flag;
+            self.sh.alf_chroma_idc = alf.chroma_idc;
+            self.sh.aps_id_ch = aps_id;
+            self.sh.aps_id_ch2 = aps_id;
+        }
 
-            {
-                let core = &mut self.core;
-                let sh = &mut self.sh;
-
-                core.qp_y = sh.qp + 6 * (BIT_DEPTH as u8 - 8);
-                core.qp_u = (core.evc_tbl_qp_chroma_dynamic_ext[0]
-                    [EVC_TBL_CHROMA_QP_OFFSET as usize + sh.qp_u as usize]
-                    + 6 * (BIT_DEPTH as i8 - 8)) as u8;
-                core.qp_v = (core.evc_tbl_qp_chroma_dynamic_ext[1]
-                    [EVC_TBL_CHROMA_QP_OFFSET as usize + sh.qp_v as usize]
-                    + 6 * (BIT_DEPTH as i8 - 8)) as u8;
-
-                sh.qp_prev_eco = sh.qp;
-                sh.qp_prev_mode = sh.qp;
-                core.dqp_data[self.log2_max_cuwh as usize - 2][self.log2_max_cuwh as usize - 2]
-                    .prev_qp = sh.qp_prev_mode;
-                core.dqp_curr_best[self.log2_max_cuwh as usize - 2]
-                    [self.log2_max_cuwh as usize - 2]
-                    .curr_qp = sh.qp;
-                core.dqp_curr_best[self.log2_max_cuwh as usize - 2]
-                    [self.log2_max_cuwh as usize - 2]
-                    .prev_qp = sh.qp;
+        /* the deblocking offsets, the slice header and the CTU flags of the ALF
+        are only known after encoding all the slices, which are written again */
+        if rewrite_slices {
+            for task in &mut tasks {
+                task.sh.sh_deblock_alpha_offset = self.sh.sh_deblock_alpha_offset;
+                task.sh.sh_deblock_beta_offset = self.sh.sh_deblock_beta_offset;
+                task.sh.alf_on = self.sh.alf_on;
+                task.sh.aps_id_y = self.sh.aps_id_y;
+                task.sh.alf_map_flag = self.sh.alf_map_flag;
+                task.sh.alf_chroma_idc = self.sh.alf_chroma_idc;
+                task.sh.aps_id_ch = self.sh.aps_id_ch;
+                task.sh.aps_id_ch2 = self.sh.aps_id_ch2;
+
+                task.bs.init();
+                task.bs.tracer = self.tracer.take();
+                task.write_slice(
+                    &self.sps,
+                    &self.pps,
+                    &self.nalu,
+                    alf.as_ref().map(|alf| &alf.map_alf[..]),
+                );
+                self.tracer = task.bs.tracer.take();
             }
+        }
 
-            self.sbac_enc.reset(&mut self.sbac_ctx);
-            self.core.s_curr_best[self.log2_max_cuwh as usize - 2][self.log2_max_cuwh as usize - 2]
-                .reset(
-                    &mut self.core.c_curr_best[self.log2_max_cuwh as usize - 2]
-                        [self.log2_max_cuwh as usize - 2],
-                );
+        /* append the slice NALUs to ctx.pkt */
+        for task in &mut tasks {
+            if let (Some(bs_pkt), Some(pkt)) = (task.bs.pkt.take(), &mut self.pkt) {
+                pkt.data.extend_from_slice(&bs_pkt.data);
+            }
+        }
+        self.tasks = tasks;
 
-            /*Set entry point for each Tile in the tile Slice*/
-            //TODO: fix slice-based x/y-LCU
-            self.core.x_lcu = 0; //entry point lcu's x location
-            self.core.y_lcu = 0; // entry point lcu's y location
-            let mut lcu_cnt = self.f_lcu;
-            self.core
-                .update_core_loc_param(self.log2_max_cuwh, self.w_lcu);
+        Ok(())
+    }
 
-            /* LCU decoding loop */
-            loop {
-                /* initialize structures *****************************************/
-                self.mode_init_lcu();
+    /* number of colour planes coded, monochrome pictures only have luma */
+    fn num_comp(&self) -> usize {
+        if self.sps.chroma_format_idc == 0 {
+            1
+        } else {
+            N_C
+        }
+    }
 
-                /* mode decision *************************************************/
-                self.core.s_curr_best[self.log2_max_cuwh as usize - 2]
-                    [self.log2_max_cuwh as usize - 2] = self.sbac_enc;
-                self.core.c_curr_best[self.log2_max_cuwh as usize - 2]
-                    [self.log2_max_cuwh as usize - 2] = self.sbac_ctx;
+    fn evce_deblock(&mut self) {
+        evc_deblock(
+            self.sh.qp_u_offset,
+            self.sh.qp_v_offset,
+            self.sps.tool_addb,
+            self.sh.sh_deblock_alpha_offset,
+            self.sh.sh_deblock_beta_offset,
+            self.w_lcu,
+            self.h_lcu,
+            self.w_scu,
+            self.h_scu,
+            self.w,
+            self.h,
+            &mut self.tracer,
+            &self.pic[PIC_IDX_CURR],
+            &mut self.map_scu,
+            &self.map_split,
+            &self.map_suco,
+            &self.map_unrefined_mv,
+            &self.map_refi,
+            &self.refp,
+            self.map_tidx.as_deref(),
+            &self.evc_tbl_qp_chroma_dynamic_ext,
+        );
+    }
 
-                self.core.s_curr_best[self.log2_max_cuwh as usize - 2]
-                    [self.log2_max_cuwh as usize - 2]
-                    .is_bitcount = true;
+    /* choose the slice offsets of the advanced deblocking filter, the current
+    picture is filtered with each candidate and the one with the least
+    distortion is kept (the picture is left unfiltered) */
+    fn evce_addb_offsets(&mut self) {
+        let (org, curr) = match (&self.pic[PIC_IDX_ORIG], &self.pic[PIC_IDX_CURR]) {
+            (Some(org), Some(curr)) => (Arc::clone(org), Arc::clone(curr)),
+            _ => return,
+        };
+        let planes = curr.read().unwrap().frame.read().unwrap().planes.clone();
+
+        /* tracing only the deblocking of the final offsets */
+        let tracer = self.tracer.take();
+        let mut best = (0, i64::MAX);
+        for &offset in &[0, -2, 2, -4, 4] {
+            self.sh.sh_deblock_alpha_offset = offset;
+            self.sh.sh_deblock_beta_offset = offset;
+            self.evce_deblock();
+
+            let (org, curr) = (org.read().unwrap(), curr.read().unwrap());
+            let (org, mut frame) = (org.frame.read().unwrap(), curr.frame.write().unwrap());
+            let mut dist = 0.0;
+            for i in 0..self.num_comp() {
+                let (xdec, ydec) = (org.planes[i].cfg.xdec, org.planes[i].cfg.ydec);
+                let (w, h) = (self.w as usize >> xdec, self.h as usize >> ydec);
+                let ssd = evce_ssd_plane(
+                    w,
+                    h,
+                    &org.planes[i].as_region(),
+                    &frame.planes[i].as_region(),
+                ) as f64;
+                dist += if i == Y_C {
+                    ssd
+                } else {
+                    ssd * self.dist_chroma_weight[i - 1]
+                };
+            }
+            if (dist as i64) < best.1 {
+                best = (offset, dist as i64);
+            }
+            frame.planes.clone_from(&planes);
+        }
+        self.tracer = tracer;
 
-                /* analyzer lcu */
+        self.sh.sh_deblock_alpha_offset = best.0;
+        self.sh.sh_deblock_beta_offset = best.0;
+    }
 
-                // TRACE_RDO = 0: comment this line, otherwise, 2: uncomment it
-                self.core.bs_temp.tracer = self.bs.tracer.take();
-                self.mode_analyze_lcu();
-                self.bs.tracer = self.core.bs_temp.tracer.take();
+    /* estimate the ALF parameters of the deblocked current picture and filter
+    it, none if the picture is not filtered */
+    fn evce_alf_picture(&mut self) -> Option<EvceAlf> {
+        let (org, curr) = (
+            self.pic[PIC_IDX_ORIG].as_ref()?,
+            self.pic[PIC_IDX_CURR].as_ref()?,
+        );
+        let (org, curr) = (org.read().unwrap(), curr.read().unwrap());
+        let org = org.frame.read().unwrap();
+        let mut frame = curr.frame.write().unwrap();
+
+        let alf = evce_alf_estimate(
+            &org,
+            &frame,
+            &self.tiles,
+            self.pps.loop_filter_across_tiles_enabled_flag,
+            self.w_lcu,
+            (self.w_lcu * self.h_lcu) as usize,
+            self.lambda[0],
+            &self.dist_chroma_weight,
+        )?;
+
+        evc_alf(
+            &alf.filter(),
+            &mut frame,
+            &self.tiles,
+            self.pps.loop_filter_across_tiles_enabled_flag,
+            &alf.map_alf,
+            self.w_lcu,
+        );
 
-                /* entropy coding ************************************************/
-                self.evce_eco_tree(
-                    self.core.x_pel,
-                    self.core.y_pel,
-                    self.max_cuwh,
-                    self.max_cuwh,
-                    0,
-                    0,
-                    true,
-                    0,
-                    0,
-                );
+        Some(alf)
+    }
 
-                /* prepare next step *********************************************/
-                self.core.x_lcu += 1;
-                if self.core.x_lcu >= self.w_lcu {
-                    self.core.x_lcu = 0;
-                    self.core.y_lcu += 1;
-                }
+    /* hand over everything a slice needs to the task encoding it */
+    fn slice_init(&self, task: &mut EvceTask, slice_num: usize) {
+        task.slice_num = slice_num;
+        task.tile = self.tiles[slice_num];
+        task.slice_type = self.slice_type;
+        task.poc_val = self.poc.poc_val;
+        task.qp = self.qp;
+
+        task.sh.clone_from(&self.sh);
+        /* the tile of the slice is signalled as a range of tiles, which is what
+        the reference decoder derives the tiles of a slice from */
+        task.sh.single_tile_in_slice_flag = self.pps.single_tile_in_pic_flag;
+        task.sh.first_tile_id = slice_num as u16;
+        task.sh.last_tile_id = slice_num as u16;
+        task.lambda = self.lambda;
+        task.sqrt_lambda = self.sqrt_lambda;
+        task.dist_chroma_weight = self.dist_chroma_weight;
+
+        for i in 0..MAX_NUM_REF_PICS {
+            for j in 0..REFP_NUM {
+                task.refp[i][j].clone_from(&self.refp[i][j]);
+            }
+        }
+        task.num_refp = self.rpm.num_refp;
+
+        task.sps_btt_flag = self.sps.sps_btt_flag;
+        task.sps_suco_flag = self.sps.sps_suco_flag;
+        task.sps_admvp_flag = self.sps.tool_admvp;
+        task.sps_hmvp_flag = self.sps.tool_hmvp;
+        task.sps_eipd_flag = self.sps.tool_eipd;
+        task.sps_affine_flag = self.sps.tool_affine;
+        task.sps_dmvr_flag = self.sps.tool_dmvr;
+        task.sps_amvr_flag = self.sps.tool_amvr;
+        task.sps_mmvd_flag = self.sps.tool_mmvd;
+        task.sps_addb_flag = self.sps.tool_addb;
+        task.sps_iqt_flag = self.sps.tool_iqt;
+        task.sps_ats_flag = self.sps.tool_ats;
+        task.sps_cm_init_flag = self.sps.tool_cm_init;
+        task.sps_adcc_flag = self.sps.tool_adcc;
+        task.chroma_format_idc = self.sps.chroma_format_idc;
+        task.log2_diff_ctu_size_max_suco_cb_size = self.sps.log2_diff_ctu_size_max_suco_cb_size;
+        task.log2_diff_max_suco_min_suco_cb_size = self.sps.log2_diff_max_suco_min_suco_cb_size;
+        task.sps_dquant_flag = self.sps.dquant_flag;
+        task.split_tbl = evc_set_split_tbl(&self.sps);
+        task.pps_cu_qp_delta_enabled_flag = self.pps.cu_qp_delta_enabled_flag;
+        task.pps_cu_qp_delta_area = self.pps.cu_qp_delta_area;
+        task.pps_constrained_intra_pred_flag = self.pps.constrained_intra_pred_flag;
+
+        /* the first slice is reconstructed into the current picture directly,
+        the others into a private picture merged after encoding */
+        task.pic[PIC_IDX_ORIG] = self.pic[PIC_IDX_ORIG].clone();
+        if slice_num == 0 {
+            task.pic[PIC_IDX_CURR] = self.pic[PIC_IDX_CURR].clone();
+            task.map_refi = self.map_refi.clone();
+            task.map_mv = self.map_mv.clone();
+        } else {
+            if task.pic_tile.is_none() {
+                task.pic_tile = Some(Arc::new(RwLock::new(EvcPic::new(
+                    self.w as usize,
+                    self.h as usize,
+                    self.param.chroma_sampling,
+                ))));
+            }
+            task.pic[PIC_IDX_CURR] = task.pic_tile.clone();
+            if let Some(pic) = &task.pic_tile {
+                let p = pic.read().unwrap();
+                evce_clear_motion_maps(&p.map_refi, &p.map_mv);
+                task.map_refi = Some(Arc::clone(&p.map_refi));
+                task.map_mv = Some(Arc::clone(&p.map_mv));
+            }
+        }
+        task.pic[PIC_IDX_MODE] = task.pic[PIC_IDX_CURR].clone();
+        if let Some(map_unrefined_mv) = &task.map_unrefined_mv {
+            for v in &mut *map_unrefined_mv.write().unwrap() {
+                *v = [[0; MV_D]; REFP_NUM];
+            }
+        }
 
-                self.core
-                    .update_core_loc_param(self.log2_max_cuwh, self.w_lcu);
-                lcu_cnt -= 1;
-                self.lcu_cnt -= 1; //To be updated properly in case of multicore
+        if task.pic_dbk.is_none() {
+            task.pic_dbk = Some(Arc::new(RwLock::new(EvcPic::new(
+                self.w as usize,
+                self.h as usize,
+                self.param.chroma_sampling,
+            ))));
+        }
 
-                if lcu_cnt == 0 {
-                    evce_eco_tile_end_flag(&mut self.bs, &mut self.sbac_enc, 1);
-                    self.sbac_enc.finish(&mut self.bs);
-                    break;
-                }
-            } //End of Slice encoding LCU loop
-
-            /* deblocking filter */
-            if self.sh.deblocking_filter_on {
-                evc_deblock(
-                    self.sh.qp_u_offset,
-                    self.sh.qp_v_offset,
-                    self.w_lcu,
-                    self.h_lcu,
-                    self.w_scu,
-                    self.h_scu,
-                    self.w,
-                    self.h,
-                    &mut self.bs.tracer,
-                    &self.pic[PIC_IDX_MODE],
-                    &mut self.map_scu,
-                    &self.map_split,
-                    &self.map_mv,
-                    &self.map_refi,
-                    &self.core.evc_tbl_qp_chroma_dynamic_ext,
-                );
+        task.mode.cost = 0.0;
+
+        /* clear map, the other tiles of the picture are not available to the slice */
+        for v in &mut task.map_scu {
+            *v = MCU::default();
+        }
+        if self.tiles.len() > 1 {
+            evc_set_scu_outside_tile(&mut task.map_scu, &task.tile, self.w_scu, self.h_scu);
+        }
+
+        /* bitstream of the slice NALU */
+        task.bs.init();
+    }
+
+    /* copy the tile of an encoded slice into the current picture and its maps */
+    fn slice_merge(&mut self, task: &EvceTask) {
+        let tile = task.tile;
+        let (x_scu, y_scu, w_scu, h_scu) = evc_tile_scu_rect(&tile, self.w_scu, self.h_scu);
+
+        for j in y_scu..y_scu + h_scu {
+            let idx = j * self.w_scu as usize + x_scu;
+            self.map_scu[idx..idx + w_scu].copy_from_slice(&task.map_scu[idx..idx + w_scu]);
+        }
+        for j in tile.y_lcu..tile.y_lcu + tile.h_lcu {
+            for i in tile.x_lcu..tile.x_lcu + tile.w_lcu {
+                let lcu_num = (j * self.w_lcu + i) as usize;
+                self.map_split[lcu_num].clone_from(&task.map_split[lcu_num]);
+                self.map_suco[lcu_num].clone_from(&task.map_suco[lcu_num]);
             }
         }
 
-        /* de-init BSW */
-        self.bs.deinit();
-        self.tracer = self.bs.tracer.take();
+        /* every slice refines motion into a map of its own */
+        if let (Some(dst), Some(src)) = (&self.map_unrefined_mv, &task.map_unrefined_mv) {
+            let (mut dst, src) = (dst.write().unwrap(), src.read().unwrap());
+            for j in y_scu..y_scu + h_scu {
+                let idx = j * self.w_scu as usize + x_scu;
+                dst[idx..idx + w_scu].copy_from_slice(&src[idx..idx + w_scu]);
+            }
+        }
 
-        /* write the bitstream size */
-        self.bs.write_nalu_size();
+        if task.slice_num == 0 {
+            return;
+        }
 
-        /* append bs.pkt to ctx.pkt */
-        if let Some(bs_pkt) = self.bs.pkt.take() {
-            if let Some(pkt) = &mut self.pkt {
-                pkt.data.extend_from_slice(&bs_pkt.data);
+        if let (Some(dst_refi), Some(dst_mv), Some(src_refi), Some(src_mv)) =
+            (&self.map_refi, &self.map_mv, &task.map_refi, &task.map_mv)
+        {
+            let (mut dst_refi, mut dst_mv) = (dst_refi.write().unwrap(), dst_mv.write().unwrap());
+            let (src_refi, src_mv) = (src_refi.read().unwrap(), src_mv.read().unwrap());
+            for j in y_scu..y_scu + h_scu {
+                let idx = j * self.w_scu as usize + x_scu;
+                dst_refi[idx..idx + w_scu].copy_from_slice(&src_refi[idx..idx + w_scu]);
+                dst_mv[idx..idx + w_scu].copy_from_slice(&src_mv[idx..idx + w_scu]);
             }
         }
 
-        Ok(())
+        if let (Some(dst), Some(src)) = (&self.pic[PIC_IDX_CURR], &task.pic[PIC_IDX_CURR]) {
+            let (dst, src) = (dst.read().unwrap(), src.read().unwrap());
+            let (dst_planes, src_planes) = (
+                &mut dst.frame.write().unwrap().planes,
+                &src.frame.read().unwrap().planes,
+            );
+            let x = (tile.x_lcu as usize) << self.log2_max_cuwh;
+            let y = (tile.y_lcu as usize) << self.log2_max_cuwh;
+            let w = std::cmp::min(
+                (tile.w_lcu as usize) << self.log2_max_cuwh,
+                self.w as usize - x,
+            );
+            let h = std::cmp::min(
+                (tile.h_lcu as usize) << self.log2_max_cuwh,
+                self.h as usize - y,
+            );
+            for c in 0..self.num_comp() {
+                let (xdec, ydec) = (dst_planes[c].cfg.xdec, dst_planes[c].cfg.ydec);
+                let area = Area::Rect {
+                    x: (x >> xdec) as isize,
+                    y: (y >> ydec) as isize,
+                    width: w >> xdec,
+                    height: h >> ydec,
+                };
+                let src_region = src_planes[c].region(area);
+                let mut dst_region = dst_planes[c].region_mut(area);
+                for j in 0..h >> ydec {
+                    dst_region[j].copy_from_slice(&src_region[j]);
+                }
+            }
+        }
     }
 
     fn evce_enc_pic_finish(&mut self) -> Result<EvcStat, EvcError> {
         let mut stat = EvcStat::default();
 
-        //TODO: adding picture signature
+        /* picture signature */
+        if self.param.picture_hash != PictureHash::None {
+            stat.sei_size = self.evce_encode_sei();
+        }
 
         /* expand current encoding picture, if needs */
         //self.fn_picbuf_expand(ctx, PIC_CURR(ctx));
         let pic_curr = &self.pic[PIC_IDX_CURR];
         if let Some(pic) = &pic_curr {
-            let frame = &pic.borrow().frame;
-            frame.borrow_mut().pad();
-            stat.rec = Some(Rc::clone(frame));
+            let frame = &pic.read().unwrap().frame;
+            frame.write().unwrap().pad();
+            stat.rec = Some(Arc::clone(frame));
         }
 
         /* picture buffer management */
@@ -1037,8 +1575,16 @@ impl EvceCtx {
             false,
             &mut self.refp,
             self.slice_ref_flag,
+            /* the references are still derived implicitly, the RPLs only
+            signal them */
+            false,
             self.ref_pic_gap_length,
         );
+        if self.lt_ref_pin {
+            if let Some(pic) = pic_curr {
+                self.rpm.evc_picman_set_lt_ref(pic);
+            }
+        }
 
         /* set stat */
         stat.bytes = if let Some(pkt) = &self.pkt {
@@ -1053,7 +1599,15 @@ impl EvceCtx {
         };
         stat.stype = self.slice_type;
         stat.fnum = self.pic_cnt as isize;
-        stat.qp = self.sh.qp;
+        stat.qp = self.rc.frame_qp();
+
+        /* update rate control */
+        self.rc.frame_end(
+            (stat.bytes << 3) as f64,
+            self.poc.poc_val,
+            self.slice_type,
+            self.tasks.iter().map(|task| task.mode.cost).sum(),
+        );
         stat.poc = self.poc.poc_val as isize;
         stat.tid = self.nalu.nuh_temporal_id as isize;
 
@@ -1074,38 +1628,30 @@ impl EvceCtx {
             &self.pic[PIC_IDX_ORIG],
             &self.pic[PIC_IDX_CURR],
         ) {
-            let (frame_org, frame_cur) = (&org.borrow().frame, &cur.borrow().frame);
+            let (frame_org, frame_cur) = (&org.read().unwrap().frame, &cur.read().unwrap().frame);
             {
-                pkt.ts = frame_org.borrow().ts;
-                frame_cur.borrow_mut().ts = pkt.ts;
+                pkt.ts = frame_org.read().unwrap().ts;
+                frame_cur.write().unwrap().ts = pkt.ts;
             }
 
             {
-                let (planes_org, planes_cur) =
-                    (&frame_org.borrow().planes, &frame_cur.borrow().planes);
-                stat.psnr = Some([
-                    calc_psnr(
-                        self.w,
-                        self.h,
-                        self.param.bit_depth,
-                        &planes_org[Y_C].as_region(),
-                        &planes_cur[Y_C].as_region(),
-                    ),
-                    calc_psnr(
-                        self.w >> 1,
-                        self.h >> 1,
-                        self.param.bit_depth,
-                        &planes_org[U_C].as_region(),
-                        &planes_cur[U_C].as_region(),
-                    ),
-                    calc_psnr(
-                        self.w >> 1,
-                        self.h >> 1,
+                let (planes_org, planes_cur) = (
+                    &frame_org.read().unwrap().planes,
+                    &frame_cur.read().unwrap().planes,
+                );
+                /* monochrome pictures report no chroma PSNR */
+                let mut psnr = [0.0; N_C];
+                for c in 0..self.num_comp() {
+                    let (xdec, ydec) = (planes_org[c].cfg.xdec, planes_org[c].cfg.ydec);
+                    psnr[c] = calc_psnr(
+                        self.w >> xdec,
+                        self.h >> ydec,
                         self.param.bit_depth,
-                        &planes_org[V_C].as_region(),
-                        &planes_cur[V_C].as_region(),
-                    ),
-                ]);
+                        &planes_org[c].as_region(),
+                        &planes_cur[c].as_region(),
+                    );
+                }
+                stat.psnr = Some(psnr);
             }
         }
 
@@ -1120,7 +1666,7 @@ impl EvceCtx {
         let mut pic_imcnt = pic_icnt;
         self.pico_idx = pic_icnt % self.pico_max_cnt;
         let pico = &self.pico_buf[self.pico_idx];
-        self.pic[PIC_IDX_ORIG] = Some(Rc::clone(&pico.pic));
+        self.pic[PIC_IDX_ORIG] = Some(Arc::clone(&pico.pic));
 
         if gop_size == 1 {
             if i_period == 1 {
@@ -1173,7 +1719,7 @@ impl EvceCtx {
 
                 /* flush the first IDR picture */
                 self.pico_idx = 0;
-                self.pic[PIC_IDX_ORIG] = Some(Rc::clone(&self.pico_buf[self.pico_idx].pic));
+                self.pic[PIC_IDX_ORIG] = Some(Arc::clone(&self.pico_buf[self.pico_idx].pic));
             } else if self.force_slice {
                 force_cnt = self.force_ignored_cnt as usize;
                 while force_cnt < gop_size {
@@ -1258,13 +1804,37 @@ impl EvceCtx {
             self.pico_idx = pic_icnt_b as usize % self.pico_max_cnt;
             let pico = &self.pico_buf[self.pico_idx];
 
-            self.pic[PIC_IDX_ORIG] = Some(Rc::clone(&pico.pic));
+            self.pic[PIC_IDX_ORIG] = Some(Arc::clone(&pico.pic));
         }
     }
 
     fn set_sps(&mut self) {
+        /* without the POC LSBs, the reference decoder derives the POC again at
+        every slice, which splits the pictures of several slices */
+        let tool_pocs = self.param.pocs || self.tasks.len() > 1;
         let sps = &mut self.sps;
-        sps.profile_idc = 0; // baseline profile only
+        sps.profile_idc = if self.param.btt
+            || self.param.suco
+            || self.param.alf
+            || self.param.addb
+            || self.param.admvp
+            || self.param.hmvp
+            || self.param.affine
+            || self.param.dmvr
+            || self.param.amvr
+            || self.param.mmvd
+            || self.param.eipd
+            || self.param.iqt
+            || self.param.ats
+            || self.param.cm_init
+            || self.param.adcc
+            || self.param.rpl
+            || tool_pocs
+        {
+            PROFILE_MAIN
+        } else {
+            PROFILE_BASELINE
+        };
         sps.level_idc = self.param.level * 3;
         sps.pic_width_in_luma_samples = self.param.width as u16;
         sps.pic_height_in_luma_samples = self.param.height as u16;
@@ -1272,31 +1842,65 @@ impl EvceCtx {
         sps.toolset_idc_l = 0;
         sps.bit_depth_luma_minus8 = 0; //TODO: self.param.out_bit_depth - 8;
         sps.bit_depth_chroma_minus8 = 0; //TODO: self.cdsc.out_bit_depth - 8;
-        sps.chroma_format_idc = 1; // YCbCr 4:2:0
+        sps.chroma_format_idc = self.param.chroma_sampling as u8;
         if self.param.max_b_frames > 0 {
             sps.max_num_ref_pics = MAX_NUM_ACTIVE_REF_FRAME_B;
         } else {
             sps.max_num_ref_pics = MAX_NUM_ACTIVE_REF_FRAME_LDB;
         }
-        sps.sps_btt_flag = false;
-        sps.sps_suco_flag = false;
-        sps.tool_amvr = false;
-        sps.tool_mmvd = false;
-        sps.tool_affine = false;
-        sps.tool_dmvr = false;
-        sps.tool_addb = false;
+        sps.sps_btt_flag = self.param.btt;
+        if sps.sps_btt_flag {
+            sps.log2_ctu_size_minus5 = 1; /* 64x64 CTUs only */
+            sps.log2_min_cb_size_minus2 = 0;
+            sps.log2_diff_ctu_max_14_cb_size = 1;
+            sps.log2_diff_ctu_max_tt_cb_size = 1;
+            sps.log2_diff_min_cb_min_tt_cb_size_minus2 = 0;
+        }
+        sps.sps_suco_flag = self.param.suco;
+        if sps.sps_suco_flag {
+            /* suco for blocks from 64x64 down to 16x16 */
+            sps.log2_diff_ctu_size_max_suco_cb_size = 0;
+            sps.log2_diff_max_suco_min_suco_cb_size = 2;
+        }
+        sps.tool_amvr = self.param.amvr;
+        sps.tool_mmvd = self.param.mmvd;
+        sps.tool_affine = self.param.affine;
+        sps.tool_dmvr = self.param.dmvr;
+        sps.tool_addb = self.param.addb;
         sps.tool_dra = false;
-        sps.tool_alf = false;
+        sps.tool_alf = self.param.alf;
         sps.tool_htdf = false;
-        sps.tool_admvp = false;
-        sps.tool_hmvp = false;
-        sps.tool_eipd = false;
-        sps.tool_iqt = false;
-        sps.tool_adcc = false;
-        sps.tool_cm_init = false;
-        sps.tool_ats = false;
-        sps.tool_rpl = false;
-        sps.tool_pocs = false;
+        sps.tool_admvp = self.param.admvp
+            || self.param.hmvp
+            || self.param.affine
+            || self.param.dmvr
+            || self.param.amvr
+            || self.param.mmvd;
+        sps.tool_hmvp = self.param.hmvp;
+        sps.tool_eipd = self.param.eipd;
+        sps.ibc_flag = false;
+        sps.tool_iqt = self.param.iqt || self.param.ats;
+        /* the advanced coefficient coding is only signalled with cm_init */
+        sps.tool_cm_init = self.param.cm_init || self.param.adcc;
+        sps.tool_adcc = self.param.adcc;
+        sps.tool_ats = self.param.ats;
+        sps.tool_rpl = self.param.rpl;
+        if sps.tool_rpl {
+            /* the sliding window keeps less than MAX_NUM_ACTIVE_REF_FRAME
+            references, besides the long-term one, the current picture and the
+            pictures waiting for output */
+            sps.sps_max_dec_pic_buffering_minus1 =
+                MAX_NUM_ACTIVE_REF_FRAME as u8 - 1 + self.param.ltr as u8 + self.param.max_b_frames;
+        }
+        sps.tool_pocs = tool_pocs;
+        if sps.tool_pocs {
+            /* 8 bits of POC LSBs, far more than the distance of the pictures
+            of a GOP */
+            sps.log2_max_pic_order_cnt_lsb_minus4 = 4;
+        }
+        /* the long-term reference picture stays in the reference picture
+        lists by its POC difference, long_term_ref_pics_flag is left 0 for the
+        decoders which do not parse the long-term entries */
 
         sps.log2_sub_gop_length = ((self.gop_size as f32).log2() + 0.5f32) as u8;
         self.ref_pic_gap_length = self.param.ref_pic_gap_length as u32;
@@ -1323,68 +1927,428 @@ impl EvceCtx {
 
     fn set_pps(&mut self) {
         let pps = &mut self.pps;
+        let num_slices_in_pic = self.tasks.len();
 
-        pps.single_tile_in_pic_flag = true;
         pps.constrained_intra_pred_flag = self.param.enable_cip;
         pps.cu_qp_delta_enabled_flag = false; //self.param.use_dqp;
         pps.cu_qp_delta_area = self.param.cu_qp_delta_area;
-        pps.single_tile_in_pic_flag = true;
+        /* every slice is a tile row of uniform height */
+        pps.single_tile_in_pic_flag = num_slices_in_pic == 1;
+        pps.num_tile_columns_minus1 = 0;
+        pps.num_tile_rows_minus1 = (num_slices_in_pic - 1) as u8;
+        pps.uniform_tile_spacing_flag = true;
+        pps.loop_filter_across_tiles_enabled_flag = false;
+        pps.tile_offset_len_minus1 = 31;
         pps.arbitrary_slice_present_flag = false;
-        pps.tile_id_len_minus1 = 0;
+        pps.tile_id_len_minus1 = CONV_LOG2(std::cmp::max(num_slices_in_pic - 1, 1));
         pps.num_ref_idx_default_active_minus1[REFP_0] = 0; /* To be checked */
         pps.num_ref_idx_default_active_minus1[REFP_1] = 0; /* To be checked */
+
+        self.tiles = evc_tile_init(pps, self.w_lcu, self.h_lcu);
+        self.map_tidx = evc_tile_map_tidx(pps, &self.tiles, self.w_lcu, self.h_lcu);
     }
 
     fn set_sh(&mut self) {
         let sh = &mut self.sh;
 
-        let qp_adapt_param = if self.param.max_b_frames == 0 {
-            if self.param.max_key_frame_interval == 1 {
-                &qp_adapt_param_ai
-            } else {
-                &qp_adapt_param_ld
-            }
-        } else {
-            &qp_adapt_param_ra
-        };
-
         sh.slice_type = self.slice_type;
         sh.no_output_of_prior_pics_flag = false;
         sh.deblocking_filter_on = if self.param.disable_dbf { false } else { true };
+        /* the ALF is decided after encoding the slices */
+        sh.alf_on = false;
+        sh.alf_map_flag = false;
+        sh.alf_chroma_idc = 0;
+        /* the larger MMVD distances are searched in all the inter slices */
+        sh.mmvd_group_enable_flag = self.sps.tool_mmvd && sh.slice_type != SliceType::EVC_ST_I;
+
+        /* set slice QP and lambda */
+        if self.sps.tool_pocs {
+            sh.poc_lsb =
+                self.poc.poc_val & ((1 << (self.sps.log2_max_pic_order_cnt_lsb_minus4 + 4)) - 1);
+        }
 
-        /* set lambda */
-        let mut qp = self.qp as i8; //EVC_CLIP3(0, MAX_QUANT, (self.param.qp_incread_frame != 0 && (int)(self.poc.poc_val) >= self.param.qp_incread_frame) ? self.qp + 1.0 : self.qp);
-
-        if !self.param.disable_hgop {
-            qp += qp_adapt_param[self.slice_depth as usize].qp_offset_layer;
-            let dqp_offset = qp as f64
-                * qp_adapt_param[self.slice_depth as usize].qp_offset_model_scale
-                + qp_adapt_param[self.slice_depth as usize].qp_offset_model_offset
-                + 0.5;
-
-            let qp_offset = EVC_CLIP3(0.0, 3.0, dqp_offset).floor() as i8;
-            qp += qp_offset;
+        /* the references of the picture are signalled explicitly */
+        if self.sps.tool_rpl {
+            let [rpl_l0, rpl_l1] =
+                self.rpm
+                    .evc_picman_get_rpl(self.slice_type, self.poc.poc_val as u32, &self.refp);
+            sh.ref_pic_list_sps_flag = [0; REFP_NUM];
+            sh.rpl_l0_idx = -1;
+            sh.rpl_l1_idx = -1;
+            sh.rpl_l0 = rpl_l0;
+            sh.rpl_l1 = rpl_l1;
+            sh.num_ref_idx_active_override_flag = true;
         }
 
-        sh.qp = EVC_CLIP3(0, MAX_QUANT as i8, qp) as u8;
         sh.qp_u_offset = self.param.cb_qp_offset;
         sh.qp_v_offset = self.param.cr_qp_offset;
-        sh.qp_u = EVC_CLIP3(-6 * (BIT_DEPTH as i8 - 8), 57, sh.qp as i8 + sh.qp_u_offset) as u8;
-        sh.qp_v = EVC_CLIP3(-6 * (BIT_DEPTH as i8 - 8), 57, sh.qp as i8 + sh.qp_v_offset) as u8;
-
-        let qp_l_i = sh.qp as i8;
-        self.lambda[0] = 0.57 * (2.0f64).powf((qp_l_i - 12) as f64 / 3.0);
-        let qp_c_i = self.core.evc_tbl_qp_chroma_dynamic_ext[0]
-            [EVC_TBL_CHROMA_QP_OFFSET as usize + sh.qp_u as usize];
-        self.dist_chroma_weight[0] = (2.0f64).powf((qp_l_i - qp_c_i) as f64 / 3.0);
-        let qp_c_i = self.core.evc_tbl_qp_chroma_dynamic_ext[1]
-            [EVC_TBL_CHROMA_QP_OFFSET as usize + sh.qp_v as usize];
-        self.dist_chroma_weight[1] = (2.0f64).powf((qp_l_i - qp_c_i) as f64 / 3.0);
-        self.lambda[1] = self.lambda[0] / self.dist_chroma_weight[0];
-        self.lambda[2] = self.lambda[0] / self.dist_chroma_weight[1];
-        self.sqrt_lambda[0] = self.lambda[0].sqrt();
-        self.sqrt_lambda[1] = self.lambda[1].sqrt();
-        self.sqrt_lambda[2] = self.lambda[2].sqrt();
+
+        /* set slice QP and lambda, the QP of a slice may still be adjusted by
+        the rate control when it starts */
+        evce_set_slice_qp(
+            sh,
+            self.rc.frame_qp(),
+            &self.evc_tbl_qp_chroma_dynamic_ext,
+            &mut self.lambda,
+            &mut self.sqrt_lambda,
+            &mut self.dist_chroma_weight,
+        );
+    }
+
+    fn evce_encode_sps(&mut self) {
+        /* bitsteam initialize for sequence */
+        self.bs.init();
+        self.bs.tracer = self.tracer.take();
+
+        /* nalu header */
+        self.nalu.set_nalu(NaluType::EVC_SPS_NUT, 0);
+
+        evce_eco_nalu(&mut self.bs, &self.nalu);
+
+        /* sequence parameter set*/
+        self.set_sps();
+        evce_eco_sps(&mut self.bs, &self.sps);
+
+        /* de-init BSW */
+        self.bs.deinit();
+        self.tracer = self.bs.tracer.take();
+
+        /* write the bitstream size */
+        self.bs.write_nalu_size();
+
+        /* append bs.pkt to ctx.pkt */
+        if let Some(bs_pkt) = self.bs.pkt.take() {
+            if let Some(pkt) = &mut self.pkt {
+                pkt.data.extend_from_slice(&bs_pkt.data);
+            }
+        }
+    }
+
+    fn evce_encode_pps(&mut self) {
+        /* bitsteam initialize for sequence */
+        self.bs.init();
+        self.bs.tracer = self.tracer.take();
+
+        /* nalu header */
+        self.nalu
+            .set_nalu(NaluType::EVC_PPS_NUT, self.nalu.nuh_temporal_id);
+
+        evce_eco_nalu(&mut self.bs, &self.nalu);
+
+        /* sequence parameter set*/
+        self.set_pps();
+        evce_eco_pps(&mut self.bs, &self.pps);
+
+        /* de-init BSW */
+        self.bs.deinit();
+        self.tracer = self.bs.tracer.take();
+
+        /* write the bitstream size */
+        self.bs.write_nalu_size();
+
+        /* append bs.pkt to ctx.pkt */
+        if let Some(bs_pkt) = self.bs.pkt.take() {
+            if let Some(pkt) = &mut self.pkt {
+                pkt.data.extend_from_slice(&bs_pkt.data);
+            }
+        }
+    }
+
+    /* write an adaptation parameter set with the ALF parameters of the current
+    picture */
+    fn evce_encode_aps(&mut self, aps_id: u8, alf: &EvceAlf) {
+        /* bitsteam initialize for APS */
+        self.bs.init();
+        self.bs.tracer = self.tracer.take();
+
+        /* nalu header */
+        let mut nalu = EvcNalu::default();
+        nalu.set_nalu(NaluType::EVC_APS_NUT, self.nalu.nuh_temporal_id);
+
+        evce_eco_nalu(&mut self.bs, &nalu);
+
+        evce_eco_aps(&mut self.bs, aps_id, &alf.param, &alf.k_min_tab);
+
+        /* de-init BSW */
+        self.bs.deinit();
+        self.tracer = self.bs.tracer.take();
+
+        /* write the bitstream size */
+        self.bs.write_nalu_size();
+
+        /* append bs.pkt to ctx.pkt */
+        if let (Some(bs_pkt), Some(pkt)) = (self.bs.pkt.take(), &mut self.pkt) {
+            pkt.data.extend_from_slice(&bs_pkt.data);
+        }
+    }
+
+    /* write the decoded picture hash SEI of the current picture, returning
+    its byte size */
+    fn evce_encode_sei(&mut self) -> usize {
+        let hash = self.param.picture_hash;
+        if let Some(pic) = &self.pic[PIC_IDX_CURR] {
+            let mut p = pic.write().unwrap();
+            let signature = evc_picbuf_signature(&p.frame.read().unwrap(), hash);
+            p.digest = signature;
+        }
+
+        /* bitsteam initialize for SEI */
+        self.bs.init();
+        self.bs.tracer = self.tracer.take();
+
+        /* nalu header */
+        let mut nalu = EvcNalu::default();
+        nalu.set_nalu(NaluType::EVC_SEI_NUT, self.nalu.nuh_temporal_id);
+
+        evce_eco_nalu(&mut self.bs, &nalu);
+
+        if let Some(pic) = &self.pic[PIC_IDX_CURR] {
+            evce_eco_sei(
+                &mut self.bs,
+                hash,
+                &pic.read().unwrap().digest,
+                self.sps.chroma_format_idc,
+            );
+        }
+
+        /* de-init BSW */
+        self.bs.deinit();
+        self.tracer = self.bs.tracer.take();
+
+        /* write the bitstream size */
+        self.bs.write_nalu_size();
+
+        /* append bs.pkt to ctx.pkt */
+        let mut size = 0;
+        if let Some(bs_pkt) = self.bs.pkt.take() {
+            if let Some(pkt) = &mut self.pkt {
+                size = bs_pkt.data.len();
+                pkt.data.extend_from_slice(&bs_pkt.data);
+            }
+        }
+        size
+    }
+}
+
+impl EvceTask {
+    /* change the QP of the slice from the one of the picture */
+    fn slice_qp(&mut self, qp: u8) {
+        if qp != self.sh.qp {
+            evce_set_slice_qp(
+                &mut self.sh,
+                qp,
+                &self.core.evc_tbl_qp_chroma_dynamic_ext,
+                &mut self.lambda,
+                &mut self.sqrt_lambda,
+                &mut self.dist_chroma_weight,
+            );
+        }
+    }
+
+    /* encode the tile of the slice into a slice NALU */
+    fn encode_slice(&mut self, sps: &EvcSps, pps: &EvcPps, nalu: &EvcNalu) {
+        /* initialize mode decision for frame encoding */
+        self.mode_init_frame();
+
+        /* mode analyze frame */
+        self.mode_analyze_frame();
+
+        /* Encode nalu header */
+        evce_eco_nalu(&mut self.bs, nalu);
+
+        /* Encode slice header */
+        evce_eco_sh(&mut self.bs, sps, pps, &self.sh, nalu.nal_unit_type);
+
+        {
+            let core = &mut self.core;
+            let sh = &mut self.sh;
+
+            core.qp_y = sh.qp + 6 * (BIT_DEPTH as u8 - 8);
+            core.qp_u = (core.evc_tbl_qp_chroma_dynamic_ext[0]
+                [EVC_TBL_CHROMA_QP_OFFSET as usize + sh.qp_u as usize]
+                + 6 * (BIT_DEPTH as i8 - 8)) as u8;
+            core.qp_v = (core.evc_tbl_qp_chroma_dynamic_ext[1]
+                [EVC_TBL_CHROMA_QP_OFFSET as usize + sh.qp_v as usize]
+                + 6 * (BIT_DEPTH as i8 - 8)) as u8;
+
+            sh.qp_prev_eco = sh.qp;
+            sh.qp_prev_mode = sh.qp;
+            core.dqp_data[self.log2_max_cuwh as usize - 2][self.log2_max_cuwh as usize - 2]
+                .prev_qp = sh.qp_prev_mode;
+            core.dqp_curr_best[self.log2_max_cuwh as usize - 2][self.log2_max_cuwh as usize - 2]
+                .curr_qp = sh.qp;
+            core.dqp_curr_best[self.log2_max_cuwh as usize - 2][self.log2_max_cuwh as usize - 2]
+                .prev_qp = sh.qp;
+        }
+
+        self.sbac_enc.reset(
+            &mut self.sbac_ctx,
+            self.sh.slice_type,
+            self.sh.qp,
+            self.sps_cm_init_flag,
+        );
+        self.core.s_curr_best[self.log2_max_cuwh as usize - 2][self.log2_max_cuwh as usize - 2]
+            .reset(
+                &mut self.core.c_curr_best[self.log2_max_cuwh as usize - 2]
+                    [self.log2_max_cuwh as usize - 2],
+                self.sh.slice_type,
+                self.sh.qp,
+                self.sps_cm_init_flag,
+            );
+
+        /*Set entry point for each Tile in the tile Slice*/
+        self.core.x_lcu = self.tile.x_lcu; //entry point lcu's x location
+        self.core.y_lcu = self.tile.y_lcu; // entry point lcu's y location
+        let mut lcu_cnt = self.tile.f_lcu;
+        self.core
+            .update_core_loc_param(self.log2_max_cuwh, self.w_lcu);
+
+        /* LCU encoding loop */
+        loop {
+            /* initialize structures *****************************************/
+            self.mode_init_lcu();
+            if self.core.x_lcu == self.tile.x_lcu {
+                /* HMVP history starts empty at each CTU row of the tile */
+                self.core.history_buffer.reset();
+            }
+
+            /* mode decision *************************************************/
+            self.core.s_curr_best[self.log2_max_cuwh as usize - 2]
+                [self.log2_max_cuwh as usize - 2] = self.sbac_enc;
+            self.core.c_curr_best[self.log2_max_cuwh as usize - 2]
+                [self.log2_max_cuwh as usize - 2] = self.sbac_ctx;
+
+            self.core.s_curr_best[self.log2_max_cuwh as usize - 2]
+                [self.log2_max_cuwh as usize - 2]
+                .is_bitcount = true;
+
+            /* analyzer lcu */
+
+            // TRACE_RDO = 0: comment this line, otherwise, 2: uncomment it
+            self.core.bs_temp.tracer = self.bs.tracer.take();
+            self.mode_analyze_lcu();
+            self.bs.tracer = self.core.bs_temp.tracer.take();
+
+            /* entropy coding ************************************************/
+            self.evce_eco_tree(
+                self.core.x_pel,
+                self.core.y_pel,
+                self.max_cuwh,
+                self.max_cuwh,
+                0,
+                0,
+                true,
+                false,
+                0,
+                0,
+                ModeCons::eAll,
+            );
+
+            /* prepare next step *********************************************/
+            self.core.x_lcu += 1;
+            if self.core.x_lcu >= self.tile.x_lcu + self.tile.w_lcu {
+                self.core.x_lcu = self.tile.x_lcu;
+                self.core.y_lcu += 1;
+            }
+
+            self.core
+                .update_core_loc_param(self.log2_max_cuwh, self.w_lcu);
+            lcu_cnt -= 1;
+
+            if lcu_cnt == 0 {
+                evce_eco_tile_end_flag(&mut self.bs, &mut self.sbac_enc, 1);
+                self.sbac_enc.finish(&mut self.bs);
+                break;
+            }
+        } //End of Slice encoding LCU loop
+
+        /* de-init BSW */
+        self.bs.deinit();
+
+        /* write the bitstream size */
+        self.bs.write_nalu_size();
+    }
+
+    /* write the slice NALU again from the modes decided while encoding it,
+    with the ALF CTU flags of map_alf */
+    fn write_slice(
+        &mut self,
+        sps: &EvcSps,
+        pps: &EvcPps,
+        nalu: &EvcNalu,
+        map_alf: Option<&[[bool; N_C]]>,
+    ) {
+        evce_eco_nalu(&mut self.bs, nalu);
+        evce_eco_sh(&mut self.bs, sps, pps, &self.sh, nalu.nal_unit_type);
+
+        self.sbac_enc.reset(
+            &mut self.sbac_ctx,
+            self.sh.slice_type,
+            self.sh.qp,
+            self.sps_cm_init_flag,
+        );
+
+        /* the CUs of the tile are coded again */
+        let (x_scu, y_scu, w_scu, h_scu) = evc_tile_scu_rect(&self.tile, self.w_scu, self.h_scu);
+        for j in y_scu..y_scu + h_scu {
+            let idx = j * self.w_scu as usize + x_scu;
+            for scu in &mut self.map_scu[idx..idx + w_scu] {
+                scu.CLR_COD();
+            }
+        }
+
+        self.core.x_lcu = self.tile.x_lcu;
+        self.core.y_lcu = self.tile.y_lcu;
+        let mut lcu_cnt = self.tile.f_lcu;
+        self.core
+            .update_core_loc_param(self.log2_max_cuwh, self.w_lcu);
+
+        loop {
+            if let Some(map_alf) = map_alf {
+                let flags = &map_alf[self.core.lcu_num as usize];
+                if self.sh.alf_map_flag {
+                    evce_eco_alf_ctb_flag(
+                        &mut self.bs,
+                        &mut self.sbac_enc,
+                        &mut self.sbac_ctx,
+                        flags[Y_C],
+                    );
+                }
+            }
+
+            self.evce_eco_tree(
+                self.core.x_pel,
+                self.core.y_pel,
+                self.max_cuwh,
+                self.max_cuwh,
+                0,
+                0,
+                true,
+                false,
+                0,
+                0,
+                ModeCons::eAll,
+            );
+
+            self.core.x_lcu += 1;
+            if self.core.x_lcu >= self.tile.x_lcu + self.tile.w_lcu {
+                self.core.x_lcu = self.tile.x_lcu;
+                self.core.y_lcu += 1;
+            }
+
+            self.core
+                .update_core_loc_param(self.log2_max_cuwh, self.w_lcu);
+            lcu_cnt -= 1;
+
+            if lcu_cnt == 0 {
+                evce_eco_tile_end_flag(&mut self.bs, &mut self.sbac_enc, 1);
+                self.sbac_enc.finish(&mut self.bs);
+                break;
+            }
+        }
+
+        self.bs.deinit();
+        self.bs.write_nalu_size();
     }
 
     fn evce_eco_tree(
@@ -1396,8 +2360,10 @@ impl EvceCtx {
         cup: u16,
         cud: u16,
         next_split: bool,
+        parent_suco: bool,
         qt_depth: u8,
         mut cu_qp_delta_code: u8,
+        mode_cons: ModeCons,
     ) {
         let core = &mut self.core;
         let bs = &mut self.bs;
@@ -1413,9 +2379,10 @@ impl EvceCtx {
             &self.map_cu_data[core.lcu_num as usize].split_mode,
         );
 
-        if self.pps.cu_qp_delta_enabled_flag && self.sps.dquant_flag {
+        if self.pps_cu_qp_delta_enabled_flag && self.sps_dquant_flag {
+            let log2_cuwh = CONV_LOG2(cuw as usize) + CONV_LOG2(cuh as usize);
             if split_mode == SplitMode::NO_SPLIT
-                && (CONV_LOG2(cuw as usize) + CONV_LOG2(cuh as usize) >= self.pps.cu_qp_delta_area)
+                && log2_cuwh >= self.pps_cu_qp_delta_area
                 && cu_qp_delta_code != 2
             {
                 if CONV_LOG2(cuw as usize) == 7 || CONV_LOG2(cuh as usize) == 7 {
@@ -1424,8 +2391,8 @@ impl EvceCtx {
                     cu_qp_delta_code = 1;
                 }
                 core.cu_qp_delta_is_coded = false;
-            } else if CONV_LOG2(cuh as usize) + CONV_LOG2(cuw as usize) == self.pps.cu_qp_delta_area
-                && cu_qp_delta_code != 2
+            } else if (split_mode.is_tt() && log2_cuwh == self.pps_cu_qp_delta_area + 1)
+                || (log2_cuwh == self.pps_cu_qp_delta_area && cu_qp_delta_code != 2)
             {
                 cu_qp_delta_code = 2;
                 core.cu_qp_delta_is_coded = false;
@@ -1433,19 +2400,75 @@ impl EvceCtx {
         }
 
         if split_mode != SplitMode::NO_SPLIT {
-            evce_eco_split_mode(
-                bs,
-                sbac,
-                sbac_ctx,
-                x0,
-                y0,
+            /* the split of a block crossing the picture boundary is only
+            signalled without binary and ternary splits */
+            if (x0 + cuw <= self.w && y0 + cuh <= self.h) || !self.sps_btt_flag {
+                evce_eco_split_mode(
+                    bs,
+                    sbac,
+                    sbac_ctx,
+                    x0,
+                    y0,
+                    cud,
+                    cup,
+                    cuw,
+                    cuh,
+                    self.max_cuwh,
+                    &self.map_cu_data[core.lcu_num as usize].split_mode,
+                    self.w,
+                    self.h,
+                    self.w_scu,
+                    &self.map_scu,
+                    self.sps_btt_flag,
+                    &self.split_tbl,
+                    mode_cons,
+                    self.sps_cm_init_flag,
+                );
+            }
+
+            let suco_flag = evc_get_suco_flag(
                 cud,
                 cup,
                 cuw,
                 cuh,
                 self.max_cuwh,
-                &self.map_cu_data[core.lcu_num as usize].split_mode,
+                &self.map_cu_data[core.lcu_num as usize].suco_flag,
             );
+            let boundary = !(x0 + cuw <= self.w && y0 + cuh <= self.h);
+            if self.sps_suco_flag
+                && evc_check_suco_cond(
+                    cuw,
+                    cuh,
+                    split_mode,
+                    boundary,
+                    self.log2_max_cuwh,
+                    self.log2_diff_ctu_size_max_suco_cb_size,
+                    self.log2_diff_max_suco_min_suco_cb_size,
+                )
+            {
+                evce_eco_suco_flag(
+                    bs,
+                    sbac,
+                    sbac_ctx,
+                    CONV_LOG2(cuw as usize),
+                    CONV_LOG2(cuh as usize),
+                    self.sps_cm_init_flag,
+                    suco_flag,
+                );
+            } else {
+                debug_assert_eq!(suco_flag, parent_suco);
+            }
+
+            /* the encoder only constrains the parts of a split to inter */
+            let mut mode_cons = mode_cons;
+            if self.sps_btt_flag
+                && self.sps_admvp_flag
+                && mode_cons == ModeCons::eAll
+                && !evc_is_chroma_split_allowed(cuw, cuh, split_mode)
+            {
+                mode_cons = ModeCons::eOnlyInter;
+                evce_eco_mode_constr(bs, sbac, sbac_ctx, mode_cons);
+            }
 
             let split_struct = evc_split_get_part_structure(
                 split_mode,
@@ -1458,7 +2481,9 @@ impl EvceCtx {
                 self.log2_culine,
             );
 
-            for cur_part_num in 0..split_struct.part_count {
+            let suco_order = evc_split_get_suco_order(suco_flag, split_mode);
+            for part_num in 0..split_struct.part_count {
+                let cur_part_num = suco_order[part_num];
                 let sub_cuw = split_struct.width[cur_part_num];
                 let sub_cuh = split_struct.height[cur_part_num];
                 let x_pos = split_struct.x_pos[cur_part_num];
@@ -1473,15 +2498,17 @@ impl EvceCtx {
                         split_struct.cup[cur_part_num],
                         split_struct.cud[cur_part_num],
                         true,
+                        suco_flag,
                         split_mode.inc_qt_depth(qt_depth),
                         cu_qp_delta_code,
+                        mode_cons,
                     );
                 }
             }
         } else {
             assert!(x0 + cuw <= self.w && y0 + cuh <= self.h);
 
-            if (cuw > MIN_CU_SIZE as u16 || cuh > MIN_CU_SIZE as u16) && next_split {
+            if (cuw > self.min_cuwh || cuh > self.min_cuwh) && next_split {
                 evce_eco_split_mode(
                     bs,
                     sbac,
@@ -1494,10 +2521,26 @@ impl EvceCtx {
                     cuh,
                     self.max_cuwh,
                     &self.map_cu_data[core.lcu_num as usize].split_mode,
+                    self.w,
+                    self.h,
+                    self.w_scu,
+                    &self.map_scu,
+                    self.sps_btt_flag,
+                    &self.split_tbl,
+                    mode_cons,
+                    self.sps_cm_init_flag,
                 );
             }
 
             core.cu_qp_delta_code = cu_qp_delta_code;
+            /* 4x4 CUs of the main profile can only be intra predicted */
+            core.mode_cons = if self.slice_type == SliceType::EVC_ST_I
+                || (self.sps_admvp_flag && cuw == MIN_CU_SIZE as u16 && cuh == MIN_CU_SIZE as u16)
+            {
+                ModeCons::eOnlyIntra
+            } else {
+                mode_cons
+            };
             self.evce_eco_unit(x0, y0, cup as usize, cuw, cuh);
         }
     }
@@ -1509,7 +2552,7 @@ impl EvceCtx {
 
         EVC_TRACE_COUNTER(&mut self.bs.tracer);
         EVC_TRACE(&mut self.bs.tracer, "poc: ");
-        EVC_TRACE(&mut self.bs.tracer, self.poc.poc_val);
+        EVC_TRACE(&mut self.bs.tracer, self.poc_val);
         EVC_TRACE(&mut self.bs.tracer, " x pos ");
         EVC_TRACE(
             &mut self.bs.tracer,
@@ -1526,6 +2569,8 @@ impl EvceCtx {
         EVC_TRACE(&mut self.bs.tracer, cuh);
         EVC_TRACE(&mut self.bs.tracer, " \n");
 
+        let mmvd_num_groups =
+            evc_mmvd_num_groups(self.sh.mmvd_group_enable_flag, cuw as usize, cuh as usize);
         {
             let core = &mut self.core;
             let cu_data = &mut self.map_cu_data[core.lcu_num as usize];
@@ -1543,6 +2588,7 @@ impl EvceCtx {
                     y,
                     cuw,
                     cuh,
+                    self.chroma_format_idc,
                 );
 
                 for i in 0..N_C {
@@ -1555,10 +2601,53 @@ impl EvceCtx {
             }
 
             /* entropy coding a CU */
-            if slice_type != SliceType::EVC_ST_I {
-                evce_eco_skip_flag(bs, sbac, sbac_ctx, core.skip_flag as u32);
+            if core.mode_cons != ModeCons::eOnlyIntra {
+                evce_eco_skip_flag(
+                    bs,
+                    sbac,
+                    sbac_ctx,
+                    core.skip_flag as u32,
+                    core.ctx_flags[CNID_SKIP_FLAG],
+                );
+
+                let affine_flag = cu_data.affine_flag[cup as usize];
+                let mmvd_flag = cu_data.mmvd_flag[cup as usize];
+                if core.skip_flag && self.sps_mmvd_flag {
+                    evce_eco_mmvd_flag(bs, sbac, sbac_ctx, mmvd_flag as u32);
+                }
+                if core.skip_flag && !mmvd_flag && self.sps_affine_flag && cuw >= 8 && cuh >= 8 {
+                    evce_eco_affine_flag(
+                        bs,
+                        sbac,
+                        sbac_ctx,
+                        (affine_flag != 0) as u32,
+                        core.ctx_flags[CNID_AFFN_FLAG],
+                    );
+                }
 
-                if core.skip_flag {
+                if core.skip_flag && mmvd_flag {
+                    evce_eco_mmvd_idx(
+                        bs,
+                        sbac,
+                        sbac_ctx,
+                        cu_data.mmvd_idx[cup as usize],
+                        mmvd_num_groups,
+                    );
+                } else if core.skip_flag && affine_flag != 0 {
+                    evce_eco_affine_mrg_idx(
+                        bs,
+                        sbac,
+                        sbac_ctx,
+                        cu_data.mvp_idx[cup as usize][REFP_0] as u32,
+                    );
+                } else if core.skip_flag && self.sps_admvp_flag {
+                    evce_eco_merge_idx(
+                        bs,
+                        sbac,
+                        sbac_ctx,
+                        cu_data.mvp_idx[cup as usize][REFP_0] as u32,
+                    );
+                } else if core.skip_flag {
                     evce_eco_mvp_idx(
                         bs,
                         sbac,
@@ -1575,60 +2664,156 @@ impl EvceCtx {
                         );
                     }
                 } else {
-                    evce_eco_pred_mode(bs, sbac, sbac_ctx, core.cu_mode);
-
-                    if core.cu_mode != PredMode::MODE_INTRA {
-                        evce_eco_direct_mode_flag(
+                    if core.mode_cons == ModeCons::eAll {
+                        evce_eco_pred_mode(
                             bs,
                             sbac,
                             sbac_ctx,
-                            if cu_data.pred_mode[cup as usize] == PredMode::MODE_DIR {
-                                1
-                            } else {
-                                0
-                            },
+                            core.cu_mode,
+                            core.ctx_flags[CNID_PRED_MODE],
                         );
+                    }
 
-                        if cu_data.pred_mode[cup as usize] != PredMode::MODE_DIR {
+                    if core.cu_mode != PredMode::MODE_INTRA {
+                        let dir_flag = cu_data.pred_mode[cup as usize] == PredMode::MODE_DIR;
+                        let mvr_idx = cu_data.mvr_idx[cup as usize];
+                        if self.sps_admvp_flag {
+                            if self.sps_amvr_flag {
+                                evce_eco_mvr_idx(bs, sbac, sbac_ctx, mvr_idx as u32);
+                            }
+                            if mvr_idx == 0 {
+                                evce_eco_merge_mode_flag(bs, sbac, sbac_ctx, dir_flag as u32);
+                            }
+                            if dir_flag {
+                                if self.sps_mmvd_flag {
+                                    evce_eco_mmvd_flag(bs, sbac, sbac_ctx, mmvd_flag as u32);
+                                }
+                                if !mmvd_flag && self.sps_affine_flag && cuw >= 8 && cuh >= 8 {
+                                    evce_eco_affine_flag(
+                                        bs,
+                                        sbac,
+                                        sbac_ctx,
+                                        (affine_flag != 0) as u32,
+                                        core.ctx_flags[CNID_AFFN_FLAG],
+                                    );
+                                }
+                                if mmvd_flag {
+                                    evce_eco_mmvd_idx(
+                                        bs,
+                                        sbac,
+                                        sbac_ctx,
+                                        cu_data.mmvd_idx[cup as usize],
+                                        mmvd_num_groups,
+                                    );
+                                } else if affine_flag != 0 {
+                                    evce_eco_affine_mrg_idx(
+                                        bs,
+                                        sbac,
+                                        sbac_ctx,
+                                        cu_data.mvp_idx[cup as usize][REFP_0] as u32,
+                                    );
+                                } else {
+                                    evce_eco_merge_idx(
+                                        bs,
+                                        sbac,
+                                        sbac_ctx,
+                                        cu_data.mvp_idx[cup as usize][REFP_0] as u32,
+                                    );
+                                }
+                            }
+                        } else {
+                            evce_eco_direct_mode_flag(bs, sbac, sbac_ctx, dir_flag as u32);
+                        }
+
+                        if !dir_flag {
                             evce_eco_inter_pred_idc(
                                 bs,
                                 sbac,
                                 sbac_ctx,
                                 &cu_data.refi[cup as usize],
                                 slice_type,
+                                cuw,
+                                cuh,
+                                self.sps_admvp_flag,
                             );
 
-                            let refi0 = cu_data.refi[cup as usize][REFP_0];
-                            let refi1 = cu_data.refi[cup as usize][REFP_1];
-                            if slice_type.IS_INTER_SLICE() && REFI_IS_VALID(refi0) {
-                                evce_eco_refi(bs, sbac, sbac_ctx, self.rpm.num_refp[REFP_0], refi0);
-                                evce_eco_mvp_idx(
+                            if mvr_idx == 0 && self.sps_affine_flag && cuw >= 16 && cuh >= 16 {
+                                evce_eco_affine_flag(
                                     bs,
                                     sbac,
                                     sbac_ctx,
-                                    cu_data.mvp_idx[cup as usize][REFP_0] as u32,
+                                    (affine_flag != 0) as u32,
+                                    core.ctx_flags[CNID_AFFN_FLAG],
                                 );
+                            }
+
+                            let refi0 = cu_data.refi[cup as usize][REFP_0];
+                            let refi1 = cu_data.refi[cup as usize][REFP_1];
+                            if affine_flag != 0 {
+                                evce_eco_affine_mode(bs, sbac, sbac_ctx, affine_flag as u32 - 1);
+                                for lidx in 0..REFP_NUM {
+                                    let refi = cu_data.refi[cup as usize][lidx];
+                                    if REFI_IS_VALID(refi) {
+                                        evce_eco_affine_mvd(
+                                            bs,
+                                            sbac,
+                                            sbac_ctx,
+                                            lidx,
+                                            self.num_refp[lidx],
+                                            refi,
+                                            cu_data.mvp_idx[cup as usize][lidx],
+                                            &cu_data.affine_mvd[cup as usize][lidx],
+                                            affine_flag as usize + 1,
+                                        );
+                                    }
+                                }
+                            } else if self.sps_admvp_flag
+                                && REFI_IS_VALID(refi0)
+                                && REFI_IS_VALID(refi1)
+                            {
+                                evce_eco_bi_idx(bs, sbac, sbac_ctx, BI_NORMAL);
+                            }
+                            if affine_flag == 0
+                                && slice_type.IS_INTER_SLICE()
+                                && REFI_IS_VALID(refi0)
+                            {
+                                evce_eco_refi(bs, sbac, sbac_ctx, self.num_refp[REFP_0], refi0);
+                                if !self.sps_admvp_flag {
+                                    evce_eco_mvp_idx(
+                                        bs,
+                                        sbac,
+                                        sbac_ctx,
+                                        cu_data.mvp_idx[cup as usize][REFP_0] as u32,
+                                    );
+                                }
                                 evce_eco_mvd(
                                     bs,
                                     sbac,
                                     sbac_ctx,
                                     &cu_data.mvd[cup as usize][REFP_0],
+                                    mvr_idx,
                                 );
                             }
 
-                            if slice_type == SliceType::EVC_ST_B && REFI_IS_VALID(refi1) {
-                                evce_eco_refi(bs, sbac, sbac_ctx, self.rpm.num_refp[REFP_1], refi1);
-                                evce_eco_mvp_idx(
-                                    bs,
-                                    sbac,
-                                    sbac_ctx,
-                                    cu_data.mvp_idx[cup as usize][REFP_1] as u32,
-                                );
+                            if affine_flag == 0
+                                && slice_type == SliceType::EVC_ST_B
+                                && REFI_IS_VALID(refi1)
+                            {
+                                evce_eco_refi(bs, sbac, sbac_ctx, self.num_refp[REFP_1], refi1);
+                                if !self.sps_admvp_flag {
+                                    evce_eco_mvp_idx(
+                                        bs,
+                                        sbac,
+                                        sbac_ctx,
+                                        cu_data.mvp_idx[cup as usize][REFP_1] as u32,
+                                    );
+                                }
                                 evce_eco_mvd(
                                     bs,
                                     sbac,
                                     sbac_ctx,
                                     &cu_data.mvd[cup as usize][REFP_1],
+                                    mvr_idx,
                                 );
                             }
                         }
@@ -1637,35 +2822,64 @@ impl EvceCtx {
             }
 
             if core.cu_mode == PredMode::MODE_INTRA {
-                assert_ne!(
-                    cu_data.ipm[0][cup as usize] as i8,
-                    IntraPredDir::IPD_INVALID as i8
-                );
-                assert_ne!(
-                    cu_data.ipm[1][cup as usize] as i8,
-                    IntraPredDir::IPD_INVALID as i8
-                );
+                assert_ne!(cu_data.ipm[0][cup as usize], IntraPredDir::IPD_INVALID);
+                assert_ne!(cu_data.ipm[1][cup as usize], IntraPredDir::IPD_INVALID);
+
+                if self.sps_eipd_flag {
+                    evc_get_mpm(
+                        core.x_scu,
+                        core.y_scu,
+                        cuw,
+                        &self.map_scu,
+                        &self.map_ipm,
+                        core.scup,
+                        self.w_scu,
+                        &mut core.mpm_list,
+                    );
 
-                core.mpm_b_list = evc_get_mpm_b(
-                    core.x_scu,
-                    core.y_scu,
-                    &self.map_scu,
-                    &self.map_ipm,
-                    core.scup,
-                    self.w_scu,
-                );
+                    evce_eco_intra_dir(
+                        bs,
+                        sbac,
+                        sbac_ctx,
+                        cu_data.ipm[0][cup].0 as u8,
+                        &core.mpm_list,
+                    );
+                    if self.chroma_format_idc != 0 {
+                        evce_eco_intra_dir_c(
+                            bs,
+                            sbac,
+                            sbac_ctx,
+                            cu_data.ipm[1][cup].0 as u8,
+                            cu_data.ipm[0][cup],
+                        );
+                    }
+                } else {
+                    core.mpm_b_list = evc_get_mpm_b(
+                        core.x_scu,
+                        core.y_scu,
+                        &self.map_scu,
+                        &self.map_ipm,
+                        core.scup,
+                        self.w_scu,
+                    );
 
-                evce_eco_intra_dir_b(
-                    bs,
-                    sbac,
-                    sbac_ctx,
-                    cu_data.ipm[0][cup] as u8,
-                    core.mpm_b_list,
-                );
+                    evce_eco_intra_dir_b(
+                        bs,
+                        sbac,
+                        sbac_ctx,
+                        cu_data.ipm[0][cup].0 as u8,
+                        core.mpm_b_list,
+                    );
+                }
             }
         }
 
+        let mut ats_inter_info = 0;
         if !self.core.skip_flag {
+            let cu_data = &self.map_cu_data[self.core.lcu_num as usize];
+            let ats_intra_cu = cu_data.ats_intra_cu[cup];
+            let ats_mode = cu_data.ats_mode[cup];
+            ats_inter_info = cu_data.ats_inter_info[cup];
             evce_eco_coef(
                 &mut self.bs,
                 &mut self.sbac_enc,
@@ -1675,19 +2889,26 @@ impl EvceCtx {
                 self.core.log2_cuh,
                 self.core.cu_mode,
                 &self.core.nnz,
-                false,
+                self.sps_admvp_flag && self.core.cu_mode == PredMode::MODE_DIR,
                 TQC_RUN::RUN_L as u8 | TQC_RUN::RUN_CB as u8 | TQC_RUN::RUN_CR as u8,
                 true,
                 self.map_cu_data[self.core.lcu_num as usize].qp_y[cup] - 6 * (BIT_DEPTH as u8 - 8),
-                self.sps.dquant_flag,
-                self.pps.cu_qp_delta_enabled_flag,
+                self.sps_dquant_flag,
+                self.pps_cu_qp_delta_enabled_flag,
                 self.core.cu_qp_delta_code,
                 &mut self.core.cu_qp_delta_is_coded,
                 &mut self.core.qp_prev_eco,
+                self.sps_ats_flag,
+                self.sps_cm_init_flag,
+                self.sps_adcc_flag,
+                self.chroma_format_idc,
+                ats_intra_cu,
+                ats_mode,
+                ats_inter_info,
             );
         }
 
-        self.evce_set_enc_info();
+        self.evce_set_enc_info(ats_inter_info);
     }
 
     fn cu_init(&mut self, x: u16, y: u16, cup: usize, cuw: u16, cuh: u16) {
@@ -1737,9 +2958,18 @@ impl EvceCtx {
         }
 
         core.avail_lr = evc_check_nev_avail(core.x_scu, core.y_scu, cuw, self.w_scu, &self.map_scu);
+        core.ctx_flags = evc_get_ctx_some_flags(
+            core.x_scu,
+            core.y_scu,
+            cuw,
+            cuh,
+            self.w_scu,
+            &self.map_scu,
+            self.sps_cm_init_flag,
+        );
     }
 
-    fn evce_set_enc_info(&mut self) {
+    fn evce_set_enc_info(&mut self, ats_inter_info: u8) {
         let w_scu = self.w_scu as usize;
         let scup = self.core.scup as usize;
         let w_cu = (1 << self.core.log2_cuw as usize) >> MIN_CU_LOG2;
@@ -1759,73 +2989,30 @@ impl EvceCtx {
                 } else {
                     map_scu[j].CLR_CBFL();
                 }
+                if ats_inter_info != 0 {
+                    map_scu[j].SET_ATS_INTER();
+                } else {
+                    map_scu[j].CLR_ATS_INTER();
+                }
 
                 map_scu[j].SET_COD();
+                map_scu[j].SET_LOGW_LOGH(self.core.log2_cuw, self.core.log2_cuh);
 
-                if self.pps.cu_qp_delta_enabled_flag {
+                if self.pps_cu_qp_delta_enabled_flag {
                     map_scu[j].RESET_QP();
                     map_scu[j].SET_QP(self.core.qp_prev_eco as u32);
                 }
             }
         }
-    }
 
-    fn evce_encode_sps(&mut self) {
-        /* bitsteam initialize for sequence */
-        self.bs.init();
-        self.bs.tracer = self.tracer.take();
-
-        /* nalu header */
-        self.nalu.set_nalu(NaluType::EVC_SPS_NUT, 0);
-
-        evce_eco_nalu(&mut self.bs, &self.nalu);
-
-        /* sequence parameter set*/
-        self.set_sps();
-        evce_eco_sps(&mut self.bs, &self.sps);
-
-        /* de-init BSW */
-        self.bs.deinit();
-        self.tracer = self.bs.tracer.take();
-
-        /* write the bitstream size */
-        self.bs.write_nalu_size();
-
-        /* append bs.pkt to ctx.pkt */
-        if let Some(bs_pkt) = self.bs.pkt.take() {
-            if let Some(pkt) = &mut self.pkt {
-                pkt.data.extend_from_slice(&bs_pkt.data);
-            }
-        }
-    }
-
-    fn evce_encode_pps(&mut self) {
-        /* bitsteam initialize for sequence */
-        self.bs.init();
-        self.bs.tracer = self.tracer.take();
-
-        /* nalu header */
-        self.nalu
-            .set_nalu(NaluType::EVC_PPS_NUT, self.nalu.nuh_temporal_id);
-
-        evce_eco_nalu(&mut self.bs, &self.nalu);
-
-        /* sequence parameter set*/
-        self.set_pps();
-        evce_eco_pps(&mut self.bs, &self.pps);
-
-        /* de-init BSW */
-        self.bs.deinit();
-        self.tracer = self.bs.tracer.take();
-
-        /* write the bitstream size */
-        self.bs.write_nalu_size();
-
-        /* append bs.pkt to ctx.pkt */
-        if let Some(bs_pkt) = self.bs.pkt.take() {
-            if let Some(pkt) = &mut self.pkt {
-                pkt.data.extend_from_slice(&bs_pkt.data);
-            }
+        if self.core.nnz[Y_C] > 0 && ats_inter_info != 0 {
+            evc_set_cu_cbf_flags(
+                ats_inter_info,
+                self.core.log2_cuw,
+                self.core.log2_cuh,
+                &mut self.map_scu[scup..],
+                w_scu,
+            );
         }
     }
 }
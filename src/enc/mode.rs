@@ -10,6 +10,7 @@ use crate::tracer::*;
 #[derive(Default)]
 pub(crate) struct EvceCUData {
     pub(crate) split_mode: LcuSplitMode,
+    pub(crate) suco_flag: LcuSucoFlag,
     pub(crate) qp_y: Vec<u8>,
     pub(crate) qp_u: Vec<u8>,
     pub(crate) qp_v: Vec<u8>,
@@ -19,8 +20,20 @@ pub(crate) struct EvceCUData {
     pub(crate) refi: Vec<[i8; REFP_NUM]>,
     pub(crate) mvp_idx: Vec<Vec<u8>>,
     pub(crate) mv: Vec<[[i16; MV_D]; REFP_NUM]>, //[MAX_CU_CNT_IN_LCU][REFP_NUM][MV_D];
-    pub(crate) mvd: Vec<Vec<Vec<i16>>>,          //[MAX_CU_CNT_IN_LCU][REFP_NUM][MV_D];
-    pub(crate) nnz: Vec<Vec<u16>>,               //[N_C];
+    /* motion vectors before decoder-side refinement */
+    pub(crate) unrefined_mv: Vec<[[i16; MV_D]; REFP_NUM]>,
+    pub(crate) mvd: Vec<Vec<Vec<i16>>>, //[MAX_CU_CNT_IN_LCU][REFP_NUM][MV_D];
+    pub(crate) mvr_idx: Vec<u8>,
+    pub(crate) mmvd_flag: Vec<bool>,
+    pub(crate) mmvd_idx: Vec<u16>,
+    pub(crate) ats_intra_cu: Vec<bool>,
+    pub(crate) ats_mode: Vec<u8>,
+    pub(crate) ats_inter_info: Vec<u8>,
+    pub(crate) affine_flag: Vec<u8>,
+    pub(crate) affine_mv: Vec<[[[i16; MV_D]; VER_NUM]; REFP_NUM]>,
+    pub(crate) affine_mvd: Vec<[[[i16; MV_D]; VER_NUM]; REFP_NUM]>,
+    pub(crate) map_affine: Vec<u32>,
+    pub(crate) nnz: Vec<Vec<u16>>, //[N_C];
     pub(crate) map_scu: Vec<MCU>,
     pub(crate) depth: Vec<i8>,
     pub(crate) coef: Vec<Vec<i16>>, //[N_C];
@@ -37,14 +50,14 @@ impl EvceCUData {
 
         let mut coef = Vec::with_capacity(N_C);
         let mut reco = Vec::with_capacity(N_C);
-        for i in 0..N_C {
-            let chroma = if i > 0 { 1 } else { 0 };
-            coef.push(vec![0; pixel_cnt >> (chroma * 2)]);
-            reco.push(vec![0; pixel_cnt >> (chroma * 2)]);
+        for _ in 0..N_C {
+            coef.push(vec![0; pixel_cnt]);
+            reco.push(vec![0; pixel_cnt]);
         }
 
         EvceCUData {
             split_mode: LcuSplitMode::default(),
+            suco_flag: LcuSucoFlag::default(),
             qp_y: vec![0; cu_cnt],
             qp_u: vec![0; cu_cnt],
             qp_v: vec![0; cu_cnt],
@@ -54,7 +67,18 @@ impl EvceCUData {
             refi: vec![[0; REFP_NUM]; cu_cnt],
             mvp_idx: vec![vec![0; REFP_NUM]; cu_cnt],
             mv: vec![[[0; MV_D]; REFP_NUM]; cu_cnt],
+            unrefined_mv: vec![[[0; MV_D]; REFP_NUM]; cu_cnt],
             mvd: vec![vec![vec![0; MV_D]; REFP_NUM]; cu_cnt],
+            mvr_idx: vec![0; cu_cnt],
+            mmvd_flag: vec![false; cu_cnt],
+            mmvd_idx: vec![0; cu_cnt],
+            ats_intra_cu: vec![false; cu_cnt],
+            ats_mode: vec![0; cu_cnt],
+            ats_inter_info: vec![0; cu_cnt],
+            affine_flag: vec![0; cu_cnt],
+            affine_mv: vec![[[[0; MV_D]; VER_NUM]; REFP_NUM]; cu_cnt],
+            affine_mvd: vec![[[[0; MV_D]; VER_NUM]; REFP_NUM]; cu_cnt],
+            map_affine: vec![0; cu_cnt],
             nnz: vec![vec![0; cu_cnt]; N_C],
             map_scu: vec![MCU::default(); cu_cnt],
             depth: vec![0; cu_cnt],
@@ -72,6 +96,9 @@ impl EvceCUData {
                 for v in &mut self.split_mode.data[i][j] {
                     *v = SplitMode::NO_SPLIT;
                 }
+                for v in &mut self.suco_flag.data[i][j] {
+                    *v = false;
+                }
             }
         }
 
@@ -93,6 +120,7 @@ impl EvceCUData {
         log2_cuh: u8,
         log2_cus: u8,
         cud: u16,
+        chroma_format_idc: u8,
     ) {
         let cx = x as usize >> MIN_CU_LOG2; //x = position in LCU, cx = 4x4 CU horizontal index
         let cy = y as usize >> MIN_CU_LOG2; //y = position in LCU, cy = 4x4 CU vertical index
@@ -114,6 +142,8 @@ impl EvceCUData {
                 for i in 0..BlockShape::NUM_BLOCK_SHAPE as usize {
                     self.split_mode.data[k][i][idx_dst..idx_dst + size]
                         .copy_from_slice(&src.split_mode.data[k][i][idx_src..idx_src + size]);
+                    self.suco_flag.data[k][i][idx_dst..idx_dst + size]
+                        .copy_from_slice(&src.suco_flag.data[k][i][idx_src..idx_src + size]);
                 }
             }
 
@@ -132,7 +162,29 @@ impl EvceCUData {
             self.mvp_idx[idx_dst..idx_dst + size]
                 .clone_from_slice(&src.mvp_idx[idx_src..idx_src + size]);
             self.mv[idx_dst..idx_dst + size].clone_from_slice(&src.mv[idx_src..idx_src + size]);
+            self.unrefined_mv[idx_dst..idx_dst + size]
+                .copy_from_slice(&src.unrefined_mv[idx_src..idx_src + size]);
             self.mvd[idx_dst..idx_dst + size].clone_from_slice(&src.mvd[idx_src..idx_src + size]);
+            self.mvr_idx[idx_dst..idx_dst + size]
+                .copy_from_slice(&src.mvr_idx[idx_src..idx_src + size]);
+            self.mmvd_flag[idx_dst..idx_dst + size]
+                .copy_from_slice(&src.mmvd_flag[idx_src..idx_src + size]);
+            self.mmvd_idx[idx_dst..idx_dst + size]
+                .copy_from_slice(&src.mmvd_idx[idx_src..idx_src + size]);
+            self.ats_intra_cu[idx_dst..idx_dst + size]
+                .copy_from_slice(&src.ats_intra_cu[idx_src..idx_src + size]);
+            self.ats_mode[idx_dst..idx_dst + size]
+                .copy_from_slice(&src.ats_mode[idx_src..idx_src + size]);
+            self.ats_inter_info[idx_dst..idx_dst + size]
+                .copy_from_slice(&src.ats_inter_info[idx_src..idx_src + size]);
+            self.affine_flag[idx_dst..idx_dst + size]
+                .copy_from_slice(&src.affine_flag[idx_src..idx_src + size]);
+            self.affine_mv[idx_dst..idx_dst + size]
+                .copy_from_slice(&src.affine_mv[idx_src..idx_src + size]);
+            self.affine_mvd[idx_dst..idx_dst + size]
+                .copy_from_slice(&src.affine_mvd[idx_src..idx_src + size]);
+            self.map_affine[idx_dst..idx_dst + size]
+                .copy_from_slice(&src.map_affine[idx_src..idx_src + size]);
             self.nnz[Y_C][idx_dst..idx_dst + size]
                 .copy_from_slice(&src.nnz[Y_C][idx_src..idx_src + size]);
         }
@@ -148,10 +200,13 @@ impl EvceCUData {
                 .copy_from_slice(&src.reco[Y_C][idx_src..idx_src + size]);
         }
 
-        let size = cuw >> 1;
-        for j in 0..cuh >> 1 {
-            let idx_dst = ((y >> 1) as usize + j) * (cus >> 1) + (x >> 1) as usize;
-            let idx_src = j * (cuw >> 1);
+        let w_shift = GET_CHROMA_W_SHIFT(chroma_format_idc);
+        let h_shift = GET_CHROMA_H_SHIFT(chroma_format_idc);
+        let size = cuw >> w_shift;
+        for j in 0..cuh >> h_shift {
+            let idx_dst =
+                ((y as usize >> h_shift) + j) * (cus >> w_shift) + (x as usize >> w_shift);
+            let idx_src = j * (cuw >> w_shift);
 
             self.coef[U_C][idx_dst..idx_dst + size]
                 .copy_from_slice(&src.coef[U_C][idx_src..idx_src + size]);
@@ -190,6 +245,7 @@ impl EvceCUData {
         mut w: usize,
         mut h: usize,
         planes: &mut [Plane<pel>; N_C],
+        chroma_format_idc: u8,
     ) {
         let mut stride = w;
         if x + w > planes[Y_C].cfg.width {
@@ -212,12 +268,18 @@ impl EvceCUData {
 
         TRACE_CU(tracer, Y_C, w, h, stride, src);
 
+        if chroma_format_idc == 0 {
+            return;
+        }
+
         /* chroma */
-        x >>= 1;
-        y >>= 1;
-        w >>= 1;
-        h >>= 1;
-        stride >>= 1;
+        let w_shift = GET_CHROMA_W_SHIFT(chroma_format_idc);
+        let h_shift = GET_CHROMA_H_SHIFT(chroma_format_idc);
+        x >>= w_shift;
+        y >>= h_shift;
+        w >>= w_shift;
+        h >>= h_shift;
+        stride >>= w_shift;
 
         {
             let dst = &mut planes[U_C].as_region_mut();
@@ -256,12 +318,15 @@ impl EvceCUData {
         rec_src: &CUBuffer<pel>,
         slice_num: usize,
         ipm: &[IntraPredDir],
+        ats_intra_cu: bool,
+        ats_mode: u8,
         mi: &EvceMode,
         qp: u8,
         qp_y: u8,
         qp_u: u8,
         qp_v: u8,
         nnz: &[u16],
+        chroma_format_idc: u8,
     ) {
         let size = cuw as usize * cuh as usize;
 
@@ -273,7 +338,7 @@ impl EvceCUData {
 
         /* copy mode info */
         let mut idx = 0;
-        for _ in 0..(cuh as usize) >> MIN_CU_LOG2 {
+        for j in 0..(cuh as usize) >> MIN_CU_LOG2 {
             for i in 0..(cuw as usize) >> MIN_CU_LOG2 {
                 self.pred_mode[idx + i] = cu_mode;
                 self.skip_flag[idx + i] = cu_mode == PredMode::MODE_SKIP;
@@ -290,6 +355,8 @@ impl EvceCUData {
                     slice_num as u32,
                     qp,
                 );
+                self.map_scu[idx + i]
+                    .SET_LOGW_LOGH(CONV_LOG2(cuw as usize), CONV_LOG2(cuh as usize));
 
                 if self.skip_flag[idx + i] {
                     self.map_scu[idx + i].SET_SF();
@@ -299,38 +366,82 @@ impl EvceCUData {
 
                 self.depth[idx + i] = cud as i8;
 
+                let affine_flag = if cu_mode == PredMode::MODE_INTRA {
+                    0
+                } else {
+                    mi.affine_flag
+                };
+                self.affine_flag[idx + i] = affine_flag;
+                self.map_scu[idx + i].SET_AFF(affine_flag as u32);
+                self.map_affine[idx + i] =
+                    evc_affine_map_info(CONV_LOG2(cuw as usize), CONV_LOG2(cuh as usize), i, j);
+
                 if cu_mode == PredMode::MODE_INTRA {
                     self.ipm[0][idx + i] = ipm[0];
                     self.mv[idx + i][REFP_0][MV_X] = 0;
                     self.mv[idx + i][REFP_0][MV_Y] = 0;
                     self.mv[idx + i][REFP_1][MV_X] = 0;
                     self.mv[idx + i][REFP_1][MV_Y] = 0;
+                    self.unrefined_mv[idx + i] = [[0; MV_D]; REFP_NUM];
                     self.refi[idx + i][REFP_0] = -1;
                     self.refi[idx + i][REFP_1] = -1;
+                    self.mvr_idx[idx + i] = 0;
+                    self.mmvd_flag[idx + i] = false;
+                    self.ats_intra_cu[idx + i] = ats_intra_cu;
+                    self.ats_mode[idx + i] = ats_mode;
+                    self.ats_inter_info[idx + i] = 0;
                 } else {
                     self.refi[idx + i][REFP_0] = mi.refi[REFP_0];
                     self.refi[idx + i][REFP_1] = mi.refi[REFP_1];
                     self.mvp_idx[idx + i][REFP_0] = mi.mvp_idx[REFP_0];
                     self.mvp_idx[idx + i][REFP_1] = mi.mvp_idx[REFP_1];
 
-                    {
-                        self.mv[idx + i][REFP_0][MV_X] = mi.mv[REFP_0][MV_X];
-                        self.mv[idx + i][REFP_0][MV_Y] = mi.mv[REFP_0][MV_Y];
-                        self.mv[idx + i][REFP_1][MV_X] = mi.mv[REFP_1][MV_X];
-                        self.mv[idx + i][REFP_1][MV_Y] = mi.mv[REFP_1][MV_Y];
-                    }
+                    /* as in the decoder, the refined motion is kept for
+                    temporal prediction */
+                    self.mv[idx + i] = if mi.dmvr_flag {
+                        mi.dmvr_mv[idx + i]
+                    } else {
+                        mi.mv
+                    };
+                    self.unrefined_mv[idx + i] = mi.mv;
 
                     self.mvd[idx + i][REFP_0][MV_X] = mi.mvd[REFP_0][MV_X];
                     self.mvd[idx + i][REFP_0][MV_Y] = mi.mvd[REFP_0][MV_Y];
                     self.mvd[idx + i][REFP_1][MV_X] = mi.mvd[REFP_1][MV_X];
                     self.mvd[idx + i][REFP_1][MV_Y] = mi.mvd[REFP_1][MV_Y];
+                    self.mvr_idx[idx + i] = mi.mvr_idx;
+                    self.mmvd_flag[idx + i] = mi.mmvd_flag;
+                    self.mmvd_idx[idx + i] = mi.mmvd_idx;
+                    self.ats_intra_cu[idx + i] = false;
+                    self.ats_mode[idx + i] = 0;
+                    self.ats_inter_info[idx + i] = mi.ats_inter_info;
+
+                    self.affine_mv[idx + i] = mi.affine_mv;
+                    self.affine_mvd[idx + i] = mi.affine_mvd;
                 }
             }
 
             idx += (cuw as usize) >> MIN_CU_LOG2;
         }
 
-        let size = (cuw as usize * cuh as usize) >> 2;
+        /* the motion field of an affine CU is stored per sub-block */
+        if cu_mode != PredMode::MODE_INTRA && mi.affine_flag != 0 {
+            evc_set_affine_mvf(
+                0,
+                cuw as usize,
+                cuh as usize,
+                (cuw as usize) >> MIN_CU_LOG2,
+                &mi.refi,
+                &mi.affine_mv,
+                mi.affine_flag as usize + 1,
+                &mut self.mv,
+            );
+            let size = (cuw as usize * cuh as usize) >> (MIN_CU_LOG2 << 1);
+            self.unrefined_mv[..size].copy_from_slice(&self.mv[..size]);
+        }
+
+        let size = (cuw as usize * cuh as usize)
+            >> (GET_CHROMA_W_SHIFT(chroma_format_idc) + GET_CHROMA_H_SHIFT(chroma_format_idc));
 
         /* copy coef */
         self.coef[U_C][0..size].copy_from_slice(&coef_src.data[U_C][0..size]);
@@ -370,18 +481,36 @@ pub(crate) struct EvceMode {
     pub(crate) refi: [i8; REFP_NUM],
     /* MVP indices */
     pub(crate) mvp_idx: [u8; REFP_NUM],
-    /* MVR indices */
-    //u8    mvr_idx;
+    /* MVR index */
+    pub(crate) mvr_idx: u8,
     bi_idx: u8,
+    /* MMVD flag and packed MMVD index */
+    pub(crate) mmvd_flag: bool,
+    pub(crate) mmvd_idx: u16,
+    /* sub-block transform of the residual, packed as idx | (pos << 4) */
+    pub(crate) ats_inter_info: u8,
     /* mv difference */
     pub(crate) mvd: [[i16; MV_D]; REFP_NUM],
 
     /* mv */
     pub(crate) mv: [[i16; MV_D]; REFP_NUM],
 
+    /* affine flag: 0 for translational, 1 for 4-parameter, 2 for 6-parameter */
+    pub(crate) affine_flag: u8,
+    /* control point motion vectors and their differences */
+    pub(crate) affine_mv: [[[i16; MV_D]; VER_NUM]; REFP_NUM],
+    pub(crate) affine_mvd: [[[i16; MV_D]; VER_NUM]; REFP_NUM],
+    /* whether the motion is refined by DMVR, and the refined motion of every
+    4x4 block */
+    pub(crate) dmvr_flag: bool,
+    pub(crate) dmvr_mv: Vec<[[i16; MV_D]; REFP_NUM]>,
+
     pub(crate) inter_best_idx: usize, //pel  *pred_y_best;
 
     cu_mode: MCU,
+
+    /* accumulated RD cost of the LCUs of current picture */
+    pub(crate) cost: f64,
 }
 
 impl EvceMode {
@@ -402,10 +531,13 @@ impl EvceMode {
 
         self.refi[REFP_0] = src.refi[idx_src][REFP_0];
         self.refi[REFP_1] = src.refi[idx_src][REFP_1];
+
+        self.affine_flag = src.affine_flag[idx_src];
+        self.affine_mv = src.affine_mv[idx_src];
     }
 }
 
-impl EvceCtx {
+impl EvceTask {
     pub(crate) fn mode_init_frame(&mut self) {
         let mi = &mut self.mode;
         /* set default values to mode information */
@@ -437,6 +569,19 @@ impl EvceCtx {
         for i in 0..REFP_NUM {
             mi.mvp_idx[i] = 0;
         }
+
+        /* initialize save & load data of split decisions */
+        if self.sps_btt_flag {
+            let max_log2 = self.log2_max_cuwh as usize - 1;
+            let cu_cnt = 1 << (self.log2_culine << 1);
+            for bef_w in self.core.bef_data[..max_log2].iter_mut() {
+                for bef_h in bef_w[..max_log2].iter_mut() {
+                    for bef in bef_h[..cu_cnt].iter_mut() {
+                        *bef = EvceBefData::default();
+                    }
+                }
+            }
+        }
         for i in 0..REFP_NUM {
             for j in 0..MV_D {
                 mi.mvd[i][j] = 0;
@@ -444,7 +589,7 @@ impl EvceCtx {
         }
 
         /* decide mode */
-        self.mode_coding_tree(
+        self.mode.cost += self.mode_coding_tree(
             self.core.x_pel,
             self.core.y_pel,
             0,
@@ -452,8 +597,10 @@ impl EvceCtx {
             self.log2_max_cuwh as usize,
             0,
             true,
+            false,
             0,
             self.qp,
+            ModeCons::eAll,
         );
 
         self.update_to_ctx_map();
@@ -466,10 +613,13 @@ impl EvceCtx {
             self.log2_max_cuwh,
             self.log2_max_cuwh,
             0,
+            self.chroma_format_idc,
         );
         // for deblock
         self.map_split[self.core.lcu_num as usize]
             .clone_from(&self.map_cu_data[self.core.lcu_num as usize].split_mode);
+        self.map_suco[self.core.lcu_num as usize]
+            .clone_from(&self.map_cu_data[self.core.lcu_num as usize].suco_flag);
 
         /* Reset all coded flag for the current lcu */
         self.core.x_scu = PEL2SCU(self.core.x_pel as usize) as u16;
@@ -505,8 +655,10 @@ impl EvceCtx {
         log2_cuh: usize,
         cud: u16,
         mut next_split: bool,
+        parent_suco: bool,
         qt_depth: u8,
         qp: u8,
+        mode_cons: ModeCons,
     ) -> f64 {
         // x0 = CU's left up corner horizontal index in entrie frame
         // y0 = CU's left up corner vertical index in entire frame
@@ -516,6 +668,7 @@ impl EvceCtx {
         let cuw = 1 << log2_cuw;
         let cuh = 1 << log2_cuh;
         let mut best_split_mode = SplitMode::NO_SPLIT;
+        let mut best_suco_flag = parent_suco;
         let mut bit_cnt = 0;
         let mut cost_best = MAX_COST;
         let mut cost_temp = MAX_COST;
@@ -546,6 +699,9 @@ impl EvceCtx {
         let mut cost_best_dqp = MAX_COST;
         let mut cu_mode_dqp = PredMode::MODE_INTRA;
         let mut dist_cu_best_dqp = 0;
+        /* HMVP history before the block and after its best coding */
+        let history_before = self.core.history_buffer;
+        let mut history_best = history_before;
 
         self.core.avail_lr = avail_lr;
 
@@ -558,7 +714,24 @@ impl EvceCtx {
         //based on CU size located at boundary
         if cuw > self.min_cuwh || cuh > self.min_cuwh {
             /***************************** Step 1: decide normatively allowed split modes ********************************/
-            evc_check_split_mode(&mut split_allow);
+            let boundary_b = boundary && x0 + cuw <= self.w && y0 + cuh > self.h;
+            let boundary_r = boundary && x0 + cuw > self.w && y0 + cuh <= self.h;
+            evc_check_split_mode(
+                &mut split_allow,
+                log2_cuw as u8,
+                log2_cuh as u8,
+                boundary,
+                boundary_b,
+                boundary_r,
+                self.log2_max_cuwh,
+                x0,
+                y0,
+                self.w,
+                self.h,
+                self.sps_btt_flag,
+                &self.split_tbl,
+                mode_cons,
+            );
             //save normatively allowed split modes, as it will be used in in child nodes for entropy coding of split mode
             curr_split_allow.copy_from_slice(&split_allow);
             for i in 1..MAX_SPLIT_NUM {
@@ -583,7 +756,15 @@ impl EvceCtx {
                 do_curr = true;
             }
 
-            self.check_run_split(next_split, do_curr, do_split, &mut split_allow);
+            self.check_run_split(
+                cup,
+                log2_cuw,
+                log2_cuh,
+                next_split,
+                do_curr,
+                do_split,
+                &mut split_allow,
+            );
         } else {
             split_allow[0] = true;
             for i in 1..MAX_SPLIT_NUM {
@@ -633,6 +814,14 @@ impl EvceCtx {
                         cuh,
                         cuw,
                         split_mode_buf,
+                        self.w,
+                        self.h,
+                        self.w_scu,
+                        &self.map_scu,
+                        self.sps_btt_flag,
+                        &self.split_tbl,
+                        mode_cons,
+                        self.sps_cm_init_flag,
                     );
 
                     bit_cnt = self.core.s_temp_run.get_bit_number();
@@ -667,7 +856,9 @@ impl EvceCtx {
                         .init(log2_cuw as u8, log2_cuh as u8);
 
                     self.clear_map_scu(x0, y0, cuw, cuh);
-                    cost_temp_dqp += self.mode_coding_unit(x0, y0, log2_cuw, log2_cuh, cud);
+                    self.core.history_buffer = history_before;
+                    cost_temp_dqp +=
+                        self.mode_coding_unit(x0, y0, log2_cuw, log2_cuh, cud, mode_cons);
 
                     if cost_best > cost_temp_dqp {
                         cu_mode_dqp = self.core.cu_mode;
@@ -681,6 +872,7 @@ impl EvceCtx {
                             log2_cuh as u8,
                             log2_cuw as u8,
                             cud,
+                            self.chroma_format_idc,
                         );
                         cost_best = cost_temp_dqp;
                         best_split_mode = SplitMode::NO_SPLIT;
@@ -699,7 +891,8 @@ impl EvceCtx {
                                 y0 as usize,
                                 cuw as usize,
                                 cuh as usize,
-                                &mut pic.borrow().frame.borrow_mut().planes,
+                                &mut pic.read().unwrap().frame.write().unwrap().planes,
+                                self.chroma_format_idc,
                             );
                         }
 
@@ -712,12 +905,37 @@ impl EvceCtx {
                             0,
                             log2_cuw as u8,
                         );
+                        history_best = history_before;
+                        if self.sps_hmvp_flag && self.core.cu_mode != PredMode::MODE_INTRA {
+                            if self.mode.affine_flag != 0 {
+                                /* affine CUs contribute the motion at their centre */
+                                let vertex_num = self.mode.affine_flag as usize + 1;
+                                let mut mv = [[0; MV_D]; REFP_NUM];
+                                let mut refi = [REFI_INVALID; REFP_NUM];
+                                for lidx in 0..REFP_NUM {
+                                    if REFI_IS_VALID(self.mode.refi[lidx]) {
+                                        mv[lidx] = evc_affine_center_mv(
+                                            &self.mode.affine_mv[lidx],
+                                            cuw as usize,
+                                            cuh as usize,
+                                            vertex_num,
+                                        );
+                                        refi[lidx] = self.mode.refi[lidx];
+                                    }
+                                }
+                                history_best.update(&mv, &refi);
+                            } else {
+                                history_best.update(&self.mode.mv, &self.mode.refi);
+                            }
+                        }
                     }
                 }
                 if is_dqp_set && self.core.cu_qp_delta_code_mode == 2 {
                     self.core.cu_qp_delta_code_mode = 0;
                 }
                 cost_temp = cost_best;
+                self.core.bef_data[log2_cuw - 2][log2_cuh - 2][cup as usize].split_cost
+                    [SplitMode::NO_SPLIT as usize] = cost_temp;
                 self.core.cu_mode = cu_mode_dqp;
                 self.core.dist_cu_best = dist_cu_best_dqp;
 
@@ -742,7 +960,7 @@ impl EvceCtx {
 
         if cost_best != MAX_COST
             && cud
-                >= if self.poc.poc_val % 2 != 0 {
+                >= if self.poc_val % 2 != 0 {
                     ENC_ECU_DEPTH_B - 2
                 } else {
                     ENC_ECU_DEPTH_B
@@ -767,230 +985,311 @@ impl EvceCtx {
             }
         }
 
-        if (cuw > MIN_CU_SIZE as u16 || cuh > MIN_CU_SIZE as u16) && next_split {
-            split_mode = SplitMode::SPLIT_QUAD;
-            if split_allow[split_mode as usize] {
-                let split_struct = evc_split_get_part_structure(
-                    split_mode,
-                    x0,
-                    y0,
-                    cuw,
-                    cuh,
-                    cup,
-                    cud,
-                    self.log2_culine,
-                );
-
-                let mut prev_log2_sub_cuw = split_struct.log_cuw[0] as usize;
-                let mut prev_log2_sub_cuh = split_struct.log_cuh[0] as usize;
-
-                self.core.cu_data_temp[log2_cuw - 2][log2_cuh - 2]
-                    .init(log2_cuw as u8, log2_cuh as u8);
-                self.clear_map_scu(x0, y0, cuw, cuh);
-
-                let mut cost_temp = 0.0;
-
-                if x0 + cuw <= self.w && y0 + cuh <= self.h {
-                    /* consider CU split flag */
-                    self.core.s_temp_run =
-                        self.core.s_curr_before_split[log2_cuw - 2][log2_cuh - 2];
-                    self.core.c_temp_run =
-                        self.core.c_curr_before_split[log2_cuw - 2][log2_cuh - 2];
-
-                    self.core.s_temp_run.bit_reset();
-                    evc_set_split_mode(
-                        &mut self.core.cu_data_temp[log2_cuw - 2][log2_cuh - 2].split_mode,
-                        split_mode,
-                        cud,
-                        0,
-                        cuw,
-                        cuh,
-                        cuw,
-                    );
-
-                    let split_mode_buf = if self.core.s_temp_run.is_bitcount {
-                        &self.core.cu_data_temp[log2_cuw - 2][log2_cuh - 2].split_mode
+        if (cuw > self.min_cuwh || cuh > self.min_cuwh) && next_split {
+            let split_mode_order = evc_split_get_split_rdo_order(cuw, cuh);
+            for split_mode_num in 1..MAX_SPLIT_NUM {
+                split_mode = split_mode_order[split_mode_num];
+                if split_allow[split_mode as usize] {
+                    /* parts with too small chroma blocks are all inter coded;
+                    intra parts would need separate luma and chroma trees */
+                    let mode_cons_signal = self.sps_btt_flag
+                        && self.sps_admvp_flag
+                        && mode_cons == ModeCons::eAll
+                        && !evc_is_chroma_split_allowed(cuw, cuh, split_mode);
+                    if mode_cons_signal
+                        && (self.sh.slice_type == SliceType::EVC_ST_I
+                            || evc_get_mode_cons_by_split(split_mode, cuw, cuh)
+                                == ModeCons::eOnlyIntra)
+                    {
+                        continue;
+                    }
+                    let child_mode_cons = if mode_cons_signal {
+                        ModeCons::eOnlyInter
                     } else {
-                        &self.map_cu_data[self.core.lcu_num as usize].split_mode
+                        mode_cons
                     };
-                    evce_eco_split_mode(
-                        &mut self.core.bs_temp,
-                        &mut self.core.s_temp_run,
-                        &mut self.core.c_temp_run,
-                        x0,
-                        y0,
-                        cud,
-                        0,
-                        cuw,
-                        cuh,
-                        cuw,
-                        split_mode_buf,
-                    );
 
-                    bit_cnt = self.core.s_temp_run.get_bit_number();
-                    cost_temp += (self.lambda[0] * bit_cnt as f64);
+                    let suco_allow = self.sps_suco_flag
+                        && evc_check_suco_cond(
+                            cuw,
+                            cuh,
+                            split_mode,
+                            boundary,
+                            self.log2_max_cuwh,
+                            self.log2_diff_ctu_size_max_suco_cb_size,
+                            self.log2_diff_max_suco_min_suco_cb_size,
+                        );
+                    /* try both coding orders where the suco flag is signalled */
+                    for suco_idx in 0..if suco_allow { 2 } else { 1 } {
+                        let suco_flag = if suco_allow {
+                            suco_idx == 1
+                        } else {
+                            parent_suco
+                        };
+                        let split_struct = evc_split_get_part_structure(
+                            split_mode,
+                            x0,
+                            y0,
+                            cuw,
+                            cuh,
+                            cup,
+                            cud,
+                            self.log2_culine,
+                        );
 
-                    self.core.s_curr_best[log2_cuw - 2][log2_cuh - 2] = self.core.s_temp_run;
-                    self.core.c_curr_best[log2_cuw - 2][log2_cuh - 2] = self.core.c_temp_run;
-                }
+                        let mut prev_log2_sub_cuw = split_struct.log_cuw[0] as usize;
+                        let mut prev_log2_sub_cuh = split_struct.log_cuh[0] as usize;
 
-                let mut min_qp = 0i8;
-                let mut max_qp = 0i8;
-                let mut is_dqp_set = false;
-                self.get_min_max_qp(
-                    &mut min_qp,
-                    &mut max_qp,
-                    &mut is_dqp_set,
-                    split_mode,
-                    cuw,
-                    cuh,
-                    qp,
-                );
+                        self.core.cu_data_temp[log2_cuw - 2][log2_cuh - 2]
+                            .init(log2_cuw as u8, log2_cuh as u8);
+                        self.clear_map_scu(x0, y0, cuw, cuh);
 
-                let mut loop_counter = 0;
-                if is_dqp_set {
-                    loop_counter = (max_qp - min_qp).abs();
-                }
-                cost_best_dqp = MAX_COST;
-                for dqp_loop in 0..=loop_counter {
-                    let dqp = min_qp + dqp_loop;
-                    self.core.qp = GET_QP(qp as i8, dqp - qp as i8) as u8;
-                    if is_dqp_set {
-                        self.core.dqp_curr_best[log2_cuw - 2][log2_cuh - 2].cu_qp_delta_code = 2;
-                        self.core.dqp_curr_best[log2_cuw - 2][log2_cuh - 2].cu_qp_delta_is_coded =
-                            false;
-                        self.core.dqp_curr_best[log2_cuw - 2][log2_cuh - 2].curr_qp = self.core.qp;
-                    }
+                        let mut cost_temp = 0.0;
 
-                    cost_temp_dqp = cost_temp;
-                    self.core.cu_data_temp[log2_cuw - 2][log2_cuh - 2]
-                        .init(log2_cuw as u8, log2_cuh as u8);
-                    self.clear_map_scu(x0, y0, cuw, cuh);
+                        if x0 + cuw <= self.w && y0 + cuh <= self.h {
+                            /* consider CU split flag */
+                            self.core.s_temp_run =
+                                self.core.s_curr_before_split[log2_cuw - 2][log2_cuh - 2];
+                            self.core.c_temp_run =
+                                self.core.c_curr_before_split[log2_cuw - 2][log2_cuh - 2];
 
-                    //#if TRACE_ENC_CU_DATA_CHECK
-                    //                  static int counter_in[MAX_CU_LOG2 - MIN_CU_LOG2][MAX_CU_LOG2 - MIN_CU_LOG2] = { 0, };
-                    //                  counter_in[log2_cuw - MIN_CU_LOG2][log2_cuh - MIN_CU_LOG2]++;
-                    // #endif
-
-                    for part_num in 0..split_struct.part_count {
-                        let cur_part_num = part_num;
-                        let log2_sub_cuw = split_struct.log_cuw[cur_part_num] as usize;
-                        let log2_sub_cuh = split_struct.log_cuh[cur_part_num] as usize;
-                        let x_pos = split_struct.x_pos[cur_part_num];
-                        let y_pos = split_struct.y_pos[cur_part_num];
-                        let cur_cuw = split_struct.width[cur_part_num];
-                        let cur_cuh = split_struct.height[cur_part_num];
-
-                        if (x_pos < self.w) && (y_pos < self.h) {
-                            if part_num == 0 {
-                                self.core.s_curr_best[log2_sub_cuw - 2][log2_sub_cuh - 2] =
-                                    self.core.s_curr_best[log2_cuw - 2][log2_cuh - 2];
-                                self.core.c_curr_best[log2_sub_cuw - 2][log2_sub_cuh - 2] =
-                                    self.core.c_curr_best[log2_cuw - 2][log2_cuh - 2];
-
-                                self.core.dqp_curr_best[log2_sub_cuw - 2][log2_sub_cuh - 2] =
-                                    self.core.dqp_curr_best[log2_cuw - 2][log2_cuh - 2];
-                            } else {
-                                self.core.s_curr_best[log2_sub_cuw - 2][log2_sub_cuh - 2] = self
-                                    .core
-                                    .s_next_best[prev_log2_sub_cuw - 2][prev_log2_sub_cuh - 2];
-                                self.core.c_curr_best[log2_sub_cuw - 2][log2_sub_cuh - 2] = self
-                                    .core
-                                    .c_next_best[prev_log2_sub_cuw - 2][prev_log2_sub_cuh - 2];
-
-                                self.core.dqp_curr_best[log2_sub_cuw - 2][log2_sub_cuh - 2] = self
-                                    .core
-                                    .dqp_next_best[prev_log2_sub_cuw - 2][prev_log2_sub_cuh - 2];
-                            }
-                            cost_temp_dqp += self.mode_coding_tree(
-                                x_pos,
-                                y_pos,
-                                split_struct.cup[cur_part_num],
-                                log2_sub_cuw as usize,
-                                log2_sub_cuh as usize,
-                                split_struct.cud[cur_part_num],
-                                true,
-                                split_mode.inc_qt_depth(qt_depth),
-                                self.core.qp,
+                            self.core.s_temp_run.bit_reset();
+                            evc_set_split_mode(
+                                &mut self.core.cu_data_temp[log2_cuw - 2][log2_cuh - 2].split_mode,
+                                split_mode,
+                                cud,
+                                0,
+                                cuw,
+                                cuh,
+                                cuw,
                             );
 
-                            self.core.qp = GET_QP(qp as i8, dqp - qp as i8) as u8;
-
-                            self.core.cu_data_temp[log2_cuw - 2][log2_cuh - 2].copy(
-                                &self.core.cu_data_best[log2_sub_cuw - 2][log2_sub_cuh - 2],
-                                x_pos - split_struct.x_pos[0],
-                                y_pos - split_struct.y_pos[0],
-                                log2_sub_cuw as u8,
-                                log2_sub_cuh as u8,
-                                log2_cuw as u8,
+                            let split_mode_buf = if self.core.s_temp_run.is_bitcount {
+                                &self.core.cu_data_temp[log2_cuw - 2][log2_cuh - 2].split_mode
+                            } else {
+                                &self.map_cu_data[self.core.lcu_num as usize].split_mode
+                            };
+                            evce_eco_split_mode(
+                                &mut self.core.bs_temp,
+                                &mut self.core.s_temp_run,
+                                &mut self.core.c_temp_run,
+                                x0,
+                                y0,
                                 cud,
+                                0,
+                                cuw,
+                                cuh,
+                                cuw,
+                                split_mode_buf,
+                                self.w,
+                                self.h,
+                                self.w_scu,
+                                &self.map_scu,
+                                self.sps_btt_flag,
+                                &self.split_tbl,
+                                mode_cons,
+                                self.sps_cm_init_flag,
                             );
 
-                            self.update_map_scu(x_pos, y_pos, cur_cuw, cur_cuh);
-                            prev_log2_sub_cuw = log2_sub_cuw;
-                            prev_log2_sub_cuh = log2_sub_cuh;
-                        }
-                    }
+                            if suco_allow {
+                                evce_eco_suco_flag(
+                                    &mut self.core.bs_temp,
+                                    &mut self.core.s_temp_run,
+                                    &mut self.core.c_temp_run,
+                                    log2_cuw as u8,
+                                    log2_cuh as u8,
+                                    self.sps_cm_init_flag,
+                                    suco_flag,
+                                );
+                            }
+                            if mode_cons_signal {
+                                evce_eco_mode_constr(
+                                    &mut self.core.bs_temp,
+                                    &mut self.core.s_temp_run,
+                                    &mut self.core.c_temp_run,
+                                    child_mode_cons,
+                                );
+                            }
 
-                    EVC_TRACE_COUNTER(&mut self.core.bs_temp.tracer);
-                    EVC_TRACE(&mut self.core.bs_temp.tracer, "Block [");
-                    EVC_TRACE(&mut self.core.bs_temp.tracer, x0);
-                    EVC_TRACE(&mut self.core.bs_temp.tracer, " , ");
-                    EVC_TRACE(&mut self.core.bs_temp.tracer, y0);
-                    EVC_TRACE(&mut self.core.bs_temp.tracer, " ]x(");
-                    EVC_TRACE(&mut self.core.bs_temp.tracer, cuw);
-                    EVC_TRACE(&mut self.core.bs_temp.tracer, " x");
-                    EVC_TRACE(&mut self.core.bs_temp.tracer, cuh);
-                    EVC_TRACE(&mut self.core.bs_temp.tracer, " ) split_type ");
-                    EVC_TRACE(
-                        &mut self.core.bs_temp.tracer,
-                        if split_mode == SplitMode::NO_SPLIT {
-                            0
-                        } else {
-                            5
-                        },
-                    );
-                    EVC_TRACE(&mut self.core.bs_temp.tracer, "  cost is ");
-                    EVC_TRACE(&mut self.core.bs_temp.tracer, cost_temp as i64);
-                    EVC_TRACE(&mut self.core.bs_temp.tracer, " \n");
+                            bit_cnt = self.core.s_temp_run.get_bit_number();
+                            cost_temp += (self.lambda[0] * bit_cnt as f64);
 
-                    if cost_best_dqp > cost_temp_dqp {
-                        cost_best_dqp = cost_temp_dqp;
-                    }
+                            self.core.s_curr_best[log2_cuw - 2][log2_cuh - 2] =
+                                self.core.s_temp_run;
+                            self.core.c_curr_best[log2_cuw - 2][log2_cuh - 2] =
+                                self.core.c_temp_run;
+                        }
 
-                    if cost_best - 0.0001 > cost_temp_dqp {
-                        /* backup the current best data */
-                        self.core.cu_data_best[log2_cuw - 2][log2_cuh - 2].copy(
-                            &self.core.cu_data_temp[log2_cuw - 2][log2_cuh - 2],
-                            0,
-                            0,
-                            log2_cuw as u8,
-                            log2_cuh as u8,
-                            log2_cuw as u8,
-                            cud,
+                        let mut min_qp = 0i8;
+                        let mut max_qp = 0i8;
+                        let mut is_dqp_set = false;
+                        self.get_min_max_qp(
+                            &mut min_qp,
+                            &mut max_qp,
+                            &mut is_dqp_set,
+                            split_mode,
+                            cuw,
+                            cuh,
+                            qp,
                         );
-                        cost_best = cost_temp_dqp;
-                        best_dqp = self.core.dqp_data[prev_log2_sub_cuw - 2][prev_log2_sub_cuh - 2]
-                            .prev_qp;
-                        dqp_temp_depth =
-                            self.core.dqp_next_best[prev_log2_sub_cuw - 2][prev_log2_sub_cuh - 2];
 
-                        s_temp_depth =
-                            self.core.s_next_best[prev_log2_sub_cuw - 2][prev_log2_sub_cuh - 2];
-                        c_temp_depth =
-                            self.core.c_next_best[prev_log2_sub_cuw - 2][prev_log2_sub_cuh - 2];
+                        let mut loop_counter = 0;
+                        if is_dqp_set {
+                            loop_counter = (max_qp - min_qp).abs();
+                        }
+                        cost_best_dqp = MAX_COST;
+                        for dqp_loop in 0..=loop_counter {
+                            let dqp = min_qp + dqp_loop;
+                            self.core.qp = GET_QP(qp as i8, dqp - qp as i8) as u8;
+                            if is_dqp_set {
+                                self.core.dqp_curr_best[log2_cuw - 2][log2_cuh - 2]
+                                    .cu_qp_delta_code = 2;
+                                self.core.dqp_curr_best[log2_cuw - 2][log2_cuh - 2]
+                                    .cu_qp_delta_is_coded = false;
+                                self.core.dqp_curr_best[log2_cuw - 2][log2_cuh - 2].curr_qp =
+                                    self.core.qp;
+                            }
 
-                        best_split_mode = split_mode;
-                    }
+                            cost_temp_dqp = cost_temp;
+                            self.core.cu_data_temp[log2_cuw - 2][log2_cuh - 2]
+                                .init(log2_cuw as u8, log2_cuh as u8);
+                            self.clear_map_scu(x0, y0, cuw, cuh);
+                            self.core.history_buffer = history_before;
+
+                            //#if TRACE_ENC_CU_DATA_CHECK
+                            //                  static int counter_in[MAX_CU_LOG2 - MIN_CU_LOG2][MAX_CU_LOG2 - MIN_CU_LOG2] = { 0, };
+                            //                  counter_in[log2_cuw - MIN_CU_LOG2][log2_cuh - MIN_CU_LOG2]++;
+                            // #endif
+
+                            let suco_order = evc_split_get_suco_order(suco_flag, split_mode);
+                            for part_num in 0..split_struct.part_count {
+                                let cur_part_num = suco_order[part_num];
+                                let log2_sub_cuw = split_struct.log_cuw[cur_part_num] as usize;
+                                let log2_sub_cuh = split_struct.log_cuh[cur_part_num] as usize;
+                                let x_pos = split_struct.x_pos[cur_part_num];
+                                let y_pos = split_struct.y_pos[cur_part_num];
+                                let cur_cuw = split_struct.width[cur_part_num];
+                                let cur_cuh = split_struct.height[cur_part_num];
+
+                                if (x_pos < self.w) && (y_pos < self.h) {
+                                    if part_num == 0 {
+                                        self.core.s_curr_best[log2_sub_cuw - 2][log2_sub_cuh - 2] =
+                                            self.core.s_curr_best[log2_cuw - 2][log2_cuh - 2];
+                                        self.core.c_curr_best[log2_sub_cuw - 2][log2_sub_cuh - 2] =
+                                            self.core.c_curr_best[log2_cuw - 2][log2_cuh - 2];
+
+                                        self.core.dqp_curr_best[log2_sub_cuw - 2]
+                                            [log2_sub_cuh - 2] =
+                                            self.core.dqp_curr_best[log2_cuw - 2][log2_cuh - 2];
+                                    } else {
+                                        self.core.s_curr_best[log2_sub_cuw - 2][log2_sub_cuh - 2] =
+                                            self.core.s_next_best[prev_log2_sub_cuw - 2]
+                                                [prev_log2_sub_cuh - 2];
+                                        self.core.c_curr_best[log2_sub_cuw - 2][log2_sub_cuh - 2] =
+                                            self.core.c_next_best[prev_log2_sub_cuw - 2]
+                                                [prev_log2_sub_cuh - 2];
+
+                                        self.core.dqp_curr_best[log2_sub_cuw - 2]
+                                            [log2_sub_cuh - 2] = self.core.dqp_next_best
+                                            [prev_log2_sub_cuw - 2][prev_log2_sub_cuh - 2];
+                                    }
+                                    cost_temp_dqp += self.mode_coding_tree(
+                                        x_pos,
+                                        y_pos,
+                                        split_struct.cup[cur_part_num],
+                                        log2_sub_cuw as usize,
+                                        log2_sub_cuh as usize,
+                                        split_struct.cud[cur_part_num],
+                                        true,
+                                        suco_flag,
+                                        split_mode.inc_qt_depth(qt_depth),
+                                        self.core.qp,
+                                        child_mode_cons,
+                                    );
+
+                                    self.core.qp = GET_QP(qp as i8, dqp - qp as i8) as u8;
+
+                                    self.core.cu_data_temp[log2_cuw - 2][log2_cuh - 2].copy(
+                                        &self.core.cu_data_best[log2_sub_cuw - 2][log2_sub_cuh - 2],
+                                        x_pos - split_struct.x_pos[0],
+                                        y_pos - split_struct.y_pos[0],
+                                        log2_sub_cuw as u8,
+                                        log2_sub_cuh as u8,
+                                        log2_cuw as u8,
+                                        cud,
+                                        self.chroma_format_idc,
+                                    );
+
+                                    self.update_map_scu(x_pos, y_pos, cur_cuw, cur_cuh);
+                                    prev_log2_sub_cuw = log2_sub_cuw;
+                                    prev_log2_sub_cuh = log2_sub_cuh;
+                                }
+                            }
 
-                    cost_temp = cost_best_dqp;
+                            EVC_TRACE_COUNTER(&mut self.core.bs_temp.tracer);
+                            EVC_TRACE(&mut self.core.bs_temp.tracer, "Block [");
+                            EVC_TRACE(&mut self.core.bs_temp.tracer, x0);
+                            EVC_TRACE(&mut self.core.bs_temp.tracer, " , ");
+                            EVC_TRACE(&mut self.core.bs_temp.tracer, y0);
+                            EVC_TRACE(&mut self.core.bs_temp.tracer, " ]x(");
+                            EVC_TRACE(&mut self.core.bs_temp.tracer, cuw);
+                            EVC_TRACE(&mut self.core.bs_temp.tracer, " x");
+                            EVC_TRACE(&mut self.core.bs_temp.tracer, cuh);
+                            EVC_TRACE(&mut self.core.bs_temp.tracer, " ) split_type ");
+                            EVC_TRACE(&mut self.core.bs_temp.tracer, split_mode as u32);
+                            EVC_TRACE(&mut self.core.bs_temp.tracer, "  cost is ");
+                            EVC_TRACE(&mut self.core.bs_temp.tracer, cost_temp as i64);
+                            EVC_TRACE(&mut self.core.bs_temp.tracer, " \n");
+
+                            if cost_best_dqp > cost_temp_dqp {
+                                cost_best_dqp = cost_temp_dqp;
+                            }
 
-                    if is_dqp_set {
-                        self.core.cu_qp_delta_code_mode = 0;
-                    }
+                            if cost_best - 0.0001 > cost_temp_dqp {
+                                /* backup the current best data */
+                                self.core.cu_data_best[log2_cuw - 2][log2_cuh - 2].copy(
+                                    &self.core.cu_data_temp[log2_cuw - 2][log2_cuh - 2],
+                                    0,
+                                    0,
+                                    log2_cuw as u8,
+                                    log2_cuh as u8,
+                                    log2_cuw as u8,
+                                    cud,
+                                    self.chroma_format_idc,
+                                );
+                                cost_best = cost_temp_dqp;
+                                best_dqp = self.core.dqp_data[prev_log2_sub_cuw - 2]
+                                    [prev_log2_sub_cuh - 2]
+                                    .prev_qp;
+                                dqp_temp_depth = self.core.dqp_next_best[prev_log2_sub_cuw - 2]
+                                    [prev_log2_sub_cuh - 2];
+
+                                s_temp_depth = self.core.s_next_best[prev_log2_sub_cuw - 2]
+                                    [prev_log2_sub_cuh - 2];
+                                c_temp_depth = self.core.c_next_best[prev_log2_sub_cuw - 2]
+                                    [prev_log2_sub_cuh - 2];
+
+                                best_split_mode = split_mode;
+                                best_suco_flag = suco_flag;
+                                history_best = self.core.history_buffer;
+                            }
+
+                            cost_temp = cost_best_dqp;
+
+                            if is_dqp_set {
+                                self.core.cu_qp_delta_code_mode = 0;
+                            }
 
-                    if split_mode != SplitMode::NO_SPLIT && cost_temp < best_split_cost {
-                        best_split_cost = cost_temp;
+                            if split_mode != SplitMode::NO_SPLIT && cost_temp < best_split_cost {
+                                best_split_cost = cost_temp;
+                            }
+                            let split_cost = &mut self.core.bef_data[log2_cuw - 2][log2_cuh - 2]
+                                [cup as usize]
+                                .split_cost[split_mode as usize];
+                            if suco_idx == 0 || cost_temp < *split_cost {
+                                *split_cost = cost_temp;
+                            }
+                        }
                     }
                 }
             }
@@ -1004,10 +1303,22 @@ impl EvceCtx {
                 y0 as usize,
                 cuw as usize,
                 cuh as usize,
-                &mut pic.borrow().frame.borrow_mut().planes,
+                &mut pic.read().unwrap().frame.write().unwrap().planes,
+                self.chroma_format_idc,
             );
         }
 
+        /* save the split decision for the next visit of the same block */
+        let bef = &mut self.core.bef_data[log2_cuw - 2][log2_cuh - 2][cup as usize];
+        if !bef.visit {
+            if best_split_mode == SplitMode::NO_SPLIT {
+                bef.nosplit += 1;
+            } else {
+                bef.split += 1;
+            }
+            bef.visit = true;
+        }
+
         /* restore best data */
         evc_set_split_mode(
             &mut self.core.cu_data_best[log2_cuw - 2][log2_cuh - 2].split_mode,
@@ -1018,11 +1329,21 @@ impl EvceCtx {
             cuh,
             cuw,
         );
+        evc_set_suco_flag(
+            &mut self.core.cu_data_best[log2_cuw - 2][log2_cuh - 2].suco_flag,
+            best_suco_flag,
+            cud,
+            0,
+            cuw,
+            cuh,
+            cuw,
+        );
 
         self.core.s_next_best[log2_cuw - 2][log2_cuh - 2] = s_temp_depth;
         self.core.c_next_best[log2_cuw - 2][log2_cuh - 2] = c_temp_depth;
 
         self.core.dqp_next_best[log2_cuw - 2][log2_cuh - 2] = dqp_temp_depth;
+        self.core.history_buffer = history_best;
 
         assert_ne!(cost_best, MAX_COST);
 
@@ -1183,6 +1504,9 @@ impl EvceCtx {
 
     fn check_run_split(
         &mut self,
+        cup: u16,
+        log2_cuw: usize,
+        log2_cuh: usize,
         next_split: bool,
         do_curr: bool,
         do_split: bool,
@@ -1200,8 +1524,32 @@ impl EvceCtx {
             return;
         }
 
-        for i in 0..MAX_SPLIT_NUM {
-            run_list[i] = true;
+        let bef = &self.core.bef_data[log2_cuw - 2][log2_cuh - 2][cup as usize];
+        if self.sps_btt_flag && bef.visit {
+            if bef.nosplit < 1 && bef.split >= 1 {
+                //the block was split before: only try the split modes close to the best one
+                let mut min_cost = MAX_COST;
+                for i in 1..MAX_SPLIT_NUM {
+                    if split_allow[i] && bef.split_cost[i] < min_cost {
+                        min_cost = bef.split_cost[i];
+                    }
+                }
+
+                if min_cost == MAX_COST {
+                    run_list[0] = true;
+                } else {
+                    for i in 1..MAX_SPLIT_NUM {
+                        run_list[i] = bef.split_cost[i] <= min_cost * 1.01;
+                    }
+                }
+            } else {
+                //the block was not split before: only try NO_SPLIT
+                run_list[0] = true;
+            }
+        } else {
+            for i in 0..MAX_SPLIT_NUM {
+                run_list[i] = true;
+            }
         }
 
         run_list[0] = run_list[0] && do_curr;
@@ -1234,11 +1582,11 @@ impl EvceCtx {
         qp: u8,
     ) {
         *is_dqp_set = false;
-        if !self.pps.cu_qp_delta_enabled_flag {
+        if !self.pps_cu_qp_delta_enabled_flag {
             *min_qp = self.sh.qp as i8; // Clip?
             *max_qp = self.sh.qp as i8;
         } else {
-            if !self.sps.dquant_flag {
+            if !self.sps_dquant_flag {
                 if split_mode != SplitMode::NO_SPLIT {
                     *min_qp = qp as i8; // Clip?
                     *max_qp = qp as i8;
@@ -1251,7 +1599,7 @@ impl EvceCtx {
                 *max_qp = qp as i8;
                 if split_mode == SplitMode::NO_SPLIT
                     && CONV_LOG2(cuw as usize) + CONV_LOG2(cuh as usize)
-                        >= self.pps.cu_qp_delta_area
+                        >= self.pps_cu_qp_delta_area
                     && self.core.cu_qp_delta_code_mode != 2
                 {
                     self.core.cu_qp_delta_code_mode = 1;
@@ -1265,9 +1613,9 @@ impl EvceCtx {
                         *is_dqp_set = false;
                     }
                 } else if (CONV_LOG2(cuw as usize) + CONV_LOG2(cuh as usize)
-                    == self.pps.cu_qp_delta_area + 1)
+                    == self.pps_cu_qp_delta_area + 1)
                     || (CONV_LOG2(cuh as usize) + CONV_LOG2(cuw as usize)
-                        == self.pps.cu_qp_delta_area
+                        == self.pps_cu_qp_delta_area
                         && self.core.cu_qp_delta_code_mode != 2)
                 {
                     self.core.cu_qp_delta_code_mode = 2;
@@ -1302,8 +1650,14 @@ impl EvceCtx {
         let mut core_idx = 0usize;
         let mut ctx_idx = ((y >> MIN_CU_LOG2) * self.w_scu + (x >> MIN_CU_LOG2)) as usize;
 
-        if let (Some(map_refi), Some(map_mv)) = (&mut self.map_refi, &mut self.map_mv) {
-            let (mut map_refi, mut map_mv) = (map_refi.borrow_mut(), map_mv.borrow_mut());
+        if let (Some(map_refi), Some(map_mv), Some(map_unrefined_mv)) =
+            (&self.map_refi, &self.map_mv, &self.map_unrefined_mv)
+        {
+            let (mut map_refi, mut map_mv, mut map_unrefined_mv) = (
+                map_refi.write().unwrap(),
+                map_mv.write().unwrap(),
+                map_unrefined_mv.write().unwrap(),
+            );
             let mut map_ipm = &mut self.map_ipm;
 
             for _ in 0..h {
@@ -1314,6 +1668,7 @@ impl EvceCtx {
                         map_mv[ctx_idx + j][REFP_0][MV_Y] = 0;
                         map_mv[ctx_idx + j][REFP_1][MV_X] = 0;
                         map_mv[ctx_idx + j][REFP_1][MV_Y] = 0;
+                        map_unrefined_mv[ctx_idx + j] = [[0; MV_D]; REFP_NUM];
                     } else {
                         map_refi[ctx_idx + j][REFP_0] = cu_data.refi[core_idx + j][REFP_0];
                         map_refi[ctx_idx + j][REFP_1] = cu_data.refi[core_idx + j][REFP_1];
@@ -1321,6 +1676,7 @@ impl EvceCtx {
                         map_mv[ctx_idx + j][REFP_0][MV_Y] = cu_data.mv[core_idx + j][REFP_0][MV_Y];
                         map_mv[ctx_idx + j][REFP_1][MV_X] = cu_data.mv[core_idx + j][REFP_1][MV_X];
                         map_mv[ctx_idx + j][REFP_1][MV_Y] = cu_data.mv[core_idx + j][REFP_1][MV_Y];
+                        map_unrefined_mv[ctx_idx + j] = cu_data.unrefined_mv[core_idx + j];
                     }
                 }
                 ctx_idx += self.w_scu as usize;
@@ -1354,6 +1710,10 @@ impl EvceCtx {
         let mut map_depth = &mut self.map_depth[pos..];
         let mut src_depth = &self.core.cu_data_best[log2_src_cuw - 2][log2_src_cuh - 2].depth[..];
 
+        let mut map_affine = &mut self.map_affine[pos..];
+        let mut src_map_affine =
+            &self.core.cu_data_best[log2_src_cuw - 2][log2_src_cuh - 2].map_affine[..];
+
         let w = if x + src_cuw > self.w {
             (self.w - x) >> MIN_CU_LOG2
         } else {
@@ -1366,8 +1726,14 @@ impl EvceCtx {
             (src_cuh >> MIN_CU_LOG2)
         } as usize;
 
-        if let (Some(map_refi), Some(map_mv)) = (&mut self.map_refi, &mut self.map_mv) {
-            let (mut map_refi, mut map_mv) = (map_refi.borrow_mut(), map_mv.borrow_mut());
+        if let (Some(map_refi), Some(map_mv), Some(map_unrefined_mv)) =
+            (&self.map_refi, &self.map_mv, &self.map_unrefined_mv)
+        {
+            let (mut map_refi, mut map_mv, mut map_unrefined_mv) = (
+                map_refi.write().unwrap(),
+                map_mv.write().unwrap(),
+                map_unrefined_mv.write().unwrap(),
+            );
 
             let mut map_refi = &mut map_refi[pos..];
             let mut src_map_refi =
@@ -1376,17 +1742,26 @@ impl EvceCtx {
             let mut map_mv = &mut map_mv[pos..];
             let mut src_map_mv = &self.core.cu_data_best[log2_src_cuw - 2][log2_src_cuh - 2].mv[..];
 
+            let mut map_unrefined_mv = &mut map_unrefined_mv[pos..];
+            let mut src_map_unrefined_mv =
+                &self.core.cu_data_best[log2_src_cuw - 2][log2_src_cuh - 2].unrefined_mv[..];
+
             for i in 0..h {
                 map_scu[..w].copy_from_slice(&src_map_scu[..w]);
                 map_ipm[..w].copy_from_slice(&src_map_ipm[..w]);
                 map_depth[..w].copy_from_slice(&src_depth[..w]);
+                map_affine[..w].copy_from_slice(&src_map_affine[..w]);
                 map_refi[..w].copy_from_slice(&src_map_refi[..w]);
                 map_mv[..w].copy_from_slice(&src_map_mv[..w]);
+                map_unrefined_mv[..w].copy_from_slice(&src_map_unrefined_mv[..w]);
 
                 if i + 1 < h {
                     map_depth = &mut map_depth[self.w_scu as usize..];
                     src_depth = &src_depth[(src_cuw >> MIN_CU_LOG2) as usize..];
 
+                    map_affine = &mut map_affine[self.w_scu as usize..];
+                    src_map_affine = &src_map_affine[(src_cuw >> MIN_CU_LOG2) as usize..];
+
                     map_scu = &mut map_scu[self.w_scu as usize..];
                     src_map_scu = &src_map_scu[(src_cuw >> MIN_CU_LOG2) as usize..];
 
@@ -1396,6 +1771,10 @@ impl EvceCtx {
                     map_mv = &mut map_mv[self.w_scu as usize..];
                     src_map_mv = &src_map_mv[(src_cuw >> MIN_CU_LOG2) as usize..];
 
+                    map_unrefined_mv = &mut map_unrefined_mv[self.w_scu as usize..];
+                    src_map_unrefined_mv =
+                        &src_map_unrefined_mv[(src_cuw >> MIN_CU_LOG2) as usize..];
+
                     map_refi = &mut map_refi[self.w_scu as usize..];
                     src_map_refi = &src_map_refi[(src_cuw >> MIN_CU_LOG2) as usize..];
                 }
@@ -1432,10 +1811,20 @@ impl EvceCtx {
         log2_cuw: usize,
         log2_cuh: usize,
         cud: u16,
+        mode_cons: ModeCons,
     ) -> f64 {
         assert!((log2_cuw as i8 - log2_cuh as i8).abs() <= 2);
         self.mode_cu_init(x, y, log2_cuw as u8, log2_cuh as u8, cud);
 
+        /* 4x4 CUs of the main profile can only be intra predicted */
+        self.core.mode_cons = if self.slice_type == SliceType::EVC_ST_I
+            || (self.sps_admvp_flag && log2_cuw == MIN_CU_LOG2 && log2_cuh == MIN_CU_LOG2)
+        {
+            ModeCons::eOnlyIntra
+        } else {
+            mode_cons
+        };
+
         self.core.avail_lr = evc_check_nev_avail(
             self.core.x_scu,
             self.core.y_scu,
@@ -1443,13 +1832,22 @@ impl EvceCtx {
             self.w_scu,
             &self.map_scu,
         );
+        self.core.ctx_flags = evc_get_ctx_some_flags(
+            self.core.x_scu,
+            self.core.y_scu,
+            1 << log2_cuw,
+            1 << log2_cuh,
+            self.w_scu,
+            &self.map_scu,
+            self.sps_cm_init_flag,
+        );
 
         let mut cost = MAX_COST;
         let mut cost_best = MAX_COST;
         self.core.cost_best = MAX_COST;
 
         /* inter *************************************************************/
-        if self.slice_type != SliceType::EVC_ST_I {
+        if self.core.mode_cons != ModeCons::eOnlyIntra {
             self.core.avail_cu = evc_get_avail_inter(
                 self.core.x_scu as usize,
                 self.core.y_scu as usize,
@@ -1470,7 +1868,7 @@ impl EvceCtx {
             if cost < cost_best {
                 cost_best = cost;
 
-                if self.pps.cu_qp_delta_enabled_flag {
+                if self.pps_cu_qp_delta_enabled_flag {
                     self.evce_set_qp(self.core.dqp_next_best[log2_cuw - 2][log2_cuh - 2].prev_qp);
                 }
 
@@ -1484,30 +1882,34 @@ impl EvceCtx {
                     &self.pinter.rec[self.mode.inter_best_idx],
                     self.slice_num,
                     &self.core.ipm,
+                    self.core.ats_intra_cu,
+                    self.core.ats_mode,
                     &self.mode,
                     self.core.qp,
                     self.core.qp_y,
                     self.core.qp_u,
                     self.core.qp_v,
                     &self.core.nnz,
+                    self.chroma_format_idc,
                 );
             }
         }
 
         /* intra *************************************************************/
-        if self.slice_type == SliceType::EVC_ST_I
-            || self.core.nnz[Y_C] != 0
-            || self.core.nnz[U_C] != 0
-            || self.core.nnz[V_C] != 0
-            || cost_best == MAX_COST
+        if self.core.mode_cons != ModeCons::eOnlyInter
+            && (self.slice_type == SliceType::EVC_ST_I
+                || self.core.nnz[Y_C] != 0
+                || self.core.nnz[U_C] != 0
+                || self.core.nnz[V_C] != 0
+                || cost_best == MAX_COST)
         {
             self.core.cost_best = cost_best;
             self.core.dist_cu_best = i32::MAX;
 
             if self.core.cost_best != MAX_COST {
                 if let Some(pic) = &self.pintra.pic_o {
-                    let frame = &pic.borrow().frame;
-                    let planes = &frame.borrow().planes;
+                    let frame = &pic.read().unwrap().frame;
+                    let planes = &frame.read().unwrap().planes;
                     self.core.inter_satd = evce_satd_16b(
                         x as usize,
                         y as usize,
@@ -1520,7 +1922,7 @@ impl EvceCtx {
             } else {
                 self.core.inter_satd = u32::MAX;
             }
-            if self.pps.cu_qp_delta_enabled_flag {
+            if self.pps_cu_qp_delta_enabled_flag {
                 self.evce_set_qp(self.core.dqp_curr_best[log2_cuw - 2][log2_cuh - 2].curr_qp as u8);
             }
 
@@ -1562,12 +1964,15 @@ impl EvceCtx {
                     &self.pintra.rec,
                     self.slice_num,
                     &self.core.ipm,
+                    self.core.ats_intra_cu,
+                    self.core.ats_mode,
                     &self.mode,
                     self.core.qp,
                     self.core.qp_y,
                     self.core.qp_u,
                     self.core.qp_v,
                     &self.core.nnz,
+                    self.chroma_format_idc,
                 );
             }
         }
@@ -1647,16 +2052,43 @@ impl EvceCtx {
                 self.core.rdoq_est.last[ctx][bin] = biari_no_bits(bin, sbac_ctx.last[ctx]);
             }
         }
+
+        if self.sps_adcc_flag {
+            for set in 0..NUM_SIG_COEFF_SET {
+                for ctx in 0..NUM_CTX_SIG_COEFF_FLAG {
+                    for bin in 0..2 {
+                        self.core.rdoq_est.sig_coeff[set][ctx][bin] =
+                            biari_no_bits(bin, sbac_ctx.sig_coeff_flag[set][ctx]);
+                    }
+                }
+            }
+
+            for ctx in 0..NUM_CTX_GTX {
+                for bin in 0..2 {
+                    self.core.rdoq_est.gtx[ctx][bin] =
+                        biari_no_bits(bin, sbac_ctx.coeff_abs_level_greaterAB_flag[ctx]);
+                }
+            }
+
+            for ctx in 0..NUM_CTX_LAST_SIG_COEFF {
+                for bin in 0..2 {
+                    self.core.rdoq_est.last_sig_coeff_x[ctx][bin] =
+                        biari_no_bits(bin, sbac_ctx.last_sig_coeff_x_prefix[ctx]);
+                    self.core.rdoq_est.last_sig_coeff_y[ctx][bin] =
+                        biari_no_bits(bin, sbac_ctx.last_sig_coeff_y_prefix[ctx]);
+                }
+            }
+        }
     }
 
-    pub(crate) fn evce_rdo_bit_cnt_cu_intra_luma(&mut self, slice_type: SliceType) {
+    pub(crate) fn evce_rdo_bit_cnt_cu_intra_luma(&mut self, _: SliceType) {
         let log2_cuw = self.core.log2_cuw;
         let log2_cuh = self.core.log2_cuh;
 
-        if slice_type != SliceType::EVC_ST_I {
+        if self.core.mode_cons == ModeCons::eAll {
             self.core.s_temp_run.encode_bin(
                 &mut self.core.bs_temp,
-                &mut self.core.c_temp_run.skip_flag[0],
+                &mut self.core.c_temp_run.skip_flag[self.core.ctx_flags[CNID_SKIP_FLAG]],
                 0,
             ); /* skip_flag */
             evce_eco_pred_mode(
@@ -1664,18 +2096,29 @@ impl EvceCtx {
                 &mut self.core.s_temp_run,
                 &mut self.core.c_temp_run,
                 PredMode::MODE_INTRA,
+                self.core.ctx_flags[CNID_PRED_MODE],
             );
         }
 
-        evce_eco_intra_dir_b(
-            &mut self.core.bs_temp,
-            &mut self.core.s_temp_run,
-            &mut self.core.c_temp_run,
-            self.core.ipm[0] as u8,
-            self.core.mpm_b_list,
-        );
+        if self.sps_eipd_flag {
+            evce_eco_intra_dir(
+                &mut self.core.bs_temp,
+                &mut self.core.s_temp_run,
+                &mut self.core.c_temp_run,
+                self.core.ipm[0].0 as u8,
+                &self.core.mpm_list,
+            );
+        } else {
+            evce_eco_intra_dir_b(
+                &mut self.core.bs_temp,
+                &mut self.core.s_temp_run,
+                &mut self.core.c_temp_run,
+                self.core.ipm[0].0 as u8,
+                self.core.mpm_b_list,
+            );
+        }
 
-        if self.pps.cu_qp_delta_enabled_flag {
+        if self.pps_cu_qp_delta_enabled_flag {
             self.core.cu_qp_delta_code = self.core.dqp_temp_run.cu_qp_delta_code;
             self.core.cu_qp_delta_is_coded = self.core.dqp_temp_run.cu_qp_delta_is_coded;
             self.core.qp_prev_eco = self.core.dqp_temp_run.prev_qp;
@@ -1694,14 +2137,21 @@ impl EvceCtx {
             TQC_RUN::RUN_L as u8,
             false,
             self.core.qp,
-            self.sps.dquant_flag,
-            self.pps.cu_qp_delta_enabled_flag,
+            self.sps_dquant_flag,
+            self.pps_cu_qp_delta_enabled_flag,
             self.core.cu_qp_delta_code,
             &mut self.core.cu_qp_delta_is_coded,
             &mut self.core.qp_prev_eco,
+            self.sps_ats_flag,
+            self.sps_cm_init_flag,
+            self.sps_adcc_flag,
+            self.chroma_format_idc,
+            self.core.ats_intra_cu,
+            self.core.ats_mode,
+            self.core.ats_inter_info,
         );
 
-        if self.pps.cu_qp_delta_enabled_flag {
+        if self.pps_cu_qp_delta_enabled_flag {
             self.core.dqp_temp_run.cu_qp_delta_code = self.core.cu_qp_delta_code;
             self.core.dqp_temp_run.cu_qp_delta_is_coded = self.core.cu_qp_delta_is_coded;
             self.core.dqp_temp_run.prev_qp = self.core.qp_prev_eco;
@@ -1713,6 +2163,16 @@ impl EvceCtx {
         let log2_cuw = self.core.log2_cuw;
         let log2_cuh = self.core.log2_cuh;
 
+        if self.sps_eipd_flag {
+            evce_eco_intra_dir_c(
+                &mut self.core.bs_temp,
+                &mut self.core.s_temp_run,
+                &mut self.core.c_temp_run,
+                self.core.ipm[1].0 as u8,
+                self.core.ipm[0],
+            );
+        }
+
         evce_eco_coef(
             &mut self.core.bs_temp,
             &mut self.core.s_temp_run,
@@ -1726,22 +2186,29 @@ impl EvceCtx {
             TQC_RUN::RUN_CB as u8 | TQC_RUN::RUN_CR as u8,
             false,
             0,
-            self.sps.dquant_flag,
-            self.pps.cu_qp_delta_enabled_flag,
+            self.sps_dquant_flag,
+            self.pps_cu_qp_delta_enabled_flag,
             self.core.cu_qp_delta_code,
             &mut self.core.cu_qp_delta_is_coded,
             &mut self.core.qp_prev_eco,
+            self.sps_ats_flag,
+            self.sps_cm_init_flag,
+            self.sps_adcc_flag,
+            self.chroma_format_idc,
+            self.core.ats_intra_cu,
+            self.core.ats_mode,
+            self.core.ats_inter_info,
         );
     }
 
-    pub(crate) fn evce_rdo_bit_cnt_cu_intra(&mut self, slice_type: SliceType) {
+    pub(crate) fn evce_rdo_bit_cnt_cu_intra(&mut self, _: SliceType) {
         let log2_cuw = self.core.log2_cuw;
         let log2_cuh = self.core.log2_cuh;
 
-        if slice_type != SliceType::EVC_ST_I {
+        if self.core.mode_cons == ModeCons::eAll {
             self.core.s_temp_run.encode_bin(
                 &mut self.core.bs_temp,
-                &mut self.core.c_temp_run.skip_flag[0],
+                &mut self.core.c_temp_run.skip_flag[self.core.ctx_flags[CNID_SKIP_FLAG]],
                 0,
             ); /* skip_flag */
             evce_eco_pred_mode(
@@ -1749,18 +2216,38 @@ impl EvceCtx {
                 &mut self.core.s_temp_run,
                 &mut self.core.c_temp_run,
                 PredMode::MODE_INTRA,
+                self.core.ctx_flags[CNID_PRED_MODE],
             );
         }
 
-        evce_eco_intra_dir_b(
-            &mut self.core.bs_temp,
-            &mut self.core.s_temp_run,
-            &mut self.core.c_temp_run,
-            self.core.ipm[0] as u8,
-            self.core.mpm_b_list,
-        );
+        if self.sps_eipd_flag {
+            evce_eco_intra_dir(
+                &mut self.core.bs_temp,
+                &mut self.core.s_temp_run,
+                &mut self.core.c_temp_run,
+                self.core.ipm[0].0 as u8,
+                &self.core.mpm_list,
+            );
+            if self.chroma_format_idc != 0 {
+                evce_eco_intra_dir_c(
+                    &mut self.core.bs_temp,
+                    &mut self.core.s_temp_run,
+                    &mut self.core.c_temp_run,
+                    self.core.ipm[1].0 as u8,
+                    self.core.ipm[0],
+                );
+            }
+        } else {
+            evce_eco_intra_dir_b(
+                &mut self.core.bs_temp,
+                &mut self.core.s_temp_run,
+                &mut self.core.c_temp_run,
+                self.core.ipm[0].0 as u8,
+                self.core.mpm_b_list,
+            );
+        }
 
-        if self.pps.cu_qp_delta_enabled_flag {
+        if self.pps_cu_qp_delta_enabled_flag {
             self.core.cu_qp_delta_code = self.core.dqp_temp_run.cu_qp_delta_code;
             self.core.cu_qp_delta_is_coded = self.core.dqp_temp_run.cu_qp_delta_is_coded;
             self.core.qp_prev_eco = self.core.dqp_temp_run.prev_qp;
@@ -1777,16 +2264,23 @@ impl EvceCtx {
             &self.core.nnz,
             false,
             TQC_RUN::RUN_L as u8 | TQC_RUN::RUN_CB as u8 | TQC_RUN::RUN_CR as u8,
-            self.pps.cu_qp_delta_enabled_flag,
+            self.pps_cu_qp_delta_enabled_flag,
             self.core.qp,
-            self.sps.dquant_flag,
-            self.pps.cu_qp_delta_enabled_flag,
+            self.sps_dquant_flag,
+            self.pps_cu_qp_delta_enabled_flag,
             self.core.cu_qp_delta_code,
             &mut self.core.cu_qp_delta_is_coded,
             &mut self.core.qp_prev_eco,
+            self.sps_ats_flag,
+            self.sps_cm_init_flag,
+            self.sps_adcc_flag,
+            self.chroma_format_idc,
+            self.core.ats_intra_cu,
+            self.core.ats_mode,
+            self.core.ats_inter_info,
         );
 
-        if self.pps.cu_qp_delta_enabled_flag {
+        if self.pps_cu_qp_delta_enabled_flag {
             self.core.dqp_temp_run.cu_qp_delta_code = self.core.cu_qp_delta_code;
             self.core.dqp_temp_run.cu_qp_delta_is_coded = self.core.cu_qp_delta_is_coded;
             self.core.dqp_temp_run.prev_qp = self.core.qp_prev_eco;
@@ -1808,83 +2302,213 @@ impl EvceCtx {
         if slice_type != SliceType::EVC_ST_I {
             self.core.s_temp_run.encode_bin(
                 &mut self.core.bs_temp,
-                &mut self.core.c_temp_run.skip_flag[0],
+                &mut self.core.c_temp_run.skip_flag[self.core.ctx_flags[CNID_SKIP_FLAG]],
                 0,
             ); /* skip_flag */
 
-            evce_eco_pred_mode(
-                &mut self.core.bs_temp,
-                &mut self.core.s_temp_run,
-                &mut self.core.c_temp_run,
-                PredMode::MODE_INTER,
-            );
+            if self.core.mode_cons == ModeCons::eAll {
+                evce_eco_pred_mode(
+                    &mut self.core.bs_temp,
+                    &mut self.core.s_temp_run,
+                    &mut self.core.c_temp_run,
+                    PredMode::MODE_INTER,
+                    self.core.ctx_flags[CNID_PRED_MODE],
+                );
+            }
 
-            let dir_flag = pidx == InterPredDir::PRED_DIR as usize;
+            let affine_flag = self.pinter.affine_flag[pidx];
+            let mvr_idx = self.pinter.mvr_idx[pidx];
+            let mmvd_flag = self.pinter.mmvd_flag[pidx];
+            let dir_flag =
+                pidx == InterPredDir::PRED_DIR as usize || pidx == InterPredDir::AFF_DIR as usize;
 
-            evce_eco_direct_mode_flag(
-                &mut self.core.bs_temp,
-                &mut self.core.s_temp_run,
-                &mut self.core.c_temp_run,
-                dir_flag as u32,
-            );
+            if self.sps_admvp_flag {
+                if self.sps_amvr_flag {
+                    evce_eco_mvr_idx(
+                        &mut self.core.bs_temp,
+                        &mut self.core.s_temp_run,
+                        &mut self.core.c_temp_run,
+                        mvr_idx as u32,
+                    );
+                }
+                if mvr_idx == 0 {
+                    evce_eco_merge_mode_flag(
+                        &mut self.core.bs_temp,
+                        &mut self.core.s_temp_run,
+                        &mut self.core.c_temp_run,
+                        dir_flag as u32,
+                    );
+                }
+                if dir_flag {
+                    if self.sps_mmvd_flag {
+                        evce_eco_mmvd_flag(
+                            &mut self.core.bs_temp,
+                            &mut self.core.s_temp_run,
+                            &mut self.core.c_temp_run,
+                            mmvd_flag as u32,
+                        );
+                    }
+                    if !mmvd_flag
+                        && self.sps_affine_flag
+                        && self.core.cuw >= 8
+                        && self.core.cuh >= 8
+                    {
+                        evce_eco_affine_flag(
+                            &mut self.core.bs_temp,
+                            &mut self.core.s_temp_run,
+                            &mut self.core.c_temp_run,
+                            (affine_flag != 0) as u32,
+                            self.core.ctx_flags[CNID_AFFN_FLAG],
+                        );
+                    }
+                    if mmvd_flag {
+                        evce_eco_mmvd_idx(
+                            &mut self.core.bs_temp,
+                            &mut self.core.s_temp_run,
+                            &mut self.core.c_temp_run,
+                            self.pinter.mmvd_idx[pidx],
+                            evc_mmvd_num_groups(
+                                self.sh.mmvd_group_enable_flag,
+                                self.core.cuw as usize,
+                                self.core.cuh as usize,
+                            ),
+                        );
+                    } else if affine_flag != 0 {
+                        evce_eco_affine_mrg_idx(
+                            &mut self.core.bs_temp,
+                            &mut self.core.s_temp_run,
+                            &mut self.core.c_temp_run,
+                            mvp_idx[REFP_0] as u32,
+                        );
+                    } else {
+                        evce_eco_merge_idx(
+                            &mut self.core.bs_temp,
+                            &mut self.core.s_temp_run,
+                            &mut self.core.c_temp_run,
+                            mvp_idx[REFP_0] as u32,
+                        );
+                    }
+                }
+            } else {
+                evce_eco_direct_mode_flag(
+                    &mut self.core.bs_temp,
+                    &mut self.core.s_temp_run,
+                    &mut self.core.c_temp_run,
+                    dir_flag as u32,
+                );
+            }
 
-            if pidx != InterPredDir::PRED_DIR as usize {
+            if !dir_flag {
                 evce_eco_inter_pred_idc(
                     &mut self.core.bs_temp,
                     &mut self.core.s_temp_run,
                     &mut self.core.c_temp_run,
                     &self.pinter.refi[pidx],
                     slice_type,
+                    self.core.cuw,
+                    self.core.cuh,
+                    self.sps_admvp_flag,
                 );
 
+                if mvr_idx == 0
+                    && self.sps_affine_flag
+                    && self.core.cuw >= 16
+                    && self.core.cuh >= 16
+                {
+                    evce_eco_affine_flag(
+                        &mut self.core.bs_temp,
+                        &mut self.core.s_temp_run,
+                        &mut self.core.c_temp_run,
+                        (affine_flag != 0) as u32,
+                        self.core.ctx_flags[CNID_AFFN_FLAG],
+                    );
+                }
+
                 let refi0 = self.pinter.refi[pidx][REFP_0];
                 let refi1 = self.pinter.refi[pidx][REFP_1];
-                if slice_type.IS_INTER_SLICE() && REFI_IS_VALID(refi0) {
-                    evce_eco_refi(
+                if affine_flag != 0 {
+                    evce_eco_affine_mode(
                         &mut self.core.bs_temp,
                         &mut self.core.s_temp_run,
                         &mut self.core.c_temp_run,
-                        self.rpm.num_refp[REFP_0],
-                        refi0,
+                        affine_flag as u32 - 1,
                     );
-                    evce_eco_mvp_idx(
+                    for lidx in 0..REFP_NUM {
+                        let refi = self.pinter.refi[pidx][lidx];
+                        if REFI_IS_VALID(refi) {
+                            evce_eco_affine_mvd(
+                                &mut self.core.bs_temp,
+                                &mut self.core.s_temp_run,
+                                &mut self.core.c_temp_run,
+                                lidx,
+                                self.num_refp[lidx],
+                                refi,
+                                mvp_idx[lidx],
+                                &self.pinter.affine_mvd[pidx][lidx],
+                                affine_flag as usize + 1,
+                            );
+                        }
+                    }
+                } else if self.sps_admvp_flag && REFI_IS_VALID(refi0) && REFI_IS_VALID(refi1) {
+                    evce_eco_bi_idx(
                         &mut self.core.bs_temp,
                         &mut self.core.s_temp_run,
                         &mut self.core.c_temp_run,
-                        mvp_idx[REFP_0] as u32,
+                        BI_NORMAL,
                     );
+                }
+                if affine_flag == 0 && slice_type.IS_INTER_SLICE() && REFI_IS_VALID(refi0) {
+                    evce_eco_refi(
+                        &mut self.core.bs_temp,
+                        &mut self.core.s_temp_run,
+                        &mut self.core.c_temp_run,
+                        self.num_refp[REFP_0],
+                        refi0,
+                    );
+                    if !self.sps_admvp_flag {
+                        evce_eco_mvp_idx(
+                            &mut self.core.bs_temp,
+                            &mut self.core.s_temp_run,
+                            &mut self.core.c_temp_run,
+                            mvp_idx[REFP_0] as u32,
+                        );
+                    }
                     evce_eco_mvd(
                         &mut self.core.bs_temp,
                         &mut self.core.s_temp_run,
                         &mut self.core.c_temp_run,
                         &self.pinter.mvd[pidx][REFP_0],
+                        mvr_idx,
                     );
                 }
 
-                if slice_type == SliceType::EVC_ST_B && REFI_IS_VALID(refi1) {
+                if affine_flag == 0 && slice_type == SliceType::EVC_ST_B && REFI_IS_VALID(refi1) {
                     evce_eco_refi(
                         &mut self.core.bs_temp,
                         &mut self.core.s_temp_run,
                         &mut self.core.c_temp_run,
-                        self.rpm.num_refp[REFP_1],
+                        self.num_refp[REFP_1],
                         refi1,
                     );
-                    evce_eco_mvp_idx(
-                        &mut self.core.bs_temp,
-                        &mut self.core.s_temp_run,
-                        &mut self.core.c_temp_run,
-                        mvp_idx[REFP_1] as u32,
-                    );
+                    if !self.sps_admvp_flag {
+                        evce_eco_mvp_idx(
+                            &mut self.core.bs_temp,
+                            &mut self.core.s_temp_run,
+                            &mut self.core.c_temp_run,
+                            mvp_idx[REFP_1] as u32,
+                        );
+                    }
                     evce_eco_mvd(
                         &mut self.core.bs_temp,
                         &mut self.core.s_temp_run,
                         &mut self.core.c_temp_run,
                         &self.pinter.mvd[pidx][REFP_1],
+                        mvr_idx,
                     );
                 }
             }
         }
-        if self.pps.cu_qp_delta_enabled_flag {
+        if self.pps_cu_qp_delta_enabled_flag {
             self.core.cu_qp_delta_code = self.core.dqp_temp_run.cu_qp_delta_code;
             self.core.cu_qp_delta_is_coded = self.core.dqp_temp_run.cu_qp_delta_is_coded;
             self.core.qp_prev_eco = self.core.dqp_temp_run.prev_qp;
@@ -1898,18 +2522,28 @@ impl EvceCtx {
             self.core.log2_cuh,
             PredMode::MODE_INTER,
             &self.core.nnz,
-            false,
+            self.sps_admvp_flag
+                && (pidx == InterPredDir::PRED_DIR as usize
+                    || pidx == InterPredDir::AFF_DIR as usize)
+                && self.core.nnz.iter().any(|&nnz| nnz != 0),
             TQC_RUN::RUN_L as u8 | TQC_RUN::RUN_CB as u8 | TQC_RUN::RUN_CR as u8,
-            self.pps.cu_qp_delta_enabled_flag,
+            self.pps_cu_qp_delta_enabled_flag,
             self.core.qp,
-            self.sps.dquant_flag,
-            self.pps.cu_qp_delta_enabled_flag,
+            self.sps_dquant_flag,
+            self.pps_cu_qp_delta_enabled_flag,
             self.core.cu_qp_delta_code,
             &mut self.core.cu_qp_delta_is_coded,
             &mut self.core.qp_prev_eco,
+            self.sps_ats_flag,
+            self.sps_cm_init_flag,
+            self.sps_adcc_flag,
+            self.chroma_format_idc,
+            self.core.ats_intra_cu,
+            self.core.ats_mode,
+            self.core.ats_inter_info,
         );
 
-        if self.pps.cu_qp_delta_enabled_flag {
+        if self.pps_cu_qp_delta_enabled_flag {
             self.core.dqp_temp_run.cu_qp_delta_code = self.core.cu_qp_delta_code;
             self.core.dqp_temp_run.cu_qp_delta_is_coded = self.core.cu_qp_delta_is_coded;
             self.core.dqp_temp_run.prev_qp = self.core.qp_prev_eco;
@@ -1943,11 +2577,18 @@ impl EvceCtx {
             run_stats,
             false,
             if ch_type == Y_C { self.core.qp } else { 0 },
-            self.sps.dquant_flag,
-            self.pps.cu_qp_delta_enabled_flag,
+            self.sps_dquant_flag,
+            self.pps_cu_qp_delta_enabled_flag,
             self.core.cu_qp_delta_code,
             &mut self.core.cu_qp_delta_is_coded,
             &mut self.core.qp_prev_eco,
+            self.sps_ats_flag,
+            self.sps_cm_init_flag,
+            self.sps_adcc_flag,
+            self.chroma_format_idc,
+            self.core.ats_intra_cu,
+            self.core.ats_mode,
+            self.core.ats_inter_info,
         );
     }
 
@@ -1956,14 +2597,71 @@ impl EvceCtx {
         slice_type: SliceType,
         mvp_idx0: u32,
         mvp_idx1: u32,
+        affine_flag: u8,
+        mmvd_flag: bool,
+        mmvd_idx: u16,
     ) {
         if slice_type != SliceType::EVC_ST_I {
             self.core.s_temp_run.encode_bin(
                 &mut self.core.bs_temp,
-                &mut self.core.c_temp_run.skip_flag[0],
+                &mut self.core.c_temp_run.skip_flag[self.core.ctx_flags[CNID_SKIP_FLAG]],
                 1,
             ); /* skip_flag */
 
+            if self.sps_mmvd_flag {
+                evce_eco_mmvd_flag(
+                    &mut self.core.bs_temp,
+                    &mut self.core.s_temp_run,
+                    &mut self.core.c_temp_run,
+                    mmvd_flag as u32,
+                );
+            }
+
+            if mmvd_flag {
+                evce_eco_mmvd_idx(
+                    &mut self.core.bs_temp,
+                    &mut self.core.s_temp_run,
+                    &mut self.core.c_temp_run,
+                    mmvd_idx,
+                    evc_mmvd_num_groups(
+                        self.sh.mmvd_group_enable_flag,
+                        self.core.cuw as usize,
+                        self.core.cuh as usize,
+                    ),
+                );
+                return;
+            }
+
+            if self.sps_affine_flag && self.core.cuw >= 8 && self.core.cuh >= 8 {
+                evce_eco_affine_flag(
+                    &mut self.core.bs_temp,
+                    &mut self.core.s_temp_run,
+                    &mut self.core.c_temp_run,
+                    (affine_flag != 0) as u32,
+                    self.core.ctx_flags[CNID_AFFN_FLAG],
+                );
+            }
+
+            if affine_flag != 0 {
+                evce_eco_affine_mrg_idx(
+                    &mut self.core.bs_temp,
+                    &mut self.core.s_temp_run,
+                    &mut self.core.c_temp_run,
+                    mvp_idx0,
+                );
+                return;
+            }
+
+            if self.sps_admvp_flag {
+                evce_eco_merge_idx(
+                    &mut self.core.bs_temp,
+                    &mut self.core.s_temp_run,
+                    &mut self.core.c_temp_run,
+                    mvp_idx0,
+                );
+                return;
+            }
+
             evce_eco_mvp_idx(
                 &mut self.core.bs_temp,
                 &mut self.core.s_temp_run,
@@ -2007,6 +2705,7 @@ impl EvceCtx {
                     &mut self.core.s_temp_run,
                     &mut self.core.c_temp_run,
                     &mvd[REFP_0],
+                    0,
                 );
             }
             if slice_type == SliceType::EVC_ST_B && REFI_IS_VALID(refi1) {
@@ -2021,6 +2720,7 @@ impl EvceCtx {
                     &mut self.core.s_temp_run,
                     &mut self.core.c_temp_run,
                     &mvd[REFP_1],
+                    0,
                 );
             }
         }
@@ -2063,6 +2763,8 @@ impl EvceCtx {
         let y_tm = 4; //must be the same as x_tm
         let log2_x_tm = 2;
         let log2_y_tm = 2;
+        let w_shift = GET_CHROMA_W_SHIFT(self.chroma_format_idc);
+        let h_shift = GET_CHROMA_H_SHIFT(self.chroma_format_idc);
 
         //reset
         for i in 0..N_C {
@@ -2072,9 +2774,9 @@ impl EvceCtx {
 
         /********************** prepare pred/rec pixels (not filtered) ****************************/
         if let (Some(dbk), Some(rec)) = (&self.pic_dbk, &self.pic[PIC_IDX_MODE]) {
-            let (frame_dbk, frame_rec) = (&dbk.borrow().frame, &rec.borrow().frame);
-            let dst = &mut frame_dbk.borrow_mut().planes;
-            let rec = &frame_rec.borrow().planes;
+            let (frame_dbk, frame_rec) = (&dbk.read().unwrap().frame, &rec.read().unwrap().frame);
+            let dst = &mut frame_dbk.write().unwrap().planes;
+            let rec = &frame_rec.read().unwrap().planes;
 
             fill_dbf_block(
                 x,
@@ -2089,37 +2791,43 @@ impl EvceCtx {
                 &rec[Y_C].as_region(),
             );
 
-            fill_dbf_block(
-                x >> 1,
-                y >> 1,
-                x_offset >> 1,
-                y_offset >> 1,
-                cuw >> 1,
-                cuh >> 1,
-                avail_lr,
-                &mut dst[U_C].as_region_mut(),
-                &src.data[U_C],
-                &rec[U_C].as_region(),
-            );
+            /* monochrome pictures have no chroma samples to filter */
+            if self.chroma_format_idc != 0 {
+                fill_dbf_block(
+                    x >> w_shift,
+                    y >> h_shift,
+                    x_offset >> w_shift,
+                    y_offset >> h_shift,
+                    cuw >> w_shift,
+                    cuh >> h_shift,
+                    avail_lr,
+                    &mut dst[U_C].as_region_mut(),
+                    &src.data[U_C],
+                    &rec[U_C].as_region(),
+                );
 
-            fill_dbf_block(
-                x >> 1,
-                y >> 1,
-                x_offset >> 1,
-                y_offset >> 1,
-                cuw >> 1,
-                cuh >> 1,
-                avail_lr,
-                &mut dst[V_C].as_region_mut(),
-                &src.data[V_C],
-                &rec[V_C].as_region(),
-            );
+                fill_dbf_block(
+                    x >> w_shift,
+                    y >> h_shift,
+                    x_offset >> w_shift,
+                    y_offset >> h_shift,
+                    cuw >> w_shift,
+                    cuh >> h_shift,
+                    avail_lr,
+                    &mut dst[V_C].as_region_mut(),
+                    &src.data[V_C],
+                    &rec[V_C].as_region(),
+                );
+            }
         }
 
         /*********************** calc dist of un-filtered pixels *******************************/
         if let (Some(dbk), Some(org)) = (&self.pic_dbk, &self.pic[PIC_IDX_ORIG]) {
-            let (frame_dbk, frame_org) = (&dbk.borrow().frame, &org.borrow().frame);
-            let (dst, org) = (&frame_dbk.borrow().planes, &frame_org.borrow().planes);
+            let (frame_dbk, frame_org) = (&dbk.read().unwrap().frame, &org.read().unwrap().frame);
+            let (dst, org) = (
+                &frame_dbk.read().unwrap().planes,
+                &frame_org.read().unwrap().planes,
+            );
 
             self.core.dist_nofilt[Y_C] = dist_nofilt(
                 x,
@@ -2135,33 +2843,35 @@ impl EvceCtx {
                 &org[Y_C].as_region(),
             );
 
-            self.core.dist_nofilt[U_C] = dist_nofilt(
-                x >> 1,
-                y >> 1,
-                x_tm >> 1,
-                y_tm >> 1,
-                log2_cuw - 1,
-                log2_cuh - 1,
-                log2_x_tm - 1,
-                log2_y_tm - 1,
-                avail_lr,
-                &dst[U_C].as_region(),
-                &org[U_C].as_region(),
-            );
+            if self.chroma_format_idc != 0 {
+                self.core.dist_nofilt[U_C] = dist_nofilt(
+                    x >> w_shift,
+                    y >> h_shift,
+                    x_tm >> w_shift,
+                    y_tm >> h_shift,
+                    log2_cuw - w_shift,
+                    log2_cuh - h_shift,
+                    log2_x_tm - w_shift,
+                    log2_y_tm - h_shift,
+                    avail_lr,
+                    &dst[U_C].as_region(),
+                    &org[U_C].as_region(),
+                );
 
-            self.core.dist_nofilt[V_C] = dist_nofilt(
-                x >> 1,
-                y >> 1,
-                x_tm >> 1,
-                y_tm >> 1,
-                log2_cuw - 1,
-                log2_cuh - 1,
-                log2_x_tm - 1,
-                log2_y_tm - 1,
-                avail_lr,
-                &dst[V_C].as_region(),
-                &org[V_C].as_region(),
-            );
+                self.core.dist_nofilt[V_C] = dist_nofilt(
+                    x >> w_shift,
+                    y >> h_shift,
+                    x_tm >> w_shift,
+                    y_tm >> h_shift,
+                    log2_cuw - w_shift,
+                    log2_cuh - h_shift,
+                    log2_x_tm - w_shift,
+                    log2_y_tm - h_shift,
+                    avail_lr,
+                    &dst[V_C].as_region(),
+                    &org[V_C].as_region(),
+                );
+            }
         }
 
         /********************************* filter the pred/rec **************************************/
@@ -2176,6 +2886,8 @@ impl EvceCtx {
         //save current best cu info
         let intra_flag_save = self.map_scu[t].GET_IF() != 0;
         let cbf_l_save = self.map_scu[t].GET_CBFL() != 0;
+        let ats_inter_save = self.map_scu[t].GET_ATS_INTER() != 0;
+        let ats_inter = !intra_flag && self.core.ats_inter_info != 0;
         //set map info of current cu to current mode
         for j in 0..h_scu {
             let ind = (y_scu + j) * self.w_scu as usize + x_scu;
@@ -2194,10 +2906,18 @@ impl EvceCtx {
                     self.map_scu[k].CLR_CBFL();
                 }
 
+                if ats_inter {
+                    self.map_scu[k].SET_ATS_INTER();
+                } else {
+                    self.map_scu[k].CLR_ATS_INTER();
+                }
+
                 if !intra_flag {
-                    if let (Some(map_refi), Some(map_mv)) = (&mut self.map_refi, &mut self.map_mv) {
+                    if let (Some(map_refi), Some(map_mv)) =
+                        (&mut self.map_refi, &mut self.map_unrefined_mv)
+                    {
                         let (mut map_refi, mut map_mv) =
-                            (map_refi.borrow_mut(), map_mv.borrow_mut());
+                            (map_refi.write().unwrap(), map_mv.write().unwrap());
 
                         map_refi[k][REFP_0] = refi[REFP_0];
                         map_refi[k][REFP_1] = refi[REFP_1];
@@ -2207,7 +2927,7 @@ impl EvceCtx {
                         map_mv[k][REFP_1][MV_Y] = mv[REFP_1][MV_Y];
                     }
                 }
-                if self.pps.cu_qp_delta_enabled_flag {
+                if self.pps_cu_qp_delta_enabled_flag {
                     self.map_scu[k].RESET_QP();
                 }
 
@@ -2219,22 +2939,33 @@ impl EvceCtx {
         }
 
         if let (Some(pic_dbk), Some(map_refi), Some(map_mv)) =
-            (&self.pic_dbk, &self.map_refi, &self.map_mv)
+            (&self.pic_dbk, &self.map_refi, &self.map_unrefined_mv)
         {
+            /* As of now filtering across tile boundaries is disabled */
+            let (tile_x, tile_y) = (
+                (self.tile.x_lcu as usize) << self.log2_max_cuwh,
+                (self.tile.y_lcu as usize) << self.log2_max_cuwh,
+            );
+            let tile_r = ((self.tile.x_lcu + self.tile.w_lcu) as usize) << self.log2_max_cuwh;
+
             //first, horizontal filtering
-            // As of now filtering across tile boundaries is disabled
             evc_deblock_cu_hor(
                 &mut self.core.bs_temp.tracer,
-                &*pic_dbk.borrow(),
+                &*pic_dbk.read().unwrap(),
                 x as usize,
                 y as usize,
                 cuw as usize,
                 cuh as usize,
                 &mut self.map_scu,
-                &*map_refi.borrow(),
-                &*map_mv.borrow(),
+                &*map_refi.read().unwrap(),
+                &*map_mv.read().unwrap(),
+                &self.refp,
                 self.w_scu as usize,
                 &self.core.evc_tbl_qp_chroma_dynamic_ext,
+                self.sps_addb_flag,
+                self.sh.sh_deblock_alpha_offset,
+                self.sh.sh_deblock_beta_offset,
+                y as usize > tile_y,
             );
 
             //clean coded flag in between two directional filtering (not necessary here)
@@ -2249,17 +2980,23 @@ impl EvceCtx {
             //then, vertical filtering
             evc_deblock_cu_ver(
                 &mut self.core.bs_temp.tracer,
-                &*pic_dbk.borrow(),
+                &*pic_dbk.read().unwrap(),
                 x as usize,
                 y as usize,
                 cuw as usize,
                 cuh as usize,
                 &mut self.map_scu,
-                &*map_refi.borrow(),
-                &*map_mv.borrow(),
+                &*map_refi.read().unwrap(),
+                &*map_mv.read().unwrap(),
+                &self.refp,
                 self.w_scu as usize,
                 &self.core.evc_tbl_qp_chroma_dynamic_ext,
+                self.sps_addb_flag,
+                self.sh.sh_deblock_alpha_offset,
+                self.sh.sh_deblock_beta_offset,
                 self.w as usize,
+                x as usize > tile_x,
+                tile_r > (x + cuw) as usize,
             );
         }
 
@@ -2281,14 +3018,23 @@ impl EvceCtx {
                     self.map_scu[k].CLR_CBFL();
                 }
 
+                if ats_inter_save {
+                    self.map_scu[k].SET_ATS_INTER();
+                } else {
+                    self.map_scu[k].CLR_ATS_INTER();
+                }
+
                 self.map_scu[k].CLR_COD();
             }
         }
 
         /*********************** calc dist of filtered pixels *******************************/
         if let (Some(dbk), Some(org)) = (&self.pic_dbk, &self.pic[PIC_IDX_ORIG]) {
-            let (frame_dbk, frame_org) = (&dbk.borrow().frame, &org.borrow().frame);
-            let (dst, org) = (&frame_dbk.borrow().planes, &frame_org.borrow().planes);
+            let (frame_dbk, frame_org) = (&dbk.read().unwrap().frame, &org.read().unwrap().frame);
+            let (dst, org) = (
+                &frame_dbk.read().unwrap().planes,
+                &frame_org.read().unwrap().planes,
+            );
 
             self.core.dist_filter[Y_C] = dist_nofilt(
                 x,
@@ -2304,33 +3050,35 @@ impl EvceCtx {
                 &org[Y_C].as_region(),
             );
 
-            self.core.dist_filter[U_C] = dist_nofilt(
-                x >> 1,
-                y >> 1,
-                x_tm >> 1,
-                y_tm >> 1,
-                log2_cuw - 1,
-                log2_cuh - 1,
-                log2_x_tm - 1,
-                log2_y_tm - 1,
-                avail_lr,
-                &dst[U_C].as_region(),
-                &org[U_C].as_region(),
-            );
+            if self.chroma_format_idc != 0 {
+                self.core.dist_filter[U_C] = dist_nofilt(
+                    x >> w_shift,
+                    y >> h_shift,
+                    x_tm >> w_shift,
+                    y_tm >> h_shift,
+                    log2_cuw - w_shift,
+                    log2_cuh - h_shift,
+                    log2_x_tm - w_shift,
+                    log2_y_tm - h_shift,
+                    avail_lr,
+                    &dst[U_C].as_region(),
+                    &org[U_C].as_region(),
+                );
 
-            self.core.dist_filter[V_C] = dist_nofilt(
-                x >> 1,
-                y >> 1,
-                x_tm >> 1,
-                y_tm >> 1,
-                log2_cuw - 1,
-                log2_cuh - 1,
-                log2_x_tm - 1,
-                log2_y_tm - 1,
-                avail_lr,
-                &dst[V_C].as_region(),
-                &org[V_C].as_region(),
-            );
+                self.core.dist_filter[V_C] = dist_nofilt(
+                    x >> w_shift,
+                    y >> h_shift,
+                    x_tm >> w_shift,
+                    y_tm >> h_shift,
+                    log2_cuw - w_shift,
+                    log2_cuh - h_shift,
+                    log2_x_tm - w_shift,
+                    log2_y_tm - h_shift,
+                    avail_lr,
+                    &dst[V_C].as_region(),
+                    &org[V_C].as_region(),
+                );
+            }
         }
 
         /******************************* derive delta dist ********************************/
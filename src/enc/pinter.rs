@@ -10,8 +10,7 @@ use crate::picman::*;
 use crate::recon::*;
 use crate::util::*;
 
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::{Arc, RwLock};
 
 /*****************************************************************************
  * inter prediction structure
@@ -88,6 +87,25 @@ pub(crate) static tbl_diapos_partial: [[[i8; 2]; 16]; 2] = [
     ],
 ];
 
+#[derive(Clone, Copy)]
+struct InterMotion {
+    refi: [i8; REFP_NUM],
+    mv: [[i16; MV_D]; REFP_NUM],
+    mvd: [[i16; MV_D]; REFP_NUM],
+    mvp_idx: [u8; REFP_NUM],
+    mvr_idx: u8,
+}
+
+/* residual of the best transform, kept while trying the sub-block transforms */
+struct InterResidue {
+    coef: CUBuffer<i16>,
+    rec: CUBuffer<pel>,
+    nnz: [u16; N_C],
+    s_temp_best: EvceSbac,
+    c_temp_best: EvcSbacCtx,
+    dqp_temp_best: EvceDQP,
+}
+
 #[derive(Default)]
 pub(crate) struct EvcePInter {
     /* temporary prediction buffer (only used for ME)*/
@@ -105,6 +123,9 @@ pub(crate) struct EvcePInter {
 
     /* MV predictor */
     mvp: [[[i16; MV_D]; MAX_NUM_MVP]; REFP_NUM],
+    /* merge candidates of the MMVD base motions, from the refined motion */
+    mmvd_refi_pred: [[i8; MAX_NUM_MVP]; REFP_NUM],
+    mmvd_mvp: [[[i16; MV_D]; MAX_NUM_MVP]; REFP_NUM],
 
     mv: [[[i16; MV_D]; REFP_NUM]; InterPredDir::PRED_NUM as usize],
     pub(crate) mvd: [[[i16; MV_D]; REFP_NUM]; InterPredDir::PRED_NUM as usize],
@@ -112,6 +133,30 @@ pub(crate) struct EvcePInter {
     pub(crate) org_bi: CUBuffer<i16>,
     pub(crate) mot_bits: [u32; REFP_NUM],
 
+    /* affine flag of each prediction: 0 for translational, 1 for 4-parameter,
+    2 for 6-parameter */
+    pub(crate) affine_flag: [u8; InterPredDir::PRED_NUM as usize],
+    /* control point motion vectors and their differences */
+    pub(crate) affine_mv: [[[[i16; MV_D]; VER_NUM]; REFP_NUM]; InterPredDir::PRED_NUM as usize],
+    pub(crate) affine_mvd: [[[[i16; MV_D]; VER_NUM]; REFP_NUM]; InterPredDir::PRED_NUM as usize],
+    /* best control point motion vectors for each reference index */
+    affine_mv_scale: [[[[i16; MV_D]; VER_NUM]; MAX_NUM_ACTIVE_REF_FRAME]; REFP_NUM],
+    affine_mvp_scale:
+        [[[[[i16; MV_D]; VER_NUM]; AFF_MAX_NUM_MVP]; MAX_NUM_ACTIVE_REF_FRAME]; REFP_NUM],
+
+    /* whether each prediction is refined by DMVR, and the refined motion of
+    every 4x4 block (the last one is used while comparing merge candidates) */
+    dmvr_flag: [bool; InterPredDir::PRED_NUM as usize + 1],
+    dmvr_mv: Vec<Vec<[[i16; MV_D]; REFP_NUM]>>,
+
+    /* motion vector resolution of each prediction, and the resolution
+    under search (used for the mv cost in motion estimation) */
+    pub(crate) mvr_idx: [u8; InterPredDir::PRED_NUM as usize],
+    pub(crate) me_mvr_idx: u8,
+    /* merge with motion vector difference of each prediction */
+    pub(crate) mmvd_flag: [bool; InterPredDir::PRED_NUM as usize],
+    pub(crate) mmvd_idx: [u16; InterPredDir::PRED_NUM as usize],
+
     /* temporary prediction buffer (only used for ME)*/
     pub(crate) pred: [[CUBuffer<pel>; 2]; InterPredDir::PRED_NUM as usize + 1],
 
@@ -123,6 +168,8 @@ pub(crate) struct EvcePInter {
     residue: CUBuffer<i16>,
 
     nnz_best: [[u16; N_C]; InterPredDir::PRED_NUM as usize],
+    /* sub-block transform of the residual of each prediction */
+    ats_inter_info: [u8; InterPredDir::PRED_NUM as usize + 1],
 
     pub(crate) num_refp: u8,
 
@@ -131,6 +178,10 @@ pub(crate) struct EvcePInter {
     /* maximum clip value */
     pub(crate) max_clip: [i16; MV_D],
 
+    /* picture size */
+    pub(crate) pic_w: i16,
+    pub(crate) pic_h: i16,
+
     /* search range for int-pel */
     pub(crate) search_range_ipel: [i16; MV_D],
     /* search range for sub-pel */
@@ -141,7 +192,7 @@ pub(crate) struct EvcePInter {
     pub(crate) search_pattern_qpel_cnt: u8,
 
     /* original (input) picture buffer */
-    pub(crate) pic_o: Option<Rc<RefCell<EvcPic>>>,
+    pub(crate) pic_o: Option<Arc<RwLock<EvcPic>>>,
 
     /* QP for luma of current encoding CU */
     pub(crate) qp_y: u8,
@@ -151,6 +202,8 @@ pub(crate) struct EvcePInter {
     pub(crate) lambda_mv: u32,
 
     slice_type: SliceType,
+    /* main profile interpolation filters are used with admvp */
+    pub(crate) admvp: bool,
     /* search level for motion estimation */
     pub(crate) me_level: usize,
     pub(crate) complexity: usize,
@@ -168,6 +221,8 @@ impl EvcePInter {
         pinter.min_clip[MV_Y] = 1 - 128; //(MAX_CU_SIZE as i16);
         pinter.max_clip[MV_X] = w as i16 - 1;
         pinter.max_clip[MV_Y] = h as i16 - 1;
+        pinter.pic_w = w as i16;
+        pinter.pic_h = h as i16;
 
         /* default values *************************************************/
         pinter.max_search_range = if max_b_frames == 0 {
@@ -190,17 +245,23 @@ impl EvcePInter {
         pinter.me_level = ME_LEV_QPEL;
         pinter.complexity = 0;
 
+        pinter.dmvr_mv = vec![
+            vec![[[0; MV_D]; REFP_NUM]; MAX_CU_CNT_IN_LCU];
+            InterPredDir::PRED_NUM as usize + 1
+        ];
+
         pinter
     }
 }
 
-impl EvceCtx {
+impl EvceTask {
     pub(crate) fn pinter_init_frame(&mut self) {
         let pi = &mut self.pinter;
 
         pi.slice_type = self.slice_type;
+        pi.admvp = self.sps_admvp_flag;
         if let Some(pic) = &self.pic[PIC_IDX_ORIG] {
-            pi.pic_o = Some(Rc::clone(pic));
+            pi.pic_o = Some(Arc::clone(pic));
         }
     }
 
@@ -213,7 +274,7 @@ impl EvceCtx {
         pi.qp_y = self.core.qp_y;
         pi.qp_u = self.core.qp_u;
         pi.qp_v = self.core.qp_v;
-        pi.poc = self.poc.poc_val;
+        pi.poc = self.poc_val;
         pi.gop_size = self.gop_size;
     }
 
@@ -226,6 +287,8 @@ impl EvceCtx {
         log2_cuw: usize,
         log2_cuh: usize,
     ) -> f64 {
+        let w_shift = GET_CHROMA_W_SHIFT(self.chroma_format_idc);
+        let h_shift = GET_CHROMA_H_SHIFT(self.chroma_format_idc);
         let cuw = (1 << log2_cuw) as usize;
         let cuh = (1 << log2_cuh) as usize;
         let mut cost_inter = vec![MAX_COST; InterPredDir::PRED_NUM as usize];
@@ -238,8 +301,15 @@ impl EvceCtx {
         let mut refi_temp = 0usize;
         let mut refi_cur = 0usize;
 
+        self.pinter.dmvr_flag = [false; InterPredDir::PRED_NUM as usize + 1];
+        self.pinter.ats_inter_info = [0; InterPredDir::PRED_NUM as usize + 1];
+        self.pinter.mvr_idx = [0; InterPredDir::PRED_NUM as usize];
+        self.pinter.me_mvr_idx = 0;
+        self.pinter.mmvd_flag = [false; InterPredDir::PRED_NUM as usize];
+        self.pinter.mmvd_idx = [0; InterPredDir::PRED_NUM as usize];
+
         /* skip mode */
-        let mut cost = self.analyze_skip_baseline(x, y, log2_cuw, log2_cuh);
+        let mut cost = self.analyze_skip(x, y, log2_cuw, log2_cuh);
         cost_inter[InterPredDir::PRED_SKIP as usize] = cost;
 
         if cost < cost_best {
@@ -257,8 +327,12 @@ impl EvceCtx {
             }
         }
 
-        if self.pinter.slice_type == SliceType::EVC_ST_B {
-            cost = self.analyze_t_direct(x, y, log2_cuw, log2_cuh);
+        if self.sps_admvp_flag || self.pinter.slice_type == SliceType::EVC_ST_B {
+            cost = if self.sps_admvp_flag {
+                self.analyze_merge(x, y, log2_cuw, log2_cuh)
+            } else {
+                self.analyze_t_direct(x, y, log2_cuw, log2_cuh)
+            };
             cost_inter[InterPredDir::PRED_DIR as usize] = cost;
             if cost < cost_best {
                 self.core.cu_mode = PredMode::MODE_DIR;
@@ -272,162 +346,290 @@ impl EvceCtx {
             }
         }
 
-        /* Motion Search *********************************************************/
-        for lidx in 0..=if self.pinter.slice_type == SliceType::EVC_ST_P {
-            InterPredDir::PRED_L0 as usize
-        } else {
-            InterPredDir::PRED_L1 as usize
-        } {
-            pidx = lidx;
+        if self.sps_affine_flag && cuw >= 8 && cuh >= 8 {
+            cost = self.analyze_affine_skip(x, y, log2_cuw, log2_cuh);
+            cost_inter[InterPredDir::AFF_SKIP as usize] = cost;
 
-            self.pinter.num_refp = self.rpm.num_refp[lidx];
+            if cost < cost_best {
+                self.core.cu_mode = PredMode::MODE_SKIP;
+                best_idx = InterPredDir::AFF_SKIP as usize;
+                cost_inter[best_idx as usize] = cost;
+                cost_best = cost;
 
-            best_mecost = std::u32::MAX;
-            refi_cur = 0;
-            while refi_cur < self.pinter.num_refp as usize {
-                let map_mv = self.map_mv.as_ref().unwrap().borrow();
-                evc_get_motion(
-                    self.core.scup as usize,
-                    lidx,
-                    &*map_mv,
-                    &self.refp,
-                    self.core.cuw as usize,
-                    self.core.cuh as usize,
-                    self.w_scu as usize,
-                    self.core.avail_cu,
-                    &mut self.pinter.refi_pred[lidx],
-                    &mut self.pinter.mvp_scale[lidx][refi_cur],
-                );
-                mvp_idx[lidx] = self.pinter.mvp_idx[InterPredDir::PRED_SKIP as usize][lidx];
+                self.core.s_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.s_temp_best;
+                self.core.c_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.c_temp_best;
+                self.core.dqp_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.dqp_temp_best;
 
-                /* motion search ********************/
+                for v in &mut self.pinter.nnz_best[InterPredDir::AFF_SKIP as usize] {
+                    *v = 0;
+                }
+            }
 
-                //copy to temp mvp and mv
-                let mvp = self.pinter.mvp_scale[lidx][refi_cur][mvp_idx[lidx] as usize];
-                let mut mv = self.pinter.mv[pidx][lidx];
-                mecost = self.pinter.pinter_me_epzs(
-                    &mut self.core.bs_temp.tracer,
-                    x as i16,
-                    y as i16,
-                    log2_cuw,
-                    log2_cuh,
-                    refi_cur as i8,
-                    lidx,
-                    &mvp,
-                    &mut mv,
-                    0,
-                    &self.refp,
-                );
-                // load mv back
-                self.pinter.mv[pidx][lidx] = mv;
+            cost = self.analyze_affine_merge(x, y, log2_cuw, log2_cuh);
+            cost_inter[InterPredDir::AFF_DIR as usize] = cost;
 
-                self.pinter.mv_scale[lidx][refi_cur as usize][MV_X] = mv[MV_X];
-                self.pinter.mv_scale[lidx][refi_cur as usize][MV_Y] = mv[MV_Y];
-                if mecost < best_mecost {
-                    best_mecost = mecost;
-                    refi_temp = refi_cur;
-                }
+            if cost < cost_best {
+                self.core.cu_mode = PredMode::MODE_DIR;
+                best_idx = InterPredDir::AFF_DIR as usize;
+                cost_inter[best_idx as usize] = cost;
+                cost_best = cost;
 
-                refi_cur += 1;
+                self.core.s_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.s_temp_best;
+                self.core.c_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.c_temp_best;
+                self.core.dqp_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.dqp_temp_best;
             }
+        }
 
-            refi_cur = refi_temp;
+        /* Motion Search *********************************************************/
+        /* with adaptive motion vector resolution, uni- and bi-prediction are
+        searched for every resolution and the best one is kept for each */
+        let num_mvr = if self.sps_amvr_flag { MAX_NUM_MVR } else { 1 };
+        for mvr_idx in 0..num_mvr as u8 {
+            self.pinter.me_mvr_idx = mvr_idx;
+
+            for lidx in 0..=if self.pinter.slice_type == SliceType::EVC_ST_P {
+                InterPredDir::PRED_L0 as usize
+            } else {
+                InterPredDir::PRED_L1 as usize
+            } {
+                pidx = lidx;
+                let prev = self.save_inter_motion(pidx);
+
+                self.pinter.num_refp = self.num_refp[lidx];
+
+                best_mecost = std::u32::MAX;
+                refi_cur = 0;
+                while refi_cur < self.pinter.num_refp as usize {
+                    let map_mv = self.map_unrefined_mv.as_ref().unwrap().read().unwrap();
+                    if self.sps_admvp_flag {
+                        /* single AMVP predictor for each reference index */
+                        let map_refi = self.map_refi.as_ref().unwrap().read().unwrap();
+                        evc_get_motion_from_mvr(
+                            mvr_idx,
+                            self.poc_val,
+                            self.core.scup as usize,
+                            lidx,
+                            refi_cur as i8,
+                            self.num_refp[lidx],
+                            &*map_mv,
+                            &*map_refi,
+                            &self.refp,
+                            self.core.cuw as usize,
+                            self.core.cuh as usize,
+                            self.w_scu as usize,
+                            self.h_scu as usize,
+                            &mut self.pinter.mvp_scale[lidx][refi_cur][0],
+                            &self.map_scu,
+                            self.core.avail_lr,
+                            &self.core.history_buffer,
+                            self.sps_hmvp_flag,
+                        );
+                        mvp_idx[lidx] = 0;
+                    } else {
+                        evc_get_motion(
+                            self.core.scup as usize,
+                            lidx,
+                            &*map_mv,
+                            &self.refp,
+                            self.core.cuw as usize,
+                            self.core.cuh as usize,
+                            self.w_scu as usize,
+                            self.core.avail_cu,
+                            &mut self.pinter.refi_pred[lidx],
+                            &mut self.pinter.mvp_scale[lidx][refi_cur],
+                        );
+                        mvp_idx[lidx] = self.pinter.mvp_idx[InterPredDir::PRED_SKIP as usize][lidx];
+                    }
 
-            let mv = &mut self.pinter.mv[pidx][lidx];
-            mv[MV_X] = self.pinter.mv_scale[lidx][refi_cur][MV_X];
-            mv[MV_Y] = self.pinter.mv_scale[lidx][refi_cur][MV_Y];
+                    /* motion search ********************/
 
-            let t0 = if lidx == 0 {
-                refi_cur as i8
-            } else {
-                REFI_INVALID
-            };
-            let t1 = if lidx == 1 {
-                refi_cur as i8
-            } else {
-                REFI_INVALID
-            };
+                    //copy to temp mvp and mv
+                    let mvp = self.pinter.mvp_scale[lidx][refi_cur][mvp_idx[lidx] as usize];
+                    let mut mv = self.pinter.mv[pidx][lidx];
+                    mecost = self.pinter.pinter_me_epzs(
+                        &mut self.core.bs_temp.tracer,
+                        x as i16,
+                        y as i16,
+                        log2_cuw,
+                        log2_cuh,
+                        refi_cur as i8,
+                        lidx,
+                        &mvp,
+                        &mut mv,
+                        0,
+                        &self.refp,
+                    );
+                    // load mv back
+                    self.pinter.mv[pidx][lidx] = mv;
+
+                    self.pinter.mv_scale[lidx][refi_cur as usize][MV_X] = mv[MV_X];
+                    self.pinter.mv_scale[lidx][refi_cur as usize][MV_Y] = mv[MV_Y];
+                    if mecost < best_mecost {
+                        best_mecost = mecost;
+                        refi_temp = refi_cur;
+                    }
 
-            let refi = &mut self.pinter.refi[pidx];
-            refi[REFP_0] = t0;
-            refi[REFP_1] = t1;
+                    refi_cur += 1;
+                }
 
-            let mvd = &mut self.pinter.mvd[pidx][lidx];
-            let mvp = &self.pinter.mvp_scale[lidx][refi_cur];
-            mvd[MV_X] = mv[MV_X] - mvp[mvp_idx[lidx] as usize][MV_X];
-            mvd[MV_Y] = mv[MV_Y] - mvp[mvp_idx[lidx] as usize][MV_Y];
+                refi_cur = refi_temp;
 
-            self.check_best_mvp(
-                self.pinter.slice_type,
-                lidx,
-                pidx,
-                refi_cur,
-                &mut mvp_idx[lidx],
-            );
+                let mv = &mut self.pinter.mv[pidx][lidx];
+                mv[MV_X] = self.pinter.mv_scale[lidx][refi_cur][MV_X];
+                mv[MV_Y] = self.pinter.mv_scale[lidx][refi_cur][MV_Y];
 
-            self.pinter.mvp_idx[pidx][lidx] = mvp_idx[lidx];
+                let t0 = if lidx == 0 {
+                    refi_cur as i8
+                } else {
+                    REFI_INVALID
+                };
+                let t1 = if lidx == 1 {
+                    refi_cur as i8
+                } else {
+                    REFI_INVALID
+                };
 
-            cost = self.pinter_residue_rdo(
-                x,
-                y,
-                log2_cuw,
-                log2_cuh,
-                pidx,
-                &mvp_idx,
-                InterPredDir::PRED_NUM as usize,
-            );
-            cost_inter[pidx] = cost;
+                let refi = &mut self.pinter.refi[pidx];
+                refi[REFP_0] = t0;
+                refi[REFP_1] = t1;
 
-            if cost < cost_best {
-                self.core.cu_mode = PredMode::MODE_INTER;
-                best_idx = pidx;
+                let mvd = &mut self.pinter.mvd[pidx][lidx];
+                let mvp = &self.pinter.mvp_scale[lidx][refi_cur];
+                mvd[MV_X] = mv[MV_X] - mvp[mvp_idx[lidx] as usize][MV_X];
+                mvd[MV_Y] = mv[MV_Y] - mvp[mvp_idx[lidx] as usize][MV_Y];
+
+                if !self.sps_admvp_flag {
+                    self.check_best_mvp(
+                        self.pinter.slice_type,
+                        lidx,
+                        pidx,
+                        refi_cur,
+                        &mut mvp_idx[lidx],
+                    );
+                }
 
-                self.pinter.mvp_idx[best_idx][lidx] = mvp_idx[lidx];
-                cost_best = cost;
-                cost_inter[best_idx] = cost;
-                self.core.s_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.s_temp_best;
-                self.core.c_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.c_temp_best;
-                self.core.dqp_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.dqp_temp_best;
+                self.pinter.mvp_idx[pidx][lidx] = mvp_idx[lidx];
+                self.pinter.mvr_idx[pidx] = mvr_idx;
 
-                let (pred_pidx, pred_num) = self
-                    .pinter
-                    .pred
-                    .split_at_mut(InterPredDir::PRED_NUM as usize);
-                let (coef_pidx, coef_num) = self
-                    .pinter
-                    .coef
-                    .split_at_mut(InterPredDir::PRED_NUM as usize);
-                for j in 0..N_C {
-                    let size_tmp = (cuw * cuh) >> (if j == 0 { 0 } else { 2 });
-                    self.pinter.nnz_best[pidx][j] = self.core.nnz[j];
-                    pred_pidx[pidx][0].data[j][..size_tmp]
-                        .copy_from_slice(&pred_num[0][0].data[j][..size_tmp]);
-                    coef_pidx[pidx].data[j][..size_tmp]
-                        .copy_from_slice(&coef_num[0].data[j][..size_tmp]);
+                cost = self.pinter_residue_rdo(
+                    x,
+                    y,
+                    log2_cuw,
+                    log2_cuh,
+                    pidx,
+                    &mvp_idx,
+                    InterPredDir::PRED_NUM as usize,
+                );
+
+                if cost >= cost_inter[pidx] {
+                    /* keep the motion found at a previous resolution */
+                    self.load_inter_motion(pidx, prev);
+                } else {
+                    cost_inter[pidx] = cost;
+
+                    if cost < cost_best {
+                        self.core.cu_mode = PredMode::MODE_INTER;
+                        best_idx = pidx;
+
+                        self.pinter.mvp_idx[best_idx][lidx] = mvp_idx[lidx];
+                        cost_best = cost;
+                        cost_inter[best_idx] = cost;
+                        self.core.s_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.s_temp_best;
+                        self.core.c_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.c_temp_best;
+                        self.core.dqp_next_best[log2_cuw - 2][log2_cuh - 2] =
+                            self.core.dqp_temp_best;
+
+                        self.copy_inter_pred_coef(pidx, cuw, cuh);
+                    }
+                }
+            }
+
+            if check_bi_applicability(self.pinter.slice_type, cuw, cuh, self.sps_admvp_flag) {
+                pidx = InterPredDir::PRED_BI as usize;
+                let prev = self.save_inter_motion(pidx);
+                cost = self.analyze_bi(x, y, log2_cuw, log2_cuh, &cost_inter);
+
+                if cost >= cost_inter[pidx] {
+                    self.load_inter_motion(pidx, prev);
+                } else {
+                    cost_inter[pidx] = cost;
+
+                    if cost < cost_best {
+                        self.core.cu_mode = PredMode::MODE_INTER;
+                        best_idx = pidx;
+                        cost_best = cost;
+                        cost_inter[best_idx] = cost;
+
+                        self.core.s_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.s_temp_best;
+                        self.core.c_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.c_temp_best;
+                        self.core.dqp_next_best[log2_cuw - 2][log2_cuh - 2] =
+                            self.core.dqp_temp_best;
+
+                        self.copy_inter_pred_coef(pidx, cuw, cuh);
+                    }
                 }
             }
         }
 
-        if self.pinter.slice_type == SliceType::EVC_ST_B {
-            pidx = InterPredDir::PRED_BI as usize;
-            cost = self.analyze_bi(x, y, log2_cuw, log2_cuh, &cost_inter);
-            cost_inter[pidx] = cost;
+        self.pinter.me_mvr_idx = 0;
 
-            if cost < cost_best {
-                self.core.cu_mode = PredMode::MODE_INTER;
-                best_idx = pidx;
-                cost_best = cost;
-                cost_inter[best_idx] = cost;
+        /* affine motion search with the 4-parameter and the 6-parameter model */
+        if self.sps_affine_flag && cuw >= 16 && cuh >= 16 {
+            for vertex_num in 2..=3 {
+                let (pidx_l0, pidx_bi) = if vertex_num == 2 {
+                    (InterPredDir::AFF_L0 as usize, InterPredDir::AFF_BI as usize)
+                } else {
+                    (
+                        InterPredDir::AFF_6_L0 as usize,
+                        InterPredDir::AFF_6_BI as usize,
+                    )
+                };
 
-                self.core.s_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.s_temp_best;
-                self.core.c_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.c_temp_best;
-                self.core.dqp_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.dqp_temp_best;
+                for lidx in 0..=if self.pinter.slice_type == SliceType::EVC_ST_P {
+                    REFP_0
+                } else {
+                    REFP_1
+                } {
+                    pidx = pidx_l0 + lidx;
+                    cost = self.analyze_affine_uni(x, y, log2_cuw, log2_cuh, lidx, vertex_num);
+                    cost_inter[pidx] = cost;
+
+                    if cost < cost_best {
+                        self.core.cu_mode = PredMode::MODE_INTER;
+                        best_idx = pidx;
+                        cost_best = cost;
+                        cost_inter[best_idx] = cost;
+
+                        self.core.s_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.s_temp_best;
+                        self.core.c_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.c_temp_best;
+                        self.core.dqp_next_best[log2_cuw - 2][log2_cuh - 2] =
+                            self.core.dqp_temp_best;
+                    }
+                }
+
+                if self.pinter.slice_type == SliceType::EVC_ST_B {
+                    pidx = pidx_bi;
+                    cost = self.analyze_affine_bi(x, y, log2_cuw, log2_cuh, vertex_num);
+                    cost_inter[pidx] = cost;
+
+                    if cost < cost_best {
+                        self.core.cu_mode = PredMode::MODE_INTER;
+                        best_idx = pidx;
+                        cost_best = cost;
+                        cost_inter[best_idx] = cost;
+
+                        self.core.s_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.s_temp_best;
+                        self.core.c_next_best[log2_cuw - 2][log2_cuh - 2] = self.core.c_temp_best;
+                        self.core.dqp_next_best[log2_cuw - 2][log2_cuh - 2] =
+                            self.core.dqp_temp_best;
+                    }
+                }
             }
         }
 
         /* reconstruct */
         for j in 0..N_C {
-            let size_tmp = (cuw * cuh) >> (if j == 0 { 0 } else { 2 });
+            let size_tmp = (cuw * cuh) >> (if j == 0 { 0 } else { w_shift + h_shift });
             self.core.ctmp.data[j][..size_tmp]
                 .copy_from_slice(&self.pinter.coef[best_idx].data[j][..size_tmp]);
             self.pinter.residue.data[j][..size_tmp]
@@ -449,6 +651,11 @@ impl EvceCtx {
             self.pinter.qp_u,
             self.pinter.qp_v,
             &is_coef,
+            self.sps_iqt_flag,
+            false,
+            0,
+            self.pinter.ats_inter_info[best_idx],
+            self.chroma_format_idc,
         );
 
         for i in 0..N_C {
@@ -457,8 +664,8 @@ impl EvceCtx {
                 &self.pinter.residue.data[i],
                 &self.pinter.pred[best_idx][0].data[i],
                 is_coef[i],
-                if i == 0 { cuw } else { cuw >> 1 },
-                if i == 0 { cuh } else { cuh >> 1 },
+                if i == 0 { cuw } else { cuw >> w_shift },
+                if i == 0 { cuh } else { cuh >> h_shift },
                 &mut self.pinter.rec[best_idx].data[i],
                 i,
             );
@@ -479,18 +686,26 @@ impl EvceCtx {
             self.mode.mvd[lidx][MV_X] = self.pinter.mvd[best_idx][lidx][MV_X];
             self.mode.mvd[lidx][MV_Y] = self.pinter.mvd[best_idx][lidx][MV_Y];
         }
+        self.mode.affine_flag = self.pinter.affine_flag[best_idx];
+        self.mode.affine_mv = self.pinter.affine_mv[best_idx];
+        self.mode.affine_mvd = self.pinter.affine_mvd[best_idx];
+        self.mode.mvr_idx = self.pinter.mvr_idx[best_idx];
+        self.mode.mmvd_flag = self.pinter.mmvd_flag[best_idx];
+        self.mode.mmvd_idx = self.pinter.mmvd_idx[best_idx];
+        self.mode.dmvr_flag = self.pinter.dmvr_flag[best_idx];
+        self.mode.ats_inter_info = self.pinter.ats_inter_info[best_idx];
+        self.core.ats_inter_info = self.mode.ats_inter_info;
+        if self.mode.dmvr_flag {
+            self.mode.dmvr_mv.clone_from(&self.pinter.dmvr_mv[best_idx]);
+        }
 
         cost_inter[best_idx as usize]
     }
 
-    fn analyze_skip_baseline(
-        &mut self,
-        x: usize,
-        y: usize,
-        log2_cuw: usize,
-        log2_cuh: usize,
-    ) -> f64 {
-        if self.pps.cu_qp_delta_enabled_flag {
+    fn analyze_skip(&mut self, x: usize, y: usize, log2_cuw: usize, log2_cuh: usize) -> f64 {
+        let w_shift = GET_CHROMA_W_SHIFT(self.chroma_format_idc);
+        let h_shift = GET_CHROMA_H_SHIFT(self.chroma_format_idc);
+        if self.pps_cu_qp_delta_enabled_flag {
             if self.core.cu_qp_delta_code_mode != 2 {
                 self.evce_set_qp(self.core.dqp_curr_best[log2_cuw - 2][log2_cuh - 2].prev_qp);
             }
@@ -499,8 +714,54 @@ impl EvceCtx {
         let cuw = (1 << log2_cuw) as usize;
         let cuh = (1 << log2_cuh) as usize;
 
-        {
-            let map_mv = self.map_mv.as_ref().unwrap().borrow();
+        if self.sps_admvp_flag {
+            let map_mv = self.map_unrefined_mv.as_ref().unwrap().read().unwrap();
+            let map_refi = self.map_refi.as_ref().unwrap().read().unwrap();
+
+            evc_get_motion_merge_main(
+                self.poc_val,
+                self.slice_type,
+                self.core.scup as usize,
+                &*map_refi,
+                &*map_mv,
+                &self.refp,
+                cuw,
+                cuh,
+                self.w_scu as usize,
+                self.h_scu as usize,
+                &mut self.pinter.refi_pred,
+                &mut self.pinter.mvp,
+                &self.map_scu,
+                self.core.avail_lr,
+                &self.core.history_buffer,
+                &self.sh,
+                MAX_CU_LOG2 as u8,
+            );
+
+            if self.sps_mmvd_flag {
+                let map_mv = self.map_mv.as_ref().unwrap().read().unwrap();
+                evc_get_motion_merge_main(
+                    self.poc_val,
+                    self.slice_type,
+                    self.core.scup as usize,
+                    &*map_refi,
+                    &*map_mv,
+                    &self.refp,
+                    cuw,
+                    cuh,
+                    self.w_scu as usize,
+                    self.h_scu as usize,
+                    &mut self.pinter.mmvd_refi_pred,
+                    &mut self.pinter.mmvd_mvp,
+                    &self.map_scu,
+                    self.core.avail_lr,
+                    &self.core.history_buffer,
+                    &self.sh,
+                    MAX_CU_LOG2 as u8,
+                );
+            }
+        } else {
+            let map_mv = self.map_unrefined_mv.as_ref().unwrap().read().unwrap();
 
             evc_get_motion(
                 self.core.scup as usize,
@@ -541,156 +802,727 @@ impl EvceCtx {
         let mut cu = 0;
         let mut cv = 0;
 
-        for idx0 in 0..4 {
-            let cnt = if self.slice_type == SliceType::EVC_ST_B {
-                4
-            } else {
-                1
-            };
-            for idx1 in 0..cnt {
-                if idx0 != idx1 {
-                    continue;
+        /* merge candidates are signalled by a single index for both lists */
+        let num_cand = if !self.sps_admvp_flag {
+            ORG_MAX_NUM_MVP as usize
+        } else if cuw * cuh <= 32 {
+            MAX_NUM_MVP_SMALL_CU
+        } else {
+            MAX_NUM_MVP
+        };
+        /* candidates are the merge candidates followed, with MMVD, by the
+        offset found for each base candidate */
+        let cnt = if self.sps_admvp_flag || self.slice_type == SliceType::EVC_ST_B {
+            num_cand
+        } else {
+            1
+        };
+        let mut cands: Vec<(usize, bool, u16)> =
+            (0..num_cand.min(cnt)).map(|idx| (idx, false, 0)).collect();
+        if self.sps_mmvd_flag {
+            for base in 0..num_cand.min(MMVD_BASE_NUM) {
+                if let Some(mmvd_idx) = self.mmvd_search_base(x, y, log2_cuw, log2_cuh, base) {
+                    cands.push((base, true, mmvd_idx));
                 }
+            }
+        }
 
-                mvp[REFP_0][MV_X] = self.pinter.mvp[REFP_0][idx0][MV_X];
-                mvp[REFP_0][MV_Y] = self.pinter.mvp[REFP_0][idx0][MV_Y];
-                mvp[REFP_1][MV_X] = self.pinter.mvp[REFP_1][idx1][MV_X];
-                mvp[REFP_1][MV_Y] = self.pinter.mvp[REFP_1][idx1][MV_Y];
+        for (idx0, mmvd_flag, mmvd_idx) in cands {
+            let idx1 = idx0;
 
-                refi[REFP_0] = self.pinter.refi_pred[REFP_0][idx0];
-                refi[REFP_1] = if self.sh.slice_type == SliceType::EVC_ST_B {
-                    self.pinter.refi_pred[REFP_1][idx1]
-                } else {
-                    REFI_INVALID
-                };
-                if !REFI_IS_VALID(refi[REFP_0]) && !REFI_IS_VALID(refi[REFP_1]) {
-                    continue;
-                }
+            mvp[REFP_0][MV_X] = self.pinter.mvp[REFP_0][idx0][MV_X];
+            mvp[REFP_0][MV_Y] = self.pinter.mvp[REFP_0][idx0][MV_Y];
+            mvp[REFP_1][MV_X] = self.pinter.mvp[REFP_1][idx1][MV_X];
+            mvp[REFP_1][MV_Y] = self.pinter.mvp[REFP_1][idx1][MV_Y];
 
-                evc_mc(
-                    x as i16,
-                    y as i16,
-                    self.w as i16,
-                    self.h as i16,
-                    cuw as i16,
-                    cuh as i16,
-                    &refi,
-                    &mvp,
+            refi[REFP_0] = self.pinter.refi_pred[REFP_0][idx0];
+            refi[REFP_1] = if self.sh.slice_type == SliceType::EVC_ST_B {
+                self.pinter.refi_pred[REFP_1][idx1]
+            } else {
+                REFI_INVALID
+            };
+            if mmvd_flag {
+                evc_get_mmvd_motion(
+                    self.poc_val,
+                    self.sh.slice_type,
                     &self.refp,
-                    &mut self.pinter.pred[InterPredDir::PRED_NUM as usize],
+                    &self.num_refp,
+                    cuw,
+                    cuh,
+                    &self.pinter.mmvd_refi_pred,
+                    &self.pinter.mmvd_mvp,
+                    mmvd_idx,
+                    &mut refi,
+                    &mut mvp,
                 );
+            }
+            if !REFI_IS_VALID(refi[REFP_0]) && !REFI_IS_VALID(refi[REFP_1]) {
+                continue;
+            }
 
-                if let Some(pic) = &self.pinter.pic_o {
-                    let frame = &pic.borrow().frame;
-                    let planes = &frame.borrow().planes;
-                    cy = evce_ssd_16b(
-                        x,
-                        y,
-                        log2_cuw,
-                        log2_cuh,
-                        &planes[Y_C].as_region(),
-                        &self.pinter.pred[InterPredDir::PRED_NUM as usize][0].data[Y_C],
-                    );
+            self.pinter.dmvr_flag[InterPredDir::PRED_NUM as usize] = evc_mc(
+                x as i16,
+                y as i16,
+                self.w as i16,
+                self.h as i16,
+                cuw as i16,
+                cuh as i16,
+                &refi,
+                &mvp,
+                &self.refp,
+                &mut self.pinter.pred[InterPredDir::PRED_NUM as usize],
+                self.poc_val as u32,
+                self.sps_dmvr_flag && !mmvd_flag,
+                &mut self.pinter.dmvr_mv[InterPredDir::PRED_NUM as usize],
+                self.sps_admvp_flag,
+            );
+
+            if let Some(pic) = &self.pinter.pic_o {
+                let frame = &pic.read().unwrap().frame;
+                let planes = &frame.read().unwrap().planes;
+                cy = evce_ssd_16b(
+                    x,
+                    y,
+                    log2_cuw,
+                    log2_cuh,
+                    &planes[Y_C].as_region(),
+                    &self.pinter.pred[InterPredDir::PRED_NUM as usize][0].data[Y_C],
+                );
+                if self.chroma_format_idc != 0 {
                     cu = evce_ssd_16b(
-                        x >> 1,
-                        y >> 1,
-                        log2_cuw - 1,
-                        log2_cuh - 1,
+                        x >> w_shift,
+                        y >> h_shift,
+                        log2_cuw - w_shift,
+                        log2_cuh - h_shift,
                         &planes[U_C].as_region(),
                         &self.pinter.pred[InterPredDir::PRED_NUM as usize][0].data[U_C],
                     );
                     cv = evce_ssd_16b(
-                        x >> 1,
-                        y >> 1,
-                        log2_cuw - 1,
-                        log2_cuh - 1,
+                        x >> w_shift,
+                        y >> h_shift,
+                        log2_cuw - w_shift,
+                        log2_cuh - h_shift,
                         &planes[V_C].as_region(),
                         &self.pinter.pred[InterPredDir::PRED_NUM as usize][0].data[V_C],
                     );
                 }
+            }
 
-                self.calc_delta_dist_filter_boundary(
-                    x as i16,
-                    y as i16,
-                    log2_cuw,
-                    log2_cuh,
-                    self.core.avail_lr,
-                    false,
-                    false,
-                    InterPredDir::PRED_NUM as usize,
-                    false,
-                    &refi,
-                    &mvp,
-                );
-
-                cy += self.core.delta_dist[Y_C];
-                cu += self.core.delta_dist[U_C];
-                cv += self.core.delta_dist[V_C];
+            self.calc_delta_dist_filter_boundary(
+                x as i16,
+                y as i16,
+                log2_cuw,
+                log2_cuh,
+                self.core.avail_lr,
+                false,
+                false,
+                InterPredDir::PRED_NUM as usize,
+                false,
+                &refi,
+                &mvp,
+            );
 
-                let mut cost = cy as f64
-                    + (self.dist_chroma_weight[0] * cu as f64)
-                    + (self.dist_chroma_weight[1] * cv as f64);
+            cy += self.core.delta_dist[Y_C];
+            cu += self.core.delta_dist[U_C];
+            cv += self.core.delta_dist[V_C];
 
-                self.core.s_temp_run = self.core.s_curr_best[log2_cuw - 2][log2_cuh - 2];
-                self.core.c_temp_run = self.core.c_curr_best[log2_cuw - 2][log2_cuh - 2];
-                self.core.dqp_temp_run = self.core.dqp_curr_best[log2_cuw - 2][log2_cuh - 2];
+            let mut cost = cy as f64
+                + (self.dist_chroma_weight[0] * cu as f64)
+                + (self.dist_chroma_weight[1] * cv as f64);
 
-                self.core.s_temp_run.bit_reset();
-                self.evce_rdo_bit_cnt_cu_skip(self.sh.slice_type, idx0 as u32, idx1 as u32);
+            self.core.s_temp_run = self.core.s_curr_best[log2_cuw - 2][log2_cuh - 2];
+            self.core.c_temp_run = self.core.c_curr_best[log2_cuw - 2][log2_cuh - 2];
+            self.core.dqp_temp_run = self.core.dqp_curr_best[log2_cuw - 2][log2_cuh - 2];
 
-                let bit_cnt = self.core.s_temp_run.get_bit_number();
-                cost += (self.lambda[0] * bit_cnt as f64);
+            self.core.s_temp_run.bit_reset();
+            self.evce_rdo_bit_cnt_cu_skip(
+                self.sh.slice_type,
+                idx0 as u32,
+                idx1 as u32,
+                0,
+                mmvd_flag,
+                mmvd_idx,
+            );
 
-                if cost < cost_best {
-                    cost_best = cost;
-                    self.pinter.mvp_idx[InterPredDir::PRED_SKIP as usize][REFP_0] = idx0 as u8;
-                    self.pinter.mvp_idx[InterPredDir::PRED_SKIP as usize][REFP_1] = idx1 as u8;
-                    self.pinter.mv[InterPredDir::PRED_SKIP as usize][REFP_0][MV_X] =
-                        mvp[REFP_0][MV_X];
-                    self.pinter.mv[InterPredDir::PRED_SKIP as usize][REFP_0][MV_Y] =
-                        mvp[REFP_0][MV_Y];
-                    self.pinter.mv[InterPredDir::PRED_SKIP as usize][REFP_1][MV_X] =
-                        mvp[REFP_1][MV_X];
-                    self.pinter.mv[InterPredDir::PRED_SKIP as usize][REFP_1][MV_Y] =
-                        mvp[REFP_1][MV_Y];
-                    self.pinter.mvd[InterPredDir::PRED_SKIP as usize][REFP_0][MV_X] = 0;
-                    self.pinter.mvd[InterPredDir::PRED_SKIP as usize][REFP_0][MV_Y] = 0;
-                    self.pinter.mvd[InterPredDir::PRED_SKIP as usize][REFP_1][MV_X] = 0;
-                    self.pinter.mvd[InterPredDir::PRED_SKIP as usize][REFP_1][MV_Y] = 0;
-                    self.pinter.refi[InterPredDir::PRED_SKIP as usize][REFP_0] = refi[REFP_0];
-                    self.pinter.refi[InterPredDir::PRED_SKIP as usize][REFP_1] = refi[REFP_1];
+            let bit_cnt = self.core.s_temp_run.get_bit_number();
+            cost += (self.lambda[0] * bit_cnt as f64);
 
-                    self.core.cost_best = if cost < self.core.cost_best {
-                        cost
-                    } else {
-                        self.core.cost_best
-                    };
+            if cost < cost_best {
+                cost_best = cost;
+                self.pinter.mvp_idx[InterPredDir::PRED_SKIP as usize][REFP_0] = idx0 as u8;
+                self.pinter.mvp_idx[InterPredDir::PRED_SKIP as usize][REFP_1] = idx1 as u8;
+                self.pinter.mv[InterPredDir::PRED_SKIP as usize][REFP_0][MV_X] = mvp[REFP_0][MV_X];
+                self.pinter.mv[InterPredDir::PRED_SKIP as usize][REFP_0][MV_Y] = mvp[REFP_0][MV_Y];
+                self.pinter.mv[InterPredDir::PRED_SKIP as usize][REFP_1][MV_X] = mvp[REFP_1][MV_X];
+                self.pinter.mv[InterPredDir::PRED_SKIP as usize][REFP_1][MV_Y] = mvp[REFP_1][MV_Y];
+                self.pinter.mvd[InterPredDir::PRED_SKIP as usize][REFP_0][MV_X] = 0;
+                self.pinter.mvd[InterPredDir::PRED_SKIP as usize][REFP_0][MV_Y] = 0;
+                self.pinter.mvd[InterPredDir::PRED_SKIP as usize][REFP_1][MV_X] = 0;
+                self.pinter.mvd[InterPredDir::PRED_SKIP as usize][REFP_1][MV_Y] = 0;
+                self.pinter.refi[InterPredDir::PRED_SKIP as usize][REFP_0] = refi[REFP_0];
+                self.pinter.refi[InterPredDir::PRED_SKIP as usize][REFP_1] = refi[REFP_1];
+                self.pinter.mmvd_flag[InterPredDir::PRED_SKIP as usize] = mmvd_flag;
+                self.pinter.mmvd_idx[InterPredDir::PRED_SKIP as usize] = mmvd_idx;
 
-                    let (pred_skip, pred_num) = self
-                        .pinter
-                        .pred
-                        .split_at_mut(InterPredDir::PRED_NUM as usize);
-                    for j in 0..N_C {
-                        let size_tmp = (cuw * cuh) >> (if j == 0 { 0 } else { 2 });
-                        pred_skip[InterPredDir::PRED_SKIP as usize][0].data[j][0..size_tmp]
-                            .copy_from_slice(&pred_num[0][0].data[j][0..size_tmp]);
-                    }
+                self.core.cost_best = if cost < self.core.cost_best {
+                    cost
+                } else {
+                    self.core.cost_best
+                };
 
-                    self.core.s_temp_best = self.core.s_temp_run;
-                    self.core.c_temp_best = self.core.c_temp_run;
-                    self.core.dqp_temp_best = self.core.dqp_temp_run;
+                let (pred_skip, pred_num) = self
+                    .pinter
+                    .pred
+                    .split_at_mut(InterPredDir::PRED_NUM as usize);
+                for j in 0..N_C {
+                    let size_tmp = (cuw * cuh) >> (if j == 0 { 0 } else { w_shift + h_shift });
+                    pred_skip[InterPredDir::PRED_SKIP as usize][0].data[j][0..size_tmp]
+                        .copy_from_slice(&pred_num[0][0].data[j][0..size_tmp]);
                 }
+                self.pinter.dmvr_flag[InterPredDir::PRED_SKIP as usize] =
+                    self.pinter.dmvr_flag[InterPredDir::PRED_NUM as usize];
+                if self.pinter.dmvr_flag[InterPredDir::PRED_SKIP as usize] {
+                    let (dmvr_skip, dmvr_num) = self
+                        .pinter
+                        .dmvr_mv
+                        .split_at_mut(InterPredDir::PRED_NUM as usize);
+                    dmvr_skip[InterPredDir::PRED_SKIP as usize].copy_from_slice(&dmvr_num[0]);
+                }
+
+                self.core.s_temp_best = self.core.s_temp_run;
+                self.core.c_temp_best = self.core.c_temp_run;
+                self.core.dqp_temp_best = self.core.dqp_temp_run;
             }
         }
 
         cost_best
     }
 
+    /* merge mode with residual: the best skip candidate is coded with
+    its prediction error */
+    fn analyze_merge(&mut self, x: usize, y: usize, log2_cuw: usize, log2_cuh: usize) -> f64 {
+        let pidx = InterPredDir::PRED_DIR as usize;
+        let skip = InterPredDir::PRED_SKIP as usize;
+
+        self.pinter.mv[pidx] = self.pinter.mv[skip];
+        self.pinter.refi[pidx] = self.pinter.refi[skip];
+        self.pinter.mvp_idx[pidx] = self.pinter.mvp_idx[skip];
+        self.pinter.mvd[pidx] = [[0; MV_D]; REFP_NUM];
+        self.pinter.mmvd_flag[pidx] = self.pinter.mmvd_flag[skip];
+        self.pinter.mmvd_idx[pidx] = self.pinter.mmvd_idx[skip];
+
+        let mvp_idx = self.pinter.mvp_idx[pidx];
+        let cost = self.pinter_residue_rdo(x, y, log2_cuw, log2_cuh, pidx, &mvp_idx, pidx);
+
+        self.pinter.nnz_best[pidx].copy_from_slice(&self.core.nnz);
+
+        /* without a residual the CU is coded as skip */
+        if self.core.nnz.iter().all(|&nnz| nnz == 0) {
+            MAX_COST
+        } else {
+            cost
+        }
+    }
+
+    /* MMVD offset of a base merge candidate: the distance group, the distance
+    and the direction are chosen by the luma SAD of the prediction and the bits
+    of the index */
+    fn mmvd_search_base(
+        &mut self,
+        x: usize,
+        y: usize,
+        log2_cuw: usize,
+        log2_cuh: usize,
+        base: usize,
+    ) -> Option<u16> {
+        let cuw = (1 << log2_cuw) as usize;
+        let cuh = (1 << log2_cuh) as usize;
+        let num_groups = evc_mmvd_num_groups(self.sh.mmvd_group_enable_flag, cuw, cuh);
+
+        let base_refi = [
+            self.pinter.mmvd_refi_pred[REFP_0][base],
+            if self.sh.slice_type == SliceType::EVC_ST_B {
+                self.pinter.mmvd_refi_pred[REFP_1][base]
+            } else {
+                REFI_INVALID
+            },
+        ];
+        if !REFI_IS_VALID(base_refi[REFP_0]) && !REFI_IS_VALID(base_refi[REFP_1]) {
+            return None;
+        }
+        let pic_o = Arc::clone(self.pinter.pic_o.as_ref()?);
+
+        let mut best = None;
+        let mut best_cost = std::u32::MAX;
+        for group_idx in 0..num_groups {
+            for dist_idx in 0..MMVD_DIST_NUM {
+                for dir_idx in 0..MMVD_DIR_NUM {
+                    let mmvd_idx = evc_mmvd_idx(group_idx, base, dist_idx, dir_idx);
+                    let mut refi = base_refi;
+                    let mut mv = [self.pinter.mmvd_mvp[REFP_0][base], [0; MV_D]];
+                    evc_get_mmvd_motion(
+                        self.poc_val,
+                        self.sh.slice_type,
+                        &self.refp,
+                        &self.num_refp,
+                        cuw,
+                        cuh,
+                        &self.pinter.mmvd_refi_pred,
+                        &self.pinter.mmvd_mvp,
+                        mmvd_idx,
+                        &mut refi,
+                        &mut mv,
+                    );
+                    if !REFI_IS_VALID(refi[REFP_0]) && !REFI_IS_VALID(refi[REFP_1]) {
+                        continue;
+                    }
+
+                    evc_mc(
+                        x as i16,
+                        y as i16,
+                        self.w as i16,
+                        self.h as i16,
+                        cuw as i16,
+                        cuh as i16,
+                        &refi,
+                        &mv,
+                        &self.refp,
+                        &mut self.pinter.pred[InterPredDir::PRED_NUM as usize],
+                        self.poc_val as u32,
+                        false,
+                        &mut [],
+                        self.sps_admvp_flag,
+                    );
+
+                    let sad = {
+                        let frame = &pic_o.read().unwrap().frame;
+                        let plane_y = &frame.read().unwrap().planes[Y_C];
+                        evce_sad_16i(
+                            x,
+                            y,
+                            cuw,
+                            cuh,
+                            &plane_y.as_region(),
+                            &self.pinter.pred[InterPredDir::PRED_NUM as usize][0].data[Y_C],
+                        )
+                    };
+                    /* truncated unary group, base and distance, two bins for
+                    direction */
+                    let group_bits = if num_groups > 1 {
+                        (group_idx + 1).min(num_groups - 1)
+                    } else {
+                        0
+                    };
+                    let bits = (group_bits + base + 1 + dist_idx + 1 + 2) as u32;
+                    let cost = sad + MV_COST(self.pinter.lambda_mv, bits);
+                    if cost < best_cost {
+                        best_cost = cost;
+                        best = Some(mmvd_idx);
+                    }
+                }
+            }
+        }
+
+        best
+    }
+
+    /* affine skip: the inherited and constructed affine merge candidates are
+    compared without a residual */
+    fn analyze_affine_skip(&mut self, x: usize, y: usize, log2_cuw: usize, log2_cuh: usize) -> f64 {
+        let w_shift = GET_CHROMA_W_SHIFT(self.chroma_format_idc);
+        let h_shift = GET_CHROMA_H_SHIFT(self.chroma_format_idc);
+        if self.pps_cu_qp_delta_enabled_flag {
+            if self.core.cu_qp_delta_code_mode != 2 {
+                self.evce_set_qp(self.core.dqp_curr_best[log2_cuw - 2][log2_cuh - 2].prev_qp);
+            }
+        }
+
+        let pidx = InterPredDir::AFF_SKIP as usize;
+        let cuw = (1 << log2_cuw) as usize;
+        let cuh = (1 << log2_cuh) as usize;
+        let mut mrg_refi = [[REFI_INVALID; REFP_NUM]; AFF_MAX_CAND];
+        let mut mrg_mv = [[[[0i16; MV_D]; VER_NUM]; REFP_NUM]; AFF_MAX_CAND];
+
+        let cp_num = {
+            let map_mv = self.map_unrefined_mv.as_ref().unwrap().read().unwrap();
+            let map_refi = self.map_refi.as_ref().unwrap().read().unwrap();
+
+            evc_get_affine_merge_candidate(
+                self.poc_val,
+                self.slice_type,
+                self.core.scup as usize,
+                &*map_refi,
+                &*map_mv,
+                &self.refp,
+                cuw,
+                cuh,
+                self.w_scu as usize,
+                self.h_scu as usize,
+                self.core.avail_lr,
+                &mut mrg_refi,
+                &mut mrg_mv,
+                &self.map_scu,
+                &self.map_affine,
+                MAX_CU_LOG2 as u8,
+                &self.sh,
+            )
+        };
+
+        let mut cost_best = MAX_COST;
+        let mut cy = 0;
+        let mut cu = 0;
+        let mut cv = 0;
+
+        for idx in 0..AFF_MAX_CAND {
+            let refi = mrg_refi[idx];
+            let vertex_num = cp_num[idx];
+            if !REFI_IS_VALID(refi[REFP_0]) && !REFI_IS_VALID(refi[REFP_1]) {
+                continue;
+            }
+
+            evc_affine_mc(
+                x as i16,
+                y as i16,
+                self.w as i16,
+                self.h as i16,
+                cuw as i16,
+                cuh as i16,
+                &refi,
+                &mrg_mv[idx],
+                &self.refp,
+                &mut self.pinter.pred[InterPredDir::PRED_NUM as usize],
+                vertex_num,
+            );
+
+            if let Some(pic) = &self.pinter.pic_o {
+                let frame = &pic.read().unwrap().frame;
+                let planes = &frame.read().unwrap().planes;
+                cy = evce_ssd_16b(
+                    x,
+                    y,
+                    log2_cuw,
+                    log2_cuh,
+                    &planes[Y_C].as_region(),
+                    &self.pinter.pred[InterPredDir::PRED_NUM as usize][0].data[Y_C],
+                );
+                if self.chroma_format_idc != 0 {
+                    cu = evce_ssd_16b(
+                        x >> w_shift,
+                        y >> h_shift,
+                        log2_cuw - w_shift,
+                        log2_cuh - h_shift,
+                        &planes[U_C].as_region(),
+                        &self.pinter.pred[InterPredDir::PRED_NUM as usize][0].data[U_C],
+                    );
+                    cv = evce_ssd_16b(
+                        x >> w_shift,
+                        y >> h_shift,
+                        log2_cuw - w_shift,
+                        log2_cuh - h_shift,
+                        &planes[V_C].as_region(),
+                        &self.pinter.pred[InterPredDir::PRED_NUM as usize][0].data[V_C],
+                    );
+                }
+            }
+
+            /* the motion of the first control point stands for the CU */
+            let mv = [mrg_mv[idx][REFP_0][0], mrg_mv[idx][REFP_1][0]];
+            self.calc_delta_dist_filter_boundary(
+                x as i16,
+                y as i16,
+                log2_cuw,
+                log2_cuh,
+                self.core.avail_lr,
+                false,
+                false,
+                InterPredDir::PRED_NUM as usize,
+                false,
+                &refi,
+                &mv,
+            );
+
+            cy += self.core.delta_dist[Y_C];
+            cu += self.core.delta_dist[U_C];
+            cv += self.core.delta_dist[V_C];
+
+            let mut cost = cy as f64
+                + (self.dist_chroma_weight[0] * cu as f64)
+                + (self.dist_chroma_weight[1] * cv as f64);
+
+            self.core.s_temp_run = self.core.s_curr_best[log2_cuw - 2][log2_cuh - 2];
+            self.core.c_temp_run = self.core.c_curr_best[log2_cuw - 2][log2_cuh - 2];
+            self.core.dqp_temp_run = self.core.dqp_curr_best[log2_cuw - 2][log2_cuh - 2];
+
+            self.core.s_temp_run.bit_reset();
+            self.evce_rdo_bit_cnt_cu_skip(
+                self.sh.slice_type,
+                idx as u32,
+                idx as u32,
+                vertex_num as u8 - 1,
+                false,
+                0,
+            );
+
+            let bit_cnt = self.core.s_temp_run.get_bit_number();
+            cost += (self.lambda[0] * bit_cnt as f64);
+
+            if cost < cost_best {
+                cost_best = cost;
+                self.pinter.affine_flag[pidx] = vertex_num as u8 - 1;
+                self.pinter.mvp_idx[pidx] = [idx as u8; REFP_NUM];
+                self.pinter.refi[pidx] = refi;
+                self.pinter.affine_mv[pidx] = mrg_mv[idx];
+                self.pinter.affine_mvd[pidx] = [[[0; MV_D]; VER_NUM]; REFP_NUM];
+                self.pinter.mv[pidx] = mv;
+                self.pinter.mvd[pidx] = [[0; MV_D]; REFP_NUM];
+
+                self.core.cost_best = if cost < self.core.cost_best {
+                    cost
+                } else {
+                    self.core.cost_best
+                };
+
+                let (pred_skip, pred_num) = self
+                    .pinter
+                    .pred
+                    .split_at_mut(InterPredDir::PRED_NUM as usize);
+                for j in 0..N_C {
+                    let size_tmp = (cuw * cuh) >> (if j == 0 { 0 } else { w_shift + h_shift });
+                    pred_skip[pidx][0].data[j][0..size_tmp]
+                        .copy_from_slice(&pred_num[0][0].data[j][0..size_tmp]);
+                }
+
+                self.core.s_temp_best = self.core.s_temp_run;
+                self.core.c_temp_best = self.core.c_temp_run;
+                self.core.dqp_temp_best = self.core.dqp_temp_run;
+            }
+        }
+
+        cost_best
+    }
+
+    /* affine merge with residual: the best affine skip candidate is coded
+    with its prediction error */
+    fn analyze_affine_merge(
+        &mut self,
+        x: usize,
+        y: usize,
+        log2_cuw: usize,
+        log2_cuh: usize,
+    ) -> f64 {
+        let pidx = InterPredDir::AFF_DIR as usize;
+        let skip = InterPredDir::AFF_SKIP as usize;
+
+        self.pinter.affine_flag[pidx] = self.pinter.affine_flag[skip];
+        self.pinter.affine_mv[pidx] = self.pinter.affine_mv[skip];
+        self.pinter.affine_mvd[pidx] = [[[0; MV_D]; VER_NUM]; REFP_NUM];
+        self.pinter.mv[pidx] = self.pinter.mv[skip];
+        self.pinter.refi[pidx] = self.pinter.refi[skip];
+        self.pinter.mvp_idx[pidx] = self.pinter.mvp_idx[skip];
+        self.pinter.mvd[pidx] = [[0; MV_D]; REFP_NUM];
+
+        let mvp_idx = self.pinter.mvp_idx[pidx];
+        let cost = self.pinter_residue_rdo(x, y, log2_cuw, log2_cuh, pidx, &mvp_idx, pidx);
+
+        self.pinter.nnz_best[pidx].copy_from_slice(&self.core.nnz);
+
+        /* without a residual the CU is coded as skip */
+        if self.core.nnz.iter().all(|&nnz| nnz == 0) {
+            MAX_COST
+        } else {
+            cost
+        }
+    }
+
+    /* affine AMVP of one list: the control points are searched for every
+    reference index and the best one is kept */
+    fn analyze_affine_uni(
+        &mut self,
+        x: usize,
+        y: usize,
+        log2_cuw: usize,
+        log2_cuh: usize,
+        lidx: usize,
+        vertex_num: usize,
+    ) -> f64 {
+        let pidx = if vertex_num == 2 {
+            InterPredDir::AFF_L0 as usize
+        } else {
+            InterPredDir::AFF_6_L0 as usize
+        } + lidx;
+        let cuw = (1 << log2_cuw) as usize;
+        let cuh = (1 << log2_cuh) as usize;
+        let mut best_mecost = std::u32::MAX;
+        let mut refi_best = 0;
+        let mut mv_best = [[0i16; MV_D]; VER_NUM];
+
+        self.pinter.num_refp = self.num_refp[lidx];
+
+        for refi_cur in 0..self.pinter.num_refp as usize {
+            {
+                let map_mv = self.map_unrefined_mv.as_ref().unwrap().read().unwrap();
+                let map_refi = self.map_refi.as_ref().unwrap().read().unwrap();
+                evc_get_affine_motion_scaling(
+                    self.core.scup as usize,
+                    lidx,
+                    refi_cur as i8,
+                    &*map_mv,
+                    &*map_refi,
+                    cuw,
+                    cuh,
+                    self.w_scu as usize,
+                    self.h_scu as usize,
+                    vertex_num,
+                    &mut self.pinter.affine_mvp_scale[lidx][refi_cur],
+                    &self.map_scu,
+                    &self.map_affine,
+                    MAX_CU_LOG2 as u8,
+                );
+            }
+            let mvp = self.pinter.affine_mvp_scale[lidx][refi_cur];
+
+            /* starting points: the predictors, the translational motion and
+            for the 6-parameter model the 4-parameter result */
+            let mut mv_start = vec![
+                mvp[0],
+                mvp[1],
+                [self.pinter.mv_scale[lidx][refi_cur]; VER_NUM],
+            ];
+            if vertex_num == 3 {
+                let mut mv = self.pinter.affine_mv_scale[lidx][refi_cur];
+                let dx = mv[1][MV_X] as i32 - mv[0][MV_X] as i32;
+                let dy = mv[1][MV_Y] as i32 - mv[0][MV_Y] as i32;
+                mv[2][MV_X] = (mv[0][MV_X] as i32 - dy * cuh as i32 / cuw as i32) as i16;
+                mv[2][MV_Y] = (mv[0][MV_Y] as i32 + dx * cuh as i32 / cuw as i32) as i16;
+                mv_start.push(mv);
+            }
+
+            let mut mv = mv_start[0];
+            let mut mecost;
+            let mut cost_start = std::u32::MAX;
+            for mvt in &mv_start {
+                let cost = self.pinter.affine_me_cost(
+                    x as i16,
+                    y as i16,
+                    cuw,
+                    cuh,
+                    refi_cur as i8,
+                    lidx,
+                    &mvp[0],
+                    mvt,
+                    vertex_num,
+                    &self.refp,
+                );
+                if cost < cost_start {
+                    cost_start = cost;
+                    mv = *mvt;
+                }
+            }
+
+            mecost = self.pinter.pinter_affine_me_gradient(
+                x as i16,
+                y as i16,
+                log2_cuw,
+                log2_cuh,
+                refi_cur as i8,
+                lidx,
+                &mvp[0],
+                &mut mv,
+                vertex_num,
+                &self.refp,
+            );
+
+            if vertex_num == 2 {
+                self.pinter.affine_mv_scale[lidx][refi_cur] = mv;
+            }
+            if mecost < best_mecost {
+                best_mecost = mecost;
+                refi_best = refi_cur;
+                mv_best = mv;
+            }
+        }
+
+        /* predictor with the least mvd bits */
+        let mvp = self.pinter.affine_mvp_scale[lidx][refi_best];
+        let mut mvp_idx = 0;
+        let mut bits_best = std::u32::MAX;
+        for idx in 0..AFF_MAX_NUM_MVP {
+            let bits = get_affine_mv_bits(
+                &mv_best,
+                &mvp[idx],
+                vertex_num,
+                self.pinter.num_refp,
+                refi_best as i8,
+            );
+            if bits < bits_best {
+                bits_best = bits;
+                mvp_idx = idx;
+            }
+        }
+
+        self.pinter.affine_flag[pidx] = vertex_num as u8 - 1;
+        self.pinter.refi[pidx] = [REFI_INVALID; REFP_NUM];
+        self.pinter.refi[pidx][lidx] = refi_best as i8;
+        self.pinter.mvp_idx[pidx] = [0; REFP_NUM];
+        self.pinter.mvp_idx[pidx][lidx] = mvp_idx as u8;
+        self.pinter.affine_mv[pidx] = [[[0; MV_D]; VER_NUM]; REFP_NUM];
+        self.pinter.affine_mv[pidx][lidx] = mv_best;
+        self.pinter.affine_mvd[pidx] = [[[0; MV_D]; VER_NUM]; REFP_NUM];
+        self.pinter.affine_mvd[pidx][lidx] = get_affine_mvd(&mv_best, &mvp[mvp_idx], vertex_num);
+        self.pinter.mv[pidx] = [[0; MV_D]; REFP_NUM];
+        self.pinter.mv[pidx][lidx] = mv_best[0];
+        self.pinter.mvd[pidx] = [[0; MV_D]; REFP_NUM];
+
+        let mvp_idx = self.pinter.mvp_idx[pidx];
+        let cost = self.pinter_residue_rdo(x, y, log2_cuw, log2_cuh, pidx, &mvp_idx, pidx);
+
+        self.pinter.nnz_best[pidx].copy_from_slice(&self.core.nnz);
+
+        cost
+    }
+
+    /* affine bi-prediction from the best control points of both lists */
+    fn analyze_affine_bi(
+        &mut self,
+        x: usize,
+        y: usize,
+        log2_cuw: usize,
+        log2_cuh: usize,
+        vertex_num: usize,
+    ) -> f64 {
+        let (pidx, pidx_l0) = if vertex_num == 2 {
+            (InterPredDir::AFF_BI as usize, InterPredDir::AFF_L0 as usize)
+        } else {
+            (
+                InterPredDir::AFF_6_BI as usize,
+                InterPredDir::AFF_6_L0 as usize,
+            )
+        };
+
+        self.pinter.affine_flag[pidx] = vertex_num as u8 - 1;
+        for lidx in 0..REFP_NUM {
+            let pidx_uni = pidx_l0 + lidx;
+            self.pinter.refi[pidx][lidx] = self.pinter.refi[pidx_uni][lidx];
+            self.pinter.mvp_idx[pidx][lidx] = self.pinter.mvp_idx[pidx_uni][lidx];
+            self.pinter.affine_mv[pidx][lidx] = self.pinter.affine_mv[pidx_uni][lidx];
+            self.pinter.affine_mvd[pidx][lidx] = self.pinter.affine_mvd[pidx_uni][lidx];
+            self.pinter.mv[pidx][lidx] = self.pinter.mv[pidx_uni][lidx];
+            self.pinter.mvd[pidx][lidx] = [0; MV_D];
+        }
+
+        let mvp_idx = self.pinter.mvp_idx[pidx];
+        let cost = self.pinter_residue_rdo(x, y, log2_cuw, log2_cuh, pidx, &mvp_idx, pidx);
+
+        self.pinter.nnz_best[pidx].copy_from_slice(&self.core.nnz);
+
+        cost
+    }
+
     fn analyze_t_direct(&mut self, x: usize, y: usize, log2_cuw: usize, log2_cuh: usize) -> f64 {
         let pidx = InterPredDir::PRED_DIR as usize;
         evc_get_mv_dir(
             &self.refp[0],
-            self.poc.poc_val,
+            self.poc_val,
             self.core.scup as usize
                 + ((1 << (self.core.log2_cuw as usize - MIN_CU_LOG2)) - 1)
                 + ((1 << (self.core.log2_cuh as usize - MIN_CU_LOG2)) - 1) * self.w_scu as usize,
@@ -768,6 +1600,9 @@ impl EvceCtx {
             self.pinter.mv[pidx][lidx_ref][MV_Y] = self.pinter.mv[pidx_ref][lidx_ref][MV_Y];
             self.pinter.mv[pidx][lidx_cnd][MV_X] = self.pinter.mv[pidx_cnd][lidx_cnd][MV_X];
             self.pinter.mv[pidx][lidx_cnd][MV_Y] = self.pinter.mv[pidx_cnd][lidx_cnd][MV_Y];
+            /* the uni-prediction may be kept at another resolution */
+            evc_round_mv_to_mvr(&mut self.pinter.mv[pidx][REFP_0], self.pinter.me_mvr_idx);
+            evc_round_mv_to_mvr(&mut self.pinter.mv[pidx][REFP_1], self.pinter.me_mvr_idx);
         }
 
         /* get MVP lidx_cnd */
@@ -796,16 +1631,20 @@ impl EvceCtx {
                 &refi,
                 &self.pinter.mv[pidx],
                 &self.refp,
-                &mut self.pinter.pred[pidx],
+                &mut self.pinter.pred[InterPredDir::PRED_NUM as usize],
+                self.poc_val as u32,
+                false,
+                &mut [],
+                self.sps_admvp_flag,
             );
 
             if let Some(pic) = &self.pinter.pic_o {
-                let frame = &pic.borrow().frame;
-                let plane_y = &frame.borrow().planes[Y_C];
+                let frame = &pic.read().unwrap().frame;
+                let plane_y = &frame.read().unwrap().planes[Y_C];
                 get_org_bi(
                     &mut self.pinter.org_bi.data[Y_C],
                     &plane_y.as_region(),
-                    &self.pinter.pred[pidx][0].data[Y_C],
+                    &self.pinter.pred[InterPredDir::PRED_NUM as usize][0].data[Y_C],
                     x,
                     y,
                     cuw,
@@ -832,7 +1671,11 @@ impl EvceCtx {
                 refi[lidx_ref] = refi_cur as i8;
 
                 // save to temp mvp and mv
-                let mvp = self.pinter.mvp[lidx_ref][mvp_idx as usize];
+                let mvp = if self.sps_admvp_flag {
+                    self.pinter.mvp_scale[lidx_ref][refi_cur][mvp_idx as usize]
+                } else {
+                    self.pinter.mvp[lidx_ref][mvp_idx as usize]
+                };
                 let mut mv = self.pinter.mv_scale[lidx_ref][refi_cur];
                 mecost = self.pinter.pinter_me_epzs(
                     &mut self.core.bs_temp.tracer,
@@ -908,13 +1751,62 @@ impl EvceCtx {
                 [self.pinter.mvp_idx[pidx][REFP_1] as usize][MV_Y];
 
         let mvp_idx = self.pinter.mvp_idx[pidx];
-        cost = self.pinter_residue_rdo(x, y, log2_cuw, log2_cuh, pidx, &mvp_idx, pidx);
-
-        self.pinter.nnz_best[pidx].copy_from_slice(&self.core.nnz);
+        self.pinter.mvr_idx[pidx] = self.pinter.me_mvr_idx;
+        cost = self.pinter_residue_rdo(
+            x,
+            y,
+            log2_cuw,
+            log2_cuh,
+            pidx,
+            &mvp_idx,
+            InterPredDir::PRED_NUM as usize,
+        );
 
         cost
     }
 
+    /* motion of a prediction, saved while trying another mv resolution */
+    fn save_inter_motion(&self, pidx: usize) -> InterMotion {
+        InterMotion {
+            refi: self.pinter.refi[pidx],
+            mv: self.pinter.mv[pidx],
+            mvd: self.pinter.mvd[pidx],
+            mvp_idx: self.pinter.mvp_idx[pidx],
+            mvr_idx: self.pinter.mvr_idx[pidx],
+        }
+    }
+
+    fn load_inter_motion(&mut self, pidx: usize, motion: InterMotion) {
+        self.pinter.refi[pidx] = motion.refi;
+        self.pinter.mv[pidx] = motion.mv;
+        self.pinter.mvd[pidx] = motion.mvd;
+        self.pinter.mvp_idx[pidx] = motion.mvp_idx;
+        self.pinter.mvr_idx[pidx] = motion.mvr_idx;
+    }
+
+    /* keep the prediction and the coefficients of the last RDO as those of pidx */
+    fn copy_inter_pred_coef(&mut self, pidx: usize, cuw: usize, cuh: usize) {
+        let w_shift = GET_CHROMA_W_SHIFT(self.chroma_format_idc);
+        let h_shift = GET_CHROMA_H_SHIFT(self.chroma_format_idc);
+        let (pred_pidx, pred_num) = self
+            .pinter
+            .pred
+            .split_at_mut(InterPredDir::PRED_NUM as usize);
+        let (coef_pidx, coef_num) = self
+            .pinter
+            .coef
+            .split_at_mut(InterPredDir::PRED_NUM as usize);
+        for j in 0..N_C {
+            let size_tmp = (cuw * cuh) >> (if j == 0 { 0 } else { w_shift + h_shift });
+            self.pinter.nnz_best[pidx][j] = self.core.nnz[j];
+            pred_pidx[pidx][0].data[j][..size_tmp]
+                .copy_from_slice(&pred_num[0][0].data[j][..size_tmp]);
+            coef_pidx[pidx].data[j][..size_tmp].copy_from_slice(&coef_num[0].data[j][..size_tmp]);
+        }
+        self.pinter.ats_inter_info[pidx] =
+            self.pinter.ats_inter_info[InterPredDir::PRED_NUM as usize];
+    }
+
     fn pinter_residue_rdo(
         &mut self,
         x: usize,
@@ -925,53 +1817,71 @@ impl EvceCtx {
         mvp_idx: &[u8],
         pred_coef_idx: usize,
     ) -> f64 {
+        let w_shift = GET_CHROMA_W_SHIFT(self.chroma_format_idc);
+        let h_shift = GET_CHROMA_H_SHIFT(self.chroma_format_idc);
         //let pred = &self.pinter.pred[pidx/PRED_NUM];
         //let coef = &self.pinter.coef[pidx/PRED_NUM];
 
-        let mut coef_t: CUBuffer<i16> = CUBuffer::default();
-        let mut cbf_idx = [0; N_C];
-        let mut nnz_store = [0; N_C];
-
-        let mut idx_y = 0;
-        let mut idx_u = 0;
-        let mut idx_v = 0;
-
-        let mut dist = [[0i64; N_C]; 2];
         let mut dist_no_resi = [0i64; N_C];
-        let mut idx_best = [0; N_C];
 
-        let cuw = 1 << log2_cuw;
-        let cuh = 1 << log2_cuh;
-        let x0 = [x, x >> 1, x >> 1];
-        let y0 = [y, y >> 1, y >> 1];
-        let w = [1 << log2_cuw, 1 << (log2_cuw - 1), 1 << (log2_cuw - 1)];
-        let h = [1 << log2_cuh, 1 << (log2_cuh - 1), 1 << (log2_cuh - 1)];
-        let log2_w = [log2_cuw, log2_cuw - 1, log2_cuw - 1];
-        let log2_h = [log2_cuh, log2_cuh - 1, log2_cuh - 1];
-
-        let mut cost = MAX_COST;
-        let mut cost_best = MAX_COST;
-        let mut cost_comp_best = MAX_COST;
+        let x0 = [x, x >> w_shift, x >> w_shift];
+        let y0 = [y, y >> h_shift, y >> h_shift];
+        let w = [
+            1 << log2_cuw,
+            1 << (log2_cuw - w_shift),
+            1 << (log2_cuw - w_shift),
+        ];
+        let h = [
+            1 << log2_cuh,
+            1 << (log2_cuh - h_shift),
+            1 << (log2_cuh - h_shift),
+        ];
+        let log2_w = [log2_cuw, log2_cuw - w_shift, log2_cuw - w_shift];
+        let log2_h = [log2_cuh, log2_cuh - h_shift, log2_cuh - h_shift];
 
         /* prediction */
-        evc_mc(
-            x as i16,
-            y as i16,
-            self.w as i16,
-            self.h as i16,
-            w[0] as i16,
-            h[0] as i16,
-            &self.pinter.refi[pidx],
-            &self.pinter.mv[pidx],
-            &self.refp,
-            &mut self.pinter.pred[pred_coef_idx],
-        );
+        if self.pinter.affine_flag[pidx] != 0 {
+            self.pinter.dmvr_flag[pidx] = false;
+            evc_affine_mc(
+                x as i16,
+                y as i16,
+                self.w as i16,
+                self.h as i16,
+                w[0] as i16,
+                h[0] as i16,
+                &self.pinter.refi[pidx],
+                &self.pinter.affine_mv[pidx],
+                &self.refp,
+                &mut self.pinter.pred[pred_coef_idx],
+                self.pinter.affine_flag[pidx] as usize + 1,
+            );
+        } else {
+            /* merge CUs are refined on the decoder side */
+            self.pinter.dmvr_flag[pidx] = evc_mc(
+                x as i16,
+                y as i16,
+                self.w as i16,
+                self.h as i16,
+                w[0] as i16,
+                h[0] as i16,
+                &self.pinter.refi[pidx],
+                &self.pinter.mv[pidx],
+                &self.refp,
+                &mut self.pinter.pred[pred_coef_idx],
+                self.poc_val as u32,
+                self.sps_dmvr_flag
+                    && pidx == InterPredDir::PRED_DIR as usize
+                    && !self.pinter.mmvd_flag[pidx],
+                &mut self.pinter.dmvr_mv[pidx],
+                self.sps_admvp_flag,
+            );
+        }
 
         /* get residual */
 
         if let Some(pic) = &self.pinter.pic_o {
-            let frame = &pic.borrow().frame;
-            let planes = &frame.borrow().planes;
+            let frame = &pic.read().unwrap().frame;
+            let planes = &frame.read().unwrap().planes;
 
             evce_diff_pred(
                 x,
@@ -981,10 +1891,13 @@ impl EvceCtx {
                 planes,
                 &self.pinter.pred[pred_coef_idx][0],
                 &mut self.pinter.resi,
+                self.chroma_format_idc,
             );
 
-            for i in 0..N_C {
-                dist[0][i] = evce_ssd_16b(
+            /* monochrome pictures leave the chroma distortion at zero */
+            let num_comp = if self.chroma_format_idc == 0 { 1 } else { N_C };
+            for i in 0..num_comp {
+                dist_no_resi[i] = evce_ssd_16b(
                     x0[i],
                     y0[i],
                     log2_w[i],
@@ -992,18 +1905,137 @@ impl EvceCtx {
                     &planes[i].as_region(),
                     &self.pinter.pred[pred_coef_idx][0].data[i],
                 );
-                dist_no_resi[i] = dist[0][i];
             }
         }
 
+        let mut cost_best = self.pinter_residue_tu_rdo(
+            x,
+            y,
+            log2_cuw,
+            log2_cuh,
+            pidx,
+            mvp_idx,
+            pred_coef_idx,
+            &dist_no_resi,
+            0,
+        );
+        let mut ats_inter_info_best = 0;
+
+        /* sub-block transforms of the residual are tried after the full one */
+        if self.sps_ats_flag && self.core.nnz.iter().any(|&nnz| nnz != 0) {
+            let mut best = InterResidue {
+                coef: self.pinter.coef[pred_coef_idx].clone(),
+                rec: self.pinter.rec[pidx].clone(),
+                nnz: [self.core.nnz[Y_C], self.core.nnz[U_C], self.core.nnz[V_C]],
+                s_temp_best: self.core.s_temp_best,
+                c_temp_best: self.core.c_temp_best,
+                dqp_temp_best: self.core.dqp_temp_best,
+            };
+
+            for idx in 1..NUM_ATS_INTER_IDX {
+                if !evc_check_ats_inter_idx(idx, log2_cuw as u8, log2_cuh as u8) {
+                    continue;
+                }
+                for pos in 0..2 {
+                    let ats_inter_info = idx | (pos << 4);
+                    let cost = self.pinter_residue_tu_rdo(
+                        x,
+                        y,
+                        log2_cuw,
+                        log2_cuh,
+                        pidx,
+                        mvp_idx,
+                        pred_coef_idx,
+                        &dist_no_resi,
+                        ats_inter_info,
+                    );
+
+                    /* without a residual the sub-block transform is not signalled */
+                    if cost < cost_best && self.core.nnz.iter().any(|&nnz| nnz != 0) {
+                        cost_best = cost;
+                        ats_inter_info_best = ats_inter_info;
+                        best.coef.clone_from(&self.pinter.coef[pred_coef_idx]);
+                        best.rec.clone_from(&self.pinter.rec[pidx]);
+                        best.nnz = [self.core.nnz[Y_C], self.core.nnz[U_C], self.core.nnz[V_C]];
+                        best.s_temp_best = self.core.s_temp_best;
+                        best.c_temp_best = self.core.c_temp_best;
+                        best.dqp_temp_best = self.core.dqp_temp_best;
+                    } else {
+                        self.pinter.coef[pred_coef_idx].clone_from(&best.coef);
+                        self.pinter.rec[pidx].clone_from(&best.rec);
+                        self.core.nnz[..N_C].copy_from_slice(&best.nnz);
+                        self.core.s_temp_best = best.s_temp_best;
+                        self.core.c_temp_best = best.c_temp_best;
+                        self.core.dqp_temp_best = best.dqp_temp_best;
+                    }
+                }
+            }
+        }
+
+        self.core.ats_inter_info = ats_inter_info_best;
+        self.pinter.ats_inter_info[pred_coef_idx] = ats_inter_info_best;
+
+        cost_best
+    }
+
+    fn pinter_residue_tu_rdo(
+        &mut self,
+        x: usize,
+        y: usize,
+        log2_cuw: usize,
+        log2_cuh: usize,
+        pidx: usize,
+        mvp_idx: &[u8],
+        pred_coef_idx: usize,
+        dist_no_resi: &[i64; N_C],
+        ats_inter_info: u8,
+    ) -> f64 {
+        let w_shift = GET_CHROMA_W_SHIFT(self.chroma_format_idc);
+        let h_shift = GET_CHROMA_H_SHIFT(self.chroma_format_idc);
+        let mut coef_t: CUBuffer<i16> = CUBuffer::default();
+        let mut cbf_idx = [0; N_C];
+        let mut nnz_store = [0; N_C];
+
+        let mut idx_y = 0;
+        let mut idx_u = 0;
+        let mut idx_v = 0;
+
+        let mut dist = [[0i64; N_C]; 2];
+        let mut idx_best = [0; N_C];
+
+        let cuw = 1 << log2_cuw;
+        let cuh = 1 << log2_cuh;
+        let x0 = [x, x >> w_shift, x >> w_shift];
+        let y0 = [y, y >> h_shift, y >> h_shift];
+        let w = [
+            1 << log2_cuw,
+            1 << (log2_cuw - w_shift),
+            1 << (log2_cuw - w_shift),
+        ];
+        let h = [
+            1 << log2_cuh,
+            1 << (log2_cuh - h_shift),
+            1 << (log2_cuh - h_shift),
+        ];
+        let log2_w = [log2_cuw, log2_cuw - w_shift, log2_cuw - w_shift];
+        let log2_h = [log2_cuh, log2_cuh - h_shift, log2_cuh - h_shift];
+
+        let mut cost;
+        let mut cost_best = MAX_COST;
+        let mut cost_comp_best;
+
+        dist[0] = *dist_no_resi;
+        self.core.ats_inter_info = ats_inter_info;
+
         //prepare tu residual
         copy_tu_from_cu(
             &mut self.pinter.coef[pred_coef_idx],
             &self.pinter.resi,
             log2_cuw,
             log2_cuh,
+            self.chroma_format_idc,
         );
-        if self.pps.cu_qp_delta_enabled_flag {
+        if self.pps_cu_qp_delta_enabled_flag {
             self.evce_set_qp(self.core.dqp_curr_best[log2_cuw - 2][log2_cuh - 2].curr_qp);
         }
 
@@ -1021,13 +2053,24 @@ impl EvceCtx {
             self.lambda[0],
             self.lambda[1],
             self.lambda[2],
-            TQC_RUN::RUN_L as u8 | TQC_RUN::RUN_CB as u8 | TQC_RUN::RUN_CR as u8,
+            if self.chroma_format_idc == 0 {
+                TQC_RUN::RUN_L as u8
+            } else {
+                TQC_RUN::RUN_L as u8 | TQC_RUN::RUN_CB as u8 | TQC_RUN::RUN_CR as u8
+            },
             &self.core.rdoq_est,
+            self.sps_iqt_flag,
+            self.sps_cm_init_flag,
+            self.sps_adcc_flag,
+            false,
+            0,
+            ats_inter_info,
+            self.chroma_format_idc,
         );
 
         if tnnz != 0 {
             for i in 0..N_C {
-                let size = (cuw * cuh) >> if i == 0 { 0 } else { 2 };
+                let size = (cuw * cuh) >> if i == 0 { 0 } else { w_shift + h_shift };
                 coef_t.data[i][0..size]
                     .copy_from_slice(&self.pinter.coef[pred_coef_idx].data[i][0..size]);
                 cbf_idx[i] = 0;
@@ -1048,6 +2091,11 @@ impl EvceCtx {
                 self.pinter.qp_u,
                 self.pinter.qp_v,
                 &is_coef,
+                self.sps_iqt_flag,
+                false,
+                0,
+                ats_inter_info,
+                self.chroma_format_idc,
             );
 
             {
@@ -1069,8 +2117,8 @@ impl EvceCtx {
             }
 
             if let Some(pic) = &self.pinter.pic_o {
-                let frame = &pic.borrow().frame;
-                let planes = &frame.borrow().planes;
+                let frame = &pic.read().unwrap().frame;
+                let planes = &frame.read().unwrap().planes;
                 for i in 0..N_C {
                     if self.core.nnz[i] > 0 {
                         evc_recon(
@@ -1138,7 +2186,7 @@ impl EvceCtx {
                 dist[1][i] += self.core.delta_dist[i];
             }
 
-            if pidx != InterPredDir::PRED_DIR as usize {
+            if pidx != InterPredDir::PRED_DIR as usize && pidx != InterPredDir::AFF_DIR as usize {
                 /* test all zero case */
                 idx_y = 0;
                 idx_u = 0;
@@ -1297,14 +2345,14 @@ impl EvceCtx {
             for i in 0..N_C {
                 self.core.nnz[i] = if cbf_idx[i] != 0 { nnz_store[i] } else { 0 };
                 if self.core.nnz[i] == 0 && nnz_store[i] != 0 {
-                    let size = (cuw * cuh) >> (if i == 0 { 0 } else { 2 });
+                    let size = (cuw * cuh) >> (if i == 0 { 0 } else { w_shift + h_shift });
                     for v in &mut self.pinter.coef[pred_coef_idx].data[i][..size] {
                         *v = 0;
                     }
                 }
             }
         } else {
-            if self.pps.cu_qp_delta_enabled_flag {
+            if self.pps_cu_qp_delta_enabled_flag {
                 if self.core.cu_qp_delta_code_mode != 2 {
                     self.evce_set_qp(self.core.dqp_curr_best[log2_cuw - 2][log2_cuh - 2].prev_qp);
                 }
@@ -12,8 +12,7 @@ use crate::plane::*;
 use crate::recon::*;
 use crate::region::*;
 
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::{Arc, RwLock};
 
 /*****************************************************************************
  * intra prediction structure
@@ -21,8 +20,8 @@ use std::rc::Rc;
 //#[derive(Default)]
 pub(crate) struct EvcePIntra {
     /* temporary prediction buffer */
-    pub(crate) pred: CUBuffer<pel>, //[N_C][MAX_CU_DIM];
-    pub(crate) pred_cache: [[pel; MAX_CU_DIM]; IntraPredDir::IPD_CNT_B as usize], // only for luma
+    pub(crate) pred: CUBuffer<pel>,                //[N_C][MAX_CU_DIM];
+    pub(crate) pred_cache: Vec<[pel; MAX_CU_DIM]>, //[IPD_CNT][MAX_CU_DIM], only for luma
 
     /* reconstruction buffer */
     pub(crate) rec: CUBuffer<pel>, //[N_C][MAX_CU_DIM];
@@ -33,9 +32,9 @@ pub(crate) struct EvcePIntra {
     pub(crate) rec_best: CUBuffer<pel>, //[N_C][MAX_CU_DIM];
 
     /* original (input) picture buffer */
-    pub(crate) pic_o: Option<Rc<RefCell<EvcPic>>>,
+    pub(crate) pic_o: Option<Arc<RwLock<EvcPic>>>,
     /* mode picture buffer */
-    pub(crate) pic_m: Option<Rc<RefCell<EvcPic>>>,
+    pub(crate) pic_m: Option<Arc<RwLock<EvcPic>>>,
 
     /* QP for luma */
     pub(crate) qp_y: u8,
@@ -51,8 +50,8 @@ impl Default for EvcePIntra {
     fn default() -> Self {
         EvcePIntra {
             /* temporary prediction buffer */
-            pred: CUBuffer::default(), //[N_C][MAX_CU_DIM];
-            pred_cache: [[0; MAX_CU_DIM]; IntraPredDir::IPD_CNT_B as usize], // only for luma
+            pred: CUBuffer::default(),                  //[N_C][MAX_CU_DIM];
+            pred_cache: vec![[0; MAX_CU_DIM]; IPD_CNT], // only for luma
 
             /* reconstruction buffer */
             rec: CUBuffer::default(), //[N_C][MAX_CU_DIM];
@@ -80,16 +79,16 @@ impl Default for EvcePIntra {
     }
 }
 
-impl EvceCtx {
+impl EvceTask {
     pub(crate) fn pintra_init_frame(&mut self) {
         let pi = &mut self.pintra;
 
         pi.slice_type = self.slice_type;
         if let Some(pic) = &self.pic[PIC_IDX_ORIG] {
-            pi.pic_o = Some(Rc::clone(pic));
+            pi.pic_o = Some(Arc::clone(pic));
         }
         if let Some(pic) = &self.pic[PIC_IDX_MODE] {
-            pi.pic_m = Some(Rc::clone(pic));
+            pi.pic_m = Some(Arc::clone(pic));
         }
     }
 
@@ -110,8 +109,8 @@ impl EvceCtx {
         let mut best_ipd_c = IntraPredDir::IPD_INVALID;
         let mut best_dist_y = 0i32;
         let mut best_dist_c = 0i32;
-        let mut ipred_list = vec![IntraPredDir::IPD_INVALID; IntraPredDir::IPD_CNT_B as usize];
-        let mut pred_cnt = IntraPredDir::IPD_CNT_B as usize;
+        let mut ipred_list = vec![IntraPredDir::IPD_INVALID; IPD_RDO_CNT];
+        let mut pred_cnt = IPD_RDO_CNT;
         let mut cost_t = MAX_COST;
         let mut cost = MAX_COST;
         let mut sec_best_ipd = IntraPredDir::IPD_INVALID;
@@ -119,132 +118,278 @@ impl EvceCtx {
         let cuw = 1 << log2_cuw;
         let cuh = 1 << log2_cuh;
         let cuwxh = cuw * cuh;
+        let w_shift = GET_CHROMA_W_SHIFT(self.chroma_format_idc);
+        let h_shift = GET_CHROMA_H_SHIFT(self.chroma_format_idc);
 
         if let Some(pic) = &self.pintra.pic_m {
-            let frame = &pic.borrow().frame;
-            let planes = &frame.borrow().planes;
+            let frame = &pic.read().unwrap().frame;
+            let planes = &frame.read().unwrap().planes;
 
             /* Y */
 
-            evc_get_nbr_b(
-                x,
-                y,
-                cuw,
-                cuh,
-                &planes[Y_C].as_region(),
-                self.core.avail_cu,
-                &mut self.core.nb.data[tbl_nb_siz_offset[Y_C]..],
-                self.core.scup as usize,
-                &self.map_scu,
-                self.w_scu as usize,
-                self.h_scu as usize,
-                Y_C,
-                self.pps.constrained_intra_pred_flag,
-            );
+            if self.sps_eipd_flag {
+                evc_get_nbr(
+                    x,
+                    y,
+                    cuw,
+                    cuh,
+                    &planes[Y_C].as_region(),
+                    self.core.avail_cu,
+                    &mut self.core.nb_lines.data[Y_C],
+                    self.core.scup as usize,
+                    &self.map_scu,
+                    self.w_scu as usize,
+                    self.h_scu as usize,
+                    Y_C,
+                    self.pps_constrained_intra_pred_flag,
+                    self.chroma_format_idc,
+                );
+            } else {
+                evc_get_nbr_b(
+                    x,
+                    y,
+                    cuw,
+                    cuh,
+                    &planes[Y_C].as_region(),
+                    self.core.avail_cu,
+                    &mut self.core.nb.data[tbl_nb_siz_offset[Y_C]..],
+                    self.core.scup as usize,
+                    &self.map_scu,
+                    self.w_scu as usize,
+                    self.h_scu as usize,
+                    Y_C,
+                    self.pps_constrained_intra_pred_flag,
+                    self.chroma_format_idc,
+                );
+            }
 
-            evc_get_nbr_b(
-                x >> 1,
-                y >> 1,
-                cuw >> 1,
-                cuh >> 1,
-                &planes[U_C].as_region(),
-                self.core.avail_cu,
-                &mut self.core.nb.data[tbl_nb_siz_offset[U_C]..],
-                self.core.scup as usize,
+            /* monochrome pictures have no chroma neighbours */
+            if self.chroma_format_idc != 0 {
+                if self.sps_eipd_flag {
+                    evc_get_nbr(
+                        x >> w_shift,
+                        y >> h_shift,
+                        cuw >> w_shift,
+                        cuh >> h_shift,
+                        &planes[U_C].as_region(),
+                        self.core.avail_cu,
+                        &mut self.core.nb_lines.data[U_C],
+                        self.core.scup as usize,
+                        &self.map_scu,
+                        self.w_scu as usize,
+                        self.h_scu as usize,
+                        U_C,
+                        self.pps_constrained_intra_pred_flag,
+                        self.chroma_format_idc,
+                    );
+                } else {
+                    evc_get_nbr_b(
+                        x >> w_shift,
+                        y >> h_shift,
+                        cuw >> w_shift,
+                        cuh >> h_shift,
+                        &planes[U_C].as_region(),
+                        self.core.avail_cu,
+                        &mut self.core.nb.data[tbl_nb_siz_offset[U_C]..],
+                        self.core.scup as usize,
+                        &self.map_scu,
+                        self.w_scu as usize,
+                        self.h_scu as usize,
+                        U_C,
+                        self.pps_constrained_intra_pred_flag,
+                        self.chroma_format_idc,
+                    );
+                }
+
+                if self.sps_eipd_flag {
+                    evc_get_nbr(
+                        x >> w_shift,
+                        y >> h_shift,
+                        cuw >> w_shift,
+                        cuh >> h_shift,
+                        &planes[V_C].as_region(),
+                        self.core.avail_cu,
+                        &mut self.core.nb_lines.data[V_C],
+                        self.core.scup as usize,
+                        &self.map_scu,
+                        self.w_scu as usize,
+                        self.h_scu as usize,
+                        V_C,
+                        self.pps_constrained_intra_pred_flag,
+                        self.chroma_format_idc,
+                    );
+                } else {
+                    evc_get_nbr_b(
+                        x >> w_shift,
+                        y >> h_shift,
+                        cuw >> w_shift,
+                        cuh >> h_shift,
+                        &planes[V_C].as_region(),
+                        self.core.avail_cu,
+                        &mut self.core.nb.data[tbl_nb_siz_offset[V_C]..],
+                        self.core.scup as usize,
+                        &self.map_scu,
+                        self.w_scu as usize,
+                        self.h_scu as usize,
+                        V_C,
+                        self.pps_constrained_intra_pred_flag,
+                        self.chroma_format_idc,
+                    );
+                }
+            }
+        }
+
+        if self.sps_eipd_flag {
+            evc_get_mpm(
+                self.core.x_scu,
+                self.core.y_scu,
+                cuw as u16,
                 &self.map_scu,
-                self.w_scu as usize,
-                self.h_scu as usize,
-                U_C,
-                self.pps.constrained_intra_pred_flag,
+                &self.map_ipm,
+                self.core.scup,
+                self.w_scu,
+                &mut self.core.mpm_list,
             );
-
-            evc_get_nbr_b(
-                x >> 1,
-                y >> 1,
-                cuw >> 1,
-                cuh >> 1,
-                &planes[V_C].as_region(),
-                self.core.avail_cu,
-                &mut self.core.nb.data[tbl_nb_siz_offset[V_C]..],
-                self.core.scup as usize,
+        } else {
+            self.core.mpm_b_list = evc_get_mpm_b(
+                self.core.x_scu,
+                self.core.y_scu,
                 &self.map_scu,
-                self.w_scu as usize,
-                self.h_scu as usize,
-                V_C,
-                self.pps.constrained_intra_pred_flag,
+                &self.map_ipm,
+                self.core.scup,
+                self.w_scu,
             );
         }
 
-        self.core.mpm_b_list = evc_get_mpm_b(
-            self.core.x_scu,
-            self.core.y_scu,
-            &self.map_scu,
-            &self.map_ipm,
-            self.core.scup,
-            self.w_scu,
-        );
-
         pred_cnt = self.make_ipred_list(x, y, log2_cuw, log2_cuh, &mut ipred_list);
         if pred_cnt == 0 {
             return MAX_COST;
         }
 
-        for j in 0..pred_cnt {
-            let mut dist_t = 0;
+        /* each prediction mode is also tried with the 4 transform pairs of the
+        adaptive transform selection */
+        let ats_cnt =
+            if self.sps_ats_flag && log2_cuw <= MAX_TR_LOG2_ATS && log2_cuh <= MAX_TR_LOG2_ATS {
+                5
+            } else {
+                1
+            };
+        let mut best_ats_intra_cu = false;
+        let mut best_ats_mode = 0;
+        self.core.ats_inter_info = 0;
 
+        for j in 0..pred_cnt {
             let i = ipred_list[j];
             self.core.ipm[0] = i;
             self.core.ipm[1] = IntraPredDir::IPD_INVALID;
-            cost_t = self.pintra_residue_rdo(x, y, log2_cuw, log2_cuh, &mut dist_t, false);
 
-            EVC_TRACE_COUNTER(&mut self.core.bs_temp.tracer);
-            EVC_TRACE(&mut self.core.bs_temp.tracer, "Luma mode ");
-            EVC_TRACE(&mut self.core.bs_temp.tracer, i as u8);
-            EVC_TRACE(&mut self.core.bs_temp.tracer, "  cost is ");
-            EVC_TRACE(&mut self.core.bs_temp.tracer, cost_t as i64);
-            EVC_TRACE(&mut self.core.bs_temp.tracer, " \n");
+            for ats_idx in 0..ats_cnt {
+                let mut dist_t = 0;
 
-            if cost_t < cost {
-                cost = cost_t;
-                best_dist_y = dist_t;
+                self.core.ats_intra_cu = ats_idx != 0;
+                self.core.ats_mode = if ats_idx != 0 { ats_idx as u8 - 1 } else { 0 };
+                cost_t = self.pintra_residue_rdo(x, y, log2_cuw, log2_cuh, &mut dist_t, false);
 
-                if sec_best_ipd != best_ipd {
-                    sec_best_ipd = best_ipd;
-                }
+                EVC_TRACE_COUNTER(&mut self.core.bs_temp.tracer);
+                EVC_TRACE(&mut self.core.bs_temp.tracer, "Luma mode ");
+                EVC_TRACE(&mut self.core.bs_temp.tracer, i.0 as u8);
+                EVC_TRACE(&mut self.core.bs_temp.tracer, "  cost is ");
+                EVC_TRACE(&mut self.core.bs_temp.tracer, cost_t as i64);
+                EVC_TRACE(&mut self.core.bs_temp.tracer, " \n");
+
+                if cost_t < cost {
+                    cost = cost_t;
+                    best_dist_y = dist_t;
 
-                best_ipd = i;
+                    if sec_best_ipd != best_ipd {
+                        sec_best_ipd = best_ipd;
+                    }
 
-                self.pintra.coef_best.data[Y_C][0..cuwxh]
-                    .copy_from_slice(&self.core.ctmp.data[Y_C][0..cuwxh]);
-                self.pintra.rec_best.data[Y_C][0..cuwxh]
-                    .copy_from_slice(&self.pintra.rec.data[Y_C][0..cuwxh]);
+                    best_ipd = i;
+                    best_ats_intra_cu = self.core.ats_intra_cu;
+                    best_ats_mode = self.core.ats_mode;
 
-                self.pintra.nnz_best[Y_C] = self.core.nnz[Y_C];
-                self.core.s_temp_prev_comp_best = self.core.s_temp_run;
-                self.core.c_temp_prev_comp_best = self.core.c_temp_run;
+                    self.pintra.coef_best.data[Y_C][0..cuwxh]
+                        .copy_from_slice(&self.core.ctmp.data[Y_C][0..cuwxh]);
+                    self.pintra.rec_best.data[Y_C][0..cuwxh]
+                        .copy_from_slice(&self.pintra.rec.data[Y_C][0..cuwxh]);
+
+                    self.pintra.nnz_best[Y_C] = self.core.nnz[Y_C];
+                    self.core.s_temp_prev_comp_best = self.core.s_temp_run;
+                    self.core.c_temp_prev_comp_best = self.core.c_temp_run;
+                }
+
+                /* the other transforms hardly code a residual the DCT-II drops */
+                if ats_idx == 0 && self.core.nnz[Y_C] == 0 {
+                    break;
+                }
             }
         }
+        self.core.ats_intra_cu = best_ats_intra_cu;
+        self.core.ats_mode = best_ats_mode;
 
         let mut dist_tc = 0i32;
         self.core.ipm[0] = best_ipd;
-        self.core.ipm[1] = best_ipd;
+        if self.chroma_format_idc == 0 {
+            /* monochrome pictures code neither a chroma mode nor a chroma residual */
+            best_ipd_c = if self.sps_eipd_flag {
+                IntraPredDir::IPD_DM_C
+            } else {
+                best_ipd
+            };
+            for j in U_C..N_C {
+                self.pintra.nnz_best[j] = 0;
+            }
+        } else if self.sps_eipd_flag {
+            /* the chroma mode predicting the same as DM cannot be signalled */
+            let ipm_dup = evc_ipred_conv_l2c(best_ipd);
+            let mut cost_c = MAX_COST;
+            for i in 0..IPD_CHROMA_CNT as u8 {
+                let ipm_c: IntraPredDir = i.into();
+                if ipm_c == ipm_dup {
+                    continue;
+                }
+
+                self.core.ipm[1] = ipm_c;
+                self.core.s_temp_run = self.core.s_temp_prev_comp_best;
+                self.core.c_temp_run = self.core.c_temp_prev_comp_best;
+                cost_t = self.pintra_residue_rdo(x, y, log2_cuw, log2_cuh, &mut dist_tc, true);
+
+                if cost_t < cost_c {
+                    cost_c = cost_t;
+                    best_ipd_c = ipm_c;
+                    best_dist_c = dist_tc;
+                    for j in U_C..N_C {
+                        let size_tmp = (cuw * cuh) >> (w_shift + h_shift);
+                        self.pintra.coef_best.data[j][0..size_tmp]
+                            .copy_from_slice(&self.core.ctmp.data[j][0..size_tmp]);
+                        self.pintra.rec_best.data[j][0..size_tmp]
+                            .copy_from_slice(&self.pintra.rec.data[j][0..size_tmp]);
+
+                        self.pintra.nnz_best[j] = self.core.nnz[j];
+                    }
+                }
+            }
+        } else {
+            self.core.ipm[1] = best_ipd;
 
-        cost_t = self.pintra_residue_rdo(x, y, log2_cuw, log2_cuh, &mut dist_tc, true);
+            cost_t = self.pintra_residue_rdo(x, y, log2_cuw, log2_cuh, &mut dist_tc, true);
 
-        best_ipd_c = self.core.ipm[1];
-        best_dist_c = dist_tc;
-        for j in U_C..N_C {
-            let size_tmp = (cuw * cuh) >> (if j == 0 { 0 } else { 2 });
-            self.pintra.coef_best.data[j][0..size_tmp]
-                .copy_from_slice(&self.core.ctmp.data[j][0..size_tmp]);
-            self.pintra.rec_best.data[j][0..size_tmp]
-                .copy_from_slice(&self.pintra.rec.data[j][0..size_tmp]);
+            best_ipd_c = self.core.ipm[1];
+            best_dist_c = dist_tc;
+            for j in U_C..N_C {
+                let size_tmp = (cuw * cuh) >> (if j == 0 { 0 } else { w_shift + h_shift });
+                self.pintra.coef_best.data[j][0..size_tmp]
+                    .copy_from_slice(&self.core.ctmp.data[j][0..size_tmp]);
+                self.pintra.rec_best.data[j][0..size_tmp]
+                    .copy_from_slice(&self.pintra.rec.data[j][0..size_tmp]);
 
-            self.pintra.nnz_best[j] = self.core.nnz[j];
+                self.pintra.nnz_best[j] = self.core.nnz[j];
+            }
         }
 
         for j in 0..N_C {
-            let size_tmp = (cuw * cuh) >> (if j == 0 { 0 } else { 2 });
+            let size_tmp = (cuw * cuh) >> (if j == 0 { 0 } else { w_shift + h_shift });
             self.core.ctmp.data[j][0..size_tmp]
                 .copy_from_slice(&self.pintra.coef_best.data[j][0..size_tmp]);
             self.pintra.rec.data[j][0..size_tmp]
@@ -314,23 +459,40 @@ impl EvceCtx {
             ipred_list[i] = IntraPredDir::IPD_DC_B;
         }
 
-        for i in 0..IntraPredDir::IPD_CNT_B as u8 {
+        let ipd_cnt = if self.sps_eipd_flag {
+            IPD_CNT
+        } else {
+            IPD_CNT_B
+        };
+        for i in 0..ipd_cnt as u8 {
             let mut shift = 0;
             let pred_buf = &mut pi.pred_cache[i as usize];
 
-            evc_ipred_b(
-                &core.nb.data[tbl_nb_siz_offset[Y_C]..tbl_nb_siz_offset[Y_C] + (cuh << 1) as usize],
-                core.nb.data[tbl_nb_siz_offset[Y_C] + (cuh << 1) as usize],
-                &core.nb.data[tbl_nb_siz_offset[Y_C] + (cuh << 1) as usize + 1..],
-                pred_buf,
-                i.into(),
-                cuw,
-                cuh,
-            );
+            if self.sps_eipd_flag {
+                evc_ipred(
+                    &core.nb_lines.data[Y_C],
+                    core.avail_lr,
+                    pred_buf,
+                    i.into(),
+                    cuw,
+                    cuh,
+                );
+            } else {
+                evc_ipred_b(
+                    &core.nb.data
+                        [tbl_nb_siz_offset[Y_C]..tbl_nb_siz_offset[Y_C] + (cuw + cuh) as usize],
+                    core.nb.data[tbl_nb_siz_offset[Y_C] + (cuw + cuh) as usize],
+                    &core.nb.data[tbl_nb_siz_offset[Y_C] + (cuw + cuh) as usize + 1..],
+                    pred_buf,
+                    i.into(),
+                    cuw,
+                    cuh,
+                );
+            }
 
             if let Some(pic) = &pi.pic_o {
-                let frame = &pic.borrow().frame;
-                let org = &frame.borrow().planes[Y_C];
+                let frame = &pic.read().unwrap().frame;
+                let org = &frame.read().unwrap().planes[Y_C];
                 cost_satd = evce_satd_16b(x, y, cuw, cuh, &org.as_region(), pred_buf);
                 cost = cost_satd as f64;
             }
@@ -339,13 +501,23 @@ impl EvceCtx {
 
             core.s_temp_run.bit_reset();
 
-            evce_eco_intra_dir_b(
-                &mut core.bs_temp,
-                &mut core.s_temp_run,
-                &mut core.c_temp_run,
-                i,
-                core.mpm_b_list,
-            );
+            if self.sps_eipd_flag {
+                evce_eco_intra_dir(
+                    &mut core.bs_temp,
+                    &mut core.s_temp_run,
+                    &mut core.c_temp_run,
+                    i,
+                    &core.mpm_list,
+                );
+            } else {
+                evce_eco_intra_dir_b(
+                    &mut core.bs_temp,
+                    &mut core.s_temp_run,
+                    &mut core.c_temp_run,
+                    i,
+                    core.mpm_b_list,
+                );
+            }
 
             let bit_cnt = core.s_temp_run.get_bit_number();
             cost += self.sqrt_lambda[0] * bit_cnt as f64;
@@ -393,18 +565,20 @@ impl EvceCtx {
 
         let cuw = 1 << log2_cuw;
         let cuh = 1 << log2_cuh;
+        let w_shift = GET_CHROMA_W_SHIFT(self.chroma_format_idc);
+        let h_shift = GET_CHROMA_H_SHIFT(self.chroma_format_idc);
 
         if !chroma {
             if let Some(pic) = &self.pintra.pic_o {
-                let frame = &pic.borrow().frame;
-                let planes = &frame.borrow().planes;
+                let frame = &pic.read().unwrap().frame;
+                let planes = &frame.read().unwrap().planes;
                 evce_diff_16b(
                     x,
                     y,
                     log2_cuw,
                     log2_cuh,
                     &planes[Y_C].as_region(),
-                    &self.pintra.pred_cache[self.core.ipm[0] as usize],
+                    &self.pintra.pred_cache[self.core.ipm[0].0 as usize],
                     &mut self.pintra.coef_tmp.data[Y_C],
                 );
             }
@@ -424,11 +598,19 @@ impl EvceCtx {
                 self.lambda[2],
                 TQC_RUN::RUN_L as u8,
                 &self.core.rdoq_est,
+                self.sps_iqt_flag,
+                self.sps_cm_init_flag,
+                self.sps_adcc_flag,
+                self.core.ats_intra_cu,
+                self.core.ats_mode,
+                0,
+                self.chroma_format_idc,
             );
 
-            //if core->ats_intra_cu != 0 &&self.core.nnz[Y_C] == 0 {
-            //   return MAX_COST;
-            //}
+            /* the ATS flag is only coded with a luma residual */
+            if self.core.ats_intra_cu && self.core.nnz[Y_C] == 0 {
+                return MAX_COST;
+            }
 
             self.core.ctmp.data[Y_C][0..cuw * cuh]
                 .copy_from_slice(&self.pintra.coef_tmp.data[Y_C][0..cuw * cuh]);
@@ -455,12 +637,17 @@ impl EvceCtx {
                 self.core.qp_u,
                 self.core.qp_v,
                 &is_coef,
+                self.sps_iqt_flag,
+                self.core.ats_intra_cu,
+                self.core.ats_mode,
+                0,
+                self.chroma_format_idc,
             );
 
             evc_recon(
                 &mut self.core.bs_temp.tracer,
                 &self.pintra.coef_tmp.data[Y_C],
-                &self.pintra.pred_cache[self.core.ipm[0] as usize],
+                &self.pintra.pred_cache[self.core.ipm[0].0 as usize],
                 self.core.nnz[Y_C] != 0,
                 cuw,
                 cuh,
@@ -469,8 +656,8 @@ impl EvceCtx {
             );
 
             if let Some(pic) = &self.pintra.pic_o {
-                let frame = &pic.borrow().frame;
-                let planes = &frame.borrow().planes;
+                let frame = &pic.read().unwrap().frame;
+                let planes = &frame.read().unwrap().planes;
                 cost += evce_ssd_16b(
                     x,
                     y,
@@ -499,43 +686,71 @@ impl EvceCtx {
             *dist = cost as i32;
             cost += (self.lambda[0] * bit_cnt as f64);
         } else {
-            evc_ipred_b(
-                &self.core.nb.data[tbl_nb_siz_offset[U_C]..tbl_nb_siz_offset[U_C] + cuh as usize],
-                self.core.nb.data[tbl_nb_siz_offset[U_C] + cuh as usize],
-                &self.core.nb.data[tbl_nb_siz_offset[U_C] + cuh as usize + 1..],
-                &mut self.pintra.pred.data[U_C],
-                self.core.ipm[1],
-                cuw as usize >> 1,
-                cuh as usize >> 1,
-            );
+            if self.sps_eipd_flag {
+                evc_ipred_uv(
+                    &self.core.nb_lines.data[U_C],
+                    self.core.avail_lr,
+                    &mut self.pintra.pred.data[U_C],
+                    self.core.ipm[1],
+                    self.core.ipm[0],
+                    cuw >> w_shift,
+                    cuh >> h_shift,
+                );
 
-            evc_ipred_b(
-                &self.core.nb.data[tbl_nb_siz_offset[V_C]..tbl_nb_siz_offset[V_C] + cuh as usize],
-                self.core.nb.data[tbl_nb_siz_offset[V_C] + cuh as usize],
-                &self.core.nb.data[tbl_nb_siz_offset[V_C] + cuh as usize + 1..],
-                &mut self.pintra.pred.data[V_C],
-                self.core.ipm[1],
-                cuw as usize >> 1,
-                cuh as usize >> 1,
-            );
+                evc_ipred_uv(
+                    &self.core.nb_lines.data[V_C],
+                    self.core.avail_lr,
+                    &mut self.pintra.pred.data[V_C],
+                    self.core.ipm[1],
+                    self.core.ipm[0],
+                    cuw >> w_shift,
+                    cuh >> h_shift,
+                );
+            } else {
+                evc_ipred_b(
+                    &self.core.nb.data[tbl_nb_siz_offset[U_C]
+                        ..tbl_nb_siz_offset[U_C] + ((cuw >> w_shift) + (cuh >> h_shift))],
+                    self.core.nb.data
+                        [tbl_nb_siz_offset[U_C] + ((cuw >> w_shift) + (cuh >> h_shift))],
+                    &self.core.nb.data
+                        [tbl_nb_siz_offset[U_C] + ((cuw >> w_shift) + (cuh >> h_shift)) + 1..],
+                    &mut self.pintra.pred.data[U_C],
+                    self.core.ipm[1],
+                    cuw >> w_shift,
+                    cuh >> h_shift,
+                );
+
+                evc_ipred_b(
+                    &self.core.nb.data[tbl_nb_siz_offset[V_C]
+                        ..tbl_nb_siz_offset[V_C] + ((cuw >> w_shift) + (cuh >> h_shift))],
+                    self.core.nb.data
+                        [tbl_nb_siz_offset[V_C] + ((cuw >> w_shift) + (cuh >> h_shift))],
+                    &self.core.nb.data
+                        [tbl_nb_siz_offset[V_C] + ((cuw >> w_shift) + (cuh >> h_shift)) + 1..],
+                    &mut self.pintra.pred.data[V_C],
+                    self.core.ipm[1],
+                    cuw >> w_shift,
+                    cuh >> h_shift,
+                );
+            }
 
             if let Some(pic) = &self.pintra.pic_o {
-                let frame = &pic.borrow().frame;
-                let planes = &frame.borrow().planes;
+                let frame = &pic.read().unwrap().frame;
+                let planes = &frame.read().unwrap().planes;
                 evce_diff_16b(
-                    x >> 1,
-                    y >> 1,
-                    log2_cuw - 1,
-                    log2_cuh - 1,
+                    x >> w_shift,
+                    y >> h_shift,
+                    log2_cuw - w_shift,
+                    log2_cuh - h_shift,
                     &planes[U_C].as_region(),
                     &self.pintra.pred.data[U_C],
                     &mut self.pintra.coef_tmp.data[U_C],
                 );
                 evce_diff_16b(
-                    x >> 1,
-                    y >> 1,
-                    log2_cuw - 1,
-                    log2_cuh - 1,
+                    x >> w_shift,
+                    y >> h_shift,
+                    log2_cuw - w_shift,
+                    log2_cuh - h_shift,
                     &planes[V_C].as_region(),
                     &self.pintra.pred.data[V_C],
                     &mut self.pintra.coef_tmp.data[V_C],
@@ -557,12 +772,21 @@ impl EvceCtx {
                 self.lambda[2],
                 TQC_RUN::RUN_CB as u8 | TQC_RUN::RUN_CR as u8,
                 &self.core.rdoq_est,
+                self.sps_iqt_flag,
+                self.sps_cm_init_flag,
+                self.sps_adcc_flag,
+                false,
+                0,
+                0,
+                self.chroma_format_idc,
             );
 
-            self.core.ctmp.data[U_C][0..(cuw * cuh) >> 2]
-                .copy_from_slice(&self.pintra.coef_tmp.data[U_C][0..(cuw * cuh) >> 2]);
-            self.core.ctmp.data[V_C][0..(cuw * cuh) >> 2]
-                .copy_from_slice(&self.pintra.coef_tmp.data[V_C][0..(cuw * cuh) >> 2]);
+            self.core.ctmp.data[U_C][0..(cuw * cuh) >> (w_shift + h_shift)].copy_from_slice(
+                &self.pintra.coef_tmp.data[U_C][0..(cuw * cuh) >> (w_shift + h_shift)],
+            );
+            self.core.ctmp.data[V_C][0..(cuw * cuh) >> (w_shift + h_shift)].copy_from_slice(
+                &self.pintra.coef_tmp.data[V_C][0..(cuw * cuh) >> (w_shift + h_shift)],
+            );
 
             let is_coef = [
                 self.core.nnz[Y_C] != 0,
@@ -578,6 +802,11 @@ impl EvceCtx {
                 self.core.qp_u,
                 self.core.qp_v,
                 &is_coef,
+                self.sps_iqt_flag,
+                self.core.ats_intra_cu,
+                self.core.ats_mode,
+                0,
+                self.chroma_format_idc,
             );
 
             evc_recon(
@@ -585,8 +814,8 @@ impl EvceCtx {
                 &self.pintra.coef_tmp.data[U_C],
                 &self.pintra.pred.data[U_C],
                 self.core.nnz[U_C] != 0,
-                cuw >> 1,
-                cuh >> 1,
+                cuw >> w_shift,
+                cuh >> h_shift,
                 &mut self.pintra.rec.data[U_C],
                 U_C,
             );
@@ -595,8 +824,8 @@ impl EvceCtx {
                 &self.pintra.coef_tmp.data[V_C],
                 &self.pintra.pred.data[V_C],
                 self.core.nnz[V_C] != 0,
-                cuw >> 1,
-                cuh >> 1,
+                cuw >> w_shift,
+                cuh >> h_shift,
                 &mut self.pintra.rec.data[V_C],
                 V_C,
             );
@@ -608,23 +837,23 @@ impl EvceCtx {
             let bit_cnt = self.core.s_temp_run.get_bit_number();
 
             if let Some(pic) = &self.pintra.pic_o {
-                let frame = &pic.borrow().frame;
-                let planes = &frame.borrow().planes;
+                let frame = &pic.read().unwrap().frame;
+                let planes = &frame.read().unwrap().planes;
                 cost += self.dist_chroma_weight[0]
                     * evce_ssd_16b(
-                        x >> 1,
-                        y >> 1,
-                        log2_cuw - 1,
-                        log2_cuh - 1,
+                        x >> w_shift,
+                        y >> h_shift,
+                        log2_cuw - w_shift,
+                        log2_cuh - h_shift,
                         &planes[U_C].as_region(),
                         &self.pintra.rec.data[U_C],
                     ) as f64;
                 cost += self.dist_chroma_weight[1]
                     * evce_ssd_16b(
-                        x >> 1,
-                        y >> 1,
-                        log2_cuw - 1,
-                        log2_cuh - 1,
+                        x >> w_shift,
+                        y >> h_shift,
+                        log2_cuw - w_shift,
+                        log2_cuh - h_shift,
                         &planes[V_C].as_region(),
                         &self.pintra.rec.data[V_C],
                     ) as f64;
@@ -650,8 +879,8 @@ impl EvceCtx {
             *dist = cost as i32;
 
             if let Some(pic) = &self.pintra.pic_o {
-                let frame = &pic.borrow().frame;
-                let planes = &frame.borrow().planes;
+                let frame = &pic.read().unwrap().frame;
+                let planes = &frame.read().unwrap().planes;
                 cost += evce_ssd_16b(
                     x,
                     y,
@@ -0,0 +1,591 @@
+use super::tbl::*;
+use super::*;
+
+use log::*;
+
+/* number of rate control classes: one per slice depth, depth 0 is intra */
+pub(crate) const RC_CLASS_NUM: usize = FRM_DEPTH_MAX as usize + 1;
+
+/* decay applied to the statistics of a class for every new observation */
+const RC_PRED_DECAY: f64 = 0.5;
+/* window (in seconds) used by ABR to pay back the accumulated bit error */
+const RC_ABR_WINDOW_SEC: f64 = 2.0;
+/* VBV fullness kept in reserve when a frame is constrained */
+const RC_VBV_MARGIN: f64 = 0.1;
+/* initial VBV fullness, as a fraction of the VBV size */
+const RC_VBV_INIT: f64 = 0.9;
+/* qp compression of the second pass: 0 is constant bitrate, 1 is constant QP */
+const RC_QCOMP: f64 = 0.6;
+/* header line of the first pass statistics */
+const RC_STATS_HEADER: &str = "#revc-rc-stats v1";
+
+/* initial bits per pixel (at qstep 1) for every slice depth, used until a
+frame of that depth has been encoded */
+#[rustfmt::skip]
+static rc_init_bpp: [f64; RC_CLASS_NUM] = [25.0, 6.0, 4.0, 2.5, 1.5, 1.0, 0.7, 0.5];
+
+#[inline]
+fn rc_qp2qstep(qp: u8) -> f64 {
+    (2.0f64).powf((qp as f64 - 4.0) / 6.0)
+}
+
+/*****************************************************************************
+ * bits predictor of one rate control class: bits = coeff / qstep
+ *****************************************************************************/
+#[derive(Default, Copy, Clone)]
+struct EvceRcPred {
+    coeff: f64,
+    count: f64,
+}
+
+impl EvceRcPred {
+    fn predict(&self, qp: u8) -> f64 {
+        self.coeff / rc_qp2qstep(qp)
+    }
+
+    fn update(&mut self, bits: f64, qp: u8) {
+        let coeff = bits * rc_qp2qstep(qp);
+        self.count *= RC_PRED_DECAY;
+        self.coeff = (self.coeff * self.count + coeff) / (self.count + 1.0);
+        self.count += 1.0;
+    }
+}
+
+/*****************************************************************************
+ * first pass statistics of one picture
+ *****************************************************************************/
+#[derive(Default, Copy, Clone, Debug, PartialEq)]
+pub(crate) struct EvceRcStat {
+    /* picture number in encoding order */
+    pub(crate) fnum: usize,
+    pub(crate) poc: i32,
+    pub(crate) slice_type: SliceType,
+    pub(crate) slice_depth: u8,
+    /* slice QP of the picture */
+    pub(crate) qp: u8,
+    /* coded size in bits */
+    pub(crate) bits: u64,
+    /* RD cost of the mode decision of the picture */
+    pub(crate) cost: f64,
+}
+
+impl EvceRcStat {
+    fn write(&self, out: &mut String) {
+        out.push_str(&format!(
+            "{} {} {} {} {} {} {:.0}\n",
+            self.fnum,
+            self.poc,
+            self.slice_type as u8,
+            self.slice_depth,
+            self.qp,
+            self.bits,
+            self.cost
+        ));
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let mut it = line.split_whitespace();
+        let stat = EvceRcStat {
+            fnum: it.next()?.parse().ok()?,
+            poc: it.next()?.parse().ok()?,
+            slice_type: it.next()?.parse::<u8>().ok()?.into(),
+            slice_depth: it.next()?.parse().ok()?,
+            qp: it.next()?.parse().ok()?,
+            bits: it.next()?.parse().ok()?,
+            cost: it.next()?.parse().ok()?,
+        };
+        if stat.slice_depth > FRM_DEPTH_MAX || stat.qp > MAX_QUANT || it.next().is_some() {
+            return None;
+        }
+        Some(stat)
+    }
+
+    /* bits * qstep, which stays about constant with the QP */
+    fn complexity(&self) -> f64 {
+        self.bits.max(1) as f64 * rc_qp2qstep(self.qp)
+    }
+
+    /* RD cost / lambda * qstep, with lambda going with qstep^2. unlike the
+    coded bits, the RD cost also holds the distortion left by the mode
+    decision and the motion search */
+    fn cost_complexity(&self) -> f64 {
+        self.cost.max(1.0) / rc_qp2qstep(self.qp)
+    }
+}
+
+/*****************************************************************************
+ * rate control state
+ *
+ * The controller picks the base QP of every picture. The slice QP is derived
+ * from the base QP with the same hierarchical GOP offsets as the constant QP
+ * mode, so one base QP is searched such that the predicted bits of an
+ * average GOP fit the per-frame budget.
+ *****************************************************************************/
+#[derive(Default)]
+pub(crate) struct EvceRc {
+    mode: RateControlMode,
+    param: EncoderConfig,
+    /* target bits per frame */
+    bits_per_frame: f64,
+    /* relative frequency of every slice depth in the sequence */
+    class_weight: [f64; RC_CLASS_NUM],
+    /* bits predictors of every slice depth */
+    pred: [EvceRcPred; RC_CLASS_NUM],
+    /* number of frames used to pay back the ABR bit error */
+    abr_window: f64,
+    /* VBV buffer size in bits (0: no VBV constraint) */
+    vbv_size: f64,
+    /* current VBV fullness in bits */
+    vbv_fullness: f64,
+    /* total bits spent so far */
+    total_bits: f64,
+    /* number of frames encoded so far */
+    frame_cnt: u64,
+
+    /* base QP of the current picture */
+    base_qp: u8,
+    /* slice QP of the current picture */
+    frame_qp: u8,
+    /* slice depth of the current picture */
+    frame_depth: u8,
+    /* predicted bits of the current picture */
+    frame_pred_bits: f64,
+    /* bits of every slice of the last picture of every slice depth */
+    slice_bits: [Vec<f64>; RC_CLASS_NUM],
+
+    /* first pass: statistics not yet handed out */
+    pass_data: String,
+    /* second pass: statistics of the first pass */
+    pass_stats: Vec<EvceRcStat>,
+    /* second pass: planned base QP of every picture */
+    pass_base_qp: Vec<u8>,
+    /* second pass: planned bits of every picture */
+    pass_bits: Vec<f64>,
+    /* second pass: planned bits of the pictures encoded so far */
+    pass_planned_bits: f64,
+}
+
+impl EvceRc {
+    pub(crate) fn new(param: &EncoderConfig, gop_size: usize) -> Self {
+        let mut rc = EvceRc {
+            mode: param.rc_mode,
+            param: *param,
+            base_qp: param.qp,
+            frame_qp: param.qp,
+            ..Default::default()
+        };
+
+        if rc.mode == RateControlMode::ConstantQP {
+            return rc;
+        }
+
+        let fps = if param.time_base.num != 0 && param.time_base.den != 0 {
+            param.time_base.den as f64 / param.time_base.num as f64
+        } else {
+            param.fps as f64
+        };
+        let fps = if fps > 0.0 { fps } else { 30.0 };
+        rc.bits_per_frame = param.bitrate as f64 / fps;
+        rc.abr_window = (fps * RC_ABR_WINDOW_SEC).max(param.max_key_frame_interval as f64);
+
+        rc.vbv_size = if param.vbv_buffer_size > 0 {
+            param.vbv_buffer_size as f64
+        } else if rc.mode == RateControlMode::ConstantBitrate {
+            param.bitrate as f64
+        } else {
+            0.0
+        };
+        rc.vbv_fullness = rc.vbv_size * RC_VBV_INIT;
+
+        let pixels = (param.width * param.height) as f64;
+        for (pred, bpp) in rc.pred.iter_mut().zip(rc_init_bpp.iter()) {
+            pred.coeff = pixels * bpp;
+        }
+
+        rc.init_class_weight(gop_size);
+
+        if param.rc_pass == RateControlPass::First {
+            rc.pass_data.push_str(RC_STATS_HEADER);
+            rc.pass_data.push('\n');
+        }
+
+        rc
+    }
+
+    fn init_class_weight(&mut self, gop_size: usize) {
+        let param = &self.param;
+        let mut weight = [0.0; RC_CLASS_NUM];
+        let i_period = param.max_key_frame_interval;
+
+        if i_period == 1 {
+            weight[FRM_DEPTH_0 as usize] = 1.0;
+        } else {
+            if gop_size == 1 {
+                let gap = param.ref_pic_gap_length as usize;
+                for i in 0..gap {
+                    let depth = if !param.disable_hgop {
+                        tbl_slice_depth_P[gap >> 2][i % gap]
+                    } else {
+                        FRM_DEPTH_1
+                    };
+                    weight[depth as usize] += 1.0 / gap as f64;
+                }
+            } else {
+                weight[FRM_DEPTH_1 as usize] += 1.0 / gop_size as f64;
+                for i in 0..gop_size - 1 {
+                    let depth = if !param.disable_hgop {
+                        tbl_slice_depth[gop_size >> 2][i]
+                    } else {
+                        FRM_DEPTH_2
+                    };
+                    weight[depth as usize] += 1.0 / gop_size as f64;
+                }
+            }
+
+            if i_period > 0 {
+                let intra = 1.0 / i_period as f64;
+                for w in weight.iter_mut() {
+                    *w *= 1.0 - intra;
+                }
+                weight[FRM_DEPTH_0 as usize] += intra;
+            }
+        }
+
+        self.class_weight = weight;
+    }
+
+    #[inline]
+    fn slice_qp(&self, base_qp: u8, slice_depth: u8) -> u8 {
+        EVC_CLIP3(
+            self.param.min_qp,
+            self.param.max_qp,
+            evce_slice_qp(&self.param, base_qp, slice_depth),
+        )
+    }
+
+    /* predicted bits of an average frame of the sequence */
+    fn predict_avg(&self, base_qp: u8) -> f64 {
+        let mut bits = 0.0;
+        for (d, (pred, w)) in self.pred.iter().zip(self.class_weight.iter()).enumerate() {
+            if *w > 0.0 {
+                bits += w * pred.predict(self.slice_qp(base_qp, d as u8));
+            }
+        }
+        bits
+    }
+
+    /* pick the base QP of the next picture */
+    pub(crate) fn frame_start(&mut self, slice_depth: u8) -> u8 {
+        self.frame_depth = slice_depth;
+
+        if self.mode == RateControlMode::ConstantQP {
+            self.base_qp = self.param.qp;
+            self.frame_qp = evce_slice_qp(&self.param, self.base_qp, slice_depth);
+            return self.base_qp;
+        }
+
+        /* follow the plan of the second pass while the GOP structure matches */
+        let idx = self.frame_cnt as usize;
+        let mut base_qp = match (self.pass_stats.get(idx), self.pass_base_qp.get(idx)) {
+            (Some(stat), Some(qp)) if stat.slice_depth == slice_depth => self.second_pass_qp(*qp),
+            _ => self.first_pass_qp(),
+        };
+
+        /* do not underflow the VBV with the current picture */
+        if self.vbv_size > 0.0 {
+            let avail = self.vbv_fullness - self.vbv_size * RC_VBV_MARGIN;
+            while base_qp < self.param.max_qp
+                && self.pred[slice_depth as usize].predict(self.slice_qp(base_qp, slice_depth))
+                    > avail
+            {
+                base_qp += 1;
+            }
+        }
+
+        self.base_qp = base_qp;
+        self.frame_qp = self.slice_qp(base_qp, slice_depth);
+        self.frame_pred_bits = self.pred[slice_depth as usize].predict(self.frame_qp);
+
+        base_qp
+    }
+
+    /* one pass: the base QP that makes an average frame fit the budget */
+    fn first_pass_qp(&self) -> u8 {
+        /* budget of an average frame, corrected by the bits spent so far */
+        let expected = self.frame_cnt as f64 * self.bits_per_frame;
+        let mut budget = self.bits_per_frame - (self.total_bits - expected) / self.abr_window;
+        if self.mode == RateControlMode::ConstantBitrate && self.vbv_size > 0.0 {
+            /* steer the buffer toward half full */
+            budget +=
+                (self.vbv_fullness - self.vbv_size * 0.5) * self.bits_per_frame / self.vbv_size;
+        }
+        budget = EVC_CLIP3(self.bits_per_frame * 0.5, self.bits_per_frame * 1.5, budget);
+
+        for qp in self.param.min_qp..=self.param.max_qp {
+            if self.predict_avg(qp) <= budget {
+                return qp;
+            }
+        }
+        self.param.max_qp
+    }
+
+    /* second pass: the planned base QP, corrected by the drift from the plan */
+    fn second_pass_qp(&self, planned_qp: u8) -> u8 {
+        let mut qp = planned_qp as f64;
+        if self.pass_planned_bits > 0.0 && self.total_bits > 0.0 {
+            let drift = (self.total_bits / self.pass_planned_bits).log2() * 6.0;
+            qp += EVC_CLIP3(-4.0, 4.0, drift * 0.5);
+        }
+        EVC_CLIP3(
+            self.param.min_qp as f64,
+            self.param.max_qp as f64,
+            qp.round(),
+        ) as u8
+    }
+
+    /* second pass: plan the base QP of every picture from the first pass statistics */
+    pub(crate) fn set_pass_stats(&mut self, stats: Vec<EvceRcStat>) {
+        let n = stats.len();
+        let total = self.bits_per_frame * n as f64;
+
+        /* RD cost complexity blurred over the neighbouring pictures in
+        encoding order */
+        let radius = (self.param.max_b_frames as usize + 1).max(2);
+        let cplx: Vec<f64> = (0..n)
+            .map(|i| {
+                let (mut sum, mut wsum) = (0.0, 0.0);
+                for j in i.saturating_sub(radius)..(i + radius + 1).min(n) {
+                    let w = 0.5f64.powi((i as i32 - j as i32).abs());
+                    sum += w * stats[j].cost_complexity();
+                    wsum += w;
+                }
+                (sum / wsum).powf(1.0 - RC_QCOMP)
+            })
+            .collect();
+
+        let plan = |rc: &Self, scale: f64| -> (Vec<u8>, Vec<f64>) {
+            let mut qps = Vec::with_capacity(n);
+            let mut bits = Vec::with_capacity(n);
+            for (stat, c) in stats.iter().zip(cplx.iter()) {
+                let qp = 4.0 + 6.0 * (scale * c).log2();
+                let qp =
+                    EVC_CLIP3(rc.param.min_qp as f64, rc.param.max_qp as f64, qp.round()) as u8;
+                let slice_qp = rc.slice_qp(qp, stat.slice_depth);
+                qps.push(qp);
+                bits.push(stat.complexity() / rc_qp2qstep(slice_qp));
+            }
+            (qps, bits)
+        };
+
+        /* search the scale of the qstep in the log domain */
+        let (mut lo, mut hi) = (-40.0f64, 40.0f64);
+        for _ in 0..64 {
+            let mid = (lo + hi) * 0.5;
+            let (_, bits) = plan(self, mid.exp2());
+            if bits.iter().sum::<f64>() > total {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+
+        let (qps, bits) = plan(self, hi.exp2());
+        self.pass_base_qp = qps;
+        self.pass_bits = bits;
+        self.pass_stats = stats;
+    }
+
+    /* first pass: statistics of the pictures encoded since the last call */
+    pub(crate) fn receive_pass_data(&mut self) -> Option<Vec<u8>> {
+        if self.pass_data.is_empty() {
+            None
+        } else {
+            Some(std::mem::take(&mut self.pass_data).into_bytes())
+        }
+    }
+
+    /* second pass: parse the statistics written by the first pass */
+    pub(crate) fn send_pass_data(&mut self, data: &[u8]) -> Result<(), EvcError> {
+        if self.param.rc_pass != RateControlPass::Second || self.frame_cnt != 0 {
+            return Err(EvcError::EVC_ERR_UNEXPECTED);
+        }
+
+        let text = std::str::from_utf8(data).map_err(|_| EvcError::EVC_ERR_INVALID_ARGUMENT)?;
+        let mut lines = text.lines();
+        if lines.next() != Some(RC_STATS_HEADER) {
+            return Err(EvcError::EVC_ERR_INVALID_ARGUMENT);
+        }
+
+        let mut stats = vec![];
+        for line in lines.filter(|l| !l.trim().is_empty()) {
+            let stat = EvceRcStat::parse(line).ok_or(EvcError::EVC_ERR_INVALID_ARGUMENT)?;
+            if stat.fnum != stats.len() {
+                return Err(EvcError::EVC_ERR_INVALID_ARGUMENT);
+            }
+            stats.push(stat);
+        }
+
+        self.set_pass_stats(stats);
+
+        Ok(())
+    }
+
+    /* adjust the QP of a slice from its share of the bits of the last picture
+    of the same depth. it is known before the slices of the picture are spread
+    on the threads, so the QP does not depend on their number */
+    pub(crate) fn slice_start(&self, num_slices: usize, slice_idx: usize) -> u8 {
+        let slice_qp = self.frame_qp;
+        let bits = &self.slice_bits[self.frame_depth as usize];
+        if self.mode == RateControlMode::ConstantQP || bits.len() != num_slices {
+            return slice_qp;
+        }
+
+        let total: f64 = bits.iter().sum();
+        if total <= 0.0 {
+            return slice_qp;
+        }
+
+        let ratio = bits[slice_idx] * num_slices as f64 / total;
+        let dqp: i8 = if ratio > 1.4 {
+            2
+        } else if ratio > 1.15 {
+            1
+        } else if ratio < 0.6 {
+            -2
+        } else if ratio < 0.85 {
+            -1
+        } else {
+            0
+        };
+
+        EVC_CLIP3(
+            self.param.min_qp as i8,
+            self.param.max_qp as i8,
+            slice_qp as i8 + dqp,
+        ) as u8
+    }
+
+    /* keep the bits of every slice of the encoded picture */
+    pub(crate) fn slice_end(&mut self, slice_bits: Vec<f64>) {
+        self.slice_bits[self.frame_depth as usize] = slice_bits;
+    }
+
+    /* update the model with the size of the encoded picture */
+    pub(crate) fn frame_end(&mut self, bits: f64, poc: i32, slice_type: SliceType, cost: f64) {
+        if self.param.rc_pass == RateControlPass::First {
+            EvceRcStat {
+                fnum: self.frame_cnt as usize,
+                poc,
+                slice_type,
+                slice_depth: self.frame_depth,
+                qp: self.frame_qp,
+                bits: bits as u64,
+                cost,
+            }
+            .write(&mut self.pass_data);
+        }
+
+        if self.mode == RateControlMode::ConstantQP {
+            self.frame_cnt += 1;
+            return;
+        }
+
+        if let Some(planned) = self.pass_bits.get(self.frame_cnt as usize) {
+            self.pass_planned_bits += planned;
+        }
+
+        self.pred[self.frame_depth as usize].update(bits, self.frame_qp);
+        self.total_bits += bits;
+        self.frame_cnt += 1;
+
+        if self.vbv_size > 0.0 {
+            self.vbv_fullness = (self.vbv_fullness - bits + self.bits_per_frame).min(self.vbv_size);
+            if self.vbv_fullness < 0.0 {
+                warn!("VBV underflow by {} bits", -self.vbv_fullness);
+                self.vbv_fullness = 0.0;
+            }
+        }
+    }
+
+    /* QP reported for the current picture */
+    pub(crate) fn frame_qp(&self) -> u8 {
+        self.frame_qp
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn slice_qp_follows_slice_bits() {
+        let param = EncoderConfig {
+            min_qp: 0,
+            max_qp: MAX_QUANT,
+            rc_mode: RateControlMode::AverageBitrate,
+            ..Default::default()
+        };
+        let qp = 32;
+        let mut rc = EvceRc {
+            mode: param.rc_mode,
+            param,
+            frame_qp: qp,
+            ..Default::default()
+        };
+
+        /* no picture of this depth yet */
+        assert_eq!(rc.slice_start(4, 0), qp);
+
+        rc.slice_end(vec![1000.0, 3000.0, 2500.0, 1500.0]);
+        assert_eq!(rc.slice_start(4, 0), qp - 2);
+        assert_eq!(rc.slice_start(4, 1), qp + 2);
+        assert_eq!(rc.slice_start(4, 2), qp + 1);
+        assert_eq!(rc.slice_start(4, 3), qp - 1);
+        /* the slices of the picture changed */
+        assert_eq!(rc.slice_start(2, 1), qp);
+
+        rc.mode = RateControlMode::ConstantQP;
+        assert_eq!(rc.slice_start(4, 1), qp);
+    }
+
+    #[test]
+    fn pass_stats_follow_rd_cost() {
+        let stat = EvceRcStat {
+            fnum: 1,
+            poc: 2,
+            slice_type: SliceType::EVC_ST_B,
+            slice_depth: 1,
+            qp: 33,
+            bits: 4000,
+            cost: 123456.0,
+        };
+        let mut line = String::new();
+        stat.write(&mut line);
+        assert_eq!(EvceRcStat::parse(&line), Some(stat));
+
+        let param = EncoderConfig {
+            min_qp: 0,
+            max_qp: MAX_QUANT,
+            rc_mode: RateControlMode::AverageBitrate,
+            ..Default::default()
+        };
+        let mut rc = EvceRc {
+            mode: param.rc_mode,
+            param,
+            bits_per_frame: 4000.0,
+            ..Default::default()
+        };
+
+        /* same coded bits, the picture of the larger RD cost gets the larger QP */
+        let stats = (0..8)
+            .map(|fnum| EvceRcStat {
+                fnum,
+                poc: fnum as i32,
+                cost: if fnum == 4 { 800000.0 } else { 100000.0 },
+                ..stat
+            })
+            .collect();
+        rc.set_pass_stats(stats);
+        assert!(rc.pass_base_qp[4] > rc.pass_base_qp[0]);
+    }
+}
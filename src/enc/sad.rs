@@ -304,6 +304,26 @@ pub(crate) fn evce_ssd_16b(
     ssd
 }
 
+/* SSD of a whole w x h plane */
+pub(crate) fn evce_ssd_plane(
+    w: usize,
+    h: usize,
+    src1: &PlaneRegion<'_, pel>,
+    src2: &PlaneRegion<'_, pel>,
+) -> i64 {
+    let shift = (BIT_DEPTH - 8) << 1;
+    let mut ssd = 0;
+
+    for j in 0..h {
+        for i in 0..w {
+            let diff = src1[j][i] as i64 - src2[j][i] as i64;
+            ssd += (diff * diff) >> shift;
+        }
+    }
+
+    ssd
+}
+
 pub(crate) fn evce_ssd_16i(
     x: i16,
     y: i16,
@@ -795,6 +795,64 @@ fn evce_trans(coef: &mut [i16], log2_cuw: usize, log2_cuh: usize) {
     tbl_txb1[log2_cuh - 1](&tb, coef, (shift1 + shift2), log2_cuw);
 }
 
+/* one forward transform stage by matrix multiplication, for the kernels that
+have no butterfly form */
+fn tx_mat_b0(src: &[i16], dst: &mut [i32], tm: &[i16], log2_size: usize, log2_line: usize) {
+    let size = 1 << log2_size;
+    for j in 0..(1 << log2_line) {
+        for k in 0..size {
+            let mut sum = 0i64;
+            for n in 0..size {
+                sum += tm[k * size + n] as i64 * src[j * size + n] as i64;
+            }
+            dst[(k << log2_line) + j] = sum as i32;
+        }
+    }
+}
+
+fn tx_mat_b1(
+    src: &[i32],
+    dst: &mut [i16],
+    tm: &[i16],
+    shift: usize,
+    log2_size: usize,
+    log2_line: usize,
+) {
+    let size = 1 << log2_size;
+    let add = 1 << (shift - 1);
+    for j in 0..(1 << log2_line) {
+        for k in 0..size {
+            let mut sum = 0i64;
+            for n in 0..size {
+                sum += tm[k * size + n] as i64 * src[j * size + n] as i64;
+            }
+            dst[(k << log2_line) + j] = ((sum + add) >> shift) as i16;
+        }
+    }
+}
+
+fn evce_trans_mat(coef: &mut [i16], log2_cuw: usize, log2_cuh: usize, tr_h: u8, tr_v: u8) {
+    let shift1 = evc_get_transform_shift(log2_cuw, 0);
+    let shift2 = evc_get_transform_shift(log2_cuh, 1);
+
+    let mut tb = [0i32; MAX_TR_DIM]; /* temp buffer */
+    tx_mat_b0(
+        coef,
+        &mut tb,
+        evc_get_tr_kernel(tr_h, log2_cuw),
+        log2_cuw,
+        log2_cuh,
+    );
+    tx_mat_b1(
+        &tb,
+        coef,
+        evc_get_tr_kernel(tr_v, log2_cuh),
+        shift1 + shift2,
+        log2_cuh,
+        log2_cuw,
+    );
+}
+
 fn get_ic_rate_cost_rl(
     abs_level: u32,
     run: u32,
@@ -878,6 +936,7 @@ fn evce_rdoq_run_length_cc(
     log2_cuw: usize,
     log2_cuh: usize,
     ch_type: usize,
+    cm_init: bool,
     rdoq_est: &EvceRdoqEst,
 ) -> u16 {
     let qp_rem = qp as usize % 6;
@@ -896,11 +955,11 @@ fn evce_rdoq_run_length_cc(
     } else {
         0
     };
-    let q_value = (quant_scale[qp_rem] * ns_scale + ns_offset) >> ns_shift;
+    let q_value = (quant_scale[qp_rem] as u32 * ns_scale + ns_offset) >> ns_shift;
     let log2_size = (log2_cuw + log2_cuh) >> 1;
     let tr_shift = MAX_TX_DYNAMIC_RANGE as isize - BIT_DEPTH as isize - log2_size as isize;
     let max_num_coef = 1 << (log2_cuw + log2_cuh);
-    let scan = &evc_scan_tbl[log2_cuw - 1];
+    let scan = &evc_scan_tbl[log2_cuw - 1][log2_cuh - 1];
     let ctx_last = if ch_type == Y_C { 0 } else { 1 };
     let q_bits = QUANT_SHIFT as isize + tr_shift + (qp as isize / 6);
     let mut nnz = 0;
@@ -971,8 +1030,14 @@ fn evce_rdoq_run_length_cc(
 
     for scan_pos in 0..max_num_coef {
         let blk_pos = scan[scan_pos] as usize;
-        let ctx_run = if ch_type == Y_C { 0 } else { 2 };
-        let ctx_level = if ch_type == Y_C { 0 } else { 2 };
+        let ctx_run = if cm_init {
+            (prev_level.min(6) as usize - 1) * 2 + if ch_type == Y_C { 0 } else { 12 }
+        } else if ch_type == Y_C {
+            0
+        } else {
+            2
+        };
+        let ctx_level = ctx_run;
 
         let level = get_coded_level_rl(
             &mut d64_uncoded_cost,
@@ -1032,6 +1097,296 @@ fn evce_rdoq_run_length_cc(
     nnz
 }
 
+/* number of bypass bins of a level remainder coded by evce_eco_coef_remain */
+fn get_coef_remain_bits(symbol: u32, rparam: u32) -> i32 {
+    let range = evc_tbl_go_rice_range[rparam as usize] as u32;
+    if symbol < (range << rparam) {
+        ((symbol >> rparam) + 1 + rparam) as i32
+    } else {
+        let symbol = symbol - ((range - 1) << rparam);
+        let length = 31 - (symbol >> rparam).leading_zeros();
+        (range + length + 1 + length + rparam) as i32
+    }
+}
+
+fn get_ic_rate_adcc(
+    abs_level: u32,
+    ctx_gta: usize,
+    ctx_gtb: usize,
+    rparam: u32,
+    rdoq_est: &EvceRdoqEst,
+) -> i32 {
+    /* sign */
+    let mut rate = GET_IEP_RATE;
+
+    if abs_level == 1 {
+        rate += rdoq_est.gtx[ctx_gta][0];
+    } else {
+        rate += rdoq_est.gtx[ctx_gta][1];
+        if abs_level == 2 {
+            rate += rdoq_est.gtx[ctx_gtb][0];
+        } else {
+            rate += rdoq_est.gtx[ctx_gtb][1];
+            rate += get_coef_remain_bits(abs_level - 3, rparam) * GET_IEP_RATE;
+        }
+    }
+
+    rate
+}
+
+fn get_coded_level_adcc(
+    rd64_uncoded_cost: &mut i64,
+    rd64_coded_cost: &mut i64,
+    rd64_sig_cost: &mut i64,
+    level_double: i64,
+    max_abs_level: u32,
+    sig_set: usize,
+    ctx_sig: usize,
+    ctx_gta: usize,
+    ctx_gtb: usize,
+    rparam: u32,
+    q_bits: isize,
+    err_scale: i64,
+    lambda: i64,
+    rdoq_est: &EvceRdoqEst,
+) -> u32 {
+    let mut best_abs_level = 0;
+    let err1 = (level_double * err_scale) >> ERR_SCALE_PRECISION_BITS;
+
+    *rd64_uncoded_cost = err1 * err1;
+    *rd64_sig_cost = rdoq_est.sig_coeff[sig_set][ctx_sig][0] as i64 * lambda;
+    *rd64_coded_cost = *rd64_uncoded_cost + *rd64_sig_cost;
+
+    let min_abs_level = if max_abs_level > 1 {
+        max_abs_level - 1
+    } else {
+        1
+    };
+    for abs_level in (min_abs_level..=max_abs_level).rev() {
+        let i64Delta = level_double - ((abs_level as i64) << q_bits);
+        let err = (i64Delta * err_scale) >> ERR_SCALE_PRECISION_BITS;
+        let sig_cost = rdoq_est.sig_coeff[sig_set][ctx_sig][1] as i64 * lambda;
+        let dCurrCost = err * err
+            + sig_cost
+            + get_ic_rate_adcc(abs_level, ctx_gta, ctx_gtb, rparam, rdoq_est) as i64 * lambda;
+
+        if dCurrCost < *rd64_coded_cost {
+            best_abs_level = abs_level;
+            *rd64_coded_cost = dCurrCost;
+            *rd64_sig_cost = sig_cost;
+        }
+    }
+    best_abs_level
+}
+
+fn get_last_pos_rate(
+    pos: usize,
+    log2_size: u8,
+    ch_type: usize,
+    last_sig_coeff: &[[i32; 2]; NUM_CTX_LAST_SIG_COEFF],
+) -> i32 {
+    let (offset, shift) = evc_get_ctx_last_pos_xy(log2_size, ch_type);
+    let group = evc_tbl_group_idx[pos] as usize;
+    let max_group = evc_tbl_group_idx[(1 << log2_size) - 1] as usize;
+    let mut rate = 0;
+
+    for i in 0..group {
+        rate += last_sig_coeff[offset + (i >> shift)][1];
+    }
+    if group < max_group {
+        rate += last_sig_coeff[offset + (group >> shift)][0];
+    }
+    if group > 3 {
+        rate += ((group as i32 >> 1) - 1) * GET_IEP_RATE;
+    }
+
+    rate
+}
+
+fn evce_rdoq_method_adcc(
+    qp: u8,
+    d_lambda: f64,
+    is_intra: bool,
+    coef: &mut [i16],
+    log2_cuw: usize,
+    log2_cuh: usize,
+    ch_type: usize,
+    rdoq_est: &EvceRdoqEst,
+) -> u16 {
+    let qp_rem = qp as usize % 6;
+    let ns_shift = if ((log2_cuw + log2_cuh) & 1) != 0 {
+        7
+    } else {
+        0
+    };
+    let ns_scale = if ((log2_cuw + log2_cuh) & 1) != 0 {
+        181
+    } else {
+        1
+    };
+    let ns_offset = if ((log2_cuw + log2_cuh) & 1) != 0 {
+        (1 << (ns_shift - 1))
+    } else {
+        0
+    };
+    let q_value = (quant_scale[qp_rem] as u32 * ns_scale + ns_offset) >> ns_shift;
+    let log2_size = (log2_cuw + log2_cuh) >> 1;
+    let tr_shift = MAX_TX_DYNAMIC_RANGE as isize - BIT_DEPTH as isize - log2_size as isize;
+    let max_num_coef = 1 << (log2_cuw + log2_cuh);
+    let scan = &evc_scan_tbl[log2_cuw - 1][log2_cuh - 1];
+    let q_bits = QUANT_SHIFT as isize + tr_shift + (qp as isize / 6);
+    let mut nnz = 0;
+    let mut last_scan_pos = None;
+
+    let mut best_last_idx_p1 = 0;
+    let mut tmp_coef = [0i16; MAX_TR_DIM];
+    let mut tmp_level_double = [0i64; MAX_TR_DIM];
+    let mut tmp_dst_coef = [0i16; MAX_TR_DIM];
+    let mut cost_coeff = [0i64; MAX_TR_DIM];
+    let mut cost_coeff0 = [0i64; MAX_TR_DIM];
+    let mut cost_sig = [0i64; MAX_TR_DIM];
+    let lambda = (d_lambda * (1 << SCALE_BITS) as f64 + 0.5) as i64;
+    let err_scale = err_scale_tbl[qp_rem][log2_size - 1];
+    let mut d64_best_cost = 0;
+    let mut d64_base_cost = 0;
+    let mut d64_block_uncoded_cost = 0;
+
+    /* ===== quantization ===== */
+    for scan_pos in 0..max_num_coef {
+        let blk_pos = scan[scan_pos] as usize;
+        let temp_level = coef[blk_pos].abs() as i64 * q_value as i64;
+        let level_double = std::cmp::min(temp_level, i32::MAX as i64 - (1i64 << (q_bits - 1)));
+        tmp_level_double[blk_pos] = level_double;
+        let mut max_abs_level = (level_double >> q_bits) as u32;
+        let lower_int =
+            (level_double - ((max_abs_level as i64) << q_bits)) < (1i64 << (q_bits - 1));
+
+        if !lower_int {
+            max_abs_level += 1;
+        }
+
+        let err_val = (level_double * err_scale) >> ERR_SCALE_PRECISION_BITS;
+        cost_coeff0[scan_pos] = err_val * err_val;
+        d64_block_uncoded_cost += err_val * err_val;
+        tmp_coef[blk_pos] = if coef[blk_pos] > 0 {
+            max_abs_level as i16
+        } else {
+            -(max_abs_level as i16)
+        };
+        if max_abs_level != 0 {
+            last_scan_pos = Some(scan_pos);
+        }
+    }
+
+    for v in &mut coef[0..max_num_coef] {
+        *v = 0;
+    }
+
+    let last_scan_pos = match last_scan_pos {
+        Some(pos) => pos,
+        None => return nnz,
+    };
+
+    if !is_intra && ch_type == Y_C {
+        d64_best_cost = d64_block_uncoded_cost + (rdoq_est.cbf_all[0] * lambda);
+        d64_base_cost = rdoq_est.cbf_all[1] * lambda;
+    } else if ch_type == Y_C {
+        d64_best_cost = d64_block_uncoded_cost + (rdoq_est.cbf_luma[0] * lambda);
+        d64_base_cost = rdoq_est.cbf_luma[1] * lambda;
+    } else if ch_type == U_C {
+        d64_best_cost = d64_block_uncoded_cost + (rdoq_est.cbf_cb[0] * lambda);
+        d64_base_cost = rdoq_est.cbf_cb[1] * lambda;
+    } else {
+        d64_best_cost = d64_block_uncoded_cost + (rdoq_est.cbf_cr[0] * lambda);
+        d64_base_cost = rdoq_est.cbf_cr[1] * lambda;
+    }
+
+    /* ===== level decision in the reverse coding order ===== */
+    let w_mask = (1 << log2_cuw) - 1;
+    let (log2_w, log2_h) = (log2_cuw as u8, log2_cuh as u8);
+    let sig_set = evc_get_sig_coeff_set(log2_w, log2_h, ch_type);
+    let ctx_gtx_offset = if ch_type == Y_C { 0 } else { NUM_CTX_GTX_LUMA };
+    for scan_pos in (0..=last_scan_pos).rev() {
+        let blk_pos = scan[scan_pos] as usize;
+        let ctx_sig = evc_get_ctx_sig_coeff_inc(&tmp_dst_coef, blk_pos, log2_w, log2_h, ch_type);
+        let ctx_gta = ctx_gtx_offset
+            + evc_get_ctx_gtx_inc(&tmp_dst_coef, blk_pos, log2_w, log2_h, ch_type, 1);
+        let ctx_gtb = ctx_gtx_offset
+            + evc_get_ctx_gtx_inc(&tmp_dst_coef, blk_pos, log2_w, log2_h, ch_type, 2);
+        let rparam = evc_get_rice_para(&tmp_dst_coef, blk_pos, log2_w, log2_h, 3);
+        let mut d64_uncoded_cost = 0;
+
+        let level = get_coded_level_adcc(
+            &mut d64_uncoded_cost,
+            &mut cost_coeff[scan_pos],
+            &mut cost_sig[scan_pos],
+            tmp_level_double[blk_pos],
+            tmp_coef[blk_pos].abs() as u32,
+            sig_set,
+            ctx_sig,
+            ctx_gta,
+            ctx_gtb,
+            rparam,
+            q_bits,
+            err_scale,
+            lambda,
+            rdoq_est,
+        );
+        tmp_dst_coef[blk_pos] = if tmp_coef[blk_pos] < 0 {
+            -(level as i16)
+        } else {
+            level as i16
+        };
+    }
+
+    /* ===== choose the last significant position ===== */
+    let mut d64_uncoded_rest = d64_block_uncoded_cost;
+    for scan_pos in 0..=last_scan_pos {
+        let blk_pos = scan[scan_pos] as usize;
+        d64_uncoded_rest -= cost_coeff0[scan_pos];
+
+        if tmp_dst_coef[blk_pos] != 0 {
+            let rate_last = get_last_pos_rate(
+                blk_pos & w_mask,
+                log2_cuw as u8,
+                ch_type,
+                &rdoq_est.last_sig_coeff_x,
+            ) + get_last_pos_rate(
+                blk_pos >> log2_cuw,
+                log2_cuh as u8,
+                ch_type,
+                &rdoq_est.last_sig_coeff_y,
+            );
+            let d64_cur_is_last_cost = d64_base_cost + cost_coeff[scan_pos] - cost_sig[scan_pos]
+                + rate_last as i64 * lambda
+                + d64_uncoded_rest;
+
+            if d64_cur_is_last_cost < d64_best_cost {
+                d64_best_cost = d64_cur_is_last_cost;
+                best_last_idx_p1 = scan_pos + 1;
+            }
+        }
+        d64_base_cost += cost_coeff[scan_pos];
+    }
+
+    /* ===== clean uncoded coeficients ===== */
+    for scan_pos in 0..max_num_coef {
+        let blk_pos = scan[scan_pos] as usize;
+
+        if scan_pos < best_last_idx_p1 {
+            if tmp_dst_coef[blk_pos] != 0 {
+                nnz += 1;
+            }
+        } else {
+            tmp_dst_coef[blk_pos] = 0;
+        }
+
+        coef[blk_pos] = tmp_dst_coef[blk_pos];
+    }
+
+    nnz
+}
+
 fn evce_quant_nnz(
     qp: u8,
     lambda: f64,
@@ -1042,6 +1397,8 @@ fn evce_quant_nnz(
     scale: u16,
     ch_type: usize,
     slice_type: SliceType,
+    cm_init: bool,
+    adcc: bool,
     rdqo_est: &EvceRdoqEst,
 ) -> u16 {
     let mut nnz = 0;
@@ -1088,9 +1445,15 @@ fn evce_quant_nnz(
     }
 
     if USE_RDOQ {
-        nnz = evce_rdoq_run_length_cc(
-            qp, lambda, is_intra, coef, log2_cuw, log2_cuh, ch_type, rdqo_est,
-        );
+        nnz = if adcc {
+            evce_rdoq_method_adcc(
+                qp, lambda, is_intra, coef, log2_cuw, log2_cuh, ch_type, rdqo_est,
+            )
+        } else {
+            evce_rdoq_run_length_cc(
+                qp, lambda, is_intra, coef, log2_cuw, log2_cuh, ch_type, cm_init, rdqo_est,
+            )
+        };
     } else {
         let offset = if slice_type == SliceType::EVC_ST_I {
             171
@@ -1120,15 +1483,28 @@ fn evce_tq_nnz(
     slice_type: SliceType,
     ch_type: usize,
     is_intra: bool,
+    iqt: bool,
+    cm_init: bool,
+    adcc: bool,
+    tr_h: u8,
+    tr_v: u8,
     rdqo_est: &EvceRdoqEst,
 ) -> u16 {
-    evce_trans(coef, log2_cuw, log2_cuh);
+    if tr_h != DCT2 || tr_v != DCT2 {
+        evce_trans_mat(coef, log2_cuw, log2_cuh, tr_h, tr_v);
+    } else {
+        evce_trans(coef, log2_cuw, log2_cuh);
+    }
 
     return evce_quant_nnz(
-        qp, lambda, is_intra, coef, log2_cuw, log2_cuh, scale, ch_type, slice_type, rdqo_est,
+        qp, lambda, is_intra, coef, log2_cuw, log2_cuh, scale, ch_type, slice_type, cm_init, adcc,
+        rdqo_est,
     );
 }
 
+/* transform and quantization of a CU. The residual of an inter ATS CU is only
+coded in its sub-block, whose coefficients are packed at the start of each
+component buffer */
 pub(crate) fn evce_sub_block_tq(
     coef: &mut CUBuffer<i16>,
     log2_cuw: usize,
@@ -1144,6 +1520,13 @@ pub(crate) fn evce_sub_block_tq(
     lambda_v: f64,
     mut run_stats: u8,
     rdqo_est: &EvceRdoqEst,
+    iqt: bool,
+    cm_init: bool,
+    adcc: bool,
+    ats_intra_cu: bool,
+    ats_mode: u8,
+    ats_inter_info: u8,
+    chroma_format_idc: u8,
 ) -> u16 {
     run_stats = evc_get_run(run_stats);
     let run = [run_stats & 1, (run_stats >> 1) & 1, (run_stats >> 2) & 1];
@@ -1153,21 +1536,60 @@ pub(crate) fn evce_sub_block_tq(
 
     for c in 0..N_C {
         if run[c] != 0 {
-            let chroma = if c > 0 { 1 } else { 0 };
-            let pos_sub_x = 0;
-            let pos_sub_y = 0;
+            let (w_shift, h_shift) = if c > 0 {
+                (
+                    GET_CHROMA_W_SHIFT(chroma_format_idc),
+                    GET_CHROMA_H_SHIFT(chroma_format_idc),
+                )
+            } else {
+                (0, 0)
+            };
+            let mut log2_w = log2_cuw - w_shift;
+            let mut log2_h = log2_cuh - h_shift;
+            let (tr_h, tr_v) = if c == Y_C {
+                evc_get_tr_type(
+                    ats_intra_cu,
+                    ats_mode,
+                    ats_inter_info,
+                    log2_cuw as u8,
+                    log2_cuh as u8,
+                )
+            } else {
+                (DCT2, DCT2)
+            };
+
+            if ats_inter_info != 0 {
+                let (x, y, log2_tw, log2_th) =
+                    evc_get_ats_inter_trs(ats_inter_info, log2_w as u8, log2_h as u8);
+                let (tw, th) = (1usize << log2_tw, 1usize << log2_th);
+                let w = 1usize << log2_w;
+
+                let mut tb = [0i16; MAX_TR_DIM];
+                for j in 0..th {
+                    tb[j * tw..(j + 1) * tw]
+                        .copy_from_slice(&coef.data[c][(y + j) * w + x..(y + j) * w + x + tw]);
+                }
+                coef.data[c][..tw * th].copy_from_slice(&tb[..tw * th]);
+                log2_w = log2_tw as usize;
+                log2_h = log2_th as usize;
+            }
 
             let scale = quant_scale[qp[c as usize] as usize % 6];
             nnz[c] = evce_tq_nnz(
                 qp[c],
                 lambda[c],
                 &mut coef.data[c],
-                log2_cuw - chroma,
-                log2_cuh - chroma,
+                log2_w,
+                log2_h,
                 scale,
                 slice_type,
                 c,
                 is_intra,
+                iqt,
+                cm_init,
+                adcc,
+                tr_h,
+                tr_v,
                 rdqo_est,
             );
         } else {
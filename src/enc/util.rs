@@ -24,7 +24,7 @@ pub(crate) fn biari_no_bits(symbol: usize, cm: SBAC_CTX_MODEL) -> i32 {
     entropy_bits[(state as usize) << 1]
 }
 
-impl EvceCtx {
+impl EvceTask {
     pub(crate) fn evce_set_qp(&mut self, qp: u8) {
         self.core.qp = qp;
         self.core.qp_y = GET_LUMA_QP(self.core.qp as i8) as u8;
@@ -47,9 +47,22 @@ impl EvceCtx {
     }
 }
 
-pub(crate) fn evc_check_split_mode(split_allow: &mut [bool]) {
-    split_allow[SplitMode::NO_SPLIT as usize] = false;
-    split_allow[SplitMode::SPLIT_QUAD as usize] = true;
+/* order in which the split modes of a block are tried */
+pub(crate) fn evc_split_get_split_rdo_order(cuw: u16, cuh: u16) -> [SplitMode; MAX_SPLIT_NUM] {
+    let mut splits = [SplitMode::NO_SPLIT; MAX_SPLIT_NUM];
+
+    if cuw < cuh {
+        splits[1] = SplitMode::SPLIT_BI_HOR;
+        splits[2] = SplitMode::SPLIT_BI_VER;
+    } else {
+        splits[1] = SplitMode::SPLIT_BI_VER;
+        splits[2] = SplitMode::SPLIT_BI_HOR;
+    }
+    splits[3] = SplitMode::SPLIT_TRI_VER;
+    splits[4] = SplitMode::SPLIT_TRI_HOR;
+    splits[5] = SplitMode::SPLIT_QUAD;
+
+    splits
 }
 
 pub(crate) fn evc_get_avail_block(
@@ -137,6 +150,7 @@ pub(crate) fn evce_diff_pred(
     planes: &[Plane<pel>],
     pred: &CUBuffer<pel>,
     diff: &mut CUBuffer<i16>,
+    chroma_format_idc: u8,
 ) {
     let mut cuw = 1 << log2_cuw;
     let mut cuh = 1 << log2_cuh;
@@ -152,12 +166,18 @@ pub(crate) fn evce_diff_pred(
         &mut diff.data[Y_C],
     );
 
-    cuw >>= 1;
-    cuh >>= 1;
-    x >>= 1;
-    y >>= 1;
-    log2_cuw -= 1;
-    log2_cuh -= 1;
+    if chroma_format_idc == 0 {
+        return;
+    }
+
+    let w_shift = GET_CHROMA_W_SHIFT(chroma_format_idc);
+    let h_shift = GET_CHROMA_H_SHIFT(chroma_format_idc);
+    cuw >>= w_shift;
+    cuh >>= h_shift;
+    x >>= w_shift;
+    y >>= h_shift;
+    log2_cuw -= w_shift;
+    log2_cuh -= h_shift;
 
     /* U */
     evce_diff_16b(
@@ -187,6 +207,7 @@ pub(crate) fn copy_tu_from_cu(
     cu_resi: &CUBuffer<i16>,
     log2_cuw: usize,
     log2_cuh: usize,
+    chroma_format_idc: u8,
 ) {
     let cuwh = (1 << log2_cuw) * (1 << log2_cuh);
 
@@ -194,7 +215,8 @@ pub(crate) fn copy_tu_from_cu(
     tu_resi.data[Y_C][0..cuwh].copy_from_slice(&cu_resi.data[Y_C][0..cuwh]);
 
     //UV
-    let cuwh = cuwh >> 2;
+    let cuwh =
+        cuwh >> (GET_CHROMA_W_SHIFT(chroma_format_idc) + GET_CHROMA_H_SHIFT(chroma_format_idc));
     tu_resi.data[U_C][0..cuwh].copy_from_slice(&cu_resi.data[U_C][0..cuwh]);
     tu_resi.data[V_C][0..cuwh].copy_from_slice(&cu_resi.data[V_C][0..cuwh]);
 }
@@ -239,7 +261,9 @@ pub(crate) fn get_exp_golomb_bits(abs_mvd: u32) -> u32 {
     return bits;
 }
 
-pub(crate) fn get_mv_bits(mvd_x: i16, mvd_y: i16, num_refp: u8, refi: i8) -> u32 {
+/* bits of an mvd signalled at the motion vector resolution mvr_idx */
+pub(crate) fn get_mv_bits(mvd_x: i16, mvd_y: i16, num_refp: u8, refi: i8, mvr_idx: u8) -> u32 {
+    let (mvd_x, mvd_y) = (mvd_x >> mvr_idx, mvd_y >> mvr_idx);
     let mut bits = if mvd_x > 2048 || mvd_x <= -2048 {
         get_exp_golomb_bits(mvd_x.abs() as u32)
     } else {
@@ -255,6 +279,47 @@ pub(crate) fn get_mv_bits(mvd_x: i16, mvd_y: i16, num_refp: u8, refi: i8) -> u32
     bits
 }
 
+/* control point mvds of an affine model: the mvd of the first vertex is
+also a predictor for the other vertices */
+pub(crate) fn get_affine_mvd(
+    mv: &[[i16; MV_D]; VER_NUM],
+    mvp: &[[i16; MV_D]; VER_NUM],
+    vertex_num: usize,
+) -> [[i16; MV_D]; VER_NUM] {
+    let mut mvd = [[0i16; MV_D]; VER_NUM];
+    for vertex in 0..vertex_num {
+        for c in 0..MV_D {
+            mvd[vertex][c] = mv[vertex][c].wrapping_sub(mvp[vertex][c]);
+            if vertex != 0 {
+                mvd[vertex][c] = mvd[vertex][c].wrapping_sub(mvd[0][c]);
+            }
+        }
+    }
+    mvd
+}
+
+pub(crate) fn get_affine_mv_bits(
+    mv: &[[i16; MV_D]; VER_NUM],
+    mvp: &[[i16; MV_D]; VER_NUM],
+    vertex_num: usize,
+    num_refp: u8,
+    refi: i8,
+) -> u32 {
+    let mvd = get_affine_mvd(mv, mvp, vertex_num);
+    let mut bits = evce_tbl_refi_bits[num_refp as usize][refi as usize] as u32;
+    for v in &mvd[..vertex_num] {
+        bits += get_mv_bits(
+            v[MV_X].max(-i16::MAX),
+            v[MV_Y].max(-i16::MAX),
+            num_refp,
+            refi,
+            0,
+        );
+        bits -= evce_tbl_refi_bits[num_refp as usize][refi as usize] as u32;
+    }
+    bits
+}
+
 #[inline]
 pub(crate) fn MV_COST(lambda_mv: u32, mv_bits: u32) -> u32 {
     (lambda_mv * mv_bits + (1 << 15)) >> 16
@@ -1,6 +1,7 @@
 use super::def::*;
 use super::region::*;
 use super::tbl::*;
+use super::util::*;
 
 pub(crate) fn evc_get_mpm_b(
     x_scu: u16,
@@ -17,18 +18,213 @@ pub(crate) fn evc_get_mpm_b(
         && map_scu[(scup - 1) as usize].GET_IF() != 0
         && map_scu[(scup - 1) as usize].GET_COD() != 0
     {
-        ipm_l = (map_ipm[(scup - 1) as usize] as i8 + 1) as usize;
+        ipm_l = (map_ipm[(scup - 1) as usize].0 + 1) as usize;
     }
     if y_scu > 0
         && map_scu[(scup - w_scu as u32) as usize].GET_IF() != 0
         && map_scu[(scup - w_scu as u32) as usize].GET_COD() != 0
     {
-        ipm_u = (map_ipm[(scup - w_scu as u32) as usize] as i8 + 1) as usize;
+        ipm_u = (map_ipm[(scup - w_scu as u32) as usize].0 + 1) as usize;
     }
 
     &evey_tbl_mpm[ipm_l as usize][ipm_u as usize]
 }
 
+/* the luma modes of the main profile in coding order: the two most probable
+modes, the secondary (pims) modes and then the remaining modes */
+pub(crate) fn evc_get_mpm(
+    x_scu: u16,
+    y_scu: u16,
+    cuw: u16,
+    map_scu: &[MCU],
+    map_ipm: &[IntraPredDir],
+    scup: u32,
+    w_scu: u16,
+    mpm: &mut [u8],
+) {
+    /* the main directions filling up the secondary modes */
+    const IPD_PIMS_FILL: [i32; 8] = [12, 24, 18, 1, 6, 30, 16, 20];
+
+    let scup = scup as usize;
+    let w_scu = w_scu as usize;
+    let is_avail = |pos: usize| map_scu[pos].GET_IF() != 0 && map_scu[pos].GET_COD() != 0;
+
+    let avail_l = x_scu > 0 && is_avail(scup - 1);
+    let avail_u = y_scu > 0 && is_avail(scup - w_scu);
+    let pos_r = scup + (cuw as usize >> MIN_CU_LOG2);
+    let avail_r = x_scu as usize + (cuw as usize >> MIN_CU_LOG2) < w_scu && is_avail(pos_r);
+    let ipm_l = if avail_l {
+        map_ipm[scup - 1].0 as i32
+    } else {
+        0
+    };
+    let ipm_u = if avail_u {
+        map_ipm[scup - w_scu].0 as i32
+    } else {
+        0
+    };
+    let ipm_r = if avail_r { map_ipm[pos_r].0 as i32 } else { 0 };
+
+    /* the right neighbour takes the place of a missing one, a third distinct
+    mode only steers the secondary modes */
+    let (mut a, mut b, mut third) = (ipm_l, ipm_u, -1);
+    if avail_r {
+        if avail_l && avail_u {
+            if ipm_l == ipm_u {
+                b = ipm_r;
+            } else if ipm_r != ipm_l && ipm_r != ipm_u {
+                third = ipm_r;
+            }
+        } else if avail_l {
+            b = ipm_r;
+        } else {
+            a = ipm_r;
+        }
+    }
+
+    let mut m0 = std::cmp::min(a, b);
+    let mut m1 = std::cmp::max(a, b);
+    if m0 == m1 {
+        m0 = IntraPredDir::IPD_DC.0 as i32;
+        if m1 == IntraPredDir::IPD_DC.0 as i32 {
+            m1 = IntraPredDir::IPD_BI.0 as i32;
+        }
+    }
+
+    let mut pims = [0i32; IPD_PIMS_CNT];
+    let mut cnt = 0;
+    let mut add = |m: i32| {
+        if cnt < IPD_PIMS_CNT && m != m0 && m != m1 && !pims[..cnt].contains(&m) {
+            pims[cnt] = m;
+            cnt += 1;
+        }
+    };
+    /* the angular neighbours of a mode, and the mode halfway between two */
+    let n0 = |m: i32| if m <= 4 { m + 1 } else { m - 2 };
+    let n1 = |m: i32| if m >= 31 { m - 1 } else { m + 2 };
+    let avg = |a: i32, b: i32| (a + b + 1) >> 1;
+    let is_ang = |m: i32| m > IntraPredDir::IPD_BI.0 as i32;
+
+    if third >= 0 && is_ang(m0) {
+        let r = third;
+        if is_ang(r) {
+            add(2);
+            add(0);
+            add(r);
+            for &m in [m0, m1, r].iter() {
+                add(n0(m));
+                add(n1(m));
+            }
+            let s0 = std::cmp::min(r, m0);
+            let s2 = std::cmp::max(r, m1);
+            let s1 = m0 + m1 + r - s0 - s2;
+            add(avg(s0, s1));
+            add(avg(s1, s2));
+        } else {
+            add(r);
+            add(if r == 2 { 0 } else { 2 });
+            for &m in [m0, m1].iter() {
+                add(n0(m));
+                add(n1(m));
+            }
+            let v = avg(m0, m1);
+            add(v);
+            add(avg(v, m0));
+            add(avg(v, m1));
+        }
+    } else if third >= 0 && is_ang(m1) && is_ang(third) {
+        let r = third;
+        add(2);
+        add(0);
+        add(1);
+        add(r);
+        for &m in [r, m1].iter() {
+            add(n0(m));
+            add(n1(m));
+        }
+        let v = avg(m1, r);
+        add(v);
+        add(avg(v, r));
+        add(avg(v, m1));
+    } else if third >= 0 && is_ang(third) {
+        let r = third;
+        add(2);
+        add(0);
+        add(1);
+        add(r);
+        add(n0(r));
+        add(n1(r));
+        for &m in IPD_PIMS_FILL.iter() {
+            add(m);
+        }
+        add(8);
+        add(28);
+    } else if is_ang(m0) {
+        add(2);
+        add(0);
+        for &m in [m0, m1].iter() {
+            add(n0(m));
+            add(n1(m));
+        }
+        let v = avg(m0, m1);
+        add(v);
+        add(avg(v, m0));
+        add(avg(v, m1));
+    } else if !is_ang(m1) {
+        add(2);
+        add(0);
+        add(1);
+    } else {
+        /* a single angular mode */
+        let x = m1;
+        add(2);
+        add(0);
+        add(1);
+        if x >= 31 {
+            for &m in [if x == 32 { 31 } else { 32 }, 30, 29, 28, 24, 18].iter() {
+                add(m);
+            }
+        } else if x <= 4 {
+            for &m in [if x == 3 { 4 } else { 3 }, 5, 6, 7, 12, 18].iter() {
+                add(m);
+            }
+        } else {
+            add(x + 2);
+            add(x - 2);
+            add(x + 1);
+            add(x - 1);
+            if (13..=23).contains(&x) {
+                add(x - 5);
+                add(x + 5);
+            } else if x < 13 {
+                add(x + 5);
+                add(x + 10);
+            } else {
+                add(x - 5);
+                add(x - 10);
+            }
+        }
+    }
+    for &m in IPD_PIMS_FILL.iter() {
+        add(m);
+    }
+
+    /* most probable modes, secondary modes and the remaining ones */
+    let mut used = [false; IPD_CNT];
+    let mut cnt = 0;
+    for &m in [m0, m1]
+        .iter()
+        .chain(pims.iter())
+        .chain(evc_tbl_ipred_rem_order.iter())
+    {
+        if !used[m as usize] {
+            used[m as usize] = true;
+            mpm[cnt] = m as u8;
+            cnt += 1;
+        }
+    }
+}
+
 pub(crate) fn evc_get_nbr_b(
     x: usize,
     y: usize,
@@ -36,34 +232,34 @@ pub(crate) fn evc_get_nbr_b(
     cuh: usize,
     src: &PlaneRegion<'_, pel>,
     avail_cu: u16,
-    nb: &mut [pel], //[[pel; cu_size*4+1]
+    nb: &mut [pel], //[left: cuw+cuh][up_left: 1][up: cuw+cuh]
     scup: usize,
     map_scu: &[MCU],
     w_scu: usize,
     h_scu: usize,
     ch_type: usize,
     constrained_intra_pred: bool,
+    chroma_format_idc: u8,
 ) {
-    let scuw = if ch_type == Y_C {
-        cuw >> MIN_CU_LOG2
-    } else {
-        cuw >> (MIN_CU_LOG2 - 1)
-    };
-    let scuh = if ch_type == Y_C {
-        cuh >> MIN_CU_LOG2
+    /* the availability units are the 4x4 luma blocks, subsampled for chroma */
+    let (w_shift, h_shift) = if ch_type == Y_C {
+        (0, 0)
     } else {
-        cuh >> (MIN_CU_LOG2 - 1)
+        (
+            GET_CHROMA_W_SHIFT(chroma_format_idc),
+            GET_CHROMA_H_SHIFT(chroma_format_idc),
+        )
     };
-    let unit_size = if ch_type == Y_C {
-        MIN_CU_SIZE
-    } else {
-        MIN_CU_SIZE >> 1
-    };
-    let x_scu = PEL2SCU(if ch_type == Y_C { x } else { x << 1 });
-    let y_scu = PEL2SCU(if ch_type == Y_C { y } else { y << 1 });
+    let unit_w = MIN_CU_SIZE >> w_shift;
+    let unit_h = MIN_CU_SIZE >> h_shift;
+    /* units of the upper row and of the left column covering cuw + cuh samples */
+    let num_up = (cuw + cuh + unit_w - 1) >> (MIN_CU_LOG2 - w_shift);
+    let num_le = (cuw + cuh + unit_h - 1) >> (MIN_CU_LOG2 - h_shift);
+    let x_scu = PEL2SCU(x << w_shift);
+    let y_scu = PEL2SCU(y << h_shift);
 
     {
-        let up_left = &mut nb[cuh << 1..];
+        let up_left = &mut nb[cuw + cuh..];
         if IS_AVAIL(avail_cu, AVAIL_UP_LE)
             && (!constrained_intra_pred || map_scu[scup - w_scu - 1].GET_IF() != 0)
         {
@@ -74,17 +270,17 @@ pub(crate) fn evc_get_nbr_b(
     }
 
     {
-        let up = &mut nb[(cuh << 1) + 1..];
-        for i in 0..(scuw + scuh) {
+        let up = &mut nb[cuw + cuh + 1..];
+        for i in 0..num_up {
             let is_avail = (y_scu > 0) && (x_scu + i < w_scu);
             if is_avail
                 && map_scu[scup - w_scu + i].GET_COD() != 0
                 && (!constrained_intra_pred || map_scu[scup - w_scu + i].GET_IF() != 0)
             {
-                up[i * unit_size..(i + 1) * unit_size]
-                    .copy_from_slice(&src[y - 1][x + i * unit_size..x + (i + 1) * unit_size]);
+                up[i * unit_w..(i + 1) * unit_w]
+                    .copy_from_slice(&src[y - 1][x + i * unit_w..x + (i + 1) * unit_w]);
             } else {
-                for v in up[i * unit_size..(i + 1) * unit_size].iter_mut() {
+                for v in up[i * unit_w..(i + 1) * unit_w].iter_mut() {
                     *v = 1 << (BIT_DEPTH - 1) as pel;
                 }
             }
@@ -92,18 +288,20 @@ pub(crate) fn evc_get_nbr_b(
     }
 
     {
-        let left = &mut nb[..(cuh << 1)];
-        for i in 0..(scuh + scuw) {
+        let left = &mut nb[..cuw + cuh];
+        for i in 0..num_le {
+            /* the last unit may reach past the cuw + cuh samples of a 4:2:2 chroma block */
+            let size = unit_h.min(cuw + cuh - i * unit_h);
             let is_avail = (x_scu > 0) && (y_scu + i < h_scu);
             if is_avail
                 && map_scu[scup - 1 + i * w_scu].GET_COD() != 0
                 && (!constrained_intra_pred || map_scu[scup - 1 + i * w_scu].GET_IF() != 0)
             {
-                for j in 0..unit_size {
-                    left[i * unit_size + j] = src[y + i * unit_size + j][x - 1];
+                for j in 0..size {
+                    left[i * unit_h + j] = src[y + i * unit_h + j][x - 1];
                 }
             } else {
-                for v in left[i * unit_size..(i + 1) * unit_size].iter_mut() {
+                for v in left[i * unit_h..i * unit_h + size].iter_mut() {
                     *v = 1 << (BIT_DEPTH - 1) as pel;
                 }
             }
@@ -111,6 +309,125 @@ pub(crate) fn evc_get_nbr_b(
     }
 }
 
+/* the reference lines of the main profile: the left column, the upper row and
+the right column, each one starting NB_ORG samples in so that the samples
+before its first one can be kept as well */
+pub(crate) const NB_LE: usize = 0;
+pub(crate) const NB_UP: usize = 1;
+pub(crate) const NB_RI: usize = 2;
+pub(crate) const NB_ORG: usize = MAX_CU_SIZE;
+pub(crate) const NB_LEN: usize = MAX_CU_SIZE * 3;
+pub(crate) type EvcNbLines = [[pel; NB_LEN]; 3];
+
+pub(crate) fn evc_get_nbr(
+    x: usize,
+    y: usize,
+    cuw: usize,
+    cuh: usize,
+    src: &PlaneRegion<'_, pel>,
+    avail_cu: u16,
+    nb: &mut EvcNbLines,
+    scup: usize,
+    map_scu: &[MCU],
+    w_scu: usize,
+    h_scu: usize,
+    ch_type: usize,
+    constrained_intra_pred: bool,
+    chroma_format_idc: u8,
+) {
+    /* the availability units are the 4x4 luma blocks, subsampled for chroma */
+    let (w_shift, h_shift) = if ch_type == Y_C {
+        (0, 0)
+    } else {
+        (
+            GET_CHROMA_W_SHIFT(chroma_format_idc),
+            GET_CHROMA_H_SHIFT(chroma_format_idc),
+        )
+    };
+    let unit_w = MIN_CU_SIZE >> w_shift;
+    let unit_h = MIN_CU_SIZE >> h_shift;
+    let scuw = cuw >> (MIN_CU_LOG2 - w_shift);
+    /* units of the upper row and of the left and right columns covering cuw + cuh samples */
+    let num_up = (cuw + cuh + unit_w - 1) >> (MIN_CU_LOG2 - w_shift);
+    let num_le = (cuw + cuh + unit_h - 1) >> (MIN_CU_LOG2 - h_shift);
+    let x_scu = PEL2SCU(x << w_shift);
+    let y_scu = PEL2SCU(y << h_shift);
+    let is_avail = |pos: usize| {
+        map_scu[pos].GET_COD() != 0 && (!constrained_intra_pred || map_scu[pos].GET_IF() != 0)
+    };
+
+    let o = NB_ORG;
+    let [left, up, right] = nb;
+
+    if IS_AVAIL(avail_cu, AVAIL_UP_LE)
+        && (!constrained_intra_pred || map_scu[scup - w_scu - 1].GET_IF() != 0)
+    {
+        up[o - 1..o - 1 + cuw].copy_from_slice(&src[y - 1][x - 1..x - 1 + cuw]);
+    } else {
+        up[o - 1] = (1 << (BIT_DEPTH - 1)) as pel;
+    }
+
+    /* upper row, an unavailable unit repeats the sample before it */
+    for i in 0..num_up {
+        let pos = o + i * unit_w;
+        if y_scu > 0 && x_scu + i < w_scu && is_avail(scup - w_scu + i) {
+            up[pos..pos + unit_w]
+                .copy_from_slice(&src[y - 1][x + i * unit_w..x + (i + 1) * unit_w]);
+        } else {
+            let v = up[pos - 1];
+            up[pos..pos + unit_w].fill(v);
+        }
+    }
+
+    /* the upper row continues to the left over the top-left sample, an
+    unavailable unit repeats the sample after it */
+    if x_scu > 0 {
+        for k in 0..cuh >> (MIN_CU_LOG2 - w_shift) {
+            let pos = o - (k + 1) * unit_w;
+            if y_scu > 0 && x_scu > k && is_avail(scup - w_scu - 1 - k) {
+                up[pos..pos + unit_w]
+                    .copy_from_slice(&src[y - 1][x - (k + 1) * unit_w..x - k * unit_w]);
+            } else {
+                let v = up[pos + unit_w];
+                up[pos..pos + unit_w].fill(v);
+            }
+        }
+    } else {
+        let v = up[o];
+        up[o - cuh..o].fill(v);
+    }
+
+    /* left column, an unavailable unit repeats the sample above it */
+    left[o - 1] = up[o - 1];
+    for i in 0..num_le {
+        let pos = o + i * unit_h;
+        if x_scu > 0 && y_scu + i < h_scu && is_avail(scup - 1 + i * w_scu) {
+            for j in 0..unit_h {
+                left[pos + j] = src[y + i * unit_h + j][x - 1];
+            }
+        } else {
+            let v = left[pos - 1];
+            left[pos..pos + unit_h].fill(v);
+        }
+    }
+    left[o - 2] = left[o - 1];
+
+    /* right column, starting below the top-right sample */
+    right[o - 1] = up[o + cuw];
+    for i in 0..num_le {
+        let pos = o + i * unit_h;
+        if x_scu + scuw < w_scu && y_scu + i < h_scu && is_avail(scup + scuw + i * w_scu) {
+            for j in 0..unit_h {
+                right[pos + j] = src[y + i * unit_h + j][x + cuw];
+            }
+        } else {
+            let v = right[pos - 1];
+            right[pos..pos + unit_h].fill(v);
+        }
+    }
+    right[o - 2] = right[o - 1];
+}
+
 /* intra prediction for baseline profile */
 pub(crate) fn evc_ipred_b(
     src_le: &[pel],
@@ -131,6 +448,92 @@ pub(crate) fn evc_ipred_b(
     }
 }
 
+/* intra prediction for main profile */
+pub(crate) fn evc_ipred(
+    nb: &EvcNbLines,
+    avail_lr: u16,
+    dst: &mut [pel],
+    ipm: IntraPredDir,
+    w: usize,
+    h: usize,
+) {
+    let le = &nb[NB_LE][NB_ORG..];
+    let up = &nb[NB_UP][NB_ORG..];
+    let ri = &nb[NB_RI][NB_ORG..];
+
+    if ipm.0 > IntraPredDir::IPD_BI.0
+        && ipm != IntraPredDir::IPD_VER
+        && ipm != IntraPredDir::IPD_HOR
+    {
+        ipred_ang(nb, avail_lr, dst, ipm, w, h);
+    } else if ipm == IntraPredDir::IPD_VER {
+        ipred_vert(up, dst, w, h);
+    } else if avail_lr == LR_01 || (avail_lr == LR_11 && ipm == IntraPredDir::IPD_PLN) {
+        /* from the right column the prediction is the mirror image of the
+        one from the left column with the upper row mirrored as well */
+        let mut nb_m = [[0 as pel; NB_LEN]; 3];
+        nb_m[NB_LE] = nb[NB_RI];
+        for k in 0..NB_LEN {
+            if let Some(pos) = (2 * NB_ORG + w - 1).checked_sub(k) {
+                if pos < NB_LEN {
+                    nb_m[NB_UP][k] = nb[NB_UP][pos];
+                }
+            }
+        }
+        evc_ipred(&nb_m, LR_10, dst, ipm, w, h);
+        for row in dst[..w * h].chunks_mut(w) {
+            row.reverse();
+        }
+    } else {
+        match ipm {
+            IntraPredDir::IPD_DC if avail_lr == LR_11 => ipred_dc_lr(le, up, ri, dst, w, h),
+            IntraPredDir::IPD_DC => ipred_dc(le, up, dst, w, h),
+            IntraPredDir::IPD_HOR if avail_lr == LR_11 => ipred_hor_lr(le, ri, dst, w, h),
+            IntraPredDir::IPD_HOR => ipred_hor_b(le, dst, w, h),
+            IntraPredDir::IPD_PLN => ipred_plane(&nb[NB_LE], &nb[NB_UP], dst, w, h),
+            IntraPredDir::IPD_BI if avail_lr == LR_11 => ipred_bi_lr(le, up, ri, dst, w, h),
+            IntraPredDir::IPD_BI => ipred_bi(le, up, dst, w, h),
+            _ => print!("\n illegal intra prediction mode\n"),
+        }
+    }
+}
+
+/* intra prediction of chroma for main profile */
+pub(crate) fn evc_ipred_uv(
+    nb: &EvcNbLines,
+    avail_lr: u16,
+    dst: &mut [pel],
+    ipm_c: IntraPredDir,
+    ipm_l: IntraPredDir,
+    w: usize,
+    h: usize,
+) {
+    let ipm = match ipm_c {
+        IntraPredDir::IPD_DM_C => ipm_l,
+        IntraPredDir::IPD_BI_C => IntraPredDir::IPD_BI,
+        IntraPredDir::IPD_DC_C => IntraPredDir::IPD_DC,
+        IntraPredDir::IPD_HOR_C => IntraPredDir::IPD_HOR,
+        IntraPredDir::IPD_VER_C => IntraPredDir::IPD_VER,
+        _ => {
+            print!("\n illegal chroma intra prediction mode\n");
+            return;
+        }
+    };
+    evc_ipred(nb, avail_lr, dst, ipm, w, h);
+}
+
+/* the chroma mode of the main profile that predicts the same as DM for the
+luma mode, it is left out of the chroma mode syntax */
+pub(crate) fn evc_ipred_conv_l2c(ipm_l: IntraPredDir) -> IntraPredDir {
+    match ipm_l {
+        IntraPredDir::IPD_DC => IntraPredDir::IPD_DC_C,
+        IntraPredDir::IPD_HOR => IntraPredDir::IPD_HOR_C,
+        IntraPredDir::IPD_VER => IntraPredDir::IPD_VER_C,
+        IntraPredDir::IPD_BI => IntraPredDir::IPD_BI_C,
+        _ => IntraPredDir::IPD_INVALID,
+    }
+}
+
 fn ipred_vert(src_up: &[pel], dst: &mut [pel], w: usize, h: usize) {
     for i in 0..h {
         dst[i * w..(i + 1) * w].copy_from_slice(&src_up[0..w]);
@@ -184,3 +587,316 @@ fn ipred_ur(src_le: &[pel], src_up: &[pel], dst: &mut [pel], w: usize, h: usize)
         }
     }
 }
+
+/* 4096 / (2^k + 1), dividing by a block size plus one */
+const LUT_SIZE_PLUS1: [i32; 8] = [2048, 1365, 819, 455, 241, 124, 63, 32];
+
+/* divides the sum over w + h samples by their number */
+fn div_size_sum(sum: i32, log2_w: usize, log2_h: usize) -> i32 {
+    if log2_w > log2_h {
+        (sum * LUT_SIZE_PLUS1[log2_w - log2_h]) >> (log2_h + 12)
+    } else {
+        (sum * LUT_SIZE_PLUS1[log2_h - log2_w]) >> (log2_w + 12)
+    }
+}
+
+fn ipred_dc(src_le: &[pel], src_up: &[pel], dst: &mut [pel], w: usize, h: usize) {
+    let mut dc = ((w + h) >> 1) as i32;
+    for i in 0..h {
+        dc += src_le[i] as i32;
+    }
+    for j in 0..w {
+        dc += src_up[j] as i32;
+    }
+
+    let dc = div_size_sum(dc, evc_tbl_log2[w] as usize, evc_tbl_log2[h] as usize) as pel;
+    for v in dst[..w * h].iter_mut() {
+        *v = dc;
+    }
+}
+
+fn ipred_dc_lr(
+    src_le: &[pel],
+    src_up: &[pel],
+    src_ri: &[pel],
+    dst: &mut [pel],
+    w: usize,
+    h: usize,
+) {
+    let mut dc = ((w + (h << 1)) >> 1) as i32;
+    for i in 0..h {
+        dc += src_le[i] as i32 + src_ri[i] as i32;
+    }
+    for j in 0..w {
+        dc += src_up[j] as i32;
+    }
+
+    let dc = div_size_sum(dc, evc_tbl_log2[w] as usize, evc_tbl_log2[h] as usize + 1) as pel;
+    for v in dst[..w * h].iter_mut() {
+        *v = dc;
+    }
+}
+
+/* linear interpolation between the left and the right column */
+fn ipred_hor_lr(src_le: &[pel], src_ri: &[pel], dst: &mut [pel], w: usize, h: usize) {
+    let mult = LUT_SIZE_PLUS1[evc_tbl_log2[w] as usize];
+    for i in 0..h {
+        for j in 0..w {
+            let sum = src_le[i] as i32 * (w - j) as i32
+                + src_ri[i] as i32 * (j + 1) as i32
+                + (w >> 1) as i32;
+            dst[i * w + j] = ((sum * mult) >> 12) as pel;
+        }
+    }
+}
+
+/* takes the whole reference lines, the top-left sample is the one before the
+first one of both */
+fn ipred_plane(line_le: &[pel], line_up: &[pel], dst: &mut [pel], w: usize, h: usize) {
+    /* indexed by the log2 of the block size minus two, sizes of 2 use the
+    entry of 4 */
+    const IB_MULT: [i32; 6] = [13, 17, 5, 11, 23, 47];
+    const IB_SHIFT: [i32; 6] = [7, 10, 11, 15, 19, 23];
+
+    let le = |k: isize| line_le[(NB_ORG as isize + k) as usize] as i32;
+    let up = |k: isize| line_up[(NB_ORG as isize + k) as usize] as i32;
+
+    let w2 = (w >> 1) as isize;
+    let h2 = (h >> 1) as isize;
+    let idx_w = std::cmp::max(evc_tbl_log2[w] as usize, 2) - 2;
+    let idx_h = std::cmp::max(evc_tbl_log2[h] as usize, 2) - 2;
+
+    let mut coef_h = 0;
+    for x in 1..w2 + 1 {
+        coef_h += x as i32 * (up(w2 - 1 + x) - up(w2 - 1 - x));
+    }
+    let mut coef_v = 0;
+    for y in 1..h2 + 1 {
+        coef_v += y as i32 * (le(h2 - 1 + y) - le(h2 - 1 - y));
+    }
+
+    let a = (le(h as isize - 1) + up(w as isize - 1)) << 4;
+    let b = ((coef_h << 5) * IB_MULT[idx_w] + (1 << (IB_SHIFT[idx_w] - 1))) >> IB_SHIFT[idx_w];
+    let c = ((coef_v << 5) * IB_MULT[idx_h] + (1 << (IB_SHIFT[idx_h] - 1))) >> IB_SHIFT[idx_h];
+
+    let mut temp = a - (h2 as i32 - 1) * c - (w2 as i32 - 1) * b + 16;
+    for y in 0..h {
+        let mut temp2 = temp;
+        for x in 0..w {
+            dst[y * w + x] = EVC_CLIP3(0, (1 << BIT_DEPTH) - 1, temp2 >> 5) as pel;
+            temp2 += b;
+        }
+        temp += c;
+    }
+}
+
+fn ipred_bi(src_le: &[pel], src_up: &[pel], dst: &mut [pel], w: usize, h: usize) {
+    const TBL_WC: [i32; 6] = [-1, 341, 205, 114, 60, 31];
+
+    let ishift_x = evc_tbl_log2[w] as i32;
+    let ishift_y = evc_tbl_log2[h] as i32;
+    let ishift = std::cmp::min(ishift_x, ishift_y);
+    let ishift_xy = ishift_x + ishift_y + 1;
+    let offset = 1 << (ishift_x + ishift_y);
+
+    /* the samples after the last ones of the upper row and the left column */
+    let a = src_up[w] as i32;
+    let b = src_le[h] as i32;
+    let c = if w == h {
+        (a + b + 1) >> 1
+    } else {
+        let wc = TBL_WC[(ishift_x - ishift_y).unsigned_abs() as usize];
+        (((a << ishift_x) + (b << ishift_y)) * wc + (1 << (ishift + 9))) >> (ishift + 10)
+    };
+    let wt = (c << 1) - a - b;
+
+    let mut ref_up = [0i32; MAX_CU_SIZE];
+    let mut up = [0i32; MAX_CU_SIZE];
+    for x in 0..w {
+        up[x] = b - src_up[x] as i32;
+        ref_up[x] = (src_up[x] as i32) << ishift_y;
+    }
+
+    let mut wy = 0;
+    for y in 0..h {
+        let le = a - src_le[y] as i32;
+        let mut predx = (src_le[y] as i32) << ishift_x;
+        let mut wxy = 0;
+        for x in 0..w {
+            predx += le;
+            ref_up[x] += up[x];
+            dst[y * w + x] = (((predx << ishift_y) + (ref_up[x] << ishift_x) + wxy + offset)
+                >> ishift_xy) as pel;
+            wxy += wy;
+        }
+        wy += wt;
+    }
+}
+
+/* the average of the interpolation between the left and the right column and
+of the one between the upper row and the bottom row of the former */
+fn ipred_bi_lr(
+    src_le: &[pel],
+    src_up: &[pel],
+    src_ri: &[pel],
+    dst: &mut [pel],
+    w: usize,
+    h: usize,
+) {
+    let mult = LUT_SIZE_PLUS1[evc_tbl_log2[w] as usize];
+    let log2_h = evc_tbl_log2[h] as i32;
+    let hor = |y: usize, x: usize| {
+        let sum =
+            src_le[y] as i32 * (w - x) as i32 + src_ri[y] as i32 * (x + 1) as i32 + (w >> 1) as i32;
+        (sum * mult) >> 12
+    };
+
+    for y in 0..h {
+        for x in 0..w {
+            let ver = (src_up[x] as i32 * (h - 1 - y) as i32
+                + hor(h - 1, x) * (y + 1) as i32
+                + (h >> 1) as i32)
+                >> log2_h;
+            dst[y * w + x] = ((ver + hor(y, x) + 1) >> 1) as pel;
+        }
+    }
+}
+
+fn ipred_ang(
+    nb: &EvcNbLines,
+    avail_lr: u16,
+    dst: &mut [pel],
+    ipm: IntraPredDir,
+    w: usize,
+    h: usize,
+) {
+    let [dx, dy] = evc_tbl_ipred_dxdy[ipm.0 as usize];
+    let pos_max = (w + h - 1) as i32;
+    let avail_r = avail_lr == LR_01 || avail_lr == LR_11;
+    let w = w as i32;
+    let h = h as i32;
+
+    /* the reference line, the integer position on it with the direction the
+    taps run in and the fraction in 1/32 units, from a position in 1/1024
+    units */
+    let split = |d: i32| (d >> 10, (d >> 5) - ((d >> 10) << 5));
+
+    for j in 0..h {
+        for i in 0..w {
+            let (line, pos, step, frac) = if ipm.0 < IntraPredDir::IPD_VER.0 {
+                /* up-right, continued on the right column past the upper row */
+                let (t, frac) = split((j + 1) * dx);
+                if avail_r && i >= w - t {
+                    let (t, frac) = split((w - i) * dy);
+                    if j - t == -1 {
+                        (NB_UP, w, 1, frac)
+                    } else {
+                        (NB_RI, j - t, -1, frac)
+                    }
+                } else {
+                    (NB_UP, i + t, 1, frac)
+                }
+            } else if ipm.0 > IntraPredDir::IPD_HOR.0 {
+                /* down-left, or up-right from the right column */
+                if avail_r {
+                    let (t, frac) = split((w - i) * dy);
+                    if t > j {
+                        let (t, frac) = split((w - i) * dx);
+                        (NB_UP, i + t, 1, frac)
+                    } else {
+                        (NB_RI, j - t, -1, frac)
+                    }
+                } else {
+                    let (t, frac) = split((i + 1) * dy);
+                    (NB_LE, j + t, 1, frac)
+                }
+            } else {
+                /* up-left, on the upper row as long as it is reached before
+                the left column */
+                let (t, frac) = split((i + 1) * dy);
+                if t > j {
+                    let (t, frac) = split((j + 1) * dx);
+                    (NB_UP, i - t, -1, frac)
+                } else if avail_lr == LR_01 {
+                    let (t, frac) = split((w - i) * dy);
+                    (NB_RI, j + t, 1, frac)
+                } else {
+                    (NB_LE, j - t, -1, frac)
+                }
+            };
+
+            let filter = &evc_tbl_ipred_adi[frac as usize];
+            let mut sum = 64;
+            for k in 0..4 {
+                let mut p = std::cmp::min(pos + (k as i32 - 1) * step, pos_max);
+                if p < 0 {
+                    p = -1;
+                }
+                sum += filter[k] * nb[line][(NB_ORG as i32 + p) as usize] as i32;
+            }
+            dst[(j * w + i) as usize] = EVC_CLIP3(0, (1 << BIT_DEPTH) - 1, sum >> 7) as pel;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flat_neighbours_predict_flat() {
+        let nb: EvcNbLines = [[500; NB_LEN]; 3];
+        for (w, h) in [(8, 8), (16, 4), (4, 32)] {
+            for avail_lr in [LR_10, LR_01, LR_11] {
+                for ipm in 0..IPD_CNT as i8 {
+                    let mut dst = vec![0; w * h];
+                    evc_ipred(&nb, avail_lr, &mut dst, IntraPredDir(ipm), w, h);
+                    /* like in the reference decoder, the weight of the
+                    bottom-right corner of the bilinear mode is rounded for
+                    non-square blocks */
+                    let tol = if IntraPredDir(ipm) == IntraPredDir::IPD_BI && w != h {
+                        2
+                    } else {
+                        0
+                    };
+                    assert!(
+                        dst.iter().all(|&p| (p as i32 - 500).abs() <= tol),
+                        "mode {} {}x{} lr {}",
+                        ipm,
+                        w,
+                        h,
+                        avail_lr
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn vertical_and_horizontal() {
+        let (w, h) = (8, 4);
+        let mut nb: EvcNbLines = [[0; NB_LEN]; 3];
+        for k in 0..NB_LEN {
+            nb[NB_LE][k] = 3 * k as pel;
+            nb[NB_UP][k] = 5 * k as pel;
+            nb[NB_RI][k] = 7 * k as pel;
+        }
+
+        let mut dst = vec![0; w * h];
+        evc_ipred(&nb, LR_10, &mut dst, IntraPredDir::IPD_VER, w, h);
+        for row in dst.chunks(w) {
+            assert_eq!(row, &nb[NB_UP][NB_ORG..NB_ORG + w]);
+        }
+
+        evc_ipred(&nb, LR_10, &mut dst, IntraPredDir::IPD_HOR, w, h);
+        for (j, row) in dst.chunks(w).enumerate() {
+            assert!(row.iter().all(|&p| p == nb[NB_LE][NB_ORG + j]));
+        }
+
+        /* from the right column only */
+        evc_ipred(&nb, LR_01, &mut dst, IntraPredDir::IPD_HOR, w, h);
+        for (j, row) in dst.chunks(w).enumerate() {
+            assert!(row.iter().all(|&p| p == nb[NB_RI][NB_ORG + j]));
+        }
+    }
+}
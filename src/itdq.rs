@@ -47,28 +47,26 @@ pub(crate) fn evc_sub_block_itdq(
     qp_u: u8,
     qp_v: u8,
     flag: &[bool],
+    iqt: bool,
+    ats_intra_cu: bool,
+    ats_mode: u8,
+    ats_inter_info: u8,
+    chroma_format_idc: u8,
 ) {
-    let qp: [u8; N_C] = [qp_y, qp_u, qp_v];
-    let mut scale = 0i32;
-
     for c in 0..N_C {
-        let chroma = if c > 0 { 1 } else { 0 };
         if flag[c] {
-            scale = (evc_tbl_dq_scale_b[qp[c] as usize % 6] as i32) << (qp[c] / 6) as i32;
-
-            evc_itdq(
-                &mut coef[c],
-                (log2_cuw - chroma) as usize,
-                (log2_cuh - chroma) as usize,
-                scale,
-            );
-
-            TRACE_RESI(
+            evc_comp_itdq(
                 tracer,
+                &mut coef[c],
                 c,
-                1 << (log2_cuw - chroma) as usize,
-                1 << (log2_cuh - chroma) as usize,
-                &coef[c],
+                log2_cuw,
+                log2_cuh,
+                [qp_y, qp_u, qp_v][c],
+                iqt,
+                ats_intra_cu,
+                ats_mode,
+                ats_inter_info,
+                chroma_format_idc,
             );
         }
     }
@@ -83,33 +81,106 @@ pub(crate) fn evc_sub_block_itdq2(
     qp_u: u8,
     qp_v: u8,
     flag: &[bool],
+    iqt: bool,
+    ats_intra_cu: bool,
+    ats_mode: u8,
+    ats_inter_info: u8,
+    chroma_format_idc: u8,
 ) {
-    let qp: [u8; N_C] = [qp_y, qp_u, qp_v];
-    let mut scale = 0i32;
-
     for c in 0..N_C {
-        let chroma = if c > 0 { 1 } else { 0 };
         if flag[c] {
-            scale = (evc_tbl_dq_scale_b[qp[c] as usize % 6] as i32) << (qp[c] / 6) as i32;
-
-            evc_itdq(
-                &mut coef[tbl_cu_dim_offset[c]..],
-                (log2_cuw - chroma) as usize,
-                (log2_cuh - chroma) as usize,
-                scale,
-            );
-
-            TRACE_RESI(
+            evc_comp_itdq(
                 tracer,
+                &mut coef[tbl_cu_dim_offset[c]..],
                 c,
-                1 << (log2_cuw - chroma) as usize,
-                1 << (log2_cuh - chroma) as usize,
-                &coef[tbl_cu_dim_offset[c]..],
+                log2_cuw,
+                log2_cuh,
+                [qp_y, qp_u, qp_v][c],
+                iqt,
+                ats_intra_cu,
+                ats_mode,
+                ats_inter_info,
+                chroma_format_idc,
             );
         }
     }
 }
 
+/* inverse transform and dequantization of one component of a CU. The coefficients
+of an inter ATS CU only cover its sub-block and are packed at the start of the
+buffer, and the residual is expanded back to the CU size */
+fn evc_comp_itdq(
+    tracer: &mut Option<Tracer>,
+    coef: &mut [i16],
+    c: usize,
+    log2_cuw: u8,
+    log2_cuh: u8,
+    qp: u8,
+    iqt: bool,
+    ats_intra_cu: bool,
+    ats_mode: u8,
+    ats_inter_info: u8,
+    chroma_format_idc: u8,
+) {
+    let (w_shift, h_shift) = if c > 0 {
+        (
+            GET_CHROMA_W_SHIFT(chroma_format_idc) as u8,
+            GET_CHROMA_H_SHIFT(chroma_format_idc) as u8,
+        )
+    } else {
+        (0, 0)
+    };
+    let log2_w = log2_cuw - w_shift;
+    let log2_h = log2_cuh - h_shift;
+    let scale = if iqt {
+        (evc_tbl_dq_scale[qp as usize % 6] as i32) << (qp / 6) as i32
+    } else {
+        (evc_tbl_dq_scale_b[qp as usize % 6] as i32) << (qp / 6) as i32
+    };
+    let (tr_h, tr_v) = if c == Y_C {
+        evc_get_tr_type(ats_intra_cu, ats_mode, ats_inter_info, log2_cuw, log2_cuh)
+    } else {
+        (DCT2, DCT2)
+    };
+
+    if ats_inter_info != 0 {
+        let (x, y, log2_tw, log2_th) = evc_get_ats_inter_trs(ats_inter_info, log2_w, log2_h);
+        let (tw, th) = (1usize << log2_tw, 1usize << log2_th);
+        let w = 1usize << log2_w;
+
+        let mut tb = [0i16; MAX_TR_DIM];
+        tb[..tw * th].copy_from_slice(&coef[..tw * th]);
+        evc_itdq_ext(
+            &mut tb,
+            log2_tw as usize,
+            log2_th as usize,
+            scale,
+            iqt,
+            tr_h,
+            tr_v,
+        );
+
+        for v in &mut coef[..w << log2_h] {
+            *v = 0;
+        }
+        for j in 0..th {
+            coef[(y + j) * w + x..(y + j) * w + x + tw].copy_from_slice(&tb[j * tw..(j + 1) * tw]);
+        }
+    } else {
+        evc_itdq_ext(
+            coef,
+            log2_w as usize,
+            log2_h as usize,
+            scale,
+            iqt,
+            tr_h,
+            tr_v,
+        );
+    }
+
+    TRACE_RESI(tracer, c, 1 << log2_w as usize, 1 << log2_h as usize, coef);
+}
+
 fn evc_dquant(coef: &mut [i16], log2_w: usize, log2_h: usize, scale: i32, offset: i32, shift: u8) {
     for v in &mut coef[0..1 << (log2_w + log2_h)] {
         *v = ((*v as i64 * scale as i64 + offset as i64) >> shift)
@@ -755,6 +826,81 @@ static tbl_itxb1: [EVC_ITXB1; MAX_TR_LOG2] = [
     itx_pb2b1, itx_pb4b1, itx_pb8b1, itx_pb16b1, itx_pb32b1, itx_pb64b1,
 ];
 
+/* one inverse transform stage by matrix multiplication, for the kernels that
+have no butterfly form */
+fn itx_mat_b0(src: &[i16], dst: &mut [i32], tm: &[i16], log2_size: usize, log2_line: usize) {
+    let size = 1 << log2_size;
+    for j in 0..(1 << log2_line) {
+        for n in 0..size {
+            let mut sum = 0i64;
+            for k in 0..size {
+                let s = src[(k << log2_line) + j];
+                if s != 0 {
+                    sum += tm[k * size + n] as i64 * s as i64;
+                }
+            }
+            dst[(j << log2_size) + n] = ITX_CLIP_32(sum);
+        }
+    }
+}
+
+fn itx_mat_b1(
+    src: &[i32],
+    dst: &mut [i16],
+    tm: &[i16],
+    shift: usize,
+    log2_size: usize,
+    log2_line: usize,
+) {
+    let size = 1 << log2_size;
+    let add = if shift == 0 { 0 } else { 1 << (shift - 1) };
+    for j in 0..(1 << log2_line) {
+        for n in 0..size {
+            let mut sum = 0i64;
+            for k in 0..size {
+                sum += tm[k * size + n] as i64 * src[(k << log2_line) + j] as i64;
+            }
+            dst[(j << log2_size) + n] = ITX_CLIP((sum + add) >> shift as i64);
+        }
+    }
+}
+
+/* the improved quantization rounds and clips the result of the first stage to
+16 bits, where the baseline transform keeps it at full precision */
+fn itx_clip_stage(tb: &mut [i32], shift: usize) {
+    let add = 1 << (shift - 1);
+    for v in tb.iter_mut() {
+        *v = ITX_CLIP((*v as i64 + add) >> shift as i64) as i32;
+    }
+}
+
+fn evc_itrans_mat(coef: &mut [i16], log2_cuw: usize, log2_cuh: usize, tr_h: u8, tr_v: u8) {
+    let mut tb = Aligned::<[i32; MAX_TR_DIM]>::uninitialized(); /* temp buffer */
+    itx_mat_b0(
+        coef,
+        &mut tb.data,
+        evc_get_tr_kernel(tr_v, log2_cuh),
+        log2_cuh,
+        log2_cuw,
+    );
+    itx_clip_stage(&mut tb.data[..1 << (log2_cuw + log2_cuh)], ITX_SHIFT1);
+    itx_mat_b1(
+        &tb.data,
+        coef,
+        evc_get_tr_kernel(tr_h, log2_cuw),
+        ITX_SHIFT2,
+        log2_cuw,
+        log2_cuh,
+    );
+}
+
+fn evc_itrans_iqt(coef: &mut [i16], log2_cuw: usize, log2_cuh: usize) {
+    let mut tb = Aligned::<[i32; MAX_TR_DIM]>::uninitialized(); /* temp buffer */
+    tbl_itxb0[log2_cuh - 1](coef, &mut tb.data, log2_cuw);
+    itx_clip_stage(&mut tb.data[..1 << (log2_cuw + log2_cuh)], ITX_SHIFT1);
+    tbl_itxb1[log2_cuw - 1](&tb.data, coef, ITX_SHIFT2, log2_cuh);
+}
+
 fn evc_itrans(coef: &mut [i16], log2_cuw: usize, log2_cuh: usize) {
     let mut tb = Aligned::<[i32; MAX_TR_DIM]>::uninitialized(); /* temp buffer */
     tbl_itxb0[log2_cuh - 1](coef, &mut tb.data, log2_cuw);
@@ -766,11 +912,82 @@ fn evc_itrans(coef: &mut [i16], log2_cuw: usize, log2_cuh: usize) {
 pub fn evc_itdq(coef: &mut [i16], log2_w: usize, log2_h: usize, scale: i32) {
     let log2_size = (log2_w + log2_h) >> 1;
     let ns_shift = if (log2_w + log2_h) & 1 != 0 { 8 } else { 0 };
+    let ns_scale = if (log2_w + log2_h) & 1 != 0 { 181 } else { 1 };
 
     let tr_shift: i8 = MAX_TX_DYNAMIC_RANGE as i8 - BIT_DEPTH as i8 - log2_size as i8;
     let shift: u8 = (QUANT_IQUANT_SHIFT as i8 - QUANT_SHIFT as i8 - tr_shift + ns_shift) as u8;
     let offset: i32 = if shift == 0 { 0 } else { 1 << (shift - 1) };
 
-    evc_dquant(coef, log2_w, log2_h, scale, offset, shift);
+    evc_dquant(coef, log2_w, log2_h, scale * ns_scale, offset, shift);
     evc_itrans(coef, log2_w, log2_h);
 }
+
+/* dequantization and inverse transform with the kernels of the improved
+quantization or the adaptive transform selection */
+fn evc_itdq_ext(
+    coef: &mut [i16],
+    log2_w: usize,
+    log2_h: usize,
+    scale: i32,
+    iqt: bool,
+    tr_h: u8,
+    tr_v: u8,
+) {
+    if !iqt && tr_h == DCT2 && tr_v == DCT2 {
+        evc_itdq(coef, log2_w, log2_h, scale);
+        return;
+    }
+
+    let log2_size = (log2_w + log2_h) >> 1;
+    let ns_shift = if (log2_w + log2_h) & 1 != 0 { 8 } else { 0 };
+    let ns_scale = if (log2_w + log2_h) & 1 != 0 { 181 } else { 1 };
+
+    let tr_shift: i8 = MAX_TX_DYNAMIC_RANGE as i8 - BIT_DEPTH as i8 - log2_size as i8;
+    let shift: u8 = (QUANT_IQUANT_SHIFT as i8 - QUANT_SHIFT as i8 - tr_shift + ns_shift) as u8;
+    let offset: i32 = if shift == 0 { 0 } else { 1 << (shift - 1) };
+
+    evc_dquant(coef, log2_w, log2_h, scale * ns_scale, offset, shift);
+    if tr_h == DCT2 && tr_v == DCT2 {
+        evc_itrans_iqt(coef, log2_w, log2_h);
+    } else {
+        evc_itrans_mat(coef, log2_w, log2_h, tr_h, tr_v);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dc_block(dc: i16) -> [i16; 64] {
+        let mut coef = [0i16; 64];
+        coef[0] = dc;
+        coef
+    }
+
+    #[test]
+    fn dc_only_is_flat() {
+        let mut coef = dc_block(64);
+        evc_itrans(&mut coef, 3, 3);
+        assert_eq!(coef, [2; 64]);
+
+        let mut coef = dc_block(64);
+        evc_itrans_iqt(&mut coef, 3, 3);
+        assert_eq!(coef, [2; 64]);
+
+        /* the first stage of the improved quantization is rounded on its own */
+        let mut coef = dc_block(15);
+        evc_itrans(&mut coef, 3, 3);
+        assert_eq!(coef, [0; 64]);
+
+        let mut coef = dc_block(15);
+        evc_itrans_iqt(&mut coef, 3, 3);
+        assert_eq!(coef, [1; 64]);
+    }
+
+    #[test]
+    fn clip_stage() {
+        let mut tb = [191, -191, 40000 << 7, -40000 << 7];
+        itx_clip_stage(&mut tb, ITX_SHIFT1);
+        assert_eq!(tb, [1, -1, 32767, -32768]);
+    }
+}
@@ -11,6 +11,7 @@ pub mod api;
 mod dec;
 mod enc;
 
+mod alf;
 mod def;
 mod df;
 mod ipred;
@@ -5,12 +5,13 @@ use super::region::*;
 use super::tbl::*;
 use super::util::*;
 use crate::api::frame::Aligned;
+use crate::api::ChromaSampling;
 
 use num_traits::*;
 
 /* padding for store intermediate values, which should be larger than
 1+ half of filter tap */
-const MC_IBUF_PAD_L: usize = 5;
+const MC_IBUF_PAD_L: usize = 7;
 const MC_IBUF_PAD_C: usize = 3;
 
 const MAC_SFT_N0: i32 = (6);
@@ -30,6 +31,14 @@ static tbl_mc_l_coeff:[[i32;6];4] = [
     [  1,  -5, 20, 52,  -5,  1, ],
 ];
 
+#[rustfmt::skip]
+static tbl_mc_l_coeff_main:[[i32;8];4] = [
+    [  0,   0,   0,  64,   0,   0,   0,   0, ],
+    [ -1,   4, -10,  58,  17,  -5,   1,   0, ],
+    [ -1,   4, -11,  40,  40, -11,   4,  -1, ],
+    [  0,   1,  -5,  17,  58, -10,   4,  -1, ],
+];
+
 #[rustfmt::skip]
 static tbl_mc_c_coeff: [[i32;4];8] = [
     [  0, 64,  0,  0 ],
@@ -42,6 +51,37 @@ static tbl_mc_c_coeff: [[i32;4];8] = [
     [ -2, 10, 58, -2 ],
 ];
 
+#[rustfmt::skip]
+static tbl_mc_c_coeff_main: [[i32;4];8] = [
+    [  0, 64,  0,  0 ],
+    [ -2, 58, 10, -2 ],
+    [ -4, 54, 16, -2 ],
+    [ -6, 46, 28, -4 ],
+    [ -4, 36, 36, -4 ],
+    [ -4, 28, 46, -6 ],
+    [ -2, 16, 54, -4 ],
+    [ -2, 10, 58, -2 ],
+];
+
+/* the main profile (admvp) interpolates with 8-tap luma filters and sharper chroma filters */
+#[inline(always)]
+fn mc_l_coeff(d: i16, admvp: bool) -> &'static [i32] {
+    if admvp {
+        &tbl_mc_l_coeff_main[d as usize]
+    } else {
+        &tbl_mc_l_coeff[d as usize]
+    }
+}
+
+#[inline(always)]
+fn mc_c_coeff(d: i16, admvp: bool) -> &'static [i32] {
+    if admvp {
+        &tbl_mc_c_coeff_main[d as usize]
+    } else {
+        &tbl_mc_c_coeff[d as usize]
+    }
+}
+
 #[inline(always)]
 pub const fn round_shift(value: i32, add: i32, shift: i32) -> i32 {
     (value + add) >> shift
@@ -59,18 +99,38 @@ unsafe fn run_filter<T: AsPrimitive<i32>>(src: *const T, stride: usize, filter:
 }
 
 fn mv_clip(
-    mut x: i16,
-    mut y: i16,
+    x: i16,
+    y: i16,
     pic_w: i16,
     pic_h: i16,
-    mut cuw: i16,
-    mut cuh: i16,
+    cuw: i16,
+    cuh: i16,
     refi: &[i8],
     mv: &[[i16; MV_D]; REFP_NUM],
     mv_t: &mut [[i16; MV_D]; REFP_NUM],
 ) {
+    for lidx in 0..REFP_NUM {
+        mv_t[lidx] = if REFI_IS_VALID(refi[lidx]) {
+            mv_clip_one(x, y, pic_w, pic_h, cuw, cuh, &mv[lidx])
+        } else {
+            mv[lidx]
+        };
+    }
+}
+
+/* keep the reference block of one list within MAX_CU_SIZE of the picture */
+fn mv_clip_one(
+    mut x: i16,
+    mut y: i16,
+    pic_w: i16,
+    pic_h: i16,
+    mut cuw: i16,
+    mut cuh: i16,
+    mv: &[i16; MV_D],
+) -> [i16; MV_D] {
     let mut min_clip = [0i16; MV_D];
     let mut max_clip = [0i16; MV_D];
+    let mut mv_t = *mv;
 
     x <<= 2;
     y <<= 2;
@@ -81,42 +141,23 @@ fn mv_clip(
     max_clip[MV_X] = (pic_w - 1 + MAX_CU_SIZE as i16) << 2;
     max_clip[MV_Y] = (pic_h - 1 + MAX_CU_SIZE as i16) << 2;
 
-    mv_t[REFP_0][MV_X] = mv[REFP_0][MV_X];
-    mv_t[REFP_0][MV_Y] = mv[REFP_0][MV_Y];
-    mv_t[REFP_1][MV_X] = mv[REFP_1][MV_X];
-    mv_t[REFP_1][MV_Y] = mv[REFP_1][MV_Y];
-
-    if REFI_IS_VALID(refi[REFP_0]) {
-        if x + mv[REFP_0][MV_X] < min_clip[MV_X] {
-            mv_t[REFP_0][MV_X] = min_clip[MV_X] - x;
-        }
-        if y + mv[REFP_0][MV_Y] < min_clip[MV_Y] {
-            mv_t[REFP_0][MV_Y] = min_clip[MV_Y] - y;
-        }
-        if x + mv[REFP_0][MV_X] + cuw - 4 > max_clip[MV_X] {
-            mv_t[REFP_0][MV_X] = max_clip[MV_X] - x - cuw + 4;
-        }
-        if y + mv[REFP_0][MV_Y] + cuh - 4 > max_clip[MV_Y] {
-            mv_t[REFP_0][MV_Y] = max_clip[MV_Y] - y - cuh + 4;
-        }
+    if x + mv[MV_X] < min_clip[MV_X] {
+        mv_t[MV_X] = min_clip[MV_X] - x;
     }
-    if REFI_IS_VALID(refi[REFP_1]) {
-        if x + mv[REFP_1][MV_X] < min_clip[MV_X] {
-            mv_t[REFP_1][MV_X] = min_clip[MV_X] - x;
-        }
-        if y + mv[REFP_1][MV_Y] < min_clip[MV_Y] {
-            mv_t[REFP_1][MV_Y] = min_clip[MV_Y] - y;
-        }
-        if x + mv[REFP_1][MV_X] + cuw - 4 > max_clip[MV_X] {
-            mv_t[REFP_1][MV_X] = max_clip[MV_X] - x - cuw + 4;
-        }
-        if y + mv[REFP_1][MV_Y] + cuh - 4 > max_clip[MV_Y] {
-            mv_t[REFP_1][MV_Y] = max_clip[MV_Y] - y - cuh + 4;
-        }
+    if y + mv[MV_Y] < min_clip[MV_Y] {
+        mv_t[MV_Y] = min_clip[MV_Y] - y;
+    }
+    if x + mv[MV_X] + cuw - 4 > max_clip[MV_X] {
+        mv_t[MV_X] = max_clip[MV_X] - x - cuw + 4;
     }
+    if y + mv[MV_Y] + cuh - 4 > max_clip[MV_Y] {
+        mv_t[MV_Y] = max_clip[MV_Y] - y - cuh + 4;
+    }
+    mv_t
 }
 
-type EVC_MC_FN = fn(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i16, cuh: i16);
+type EVC_MC_FN =
+    fn(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i16, cuh: i16, admvp: bool);
 
 static evc_tbl_mc_l: [[EVC_MC_FN; 2]; 2] = [
     [
@@ -140,7 +181,15 @@ static evc_tbl_mc_c: [[EVC_MC_FN; 2]; 2] = [
     ],
 ];
 
-fn evc_mc_l_00(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i16, cuh: i16) {
+fn evc_mc_l_00(
+    p: &Plane<pel>,
+    gmv_x: i16,
+    gmv_y: i16,
+    pred: &mut [pel],
+    cuw: i16,
+    cuh: i16,
+    _admvp: bool,
+) {
     let po = PlaneOffset {
         x: (gmv_x >> 2) as isize,
         y: (gmv_y >> 2) as isize,
@@ -154,10 +203,18 @@ fn evc_mc_l_00(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i1
         dst = &mut dst[cuw as usize..];
     }
 }
-fn evc_mc_l_n0(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i16, cuh: i16) {
-    let dx = gmv_x & 3;
+fn evc_mc_l_n0(
+    p: &Plane<pel>,
+    gmv_x: i16,
+    gmv_y: i16,
+    pred: &mut [pel],
+    cuw: i16,
+    cuh: i16,
+    admvp: bool,
+) {
+    let coeff = mc_l_coeff(gmv_x & 3, admvp);
     let po = PlaneOffset {
-        x: (gmv_x >> 2) as isize - 2,
+        x: (gmv_x >> 2) as isize - (coeff.len() / 2 - 1) as isize,
         y: (gmv_y >> 2) as isize,
     };
     let r = p.slice(po).clamp();
@@ -167,7 +224,7 @@ fn evc_mc_l_n0(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i1
         let src = &r[y];
         for x in 0..cuw as usize {
             dst[x] = round_shift(
-                unsafe { run_filter(src[x..].as_ptr(), 1, &tbl_mc_l_coeff[dx as usize]) },
+                unsafe { run_filter(src[x..].as_ptr(), 1, coeff) },
                 MAC_ADD_N0,
                 MAC_SFT_N0,
             )
@@ -178,11 +235,19 @@ fn evc_mc_l_n0(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i1
     }
 }
 
-fn evc_mc_l_0n(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i16, cuh: i16) {
-    let dy = gmv_y & 3;
+fn evc_mc_l_0n(
+    p: &Plane<pel>,
+    gmv_x: i16,
+    gmv_y: i16,
+    pred: &mut [pel],
+    cuw: i16,
+    cuh: i16,
+    admvp: bool,
+) {
+    let coeff = mc_l_coeff(gmv_y & 3, admvp);
     let po = PlaneOffset {
         x: (gmv_x >> 2) as isize,
-        y: (gmv_y >> 2) as isize - 2,
+        y: (gmv_y >> 2) as isize - (coeff.len() / 2 - 1) as isize,
     };
     let r = p.slice(po).clamp();
     let stride = p.cfg.stride;
@@ -192,7 +257,7 @@ fn evc_mc_l_0n(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i1
         let src = &r[y];
         for x in 0..cuw as usize {
             dst[x] = round_shift(
-                unsafe { run_filter(src[x..].as_ptr(), stride, &tbl_mc_l_coeff[dy as usize]) },
+                unsafe { run_filter(src[x..].as_ptr(), stride, coeff) },
                 MAC_ADD_0N,
                 MAC_SFT_0N,
             )
@@ -203,23 +268,32 @@ fn evc_mc_l_0n(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i1
     }
 }
 
-fn evc_mc_l_nn(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i16, cuh: i16) {
+fn evc_mc_l_nn(
+    p: &Plane<pel>,
+    gmv_x: i16,
+    gmv_y: i16,
+    pred: &mut [pel],
+    cuw: i16,
+    cuh: i16,
+    admvp: bool,
+) {
     let mut intermediate = Aligned::<[i16; (MAX_CU_SIZE + MC_IBUF_PAD_L) * 8]>::uninitialized();
 
-    let dx = gmv_x & 3;
-    let dy = gmv_y & 3;
+    let coeff_x = mc_l_coeff(gmv_x & 3, admvp);
+    let coeff_y = mc_l_coeff(gmv_y & 3, admvp);
+    let taps = coeff_x.len();
     let po = PlaneOffset {
-        x: (gmv_x >> 2) as isize - 2,
-        y: (gmv_y >> 2) as isize - 2,
+        x: (gmv_x >> 2) as isize - (taps / 2 - 1) as isize,
+        y: (gmv_y >> 2) as isize - (taps / 2 - 1) as isize,
     };
     let r = p.slice(po).clamp();
 
     for cg in (0..cuw as usize).step_by(8) {
-        for y in 0..(cuh + MC_IBUF_PAD_L as i16) as usize {
+        for y in 0..cuh as usize + taps - 1 {
             let src = &r[y];
             for x in cg..(cg + 8).min(cuw as usize) {
                 intermediate.data[8 * y + x - cg] = round_shift(
-                    unsafe { run_filter(src[x..].as_ptr(), 1, &tbl_mc_l_coeff[dx as usize]) },
+                    unsafe { run_filter(src[x..].as_ptr(), 1, coeff_x) },
                     MAC_ADD_NN_S1,
                     MAC_SFT_NN_S1,
                 ) as i16;
@@ -230,13 +304,7 @@ fn evc_mc_l_nn(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i1
         for y in 0..cuh as usize {
             for x in cg..(cg + 8).min(cuw as usize) {
                 dst[x] = round_shift(
-                    unsafe {
-                        run_filter(
-                            intermediate.data[8 * y + x - cg..].as_ptr(),
-                            8,
-                            &tbl_mc_l_coeff[dy as usize],
-                        )
-                    },
+                    unsafe { run_filter(intermediate.data[8 * y + x - cg..].as_ptr(), 8, coeff_y) },
                     MAC_ADD_NN_S2,
                     MAC_SFT_NN_S2,
                 )
@@ -248,7 +316,15 @@ fn evc_mc_l_nn(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i1
     }
 }
 
-fn evc_mc_c_00(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i16, cuh: i16) {
+fn evc_mc_c_00(
+    p: &Plane<pel>,
+    gmv_x: i16,
+    gmv_y: i16,
+    pred: &mut [pel],
+    cuw: i16,
+    cuh: i16,
+    _admvp: bool,
+) {
     let po = PlaneOffset {
         x: (gmv_x >> 3) as isize,
         y: (gmv_y >> 3) as isize,
@@ -262,7 +338,15 @@ fn evc_mc_c_00(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i1
         dst = &mut dst[cuw as usize..];
     }
 }
-fn evc_mc_c_n0(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i16, cuh: i16) {
+fn evc_mc_c_n0(
+    p: &Plane<pel>,
+    gmv_x: i16,
+    gmv_y: i16,
+    pred: &mut [pel],
+    cuw: i16,
+    cuh: i16,
+    admvp: bool,
+) {
     let dx = gmv_x & 7;
     let po = PlaneOffset {
         x: (gmv_x >> 3) as isize - 1,
@@ -275,7 +359,7 @@ fn evc_mc_c_n0(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i1
         let src = &r[y];
         for x in 0..cuw as usize {
             dst[x] = round_shift(
-                unsafe { run_filter(src[x..].as_ptr(), 1, &tbl_mc_c_coeff[dx as usize]) },
+                unsafe { run_filter(src[x..].as_ptr(), 1, mc_c_coeff(dx, admvp)) },
                 MAC_ADD_N0,
                 MAC_SFT_N0,
             )
@@ -286,7 +370,15 @@ fn evc_mc_c_n0(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i1
     }
 }
 
-fn evc_mc_c_0n(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i16, cuh: i16) {
+fn evc_mc_c_0n(
+    p: &Plane<pel>,
+    gmv_x: i16,
+    gmv_y: i16,
+    pred: &mut [pel],
+    cuw: i16,
+    cuh: i16,
+    admvp: bool,
+) {
     let dy = gmv_y & 7;
     let po = PlaneOffset {
         x: (gmv_x >> 3) as isize,
@@ -300,7 +392,7 @@ fn evc_mc_c_0n(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i1
         let src = &r[y];
         for x in 0..cuw as usize {
             dst[x] = round_shift(
-                unsafe { run_filter(src[x..].as_ptr(), stride, &tbl_mc_c_coeff[dy as usize]) },
+                unsafe { run_filter(src[x..].as_ptr(), stride, mc_c_coeff(dy, admvp)) },
                 MAC_ADD_0N,
                 MAC_SFT_0N,
             )
@@ -311,9 +403,16 @@ fn evc_mc_c_0n(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i1
     }
 }
 
-fn evc_mc_c_nn(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i16, cuh: i16) {
-    let mut intermediate =
-        Aligned::<[i16; ((MAX_CU_SIZE >> 1) + MC_IBUF_PAD_C) * 8]>::uninitialized();
+fn evc_mc_c_nn(
+    p: &Plane<pel>,
+    gmv_x: i16,
+    gmv_y: i16,
+    pred: &mut [pel],
+    cuw: i16,
+    cuh: i16,
+    admvp: bool,
+) {
+    let mut intermediate = Aligned::<[i16; (MAX_CU_SIZE + MC_IBUF_PAD_C) * 8]>::uninitialized();
 
     let dx = gmv_x & 7;
     let dy = gmv_y & 7;
@@ -328,7 +427,7 @@ fn evc_mc_c_nn(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i1
             let src = &r[y];
             for x in cg..(cg + 8).min(cuw as usize) {
                 intermediate.data[8 * y + x - cg] = round_shift(
-                    unsafe { run_filter(src[x..].as_ptr(), 1, &tbl_mc_c_coeff[dx as usize]) },
+                    unsafe { run_filter(src[x..].as_ptr(), 1, mc_c_coeff(dx, admvp)) },
                     MAC_ADD_NN_S1,
                     MAC_SFT_NN_S1,
                 ) as i16;
@@ -343,7 +442,7 @@ fn evc_mc_c_nn(p: &Plane<pel>, gmv_x: i16, gmv_y: i16, pred: &mut [pel], cuw: i1
                         run_filter(
                             intermediate.data[8 * y + x - cg..].as_ptr(),
                             8,
-                            &tbl_mc_c_coeff[dy as usize],
+                            mc_c_coeff(dy, admvp),
                         )
                     },
                     MAC_ADD_NN_S2,
@@ -368,10 +467,11 @@ pub fn evc_mc_l(
     pred: &mut [pel],
     cuw: i16,
     cuh: i16,
+    admvp: bool,
 ) {
     let x = ((ori_mv_x | (ori_mv_x >> 1)) & 0x1) as usize;
     let y = ((ori_mv_y | (ori_mv_y >> 1)) & 0x1) as usize;
-    evc_tbl_mc_l[x][y](r, gmv_x, gmv_y, pred, cuw, cuh)
+    evc_tbl_mc_l[x][y](r, gmv_x, gmv_y, pred, cuw, cuh, admvp)
 }
 
 //TODO: evc_mc_l should be private, but in order to be visible for benchmark,
@@ -385,10 +485,11 @@ pub fn evc_mc_c(
     pred: &mut [pel],
     cuw: i16,
     cuh: i16,
+    admvp: bool,
 ) {
     let x = ((ori_mv_x | (ori_mv_x >> 1) | (ori_mv_x >> 2)) & 0x1) as usize;
     let y = ((ori_mv_y | (ori_mv_y >> 1) | (ori_mv_y >> 2)) & 0x1) as usize;
-    evc_tbl_mc_c[x][y](r, gmv_x, gmv_y, pred, cuw, cuh)
+    evc_tbl_mc_c[x][y](r, gmv_x, gmv_y, pred, cuw, cuh, admvp)
 }
 
 pub(crate) fn evc_mc(
@@ -402,9 +503,39 @@ pub(crate) fn evc_mc(
     mv: &[[i16; MV_D]; REFP_NUM],
     refp: &Vec<Vec<EvcRefP>>,
     pred: &mut [CUBuffer<pel>; 2],
-) {
+    poc_c: u32,
+    apply_dmvr: bool,
+    dmvr_mv: &mut [[[i16; MV_D]; REFP_NUM]],
+    admvp: bool,
+) -> bool {
+    if apply_dmvr && evc_dmvr_check(cuw, cuh, refi, refp, poc_c) {
+        let (pred0, pred1) = pred.split_at_mut(1);
+        let [y0, u0, v0] = &mut pred0[0].data[..] else {
+            unreachable!()
+        };
+        let [y1, u1, v1] = &mut pred1[0].data[..] else {
+            unreachable!()
+        };
+        evc_dmvr_mc_bi(
+            x,
+            y,
+            pic_w,
+            pic_h,
+            cuw,
+            cuh,
+            refi,
+            mv,
+            refp,
+            [y0, u0, v0],
+            [y1, u1, v1],
+            dmvr_mv,
+        );
+        return true;
+    }
+
     let mut bidx = 0;
     let mut mv_t = [[0i16; MV_D]; REFP_NUM];
+    let mut chroma_sampling = ChromaSampling::Cs420;
 
     //store it to pass it to interpolation function for deriving correct interpolation filter
     let mv_before_clipping = [
@@ -419,8 +550,10 @@ pub(crate) fn evc_mc(
         if let Some(ref_pic) = &refp[refi[REFP_0] as usize][REFP_0].pic {
             let qpel_gmv_x = (x << 2) + mv_t[REFP_0][MV_X];
             let qpel_gmv_y = (y << 2) + mv_t[REFP_0][MV_Y];
-            let pic = ref_pic.borrow();
-            let planes = &pic.frame.borrow().planes;
+            let pic = ref_pic.read().unwrap();
+            let frame = pic.frame.read().unwrap();
+            let planes = &frame.planes;
+            chroma_sampling = frame.chroma_sampling;
 
             evc_mc_l(
                 mv_before_clipping[REFP_0][MV_X],
@@ -431,27 +564,34 @@ pub(crate) fn evc_mc(
                 &mut pred[0].data[Y_C],
                 cuw,
                 cuh,
+                admvp,
             );
-            evc_mc_c(
-                mv_before_clipping[REFP_0][MV_X],
-                mv_before_clipping[REFP_0][MV_Y],
-                &planes[U_C],
-                qpel_gmv_x,
-                qpel_gmv_y,
-                &mut pred[0].data[U_C],
-                cuw >> 1,
-                cuh >> 1,
-            );
-            evc_mc_c(
-                mv_before_clipping[REFP_0][MV_X],
-                mv_before_clipping[REFP_0][MV_Y],
-                &planes[V_C],
-                qpel_gmv_x,
-                qpel_gmv_y,
-                &mut pred[0].data[V_C],
-                cuw >> 1,
-                cuh >> 1,
-            );
+            if chroma_sampling != ChromaSampling::Cs400 {
+                /* chroma motion is in 1/8 chroma samples */
+                let (w_shift, h_shift) = chroma_sampling.get_decimation();
+                evc_mc_c(
+                    mv_before_clipping[REFP_0][MV_X] << (1 - w_shift),
+                    mv_before_clipping[REFP_0][MV_Y] << (1 - h_shift),
+                    &planes[U_C],
+                    qpel_gmv_x << (1 - w_shift),
+                    qpel_gmv_y << (1 - h_shift),
+                    &mut pred[0].data[U_C],
+                    cuw >> w_shift,
+                    cuh >> h_shift,
+                    admvp,
+                );
+                evc_mc_c(
+                    mv_before_clipping[REFP_0][MV_X] << (1 - w_shift),
+                    mv_before_clipping[REFP_0][MV_Y] << (1 - h_shift),
+                    &planes[V_C],
+                    qpel_gmv_x << (1 - w_shift),
+                    qpel_gmv_y << (1 - h_shift),
+                    &mut pred[0].data[V_C],
+                    cuw >> w_shift,
+                    cuh >> h_shift,
+                    admvp,
+                );
+            }
 
             bidx += 1;
         }
@@ -459,15 +599,17 @@ pub(crate) fn evc_mc(
 
     /* check identical motion */
     if REFI_IS_VALID(refi[REFP_0]) && REFI_IS_VALID(refi[REFP_1]) {
-        if let (Some(pic0), Some(pic1)) = (
-            &refp[refi[REFP_0] as usize][REFP_0].pic,
-            &refp[refi[REFP_1] as usize][REFP_1].pic,
-        ) {
-            if pic0.borrow().poc == pic1.borrow().poc
+        let (refp0, refp1) = (
+            &refp[refi[REFP_0] as usize][REFP_0],
+            &refp[refi[REFP_1] as usize][REFP_1],
+        );
+        /* compare POCs cached in refp so that the same picture lock is never read twice */
+        if refp0.pic.is_some() && refp1.pic.is_some() {
+            if refp0.poc == refp1.poc
                 && mv_t[REFP_0][MV_X] == mv_t[REFP_1][MV_X]
                 && mv_t[REFP_0][MV_Y] == mv_t[REFP_1][MV_Y]
             {
-                return;
+                return false;
             }
         }
     }
@@ -477,8 +619,10 @@ pub(crate) fn evc_mc(
         if let Some(ref_pic) = &refp[refi[REFP_1] as usize][REFP_1].pic {
             let qpel_gmv_x = (x << 2) + mv_t[REFP_1][MV_X];
             let qpel_gmv_y = (y << 2) + mv_t[REFP_1][MV_Y];
-            let pic = ref_pic.borrow();
-            let planes = &pic.frame.borrow().planes;
+            let pic = ref_pic.read().unwrap();
+            let frame = pic.frame.read().unwrap();
+            let planes = &frame.planes;
+            chroma_sampling = frame.chroma_sampling;
 
             evc_mc_l(
                 mv_before_clipping[REFP_1][MV_X],
@@ -489,27 +633,34 @@ pub(crate) fn evc_mc(
                 &mut pred[bidx].data[Y_C],
                 cuw,
                 cuh,
+                admvp,
             );
-            evc_mc_c(
-                mv_before_clipping[REFP_1][MV_X],
-                mv_before_clipping[REFP_1][MV_Y],
-                &planes[U_C],
-                qpel_gmv_x,
-                qpel_gmv_y,
-                &mut pred[bidx].data[U_C],
-                cuw >> 1,
-                cuh >> 1,
-            );
-            evc_mc_c(
-                mv_before_clipping[REFP_1][MV_X],
-                mv_before_clipping[REFP_1][MV_Y],
-                &planes[V_C],
-                qpel_gmv_x,
-                qpel_gmv_y,
-                &mut pred[bidx].data[V_C],
-                cuw >> 1,
-                cuh >> 1,
-            );
+            if chroma_sampling != ChromaSampling::Cs400 {
+                /* chroma motion is in 1/8 chroma samples */
+                let (w_shift, h_shift) = chroma_sampling.get_decimation();
+                evc_mc_c(
+                    mv_before_clipping[REFP_1][MV_X] << (1 - w_shift),
+                    mv_before_clipping[REFP_1][MV_Y] << (1 - h_shift),
+                    &planes[U_C],
+                    qpel_gmv_x << (1 - w_shift),
+                    qpel_gmv_y << (1 - h_shift),
+                    &mut pred[bidx].data[U_C],
+                    cuw >> w_shift,
+                    cuh >> h_shift,
+                    admvp,
+                );
+                evc_mc_c(
+                    mv_before_clipping[REFP_1][MV_X] << (1 - w_shift),
+                    mv_before_clipping[REFP_1][MV_Y] << (1 - h_shift),
+                    &planes[V_C],
+                    qpel_gmv_x << (1 - w_shift),
+                    qpel_gmv_y << (1 - h_shift),
+                    &mut pred[bidx].data[V_C],
+                    cuw >> w_shift,
+                    cuh >> h_shift,
+                    admvp,
+                );
+            }
 
             bidx += 1;
         }
@@ -527,26 +678,29 @@ pub(crate) fn evc_mc(
             p1 = &p1[cuw as usize..];
         }
 
+        let (w_shift, h_shift) = chroma_sampling.get_decimation();
         let mut p0 = pred0[0].data[U_C].as_mut_slice();
         let mut p1 = pred1[0].data[U_C].as_slice();
-        for _ in 0..cuh >> 1 {
-            for x in 0..cuw as usize >> 1 {
+        for _ in 0..cuh >> h_shift {
+            for x in 0..cuw as usize >> w_shift {
                 p0[x] = (p0[x] + p1[x] + 1) >> 1;
             }
-            p0 = &mut p0[cuw as usize >> 1..];
-            p1 = &p1[cuw as usize >> 1..];
+            p0 = &mut p0[cuw as usize >> w_shift..];
+            p1 = &p1[cuw as usize >> w_shift..];
         }
 
         let mut p0 = pred0[0].data[V_C].as_mut_slice();
         let mut p1 = pred1[0].data[V_C].as_slice();
-        for _ in 0..cuh >> 1 {
-            for x in 0..cuw as usize >> 1 {
+        for _ in 0..cuh >> h_shift {
+            for x in 0..cuw as usize >> w_shift {
                 p0[x] = (p0[x] + p1[x] + 1) >> 1;
             }
-            p0 = &mut p0[cuw as usize >> 1..];
-            p1 = &p1[cuw as usize >> 1..];
+            p0 = &mut p0[cuw as usize >> w_shift..];
+            p1 = &p1[cuw as usize >> w_shift..];
         }
     }
+
+    false
 }
 
 pub(crate) fn evc_mc2(
@@ -561,9 +715,32 @@ pub(crate) fn evc_mc2(
     refp: &Vec<Vec<EvcRefP>>,
     pred0: &mut [pel],
     pred1: &mut [pel],
-) {
+    poc_c: u32,
+    apply_dmvr: bool,
+    dmvr_mv: &mut [[[i16; MV_D]; REFP_NUM]],
+    admvp: bool,
+) -> bool {
+    if apply_dmvr && evc_dmvr_check(cuw, cuh, refi, refp, poc_c) {
+        evc_dmvr_mc_bi(
+            x,
+            y,
+            pic_w,
+            pic_h,
+            cuw,
+            cuh,
+            refi,
+            mv,
+            refp,
+            split_pred(pred0),
+            split_pred(pred1),
+            dmvr_mv,
+        );
+        return true;
+    }
+
     let mut bidx = 0;
     let mut mv_t = [[0i16; MV_D]; REFP_NUM];
+    let mut chroma_sampling = ChromaSampling::Cs420;
 
     //store it to pass it to interpolation function for deriving correct interpolation filter
     let mv_before_clipping = [
@@ -578,8 +755,10 @@ pub(crate) fn evc_mc2(
         if let Some(ref_pic) = &refp[refi[REFP_0] as usize][REFP_0].pic {
             let qpel_gmv_x = (x << 2) + mv_t[REFP_0][MV_X];
             let qpel_gmv_y = (y << 2) + mv_t[REFP_0][MV_Y];
-            let pic = ref_pic.borrow();
-            let planes = &pic.frame.borrow().planes;
+            let pic = ref_pic.read().unwrap();
+            let frame = pic.frame.read().unwrap();
+            let planes = &frame.planes;
+            chroma_sampling = frame.chroma_sampling;
 
             evc_mc_l(
                 mv_before_clipping[REFP_0][MV_X],
@@ -590,27 +769,34 @@ pub(crate) fn evc_mc2(
                 &mut pred0[tbl_cu_dim_offset[Y_C]..],
                 cuw,
                 cuh,
+                admvp,
             );
-            evc_mc_c(
-                mv_before_clipping[REFP_0][MV_X],
-                mv_before_clipping[REFP_0][MV_Y],
-                &planes[U_C],
-                qpel_gmv_x,
-                qpel_gmv_y,
-                &mut pred0[tbl_cu_dim_offset[U_C]..],
-                cuw >> 1,
-                cuh >> 1,
-            );
-            evc_mc_c(
-                mv_before_clipping[REFP_0][MV_X],
-                mv_before_clipping[REFP_0][MV_Y],
-                &planes[V_C],
-                qpel_gmv_x,
-                qpel_gmv_y,
-                &mut pred0[tbl_cu_dim_offset[V_C]..],
-                cuw >> 1,
-                cuh >> 1,
-            );
+            if chroma_sampling != ChromaSampling::Cs400 {
+                /* chroma motion is in 1/8 chroma samples */
+                let (w_shift, h_shift) = chroma_sampling.get_decimation();
+                evc_mc_c(
+                    mv_before_clipping[REFP_0][MV_X] << (1 - w_shift),
+                    mv_before_clipping[REFP_0][MV_Y] << (1 - h_shift),
+                    &planes[U_C],
+                    qpel_gmv_x << (1 - w_shift),
+                    qpel_gmv_y << (1 - h_shift),
+                    &mut pred0[tbl_cu_dim_offset[U_C]..],
+                    cuw >> w_shift,
+                    cuh >> h_shift,
+                    admvp,
+                );
+                evc_mc_c(
+                    mv_before_clipping[REFP_0][MV_X] << (1 - w_shift),
+                    mv_before_clipping[REFP_0][MV_Y] << (1 - h_shift),
+                    &planes[V_C],
+                    qpel_gmv_x << (1 - w_shift),
+                    qpel_gmv_y << (1 - h_shift),
+                    &mut pred0[tbl_cu_dim_offset[V_C]..],
+                    cuw >> w_shift,
+                    cuh >> h_shift,
+                    admvp,
+                );
+            }
 
             bidx += 1;
         }
@@ -618,15 +804,17 @@ pub(crate) fn evc_mc2(
 
     /* check identical motion */
     if REFI_IS_VALID(refi[REFP_0]) && REFI_IS_VALID(refi[REFP_1]) {
-        if let (Some(pic0), Some(pic1)) = (
-            &refp[refi[REFP_0] as usize][REFP_0].pic,
-            &refp[refi[REFP_1] as usize][REFP_1].pic,
-        ) {
-            if pic0.borrow().poc == pic1.borrow().poc
+        let (refp0, refp1) = (
+            &refp[refi[REFP_0] as usize][REFP_0],
+            &refp[refi[REFP_1] as usize][REFP_1],
+        );
+        /* compare POCs cached in refp so that the same picture lock is never read twice */
+        if refp0.pic.is_some() && refp1.pic.is_some() {
+            if refp0.poc == refp1.poc
                 && mv_t[REFP_0][MV_X] == mv_t[REFP_1][MV_X]
                 && mv_t[REFP_0][MV_Y] == mv_t[REFP_1][MV_Y]
             {
-                return;
+                return false;
             }
         }
     }
@@ -636,8 +824,10 @@ pub(crate) fn evc_mc2(
         if let Some(ref_pic) = &refp[refi[REFP_1] as usize][REFP_1].pic {
             let qpel_gmv_x = (x << 2) + mv_t[REFP_1][MV_X];
             let qpel_gmv_y = (y << 2) + mv_t[REFP_1][MV_Y];
-            let pic = ref_pic.borrow();
-            let planes = &pic.frame.borrow().planes;
+            let pic = ref_pic.read().unwrap();
+            let frame = pic.frame.read().unwrap();
+            let planes = &frame.planes;
+            chroma_sampling = frame.chroma_sampling;
 
             evc_mc_l(
                 mv_before_clipping[REFP_1][MV_X],
@@ -652,35 +842,42 @@ pub(crate) fn evc_mc2(
                 },
                 cuw,
                 cuh,
+                admvp,
             );
-            evc_mc_c(
-                mv_before_clipping[REFP_1][MV_X],
-                mv_before_clipping[REFP_1][MV_Y],
-                &planes[U_C],
-                qpel_gmv_x,
-                qpel_gmv_y,
-                if bidx == 0 {
-                    &mut pred0[tbl_cu_dim_offset[U_C]..]
-                } else {
-                    &mut pred1[tbl_cu_dim_offset[U_C]..]
-                },
-                cuw >> 1,
-                cuh >> 1,
-            );
-            evc_mc_c(
-                mv_before_clipping[REFP_1][MV_X],
-                mv_before_clipping[REFP_1][MV_Y],
-                &planes[V_C],
-                qpel_gmv_x,
-                qpel_gmv_y,
-                if bidx == 0 {
-                    &mut pred0[tbl_cu_dim_offset[V_C]..]
-                } else {
-                    &mut pred1[tbl_cu_dim_offset[V_C]..]
-                },
-                cuw >> 1,
-                cuh >> 1,
-            );
+            if chroma_sampling != ChromaSampling::Cs400 {
+                /* chroma motion is in 1/8 chroma samples */
+                let (w_shift, h_shift) = chroma_sampling.get_decimation();
+                evc_mc_c(
+                    mv_before_clipping[REFP_1][MV_X] << (1 - w_shift),
+                    mv_before_clipping[REFP_1][MV_Y] << (1 - h_shift),
+                    &planes[U_C],
+                    qpel_gmv_x << (1 - w_shift),
+                    qpel_gmv_y << (1 - h_shift),
+                    if bidx == 0 {
+                        &mut pred0[tbl_cu_dim_offset[U_C]..]
+                    } else {
+                        &mut pred1[tbl_cu_dim_offset[U_C]..]
+                    },
+                    cuw >> w_shift,
+                    cuh >> h_shift,
+                    admvp,
+                );
+                evc_mc_c(
+                    mv_before_clipping[REFP_1][MV_X] << (1 - w_shift),
+                    mv_before_clipping[REFP_1][MV_Y] << (1 - h_shift),
+                    &planes[V_C],
+                    qpel_gmv_x << (1 - w_shift),
+                    qpel_gmv_y << (1 - h_shift),
+                    if bidx == 0 {
+                        &mut pred0[tbl_cu_dim_offset[V_C]..]
+                    } else {
+                        &mut pred1[tbl_cu_dim_offset[V_C]..]
+                    },
+                    cuw >> w_shift,
+                    cuh >> h_shift,
+                    admvp,
+                );
+            }
 
             bidx += 1;
         }
@@ -697,24 +894,911 @@ pub(crate) fn evc_mc2(
             p1 = &p1[cuw as usize..];
         }
 
-        let mut p0 = &mut pred0[tbl_cu_dim_offset[U_C]..];
-        let mut p1 = &pred1[tbl_cu_dim_offset[U_C]..];
-        for _ in 0..cuh >> 1 {
-            for x in 0..cuw as usize >> 1 {
-                p0[x] = (p0[x] + p1[x] + 1) >> 1;
+        if chroma_sampling != ChromaSampling::Cs400 {
+            let (w_shift, h_shift) = chroma_sampling.get_decimation();
+            let mut p0 = &mut pred0[tbl_cu_dim_offset[U_C]..];
+            let mut p1 = &pred1[tbl_cu_dim_offset[U_C]..];
+            for _ in 0..cuh >> h_shift {
+                for x in 0..cuw as usize >> w_shift {
+                    p0[x] = (p0[x] + p1[x] + 1) >> 1;
+                }
+                p0 = &mut p0[cuw as usize >> w_shift..];
+                p1 = &p1[cuw as usize >> w_shift..];
+            }
+
+            let mut p0 = &mut pred0[tbl_cu_dim_offset[V_C]..];
+            let mut p1 = &pred1[tbl_cu_dim_offset[V_C]..];
+            for _ in 0..cuh >> h_shift {
+                for x in 0..cuw as usize >> w_shift {
+                    p0[x] = (p0[x] + p1[x] + 1) >> 1;
+                }
+                p0 = &mut p0[cuw as usize >> w_shift..];
+                p1 = &p1[cuw as usize >> w_shift..];
             }
-            p0 = &mut p0[cuw as usize >> 1..];
-            p1 = &p1[cuw as usize >> 1..];
         }
+    }
 
-        let mut p0 = &mut pred0[tbl_cu_dim_offset[V_C]..];
-        let mut p1 = &pred1[tbl_cu_dim_offset[V_C]..];
-        for _ in 0..cuh >> 1 {
-            for x in 0..cuw as usize >> 1 {
-                p0[x] = (p0[x] + p1[x] + 1) >> 1;
+    false
+}
+
+/* affine motion compensation: sub-blocks of at least 8x8 are predicted with
+1/16 sample luma (1/32 chroma) interpolation, other CUs with the enhanced
+interpolation filter (EIF) sample by sample */
+#[rustfmt::skip]
+static tbl_mc_l_coeff_affine: [[i32; 8]; 16] = [
+    [  0,  0,   0, 64,  0,   0,  0,  0 ],
+    [  0,  1,  -3, 63,  4,  -2,  1,  0 ],
+    [ -1,  2,  -5, 62,  8,  -3,  1,  0 ],
+    [ -1,  3,  -8, 60, 13,  -4,  1,  0 ],
+    [ -1,  4, -10, 58, 17,  -5,  1,  0 ],
+    [ -1,  4, -11, 52, 26,  -8,  3, -1 ],
+    [ -1,  3,  -9, 47, 31, -10,  4, -1 ],
+    [ -1,  4, -11, 45, 34, -10,  4, -1 ],
+    [ -1,  4, -11, 40, 40, -11,  4, -1 ],
+    [ -1,  4, -10, 34, 45, -11,  4, -1 ],
+    [ -1,  4, -10, 31, 47,  -9,  3, -1 ],
+    [ -1,  3,  -8, 26, 52, -11,  4, -1 ],
+    [  0,  1,  -5, 17, 58, -10,  4, -1 ],
+    [  0,  1,  -4, 13, 60,  -8,  3, -1 ],
+    [  0,  1,  -3,  8, 62,  -5,  2, -1 ],
+    [  0,  1,  -2,  4, 63,  -3,  1,  0 ],
+];
+
+#[rustfmt::skip]
+static tbl_mc_c_coeff_affine: [[i32; 4]; 32] = [
+    [  0, 64,  0,  0 ], [ -1, 63,  2,  0 ], [ -2, 62,  4,  0 ], [ -2, 60,  7, -1 ],
+    [ -2, 58, 10, -2 ], [ -3, 57, 12, -2 ], [ -4, 56, 14, -2 ], [ -4, 55, 15, -2 ],
+    [ -4, 54, 16, -2 ], [ -5, 53, 18, -2 ], [ -6, 52, 20, -2 ], [ -6, 49, 24, -3 ],
+    [ -6, 46, 28, -4 ], [ -5, 44, 29, -4 ], [ -4, 42, 30, -4 ], [ -4, 39, 33, -4 ],
+    [ -4, 36, 36, -4 ], [ -4, 33, 39, -4 ], [ -4, 30, 42, -4 ], [ -4, 29, 44, -5 ],
+    [ -4, 28, 46, -6 ], [ -3, 24, 49, -6 ], [ -2, 20, 52, -6 ], [ -2, 18, 53, -5 ],
+    [ -2, 16, 54, -4 ], [ -2, 15, 55, -4 ], [ -2, 14, 56, -4 ], [ -2, 12, 57, -3 ],
+    [ -2, 10, 58, -2 ], [ -1,  7, 60, -2 ], [  0,  4, 62, -2 ], [  0,  2, 63, -1 ],
+];
+
+/* maximum deviation of the EIF motion from the CU center, indexed by log2(size) - 3 */
+static tbl_aff_mv_dev: [i32; 5] = [128, 256, 544, 1120, 2272];
+
+const AFF_MV_MAX: i32 = (1 << 17) - 1;
+const AFF_MV_MIN: i32 = -(1 << 17);
+const EIF_BUF_STRIDE: usize = MAX_CU_SIZE + 2;
+
+/* interpolate one sub-block at a motion with frac_bits fractional bits */
+fn evc_affine_mc_blk(
+    p: &Plane<pel>,
+    gmv_x: i32,
+    gmv_y: i32,
+    frac_bits: i32,
+    coeff_x: &[i32],
+    coeff_y: &[i32],
+    pred: &mut [pel],
+    s_pred: usize,
+    w: usize,
+    h: usize,
+) {
+    let mask = (1 << frac_bits) - 1;
+    let taps = coeff_x.len();
+
+    if gmv_x & mask == 0 && gmv_y & mask == 0 {
+        let po = PlaneOffset {
+            x: (gmv_x >> frac_bits) as isize,
+            y: (gmv_y >> frac_bits) as isize,
+        };
+        let r = p.slice(po).clamp();
+        for y in 0..h {
+            pred[y * s_pred..y * s_pred + w].copy_from_slice(&r[y][..w]);
+        }
+        return;
+    }
+
+    /* a zero phase filter is the identity, so the two stage filter covers
+    the horizontal and vertical only cases as well */
+    let mut intermediate = Aligned::<[i16; (MAX_CU_SIZE + MC_IBUF_PAD_L) * 8]>::uninitialized();
+    let po = PlaneOffset {
+        x: (gmv_x >> frac_bits) as isize - (taps / 2 - 1) as isize,
+        y: (gmv_y >> frac_bits) as isize - (taps / 2 - 1) as isize,
+    };
+    let r = p.slice(po).clamp();
+
+    for cg in (0..w).step_by(8) {
+        for y in 0..h + taps - 1 {
+            let src = &r[y];
+            for x in cg..(cg + 8).min(w) {
+                intermediate.data[8 * y + x - cg] = round_shift(
+                    unsafe { run_filter(src[x..].as_ptr(), 1, coeff_x) },
+                    MAC_ADD_NN_S1,
+                    MAC_SFT_NN_S1,
+                ) as i16;
+            }
+        }
+
+        for y in 0..h {
+            let dst = &mut pred[y * s_pred..];
+            for x in cg..(cg + 8).min(w) {
+                dst[x] = round_shift(
+                    unsafe { run_filter(intermediate.data[8 * y + x - cg..].as_ptr(), 8, coeff_y) },
+                    MAC_ADD_NN_S2,
+                    MAC_SFT_NN_S2,
+                )
+                .max(0)
+                .min(MAX_SAMPLE_VAL_I32) as pel;
             }
-            p0 = &mut p0[cuw as usize >> 1..];
-            p1 = &p1[cuw as usize >> 1..];
         }
     }
 }
+
+/* motion clipping range of the EIF in 1/32 sample units, as (min, max) */
+fn eif_derive_mv_clip_range(
+    x: i32,
+    y: i32,
+    cuw: i32,
+    cuh: i32,
+    d: &[i32; 4],
+    mv_scale: &[i32; MV_D],
+    pic_w: i32,
+    pic_h: i32,
+    range_flag: bool,
+) -> ([i32; MV_D], [i32; MV_D]) {
+    let mut min = [(-128 - x) << 5, (-128 - y) << 5];
+    let mut max = [
+        (pic_w + 128 - x - cuw - 1) << 5,
+        (pic_h + 128 - y - cuh - 1) << 5,
+    ];
+
+    if range_flag {
+        /* keep the fetched reference area around the motion of the CU center */
+        let size = [cuw, cuh];
+        for c in 0..MV_D {
+            let center =
+                evc_rounding_s32((cuw >> 1) * d[c] + mv_scale[c] + (cuh >> 1) * d[2 + c], 4);
+            let dev = tbl_aff_mv_dev[CONV_LOG2(size[c] as usize) as usize - 3];
+            let (lo, hi) = if center - dev < min[c] {
+                (min[c], (min[c] + 2 * dev).min(max[c]))
+            } else if center + dev > max[c] {
+                ((max[c] - 2 * dev).max(min[c]), max[c])
+            } else {
+                (center - dev, center + dev)
+            };
+            min[c] = lo;
+            max[c] = hi;
+        }
+    }
+
+    for c in 0..MV_D {
+        min[c] = EVC_CLIP3(AFF_MV_MIN, AFF_MV_MAX, min[c]);
+        max[c] = EVC_CLIP3(AFF_MV_MIN, AFF_MV_MAX, max[c]);
+    }
+    (min, max)
+}
+
+/* enhanced interpolation filter: bilinear interpolation at the motion of
+each sample followed by a 3-tap high-pass filter */
+fn evc_eif_mc(
+    p: &Plane<pel>,
+    x: i32,
+    y: i32,
+    w: usize,
+    h: usize,
+    mv_scale: &[i32; MV_D],
+    d: &[i32; 4],
+    min: &[i32; MV_D],
+    max: &[i32; MV_D],
+    pred: &mut [pel],
+    s_pred: usize,
+) {
+    let mut tmp = Aligned::<[i16; EIF_BUF_STRIDE * EIF_BUF_STRIDE]>::uninitialized();
+    let pic_w = p.cfg.width as i32;
+    let pic_h = p.cfg.height as i32;
+    let sample = |px: i32, py: i32| {
+        p.p(
+            px.max(0).min(pic_w - 1) as usize,
+            py.max(0).min(pic_h - 1) as usize,
+        ) as i32
+    };
+
+    /* bilinear interpolation with a one sample border */
+    for j in -1..=h as i32 {
+        let t = &mut tmp.data[(j + 1) as usize * EIF_BUF_STRIDE..];
+        for i in -1..=w as i32 {
+            let mv_x = mv_scale[MV_X] + d[0] * i + d[2] * j;
+            let mv_y = mv_scale[MV_Y] + d[1] * i + d[3] * j;
+            let ax = EVC_CLIP3(min[MV_X], max[MV_X], mv_x >> 4);
+            let ay = EVC_CLIP3(min[MV_Y], max[MV_Y], mv_y >> 4);
+            let px = x + i + (ax >> 5);
+            let py = y + j + (ay >> 5);
+            let fx = ax & 31;
+            let fy = ay & 31;
+
+            let r0 = ((sample(px, py) * (64 - 2 * fx) + sample(px + 1, py) * 2 * fx) >> 2) as i16;
+            let r1 = ((sample(px, py + 1) * (64 - 2 * fx) + sample(px + 1, py + 1) * 2 * fx) >> 2)
+                as i16;
+            t[(i + 1) as usize] =
+                ((r0 as i32 * (64 - 2 * fy) + r1 as i32 * 2 * fy + 512) >> 10) as i16;
+        }
+    }
+
+    /* horizontal high-pass filter */
+    for j in 0..h + 2 {
+        let t = &mut tmp.data[j * EIF_BUF_STRIDE..];
+        for i in 0..w {
+            t[i] = (10 * t[i + 1] as i32 - t[i] as i32 - t[i + 2] as i32) as i16;
+        }
+    }
+
+    /* vertical high-pass filter */
+    for j in 0..h {
+        let dst = &mut pred[j * s_pred..];
+        for i in 0..w {
+            let t = |r: usize| tmp.data[(j + r) * EIF_BUF_STRIDE + i] as i32;
+            let v = ((10 * t(1) - t(0) - t(2) + 32) >> 6) as i16;
+            dst[i] = v.max(0).min(MAX_SAMPLE_VAL_I32 as i16) as pel;
+        }
+    }
+}
+
+/* affine prediction of one list into the luma and chroma buffers */
+fn evc_affine_mc_lc(
+    x: i32,
+    y: i32,
+    pic_w: i32,
+    pic_h: i32,
+    cuw: usize,
+    cuh: usize,
+    mv: &[[i16; MV_D]; VER_NUM],
+    planes: &[Plane<pel>],
+    chroma_sampling: ChromaSampling,
+    pred: [&mut [pel]; N_C],
+    vertex_num: usize,
+    sub_w: usize,
+    sub_h: usize,
+    mem_band: bool,
+) {
+    let chroma = chroma_sampling != ChromaSampling::Cs400;
+    let (w_shift, h_shift) = chroma_sampling.get_decimation();
+    let d = evc_affine_mv_gradient(mv, cuw, cuh, vertex_num);
+    let mv_scale = [(mv[0][MV_X] as i32) << 7, (mv[0][MV_Y] as i32) << 7];
+    let [pred_y, pred_u, pred_v] = pred;
+
+    if sub_w > 7 && sub_h > 7 {
+        let half_w = (sub_w >> 1) as i32;
+        let half_h = (sub_h >> 1) as i32;
+        let min = [(-128 - x) << 4, (-128 - y) << 4];
+        let max = [
+            (pic_w + 128 - x - cuw as i32) << 4,
+            (pic_h + 128 - y - cuh as i32) << 4,
+        ];
+        let sub_mv = [MV_X, MV_Y].map(|c| {
+            let v = mv_scale[c] + d[c] * half_w + d[2 + c] * half_h;
+            EVC_CLIP3(
+                min[c],
+                max[c],
+                EVC_CLIP3(AFF_MV_MIN, AFF_MV_MAX, evc_rounding_s32(v, 5)),
+            )
+        });
+
+        for h in (0..cuh).step_by(sub_h) {
+            for w in (0..cuw).step_by(sub_w) {
+                let gmv_x = ((x + w as i32) << 4) + sub_mv[MV_X];
+                let gmv_y = ((y + h as i32) << 4) + sub_mv[MV_Y];
+                let coeff_x = &tbl_mc_l_coeff_affine[(gmv_x & 15) as usize];
+                let coeff_y = &tbl_mc_l_coeff_affine[(gmv_y & 15) as usize];
+                evc_affine_mc_blk(
+                    &planes[Y_C],
+                    gmv_x,
+                    gmv_y,
+                    4,
+                    coeff_x,
+                    coeff_y,
+                    &mut pred_y[h * cuw + w..],
+                    cuw,
+                    sub_w,
+                    sub_h,
+                );
+                if !chroma {
+                    continue;
+                }
+
+                /* chroma motion is in 1/32 chroma samples */
+                let gmv_x = gmv_x << (1 - w_shift);
+                let gmv_y = gmv_y << (1 - h_shift);
+                let coeff_x = &tbl_mc_c_coeff_affine[(gmv_x & 31) as usize];
+                let coeff_y = &tbl_mc_c_coeff_affine[(gmv_y & 31) as usize];
+                let offset = (h >> h_shift) * (cuw >> w_shift) + (w >> w_shift);
+                for (comp, pred_c) in [(U_C, &mut *pred_u), (V_C, &mut *pred_v)] {
+                    evc_affine_mc_blk(
+                        &planes[comp],
+                        gmv_x,
+                        gmv_y,
+                        5,
+                        coeff_x,
+                        coeff_y,
+                        &mut pred_c[offset..],
+                        cuw >> w_shift,
+                        sub_w >> w_shift,
+                        sub_h >> h_shift,
+                    );
+                }
+            }
+        }
+    } else {
+        let (min, max) = eif_derive_mv_clip_range(
+            x, y, cuw as i32, cuh as i32, &d, &mv_scale, pic_w, pic_h, !mem_band,
+        );
+        evc_eif_mc(
+            &planes[Y_C],
+            x,
+            y,
+            cuw,
+            cuh,
+            &mv_scale,
+            &d,
+            &min,
+            &max,
+            pred_y,
+            cuw,
+        );
+        if !chroma {
+            return;
+        }
+
+        /* motion and gradients scaled from luma to chroma samples */
+        let scale = |v: &[i32; MV_D]| [v[MV_X] >> w_shift, v[MV_Y] >> h_shift];
+        let d_c = [
+            d[0] << w_shift >> w_shift,
+            d[1] << w_shift >> h_shift,
+            d[2] << h_shift >> w_shift,
+            d[3] << h_shift >> h_shift,
+        ];
+        for (comp, pred_c) in [(U_C, &mut *pred_u), (V_C, &mut *pred_v)] {
+            evc_eif_mc(
+                &planes[comp],
+                x >> w_shift,
+                y >> h_shift,
+                cuw >> w_shift,
+                cuh >> h_shift,
+                &scale(&mv_scale),
+                &d_c,
+                &scale(&min),
+                &scale(&max),
+                pred_c,
+                cuw >> w_shift,
+            );
+        }
+    }
+}
+
+/* rounded average of the bi-directional predictions */
+fn average_pred(p0: &mut [pel], p1: &[pel], len: usize) {
+    for (a, b) in p0[..len].iter_mut().zip(&p1[..len]) {
+        *a = (*a + *b + 1) >> 1;
+    }
+}
+
+fn split_pred(pred: &mut [pel]) -> [&mut [pel]; N_C] {
+    let (y, uv) = pred.split_at_mut(tbl_cu_dim_offset[U_C]);
+    let (u, v) = uv.split_at_mut(tbl_cu_dim_offset[V_C] - tbl_cu_dim_offset[U_C]);
+    [y, u, v]
+}
+
+pub(crate) fn evc_affine_mc(
+    x: i16,
+    y: i16,
+    pic_w: i16,
+    pic_h: i16,
+    cuw: i16,
+    cuh: i16,
+    refi: &[i8; REFP_NUM],
+    mv: &[[[i16; MV_D]; VER_NUM]; REFP_NUM],
+    refp: &Vec<Vec<EvcRefP>>,
+    pred: &mut [CUBuffer<pel>; 2],
+    vertex_num: usize,
+) {
+    let (pred0, pred1) = pred.split_at_mut(1);
+    let [y0, u0, v0] = &mut pred0[0].data[..] else {
+        unreachable!()
+    };
+    let [y1, u1, v1] = &mut pred1[0].data[..] else {
+        unreachable!()
+    };
+    evc_affine_mc_bi(
+        x,
+        y,
+        pic_w,
+        pic_h,
+        cuw,
+        cuh,
+        refi,
+        mv,
+        refp,
+        [y0, u0, v0],
+        [y1, u1, v1],
+        vertex_num,
+    );
+}
+
+pub(crate) fn evc_affine_mc2(
+    x: i16,
+    y: i16,
+    pic_w: i16,
+    pic_h: i16,
+    cuw: i16,
+    cuh: i16,
+    refi: &[i8; REFP_NUM],
+    mv: &[[[i16; MV_D]; VER_NUM]; REFP_NUM],
+    refp: &Vec<Vec<EvcRefP>>,
+    pred0: &mut [pel],
+    pred1: &mut [pel],
+    vertex_num: usize,
+) {
+    evc_affine_mc_bi(
+        x,
+        y,
+        pic_w,
+        pic_h,
+        cuw,
+        cuh,
+        refi,
+        mv,
+        refp,
+        split_pred(pred0),
+        split_pred(pred1),
+        vertex_num,
+    );
+}
+
+fn evc_affine_mc_bi<'a>(
+    x: i16,
+    y: i16,
+    pic_w: i16,
+    pic_h: i16,
+    cuw: i16,
+    cuh: i16,
+    refi: &[i8; REFP_NUM],
+    mv: &[[[i16; MV_D]; VER_NUM]; REFP_NUM],
+    refp: &Vec<Vec<EvcRefP>>,
+    mut pred0: [&'a mut [pel]; N_C],
+    mut pred1: [&'a mut [pel]; N_C],
+    vertex_num: usize,
+) {
+    let (cuw, cuh) = (cuw as usize, cuh as usize);
+    let (sub_w, sub_h, mem_band) = derive_affine_subblock_size_bi(mv, refi, cuw, cuh, vertex_num);
+    let mut bidx = 0;
+    let mut chroma_sampling = ChromaSampling::Cs420;
+
+    for lidx in 0..REFP_NUM {
+        if !REFI_IS_VALID(refi[lidx]) {
+            continue;
+        }
+        if let Some(ref_pic) = &refp[refi[lidx] as usize][lidx].pic {
+            let pic = ref_pic.read().unwrap();
+            let frame = pic.frame.read().unwrap();
+            chroma_sampling = frame.chroma_sampling;
+            let pred = if bidx == 0 { &mut pred0 } else { &mut pred1 };
+            let [pred_y, pred_u, pred_v] = pred;
+
+            evc_affine_mc_lc(
+                x as i32,
+                y as i32,
+                pic_w as i32,
+                pic_h as i32,
+                cuw,
+                cuh,
+                &mv[lidx],
+                &frame.planes[..],
+                chroma_sampling,
+                [&mut **pred_y, &mut **pred_u, &mut **pred_v],
+                vertex_num,
+                sub_w,
+                sub_h,
+                mem_band,
+            );
+            bidx += 1;
+        }
+    }
+
+    if bidx == 2 {
+        average_pred(pred0[Y_C], pred1[Y_C], cuw * cuh);
+        if chroma_sampling != ChromaSampling::Cs400 {
+            let (w_shift, h_shift) = chroma_sampling.get_decimation();
+            let len = (cuw >> w_shift) * (cuh >> h_shift);
+            average_pred(pred0[U_C], pred1[U_C], len);
+            average_pred(pred0[V_C], pred1[V_C], len);
+        }
+    }
+}
+
+/* decoder-side motion vector refinement (DMVR): the motion of a bi-predicted
+CU with symmetric references is refined per sub-block by a bilateral search
+on bilinear predictions, then the sub-blocks are predicted with the refined
+motion from a reference area padded around the unrefined motion */
+const DMVR_ITER_COUNT: usize = 2;
+const DMVR_SUB_SIZE: usize = 16;
+const DMVR_IP_STRIDE: usize = MAX_CU_SIZE + 2 * DMVR_ITER_COUNT;
+const DMVR_PAD_MARGIN: i32 = 4;
+
+#[rustfmt::skip]
+static tbl_mc_l_coeff_bilinear: [[i32; 2]; 16] = [
+    [ 64,  0 ], [ 60,  4 ], [ 56,  8 ], [ 52, 12 ],
+    [ 48, 16 ], [ 44, 20 ], [ 40, 24 ], [ 36, 28 ],
+    [ 32, 32 ], [ 28, 36 ], [ 24, 40 ], [ 20, 44 ],
+    [ 16, 48 ], [ 12, 52 ], [  8, 56 ], [  4, 60 ],
+];
+
+/* DMVR applies to CUs of at least 8x8 predicted from one past and one future
+picture at the same distance */
+fn evc_dmvr_check(cuw: i16, cuh: i16, refi: &[i8], refp: &Vec<Vec<EvcRefP>>, poc_c: u32) -> bool {
+    if cuw < 8 || cuh < 8 || !REFI_IS_VALID(refi[REFP_0]) || !REFI_IS_VALID(refi[REFP_1]) {
+        return false;
+    }
+    let (refp0, refp1) = (
+        &refp[refi[REFP_0] as usize][REFP_0],
+        &refp[refi[REFP_1] as usize][REFP_1],
+    );
+    if refp0.pic.is_none() || refp1.pic.is_none() {
+        return false;
+    }
+    let d0 = poc_c as i64 - refp0.poc as i64;
+    let d1 = poc_c as i64 - refp1.poc as i64;
+
+    d0 * d1 < 0 && d0.abs() == d1.abs()
+}
+
+fn dmvr_sad(ip0: &[pel], ip1: &[pel], s: usize, w: usize, h: usize) -> i32 {
+    let mut sad = 0;
+    for y in 0..h {
+        for x in 0..w {
+            sad += (ip0[y * s + x] as i32 - ip1[y * s + x] as i32).abs();
+        }
+    }
+    sad
+}
+
+/* quotient of n / d in 1/8 units for n < 8 * d */
+fn dmvr_div_for_maxq7(mut n: i32, mut d: i32) -> i32 {
+    let sign = n < 0;
+    if sign {
+        n = -n;
+    }
+    let mut q = 0;
+    d <<= 3;
+    if n >= d {
+        n -= d;
+        q = 4;
+    }
+    d >>= 1;
+    if n >= d {
+        n -= d;
+        q += 2;
+    }
+    if n >= d >> 1 {
+        q += 1;
+    }
+    if sign {
+        -q
+    } else {
+        q
+    }
+}
+
+/* minimum of the parametric error surface through the costs around the best
+integer offset, in 1/16 sample units */
+fn dmvr_subpel_offset(cost_m: i32, cost_0: i32, cost_p: i32) -> i32 {
+    let den = cost_m + cost_p - 2 * cost_0;
+    if den == 0 {
+        0
+    } else if cost_m == cost_0 {
+        -8
+    } else if cost_p == cost_0 {
+        8
+    } else {
+        dmvr_div_for_maxq7((cost_m - cost_p) << 4, den)
+    }
+}
+
+/* bilateral search of one sub-block: the list 0 prediction moves by the
+offset and the list 1 prediction by its mirror. The offset is returned in
+1/16 sample units. */
+fn dmvr_refine(ip0: &[pel], ip1: &[pel], base: usize, s: usize, w: usize, h: usize) -> [i32; MV_D] {
+    let cost = |off: [i32; MV_D]| {
+        let d = off[MV_Y] as isize * s as isize + off[MV_X] as isize;
+        dmvr_sad(
+            &ip0[(base as isize + d) as usize..],
+            &ip1[(base as isize - d) as usize..],
+            s,
+            w,
+            h,
+        )
+    };
+    /* bottom, top, right and left neighbours */
+    const CAND: [[i32; MV_D]; 4] = [[0, 1], [0, -1], [1, 0], [-1, 0]];
+
+    let mut tot = [0i32; MV_D];
+    let mut sub = [0i32; MV_D];
+    let mut min_cost = 0;
+
+    for it in 0..DMVR_ITER_COUNT {
+        if it == 0 {
+            min_cost = cost(tot);
+            if min_cost < (w * h) as i32 {
+                break;
+            }
+        } else if min_cost == 0 {
+            break;
+        }
+
+        let cost_c = min_cost;
+        let mut cost_n = [0i32; 4];
+        let mut delta = [0i32; MV_D];
+        for (i, cand) in CAND.iter().enumerate() {
+            cost_n[i] = cost([tot[MV_X] + cand[MV_X], tot[MV_Y] + cand[MV_Y]]);
+            if cost_n[i] < min_cost {
+                min_cost = cost_n[i];
+                delta = *cand;
+            }
+        }
+        let diag = [
+            if cost_n[2] < cost_n[3] { 1 } else { -1 },
+            if cost_n[0] < cost_n[1] { 1 } else { -1 },
+        ];
+        let cost_d = cost([tot[MV_X] + diag[MV_X], tot[MV_Y] + diag[MV_Y]]);
+        if cost_d < min_cost {
+            min_cost = cost_d;
+            delta = diag;
+        }
+
+        if delta == [0, 0] {
+            sub[MV_X] = dmvr_subpel_offset(cost_n[3], cost_c, cost_n[2]);
+            sub[MV_Y] = dmvr_subpel_offset(cost_n[1], cost_c, cost_n[0]);
+            break;
+        }
+        tot[MV_X] += delta[MV_X];
+        tot[MV_Y] += delta[MV_Y];
+    }
+
+    [(tot[MV_X] << 4) + sub[MV_X], (tot[MV_Y] << 4) + sub[MV_Y]]
+}
+
+/* reference area of a sub-block around its unrefined position (x0, y0):
+samples further than ext from the block repeat the boundary */
+fn dmvr_pad_window(p: &Plane<pel>, x0: i32, y0: i32, w: usize, h: usize, ext: i32) -> Plane<pel> {
+    let b = ext + DMVR_PAD_MARGIN;
+    let (ww, wh) = (w + 2 * b as usize, h + 2 * b as usize);
+    let r = p.slice(PlaneOffset {
+        x: (x0 - ext) as isize,
+        y: (y0 - ext) as isize,
+    });
+    let mut data = Vec::with_capacity(ww * wh);
+    for j in 0..wh as i32 {
+        let row = &r[((j - b).max(-ext).min(h as i32 + ext) + ext) as usize];
+        for i in 0..ww as i32 {
+            data.push(row[((i - b).max(-ext).min(w as i32 + ext) + ext) as usize]);
+        }
+    }
+    Plane::wrap(data, ww)
+}
+
+/* predict one sub-block of one list with the refined motion mv16 in 1/16
+sample units */
+fn dmvr_mc_sub(
+    planes: &[Plane<pel>],
+    chroma_sampling: ChromaSampling,
+    x: i16,
+    y: i16,
+    pic_w: i16,
+    pic_h: i16,
+    cuw: i16,
+    cuh: i16,
+    sub_x: usize,
+    sub_y: usize,
+    sub_w: usize,
+    sub_h: usize,
+    start: &[i16; MV_D],
+    mv16: &[i32; MV_D],
+    pred: &mut [&mut [pel]; N_C],
+) {
+    /* motion leaving the clipping range falls back to 1/4 sample accuracy */
+    let mv4 = [(mv16[MV_X] >> 2) as i16, (mv16[MV_Y] >> 2) as i16];
+    let mv4_t = mv_clip_one(x, y, pic_w, pic_h, cuw, cuh, &mv4);
+    let mv16 = if mv4_t != mv4 {
+        [(mv4_t[MV_X] as i32) << 2, (mv4_t[MV_Y] as i32) << 2]
+    } else {
+        *mv16
+    };
+    let xs = x as i32 + sub_x as i32;
+    let ys = y as i32 + sub_y as i32;
+    let num_comp = if chroma_sampling == ChromaSampling::Cs400 {
+        1
+    } else {
+        N_C
+    };
+    let (w_shift, h_shift) = chroma_sampling.get_decimation();
+
+    for c in 0..num_comp {
+        /* luma motion is in 1/16 samples, chroma motion in 1/32 chroma samples */
+        let (sft_x, sft_y, frac_bits, ext) = if c == Y_C {
+            (0, 0, 4, 3)
+        } else {
+            (w_shift, h_shift, 5, 1)
+        };
+        let mask = (1 << frac_bits) - 1;
+        let pos_x = ((xs >> sft_x) << frac_bits) + (mv16[MV_X] << (frac_bits - 4 - sft_x as i32));
+        let pos_y = ((ys >> sft_y) << frac_bits) + (mv16[MV_Y] << (frac_bits - 4 - sft_y as i32));
+        let x0 = (xs >> sft_x) + (start[MV_X] as i32 >> (2 + sft_x));
+        let y0 = (ys >> sft_y) + (start[MV_Y] as i32 >> (2 + sft_y));
+        let (w, h) = (sub_w >> sft_x, sub_h >> sft_y);
+        let window = dmvr_pad_window(&planes[c], x0, y0, w, h, ext);
+        let b = ext + DMVR_PAD_MARGIN;
+        let gmv_x = (((pos_x >> frac_bits) - x0 + b) << frac_bits) | (pos_x & mask);
+        let gmv_y = (((pos_y >> frac_bits) - y0 + b) << frac_bits) | (pos_y & mask);
+        let (coeff_x, coeff_y): (&[i32], &[i32]) = if c == Y_C {
+            (
+                &tbl_mc_l_coeff_affine[(pos_x & mask) as usize],
+                &tbl_mc_l_coeff_affine[(pos_y & mask) as usize],
+            )
+        } else {
+            (
+                &tbl_mc_c_coeff_affine[(pos_x & mask) as usize],
+                &tbl_mc_c_coeff_affine[(pos_y & mask) as usize],
+            )
+        };
+        let s_pred = cuw as usize >> sft_x;
+
+        evc_affine_mc_blk(
+            &window,
+            gmv_x,
+            gmv_y,
+            frac_bits,
+            coeff_x,
+            coeff_y,
+            &mut pred[c][(sub_y >> sft_y) * s_pred + (sub_x >> sft_x)..],
+            s_pred,
+            w,
+            h,
+        );
+    }
+}
+
+/* bi-prediction with DMVR; the refined motion of each 4x4 block is stored in
+dmvr_mv in 1/4 sample units */
+fn evc_dmvr_mc_bi<'a>(
+    x: i16,
+    y: i16,
+    pic_w: i16,
+    pic_h: i16,
+    cuw: i16,
+    cuh: i16,
+    refi: &[i8],
+    mv: &[[i16; MV_D]; REFP_NUM],
+    refp: &Vec<Vec<EvcRefP>>,
+    mut pred0: [&'a mut [pel]; N_C],
+    mut pred1: [&'a mut [pel]; N_C],
+    dmvr_mv: &mut [[[i16; MV_D]; REFP_NUM]],
+) {
+    let (w, h) = (cuw as usize, cuh as usize);
+    let mut start = [[0i16; MV_D]; REFP_NUM];
+    mv_clip(x, y, pic_w, pic_h, cuw, cuh, refi, mv, &mut start);
+
+    let (Some(ref_pic0), Some(ref_pic1)) = (
+        &refp[refi[REFP_0] as usize][REFP_0].pic,
+        &refp[refi[REFP_1] as usize][REFP_1].pic,
+    ) else {
+        return;
+    };
+    let (pic0, pic1) = (ref_pic0.read().unwrap(), ref_pic1.read().unwrap());
+    let (frame0, frame1) = (pic0.frame.read().unwrap(), pic1.frame.read().unwrap());
+    let planes = [&frame0.planes[..], &frame1.planes[..]];
+    let chroma_sampling = frame0.chroma_sampling;
+
+    /* bilinear luma predictions extended by the search range */
+    let s = w + 2 * DMVR_ITER_COUNT;
+    let mut ip = [[0 as pel; DMVR_IP_STRIDE * DMVR_IP_STRIDE]; REFP_NUM];
+    for lidx in 0..REFP_NUM {
+        let gmv_x =
+            (((x as i32) << 2) + start[lidx][MV_X] as i32 - ((DMVR_ITER_COUNT as i32) << 2)) << 2;
+        let gmv_y =
+            (((y as i32) << 2) + start[lidx][MV_Y] as i32 - ((DMVR_ITER_COUNT as i32) << 2)) << 2;
+        evc_affine_mc_blk(
+            &planes[lidx][Y_C],
+            gmv_x,
+            gmv_y,
+            4,
+            &tbl_mc_l_coeff_bilinear[(gmv_x & 15) as usize],
+            &tbl_mc_l_coeff_bilinear[(gmv_y & 15) as usize],
+            &mut ip[lidx],
+            s,
+            s,
+            h + 2 * DMVR_ITER_COUNT,
+        );
+    }
+
+    let sub_w = w.min(DMVR_SUB_SIZE);
+    let sub_h = h.min(DMVR_SUB_SIZE);
+    for sub_y in (0..h).step_by(sub_h) {
+        for sub_x in (0..w).step_by(sub_w) {
+            let base = (DMVR_ITER_COUNT + sub_y) * s + DMVR_ITER_COUNT + sub_x;
+            let delta = dmvr_refine(&ip[REFP_0], &ip[REFP_1], base, s, sub_w, sub_h);
+            let mut mv16 = [[0i32; MV_D]; REFP_NUM];
+            for c in 0..MV_D {
+                mv16[REFP_0][c] = ((start[REFP_0][c] as i32) << 2) + delta[c];
+                mv16[REFP_1][c] = ((start[REFP_1][c] as i32) << 2) - delta[c];
+            }
+
+            for y4 in (sub_y >> 2)..((sub_y + sub_h) >> 2) {
+                for x4 in (sub_x >> 2)..((sub_x + sub_w) >> 2) {
+                    for lidx in 0..REFP_NUM {
+                        dmvr_mv[y4 * (w >> 2) + x4][lidx] = [
+                            (mv16[lidx][MV_X] >> 2) as i16,
+                            (mv16[lidx][MV_Y] >> 2) as i16,
+                        ];
+                    }
+                }
+            }
+
+            for lidx in 0..REFP_NUM {
+                dmvr_mc_sub(
+                    planes[lidx],
+                    chroma_sampling,
+                    x,
+                    y,
+                    pic_w,
+                    pic_h,
+                    cuw,
+                    cuh,
+                    sub_x,
+                    sub_y,
+                    sub_w,
+                    sub_h,
+                    &start[lidx],
+                    &mv16[lidx],
+                    if lidx == REFP_0 {
+                        &mut pred0
+                    } else {
+                        &mut pred1
+                    },
+                );
+            }
+        }
+    }
+
+    average_pred(pred0[Y_C], pred1[Y_C], w * h);
+    if chroma_sampling != ChromaSampling::Cs400 {
+        let (w_shift, h_shift) = chroma_sampling.get_decimation();
+        let len = (w >> w_shift) * (h >> h_shift);
+        average_pred(pred0[U_C], pred1[U_C], len);
+        average_pred(pred0[V_C], pred1[V_C], len);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dmvr_subpel() {
+        assert_eq!(dmvr_subpel_offset(4, 0, 4), 0);
+        assert_eq!(dmvr_subpel_offset(2, 0, 6), -4);
+        assert_eq!(dmvr_subpel_offset(6, 0, 2), 4);
+        assert_eq!(dmvr_subpel_offset(0, 0, 6), -8);
+        assert_eq!(dmvr_subpel_offset(6, 0, 0), 8);
+    }
+
+    #[test]
+    fn dmvr_finds_mirrored_offset() {
+        let (s, w, h) = (16, 8, 8);
+        let g = |x: usize, y: usize| (x * x * 3 + y * 7) as pel;
+        let mut ip0 = vec![0; s * s];
+        let mut ip1 = vec![0; s * s];
+        for y in 0..s {
+            for x in 0..s {
+                /* the list 0 prediction is two samples ahead of the list 1
+                prediction, each one moves by one sample */
+                ip0[y * s + x] = g(x + 3, y);
+                ip1[y * s + x] = g(x + 1, y);
+            }
+        }
+
+        let base = 4 * s + 4;
+        assert_eq!(dmvr_refine(&ip0, &ip1, base, s, w, h), [-16, 0]);
+        assert_eq!(dmvr_refine(&ip0, &ip0, base, s, w, h), [0, 0]);
+    }
+}
@@ -2,26 +2,27 @@ use super::api::frame::*;
 use super::def::*;
 use crate::api::*;
 
-use std::cell::RefCell;
 use std::cmp::Ordering;
-use std::rc::Rc;
+use std::sync::{Arc, RwLock};
 
 /* picture store structure */
 #[derive(Default)]
 pub(crate) struct EvcPic {
-    pub(crate) frame: Rc<RefCell<Frame<pel>>>,
+    pub(crate) frame: Arc<RwLock<Frame<pel>>>,
 
     /* presentation temporal reference of this picture */
     pub(crate) poc: u32,
     /* 0: not used for reference buffer, reference picture type */
     pub(crate) is_ref: bool,
+    /* marked as long-term reference picture */
+    pub(crate) is_lt: bool,
     /* needed for output? */
     pub(crate) need_for_out: bool,
     /* scalable layer id */
     pub(crate) temporal_id: u8,
 
-    pub(crate) map_mv: Rc<RefCell<Vec<[[i16; MV_D]; REFP_NUM]>>>,
-    pub(crate) map_refi: Rc<RefCell<Vec<[i8; REFP_NUM]>>>,
+    pub(crate) map_mv: Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>,
+    pub(crate) map_refi: Arc<RwLock<Vec<[i8; REFP_NUM]>>>,
     pub(crate) list_poc: [u32; MAX_NUM_REF_PICS],
 
     pub(crate) pic_qp_u_offset: i8,
@@ -37,13 +38,14 @@ impl EvcPic {
         let f_scu = w_scu * h_scu;
 
         EvcPic {
-            frame: Rc::new(RefCell::new(Frame::new(width, height, chroma_sampling))),
+            frame: Arc::new(RwLock::new(Frame::new(width, height, chroma_sampling))),
             poc: 0,
             is_ref: false,
+            is_lt: false,
             need_for_out: false,
             temporal_id: 0,
-            map_mv: Rc::new(RefCell::new(vec![[[0; MV_D]; REFP_NUM]; f_scu])),
-            map_refi: Rc::new(RefCell::new(vec![[0; REFP_NUM]; f_scu])),
+            map_mv: Arc::new(RwLock::new(vec![[[0; MV_D]; REFP_NUM]; f_scu])),
+            map_refi: Arc::new(RwLock::new(vec![[0; REFP_NUM]; f_scu])),
             list_poc: [0; MAX_NUM_REF_PICS],
             pic_qp_u_offset: 0,
             pic_qp_v_offset: 0,
@@ -52,15 +54,24 @@ impl EvcPic {
     }
 }
 
+/* POC LSBs identifying a long-term reference picture */
+fn evc_poc_lsb(poc: u32, len: u8) -> u32 {
+    if len >= 32 {
+        poc
+    } else {
+        poc & ((1 << len) - 1)
+    }
+}
+
 /* reference picture structure */
-//#[derive(Default)]
+#[derive(Clone)]
 pub(crate) struct EvcRefP {
     /* address of reference picture */
-    pub(crate) pic: Option<Rc<RefCell<EvcPic>>>,
+    pub(crate) pic: Option<Arc<RwLock<EvcPic>>>,
     /* POC of reference picture */
     pub(crate) poc: u32,
-    pub(crate) map_mv: Option<Rc<RefCell<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
-    pub(crate) map_refi: Option<Rc<RefCell<Vec<[i8; REFP_NUM]>>>>,
+    pub(crate) map_mv: Option<Arc<RwLock<Vec<[[i16; MV_D]; REFP_NUM]>>>>,
+    pub(crate) map_refi: Option<Arc<RwLock<Vec<[i8; REFP_NUM]>>>>,
     pub(crate) list_poc: [u32; MAX_NUM_REF_PICS],
 }
 
@@ -75,11 +86,11 @@ impl EvcRefP {
         }
     }
 
-    fn set_refp(&mut self, pic_ref: Rc<RefCell<EvcPic>>) {
+    fn set_refp(&mut self, pic_ref: Arc<RwLock<EvcPic>>) {
         {
-            let pic = pic_ref.borrow();
-            self.map_mv = Some(Rc::clone(&pic.map_mv));
-            self.map_refi = Some(Rc::clone(&pic.map_refi));
+            let pic = pic_ref.read().unwrap();
+            self.map_mv = Some(Arc::clone(&pic.map_mv));
+            self.map_refi = Some(Arc::clone(&pic.map_refi));
             self.list_poc.copy_from_slice(&pic.list_poc);
             self.poc = pic.poc;
         }
@@ -89,19 +100,19 @@ impl EvcRefP {
 
     fn copy_refp(&mut self, refp_src: &EvcRefP) {
         if let Some(map_mv) = &refp_src.map_mv {
-            self.map_mv = Some(Rc::clone(map_mv));
+            self.map_mv = Some(Arc::clone(map_mv));
         } else {
             self.map_mv = None;
         }
         if let Some(map_refi) = &refp_src.map_refi {
-            self.map_refi = Some(Rc::clone(map_refi));
+            self.map_refi = Some(Arc::clone(map_refi));
         } else {
             self.map_refi = None;
         }
         self.list_poc.copy_from_slice(&refp_src.list_poc);
         self.poc = refp_src.poc;
         self.pic = if let Some(pic) = &refp_src.pic {
-            Some(Rc::clone(pic))
+            Some(Arc::clone(pic))
         } else {
             None
         };
@@ -118,9 +129,9 @@ pub(crate) struct EvcPm {
     pub(crate) chroma_sampling: ChromaSampling,
 
     /* picture store (including reference and non-reference) */
-    pub(crate) pic: Vec<Option<Rc<RefCell<EvcPic>>>>, //[Option<Rc<RefCell<EvcPic<T>>>>; MAX_PB_SIZE],
+    pub(crate) pic: Vec<Option<Arc<RwLock<EvcPic>>>>, //[Option<Arc<RwLock<EvcPic<T>>>>; MAX_PB_SIZE],
     /* address of reference pictures */
-    pub(crate) pic_ref: Vec<Option<Rc<RefCell<EvcPic>>>>, //[Option<Rc<RefCell<EvcPic<T>>>>; MAX_NUM_REF_PICS],
+    pub(crate) pic_ref: Vec<Option<Arc<RwLock<EvcPic>>>>, //[Option<Arc<RwLock<EvcPic<T>>>>; MAX_NUM_REF_PICS],
     /* maximum reference picture count */
     pub(crate) max_num_ref_pics: u8,
     /* current count of available reference pictures in PB */
@@ -136,7 +147,7 @@ pub(crate) struct EvcPm {
     /* current picture buffer size */
     pub(crate) cur_pb_size: u8,
     /* address of leased picture for current decoding/encoding buffer */
-    pub(crate) pic_lease: Option<Rc<RefCell<EvcPic>>>,
+    pub(crate) pic_lease: Option<Arc<RwLock<EvcPic>>>,
     /* picture buffer allocator */
     //PICBUF_ALLOCATOR pa;
 }
@@ -189,7 +200,7 @@ impl EvcPm {
         cnt
     }
 
-    fn picman_move_pic(pic: &mut [Option<Rc<RefCell<EvcPic>>>], from: usize, to: usize) {
+    fn picman_move_pic(pic: &mut [Option<Arc<RwLock<EvcPic>>>], from: usize, to: usize) {
         for i in from..to {
             pic.swap(i, i + 1);
         }
@@ -201,16 +212,122 @@ impl EvcPm {
         }
     }
 
+    /* move the pictures which are no longer used for reference behind the
+    reference pictures */
+    fn picman_move_unmarked(&mut self, tbm: &mut [bool]) {
+        let mut i = 0;
+        while i < tbm.len() {
+            if tbm[i] {
+                EvcPm::picman_move_pic(&mut self.pic, i, MAX_PB_SIZE - 1);
+                EvcPm::picman_move_tbm(tbm, i, MAX_PB_SIZE - 1);
+                tbm[MAX_PB_SIZE - 1] = false;
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /* the reference pictures which are not in the reference picture lists of
+    the current slice are marked as unused for reference, the ones referred
+    by a long-term entry are marked as long-term reference pictures */
+    pub(crate) fn evc_picman_refpic_marking(&mut self, sh: &EvcSh, poc: u32) {
+        let lt_refs: Vec<_> = [&sh.rpl_l0, &sh.rpl_l1]
+            .iter()
+            .flat_map(|rpl| {
+                (0..rpl.ref_pic_num as usize)
+                    .filter(|&i| rpl.ref_pic_lt[i])
+                    .map(|i| (rpl.ref_pics[i] as u32, rpl.lt_poc_lsb_len))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let in_st_rpl = |rpl: &EvcRpl, pic_poc: u32| {
+            (0..rpl.ref_pic_num as usize)
+                .any(|i| !rpl.ref_pic_lt[i] && poc as i32 - rpl.ref_pics[i] == pic_poc as i32)
+        };
+
+        /* a long-term entry refers to the long-term reference picture with
+        these POC LSBs if any, otherwise to a short-term one */
+        let lt_marked: Vec<bool> = lt_refs
+            .iter()
+            .map(|&(lsb, len)| {
+                self.pic.iter().flatten().any(|pic| {
+                    let p = pic.read().unwrap();
+                    p.is_ref && p.is_lt && evc_poc_lsb(p.poc, len) == lsb
+                })
+            })
+            .collect();
+
+        let mut cur_num_ref_pics = 0;
+        let mut tbm = vec![false; MAX_PB_SIZE];
+        for (i, pic) in self.pic.iter().enumerate() {
+            if let Some(p) = &pic {
+                let mut p = p.write().unwrap();
+                if p.is_ref {
+                    let in_lt_rpl = lt_refs
+                        .iter()
+                        .zip(&lt_marked)
+                        .any(|(&(lsb, len), &marked)| {
+                            (p.is_lt || !marked) && evc_poc_lsb(p.poc, len) == lsb
+                        });
+                    if in_lt_rpl {
+                        p.is_lt = true;
+                        cur_num_ref_pics += 1;
+                    } else if !p.is_lt
+                        && (in_st_rpl(&sh.rpl_l0, p.poc) || in_st_rpl(&sh.rpl_l1, p.poc))
+                    {
+                        cur_num_ref_pics += 1;
+                    } else {
+                        p.is_ref = false;
+                        p.is_lt = false;
+                        tbm[i] = true;
+                    }
+                }
+            }
+        }
+
+        self.picman_move_unmarked(&mut tbm);
+
+        self.cur_num_ref_pics = cur_num_ref_pics;
+    }
+
+    /* the picture is kept as long-term reference picture, replacing the
+    previous one */
+    pub(crate) fn evc_picman_set_lt_ref(&mut self, pic: &Arc<RwLock<EvcPic>>) {
+        let mut tbm = vec![false; MAX_PB_SIZE];
+        for (i, p) in self.pic.iter().enumerate() {
+            if let Some(p) = &p {
+                if Arc::ptr_eq(p, pic) {
+                    continue;
+                }
+                let mut p = p.write().unwrap();
+                if p.is_ref && p.is_lt {
+                    p.is_ref = false;
+                    p.is_lt = false;
+                    tbm[i] = true;
+                    self.cur_num_ref_pics -= 1;
+                }
+            }
+        }
+
+        self.picman_move_unmarked(&mut tbm);
+
+        let mut p = pic.write().unwrap();
+        assert!(p.is_ref);
+        p.is_lt = true;
+    }
+
     fn pic_marking_no_rpl(&mut self, ref_pic_gap_length: u32) {
         // mark all pics with layer id > 0 as unused for reference
         /* this is coding order */
         let mut cur_num_ref_pics = 0;
+        let mut num_lt_ref_pics = 0;
         let mut i = 0;
         let mut tbm = vec![false; MAX_PB_SIZE];
         for pic in &self.pic {
             if let Some(p) = &pic {
-                let mut p = p.borrow_mut();
+                let mut p = p.write().unwrap();
                 if p.is_ref
+                    && !p.is_lt
                     && (p.temporal_id > 0
                         || (i > 0 && ref_pic_gap_length > 0 && p.poc % ref_pic_gap_length != 0))
                 {
@@ -220,29 +337,24 @@ impl EvcPm {
 
                 if p.is_ref {
                     cur_num_ref_pics += 1;
+                    if p.is_lt {
+                        num_lt_ref_pics += 1;
+                    }
                 }
             }
             i += 1;
         }
 
-        i = 0;
-        while i < tbm.len() {
-            if tbm[i] {
-                EvcPm::picman_move_pic(&mut self.pic, i, MAX_PB_SIZE - 1);
-                EvcPm::picman_move_tbm(&mut tbm, i, MAX_PB_SIZE - 1);
-                tbm[MAX_PB_SIZE - 1] = false;
-            } else {
-                i += 1;
-            }
-        }
+        self.picman_move_unmarked(&mut tbm);
 
+        /* the sliding window only applies to the short-term reference pictures */
         // TODO: change to signalled num ref pics
-        while cur_num_ref_pics >= MAX_NUM_ACTIVE_REF_FRAME {
+        while cur_num_ref_pics - num_lt_ref_pics >= MAX_NUM_ACTIVE_REF_FRAME {
             i = 0;
             for pic in &self.pic {
                 if let Some(p) = &pic {
-                    let mut p = p.borrow_mut();
-                    if p.is_ref {
+                    let mut p = p.write().unwrap();
+                    if p.is_ref && !p.is_lt {
                         p.is_ref = false;
                         tbm[i] = true;
 
@@ -267,7 +379,9 @@ impl EvcPm {
         /* mark all frames unused */
         for i in 0..MAX_PB_SIZE {
             if let Some(pic) = &self.pic[i] {
-                pic.borrow_mut().is_ref = false;
+                let mut p = pic.write().unwrap();
+                p.is_ref = false;
+                p.is_lt = false;
             }
         }
         self.cur_num_ref_pics = 0;
@@ -277,8 +391,8 @@ impl EvcPm {
         let mut j = 0;
         for i in 0..MAX_PB_SIZE {
             if let Some(pic) = &self.pic[i] {
-                if pic.borrow().is_ref {
-                    self.pic_ref[j] = Some(Rc::clone(pic));
+                if pic.read().unwrap().is_ref {
+                    self.pic_ref[j] = Some(Arc::clone(pic));
                     j += 1;
                 }
             }
@@ -290,10 +404,10 @@ impl EvcPm {
         }
 
         /* descending order sort based on POC */
-        self.pic_ref[0..cnt].sort_by_key(|k| -(k.as_ref().unwrap().borrow().poc as i32));
+        self.pic_ref[0..cnt].sort_by_key(|k| -(k.as_ref().unwrap().read().unwrap().poc as i32));
     }
 
-    fn picman_remove_pic_from_pb(&mut self, pos: usize) -> Option<Rc<RefCell<EvcPic>>> {
+    fn picman_remove_pic_from_pb(&mut self, pos: usize) -> Option<Arc<RwLock<EvcPic>>> {
         let pic_rem = self.pic[pos].take();
 
         /* fill empty pic buffer */
@@ -309,12 +423,12 @@ impl EvcPm {
 
     fn picman_set_pic_to_pb(
         &mut self,
-        pic: Rc<RefCell<EvcPic>>,
+        pic: Arc<RwLock<EvcPic>>,
         refp: &mut Vec<Vec<EvcRefP>>,
         pos: isize,
     ) {
         for i in 0..self.num_refp[REFP_0] as usize {
-            pic.borrow_mut().list_poc[i] = refp[i][REFP_0].poc;
+            pic.write().unwrap().list_poc[i] = refp[i][REFP_0].poc;
         }
         if pos >= 0 {
             assert!(self.pic[pos as usize].is_none());
@@ -342,7 +456,7 @@ impl EvcPm {
     fn picman_get_empty_pic_from_list(&self) -> Result<usize, EvcError> {
         for i in 0..MAX_PB_SIZE {
             if let Some(pic) = &self.pic[i] {
-                let p = pic.borrow();
+                let p = pic.read().unwrap();
                 if !p.is_ref && !p.need_for_out {
                     //imgb = pic -> imgb;
                     //evc_assert(imgb != NULL);
@@ -377,12 +491,25 @@ impl EvcPm {
 
     pub(crate) fn evc_picman_get_empty_pic(
         &mut self,
-    ) -> Result<Option<Rc<RefCell<EvcPic>>>, EvcError> {
+    ) -> Result<Option<Arc<RwLock<EvcPic>>>, EvcError> {
         /* try to find empty picture buffer in list */
         if let Ok(pos) = self.picman_get_empty_pic_from_list() {
             self.pic_lease = self.picman_remove_pic_from_pb(pos);
             if let Some(pic) = &self.pic_lease {
-                return Ok(Some(Rc::clone(pic)));
+                /* the frame may still be held by the application (output or
+                reconstructed frame), so give the picture a fresh one instead of
+                overwriting it */
+                {
+                    let mut p = pic.write().unwrap();
+                    if Arc::strong_count(&p.frame) > 1 {
+                        p.frame = Arc::new(RwLock::new(Frame::new(
+                            self.width,
+                            self.height,
+                            self.chroma_sampling,
+                        )));
+                    }
+                }
+                return Ok(Some(Arc::clone(pic)));
             }
         }
         /* else if available, allocate picture buffer */
@@ -390,28 +517,40 @@ impl EvcPm {
 
         if self.cur_pb_size < self.max_pb_size {
             /* create picture buffer */
-            self.pic_lease = Some(Rc::new(RefCell::new(EvcPic::new(
+            self.pic_lease = Some(Arc::new(RwLock::new(EvcPic::new(
                 self.width,
                 self.height,
                 self.chroma_sampling,
             ))));
             if let Some(pic) = &self.pic_lease {
-                return Ok(Some(Rc::clone(pic)));
+                return Ok(Some(Arc::clone(pic)));
             }
         }
 
         Err(EvcError::EVC_ERR_UNKNOWN)
     }
 
+    /* reference picture closest to poc in output order, used to conceal
+    the LCUs which cannot be decoded */
+    pub(crate) fn evc_picman_closest_ref_pic(&self, poc: u32) -> Option<Arc<RwLock<EvcPic>>> {
+        self.pic
+            .iter()
+            .flatten()
+            .filter(|pic| pic.read().unwrap().is_ref)
+            .min_by_key(|pic| (pic.read().unwrap().poc as i64 - poc as i64).abs())
+            .map(Arc::clone)
+    }
+
     pub(crate) fn evc_picman_put_pic(
         &mut self,
-        pic: &Option<Rc<RefCell<EvcPic>>>,
+        pic: &Option<Arc<RwLock<EvcPic>>>,
         is_idr: bool,
         poc: u32,
         temporal_id: u8,
         need_for_output: bool,
         refp: &mut Vec<Vec<EvcRefP>>,
         ref_pic: bool,
+        rpl_marking: bool,
         ref_pic_gap_length: u32,
     ) {
         /* manage RPB */
@@ -419,18 +558,19 @@ impl EvcPm {
             self.picman_flush_pb();
         }
         //Perform picture marking if RPL approach is not used
-        else if temporal_id == 0 {
+        else if temporal_id == 0 && !rpl_marking {
             self.pic_marking_no_rpl(ref_pic_gap_length);
         }
 
         if let Some(pic) = pic {
             let mut is_ref = {
-                let mut p = pic.borrow_mut();
+                let mut p = pic.write().unwrap();
                 if !ref_pic {
                     p.is_ref = false;
                 } else {
                     p.is_ref = true;
                 }
+                p.is_lt = false;
 
                 p.temporal_id = temporal_id;
                 p.poc = poc;
@@ -440,16 +580,17 @@ impl EvcPm {
 
             /* put picture into listed RPB */
             if is_ref {
-                self.picman_set_pic_to_pb(Rc::clone(pic), refp, self.cur_num_ref_pics as isize);
+                self.picman_set_pic_to_pb(Arc::clone(pic), refp, self.cur_num_ref_pics as isize);
                 self.cur_num_ref_pics += 1;
             } else {
-                self.picman_set_pic_to_pb(Rc::clone(pic), refp, -1);
+                self.picman_set_pic_to_pb(Arc::clone(pic), refp, -1);
             }
         }
 
         if self.pic_lease.is_some()
             && pic.is_some()
-            && self.pic_lease.as_ref().unwrap().borrow().poc == pic.as_ref().unwrap().borrow().poc
+            && self.pic_lease.as_ref().unwrap().read().unwrap().poc
+                == pic.as_ref().unwrap().read().unwrap().poc
         {
             self.pic_lease = None;
         }
@@ -457,11 +598,14 @@ impl EvcPm {
         //self.PRINT_DPB();
     }
 
-    pub(crate) fn evc_picman_out_pic(&mut self) -> Result<Option<Rc<RefCell<EvcPic>>>, EvcError> {
+    pub(crate) fn evc_picman_out_pic(
+        &mut self,
+        flush: bool,
+    ) -> Result<Option<Arc<RwLock<EvcPic>>>, EvcError> {
         let mut any_need_for_out = false;
         for i in 0..MAX_PB_SIZE {
             if let Some(pic) = &self.pic[i] {
-                let mut ps = pic.borrow_mut();
+                let mut ps = pic.write().unwrap();
                 if ps.need_for_out {
                     any_need_for_out = true;
 
@@ -469,16 +613,38 @@ impl EvcPm {
                         ps.need_for_out = false;
                         self.poc_next_output = ps.poc + self.poc_increase as u32;
 
-                        return Ok(Some(Rc::clone(pic)));
+                        return Ok(Some(Arc::clone(pic)));
                     }
                 }
             }
         }
         if !any_need_for_out {
-            Err(EvcError::EVC_OK_NO_MORE_OUTPUT)
-        } else {
-            Ok(None)
+            return Err(EvcError::EVC_OK_NO_MORE_OUTPUT);
+        }
+
+        /* the picture to output next is missing: the output would stall, so
+        the next picture in output order is bumped out when flushing or when
+        no picture buffer is left for decoding */
+        if flush
+            || (self.picman_get_num_allocated_pics() >= self.max_pb_size
+                && self.picman_get_empty_pic_from_list().is_err())
+        {
+            let pic = self
+                .pic
+                .iter()
+                .flatten()
+                .filter(|pic| pic.read().unwrap().need_for_out)
+                .min_by_key(|pic| pic.read().unwrap().poc)
+                .map(Arc::clone);
+            if let Some(pic) = &pic {
+                let mut ps = pic.write().unwrap();
+                ps.need_for_out = false;
+                self.poc_next_output = ps.poc + self.poc_increase as u32;
+            }
+            return Ok(pic);
         }
+
+        Ok(None)
     }
 
     pub(crate) fn evc_picman_init(
@@ -513,7 +679,9 @@ impl EvcPm {
         }
 
         self.picman_update_pic_ref();
-        evc_assert_rv(self.cur_num_ref_pics > 0, EvcError::EVC_ERR_UNEXPECTED)?;
+        if self.cur_num_ref_pics == 0 {
+            return Err(EvcError::EVC_ERR_UNEXPECTED);
+        }
 
         for i in 0..MAX_NUM_REF_PICS {
             refp[i][REFP_0].pic = None;
@@ -522,6 +690,14 @@ impl EvcPm {
         self.num_refp[REFP_0] = 0;
         self.num_refp[REFP_1] = 0;
 
+        let lt_refs: Vec<_> = self
+            .pic_ref
+            .iter()
+            .flatten()
+            .filter(|pic| pic.read().unwrap().is_lt)
+            .map(Arc::clone)
+            .collect();
+
         let (mut i, mut cnt) = (0i8, 0usize);
 
         /* forward */
@@ -531,18 +707,18 @@ impl EvcPm {
                 cnt = 0;
                 while i < self.cur_num_ref_pics as i8 && cnt < max_num_ref_pics as usize {
                     if let Some(pic_ref) = &self.pic_ref[i as usize] {
-                        let pr = pic_ref.borrow();
+                        let pr = pic_ref.read().unwrap();
                         /* if(poc >= last_intra && pm->pic_ref[i]->poc < last_intra) continue; */
                         if layer_id == 1 {
                             if pr.poc < poc && pr.temporal_id <= layer_id {
-                                refp[cnt][REFP_0].set_refp(Rc::clone(pic_ref));
+                                refp[cnt][REFP_0].set_refp(Arc::clone(pic_ref));
                                 cnt += 1;
                             }
                         } else if pr.poc < poc && cnt == 0 {
-                            refp[cnt][REFP_0].set_refp(Rc::clone(pic_ref));
+                            refp[cnt][REFP_0].set_refp(Arc::clone(pic_ref));
                             cnt += 1;
                         } else if cnt != 0 && pr.poc < poc && pr.temporal_id <= 1 {
-                            refp[cnt][REFP_0].set_refp(Rc::clone(pic_ref));
+                            refp[cnt][REFP_0].set_refp(Arc::clone(pic_ref));
                             cnt += 1;
                         }
                         i += 1;
@@ -557,13 +733,14 @@ impl EvcPm {
                 cnt = 0;
                 while i < self.cur_num_ref_pics as i8 && cnt < max_num_ref_pics as usize {
                     if let Some(pic_ref) = &self.pic_ref[i as usize] {
-                        let pr = pic_ref.borrow();
-                        if poc >= last_intra as u32 && pr.poc < last_intra as u32 {
+                        let pr = pic_ref.read().unwrap();
+                        if !pr.is_lt && poc >= last_intra as u32 && pr.poc < last_intra as u32 {
+                            i += 1;
                             continue;
                         }
 
                         if pr.poc < poc {
-                            refp[cnt][REFP_0].set_refp(Rc::clone(pic_ref));
+                            refp[cnt][REFP_0].set_refp(Arc::clone(pic_ref));
                             cnt += 1;
                         }
                         i += 1;
@@ -580,13 +757,14 @@ impl EvcPm {
             cnt = 0;
             while i < self.cur_num_ref_pics as i8 && cnt < max_num_ref_pics as usize {
                 if let Some(pic_ref) = &self.pic_ref[i as usize] {
-                    let pr = pic_ref.borrow();
-                    if poc >= last_intra as u32 && pr.poc < last_intra as u32 {
+                    let pr = pic_ref.read().unwrap();
+                    if !pr.is_lt && poc >= last_intra as u32 && pr.poc < last_intra as u32 {
+                        i += 1;
                         continue;
                     }
 
                     if pr.poc < poc && pr.temporal_id <= next_layer_id {
-                        refp[cnt][REFP_0].set_refp(Rc::clone(pic_ref));
+                        refp[cnt][REFP_0].set_refp(Arc::clone(pic_ref));
                         cnt += 1;
                         next_layer_id = std::cmp::max(pr.temporal_id, 1) - 1;
                     }
@@ -602,13 +780,14 @@ impl EvcPm {
             i = self.cur_num_ref_pics as i8 - 1;
             while i >= 0 && cnt < max_num_ref_pics as usize {
                 if let Some(pic_ref) = &self.pic_ref[i as usize] {
-                    let pr = pic_ref.borrow();
-                    if poc >= last_intra as u32 && pr.poc < last_intra as u32 {
+                    let pr = pic_ref.read().unwrap();
+                    if !pr.is_lt && poc >= last_intra as u32 && pr.poc < last_intra as u32 {
+                        i -= 1;
                         continue;
                     }
 
                     if pr.poc > poc && pr.temporal_id <= next_layer_id {
-                        refp[cnt][REFP_0].set_refp(Rc::clone(pic_ref));
+                        refp[cnt][REFP_0].set_refp(Arc::clone(pic_ref));
                         cnt += 1;
                         next_layer_id = std::cmp::max(pr.temporal_id, 1) - 1;
                     }
@@ -619,7 +798,9 @@ impl EvcPm {
             }
         }
 
-        evc_assert_rv(cnt > 0, EvcError::EVC_ERR_UNEXPECTED)?;
+        if cnt == 0 && lt_refs.is_empty() {
+            return Err(EvcError::EVC_ERR_UNEXPECTED);
+        }
         self.num_refp[REFP_0] = cnt as u8;
 
         /* backward */
@@ -629,13 +810,14 @@ impl EvcPm {
             cnt = 0;
             while i >= 0 && cnt < max_num_ref_pics as usize {
                 if let Some(pic_ref) = &self.pic_ref[i as usize] {
-                    let pr = pic_ref.borrow();
-                    if poc >= last_intra as u32 && pr.poc < last_intra as u32 {
+                    let pr = pic_ref.read().unwrap();
+                    if !pr.is_lt && poc >= last_intra as u32 && pr.poc < last_intra as u32 {
+                        i -= 1;
                         continue;
                     }
 
                     if pr.poc > poc && pr.temporal_id <= next_layer_id {
-                        refp[cnt][REFP_1].set_refp(Rc::clone(pic_ref));
+                        refp[cnt][REFP_1].set_refp(Arc::clone(pic_ref));
                         cnt += 1;
                         next_layer_id = std::cmp::max(pr.temporal_id, 1) - 1;
                     }
@@ -650,13 +832,14 @@ impl EvcPm {
                 i = 0;
                 while i < self.cur_num_ref_pics as i8 && cnt < max_num_ref_pics as usize {
                     if let Some(pic_ref) = &self.pic_ref[i as usize] {
-                        let pr = pic_ref.borrow();
-                        if poc >= last_intra as u32 && pr.poc < last_intra as u32 {
+                        let pr = pic_ref.read().unwrap();
+                        if !pr.is_lt && poc >= last_intra as u32 && pr.poc < last_intra as u32 {
+                            i += 1;
                             continue;
                         }
 
                         if pr.poc < poc && pr.temporal_id <= next_layer_id {
-                            refp[cnt][REFP_1].set_refp(Rc::clone(pic_ref));
+                            refp[cnt][REFP_1].set_refp(Arc::clone(pic_ref));
                             cnt += 1;
                             next_layer_id = std::cmp::max(pr.temporal_id, 1) - 1;
                         }
@@ -667,7 +850,9 @@ impl EvcPm {
                 }
             }
 
-            evc_assert_rv(cnt > 0, EvcError::EVC_ERR_UNEXPECTED)?;
+            if cnt == 0 {
+                return Err(EvcError::EVC_ERR_UNEXPECTED);
+            }
             self.num_refp[REFP_1] = cnt as u8;
         }
 
@@ -676,6 +861,253 @@ impl EvcPm {
             self.num_refp[REFP_1] = std::cmp::min(self.num_refp[REFP_1], max_num_ref_pics);
         }
 
+        /* the long-term reference pictures are not dropped with the older
+        short-term ones: they follow them in the list 0 if not already there */
+        for pic_ref in lt_refs {
+            if self.num_refp[REFP_0] as usize >= MAX_NUM_ACTIVE_REF_FRAME {
+                break;
+            }
+            if refp[..self.num_refp[REFP_0] as usize].iter().any(|r| {
+                r[REFP_0]
+                    .pic
+                    .as_ref()
+                    .map_or(false, |p| Arc::ptr_eq(p, &pic_ref))
+            }) {
+                continue;
+            }
+            refp[self.num_refp[REFP_0] as usize][REFP_0].set_refp(pic_ref);
+            self.num_refp[REFP_0] += 1;
+        }
+
+        Ok(())
+    }
+
+    /* reference picture lists signalled by the slice: every active entry has
+    to be a reference picture of the DPB */
+    pub(crate) fn evc_picman_refp_rpl_based_init(
+        &mut self,
+        sh: &EvcSh,
+        poc: u32,
+        refp: &mut Vec<Vec<EvcRefP>>,
+    ) -> Result<(), EvcError> {
+        if sh.slice_type == SliceType::EVC_ST_I {
+            return Ok(());
+        }
+
+        self.picman_update_pic_ref();
+
+        for i in 0..MAX_NUM_REF_PICS {
+            refp[i][REFP_0].pic = None;
+            refp[i][REFP_1].pic = None;
+        }
+        self.num_refp[REFP_0] = 0;
+        self.num_refp[REFP_1] = 0;
+
+        let num_lists = if sh.slice_type == SliceType::EVC_ST_B {
+            REFP_NUM
+        } else {
+            1
+        };
+        for lidx in 0..num_lists {
+            let rpl = if lidx == REFP_0 {
+                &sh.rpl_l0
+            } else {
+                &sh.rpl_l1
+            };
+            if rpl.ref_pic_active_num == 0
+                || rpl.ref_pic_active_num > rpl.ref_pic_num
+                || rpl.ref_pic_active_num as usize > MAX_NUM_ACTIVE_REF_FRAME
+            {
+                return Err(EvcError::EVC_ERR_MALFORMED_BITSTREAM);
+            }
+
+            for i in 0..rpl.ref_pic_active_num as usize {
+                let ref_poc = poc as i32 - rpl.ref_pics[i];
+                let pic_ref = self
+                    .pic_ref
+                    .iter()
+                    .flatten()
+                    .find(|pic| {
+                        let p = pic.read().unwrap();
+                        if rpl.ref_pic_lt[i] {
+                            p.is_lt
+                                && evc_poc_lsb(p.poc, rpl.lt_poc_lsb_len) == rpl.ref_pics[i] as u32
+                        } else {
+                            !p.is_lt && p.poc as i32 == ref_poc
+                        }
+                    })
+                    .map(Arc::clone)
+                    .ok_or(EvcError::EVC_ERR_UNEXPECTED)?;
+                refp[i][lidx].set_refp(pic_ref);
+            }
+            self.num_refp[lidx] = rpl.ref_pic_active_num;
+        }
+
         Ok(())
     }
+
+    /* reference picture lists describing the references of the current
+    picture: the inactive entries of the list 0 keep the other reference
+    pictures for the following pictures. the long-term reference picture is
+    kept by its POC difference like the short-term ones, the reference decoder
+    does not parse the long-term entries */
+    pub(crate) fn evc_picman_get_rpl(
+        &self,
+        slice_type: SliceType,
+        poc: u32,
+        refp: &[Vec<EvcRefP>],
+    ) -> [EvcRpl; REFP_NUM] {
+        let mut rpl: [EvcRpl; REFP_NUM] = Default::default();
+        let num_lists = match slice_type {
+            SliceType::EVC_ST_B => REFP_NUM,
+            SliceType::EVC_ST_P => 1,
+            _ => 0,
+        };
+        for lidx in 0..num_lists {
+            for i in 0..self.num_refp[lidx] as usize {
+                let p = refp[i][lidx].pic.as_ref().unwrap().read().unwrap();
+                rpl[lidx].ref_pics[i] = poc as i32 - p.poc as i32;
+            }
+            rpl[lidx].ref_pic_num = self.num_refp[lidx];
+            rpl[lidx].ref_pic_active_num = self.num_refp[lidx];
+        }
+
+        for pic in self.pic.iter().flatten() {
+            let p = pic.read().unwrap();
+            let ref_pic = poc as i32 - p.poc as i32;
+            if p.is_ref
+                && !rpl
+                    .iter()
+                    .any(|rpl| rpl.ref_pics[..rpl.ref_pic_num as usize].contains(&ref_pic))
+                && (rpl[REFP_0].ref_pic_num as usize) < MAX_NUM_REF_PICS
+            {
+                let i = rpl[REFP_0].ref_pic_num as usize;
+                rpl[REFP_0].ref_pics[i] = ref_pic;
+                rpl[REFP_0].ref_pic_num += 1;
+            }
+        }
+
+        rpl
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn long_term_ref_outlives_sliding_window() {
+        let mut pm = EvcPm::new(16, 16, ChromaSampling::Cs420);
+        assert!(pm
+            .evc_picman_init(MAX_PB_SIZE as u8, MAX_NUM_REF_PICS as u8)
+            .is_ok());
+        let mut refp: Vec<Vec<EvcRefP>> = (0..MAX_NUM_REF_PICS)
+            .map(|_| vec![EvcRefP::new(); REFP_NUM])
+            .collect();
+
+        /* low delay pictures, the first one is pinned */
+        for poc in 0..10 {
+            if poc > 0 {
+                let slice_type = SliceType::EVC_ST_B;
+                assert!(pm
+                    .evc_picman_refp_init(
+                        MAX_NUM_ACTIVE_REF_FRAME_LDB,
+                        slice_type,
+                        poc,
+                        0,
+                        0,
+                        &mut refp
+                    )
+                    .is_ok());
+                let l0: Vec<u32> = refp[..pm.num_refp[REFP_0] as usize]
+                    .iter()
+                    .map(|r| r[REFP_0].poc)
+                    .collect();
+                assert!(l0.contains(&0), "POC {} refers {:?}", poc, l0);
+                assert!(l0.contains(&(poc - 1)));
+                assert!(l0.len() <= MAX_NUM_ACTIVE_REF_FRAME);
+
+                /* the RPLs signal it by its POC difference */
+                let rpl = pm.evc_picman_get_rpl(slice_type, poc, &refp);
+                let l0 = &rpl[REFP_0].ref_pics[..rpl[REFP_0].ref_pic_active_num as usize];
+                assert!(l0.contains(&(poc as i32)));
+                assert!(!rpl[REFP_0].ref_pic_lt.contains(&true));
+            }
+
+            let pic = pm.evc_picman_get_empty_pic().unwrap();
+            pm.evc_picman_put_pic(&pic, poc == 0, poc, 0, false, &mut refp, true, false, 1);
+            if poc == 0 {
+                pm.evc_picman_set_lt_ref(pic.as_ref().unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn rpl_based_lists_match_derived_lists() {
+        let mut pm = EvcPm::new(16, 16, ChromaSampling::Cs420);
+        assert!(pm
+            .evc_picman_init(MAX_PB_SIZE as u8, MAX_NUM_REF_PICS as u8)
+            .is_ok());
+        let new_refp = || -> Vec<Vec<EvcRefP>> {
+            (0..MAX_NUM_REF_PICS)
+                .map(|_| vec![EvcRefP::new(); REFP_NUM])
+                .collect()
+        };
+        let list_pocs = |pm: &EvcPm, refp: &[Vec<EvcRefP>], lidx: usize| -> Vec<u32> {
+            refp[..pm.num_refp[lidx] as usize]
+                .iter()
+                .map(|r| r[lidx].poc)
+                .collect()
+        };
+        let mut refp = new_refp();
+
+        /* hierarchical GOP of 4 pictures in coding order */
+        for (poc, temporal_id) in [(0, 0), (4, 1), (2, 2), (1, 3), (3, 3)] {
+            if poc > 0 {
+                let slice_type = SliceType::EVC_ST_B;
+                assert!(pm
+                    .evc_picman_refp_init(
+                        MAX_NUM_ACTIVE_REF_FRAME_B,
+                        slice_type,
+                        poc,
+                        temporal_id,
+                        0,
+                        &mut refp
+                    )
+                    .is_ok());
+                let l0 = list_pocs(&pm, &refp, REFP_0);
+                let l1 = list_pocs(&pm, &refp, REFP_1);
+                if poc == 2 {
+                    assert_eq!((l0[0], l1[0]), (0, 4));
+                }
+
+                let rpl = pm.evc_picman_get_rpl(slice_type, poc, &refp);
+                let sh = EvcSh {
+                    slice_type,
+                    rpl_l0: rpl[REFP_0].clone(),
+                    rpl_l1: rpl[REFP_1].clone(),
+                    ..Default::default()
+                };
+                let mut refp_rpl = new_refp();
+                assert!(pm
+                    .evc_picman_refp_rpl_based_init(&sh, poc, &mut refp_rpl)
+                    .is_ok());
+                assert_eq!(list_pocs(&pm, &refp_rpl, REFP_0), l0, "POC {}", poc);
+                assert_eq!(list_pocs(&pm, &refp_rpl, REFP_1), l1, "POC {}", poc);
+            }
+
+            let pic = pm.evc_picman_get_empty_pic().unwrap();
+            pm.evc_picman_put_pic(
+                &pic,
+                poc == 0,
+                poc,
+                temporal_id,
+                false,
+                &mut refp,
+                true,
+                false,
+                0,
+            );
+        }
+    }
 }
@@ -145,11 +145,10 @@ where
     T: Display,
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "Plane {{ data: [{}, ...], cfg: {:?} }}",
-            self.data[0], self.cfg
-        )
+        match self.data.first() {
+            Some(v) => write!(f, "Plane {{ data: [{}, ...], cfg: {:?} }}", v, self.cfg),
+            None => write!(f, "Plane {{ data: [], cfg: {:?} }}", self.cfg),
+        }
     }
 }
 
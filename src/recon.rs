@@ -86,6 +86,7 @@ pub(crate) fn evc_recon_yuv(
     pred: &[pel],
     nnz: &[bool; N_C],
     planes: &mut [Plane<pel>; N_C],
+    chroma_format_idc: u8,
 ) {
     /* Y */
     let rec = &mut planes[Y_C].as_region_mut();
@@ -102,11 +103,17 @@ pub(crate) fn evc_recon_yuv(
         Y_C,
     );
 
+    if chroma_format_idc == 0 {
+        return;
+    }
+
     /* chroma */
-    x >>= 1;
-    y >>= 1;
-    cuw >>= 1;
-    cuh >>= 1;
+    let w_shift = GET_CHROMA_W_SHIFT(chroma_format_idc);
+    let h_shift = GET_CHROMA_H_SHIFT(chroma_format_idc);
+    x >>= w_shift;
+    y >>= h_shift;
+    cuw >>= w_shift;
+    cuh >>= h_shift;
 
     let rec = &mut planes[U_C].as_region_mut();
     evc_recon_plane_region(
@@ -2,12 +2,10 @@ use super::api::*;
 use super::def::*;
 use super::util::*;
 
-pub(crate) static tbl_cu_dim_offset: [usize; 3] = [0, MAX_CU_DIM, MAX_CU_DIM + (MAX_CU_DIM >> 2)];
-pub(crate) static tbl_nb_siz_offset: [usize; 3] = [
-    0,
-    (MAX_CU_SIZE << 2) + 1,
-    (MAX_CU_SIZE << 2) + 1 + (MAX_CU_SIZE << 1) + 1,
-];
+/* the chroma buffers are sized for 4:4:4 */
+pub(crate) static tbl_cu_dim_offset: [usize; 3] = [0, MAX_CU_DIM, MAX_CU_DIM << 1];
+pub(crate) static tbl_nb_siz_offset: [usize; 3] =
+    [0, (MAX_CU_SIZE << 2) + 1, ((MAX_CU_SIZE << 2) + 1) << 1];
 
 pub(crate) static evey_tbl_mpm: [[[u8; 5]; 6]; 6] = [
     [
@@ -60,6 +58,63 @@ pub(crate) static evey_tbl_mpm: [[[u8; 5]; 6]; 6] = [
     ],
 ];
 
+/* tangents of the intra angular modes of the main profile in 1/1024 units,
+{dx/dy, dy/dx} */
+#[rustfmt::skip]
+pub(crate) static evc_tbl_ipred_dxdy: [[i32; 2]; IPD_CNT] = [
+    [0, 0], [0, 0], [0, 0], /* DC, planar, bilinear */
+    [2816, 372], [2048, 512], [1408, 744], [1024, 1024], [744, 1408], /* 3 - 7 */
+    [512, 2048], [372, 2816], [256, 4096], [128, 8192], [0, 0], /* 8 - 12 */
+    [128, 8192], [256, 4096], [372, 2816], [512, 2048], [744, 1408], /* 13 - 17 */
+    [1024, 1024], [1408, 744], [2048, 512], [2816, 372], [4096, 256], /* 18 - 22 */
+    [8192, 128], [0, 0], [8192, 128], [4096, 256], [2816, 372], /* 23 - 27 */
+    [2048, 512], [1408, 744], [1024, 1024], [744, 1408], [512, 2048], /* 28 - 32 */
+];
+
+/* 4-tap filters interpolating the reference samples at 1/32 positions */
+pub(crate) static evc_tbl_ipred_adi: [[i32; 4]; 32] = [
+    [32, 64, 32, 0],
+    [31, 63, 33, 1],
+    [30, 62, 34, 2],
+    [29, 61, 35, 3],
+    [28, 60, 36, 4],
+    [27, 59, 37, 5],
+    [26, 58, 38, 6],
+    [25, 57, 39, 7],
+    [24, 56, 40, 8],
+    [23, 55, 41, 9],
+    [22, 54, 42, 10],
+    [21, 53, 43, 11],
+    [20, 52, 44, 12],
+    [19, 51, 45, 13],
+    [18, 50, 46, 14],
+    [17, 49, 47, 15],
+    [16, 48, 48, 16],
+    [15, 47, 49, 17],
+    [14, 46, 50, 18],
+    [13, 45, 51, 19],
+    [12, 44, 52, 20],
+    [11, 43, 53, 21],
+    [10, 42, 54, 22],
+    [9, 41, 55, 23],
+    [8, 40, 56, 24],
+    [7, 39, 57, 25],
+    [6, 38, 58, 26],
+    [5, 37, 59, 27],
+    [4, 36, 60, 28],
+    [3, 35, 61, 29],
+    [2, 34, 62, 30],
+    [1, 33, 63, 31],
+];
+
+/* the luma modes of the main profile in the order the remaining modes are
+coded after the most probable and the secondary modes */
+#[rustfmt::skip]
+pub(crate) static evc_tbl_ipred_rem_order: [i32; IPD_CNT] = [
+    0, 2, 12, 1, 24, 11, 13, 10, 14, 9, 15, 23, 25, 22, 26, 21, 27,
+    18, 6, 3, 4, 5, 30, 31, 32, 17, 16, 20, 19, 7, 8, 29, 28,
+];
+
 pub(crate) static evc_tbl_log2: [u8; 257] = [
     /* 0, 1 */
     0, 0, /* 2, 3 */
@@ -86,9 +141,18 @@ pub(crate) static evc_tbl_qp_chroma_ajudst_base: [i8; MAX_QP_TABLE_SIZE_EXT] = [
     33, 34, 34, 35, 35, 36, 36, 36, 37, 37, 37, 38, 38, 39, 39, 40, 40, 40, 41, 41, 41,
 ];
 
+/* chroma QP adjustment of the improved quantization */
+pub(crate) static evc_tbl_qp_chroma_ajudst_main: [i8; MAX_QP_TABLE_SIZE_EXT] = [
+    -12, -11, -10, -9, -8, -7, -6, -5, -4, -3, -2, -1, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 29, 30, 31, 32, 33, 34, 35,
+    36, 37, 37, 38, 39, 40, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 51, 52, 53, 54,
+];
+
 pub(crate) const EVC_TBL_CHROMA_QP_OFFSET: i8 = 6 * (BIT_DEPTH as i8 - 8);
 
 pub(crate) static evc_tbl_dq_scale_b: [i16; 6] = [40, 45, 51, 57, 64, 71];
+/* dequantization scale of the improved quantization */
+pub(crate) static evc_tbl_dq_scale: [i16; 6] = [40, 45, 51, 57, 64, 72];
 
 #[rustfmt::skip]
 pub(crate) static  evc_tbl_tm2:[[i8;2];2] = [
@@ -275,34 +339,419 @@ pub(crate) static evc_tbl_tm[i8;MAX_CU_DEPTH] =
 };*/
 
 lazy_static! {
-    pub(crate) static ref evc_scan_tbl: [Box<[u16]>; MAX_CU_LOG2] = {
-        [
-            scan_tbl(2),
-            scan_tbl(4),
-            scan_tbl(8),
-            scan_tbl(16),
-            scan_tbl(32),
-            scan_tbl(64),
-        ]
+    /* zig-zag scan of each block size, indexed by [log2_w - 1][log2_h - 1] */
+    pub(crate) static ref evc_scan_tbl: Vec<Vec<Box<[u16]>>> = {
+        (1..=MAX_CU_LOG2)
+            .map(|log2_w| {
+                (1..=MAX_CU_LOG2)
+                    .map(|log2_h| scan_tbl(1 << log2_w, 1 << log2_h))
+                    .collect()
+            })
+            .collect()
     };
-    pub(crate) static ref evc_tbl_tr: [Box<[i16]>; MAX_CU_LOG2] = {
-        [
-            evc_init_multi_tbl(2),
-            evc_init_multi_tbl(4),
-            evc_init_multi_tbl(8),
-            evc_init_multi_tbl(16),
-            evc_init_multi_tbl(32),
-            evc_init_multi_tbl(64),
-        ]
+    /* inverse of evc_scan_tbl, giving the scan position of each coefficient */
+    pub(crate) static ref evc_inv_scan_tbl: Vec<Vec<Box<[u16]>>> = {
+        evc_scan_tbl
+            .iter()
+            .map(|tbls| {
+                tbls.iter()
+                    .map(|scan| {
+                        let mut inv_scan = vec![0u16; scan.len()].into_boxed_slice();
+                        for (pos, blk_pos) in scan.iter().enumerate() {
+                            inv_scan[*blk_pos as usize] = pos as u16;
+                        }
+                        inv_scan
+                    })
+                    .collect()
+            })
+            .collect()
     };
-    pub(crate) static ref evc_tbl_inv_tr: [Box<[i16]>; MAX_CU_LOG2] = {
-        [
-            evc_init_multi_inv_tbl(2),
-            evc_init_multi_inv_tbl(4),
-            evc_init_multi_inv_tbl(8),
-            evc_init_multi_inv_tbl(16),
-            evc_init_multi_inv_tbl(32),
-            evc_init_multi_inv_tbl(64),
-        ]
+    /* DST-VII and DCT-VIII kernels of the adaptive transform selection */
+    pub(crate) static ref evc_tbl_tr: [[Box<[i16]>; MAX_TR_LOG2]; NUM_TRANS_TYPE] = {
+        let init = |tr_type| {
+            [
+                evc_init_multi_tbl(2, tr_type),
+                evc_init_multi_tbl(4, tr_type),
+                evc_init_multi_tbl(8, tr_type),
+                evc_init_multi_tbl(16, tr_type),
+                evc_init_multi_tbl(32, tr_type),
+                evc_init_multi_tbl(64, tr_type),
+            ]
+        };
+        [init(DST7), init(DCT8)]
     };
 }
+
+/* advanced coefficient coding: prefix group of each last significant position */
+#[rustfmt::skip]
+pub(crate) static evc_tbl_group_idx: [u8; MAX_TR_SIZE] = [
+    0, 1, 2, 3, 4, 4, 5, 5, 6, 6, 6, 6, 7, 7, 7, 7,
+    8, 8, 8, 8, 8, 8, 8, 8, 9, 9, 9, 9, 9, 9, 9, 9,
+    10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10,
+    11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 11, 11,
+];
+/* first position of each prefix group */
+pub(crate) static evc_tbl_min_in_group: [u8; NUM_LAST_POS_GROUP] =
+    [0, 1, 2, 3, 4, 6, 8, 12, 16, 24, 32, 48];
+/* Rice parameter of the level remainder by the sum of the template levels */
+#[rustfmt::skip]
+pub(crate) static evc_tbl_go_rice_par: [u8; 32] = [
+    0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 2, 2,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3,
+];
+/* length of the Rice prefix of the level remainder before the Exp-Golomb
+escape, by Rice parameter */
+pub(crate) static evc_tbl_go_rice_range: [u8; 4] = [6, 5, 6, 3];
+
+/* adaptive loop filter: fixed filter candidates of each class */
+pub(crate) static evc_tbl_alf_class_to_filter: [[u8; 16]; MAX_NUM_ALF_CLASSES] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 9, 19, 32, 41, 42, 44, 46, 63],
+    [0, 1, 2, 4, 5, 6, 7, 9, 11, 16, 25, 27, 28, 31, 32, 47],
+    [5, 7, 9, 11, 12, 14, 15, 16, 17, 18, 19, 21, 22, 27, 31, 35],
+    [7, 8, 9, 11, 14, 15, 16, 17, 18, 19, 22, 23, 24, 25, 35, 36],
+    [7, 8, 11, 13, 14, 15, 16, 17, 19, 20, 21, 22, 23, 24, 25, 27],
+    [1, 2, 3, 4, 6, 19, 29, 30, 33, 34, 37, 41, 42, 44, 47, 54],
+    [1, 2, 3, 4, 6, 11, 28, 29, 30, 31, 32, 33, 34, 37, 47, 63],
+    [0, 1, 4, 6, 10, 12, 13, 19, 28, 29, 31, 32, 34, 35, 36, 37],
+    [6, 9, 10, 12, 13, 16, 19, 20, 28, 31, 35, 36, 37, 38, 39, 52],
+    [7, 8, 10, 11, 12, 13, 19, 23, 25, 27, 28, 31, 35, 36, 38, 39],
+    [1, 2, 3, 5, 29, 30, 33, 34, 40, 43, 44, 46, 54, 55, 59, 62],
+    [1, 2, 3, 4, 29, 30, 31, 33, 34, 37, 40, 41, 43, 44, 59, 61],
+    [0, 1, 3, 6, 19, 28, 29, 30, 31, 32, 33, 34, 37, 41, 44, 61],
+    [1, 6, 10, 13, 19, 28, 29, 30, 32, 33, 34, 35, 37, 41, 48, 52],
+    [0, 5, 6, 10, 19, 27, 28, 29, 32, 37, 38, 40, 41, 47, 49, 58],
+    [1, 2, 3, 4, 11, 29, 33, 42, 43, 44, 45, 46, 48, 55, 56, 59],
+    [0, 1, 2, 5, 7, 9, 29, 40, 43, 44, 45, 47, 48, 56, 59, 63],
+    [0, 4, 5, 9, 14, 19, 26, 35, 36, 43, 45, 47, 48, 49, 50, 51],
+    [
+        9, 11, 12, 14, 16, 19, 20, 24, 26, 36, 38, 47, 49, 50, 51, 53,
+    ],
+    [7, 8, 13, 14, 20, 21, 24, 25, 26, 27, 35, 38, 47, 50, 52, 53],
+    [1, 2, 4, 29, 33, 40, 41, 42, 43, 44, 45, 46, 54, 55, 56, 58],
+    [2, 4, 32, 40, 42, 43, 44, 45, 46, 54, 55, 56, 58, 59, 60, 62],
+    [
+        0, 19, 42, 43, 45, 46, 48, 54, 55, 56, 57, 58, 59, 60, 61, 62,
+    ],
+    [
+        8, 13, 36, 42, 45, 46, 51, 53, 54, 57, 58, 59, 60, 61, 62, 63,
+    ],
+    [
+        8, 13, 20, 27, 36, 38, 42, 46, 52, 53, 56, 57, 59, 61, 62, 63,
+    ],
+];
+
+/* adaptive loop filter: coefficients of the fixed filters */
+pub(crate) static evc_tbl_alf_fixed_coeff: [[i16; MAX_NUM_ALF_LUMA_COEFF]; 64] = [
+    [0, 2, 7, -12, -4, -11, -2, 31, -9, 6, -4, 30, -68],
+    [-26, 4, 17, 22, -7, 19, 40, 47, 49, -28, 35, 48, -440],
+    [-24, -8, 30, 64, -13, 18, 18, 27, 80, 0, 31, 19, -484],
+    [-4, -14, 44, 100, -7, 6, -4, 8, 90, 26, 26, -12, -518],
+    [-17, -9, 23, -3, -15, 20, 53, 48, 16, -25, 42, 66, -398],
+    [-12, -2, 1, -19, -5, 8, 66, 80, -2, -25, 20, 78, -376],
+    [2, 8, -23, -14, -3, -23, 64, 86, 35, -17, -4, 79, -380],
+    [12, 4, -39, -7, 1, -20, 78, 13, -8, 11, -42, 98, -202],
+    [0, 3, -4, 0, 2, -7, 6, 0, 0, 3, -8, 11, -12],
+    [4, -7, -25, -19, -9, 8, 86, 65, -14, -7, -7, 97, -344],
+    [3, 3, 2, -30, 6, -34, 43, 71, -10, 4, -23, 77, -224],
+    [12, -3, -34, -14, -5, -14, 88, 28, -12, 8, -34, 112, -264],
+    [-1, 6, 8, -29, 7, -27, 15, 60, -4, 6, -21, 39, -118],
+    [8, -1, -7, -22, 5, -41, 63, 40, -13, 7, -28, 105, -232],
+    [1, 3, -5, -1, 1, -10, 12, -1, 0, 3, -9, 19, -26],
+    [10, -1, -23, -14, -3, -27, 78, 24, -14, 8, -28, 102, -224],
+    [0, 0, -1, 0, 0, -1, 1, 0, 0, 0, 0, 1, 0],
+    [7, 3, -19, -7, 2, -27, 51, 8, -6, 7, -24, 64, -118],
+    [11, -10, -22, -22, -11, -12, 87, 49, -20, 4, -16, 108, -292],
+    [17, -2, -69, -4, -4, 22, 106, 31, -7, 13, -63, 121, -322],
+    [1, 4, -1, -7, 5, -26, 24, 0, 1, 3, -18, 51, -74],
+    [3, 5, -10, -2, 4, -17, 17, 1, -2, 6, -16, 27, -32],
+    [9, 2, -23, -5, 6, -45, 90, -22, 1, 7, -39, 121, -204],
+    [4, 5, -15, -2, 4, -22, 34, -2, -2, 7, -22, 48, -74],
+    [6, 8, -22, -3, 4, -32, 57, -3, -4, 11, -43, 102, -162],
+    [2, 5, -11, 1, 12, -46, 64, -32, 7, 4, -31, 85, -120],
+    [5, 5, -12, -8, 6, -48, 74, -13, -1, 7, -41, 129, -206],
+    [0, 1, -1, 0, 1, -3, 2, 0, 0, 1, -3, 4, -4],
+    [-1, 3, 16, -42, 6, -16, 2, 105, 6, 6, -31, 43, -194],
+    [7, 8, -27, -4, -4, -23, 46, 79, 64, -8, -13, 68, -386],
+    [-3, 12, -4, -34, 14, -6, -24, 179, 56, 2, -48, 15, -318],
+    [8, 0, -16, -25, -1, -29, 68, 84, 3, -3, -18, 94, -330],
+    [-3, -1, 22, -32, 2, -20, 5, 89, 0, 9, -18, 40, -186],
+    [14, 6, -51, 22, -10, -22, 36, 75, 106, -4, -11, 56, -434],
+    [1, 38, -59, 14, 8, -44, -18, 156, 80, -1, -42, 29, -324],
+    [-1, 2, 4, -9, 3, -13, 7, 17, -4, 2, -6, 17, -38],
+    [11, -2, -15, -36, 2, -32, 67, 89, -19, -1, -14, 103, -306],
+    [-1, 10, 3, -28, 7, -27, 7, 117, 34, 1, -35, 51, -278],
+    [3, 3, 4, -18, 6, -40, 36, 18, -8, 7, -25, 86, -144],
+    [-1, 3, 9, -18, 5, -26, 12, 37, -11, 3, -7, 32, -76],
+    [0, 17, -38, -9, -28, -17, 25, 48, 103, 2, 40, 69, -424],
+    [6, 4, -11, -20, 5, -32, 51, 77, 17, 0, -25, 84, -312],
+    [0, -5, 28, -24, -1, -22, 18, -9, 17, -1, -12, 107, -192],
+    [-10, -4, 17, -30, -29, 31, 40, 49, 44, -26, 67, 67, -432],
+    [-30, -12, 39, 15, -21, 32, 29, 26, 71, 20, 43, 28, -480],
+    [6, -7, -7, -34, -21, 15, 53, 60, 12, -26, 45, 89, -370],
+    [-1, -5, 59, -58, -8, -30, 2, 17, 34, -7, 25, 111, -278],
+    [7, 1, -7, -20, -9, -22, 48, 27, -4, -6, 0, 107, -244],
+    [-2, 22, 29, -70, -4, -28, 2, 19, 94, -40, 14, 110, -292],
+    [13, 0, -22, -27, -11, -15, 66, 44, -7, -5, -10, 121, -294],
+    [10, 6, -22, -14, -2, -33, 68, 15, -9, 5, -35, 135, -248],
+    [2, 11, 4, -32, -3, -20, 23, 18, 17, -1, -28, 88, -158],
+    [0, 3, -2, -1, 3, -16, 16, -3, 0, 2, -12, 35, -50],
+    [1, 6, -6, -3, 10, -51, 70, -31, 5, 6, -42, 125, -180],
+    [5, -7, 61, -71, -36, -6, -2, 15, 57, 18, 14, 108, -312],
+    [9, 1, 35, -70, -73, 28, 13, 1, 96, 40, 36, 80, -392],
+    [11, -7, 33, -72, -78, 48, 33, 37, 35, 7, 85, 76, -416],
+    [4, 15, 1, -26, -24, -19, 32, 29, -8, -6, 21, 125, -288],
+    [11, 8, 14, -57, -63, 21, 34, 51, 7, -3, 69, 89, -362],
+    [7, 16, -7, -31, -38, -5, 41, 44, -11, -10, 45, 109, -320],
+    [5, 16, 16, -46, -55, 3, 22, 32, 13, 0, 48, 107, -322],
+    [2, 10, -3, -14, -9, -28, 39, 15, -10, -5, -1, 123, -238],
+    [3, 11, 11, -27, -17, -24, 18, 22, 2, 4, 3, 100, -212],
+    [0, 1, 7, -9, 3, -20, 16, 3, -2, 0, -9, 61, -102],
+];
+
+/* adaptive loop filter: golomb order index of each coefficient */
+pub(crate) static evc_tbl_alf_golomb_idx7: [u8; MAX_NUM_ALF_LUMA_COEFF - 1] =
+    [0, 0, 1, 0, 0, 1, 2, 1, 0, 0, 1, 2];
+pub(crate) static evc_tbl_alf_golomb_idx5: [u8; MAX_NUM_ALF_CHROMA_COEFF - 1] = [0, 0, 1, 0, 0, 1];
+
+/* adaptive loop filter: position in the 7x7 filter of the coefficients of
+each filter shape, 0 if the position is not covered */
+pub(crate) static evc_tbl_alf_pattern_to_large7: [u8; MAX_NUM_ALF_LUMA_COEFF] =
+    [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13];
+pub(crate) static evc_tbl_alf_pattern_to_large5: [u8; MAX_NUM_ALF_LUMA_COEFF] =
+    [0, 0, 1, 0, 0, 2, 3, 4, 0, 0, 5, 6, 7];
+
+/* class of CU size selecting the contexts of btt_split_flag, by log2 of the
+width and height minus 2. 4x4 CUs are not split */
+pub(crate) static evc_tbl_split_flag_ctx: [[u8; 6]; 6] = [
+    [0, 4, 4, 14, 15, 15],
+    [4, 4, 3, 3, 2, 2],
+    [4, 3, 3, 2, 2, 1],
+    [14, 3, 2, 2, 1, 1],
+    [15, 2, 2, 1, 1, 0],
+    [15, 2, 1, 1, 0, 0],
+];
+
+/* merge with motion vector difference: offset distances in quarter samples,
+and offset directions */
+pub(crate) static evc_tbl_mmvd_dist: [i32; MMVD_DIST_NUM] = [1, 2, 4, 8, 16, 32, 64, 128];
+pub(crate) static evc_tbl_mmvd_dir: [[i32; MV_D]; MMVD_DIR_NUM] =
+    [[1, 0], [-1, 0], [0, 1], [0, -1]];
+
+/* alpha of the advanced deblocking filter by QP */
+pub(crate) static evc_tbl_addb_alpha: [u8; 52] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 4, 5, 6, 7, 8, 9, 10, 12, 13, 15, 17, 20,
+    22, 25, 28, 32, 36, 40, 45, 50, 56, 63, 71, 80, 90, 101, 113, 127, 144, 162, 182, 203, 226,
+    255, 255,
+];
+
+/* beta of the advanced deblocking filter by QP */
+pub(crate) static evc_tbl_addb_beta: [u8; 52] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 6, 6, 7, 7, 8, 8,
+    9, 9, 10, 10, 11, 11, 12, 12, 13, 13, 14, 14, 15, 15, 16, 16, 17, 17, 18, 18,
+];
+
+/* clipping values of the advanced deblocking filter by QP and boundary strength */
+pub(crate) static evc_tbl_addb_clip: [[u8; 5]; 52] = [
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 1, 1],
+    [0, 0, 0, 1, 1],
+    [0, 0, 0, 1, 1],
+    [0, 0, 0, 1, 1],
+    [0, 0, 1, 1, 1],
+    [0, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1],
+    [0, 1, 1, 1, 1],
+    [0, 1, 1, 1, 1],
+    [0, 1, 1, 1, 1],
+    [0, 1, 1, 2, 2],
+    [0, 1, 1, 2, 2],
+    [0, 1, 1, 2, 2],
+    [0, 1, 1, 2, 2],
+    [0, 1, 2, 3, 3],
+    [0, 1, 2, 3, 3],
+    [0, 2, 2, 3, 3],
+    [0, 2, 2, 4, 4],
+    [0, 2, 3, 4, 4],
+    [0, 2, 3, 4, 4],
+    [0, 3, 3, 5, 5],
+    [0, 3, 4, 6, 6],
+    [0, 3, 4, 6, 6],
+    [0, 4, 5, 7, 7],
+    [0, 4, 5, 8, 8],
+    [0, 4, 6, 9, 9],
+    [0, 5, 7, 10, 10],
+    [0, 6, 8, 11, 11],
+    [0, 6, 8, 13, 13],
+    [0, 7, 10, 14, 14],
+    [0, 8, 11, 16, 16],
+    [0, 9, 12, 18, 18],
+    [0, 10, 13, 20, 20],
+    [0, 11, 15, 23, 23],
+    [0, 13, 17, 25, 25],
+];
+
+/* initial values of the context models with sps_cm_init_flag, one row for
+I slices and one for P and B slices. each value holds the slope of the
+probability state over the slice QP in bits 0-3 and its offset in bits 4-10,
+both with their sign in the lowest bit */
+pub(crate) static evc_tbl_ctx_init_skip_flag: [[i16; NUM_CTX_SKIP_FLAG]; 2] = [[0, 0], [711, 233]];
+pub(crate) static evc_tbl_ctx_init_mmvd_flag: [[i16; NUM_CTX_MMVD_FLAG]; 2] = [[0], [194]];
+pub(crate) static evc_tbl_ctx_init_mmvd_merge_idx: [[i16; NUM_CTX_MMVD_MERGE_IDX]; 2] =
+    [[0, 0, 0], [49, 129, 82]];
+pub(crate) static evc_tbl_ctx_init_mmvd_dist_idx: [[i16; NUM_CTX_MMVD_DIST_IDX]; 2] =
+    [[0, 0, 0, 0, 0, 0, 0], [179, 5, 133, 131, 227, 64, 128]];
+pub(crate) static evc_tbl_ctx_init_mmvd_direction_idx: [[i16; NUM_CTX_MMVD_DIRECTION_IDX]; 2] =
+    [[0, 0], [161, 33]];
+pub(crate) static evc_tbl_ctx_init_mmvd_group_idx: [[i16; NUM_CTX_MMVD_GROUP_IDX]; 2] =
+    [[0, 0], [453, 48]];
+pub(crate) static evc_tbl_ctx_init_direct_mode_flag: [[i16; NUM_CTX_DIRECT_MODE_FLAG]; 2] =
+    [[0], [0]];
+pub(crate) static evc_tbl_ctx_init_merge_mode_flag: [[i16; NUM_CTX_MERGE_MODE_FLAG]; 2] =
+    [[0], [464]];
+pub(crate) static evc_tbl_ctx_init_inter_dir: [[i16; NUM_CTX_INTER_PRED_IDC]; 2] =
+    [[0, 0], [242, 80]];
+pub(crate) static evc_tbl_ctx_init_intra_dir: [[i16; NUM_CTX_INTRA_PRED_MODE]; 2] =
+    [[0, 0], [0, 0]];
+pub(crate) static evc_tbl_ctx_init_intra_luma_pred_mpm_flag: [[i16;
+    NUM_CTX_INTRA_LUMA_PRED_MPM_FLAG];
+    2] = [[263], [225]];
+pub(crate) static evc_tbl_ctx_init_intra_luma_pred_mpm_idx: [[i16;
+    NUM_CTX_INTRA_LUMA_PRED_MPM_IDX];
+    2] = [[436], [724]];
+pub(crate) static evc_tbl_ctx_init_intra_dir_c: [[i16; NUM_CTX_INTRA_PRED_MODE_C]; 2] =
+    [[465], [560]];
+pub(crate) static evc_tbl_ctx_init_pred_mode: [[i16; NUM_CTX_PRED_MODE]; 2] =
+    [[64, 0, 0], [481, 16, 368]];
+pub(crate) static evc_tbl_ctx_init_mode_cons: [[i16; NUM_CTX_MODE_CONS]; 2] =
+    [[64, 0, 0], [481, 16, 368]];
+pub(crate) static evc_tbl_ctx_init_refi: [[i16; NUM_CTX_REF_IDX]; 2] = [[0, 0], [288, 0]];
+pub(crate) static evc_tbl_ctx_init_merge_idx: [[i16; NUM_CTX_MERGE_IDX]; 2] =
+    [[0, 0, 0, 496, 496], [18, 128, 146, 37, 69]];
+pub(crate) static evc_tbl_ctx_init_mvp_idx: [[i16; NUM_CTX_MVP_IDX]; 2] = [[0, 0, 0], [0, 0, 0]];
+pub(crate) static evc_tbl_ctx_init_affine_mvp_idx: [[i16; NUM_CTX_AFFINE_MVP_IDX]; 2] =
+    [[0], [161]];
+pub(crate) static evc_tbl_ctx_init_mvr_idx: [[i16; NUM_CTX_MVR_IDX]; 2] =
+    [[0, 0, 0, 496], [773, 101, 421, 199]];
+pub(crate) static evc_tbl_ctx_init_bi_idx: [[i16; NUM_CTX_BI_IDX]; 2] = [[0, 0], [49, 17]];
+pub(crate) static evc_tbl_ctx_init_mvd: [[i16; NUM_CTX_MVD]; 2] = [[0], [18]];
+pub(crate) static evc_tbl_ctx_init_cbf_all: [[i16; NUM_CTX_CBF_ALL]; 2] = [[0], [794]];
+pub(crate) static evc_tbl_ctx_init_cbf_luma: [[i16; NUM_CTX_CBF_LUMA]; 2] = [[664], [368]];
+pub(crate) static evc_tbl_ctx_init_cbf_cb: [[i16; NUM_CTX_CBF_CB]; 2] = [[384], [416]];
+pub(crate) static evc_tbl_ctx_init_cbf_cr: [[i16; NUM_CTX_CBF_CR]; 2] = [[320], [288]];
+pub(crate) static evc_tbl_ctx_init_run: [[i16; NUM_CTX_CC_RUN]; 2] = [
+    [
+        48, 112, 128, 0, 321, 82, 419, 160, 385, 323, 353, 129, 225, 193, 387, 389, 453, 227, 453,
+        161, 421, 161, 481, 225,
+    ],
+    [
+        129, 178, 453, 97, 583, 259, 517, 259, 453, 227, 871, 355, 291, 227, 195, 97, 161, 65, 97,
+        33, 65, 1, 1003, 227,
+    ],
+];
+pub(crate) static evc_tbl_ctx_init_last: [[i16; NUM_CTX_CC_LAST]; 2] = [[421, 337], [33, 790]];
+pub(crate) static evc_tbl_ctx_init_level: [[i16; NUM_CTX_CC_LEVEL]; 2] = [
+    [
+        416, 98, 128, 66, 32, 82, 17, 48, 272, 112, 52, 50, 448, 419, 385, 355, 161, 225, 82, 97,
+        210, 0, 416, 224,
+    ],
+    [
+        805, 775, 775, 581, 355, 389, 65, 195, 48, 33, 224, 225, 775, 227, 355, 161, 129, 97, 33,
+        65, 16, 1, 841, 355,
+    ],
+];
+pub(crate) static evc_tbl_ctx_init_btt_split_flag: [[i16; NUM_CTX_BTT_SPLIT_FLAG]; 2] = [
+    [
+        145, 560, 528, 308, 594, 560, 180, 500, 626, 84, 406, 662, 320, 36, 340,
+    ],
+    [
+        536, 726, 594, 66, 338, 528, 258, 404, 464, 98, 342, 370, 384, 256, 65,
+    ],
+];
+pub(crate) static evc_tbl_ctx_init_btt_split_dir: [[i16; NUM_CTX_BTT_SPLIT_DIR]; 2] =
+    [[0, 417, 389, 99, 0], [0, 128, 81, 49, 0]];
+pub(crate) static evc_tbl_ctx_init_btt_split_type: [[i16; NUM_CTX_BTT_SPLIT_TYPE]; 2] =
+    [[257], [225]];
+pub(crate) static evc_tbl_ctx_init_affine_flag: [[i16; NUM_CTX_AFFINE_FLAG]; 2] =
+    [[0, 0], [320, 210]];
+pub(crate) static evc_tbl_ctx_init_affine_mode: [[i16; NUM_CTX_AFFINE_MODE]; 2] = [[0], [225]];
+pub(crate) static evc_tbl_ctx_init_affine_mrg: [[i16; NUM_CTX_AFFINE_MRG]; 2] =
+    [[0, 0, 0, 0, 0], [193, 129, 32, 323, 0]];
+pub(crate) static evc_tbl_ctx_init_affine_mvd_flag: [[i16; NUM_CTX_AFFINE_MVD_FLAG]; 2] =
+    [[0, 0], [547, 645]];
+pub(crate) static evc_tbl_ctx_init_suco_flag: [[i16; NUM_CTX_SUCO_FLAG]; 2] = [
+    [0, 0, 0, 0, 0, 0, 545, 0, 481, 515, 0, 32, 0, 0],
+    [0, 0, 0, 0, 0, 0, 577, 0, 481, 2, 0, 97, 0, 0],
+];
+pub(crate) static evc_tbl_ctx_init_alf_ctb_flag: [[i16; NUM_CTX_ALF_CTB_FLAG]; 2] = [[0], [0]];
+pub(crate) static evc_tbl_ctx_init_split_cu_flag: [[i16; NUM_CTX_SPLIT_CU_FLAG]; 2] = [[0], [0]];
+pub(crate) static evc_tbl_ctx_init_delta_qp: [[i16; NUM_CTX_DELTA_QP]; 2] = [[4], [4]];
+pub(crate) static evc_tbl_ctx_init_ats_mode: [[i16; NUM_CTX_ATS_MODE_FLAG]; 2] = [[512], [673]];
+pub(crate) static evc_tbl_ctx_init_ats_inter_flag: [[i16; NUM_CTX_ATS_INTER_FLAG]; 2] =
+    [[0, 0], [0, 0]];
+pub(crate) static evc_tbl_ctx_init_ats_inter_quad_flag: [[i16; NUM_CTX_ATS_INTER_QUAD_FLAG]; 2] =
+    [[0], [0]];
+pub(crate) static evc_tbl_ctx_init_ats_inter_hor_flag: [[i16; NUM_CTX_ATS_INTER_HOR_FLAG]; 2] =
+    [[0, 0, 0], [0, 0, 0]];
+pub(crate) static evc_tbl_ctx_init_ats_inter_pos_flag: [[i16; NUM_CTX_ATS_INTER_POS_FLAG]; 2] =
+    [[0], [0]];
+/* the unused contexts of the chroma set are zero */
+#[rustfmt::skip]
+pub(crate) static evc_tbl_ctx_init_sig_coeff_flag:
+    [[[i16; NUM_CTX_SIG_COEFF_FLAG]; NUM_SIG_COEFF_SET]; 2] = [
+    [
+        [387,  98, 233, 346, 717, 306, 233,  37, 321, 293, 244,  37, 329],
+        [645, 408, 493, 164, 781, 101, 179, 369, 871, 585, 244, 361, 147],
+        [416, 408, 628, 352, 406, 502, 566, 466,  54,  97, 521, 113, 147],
+        [519,  36, 297, 132, 457, 308, 231, 534,   0,   0,   0,   0,   0],
+    ],
+    [
+        [ 66,  34, 241, 321, 293, 113,  35,  83, 226, 519, 553, 229, 751],
+        [224, 129, 133, 162, 227, 178, 165, 532, 417, 357,  33, 489, 199],
+        [387, 939, 133, 515,  32, 131,   3, 305, 579, 323,  65,  99, 425],
+        [453, 291, 329, 679, 683, 391, 751,  51,   0,   0,   0,   0,   0],
+    ],
+];
+pub(crate) static evc_tbl_ctx_init_coeff_abs_level_greaterAB_flag: [[i16; NUM_CTX_GTX]; 2] = [
+    [
+        40, 225, 306, 272, 85, 120, 389, 664, 209, 322, 291, 536, 338, 709, 54, 244, 19, 566,
+    ],
+    [
+        38, 352, 340, 19, 305, 258, 18, 33, 209, 773, 517, 406, 719, 741, 613, 295, 37, 498,
+    ],
+];
+pub(crate) static evc_tbl_ctx_init_last_sig_coeff_x_prefix: [[i16; NUM_CTX_LAST_SIG_COEFF]; 2] = [
+    [
+        762, 310, 288, 828, 342, 451, 502, 51, 97, 416, 662, 890, 340, 146, 20, 337, 468, 975, 216,
+        66, 54,
+    ],
+    [
+        892, 84, 581, 600, 278, 419, 372, 568, 408, 485, 338, 632, 666, 732, 17, 178, 180, 585,
+        581, 34, 257,
+    ],
+];
+pub(crate) static evc_tbl_ctx_init_last_sig_coeff_y_prefix: [[i16; NUM_CTX_LAST_SIG_COEFF]; 2] = [
+    [
+        81, 440, 4, 534, 406, 226, 370, 370, 259, 38, 598, 792, 860, 312, 88, 662, 924, 161, 248,
+        20, 54,
+    ],
+    [
+        470, 376, 323, 276, 602, 52, 340, 600, 376, 378, 598, 502, 730, 538, 17, 195, 504, 378,
+        320, 160, 572,
+    ],
+];
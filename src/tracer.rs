@@ -5,7 +5,7 @@ use std::io::Write;
 use super::def::*;
 use super::region::*;
 
-pub(crate) type Tracer = (Box<dyn Write>, isize);
+pub(crate) type Tracer = (Box<dyn Write + Send>, isize);
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 #[cfg(feature = "trace")]
@@ -206,6 +206,7 @@ pub(crate) fn TRACE_DBF(
     tracer: &mut Option<Tracer>,
     ch_type: usize,
     size: usize,
+    taps: usize,
     hor: bool,
     dbf: &PlaneRegionMut<'_, pel>,
 ) {
@@ -213,32 +214,18 @@ pub(crate) fn TRACE_DBF(
     EVC_TRACE(tracer, "Dbf for ");
     EVC_TRACE(tracer, ch_type);
     EVC_TRACE(tracer, " x ");
-    EVC_TRACE(tracer, x);
+    EVC_TRACE(tracer, dbf.rect().x);
     EVC_TRACE(tracer, " y ");
-    EVC_TRACE(tracer, y);
+    EVC_TRACE(tracer, dbf.rect().y);
     EVC_TRACE(tracer, " size ");
     EVC_TRACE(tracer, size);
     EVC_TRACE(tracer, " hor ");
     EVC_TRACE(tracer, hor as u8);
     EVC_TRACE(tracer, " : ");
+    /* taps samples across the edge for each of the size lines */
     for k in 0..size {
-        if hor {
-            EVC_TRACE(tracer, dbf[0][k]);
-            EVC_TRACE(tracer, " , ");
-            EVC_TRACE(tracer, dbf[1][k]);
-            EVC_TRACE(tracer, " , ");
-            EVC_TRACE(tracer, dbf[2][k]);
-            EVC_TRACE(tracer, " , ");
-            EVC_TRACE(tracer, dbf[3][k]);
-            EVC_TRACE(tracer, " , ");
-        } else {
-            EVC_TRACE(tracer, dbf[k][0]);
-            EVC_TRACE(tracer, " , ");
-            EVC_TRACE(tracer, dbf[k][1]);
-            EVC_TRACE(tracer, " , ");
-            EVC_TRACE(tracer, dbf[k][2]);
-            EVC_TRACE(tracer, " , ");
-            EVC_TRACE(tracer, dbf[k][3]);
+        for n in 0..taps {
+            EVC_TRACE(tracer, if hor { dbf[n][k] } else { dbf[k][n] });
             EVC_TRACE(tracer, " , ");
         }
     }
@@ -381,6 +368,7 @@ pub(crate) fn TRACE_DBF(
     tracer: &mut Option<Tracer>,
     ch_type: usize,
     size: usize,
+    taps: usize,
     hor: bool,
     dbf: &PlaneRegionMut<'_, pel>,
 ) {
@@ -1,6 +1,7 @@
 use super::def::*;
 use super::picman::*;
 use super::tbl::*;
+use crate::api::frame::*;
 use crate::api::*;
 
 use std::ops::Neg;
@@ -53,6 +54,138 @@ pub(crate) fn evc_poc_derivation(sps: &EvcSps, tid: u8, poc: &mut EvcPoc) {
     poc.prev_doc_offset = doc_offset;
 }
 
+/* POC of a picture signalling its POC LSBs: the MSBs follow the previous
+Tid0 picture, wrapping around when the LSBs are more than half the range
+apart */
+pub(crate) fn evc_poc_lsb_derivation(sps: &EvcSps, tid: u8, poc_lsb: i32, poc: &mut EvcPoc) {
+    let max_poc_lsb = 1i32 << (sps.log2_max_pic_order_cnt_lsb_minus4 + 4);
+    let prev_poc_lsb = poc.prev_poc_val as i32 & (max_poc_lsb - 1);
+    let prev_poc_msb = poc.prev_poc_val as i32 - prev_poc_lsb;
+
+    let poc_msb = if poc_lsb < prev_poc_lsb && prev_poc_lsb - poc_lsb >= max_poc_lsb / 2 {
+        prev_poc_msb + max_poc_lsb
+    } else if poc_lsb > prev_poc_lsb && poc_lsb - prev_poc_lsb > max_poc_lsb / 2 {
+        prev_poc_msb - max_poc_lsb
+    } else {
+        prev_poc_msb
+    };
+
+    poc.poc_val = poc_msb + poc_lsb;
+    if tid == 0 {
+        poc.prev_poc_val = poc.poc_val as u32;
+    }
+}
+
+/* derive the tiles of a picture in raster scan order of the tile grid */
+pub(crate) fn evc_tile_init(pps: &EvcPps, w_lcu: u16, h_lcu: u16) -> Vec<EvcTile> {
+    if pps.single_tile_in_pic_flag {
+        return vec![EvcTile {
+            x_lcu: 0,
+            y_lcu: 0,
+            w_lcu,
+            h_lcu,
+            f_lcu: w_lcu as u32 * h_lcu as u32,
+        }];
+    }
+
+    let num_cols = pps.num_tile_columns_minus1 as usize + 1;
+    let num_rows = pps.num_tile_rows_minus1 as usize + 1;
+    let mut col_bd = vec![0u16; num_cols + 1];
+    let mut row_bd = vec![0u16; num_rows + 1];
+
+    if pps.uniform_tile_spacing_flag {
+        for i in 0..=num_cols {
+            col_bd[i] = ((i * w_lcu as usize) / num_cols) as u16;
+        }
+        for j in 0..=num_rows {
+            row_bd[j] = ((j * h_lcu as usize) / num_rows) as u16;
+        }
+    } else {
+        /* the last column and row take the rest of the picture */
+        for i in 0..num_cols - 1 {
+            col_bd[i + 1] = EVC_CLIP3(0, w_lcu, col_bd[i] + pps.tile_column_width_minus1[i] + 1);
+        }
+        for j in 0..num_rows - 1 {
+            row_bd[j + 1] = EVC_CLIP3(0, h_lcu, row_bd[j] + pps.tile_row_height_minus1[j] + 1);
+        }
+        col_bd[num_cols] = w_lcu;
+        row_bd[num_rows] = h_lcu;
+    }
+
+    let mut tiles = Vec::with_capacity(num_cols * num_rows);
+    for j in 0..num_rows {
+        for i in 0..num_cols {
+            let w = col_bd[i + 1] - col_bd[i];
+            let h = row_bd[j + 1] - row_bd[j];
+            tiles.push(EvcTile {
+                x_lcu: col_bd[i],
+                y_lcu: row_bd[j],
+                w_lcu: w,
+                h_lcu: h,
+                f_lcu: w as u32 * h as u32,
+            });
+        }
+    }
+    tiles
+}
+
+/* get the tile index of each LCU, unless the deblocking filter runs across tiles */
+pub(crate) fn evc_tile_map_tidx(
+    pps: &EvcPps,
+    tiles: &[EvcTile],
+    w_lcu: u16,
+    h_lcu: u16,
+) -> Option<Vec<u16>> {
+    if pps.single_tile_in_pic_flag || pps.loop_filter_across_tiles_enabled_flag {
+        return None;
+    }
+
+    let mut map_tidx = vec![0; w_lcu as usize * h_lcu as usize];
+    for (tidx, tile) in tiles.iter().enumerate() {
+        for j in tile.y_lcu..tile.y_lcu + tile.h_lcu {
+            for i in tile.x_lcu..tile.x_lcu + tile.w_lcu {
+                map_tidx[j as usize * w_lcu as usize + i as usize] = tidx as u16;
+            }
+        }
+    }
+    Some(map_tidx)
+}
+
+/* get the SCU rectangle (x, y, width, height) covered by a tile */
+pub(crate) fn evc_tile_scu_rect(
+    tile: &EvcTile,
+    w_scu: u16,
+    h_scu: u16,
+) -> (usize, usize, usize, usize) {
+    let scu_in_lcu_wh = 1 << (MAX_CU_LOG2 - MIN_CU_LOG2);
+    let x_scu = tile.x_lcu as usize * scu_in_lcu_wh;
+    let y_scu = tile.y_lcu as usize * scu_in_lcu_wh;
+    let w = std::cmp::min(tile.w_lcu as usize * scu_in_lcu_wh, w_scu as usize - x_scu);
+    let h = std::cmp::min(tile.h_lcu as usize * scu_in_lcu_wh, h_scu as usize - y_scu);
+    (x_scu, y_scu, w, h)
+}
+
+/* make the SCUs outside of a tile unavailable as neighbours: they are marked
+as not coded intra CUs, since the availability of upper inter neighbours is
+derived from the intra flag only */
+pub(crate) fn evc_set_scu_outside_tile(
+    map_scu: &mut [MCU],
+    tile: &EvcTile,
+    w_scu: u16,
+    h_scu: u16,
+) {
+    let (x_scu, y_scu, w, h) = evc_tile_scu_rect(tile, w_scu, h_scu);
+    for j in 0..h_scu as usize {
+        for i in 0..w_scu as usize {
+            if j < y_scu || j >= y_scu + h || i < x_scu || i >= x_scu + w {
+                let scu = &mut map_scu[j * w_scu as usize + i];
+                scu.CLR_COD();
+                scu.SET_IF();
+            }
+        }
+    }
+}
+
 pub(crate) fn evc_set_split_mode(
     split_mode_buf: &mut LcuSplitMode,
     split_mode: SplitMode,
@@ -65,10 +198,216 @@ pub(crate) fn evc_set_split_mode(
     let pos = cup
         + (((cuh >> 1) >> MIN_CU_LOG2 as u16) * (lcu_s >> MIN_CU_LOG2 as u16)
             + ((cuw >> 1) >> MIN_CU_LOG2 as u16));
-    let shape = BlockShape::SQUARE as u8 + (CONV_LOG2(cuw as usize) - CONV_LOG2(cuh as usize));
+    let shape = (BlockShape::SQUARE as i8 + CONV_LOG2(cuw as usize) as i8
+        - CONV_LOG2(cuh as usize) as i8) as usize;
 
     if cuw >= 8 || cuh >= 8 {
-        split_mode_buf.data[cud as usize][shape as usize][pos as usize] = split_mode;
+        split_mode_buf.data[cud as usize][shape][pos as usize] = split_mode;
+    }
+}
+
+pub(crate) fn evc_set_suco_flag(
+    suco_flag_buf: &mut LcuSucoFlag,
+    suco_flag: bool,
+    cud: u16,
+    cup: u16,
+    cuw: u16,
+    cuh: u16,
+    lcu_s: u16,
+) {
+    let pos = cup
+        + (((cuh >> 1) >> MIN_CU_LOG2 as u16) * (lcu_s >> MIN_CU_LOG2 as u16)
+            + ((cuw >> 1) >> MIN_CU_LOG2 as u16));
+    let shape = (BlockShape::SQUARE as i8 + CONV_LOG2(cuw as usize) as i8
+        - CONV_LOG2(cuh as usize) as i8) as usize;
+
+    suco_flag_buf.data[cud as usize][shape][pos as usize] = suco_flag;
+}
+
+pub(crate) fn evc_get_suco_flag(
+    cud: u16,
+    cup: u16,
+    cuw: u16,
+    cuh: u16,
+    lcu_s: u16,
+    suco_flag_buf: &LcuSucoFlag,
+) -> bool {
+    let pos =
+        cup + (((cuh >> 1) >> MIN_CU_LOG2) * (lcu_s >> MIN_CU_LOG2) + ((cuw >> 1) >> MIN_CU_LOG2));
+    let shape = (BlockShape::SQUARE as i8 + CONV_LOG2(cuw as usize) as i8
+        - CONV_LOG2(cuh as usize) as i8) as usize;
+    suco_flag_buf.data[cud as usize][shape][pos as usize]
+}
+
+/* derive the split table from the block sizes signalled in the SPS */
+pub(crate) fn evc_set_split_tbl(sps: &EvcSps) -> EvcSplitTbl {
+    let mut split_tbl = EvcSplitTbl::default();
+    let (log2_max_cuwh, log2_min_cuwh) = if sps.sps_btt_flag {
+        (
+            sps.log2_ctu_size_minus5 + 5,
+            sps.log2_min_cb_size_minus2 + 2,
+        )
+    } else {
+        (MAX_CU_LOG2 as u8, MIN_CU_LOG2 as u8)
+    };
+
+    split_tbl[BLOCK_11][IDX_MAX] = log2_max_cuwh;
+    split_tbl[BLOCK_11][IDX_MIN] = log2_min_cuwh;
+    split_tbl[BLOCK_12][IDX_MAX] = log2_max_cuwh;
+    split_tbl[BLOCK_12][IDX_MIN] = split_tbl[BLOCK_11][IDX_MIN] + 1;
+    split_tbl[BLOCK_14][IDX_MAX] = log2_max_cuwh - sps.log2_diff_ctu_max_14_cb_size;
+    split_tbl[BLOCK_14][IDX_MIN] = split_tbl[BLOCK_12][IDX_MIN] + 1;
+    split_tbl[BLOCK_TT][IDX_MAX] = log2_max_cuwh - sps.log2_diff_ctu_max_tt_cb_size;
+    split_tbl[BLOCK_TT][IDX_MIN] =
+        split_tbl[BLOCK_11][IDX_MIN] + sps.log2_diff_min_cb_min_tt_cb_size_minus2 + 2;
+
+    split_tbl
+}
+
+#[inline]
+fn ALLOW_SPLIT_RATIO(split_tbl: &EvcSplitTbl, long_side: u8, tbl_idx: usize) -> bool {
+    split_tbl[tbl_idx][IDX_MAX] >= long_side && split_tbl[tbl_idx][IDX_MIN] <= long_side
+}
+
+/* split modes allowed for a block. a block crossing the picture boundary
+is split by the only binary split allowed, which is not signalled */
+pub(crate) fn evc_check_split_mode(
+    split_allow: &mut [bool],
+    log2_cuw: u8,
+    log2_cuh: u8,
+    boundary: bool,
+    boundary_b: bool,
+    boundary_r: bool,
+    log2_max_cuwh: u8,
+    x: u16,
+    y: u16,
+    im_w: u16,
+    im_h: u16,
+    sps_btt_flag: bool,
+    split_tbl: &EvcSplitTbl,
+    mode_cons: ModeCons,
+) {
+    for v in split_allow[..MAX_SPLIT_NUM].iter_mut() {
+        *v = false;
+    }
+
+    if !sps_btt_flag {
+        split_allow[SplitMode::SPLIT_QUAD as usize] = true;
+        return;
+    }
+
+    let cu_max = 1u16 << (log2_max_cuwh - 1);
+    let from_boundary_b = y >= im_h - im_h % cu_max && x < im_w - im_w % cu_max;
+    let long_side = std::cmp::max(log2_cuw, log2_cuh);
+
+    if log2_cuw == log2_cuh {
+        let allow_bt = ALLOW_SPLIT_RATIO(split_tbl, long_side, BLOCK_11);
+        let allow_tt = ALLOW_SPLIT_RATIO(split_tbl, long_side, BLOCK_TT)
+            && ALLOW_SPLIT_RATIO(split_tbl, long_side, BLOCK_14);
+        split_allow[SplitMode::SPLIT_BI_VER as usize] = allow_bt;
+        split_allow[SplitMode::SPLIT_BI_HOR as usize] = allow_bt;
+        split_allow[SplitMode::SPLIT_TRI_VER as usize] = allow_tt;
+        split_allow[SplitMode::SPLIT_TRI_HOR as usize] = allow_tt;
+    } else if log2_cuw > log2_cuh {
+        if log2_cuw - log2_cuh == 1 {
+            split_allow[SplitMode::SPLIT_BI_HOR as usize] =
+                ALLOW_SPLIT_RATIO(split_tbl, long_side, BLOCK_14);
+        }
+
+        let ratio = (log2_cuw as i8 - 1 - log2_cuh as i8).abs() as usize;
+        split_allow[SplitMode::SPLIT_BI_VER as usize] = if ratio <= BLOCK_14 {
+            ALLOW_SPLIT_RATIO(split_tbl, std::cmp::max(log2_cuw - 1, log2_cuh), ratio)
+        } else {
+            /* very narrow blocks are only made at the bottom of the picture */
+            from_boundary_b && (ratio == 3 || ratio == 4)
+        };
+
+        split_allow[SplitMode::SPLIT_TRI_VER as usize] =
+            ALLOW_SPLIT_RATIO(split_tbl, long_side, BLOCK_TT);
+    } else {
+        let ratio = (log2_cuw as i8 - (log2_cuh as i8 - 1)).abs() as usize;
+        if ratio <= BLOCK_14 {
+            split_allow[SplitMode::SPLIT_BI_HOR as usize] =
+                ALLOW_SPLIT_RATIO(split_tbl, std::cmp::max(log2_cuw, log2_cuh - 1), ratio);
+        }
+
+        if log2_cuh - log2_cuw == 1 {
+            split_allow[SplitMode::SPLIT_BI_VER as usize] =
+                ALLOW_SPLIT_RATIO(split_tbl, long_side, BLOCK_14);
+        }
+
+        split_allow[SplitMode::SPLIT_TRI_HOR as usize] =
+            ALLOW_SPLIT_RATIO(split_tbl, long_side, BLOCK_TT);
+    }
+
+    if boundary {
+        split_allow[SplitMode::NO_SPLIT as usize] = false;
+        split_allow[SplitMode::SPLIT_TRI_VER as usize] = false;
+        split_allow[SplitMode::SPLIT_TRI_HOR as usize] = false;
+        if !boundary_r {
+            split_allow[SplitMode::SPLIT_BI_VER as usize] =
+                !split_allow[SplitMode::SPLIT_BI_HOR as usize];
+        } else {
+            split_allow[SplitMode::SPLIT_BI_HOR as usize] =
+                !split_allow[SplitMode::SPLIT_BI_VER as usize];
+        }
+    }
+
+    if mode_cons == ModeCons::eOnlyInter {
+        /* 4x4 parts could not be inter predicted */
+        let cuw = 1u16 << log2_cuw;
+        let cuh = 1u16 << log2_cuh;
+        for split_mode in [
+            SplitMode::SPLIT_BI_VER,
+            SplitMode::SPLIT_BI_HOR,
+            SplitMode::SPLIT_TRI_VER,
+            SplitMode::SPLIT_TRI_HOR,
+        ] {
+            if evc_get_mode_cons_by_split(split_mode, cuw, cuh) == ModeCons::eOnlyIntra {
+                split_allow[split_mode as usize] = false;
+            }
+        }
+    }
+}
+
+/* split mode of a block crossing the picture boundary, which is not
+signalled when binary and ternary splits are enabled */
+pub(crate) fn evc_boundary_split_mode(
+    x: u16,
+    y: u16,
+    log2_cuw: u8,
+    log2_cuh: u8,
+    log2_max_cuwh: u8,
+    im_w: u16,
+    im_h: u16,
+    split_tbl: &EvcSplitTbl,
+) -> SplitMode {
+    let cuw = 1u16 << log2_cuw;
+    let cuh = 1u16 << log2_cuh;
+    let mut split_allow = [false; MAX_SPLIT_NUM];
+    evc_check_split_mode(
+        &mut split_allow,
+        log2_cuw,
+        log2_cuh,
+        true,
+        x + cuw <= im_w && y + cuh > im_h,
+        x + cuw > im_w && y + cuh <= im_h,
+        log2_max_cuwh,
+        x,
+        y,
+        im_w,
+        im_h,
+        true,
+        split_tbl,
+        ModeCons::eAll,
+    );
+
+    if split_allow[SplitMode::SPLIT_BI_VER as usize] {
+        SplitMode::SPLIT_BI_VER
+    } else if split_allow[SplitMode::SPLIT_BI_HOR as usize] {
+        SplitMode::SPLIT_BI_HOR
+    } else {
+        SplitMode::NO_SPLIT
     }
 }
 
@@ -111,7 +450,7 @@ pub(crate) fn evc_split_get_part_structure(
         split_struct.height[0] = cuh;
         split_struct.log_cuw[0] = log_cuw;
         split_struct.log_cuh[0] = log_cuh;
-    } else {
+    } else if split_mode == SplitMode::SPLIT_QUAD {
         split_struct.width[0] = cuw >> 1;
         split_struct.height[0] = cuh >> 1;
         split_struct.log_cuw[0] = log_cuw - 1;
@@ -137,11 +476,130 @@ pub(crate) fn evc_split_get_part_structure(
         split_struct.cud[1] = cud + 2;
         split_struct.cud[2] = cud + 2;
         split_struct.cud[3] = cud + 2;
+    } else {
+        if split_mode.is_vertical() {
+            for i in 0..split_struct.part_count {
+                split_struct.width[i] = split_mode.part_size(i, cuw as usize) as u16;
+                split_struct.log_cuw[i] = split_mode.part_size_idx(i, log_cuw as usize) as u8;
+                split_struct.height[i] = cuh;
+                split_struct.log_cuh[i] = log_cuh;
+                if i > 0 {
+                    split_struct.x_pos[i] = split_struct.x_pos[i - 1] + split_struct.width[i - 1];
+                    split_struct.y_pos[i] = split_struct.y_pos[i - 1];
+                    split_struct.cup[i] =
+                        split_struct.cup[i - 1] + (split_struct.width[i - 1] >> MIN_CU_LOG2 as u16);
+                }
+            }
+        } else {
+            for i in 0..split_struct.part_count {
+                split_struct.width[i] = cuw;
+                split_struct.log_cuw[i] = log_cuw;
+                split_struct.height[i] = split_mode.part_size(i, cuh as usize) as u16;
+                split_struct.log_cuh[i] = split_mode.part_size_idx(i, log_cuh as usize) as u8;
+                if i > 0 {
+                    split_struct.x_pos[i] = split_struct.x_pos[i - 1];
+                    split_struct.y_pos[i] = split_struct.y_pos[i - 1] + split_struct.height[i - 1];
+                    split_struct.cup[i] = split_struct.cup[i - 1]
+                        + ((split_struct.height[i - 1] >> MIN_CU_LOG2 as u16)
+                            << log2_culine as u16);
+                }
+            }
+        }
+
+        if split_mode.is_bt() {
+            split_struct.cud[0] = cud + 1;
+            split_struct.cud[1] = cud + 1;
+        } else {
+            split_struct.cud[0] = cud + 2;
+            split_struct.cud[1] = cud + 1;
+            split_struct.cud[2] = cud + 2;
+        }
     }
 
     split_struct
 }
 
+/* coding order of the parts of a split: right to left (and bottom-right
+before bottom-left for a quad split) when the suco flag is set; horizontal
+splits always keep the top to bottom order */
+pub(crate) fn evc_split_get_suco_order(
+    suco_flag: bool,
+    split_mode: SplitMode,
+) -> [usize; SPLIT_MAX_PART_COUNT] {
+    if !suco_flag || split_mode == SplitMode::SPLIT_BI_HOR || split_mode == SplitMode::SPLIT_TRI_HOR
+    {
+        [0, 1, 2, 3]
+    } else if split_mode == SplitMode::SPLIT_QUAD {
+        [1, 0, 3, 2]
+    } else {
+        let mut suco_order = [0; SPLIT_MAX_PART_COUNT];
+        let part_count = split_mode.part_count();
+        for (i, order) in suco_order[..part_count].iter_mut().enumerate() {
+            *order = part_count - 1 - i;
+        }
+        suco_order
+    }
+}
+
+/* whether split_unit_coding_order_flag is present for a split block */
+pub(crate) fn evc_check_suco_cond(
+    cuw: u16,
+    cuh: u16,
+    split_mode: SplitMode,
+    boundary: bool,
+    log2_max_cuwh: u8,
+    suco_max_depth: u8,
+    suco_depth: u8,
+) -> bool {
+    let suco_log2_maxsize = std::cmp::min(log2_max_cuwh as i8 - suco_max_depth as i8, 6);
+    let suco_log2_minsize = std::cmp::max(suco_log2_maxsize - suco_depth as i8, 4);
+    if std::cmp::min(cuw, cuh) < (1 << suco_log2_minsize)
+        || std::cmp::max(cuw, cuh) > (1 << suco_log2_maxsize)
+    {
+        return false;
+    }
+
+    if boundary
+        || split_mode == SplitMode::NO_SPLIT
+        || split_mode == SplitMode::SPLIT_BI_HOR
+        || split_mode == SplitMode::SPLIT_TRI_HOR
+    {
+        return false;
+    }
+
+    split_mode == SplitMode::SPLIT_QUAD || cuw > cuh
+}
+
+/* whether the chroma blocks of a binary or ternary split keep at least
+16 chroma samples (64 luma samples) */
+pub(crate) fn evc_is_chroma_split_allowed(w: u16, h: u16, split_mode: SplitMode) -> bool {
+    match split_mode {
+        SplitMode::SPLIT_BI_VER => (w >> 1) * h >= 64,
+        SplitMode::SPLIT_BI_HOR => w * (h >> 1) >= 64,
+        SplitMode::SPLIT_TRI_VER => (w >> 2) * h >= 64,
+        SplitMode::SPLIT_TRI_HOR => w * (h >> 2) >= 64,
+        _ => false,
+    }
+}
+
+/* prediction constraint implied by the smallest part of a split: 4x4 blocks
+can only be intra predicted */
+pub(crate) fn evc_get_mode_cons_by_split(split_mode: SplitMode, cuw: u16, cuh: u16) -> ModeCons {
+    let (w, h) = match split_mode {
+        SplitMode::SPLIT_BI_VER => (cuw >> 1, cuh),
+        SplitMode::SPLIT_BI_HOR => (cuw, cuh >> 1),
+        SplitMode::SPLIT_TRI_VER => (cuw >> 2, cuh),
+        SplitMode::SPLIT_TRI_HOR => (cuw, cuh >> 2),
+        _ => (cuw, cuh),
+    };
+
+    if w == 4 && h == 4 {
+        ModeCons::eOnlyIntra
+    } else {
+        ModeCons::eAll
+    }
+}
+
 pub(crate) fn evc_check_nev_avail(
     x_scu: u16,
     y_scu: u16,
@@ -160,7 +618,7 @@ pub(crate) fn evc_check_nev_avail(
         avail_lr += 1;
     }
 
-    if y_scu > 0 && x_scu + scuw < w_scu && map_scu[(scup + scuw) as usize].GET_COD() != 0 {
+    if x_scu + scuw < w_scu && map_scu[(scup + scuw) as usize].GET_COD() != 0 {
         avail_lr += 2;
     }
 
@@ -287,18 +745,23 @@ pub(crate) fn evc_get_avail_intra(
 }
 
 #[inline]
-pub(crate) fn check_bi_applicability(slice_type: SliceType) -> bool {
-    if slice_type == SliceType::EVC_ST_B {
+pub(crate) fn check_bi_applicability(
+    slice_type: SliceType,
+    cuw: usize,
+    cuh: usize,
+    admvp: bool,
+) -> bool {
+    if slice_type == SliceType::EVC_ST_B && (!admvp || cuw + cuh > 12) {
         true
     } else {
         false
     }
 }
 
-pub(crate) fn scan_tbl(size: i16) -> Box<[u16]> {
+pub(crate) fn scan_tbl(size_x: i16, size_y: i16) -> Box<[u16]> {
     let mut pos = 0;
-    let num_line = size + size - 1;
-    let mut scan = vec![0; (size * size) as usize].into_boxed_slice();
+    let num_line = size_x + size_y - 1;
+    let mut scan = vec![0; (size_x * size_y) as usize].into_boxed_slice();
     /* starting point */
     scan[pos] = 0;
     pos += 1;
@@ -307,11 +770,11 @@ pub(crate) fn scan_tbl(size: i16) -> Box<[u16]> {
     for l in 1..num_line {
         if l % 2 != 0 {
             /* decreasing loop */
-            let mut x = std::cmp::min(l, size - 1);
-            let mut y = std::cmp::max(0, l - (size - 1));
+            let mut x = std::cmp::min(l, size_x - 1);
+            let mut y = std::cmp::max(0, l - (size_x - 1));
 
-            while x >= 0 && y < size {
-                scan[pos] = (y * size + x) as u16;
+            while x >= 0 && y < size_y {
+                scan[pos] = (y * size_x + x) as u16;
                 pos += 1;
                 x -= 1;
                 y += 1;
@@ -319,10 +782,10 @@ pub(crate) fn scan_tbl(size: i16) -> Box<[u16]> {
         } else
         /* increasing loop */
         {
-            let mut y = std::cmp::min(l, size - 1);
-            let mut x = std::cmp::max(0, l - (size - 1));
-            while y >= 0 && x < size {
-                scan[pos] = (y * size + x) as u16;
+            let mut y = std::cmp::min(l, size_y - 1);
+            let mut x = std::cmp::max(0, l - (size_y - 1));
+            while y >= 0 && x < size_x {
+                scan[pos] = (y * size_x + x) as u16;
                 pos += 1;
                 x += 1;
                 y -= 1;
@@ -333,16 +796,25 @@ pub(crate) fn scan_tbl(size: i16) -> Box<[u16]> {
     scan
 }
 
-pub(crate) fn evc_init_multi_tbl(c: usize) -> Box<[i16]> {
+pub(crate) fn evc_init_multi_tbl(c: usize, tr_type: u8) -> Box<[i16]> {
     let mut tm = vec![0i16; c * c].into_boxed_slice();
     let s = (c as f64).sqrt() * 64.0;
 
     for k in 0..c {
         for n in 0..c {
-            /* DCT-VIII */
-            let a = std::f64::consts::PI * (k as f64 + 0.5) * (n as f64 + 0.5) / (c as f64 + 0.5);
-            let b = 2.0 / (c as f64 + 0.5);
-            let v = a.cos() * b.sqrt();
+            let v = if tr_type == DST7 {
+                /* DST-VII */
+                let a =
+                    std::f64::consts::PI * (k as f64 + 0.5) * (n as f64 + 1.0) / (c as f64 + 0.5);
+                let b = 2.0 / (c as f64 + 0.5);
+                a.sin() * b.sqrt()
+            } else {
+                /* DCT-VIII */
+                let a =
+                    std::f64::consts::PI * (k as f64 + 0.5) * (n as f64 + 0.5) / (c as f64 + 0.5);
+                let b = 2.0 / (c as f64 + 0.5);
+                a.cos() * b.sqrt()
+            };
             tm[k * c + n] = (s * v + if v > 0.0 { 0.5 } else { -0.5 }) as i16;
         }
     }
@@ -350,21 +822,226 @@ pub(crate) fn evc_init_multi_tbl(c: usize) -> Box<[i16]> {
     tm
 }
 
-pub(crate) fn evc_init_multi_inv_tbl(c: usize) -> Box<[i16]> {
-    let mut tm = vec![0i16; c * c].into_boxed_slice();
-    let s = (c as f64).sqrt() * 64.0;
+/* transform kernel of one direction of a transform block */
+pub(crate) fn evc_get_tr_kernel(tr_type: u8, log2_size: usize) -> &'static [i16] {
+    &evc_tbl_tr[tr_type as usize][log2_size - 1]
+}
 
-    for k in 0..c {
-        for n in 0..c {
-            /* DCT-VIII */
-            let a = std::f64::consts::PI * (k as f64 + 0.5) * (n as f64 + 0.5) / (c as f64 + 0.5);
-            let b = 2.0 / (c as f64 + 0.5);
-            let v = a.cos() * b.sqrt();
-            tm[n * c + k] = (s * v + if v > 0.0 { 0.5 } else { -0.5 }) as i16;
+/* horizontal and vertical transform types of the luma transform block, the
+sub-block transform of a CU larger than 32 keeps the DCT-II */
+pub(crate) fn evc_get_tr_type(
+    ats_intra_cu: bool,
+    ats_mode: u8,
+    ats_inter_info: u8,
+    log2_cuw: u8,
+    log2_cuh: u8,
+) -> (u8, u8) {
+    if ats_intra_cu {
+        (ats_mode >> 1, ats_mode & 1)
+    } else if ats_inter_info != 0
+        && log2_cuw as usize <= MAX_TR_LOG2_ATS
+        && log2_cuh as usize <= MAX_TR_LOG2_ATS
+    {
+        let idx = ats_inter_info & 0xf;
+        let pos = ats_inter_info >> 4;
+        let is_hor = idx == ATS_INTER_HALF_HOR || idx == ATS_INTER_QUAD_HOR;
+        if pos != 0 {
+            (DST7, DST7)
+        } else if is_hor {
+            (DST7, DCT8)
+        } else {
+            (DCT8, DST7)
         }
+    } else {
+        (DCT2, DCT2)
+    }
+}
+
+/* whether a sub-block mode of the inter adaptive transform selection fits the CU */
+pub(crate) fn evc_check_ats_inter_idx(idx: u8, log2_cuw: u8, log2_cuh: u8) -> bool {
+    if log2_cuw as usize > MAX_TR_LOG2 || log2_cuh as usize > MAX_TR_LOG2 {
+        return false;
     }
 
-    tm
+    match idx {
+        ATS_INTER_HALF_VER => log2_cuw >= 3,
+        ATS_INTER_HALF_HOR => log2_cuh >= 3,
+        ATS_INTER_QUAD_VER => log2_cuw >= 4,
+        ATS_INTER_QUAD_HOR => log2_cuh >= 4,
+        _ => false,
+    }
+}
+
+/* position and size of the residual sub-block of the inter adaptive transform
+selection, in the sample units of the given component */
+pub(crate) fn evc_get_ats_inter_trs(
+    ats_inter_info: u8,
+    log2_cuw: u8,
+    log2_cuh: u8,
+) -> (usize, usize, u8, u8) {
+    let idx = ats_inter_info & 0xf;
+    let pos = (ats_inter_info >> 4) as usize;
+    let cuw = 1usize << log2_cuw;
+    let cuh = 1usize << log2_cuh;
+
+    match idx {
+        ATS_INTER_HALF_VER => (pos * (cuw >> 1), 0, log2_cuw - 1, log2_cuh),
+        ATS_INTER_HALF_HOR => (0, pos * (cuh >> 1), log2_cuw, log2_cuh - 1),
+        ATS_INTER_QUAD_VER => (pos * (cuw - (cuw >> 2)), 0, log2_cuw - 2, log2_cuh),
+        ATS_INTER_QUAD_HOR => (0, pos * (cuh - (cuh >> 2)), log2_cuw, log2_cuh - 2),
+        _ => (0, 0, log2_cuw, log2_cuh),
+    }
+}
+
+/* a luma residual coded with the inter adaptive transform selection only marks
+the SCUs of its sub-block as coded */
+pub(crate) fn evc_set_cu_cbf_flags(
+    ats_inter_info: u8,
+    log2_cuw: u8,
+    log2_cuh: u8,
+    map_scu: &mut [MCU],
+    w_scu: usize,
+) {
+    let (x, y, log2_tw, log2_th) = evc_get_ats_inter_trs(ats_inter_info, log2_cuw, log2_cuh);
+    let (x0, y0) = (x >> MIN_CU_LOG2, y >> MIN_CU_LOG2);
+    let (x1, y1) = (
+        x0 + ((1 << log2_tw) >> MIN_CU_LOG2),
+        y0 + ((1 << log2_th) >> MIN_CU_LOG2),
+    );
+
+    for j in 0..(1 << log2_cuh) >> MIN_CU_LOG2 {
+        for i in 0..(1 << log2_cuw) >> MIN_CU_LOG2 {
+            if i < x0 || i >= x1 || j < y0 || j >= y1 {
+                map_scu[j * w_scu + i].CLR_CBFL();
+            }
+        }
+    }
+}
+
+/* absolute levels at the template positions (x + 1, y), (x + 2, y), (x + 1, y + 1),
+(x, y + 1) and (x, y + 2) of the advanced coefficient coding, zero outside the
+block; they all follow (x, y) in the scan and are therefore coded before it */
+fn evc_get_adcc_template(coef: &[i16], blk_pos: usize, log2_w: u8, log2_h: u8) -> [u32; 5] {
+    let w = 1usize << log2_w;
+    let h = 1usize << log2_h;
+    let x = blk_pos & (w - 1);
+    let y = blk_pos >> log2_w;
+    let level = |pos: usize| coef[blk_pos + pos].unsigned_abs() as u32;
+    let mut tmpl = [0; 5];
+
+    if x < w - 1 {
+        tmpl[0] = level(1);
+        if x < w - 2 {
+            tmpl[1] = level(2);
+        }
+        if y < h - 1 {
+            tmpl[2] = level(w + 1);
+        }
+    }
+    if y < h - 1 {
+        tmpl[3] = level(w);
+        if y < h - 2 {
+            tmpl[4] = level(w << 1);
+        }
+    }
+
+    tmpl
+}
+
+/* sig_coeff_flag context set of the block */
+pub(crate) fn evc_get_sig_coeff_set(log2_w: u8, log2_h: u8, ch_type: usize) -> usize {
+    if ch_type != Y_C {
+        return NUM_SIG_COEFF_SET - 1;
+    }
+
+    let log2_min = std::cmp::min(log2_w, log2_h);
+    if log2_min <= 2 {
+        0
+    } else {
+        std::cmp::min(log2_min as usize, 4) - 2
+    }
+}
+
+pub(crate) fn evc_get_ctx_sig_coeff_inc(
+    coef: &[i16],
+    blk_pos: usize,
+    log2_w: u8,
+    log2_h: u8,
+    ch_type: usize,
+) -> usize {
+    let num_sig = evc_get_adcc_template(coef, blk_pos, log2_w, log2_h)
+        .iter()
+        .filter(|&&level| level != 0)
+        .count();
+    let ctx = std::cmp::min(num_sig, 4) + 1;
+    let diag = (blk_pos & ((1 << log2_w) - 1)) + (blk_pos >> log2_w);
+
+    if diag < 2 {
+        std::cmp::min(ctx, 2)
+    } else if ch_type == Y_C && diag >= 5 {
+        ctx + 7
+    } else {
+        ctx + 2
+    }
+}
+
+/* context of coeff_abs_level_greaterA_flag (level 1) and
+coeff_abs_level_greaterB_flag (level 2), which share their contexts */
+pub(crate) fn evc_get_ctx_gtx_inc(
+    coef: &[i16],
+    blk_pos: usize,
+    log2_w: u8,
+    log2_h: u8,
+    ch_type: usize,
+    level: u32,
+) -> usize {
+    let num_gtx = evc_get_adcc_template(coef, blk_pos, log2_w, log2_h)
+        .iter()
+        .filter(|&&abs| abs > level)
+        .count();
+    let ctx = std::cmp::min(num_gtx, 3) + 1;
+
+    if ch_type != Y_C {
+        return ctx;
+    }
+
+    let diag = (blk_pos & ((1 << log2_w) - 1)) + (blk_pos >> log2_w);
+    if diag <= 2 {
+        ctx
+    } else if diag <= 9 {
+        ctx + 4
+    } else {
+        ctx + 8
+    }
+}
+
+/* Rice parameter of the level remainder above base */
+pub(crate) fn evc_get_rice_para(
+    coef: &[i16],
+    blk_pos: usize,
+    log2_w: u8,
+    log2_h: u8,
+    base: u32,
+) -> u32 {
+    let sum_abs: u32 = evc_get_adcc_template(coef, blk_pos, log2_w, log2_h)
+        .iter()
+        .sum();
+    let idx = EVC_CLIP3(0, 31, sum_abs as i32 - 5 * base as i32);
+
+    evc_tbl_go_rice_par[idx as usize] as u32
+}
+
+/* context offset and shift of the last significant position prefix bins */
+pub(crate) fn evc_get_ctx_last_pos_xy(log2_size: u8, ch_type: usize) -> (usize, usize) {
+    let log2_size = log2_size as usize;
+    let m = log2_size.saturating_sub(2);
+    if ch_type != Y_C {
+        (NUM_CTX_LAST_SIG_COEFF_LUMA, std::cmp::min(m, 2))
+    } else if log2_size > 5 {
+        (3 * m + ((m + 1) >> 2) + 2, 2)
+    } else {
+        (3 * m + ((m + 1) >> 2), (m + 3) >> 2)
+    }
 }
 
 pub(crate) fn evc_get_motion(
@@ -411,7 +1088,7 @@ pub(crate) fn evc_get_motion(
     refi[3] = 0;
 
     if let Some(map_mv) = &refp[0][lidx].map_mv {
-        let mv = map_mv.borrow();
+        let mv = map_mv.read().unwrap();
         mvp[3][MV_X] = mv[scup][0][MV_X];
         mvp[3][MV_Y] = mv[scup][0][MV_Y];
     }
@@ -426,7 +1103,7 @@ pub(crate) fn evc_get_mv_dir(
     let mut mvc = [0i16; MV_D];
 
     if let Some(map_mv) = &refp[REFP_1].map_mv {
-        let mv = map_mv.borrow();
+        let mv = map_mv.read().unwrap();
         mvc[MV_X] = mv[scup][0][MV_X];
         mvc[MV_Y] = mv[scup][0][MV_Y];
     }
@@ -448,48 +1125,1565 @@ pub(crate) fn evc_get_mv_dir(
     }
 }
 
-pub(crate) fn evc_derived_chroma_qp_mapping_tables(
-    structChromaQP: &EvcChromaTable,
-) -> Vec<Vec<i8>> {
-    let MAX_QP = MAX_QP_TABLE_SIZE as i8 - 1;
-    let mut qpInVal = [0i8; MAX_QP_TABLE_SIZE_EXT];
-    let mut qpOutVal = [0i8; MAX_QP_TABLE_SIZE_EXT];
-    let mut p_evc_tbl_qp_chroma_dynamic = Vec::with_capacity(2);
-    p_evc_tbl_qp_chroma_dynamic.push(vec![0; MAX_QP_TABLE_SIZE_EXT]);
-    p_evc_tbl_qp_chroma_dynamic.push(vec![0; MAX_QP_TABLE_SIZE_EXT]);
-
-    let startQp = if structChromaQP.global_offset_flag {
-        16
+/* scale a motion vector by a POC distance ratio in 1/32 units */
+fn scaling_mv(ratio: i32, mv: i16) -> i16 {
+    let tmp_mv = mv as i32 * ratio;
+    if tmp_mv == 0 {
+        0
+    } else if tmp_mv > 0 {
+        std::cmp::min(32767, (tmp_mv + 16) >> 5) as i16
     } else {
-        -EVC_TBL_CHROMA_QP_OFFSET
-    };
+        std::cmp::max(-32768, -((16 - tmp_mv) >> 5)) as i16
+    }
+}
 
-    for i in 0..if structChromaQP.same_qp_table_for_chroma {
-        1
-    } else {
-        2
-    } {
-        qpInVal[0] = startQp + structChromaQP.delta_qp_in_val_minus1[i][0];
-        qpOutVal[0] = startQp
-            + structChromaQP.delta_qp_in_val_minus1[i][0]
-            + structChromaQP.delta_qp_out_val[i][0];
-        for j in 1..=structChromaQP.num_points_in_qp_table_minus1[i] {
-            qpInVal[j] = qpInVal[j - 1] + structChromaQP.delta_qp_in_val_minus1[i][j] + 1;
-            qpOutVal[j] = qpOutVal[j - 1]
-                + (structChromaQP.delta_qp_in_val_minus1[i][j]
-                    + 1
-                    + structChromaQP.delta_qp_out_val[i][j]);
-        }
+/* keep a collocated motion vector within the padding of the picture */
+pub(crate) fn evc_clip_mv_pic(
+    x: i32,
+    y: i32,
+    maxX: i32,
+    maxY: i32,
+    mvp: &mut [[i16; MV_D]; REFP_NUM],
+) {
+    let minXY = -(MV_CLIP_PAD_SIZE as i32);
+    let pad = MV_CLIP_PAD_SIZE as i32;
 
-        for j in 0..=structChromaQP.num_points_in_qp_table_minus1[i] {
-            assert!(qpInVal[j] >= -EVC_TBL_CHROMA_QP_OFFSET && qpInVal[j] <= MAX_QP);
-            assert!(qpOutVal[j] >= -EVC_TBL_CHROMA_QP_OFFSET && qpOutVal[j] <= MAX_QP);
+    for lidx in 0..REFP_NUM {
+        if x + (mvp[lidx][MV_X] as i32) < minXY {
+            mvp[lidx][MV_X] = (pad - x) as i16;
+        }
+        if y + (mvp[lidx][MV_Y] as i32) < minXY {
+            mvp[lidx][MV_Y] = (pad - y) as i16;
         }
+        if x + mvp[lidx][MV_X] as i32 > maxX {
+            mvp[lidx][MV_X] = (maxX - x) as i16;
+        }
+        if y + mvp[lidx][MV_Y] as i32 > maxY {
+            mvp[lidx][MV_Y] = (maxY - y) as i16;
+        }
+    }
+}
 
-        p_evc_tbl_qp_chroma_dynamic[i][(EVC_TBL_CHROMA_QP_OFFSET + qpInVal[0]) as usize] =
-            qpOutVal[0];
-        let mut k = qpInVal[0] - 1;
-        while k >= -EVC_TBL_CHROMA_QP_OFFSET {
+/* get the scaled motion of the collocated block c_scu, returning
+bit 0 set when the list 0 motion is available and bit 1 for list 1 */
+pub(crate) fn evc_get_mv_collocated(
+    refp: &Vec<Vec<EvcRefP>>,
+    poc: i32,
+    c_scu: usize,
+    scup: usize,
+    w_scu: usize,
+    h_scu: usize,
+    mvp: &mut [[i16; MV_D]; REFP_NUM],
+    slice_type: SliceType,
+    sh: &EvcSh,
+) -> u8 {
+    let mut avail_idx = 0;
+    let dpoc = [
+        poc - refp[0][REFP_0].poc as i32,
+        poc - refp[0][REFP_1].poc as i32,
+    ];
+
+    *mvp = [[0; MV_D]; REFP_NUM];
+
+    let col_pic = if sh.temporal_mvp_asigned_flag {
+        &refp[sh.collocated_from_ref_idx as usize][sh.collocated_from_list_idx as usize]
+    } else if slice_type == SliceType::EVC_ST_B {
+        &refp[0][REFP_1]
+    } else {
+        &refp[0][REFP_0]
+    };
+
+    if let (Some(map_mv), Some(map_refi)) = (&col_pic.map_mv, &col_pic.map_refi) {
+        let map_mv = map_mv.read().unwrap();
+        let map_refi = map_refi.read().unwrap();
+
+        if sh.temporal_mvp_asigned_flag {
+            let lidx = sh.collocated_mvp_source_list_idx as usize;
+            let ver_refi = map_refi[c_scu][lidx];
+            if REFI_IS_VALID(ver_refi) {
+                let dpoc_co = col_pic.poc as i32 - col_pic.list_poc[ver_refi as usize] as i32;
+                if dpoc_co != 0 {
+                    for l in 0..REFP_NUM {
+                        let ratio = (dpoc[l] << 5) / dpoc_co;
+                        mvp[l][MV_X] = scaling_mv(ratio, map_mv[c_scu][lidx][MV_X]);
+                        mvp[l][MV_Y] = scaling_mv(ratio, map_mv[c_scu][lidx][MV_Y]);
+                    }
+                    avail_idx = 3;
+                }
+            }
+        } else {
+            for lidx in 0..REFP_NUM {
+                let ver_refi = map_refi[c_scu][lidx];
+                if REFI_IS_VALID(ver_refi) {
+                    let dpoc_co = col_pic.poc as i32 - col_pic.list_poc[ver_refi as usize] as i32;
+                    if dpoc_co != 0 {
+                        let ratio = (dpoc[lidx] << 5) / dpoc_co;
+                        mvp[lidx][MV_X] = scaling_mv(ratio, map_mv[c_scu][lidx][MV_X]);
+                        mvp[lidx][MV_Y] = scaling_mv(ratio, map_mv[c_scu][lidx][MV_Y]);
+                        avail_idx |= 1 << lidx;
+                    }
+                }
+            }
+        }
+    }
+
+    evc_clip_mv_pic(
+        ((scup % w_scu) << MIN_CU_LOG2) as i32,
+        ((scup / w_scu) << MIN_CU_LOG2) as i32,
+        ((w_scu << MIN_CU_LOG2) + MV_CLIP_PAD_SIZE - 1) as i32,
+        ((h_scu << MIN_CU_LOG2) + MV_CLIP_PAD_SIZE - 1) as i32,
+        mvp,
+    );
+
+    avail_idx
+}
+
+/* get the spatial neighbours used for merge and AMVP candidates and
+whether they hold inter motion */
+pub(crate) fn evc_check_motion_availability(
+    scup: usize,
+    cuw: usize,
+    cuh: usize,
+    w_scu: usize,
+    h_scu: usize,
+    neb_addr: &mut [usize; MAX_NUM_POSSIBLE_SCAND],
+    valid_flag: &mut [bool; MAX_NUM_POSSIBLE_SCAND],
+    map_scu: &[MCU],
+    avail_lr: u16,
+) {
+    let x_scu = scup % w_scu;
+    let y_scu = scup / w_scu;
+    let scuw = cuw >> MIN_CU_LOG2;
+    let scuh = cuh >> MIN_CU_LOG2;
+    let mut cands = [None; MAX_NUM_POSSIBLE_SCAND];
+
+    if avail_lr == LR_01 {
+        if x_scu + scuw < w_scu {
+            cands[0] = Some(scup + (scuh - 1) * w_scu + scuw); /* right */
+        }
+        if y_scu > 0 {
+            cands[1] = Some(scup - w_scu); /* up */
+        }
+        if y_scu > 0 && x_scu > 0 {
+            cands[2] = Some(scup - w_scu - 1); /* up-left */
+        }
+        if y_scu + scuh < h_scu && x_scu + scuw < w_scu {
+            cands[3] = Some(scup + scuh * w_scu + scuw); /* low-right */
+        }
+        if y_scu > 0 && x_scu + scuw < w_scu {
+            cands[4] = Some(scup - w_scu + scuw); /* up-right */
+        }
+    } else if avail_lr == LR_11 {
+        if x_scu > 0 {
+            cands[0] = Some(scup + (scuh - 1) * w_scu - 1); /* left */
+        }
+        if x_scu + scuw < w_scu {
+            cands[1] = Some(scup + (scuh - 1) * w_scu + scuw); /* right */
+        }
+        if y_scu > 0 {
+            cands[2] = Some(scup - w_scu); /* up */
+        }
+        if y_scu > 0 && x_scu + scuw < w_scu {
+            cands[3] = Some(scup - w_scu + scuw); /* up-right */
+        }
+        if y_scu > 0 && x_scu > 0 {
+            cands[4] = Some(scup - w_scu - 1); /* up-left */
+        }
+    } else {
+        if x_scu > 0 {
+            cands[0] = Some(scup + (scuh - 1) * w_scu - 1); /* left */
+        }
+        if y_scu > 0 {
+            cands[1] = Some(scup - w_scu + scuw - 1); /* up */
+        }
+        if y_scu > 0 && x_scu + scuw < w_scu {
+            cands[2] = Some(scup - w_scu + scuw); /* up-right */
+        }
+        if y_scu + scuh < h_scu && x_scu > 0 {
+            cands[3] = Some(scup + scuh * w_scu - 1); /* low-left */
+        }
+        if y_scu > 0 && x_scu > 0 {
+            cands[4] = Some(scup - w_scu - 1); /* up-left */
+        }
+    }
+
+    for k in 0..MAX_NUM_POSSIBLE_SCAND {
+        neb_addr[k] = 0;
+        valid_flag[k] = false;
+        if let Some(addr) = cands[k] {
+            neb_addr[k] = addr;
+            valid_flag[k] = map_scu[addr].GET_COD() != 0 && map_scu[addr].GET_IF() == 0;
+        }
+    }
+}
+
+#[inline]
+fn same_motion(
+    refi: &[[i8; MAX_NUM_MVP]; REFP_NUM],
+    mvp: &[[[i16; MV_D]; MAX_NUM_MVP]; REFP_NUM],
+    cnt: usize,
+    cand_refi: &[i8; REFP_NUM],
+    cand_mv: &[[i16; MV_D]; REFP_NUM],
+) -> bool {
+    (0..cnt).any(|i| {
+        refi[REFP_0][i] == cand_refi[REFP_0]
+            && mvp[REFP_0][i] == cand_mv[REFP_0]
+            && refi[REFP_1][i] == cand_refi[REFP_1]
+            && mvp[REFP_1][i] == cand_mv[REFP_1]
+    })
+}
+
+/* merge candidate list: spatial, temporal, history, combined
+bi-predictive and zero motion candidates */
+pub(crate) fn evc_get_motion_merge_main(
+    poc: i32,
+    slice_type: SliceType,
+    scup: usize,
+    map_refi: &[[i8; REFP_NUM]],
+    map_mv: &[[[i16; MV_D]; REFP_NUM]],
+    refp: &Vec<Vec<EvcRefP>>,
+    cuw: usize,
+    cuh: usize,
+    w_scu: usize,
+    h_scu: usize,
+    refi: &mut [[i8; MAX_NUM_MVP]; REFP_NUM],
+    mvp: &mut [[[i16; MV_D]; MAX_NUM_MVP]; REFP_NUM],
+    map_scu: &[MCU],
+    avail_lr: u16,
+    history_buffer: &EvcHistoryBuffer,
+    sh: &EvcSh,
+    log2_max_cuwh: u8,
+) {
+    const PRIORITY_LIST0: [usize; 20] =
+        [0, 1, 0, 2, 1, 2, 0, 3, 1, 3, 2, 3, 0, 4, 1, 4, 2, 4, 3, 4];
+    const PRIORITY_LIST1: [usize; 20] =
+        [1, 0, 2, 0, 2, 1, 3, 0, 3, 1, 3, 2, 4, 0, 4, 1, 4, 2, 4, 3];
+
+    let x_scu = scup % w_scu;
+    let y_scu = scup / w_scu;
+    let scuw = cuw >> MIN_CU_LOG2;
+    let scuh = cuh >> MIN_CU_LOG2;
+    let small_cu = cuw * cuh <= 32;
+    let max_num_cand = if small_cu {
+        MAX_NUM_MVP_SMALL_CU
+    } else {
+        MAX_NUM_MVP
+    };
+    let is_b = slice_type == SliceType::EVC_ST_B;
+    let bi_allowed = cuw + cuh > 12;
+    let mut neb_addr = [0; MAX_NUM_POSSIBLE_SCAND];
+    let mut valid_flag = [false; MAX_NUM_POSSIBLE_SCAND];
+    let mut cnt = 0;
+
+    for lidx in 0..REFP_NUM {
+        for k in 0..MAX_NUM_MVP {
+            refi[lidx][k] = REFI_INVALID;
+            mvp[lidx][k] = [0; MV_D];
+        }
+    }
+
+    /* motion of a neighbouring or history entry as a candidate */
+    let cand_from = |src_refi: &[i8; REFP_NUM], src_mv: &[[i16; MV_D]; REFP_NUM]| {
+        let mut cand_refi = [REFI_INVALID; REFP_NUM];
+        let mut cand_mv = [[0i16; MV_D]; REFP_NUM];
+        if REFI_IS_VALID(src_refi[REFP_0]) {
+            cand_refi[REFP_0] = src_refi[REFP_0];
+        }
+        cand_mv[REFP_0] = src_mv[REFP_0];
+        if is_b && (!REFI_IS_VALID(src_refi[REFP_0]) || bi_allowed) {
+            if REFI_IS_VALID(src_refi[REFP_1]) {
+                cand_refi[REFP_1] = src_refi[REFP_1];
+            }
+            cand_mv[REFP_1] = src_mv[REFP_1];
+        }
+        (cand_refi, cand_mv)
+    };
+
+    /* spatial candidates */
+    evc_check_motion_availability(
+        scup,
+        cuw,
+        cuh,
+        w_scu,
+        h_scu,
+        &mut neb_addr,
+        &mut valid_flag,
+        map_scu,
+        avail_lr,
+    );
+    for k in 0..MAX_NUM_POSSIBLE_SCAND {
+        if valid_flag[k] {
+            let (cand_refi, cand_mv) = cand_from(&map_refi[neb_addr[k]], &map_mv[neb_addr[k]]);
+            if cnt == 0 || !same_motion(refi, mvp, cnt, &cand_refi, &cand_mv) {
+                for lidx in 0..REFP_NUM {
+                    refi[lidx][cnt] = cand_refi[lidx];
+                    mvp[lidx][cnt] = cand_mv[lidx];
+                }
+                cnt += 1;
+            }
+        }
+        if cnt == max_num_cand - 1 {
+            break;
+        }
+    }
+
+    /* temporal candidate: center, bottom and right (left) collocated positions */
+    if cnt < max_num_cand {
+        let log2_max_cuwh = log2_max_cuwh as usize;
+        let mut col_pos = [None; 3];
+        col_pos[0] = Some(((y_scu + (scuh >> 1)) & !1) * w_scu + ((x_scu + (scuw >> 1)) & !1));
+        if y_scu + scuh < h_scu
+            && ((y_scu + scuh) << MIN_CU_LOG2) >> log2_max_cuwh
+                == ((y_scu + scuh - 1) << MIN_CU_LOG2) >> log2_max_cuwh
+        {
+            let x = if avail_lr == LR_01 {
+                x_scu
+            } else {
+                x_scu + scuw - 1
+            };
+            col_pos[1] = Some(((y_scu + scuh) & !1) * w_scu + (x & !1));
+        }
+        if avail_lr == LR_01 {
+            if x_scu > 0
+                && (x_scu << MIN_CU_LOG2) >> log2_max_cuwh
+                    == ((x_scu - 1) << MIN_CU_LOG2) >> log2_max_cuwh
+            {
+                col_pos[2] = Some(((y_scu + scuh - 1) & !1) * w_scu + ((x_scu - 1) & !1));
+            }
+        } else if x_scu + scuw < w_scu
+            && ((x_scu + scuw) << MIN_CU_LOG2) >> log2_max_cuwh
+                == ((x_scu + scuw - 1) << MIN_CU_LOG2) >> log2_max_cuwh
+        {
+            col_pos[2] = Some(((y_scu + scuh - 1) & !1) * w_scu + ((x_scu + scuw) & !1));
+        }
+
+        for c_scu in col_pos.iter().flatten() {
+            let mut mvc = [[0i16; MV_D]; REFP_NUM];
+            let avail_idx = evc_get_mv_collocated(
+                refp, poc, *c_scu, scup, w_scu, h_scu, &mut mvc, slice_type, sh,
+            );
+            if avail_idx == 0 {
+                continue;
+            }
+
+            let mut cand_refi = [REFI_INVALID; REFP_NUM];
+            let mut cand_mv = [[0i16; MV_D]; REFP_NUM];
+            let l0_avail = avail_idx & 1 != 0;
+            cand_refi[REFP_0] = if l0_avail { 0 } else { REFI_INVALID };
+            cand_mv[REFP_0] = mvc[REFP_0];
+            if is_b && !(l0_avail && !bi_allowed) {
+                cand_refi[REFP_1] = if avail_idx & 2 != 0 { 0 } else { REFI_INVALID };
+                cand_mv[REFP_1] = mvc[REFP_1];
+            }
+
+            if !same_motion(refi, mvp, cnt, &cand_refi, &cand_mv) {
+                for lidx in 0..REFP_NUM {
+                    refi[lidx][cnt] = cand_refi[lidx];
+                    mvp[lidx][cnt] = cand_mv[lidx];
+                }
+                cnt += 1;
+                break;
+            }
+        }
+        if cnt >= max_num_cand {
+            return;
+        }
+    }
+
+    /* history-based candidates */
+    if cnt < max_num_cand {
+        let max_checked = std::cmp::min(
+            history_buffer.currCnt,
+            if small_cu {
+                ALLOWED_CHECKED_NUM_SMALL_CU
+            } else {
+                ALLOWED_CHECKED_NUM
+            },
+        );
+        let mut k = 3;
+        while k <= max_checked {
+            let i = history_buffer.currCnt - k;
+            let (cand_refi, cand_mv) = cand_from(
+                &history_buffer.history_refi_table[i],
+                &history_buffer.history_mv_table[i],
+            );
+            if cnt == 0 || !same_motion(refi, mvp, cnt, &cand_refi, &cand_mv) {
+                for lidx in 0..REFP_NUM {
+                    refi[lidx][cnt] = cand_refi[lidx];
+                    mvp[lidx][cnt] = cand_mv[lidx];
+                }
+                cnt += 1;
+                if cnt >= max_num_cand {
+                    return;
+                }
+            }
+            k += 4;
+        }
+    }
+
+    /* combined bi-predictive candidates */
+    if is_b && bi_allowed {
+        let num = cnt * cnt.saturating_sub(1);
+        for i in 0..num {
+            if cnt == max_num_cand {
+                break;
+            }
+            let i0 = PRIORITY_LIST0[i];
+            let i1 = PRIORITY_LIST1[i];
+            if REFI_IS_VALID(refi[REFP_0][i0]) && REFI_IS_VALID(refi[REFP_1][i1]) {
+                refi[REFP_0][cnt] = refi[REFP_0][i0];
+                mvp[REFP_0][cnt] = mvp[REFP_0][i0];
+                refi[REFP_1][cnt] = refi[REFP_1][i1];
+                mvp[REFP_1][cnt] = mvp[REFP_1][i1];
+                cnt += 1;
+            }
+        }
+    }
+
+    /* zero motion candidates */
+    for k in cnt..max_num_cand {
+        refi[REFP_0][k] = 0;
+        mvp[REFP_0][k] = [0; MV_D];
+        refi[REFP_1][k] = if is_b && bi_allowed { 0 } else { REFI_INVALID };
+        mvp[REFP_1][k] = [0; MV_D];
+    }
+}
+
+/* default AMVP motion when the candidate neighbour has no motion in the list */
+fn evc_get_default_motion(
+    neb_addr: &[usize; MAX_NUM_POSSIBLE_SCAND],
+    valid_flag: &[bool; MAX_NUM_POSSIBLE_SCAND],
+    cur_refi: i8,
+    lidx: usize,
+    map_refi: &[[i8; REFP_NUM]],
+    map_mv: &[[[i16; MV_D]; REFP_NUM]],
+    history_buffer: &EvcHistoryBuffer,
+    hmvp_flag: bool,
+) -> (i8, [i16; MV_D]) {
+    /* prefer the reference index of the current block, then any valid one */
+    for same_refi in [true, false] {
+        for k in 0..2 {
+            if valid_flag[k] {
+                let refi = map_refi[neb_addr[k]][lidx];
+                if (same_refi && refi == cur_refi) || (!same_refi && REFI_IS_VALID(refi)) {
+                    return (refi, map_mv[neb_addr[k]][lidx]);
+                }
+            }
+        }
+    }
+
+    if hmvp_flag {
+        let num = std::cmp::min(history_buffer.currCnt, ALLOWED_CHECKED_AMVP_NUM);
+        for same_refi in [true, false] {
+            for k in 1..=num {
+                let i = history_buffer.currCnt - k;
+                let refi = history_buffer.history_refi_table[i][lidx];
+                if (same_refi && refi == cur_refi) || (!same_refi && REFI_IS_VALID(refi)) {
+                    return (refi, history_buffer.history_mv_table[i][lidx]);
+                }
+            }
+        }
+    }
+
+    (0, [0; MV_D])
+}
+
+/* AMVP predictor of list lidx for reference index cur_refi, taken from the
+neighbour selected by the motion vector resolution index */
+pub(crate) fn evc_get_motion_from_mvr(
+    mvr_idx: u8,
+    poc: i32,
+    scup: usize,
+    lidx: usize,
+    cur_refi: i8,
+    num_refp: u8,
+    map_mv: &[[[i16; MV_D]; REFP_NUM]],
+    map_refi: &[[i8; REFP_NUM]],
+    refp: &Vec<Vec<EvcRefP>>,
+    cuw: usize,
+    cuh: usize,
+    w_scu: usize,
+    h_scu: usize,
+    mvp: &mut [i16; MV_D],
+    map_scu: &[MCU],
+    avail_lr: u16,
+    history_buffer: &EvcHistoryBuffer,
+    hmvp_flag: bool,
+) {
+    let mut neb_addr = [0; MAX_NUM_POSSIBLE_SCAND];
+    let mut valid_flag = [false; MAX_NUM_POSSIBLE_SCAND];
+    let mut ratio = [0i32; MAX_NUM_REF_PICS];
+
+    evc_check_motion_availability(
+        scup,
+        cuw,
+        cuh,
+        w_scu,
+        h_scu,
+        &mut neb_addr,
+        &mut valid_flag,
+        map_scu,
+        avail_lr,
+    );
+    let (default_refi, default_mv) = evc_get_default_motion(
+        &neb_addr,
+        &valid_flag,
+        cur_refi,
+        lidx,
+        map_refi,
+        map_mv,
+        history_buffer,
+        hmvp_flag,
+    );
+
+    let dpoc_cur = poc - refp[cur_refi as usize][lidx].poc as i32;
+    for i in 0..num_refp as usize {
+        let dpoc = poc - refp[i][lidx].poc as i32;
+        ratio[i] = if dpoc != 0 {
+            (dpoc_cur << 5) / dpoc
+        } else {
+            1 << 5
+        };
+    }
+
+    let cand = mvr_idx as usize;
+    let (refi, mv) = if valid_flag[cand] && REFI_IS_VALID(map_refi[neb_addr[cand]][lidx]) {
+        (map_refi[neb_addr[cand]][lidx], map_mv[neb_addr[cand]][lidx])
+    } else {
+        (default_refi, default_mv)
+    };
+    if refi == cur_refi {
+        *mvp = mv;
+    } else {
+        mvp[MV_X] = scaling_mv(ratio[refi as usize], mv[MV_X]);
+        mvp[MV_Y] = scaling_mv(ratio[refi as usize], mv[MV_Y]);
+    }
+
+    evc_round_mv_to_mvr(mvp, mvr_idx);
+}
+
+/* rounding of a motion vector to the precision of the motion vector
+resolution index, halves away from zero */
+pub(crate) fn evc_round_mv_to_mvr(mv: &mut [i16], mvr_idx: u8) {
+    let rounding = if mvr_idx > 0 { 1 << (mvr_idx - 1) } else { 0 };
+    for c in 0..MV_D {
+        let v = mv[c] as i32;
+        mv[c] = clip_s16(if v >= 0 {
+            ((v + rounding) >> mvr_idx) << mvr_idx
+        } else {
+            -(((rounding - v) >> mvr_idx) << mvr_idx)
+        });
+    }
+}
+
+/* number of groups of MMVD base predictions a CU may select from */
+#[inline]
+pub(crate) fn evc_mmvd_num_groups(mmvd_group_enable_flag: bool, cuw: usize, cuh: usize) -> usize {
+    if mmvd_group_enable_flag && cuw * cuh > MMVD_GRP_MIN_CU_SIZE {
+        MMVD_GRP_NUM
+    } else {
+        1
+    }
+}
+
+/* packed MMVD index of a prediction group, a base merge candidate, a distance
+and a direction */
+#[inline]
+pub(crate) fn evc_mmvd_idx(
+    group_idx: usize,
+    merge_idx: usize,
+    dist_idx: usize,
+    dir_idx: usize,
+) -> u16 {
+    (((group_idx * MMVD_BASE_NUM + merge_idx) * MMVD_DIST_NUM + dist_idx) * MMVD_DIR_NUM + dir_idx)
+        as u16
+}
+
+/* prediction group, base merge candidate, distance and direction of a packed
+MMVD index */
+#[inline]
+pub(crate) fn evc_mmvd_idx_split(mmvd_idx: u16) -> (usize, usize, usize, usize) {
+    let idx = mmvd_idx as usize;
+    (
+        idx / (MMVD_BASE_NUM * MMVD_DIST_NUM * MMVD_DIR_NUM),
+        (idx / (MMVD_DIST_NUM * MMVD_DIR_NUM)) % MMVD_BASE_NUM,
+        (idx / MMVD_DIR_NUM) % MMVD_DIST_NUM,
+        idx % MMVD_DIR_NUM,
+    )
+}
+
+/* scales a motion vector component by a POC distance ratio in 1/32 units,
+rounding the magnitude */
+#[inline]
+fn evc_mmvd_scale_mv(v: i32, scale: i32) -> i32 {
+    let v = v * scale;
+    let sign = if v < 0 { -1 } else { 1 };
+    EVC_CLIP3(-32768, 32767, sign * ((v.abs() + 16) >> 5))
+}
+
+/* scales a motion vector component of a single-list P candidate by a POC
+distance ratio in 1/32 units; the sign is dropped as in the reference */
+#[inline]
+fn evc_mmvd_scale_mv_p(v: i32, scale: i32) -> i32 {
+    let v = (v * scale).abs() + 16;
+    if v > 0xfffff {
+        0x7fff
+    } else {
+        v >> 5
+    }
+}
+
+/* scales an MMVD distance by a POC distance ratio in 1/32 units */
+#[inline]
+fn evc_mmvd_scale_dist(dist: i32, num: i32, den: i32) -> i32 {
+    let v = ((num << 5) / den) * dist + 16;
+    EVC_CLIP3(-32768, 32767, if v > 0xfffff { 0x7fff } else { v >> 5 })
+}
+
+/* motion of a packed MMVD index. The group selects the prediction of the base
+merge candidate: in a B slice bi-prediction, then each single list, a missing
+list being made by scaling the other one by the POC distances; in a P slice
+the first reference picture list with different reference pictures. The base
+motion is then moved by the distance in the direction, the distance of the
+list with the nearer reference picture being scaled down and the second list
+being mirrored when its reference picture is on the other side of the current
+picture. The second list is only set in a B slice. */
+pub(crate) fn evc_get_mmvd_motion(
+    poc: i32,
+    slice_type: SliceType,
+    refp: &Vec<Vec<EvcRefP>>,
+    num_refp: &[u8; REFP_NUM],
+    cuw: usize,
+    cuh: usize,
+    srefi: &[[i8; MAX_NUM_MVP]; REFP_NUM],
+    smvp: &[[[i16; MV_D]; MAX_NUM_MVP]; REFP_NUM],
+    mmvd_idx: u16,
+    refi: &mut [i8; REFP_NUM],
+    mv: &mut [[i16; MV_D]; REFP_NUM],
+) {
+    let (group_idx, base_idx, dist_idx, dir_idx) = evc_mmvd_idx_split(mmvd_idx);
+    let is_b = slice_type == SliceType::EVC_ST_B;
+    let ref_poc = |lidx: usize, r: i32| refp[r as usize][lidx].poc as i32;
+
+    /* the second list of a P slice is taken from the first candidate */
+    let l1_idx = if is_b { base_idx } else { 0 };
+    let base_refi = [srefi[REFP_0][base_idx] as i32, srefi[REFP_1][l1_idx] as i32];
+    let base_mv = [
+        [
+            smvp[REFP_0][base_idx][MV_X] as i32,
+            smvp[REFP_0][base_idx][MV_Y] as i32,
+        ],
+        [
+            smvp[REFP_1][l1_idx][MV_X] as i32,
+            smvp[REFP_1][l1_idx][MV_Y] as i32,
+        ],
+    ];
+
+    /* prediction of each group: 0 bi, 1 first list, 2 second list, 3 none */
+    let mut full_refi = base_refi;
+    let mut full_mv = base_mv;
+    let mut p_refi = base_refi[REFP_0];
+    let mut p_mv = base_mv[REFP_0];
+    let mut types = match (base_refi[REFP_0] != -1, base_refi[REFP_1] != -1) {
+        (true, true) => [0, 1, 2],
+        (false, false) => [3, 3, 3],
+        (src_valid, _) => {
+            let (src, dst) = if src_valid {
+                (REFP_0, REFP_1)
+            } else {
+                (REFP_1, REFP_0)
+            };
+            if src_valid && slice_type == SliceType::EVC_ST_P {
+                let (x, y) = (base_mv[REFP_0][MV_X], base_mv[REFP_0][MV_Y]);
+                let r0 = base_refi[REFP_0];
+                let n = num_refp[REFP_0];
+                let group_refi = [
+                    r0,
+                    if n == 1 { r0 } else { (r0 == 0) as i32 },
+                    if n > 2 {
+                        if r0 < 2 {
+                            2
+                        } else {
+                            1
+                        }
+                    } else {
+                        r0
+                    },
+                ];
+                p_refi = group_refi[group_idx];
+                p_mv = match group_idx {
+                    0 => [x, y],
+                    1 if n == 1 => [x + 3, y],
+                    2 if n <= 2 => [x - 3, y],
+                    _ => {
+                        let scale =
+                            ((poc - ref_poc(REFP_0, r0)) << 5) / (poc - ref_poc(REFP_0, p_refi));
+                        [evc_mmvd_scale_mv_p(x, scale), evc_mmvd_scale_mv_p(y, scale)]
+                    }
+                };
+                [1, 1, 1]
+            } else {
+                let dpoc = poc - ref_poc(src, base_refi[src]);
+                let r = if num_refp[dst] > 1 && ref_poc(dst, 1) - poc == dpoc {
+                    1
+                } else {
+                    0
+                };
+                let scale = ((poc - ref_poc(dst, r)) << 5) / dpoc;
+                full_refi[dst] = r;
+                for c in 0..MV_D {
+                    full_mv[dst][c] = evc_mmvd_scale_mv(base_mv[src][c], scale);
+                }
+                if src_valid {
+                    [1, 0, 2]
+                } else {
+                    [2, 0, 1]
+                }
+            }
+        }
+    };
+    if cuw * cuh <= MMVD_GRP_MIN_CU_SIZE {
+        types[0] = 1;
+    }
+
+    /* lists that are not predicted keep the motion vector of the base */
+    let mut cur_refi = base_refi;
+    let mut cur_mv = base_mv;
+    match types[group_idx] {
+        0 => {
+            cur_refi = full_refi;
+            cur_mv = full_mv;
+        }
+        1 => {
+            if slice_type == SliceType::EVC_ST_P {
+                cur_refi[REFP_0] = p_refi;
+                cur_mv[REFP_0] = p_mv;
+            } else {
+                cur_refi[REFP_0] = full_refi[REFP_0];
+                cur_mv[REFP_0] = full_mv[REFP_0];
+            }
+            cur_refi[REFP_1] = -1;
+        }
+        2 => {
+            cur_refi[REFP_0] = -1;
+            cur_refi[REFP_1] = full_refi[REFP_1];
+            cur_mv[REFP_1] = full_mv[REFP_1];
+        }
+        _ => cur_refi = [-1, -1],
+    }
+
+    let bi = cur_refi[REFP_0] != -1 && cur_refi[REFP_1] != -1;
+    let mut sign = 1;
+    if is_b && bi {
+        let dpoc0 = ref_poc(REFP_0, cur_refi[REFP_0]) - poc;
+        let dpoc1 = poc - ref_poc(REFP_1, cur_refi[REFP_1]);
+        sign = if dpoc0 * dpoc1 <= 0 { 1 } else { -1 };
+    }
+
+    let dist = evc_tbl_mmvd_dist[dist_idx];
+    let mut mvd = [dist, dist];
+    if bi {
+        let list0_r = (ref_poc(REFP_0, cur_refi[REFP_0]) - poc).abs();
+        let list1_r = (ref_poc(REFP_1, cur_refi[REFP_1]) - poc).abs();
+        if list0_r <= list1_r {
+            mvd[REFP_0] = evc_mmvd_scale_dist(dist, list0_r, list1_r);
+        } else {
+            mvd[REFP_1] = evc_mmvd_scale_dist(dist, list1_r, list0_r);
+        }
+    }
+    mvd[REFP_1] *= sign;
+
+    let num_lists = if is_b { REFP_NUM } else { 1 };
+    for lidx in 0..num_lists {
+        refi[lidx] = cur_refi[lidx] as i8;
+        for c in 0..MV_D {
+            mv[lidx][c] = (cur_mv[lidx][c] + evc_tbl_mmvd_dir[dir_idx][c] * mvd[lidx]) as i16;
+        }
+    }
+}
+
+/* reference index of a list whose motion vector difference is not signalled */
+pub(crate) fn evc_get_first_refi(
+    scup: usize,
+    lidx: usize,
+    map_refi: &[[i8; REFP_NUM]],
+    map_mv: &[[[i16; MV_D]; REFP_NUM]],
+    cuw: usize,
+    cuh: usize,
+    w_scu: usize,
+    h_scu: usize,
+    map_scu: &[MCU],
+    mvr_idx: u8,
+    avail_lr: u16,
+    history_buffer: &EvcHistoryBuffer,
+    hmvp_flag: bool,
+) -> i8 {
+    let mut neb_addr = [0; MAX_NUM_POSSIBLE_SCAND];
+    let mut valid_flag = [false; MAX_NUM_POSSIBLE_SCAND];
+
+    evc_check_motion_availability(
+        scup,
+        cuw,
+        cuh,
+        w_scu,
+        h_scu,
+        &mut neb_addr,
+        &mut valid_flag,
+        map_scu,
+        avail_lr,
+    );
+    let (default_refi, _) = evc_get_default_motion(
+        &neb_addr,
+        &valid_flag,
+        0,
+        lidx,
+        map_refi,
+        map_mv,
+        history_buffer,
+        hmvp_flag,
+    );
+
+    let cand = mvr_idx as usize;
+    if valid_flag[cand] && REFI_IS_VALID(map_refi[neb_addr[cand]][lidx]) {
+        map_refi[neb_addr[cand]][lidx]
+    } else {
+        default_refi
+    }
+}
+
+/* rounding of a motion value to 1/2^shift precision, halves toward zero */
+#[inline]
+pub(crate) fn evc_rounding_s32(value: i32, shift: u8) -> i32 {
+    let offset = if shift > 0 { 1 << (shift - 1) } else { 0 };
+    (value + offset - (value >= 0) as i32) >> shift
+}
+
+#[inline]
+fn clip_s16(value: i32) -> i16 {
+    EVC_CLIP3(-32768, 32767, value) as i16
+}
+
+/* motion change per sample of an affine model in 1/512 sample units,
+returned as [dhx, dhy, dvx, dvy] along the width (h) and the height (v) */
+pub(crate) fn evc_affine_mv_gradient(
+    mv: &[[i16; MV_D]; VER_NUM],
+    cuw: usize,
+    cuh: usize,
+    vertex_num: usize,
+) -> [i32; 4] {
+    let log2_cuw = CONV_LOG2(cuw);
+    let dhx = ((mv[1][MV_X] as i32 - mv[0][MV_X] as i32) << 7) >> log2_cuw;
+    let dhy = ((mv[1][MV_Y] as i32 - mv[0][MV_Y] as i32) << 7) >> log2_cuw;
+    if vertex_num == 3 {
+        let log2_cuh = CONV_LOG2(cuh);
+        let dvx = ((mv[2][MV_X] as i32 - mv[0][MV_X] as i32) << 7) >> log2_cuh;
+        let dvy = ((mv[2][MV_Y] as i32 - mv[0][MV_Y] as i32) << 7) >> log2_cuh;
+        [dhx, dhy, dvx, dvy]
+    } else {
+        [dhx, dhy, -dhy, dhx]
+    }
+}
+
+/* motion of an affine model at sample (x, y) relative to its top-left corner,
+in 1/512 sample units */
+#[inline]
+fn affine_mv_at(mv0: &[i16; MV_D], d: &[i32; 4], x: i32, y: i32) -> [i32; MV_D] {
+    [
+        ((mv0[MV_X] as i32) << 7) + d[0] * x + d[2] * y,
+        ((mv0[MV_Y] as i32) << 7) + d[1] * x + d[3] * y,
+    ]
+}
+
+/* motion of an affine CU at its center, stored to the history buffer */
+pub(crate) fn evc_affine_center_mv(
+    mv: &[[i16; MV_D]; VER_NUM],
+    cuw: usize,
+    cuh: usize,
+    vertex_num: usize,
+) -> [i16; MV_D] {
+    let d = evc_affine_mv_gradient(mv, cuw, cuh, vertex_num);
+    let c = affine_mv_at(&mv[0], &d, (cuw >> 1) as i32, (cuh >> 1) as i32);
+    [
+        clip_s16(evc_rounding_s32(c[MV_X], 7)),
+        clip_s16(evc_rounding_s32(c[MV_Y], 7)),
+    ]
+}
+
+/* packed size and position of the SCU inside its CU, kept in map_affine */
+#[inline]
+pub(crate) fn evc_affine_map_info(log2_cuw: u8, log2_cuh: u8, x_off: usize, y_off: usize) -> u32 {
+    log2_cuw as u32 | (log2_cuh as u32) << 8 | (x_off as u32) << 16 | (y_off as u32) << 24
+}
+
+/* control point motion of the current CU inherited from the affine model
+of the neighbouring CU containing neb */
+pub(crate) fn evc_derive_affine_model_mv(
+    scup: usize,
+    neb: usize,
+    lidx: usize,
+    map_mv: &[[[i16; MV_D]; REFP_NUM]],
+    cuw: usize,
+    cuh: usize,
+    w_scu: usize,
+    vertex_num: usize,
+    map_affine: &[u32],
+    log2_max_cuwh: u8,
+) -> [[i16; MV_D]; VER_NUM] {
+    let info = map_affine[neb];
+    let log2_neb_w = (info & 0xFF) as u8;
+    let log2_neb_h = ((info >> 8) & 0xFF) as u8;
+    let x_off = ((info >> 16) & 0xFF) as usize;
+    let y_off = ((info >> 24) & 0xFF) as usize;
+
+    let tl = neb - x_off - y_off * w_scu;
+    let tr = tl + ((1 << log2_neb_w) >> MIN_CU_LOG2) - 1;
+    let bl = tl + (((1 << log2_neb_h) >> MIN_CU_LOG2) - 1) * w_scu;
+    let br = bl + tr - tl;
+
+    let neb_x = ((tl % w_scu) << MIN_CU_LOG2) as i32;
+    let neb_y = ((tl / w_scu) << MIN_CU_LOG2) as i32;
+    let cur_x = ((scup % w_scu) << MIN_CU_LOG2) as i32;
+    let cur_y = ((scup / w_scu) << MIN_CU_LOG2) as i32;
+    let neb_h = 1 << log2_neb_h;
+
+    /* a neighbour above the CTU row only provides its bottom motion */
+    let (mv0, mv1, base_y, six_param) =
+        if (neb_y + neb_h) % (1 << log2_max_cuwh) == 0 && neb_y + neb_h == cur_y {
+            (map_mv[bl][lidx], map_mv[br][lidx], neb_y + neb_h, false)
+        } else {
+            (map_mv[tl][lidx], map_mv[tr][lidx], neb_y, vertex_num == 3)
+        };
+
+    let dhx = ((mv1[MV_X] as i32 - mv0[MV_X] as i32) << 7) >> log2_neb_w;
+    let dhy = ((mv1[MV_Y] as i32 - mv0[MV_Y] as i32) << 7) >> log2_neb_w;
+    let (dvx, dvy) = if six_param {
+        let mv2 = map_mv[bl][lidx];
+        (
+            ((mv2[MV_X] as i32 - mv0[MV_X] as i32) << 7) >> log2_neb_h,
+            ((mv2[MV_Y] as i32 - mv0[MV_Y] as i32) << 7) >> log2_neb_h,
+        )
+    } else {
+        (-dhy, dhx)
+    };
+
+    let d = [dhx, dhy, dvx, dvy];
+    let dx = cur_x - neb_x;
+    let dy = cur_y - base_y;
+    let derive = |x: i32, y: i32| {
+        let v = affine_mv_at(&mv0, &d, x, y);
+        [
+            clip_s16(evc_rounding_s32(v[MV_X], 7)),
+            clip_s16(evc_rounding_s32(v[MV_Y], 7)),
+        ]
+    };
+
+    let mut mvp = [[0i16; MV_D]; VER_NUM];
+    mvp[0] = derive(dx, dy);
+    mvp[1] = derive(dx + cuw as i32, dy);
+    if vertex_num == 3 {
+        mvp[2] = derive(dx, dy + cuh as i32);
+    }
+    mvp
+}
+
+/* affine merge candidate built from the corner motion of the control
+points cp_idx; None when the corners do not share a reference picture */
+fn evc_derive_affine_constructed_candidate(
+    cuw: usize,
+    cuh: usize,
+    cp_valid: &[bool; 4],
+    cp_mv: &[[[i16; MV_D]; 4]; REFP_NUM],
+    cp_refi: &[[i8; 4]; REFP_NUM],
+    cp_idx: &[usize],
+    model_idx: usize,
+) -> Option<([i8; REFP_NUM], [[[i16; MV_D]; VER_NUM]; REFP_NUM])> {
+    if cp_idx.iter().any(|&i| !cp_valid[i]) {
+        return None;
+    }
+
+    let valid_model = [REFP_0, REFP_1].map(|lidx| {
+        let refi = cp_refi[lidx][cp_idx[0]];
+        REFI_IS_VALID(refi) && cp_idx.iter().all(|&i| cp_refi[lidx][i] == refi)
+    });
+    if !valid_model[REFP_0] && !valid_model[REFP_1] {
+        return None;
+    }
+
+    let mut refi = [REFI_INVALID; REFP_NUM];
+    let mut mv = [[[0i16; MV_D]; VER_NUM]; REFP_NUM];
+    for lidx in 0..REFP_NUM {
+        if !valid_model[lidx] {
+            continue;
+        }
+        refi[lidx] = cp_refi[lidx][cp_idx[0]];
+
+        let mut v = [[0i32; MV_D]; 4];
+        for &i in cp_idx {
+            v[i] = [cp_mv[lidx][i][MV_X] as i32, cp_mv[lidx][i][MV_Y] as i32];
+        }
+        for c in 0..MV_D {
+            match model_idx {
+                1 => v[2][c] = v[0][c] + v[3][c] - v[1][c], /* {LT, RT, RB} */
+                2 => v[1][c] = v[0][c] + v[3][c] - v[2][c], /* {LT, LB, RB} */
+                3 => v[0][c] = v[1][c] + v[2][c] - v[3][c], /* {RT, LB, RB} */
+                _ => {}
+            }
+        }
+        if model_idx == 5 {
+            /* {LT, LB}: derive RT of the 4-parameter model */
+            let shift = CONV_LOG2(cuw) + 7 - CONV_LOG2(cuh);
+            let v1x = (v[0][MV_X] << 7) + ((v[2][MV_Y] - v[0][MV_Y]) << shift);
+            let v1y = (v[0][MV_Y] << 7) - ((v[2][MV_X] - v[0][MV_X]) << shift);
+            v[1] = [evc_rounding_s32(v1x, 7), evc_rounding_s32(v1y, 7)];
+        }
+
+        for i in 0..cp_idx.len() {
+            mv[lidx][i] = [clip_s16(v[i][MV_X]), clip_s16(v[i][MV_Y])];
+        }
+    }
+
+    Some((refi, mv))
+}
+
+/* affine merge candidate list: models inherited from affine neighbours,
+models constructed from the corner motion and zero motion.
+returns the number of control points of each candidate */
+pub(crate) fn evc_get_affine_merge_candidate(
+    poc: i32,
+    slice_type: SliceType,
+    scup: usize,
+    map_refi: &[[i8; REFP_NUM]],
+    map_mv: &[[[i16; MV_D]; REFP_NUM]],
+    refp: &Vec<Vec<EvcRefP>>,
+    cuw: usize,
+    cuh: usize,
+    w_scu: usize,
+    h_scu: usize,
+    avail_lr: u16,
+    mrg_refi: &mut [[i8; REFP_NUM]; AFF_MAX_CAND],
+    mrg_mv: &mut [[[[i16; MV_D]; VER_NUM]; REFP_NUM]; AFF_MAX_CAND],
+    map_scu: &[MCU],
+    map_affine: &[u32],
+    log2_max_cuwh: u8,
+    sh: &EvcSh,
+) -> [usize; AFF_MAX_CAND] {
+    const MODELS: [&[usize]; 6] = [
+        &[0, 1, 2],
+        &[0, 1, 3],
+        &[0, 2, 3],
+        &[1, 2, 3],
+        &[0, 1],
+        &[0, 2],
+    ];
+
+    let x_scu = scup % w_scu;
+    let y_scu = scup / w_scu;
+    let scuw = cuw >> MIN_CU_LOG2;
+    let scuh = cuh >> MIN_CU_LOG2;
+    let is_b = slice_type == SliceType::EVC_ST_B;
+    let mut cp_num = [0usize; AFF_MAX_CAND];
+    let mut cnt = 0;
+
+    let is_inter = |addr: usize| map_scu[addr].GET_COD() != 0 && map_scu[addr].GET_IF() == 0;
+
+    /* inherited candidates */
+    let mut cands = [None; MAX_NUM_POSSIBLE_SCAND];
+    if avail_lr == LR_01 {
+        if x_scu + scuw < w_scu {
+            cands[0] = Some(scup + (scuh - 1) * w_scu + scuw);
+        }
+        if y_scu > 0 {
+            cands[1] = Some(scup - w_scu);
+        }
+        if x_scu > 0 && y_scu > 0 {
+            cands[2] = Some(scup - w_scu - 1);
+        }
+        if x_scu + scuw < w_scu && y_scu + scuh < h_scu {
+            cands[3] = Some(scup + scuh * w_scu + scuw);
+        }
+        if y_scu > 0 && x_scu + scuw < w_scu {
+            cands[4] = Some(scup - w_scu + scuw);
+        }
+    } else {
+        if x_scu > 0 {
+            cands[0] = Some(scup + (scuh - 1) * w_scu - 1);
+        }
+        if y_scu > 0 {
+            cands[1] = Some(scup - w_scu + scuw - 1);
+        }
+        if y_scu > 0 && x_scu + scuw < w_scu {
+            cands[2] = Some(scup - w_scu + scuw);
+        }
+        if x_scu > 0 && y_scu + scuh < h_scu {
+            cands[3] = Some(scup + scuh * w_scu - 1);
+        }
+        if x_scu > 0 && y_scu > 0 {
+            cands[4] = Some(scup - w_scu - 1);
+        }
+    }
+
+    let mut valid = [false; MAX_NUM_POSSIBLE_SCAND];
+    let mut top_left = [0usize; MAX_NUM_POSSIBLE_SCAND];
+    for k in 0..MAX_NUM_POSSIBLE_SCAND {
+        if let Some(addr) = cands[k] {
+            if is_inter(addr) && map_scu[addr].GET_AFF() != 0 {
+                let info = map_affine[addr];
+                valid[k] = true;
+                top_left[k] =
+                    addr - ((info >> 16) & 0xFF) as usize - ((info >> 24) & 0xFF) as usize * w_scu;
+            }
+        }
+    }
+    /* prune neighbours belonging to the same CU */
+    if valid[2] && valid[1] && top_left[1] == top_left[2] {
+        valid[2] = false;
+    }
+    if valid[3] && valid[0] && top_left[0] == top_left[3] {
+        valid[3] = false;
+    }
+    if valid[4]
+        && ((valid[0] && top_left[4] == top_left[0]) || (valid[1] && top_left[4] == top_left[1]))
+    {
+        valid[4] = false;
+    }
+
+    for k in 0..MAX_NUM_POSSIBLE_SCAND {
+        if !valid[k] || cnt >= AFF_MAX_CAND {
+            continue;
+        }
+        let neb = cands[k].unwrap();
+        let vertex_num = if map_scu[neb].GET_AFF() == 1 { 2 } else { 3 };
+        for lidx in 0..REFP_NUM {
+            if REFI_IS_VALID(map_refi[neb][lidx]) {
+                mrg_refi[cnt][lidx] = map_refi[neb][lidx];
+                mrg_mv[cnt][lidx] = evc_derive_affine_model_mv(
+                    scup,
+                    neb,
+                    lidx,
+                    map_mv,
+                    cuw,
+                    cuh,
+                    w_scu,
+                    vertex_num,
+                    map_affine,
+                    log2_max_cuwh,
+                );
+            } else {
+                mrg_refi[cnt][lidx] = REFI_INVALID;
+                mrg_mv[cnt][lidx] = [[0; MV_D]; VER_NUM];
+            }
+        }
+        cp_num[cnt] = vertex_num;
+        cnt += 1;
+    }
+
+    /* constructed candidates from the motion at the four corners */
+    if cnt < AFF_MAX_CAND {
+        let mut cp_mv = [[[0i16; MV_D]; 4]; REFP_NUM];
+        let mut cp_refi = [[REFI_INVALID; 4]; REFP_NUM];
+        let mut cp_valid = [false; 4];
+
+        let mut take_spatial = |corner: usize, nebs: &[Option<usize>]| {
+            if let Some(&addr) = nebs.iter().flatten().find(|&&addr| is_inter(addr)) {
+                for lidx in 0..REFP_NUM {
+                    cp_refi[lidx][corner] = map_refi[addr][lidx];
+                    cp_mv[lidx][corner] = map_mv[addr][lidx];
+                }
+                cp_valid[corner] = true;
+            }
+        };
+
+        /* left-top: B2, B3, A2 */
+        take_spatial(
+            0,
+            &[
+                (x_scu > 0 && y_scu > 0).then(|| scup - w_scu - 1),
+                (y_scu > 0).then(|| scup - w_scu),
+                (x_scu > 0).then(|| scup - 1),
+            ],
+        );
+        /* right-top: B0, B1, right */
+        take_spatial(
+            1,
+            &[
+                (y_scu > 0 && x_scu + scuw < w_scu).then(|| scup - w_scu + scuw),
+                (y_scu > 0).then(|| scup - w_scu + scuw - 1),
+                (x_scu + scuw < w_scu).then(|| scup + scuw),
+            ],
+        );
+        /* left-bottom: A0, A1 */
+        if avail_lr == LR_10 || avail_lr == LR_11 {
+            take_spatial(
+                2,
+                &[
+                    (x_scu > 0 && y_scu + scuh < h_scu).then(|| scup + scuh * w_scu - 1),
+                    (x_scu > 0).then(|| scup + (scuh - 1) * w_scu - 1),
+                ],
+            );
+        }
+        /* right-bottom */
+        if avail_lr == LR_01 || avail_lr == LR_11 {
+            take_spatial(
+                3,
+                &[
+                    (x_scu + scuw < w_scu && y_scu + scuh < h_scu)
+                        .then(|| scup + scuh * w_scu + scuw),
+                    (x_scu + scuw < w_scu).then(|| scup + (scuh - 1) * w_scu + scuw),
+                ],
+            );
+        }
+
+        /* bottom corners without a decoded neighbour use the collocated motion
+        when it stays in the current CTU row */
+        let same_ctu_row = y_scu + scuh < h_scu
+            && (y_scu << MIN_CU_LOG2) >> log2_max_cuwh
+                == ((y_scu + scuh) << MIN_CU_LOG2) >> log2_max_cuwh;
+        for corner in 2..4 {
+            let col = if corner == 2 {
+                if avail_lr == LR_10 || avail_lr == LR_11 || x_scu == 0 {
+                    continue;
+                }
+                x_scu - 1
+            } else {
+                if avail_lr == LR_01 || avail_lr == LR_11 || x_scu + scuw >= w_scu {
+                    continue;
+                }
+                x_scu + scuw
+            };
+            if !same_ctu_row {
+                continue;
+            }
+
+            let c_scu = ((y_scu + scuh) & !1) * w_scu + (col & !1);
+            let mut mvc = [[0i16; MV_D]; REFP_NUM];
+            let avail_idx = evc_get_mv_collocated(
+                refp, poc, c_scu, scup, w_scu, h_scu, &mut mvc, slice_type, sh,
+            );
+            for lidx in 0..REFP_NUM {
+                if avail_idx & (1 << lidx) != 0 && (lidx == REFP_0 || is_b) {
+                    cp_refi[lidx][corner] = 0;
+                    cp_mv[lidx][corner] = mvc[lidx];
+                } else {
+                    cp_refi[lidx][corner] = REFI_INVALID;
+                    cp_mv[lidx][corner] = [0; MV_D];
+                }
+            }
+            cp_valid[corner] =
+                REFI_IS_VALID(cp_refi[REFP_0][corner]) || REFI_IS_VALID(cp_refi[REFP_1][corner]);
+        }
+
+        for (model_idx, cp_idx) in MODELS.iter().enumerate() {
+            if cnt >= AFF_MAX_CAND {
+                break;
+            }
+            if let Some((refi, mv)) = evc_derive_affine_constructed_candidate(
+                cuw, cuh, &cp_valid, &cp_mv, &cp_refi, cp_idx, model_idx,
+            ) {
+                mrg_refi[cnt] = refi;
+                mrg_mv[cnt] = mv;
+                cp_num[cnt] = cp_idx.len();
+                cnt += 1;
+            }
+        }
+    }
+
+    /* zero motion candidates */
+    for k in cnt..AFF_MAX_CAND {
+        cp_num[k] = 2;
+        mrg_refi[k] = [0, if is_b { 0 } else { REFI_INVALID }];
+        mrg_mv[k] = [[[0; MV_D]; VER_NUM]; REFP_NUM];
+    }
+
+    cp_num
+}
+
+/* affine AMVP candidate list of list lidx for reference index cur_refi */
+pub(crate) fn evc_get_affine_motion_scaling(
+    scup: usize,
+    lidx: usize,
+    cur_refi: i8,
+    map_mv: &[[[i16; MV_D]; REFP_NUM]],
+    map_refi: &[[i8; REFP_NUM]],
+    cuw: usize,
+    cuh: usize,
+    w_scu: usize,
+    h_scu: usize,
+    vertex_num: usize,
+    mvp: &mut [[[i16; MV_D]; VER_NUM]; AFF_MAX_NUM_MVP],
+    map_scu: &[MCU],
+    map_affine: &[u32],
+    log2_max_cuwh: u8,
+) {
+    let x_scu = scup % w_scu;
+    let y_scu = scup / w_scu;
+    let scuw = cuw >> MIN_CU_LOG2;
+    let scuh = cuh >> MIN_CU_LOG2;
+    let mut cnt = 0;
+
+    let same_ref = |addr: usize| {
+        map_scu[addr].GET_COD() != 0
+            && map_scu[addr].GET_IF() == 0
+            && map_refi[addr][lidx] == cur_refi
+    };
+
+    let a0 = (x_scu > 0 && y_scu + scuh < h_scu).then(|| scup + scuh * w_scu - 1);
+    let a1 = (x_scu > 0).then(|| scup + (scuh - 1) * w_scu - 1);
+    let a2 = (x_scu > 0).then(|| scup - 1);
+    let b0 = (y_scu > 0 && x_scu + scuw < w_scu).then(|| scup - w_scu + scuw);
+    let b1 = (y_scu > 0).then(|| scup - w_scu + scuw - 1);
+    let b2 = (x_scu > 0 && y_scu > 0).then(|| scup - w_scu - 1);
+    let b3 = (y_scu > 0).then(|| scup - w_scu);
+    let ar = (x_scu + scuw < w_scu).then(|| scup + scuw);
+    let ar0 = (x_scu + scuw < w_scu && y_scu + scuh < h_scu).then(|| scup + scuh * w_scu + scuw);
+    let ar1 = (x_scu + scuw < w_scu).then(|| scup + (scuh - 1) * w_scu + scuw);
+
+    /* inherited candidates: left, above and right affine neighbours */
+    for group in [&[a0, a1][..], &[b0, b1, b2][..], &[ar0, ar1][..]] {
+        if let Some(&neb) = group
+            .iter()
+            .flatten()
+            .find(|&&addr| same_ref(addr) && map_scu[addr].GET_AFF() != 0)
+        {
+            mvp[cnt] = evc_derive_affine_model_mv(
+                scup,
+                neb,
+                lidx,
+                map_mv,
+                cuw,
+                cuh,
+                w_scu,
+                vertex_num,
+                map_affine,
+                log2_max_cuwh,
+            );
+            cnt += 1;
+            if cnt == AFF_MAX_NUM_MVP {
+                return;
+            }
+        }
+    }
+
+    /* constructed candidate from the corner motion */
+    let corner = |nebs: &[Option<usize>]| {
+        nebs.iter()
+            .flatten()
+            .find(|&&addr| same_ref(addr))
+            .map(|&addr| map_mv[addr][lidx])
+    };
+    let lt = corner(&[b2, b3, a2]);
+    let rt = corner(&[b0, b1, ar]);
+    let lb = corner(&[a0, a1]);
+    let rb = corner(&[ar0, ar1]);
+
+    if let (Some(lt), Some(rt)) = (lt, rt) {
+        if vertex_num == 2 || lb.is_some() || rb.is_some() {
+            let v2 = match (lb, rb) {
+                (Some(lb), _) => lb,
+                (None, Some(rb)) => [
+                    clip_s16(lt[MV_X] as i32 + rb[MV_X] as i32 - rt[MV_X] as i32),
+                    clip_s16(lt[MV_Y] as i32 + rb[MV_Y] as i32 - rt[MV_Y] as i32),
+                ],
+                (None, None) => [0; MV_D],
+            };
+            mvp[cnt] = [lt, rt, v2];
+            cnt += 1;
+            if cnt == AFF_MAX_NUM_MVP {
+                return;
+            }
+        }
+    }
+
+    /* translational candidates from a single corner */
+    for mv in [lb.or(rb), rt, lt].iter().flatten() {
+        mvp[cnt] = [*mv; VER_NUM];
+        cnt += 1;
+        if cnt == AFF_MAX_NUM_MVP {
+            return;
+        }
+    }
+
+    for k in cnt..AFF_MAX_NUM_MVP {
+        mvp[k] = [[0; MV_D]; VER_NUM];
+    }
+}
+
+/* whether the enhanced interpolation filter can predict the whole CU at
+once, together with whether its reference fetch fits the memory bandwidth */
+fn check_eif_applicability_uni(
+    mv: &[[i16; MV_D]; VER_NUM],
+    cuw: usize,
+    cuh: usize,
+    vertex_num: usize,
+) -> (bool, bool) {
+    let [dhx, dhy, dvx, dvy] = evc_affine_mv_gradient(mv, cuw, cuh, vertex_num);
+
+    /* number of reference samples fetched per sample row and column */
+    let fetched = |a: i32, b: i32| {
+        let corners = [0, a, b, a + b];
+        let range = corners.iter().max().unwrap() - corners.iter().min().unwrap();
+        ((range + 511) >> 9) + 2
+    };
+    let nx = fetched(5 * dhx + 2560, 5 * dvx);
+    let ny = fetched(5 * dhy, 5 * dvy + 2560);
+    let mem_band = nx * ny <= 72;
+
+    let ok = dvy >= -512 && 5 * (dhy.abs() + dvy.max(0)) <= 512;
+    (ok, mem_band)
+}
+
+/* sub-block size of a uni-directional affine model */
+fn derive_affine_subblock_size(
+    mv: &[[i16; MV_D]; VER_NUM],
+    cuw: usize,
+    cuh: usize,
+    vertex_num: usize,
+) -> (usize, usize) {
+    const SB_SIZE: [usize; 4] = [32, 16, 8, 8];
+
+    let [dhx, dhy, dvx, dvy] = evc_affine_mv_gradient(mv, cuw, cuh, vertex_num);
+    let size = |d: i32, full: usize| match d {
+        0 => full,
+        1..=4 => SB_SIZE[d as usize - 1],
+        _ => 4,
+    };
+    (
+        size(dhx.abs().max(dhy.abs()), cuw),
+        size(dvx.abs().max(dvy.abs()), cuh),
+    )
+}
+
+/* sub-block size of an affine CU shared by both lists, and whether the
+enhanced interpolation filter fits the memory bandwidth */
+pub(crate) fn derive_affine_subblock_size_bi(
+    mv: &[[[i16; MV_D]; VER_NUM]; REFP_NUM],
+    refi: &[i8; REFP_NUM],
+    cuw: usize,
+    cuh: usize,
+    vertex_num: usize,
+) -> (usize, usize, bool) {
+    let mut sub_w = cuw;
+    let mut sub_h = cuh;
+    let mut mem_band = true;
+
+    for lidx in 0..REFP_NUM {
+        if REFI_IS_VALID(refi[lidx]) {
+            let (w, h) = derive_affine_subblock_size(&mv[lidx], cuw, cuh, vertex_num);
+            sub_w = sub_w.min(w);
+            sub_h = sub_h.min(h);
+        }
+    }
+
+    for lidx in 0..REFP_NUM {
+        if REFI_IS_VALID(refi[lidx]) {
+            let (ok, band) = check_eif_applicability_uni(&mv[lidx], cuw, cuh, vertex_num);
+            mem_band &= band;
+            if !ok {
+                sub_w = sub_w.max(8);
+                sub_h = sub_h.max(8);
+                break;
+            }
+        }
+    }
+
+    (sub_w, sub_h, mem_band)
+}
+
+/* store the sub-block motion of an affine CU to the motion map; the corner
+sub-blocks keep the control point motion for the neighbouring CUs */
+pub(crate) fn evc_set_affine_mvf(
+    scup: usize,
+    cuw: usize,
+    cuh: usize,
+    w_scu: usize,
+    refi: &[i8; REFP_NUM],
+    mv: &[[[i16; MV_D]; VER_NUM]; REFP_NUM],
+    vertex_num: usize,
+    map_mv: &mut [[[i16; MV_D]; REFP_NUM]],
+) {
+    let (sub_w, sub_h, _) = derive_affine_subblock_size_bi(mv, refi, cuw, cuh, vertex_num);
+    let sub_w_scu = sub_w >> MIN_CU_LOG2;
+    let sub_h_scu = sub_h >> MIN_CU_LOG2;
+
+    for lidx in 0..REFP_NUM {
+        if !REFI_IS_VALID(refi[lidx]) {
+            continue;
+        }
+        let d = evc_affine_mv_gradient(&mv[lidx], cuw, cuh, vertex_num);
+
+        for h in (0..cuh).step_by(sub_h) {
+            for w in (0..cuw).step_by(sub_w) {
+                let sub_mv = if w == 0 && h == 0 {
+                    mv[lidx][0]
+                } else if h == 0 && w + sub_w == cuw {
+                    mv[lidx][1]
+                } else if w == 0 && h + sub_h == cuh && vertex_num == 3 {
+                    mv[lidx][2]
+                } else {
+                    let v = affine_mv_at(
+                        &mv[lidx][0],
+                        &d,
+                        (w + (sub_w >> 1)) as i32,
+                        (h + (sub_h >> 1)) as i32,
+                    );
+                    [MV_X, MV_Y].map(|c| {
+                        (EVC_CLIP3(-(1 << 17), (1 << 17) - 1, evc_rounding_s32(v[c], 5)) >> 2)
+                            as i16
+                    })
+                };
+
+                let s = scup + (h >> MIN_CU_LOG2) * w_scu + (w >> MIN_CU_LOG2);
+                for j in 0..sub_h_scu {
+                    for i in 0..sub_w_scu {
+                        map_mv[s + j * w_scu + i][lidx] = sub_mv;
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn evc_derived_chroma_qp_mapping_tables(
+    structChromaQP: &EvcChromaTable,
+) -> Vec<Vec<i8>> {
+    let MAX_QP = MAX_QP_TABLE_SIZE as i8 - 1;
+    let mut qpInVal = [0i8; MAX_QP_TABLE_SIZE_EXT];
+    let mut qpOutVal = [0i8; MAX_QP_TABLE_SIZE_EXT];
+    let mut p_evc_tbl_qp_chroma_dynamic = Vec::with_capacity(2);
+    p_evc_tbl_qp_chroma_dynamic.push(vec![0; MAX_QP_TABLE_SIZE_EXT]);
+    p_evc_tbl_qp_chroma_dynamic.push(vec![0; MAX_QP_TABLE_SIZE_EXT]);
+
+    let startQp = if structChromaQP.global_offset_flag {
+        16
+    } else {
+        -EVC_TBL_CHROMA_QP_OFFSET
+    };
+
+    for i in 0..if structChromaQP.same_qp_table_for_chroma {
+        1
+    } else {
+        2
+    } {
+        qpInVal[0] = startQp + structChromaQP.delta_qp_in_val_minus1[i][0];
+        qpOutVal[0] = startQp
+            + structChromaQP.delta_qp_in_val_minus1[i][0]
+            + structChromaQP.delta_qp_out_val[i][0];
+        for j in 1..=structChromaQP.num_points_in_qp_table_minus1[i] {
+            qpInVal[j] = qpInVal[j - 1] + structChromaQP.delta_qp_in_val_minus1[i][j] + 1;
+            qpOutVal[j] = qpOutVal[j - 1]
+                + (structChromaQP.delta_qp_in_val_minus1[i][j]
+                    + 1
+                    + structChromaQP.delta_qp_out_val[i][j]);
+        }
+
+        for j in 0..=structChromaQP.num_points_in_qp_table_minus1[i] {
+            assert!(qpInVal[j] >= -EVC_TBL_CHROMA_QP_OFFSET && qpInVal[j] <= MAX_QP);
+            assert!(qpOutVal[j] >= -EVC_TBL_CHROMA_QP_OFFSET && qpOutVal[j] <= MAX_QP);
+        }
+
+        p_evc_tbl_qp_chroma_dynamic[i][(EVC_TBL_CHROMA_QP_OFFSET + qpInVal[0]) as usize] =
+            qpOutVal[0];
+        let mut k = qpInVal[0] - 1;
+        while k >= -EVC_TBL_CHROMA_QP_OFFSET {
             p_evc_tbl_qp_chroma_dynamic[i][(EVC_TBL_CHROMA_QP_OFFSET + k) as usize] = EVC_CLIP3(
                 -EVC_TBL_CHROMA_QP_OFFSET,
                 MAX_QP,
@@ -525,6 +2719,26 @@ pub(crate) fn evc_derived_chroma_qp_mapping_tables(
     p_evc_tbl_qp_chroma_dynamic
 }
 
+/* chroma QP mapping tables used when the SPS does not signal them, 4:2:2 and 4:4:4 chroma
+follows the luma QP clipped at 51 instead of the 4:2:0 adjustment */
+pub(crate) fn evc_default_chroma_qp_mapping_tables(
+    chroma_format_idc: u8,
+    iqt: bool,
+) -> Vec<Vec<i8>> {
+    let tbl = if chroma_format_idc == ChromaSampling::Cs422 as u8
+        || chroma_format_idc == ChromaSampling::Cs444 as u8
+    {
+        (0..MAX_QP_TABLE_SIZE_EXT)
+            .map(|i| (i as i8 - EVC_TBL_CHROMA_QP_OFFSET).min(51))
+            .collect()
+    } else if iqt {
+        evc_tbl_qp_chroma_ajudst_main.to_vec()
+    } else {
+        evc_tbl_qp_chroma_ajudst_base.to_vec()
+    };
+    vec![tbl.clone(), tbl]
+}
+
 pub(crate) fn evc_get_split_mode(
     cud: u16,
     cup: u16,
@@ -543,3 +2757,414 @@ pub(crate) fn evc_get_split_mode(
         split_mode_buf.data[cud as usize][shape][pos as usize]
     }
 }
+
+/* initialization of a set of context models from the slice QP, with the
+values of the I or the P/B row of its table */
+pub(crate) fn evc_eco_sbac_ctx_initialize<const N: usize>(
+    model: &mut [SBAC_CTX_MODEL; N],
+    ctx_init_model: &[[i16; N]; 2],
+    slice_type: SliceType,
+    slice_qp: u8,
+) {
+    let qp = EVC_CLIP3(0, 51, slice_qp as i32);
+    let is_inter_slice = !slice_type.IS_INTRA_SLICE();
+    for (m, &init) in model
+        .iter_mut()
+        .zip(ctx_init_model[is_inter_slice as usize].iter())
+    {
+        let init = init as i32;
+        let mut slope = (init & 14) << 4;
+        if init & 1 != 0 {
+            slope = -slope;
+        }
+        let mut offset = ((init >> 4) & 62) << 7;
+        if (init >> 4) & 1 != 0 {
+            offset = -offset;
+        }
+        offset += 4096;
+
+        let mut state = EVC_CLIP3(1, 511, (slope * qp + offset) >> 4);
+        let mps = if state > 256 {
+            state = 512 - state;
+            0
+        } else {
+            1
+        };
+        *m = ((state << 1) + mps) as SBAC_CTX_MODEL;
+    }
+}
+
+/* initialization of the context models of a slice with sps_cm_init_flag */
+pub(crate) fn evc_eco_sbac_ctx_init(sbac_ctx: &mut EvcSbacCtx, slice_type: SliceType, qp: u8) {
+    macro_rules! ctx_init {
+        ($($ctx:ident: $tbl:ident,)*) => {
+            $(evc_eco_sbac_ctx_initialize(&mut sbac_ctx.$ctx, &$tbl, slice_type, qp);)*
+        };
+    }
+    ctx_init!(
+        skip_flag: evc_tbl_ctx_init_skip_flag,
+        mmvd_flag: evc_tbl_ctx_init_mmvd_flag,
+        mmvd_merge_idx: evc_tbl_ctx_init_mmvd_merge_idx,
+        mmvd_dist_idx: evc_tbl_ctx_init_mmvd_dist_idx,
+        mmvd_direction_idx: evc_tbl_ctx_init_mmvd_direction_idx,
+        mmvd_group_idx: evc_tbl_ctx_init_mmvd_group_idx,
+        direct_mode_flag: evc_tbl_ctx_init_direct_mode_flag,
+        merge_mode_flag: evc_tbl_ctx_init_merge_mode_flag,
+        inter_dir: evc_tbl_ctx_init_inter_dir,
+        intra_dir: evc_tbl_ctx_init_intra_dir,
+        intra_luma_pred_mpm_flag: evc_tbl_ctx_init_intra_luma_pred_mpm_flag,
+        intra_luma_pred_mpm_idx: evc_tbl_ctx_init_intra_luma_pred_mpm_idx,
+        intra_dir_c: evc_tbl_ctx_init_intra_dir_c,
+        pred_mode: evc_tbl_ctx_init_pred_mode,
+        mode_cons: evc_tbl_ctx_init_mode_cons,
+        refi: evc_tbl_ctx_init_refi,
+        merge_idx: evc_tbl_ctx_init_merge_idx,
+        mvp_idx: evc_tbl_ctx_init_mvp_idx,
+        affine_mvp_idx: evc_tbl_ctx_init_affine_mvp_idx,
+        mvr_idx: evc_tbl_ctx_init_mvr_idx,
+        bi_idx: evc_tbl_ctx_init_bi_idx,
+        mvd: evc_tbl_ctx_init_mvd,
+        cbf_all: evc_tbl_ctx_init_cbf_all,
+        cbf_luma: evc_tbl_ctx_init_cbf_luma,
+        cbf_cb: evc_tbl_ctx_init_cbf_cb,
+        cbf_cr: evc_tbl_ctx_init_cbf_cr,
+        run: evc_tbl_ctx_init_run,
+        last: evc_tbl_ctx_init_last,
+        level: evc_tbl_ctx_init_level,
+        btt_split_flag: evc_tbl_ctx_init_btt_split_flag,
+        btt_split_dir: evc_tbl_ctx_init_btt_split_dir,
+        btt_split_type: evc_tbl_ctx_init_btt_split_type,
+        affine_flag: evc_tbl_ctx_init_affine_flag,
+        affine_mode: evc_tbl_ctx_init_affine_mode,
+        affine_mrg: evc_tbl_ctx_init_affine_mrg,
+        affine_mvd_flag: evc_tbl_ctx_init_affine_mvd_flag,
+        suco_flag: evc_tbl_ctx_init_suco_flag,
+        alf_ctb_flag: evc_tbl_ctx_init_alf_ctb_flag,
+        split_cu_flag: evc_tbl_ctx_init_split_cu_flag,
+        delta_qp: evc_tbl_ctx_init_delta_qp,
+        ats_mode: evc_tbl_ctx_init_ats_mode,
+        ats_inter_flag: evc_tbl_ctx_init_ats_inter_flag,
+        ats_inter_quad_flag: evc_tbl_ctx_init_ats_inter_quad_flag,
+        ats_inter_hor_flag: evc_tbl_ctx_init_ats_inter_hor_flag,
+        ats_inter_pos_flag: evc_tbl_ctx_init_ats_inter_pos_flag,
+        coeff_abs_level_greaterAB_flag: evc_tbl_ctx_init_coeff_abs_level_greaterAB_flag,
+        last_sig_coeff_x_prefix: evc_tbl_ctx_init_last_sig_coeff_x_prefix,
+        last_sig_coeff_y_prefix: evc_tbl_ctx_init_last_sig_coeff_y_prefix,
+    );
+    for (set, models) in sbac_ctx.sig_coeff_flag.iter_mut().enumerate() {
+        let tbl = [
+            evc_tbl_ctx_init_sig_coeff_flag[0][set],
+            evc_tbl_ctx_init_sig_coeff_flag[1][set],
+        ];
+        evc_eco_sbac_ctx_initialize(models, &tbl, slice_type, qp);
+    }
+}
+
+/* contexts of cu_skip_flag, pred_mode_flag and affine_flag from the upper
+neighbour and the left and right neighbours of the bottom row of the CU. they
+all use the first context without sps_cm_init_flag */
+pub(crate) fn evc_get_ctx_some_flags(
+    x_scu: u16,
+    y_scu: u16,
+    cuw: u16,
+    cuh: u16,
+    w_scu: u16,
+    map_scu: &[MCU],
+    sps_cm_init_flag: bool,
+) -> [usize; NUM_CNID] {
+    let mut ctx = [0; NUM_CNID];
+    if !sps_cm_init_flag {
+        return ctx;
+    }
+
+    let w_scu = w_scu as usize;
+    let (x_scu, y_scu) = (x_scu as usize, y_scu as usize);
+    let (scuw, scuh) = ((cuw >> MIN_CU_LOG2) as usize, (cuh >> MIN_CU_LOG2) as usize);
+    let scup = x_scu + y_scu * w_scu;
+
+    let mut neighbours = [None; 3];
+    if y_scu > 0 {
+        neighbours[0] = Some(map_scu[scup - w_scu]);
+    }
+    if x_scu > 0 {
+        neighbours[1] = Some(map_scu[scup + (scuh - 1) * w_scu - 1]);
+    }
+    if x_scu + scuw < w_scu {
+        neighbours[2] = Some(map_scu[scup + scuw + (scuh - 1) * w_scu]);
+    }
+
+    for scu in neighbours.iter().flatten() {
+        if scu.GET_COD() == 0 {
+            continue;
+        }
+        ctx[CNID_SKIP_FLAG] |= scu.GET_SF() as usize;
+        ctx[CNID_PRED_MODE] += scu.GET_IF() as usize;
+        ctx[CNID_AFFN_FLAG] |= (scu.GET_AFF() != 0) as usize;
+    }
+    ctx[CNID_PRED_MODE] = ctx[CNID_PRED_MODE].min(NUM_CTX_PRED_MODE - 1);
+
+    ctx
+}
+
+/* context of btt_split_flag: without sps_cm_init_flag it has a single
+context, otherwise it counts the upper CU narrower and the left and right CUs
+shorter than the current one, for each class of CU size */
+pub(crate) fn evc_get_ctx_btt_split_flag(
+    x: u16,
+    y: u16,
+    log2_cuw: u8,
+    log2_cuh: u8,
+    w_scu: u16,
+    map_scu: &[MCU],
+    sps_cm_init_flag: bool,
+) -> usize {
+    if !sps_cm_init_flag {
+        return 0;
+    }
+
+    let x_scu = x as usize >> MIN_CU_LOG2;
+    let y_scu = y as usize >> MIN_CU_LOG2;
+    let scuw = 1 << (log2_cuw as usize - MIN_CU_LOG2);
+    let w_scu = w_scu as usize;
+    let scup = x_scu + y_scu * w_scu;
+
+    let mut smaller = 0;
+    if y_scu > 0 {
+        let up = map_scu[scup - w_scu];
+        if up.GET_COD() != 0 && up.GET_LOGW() < log2_cuw {
+            smaller += 1;
+        }
+    }
+    if x_scu > 0 {
+        let left = map_scu[scup - 1];
+        if left.GET_COD() != 0 && left.GET_LOGH() < log2_cuh {
+            smaller += 1;
+        }
+    }
+    if x_scu + scuw < w_scu {
+        let right = map_scu[scup + scuw];
+        if right.GET_COD() != 0 && right.GET_LOGH() < log2_cuh {
+            smaller += 1;
+        }
+    }
+
+    let size_class = evc_tbl_split_flag_ctx[log2_cuw as usize - 2][log2_cuh as usize - 2];
+    smaller.min(2) + 3 * size_class as usize
+}
+
+/* context of btt_split_dir: without sps_cm_init_flag it has a single context,
+otherwise one for each aspect ratio of the CU, from 1:4 to 4:1 */
+pub(crate) fn evc_get_ctx_btt_split_dir(
+    log2_cuw: u8,
+    log2_cuh: u8,
+    sps_cm_init_flag: bool,
+) -> usize {
+    if sps_cm_init_flag {
+        (log2_cuw as i8 - log2_cuh as i8 + 2) as usize
+    } else {
+        0
+    }
+}
+
+/* context of suco_flag: without sps_cm_init_flag it has a single context,
+otherwise square and rectangular CUs have their own context for each size of
+their longer side */
+pub(crate) fn evc_get_ctx_suco_flag(log2_cuw: u8, log2_cuh: u8, sps_cm_init_flag: bool) -> usize {
+    if sps_cm_init_flag {
+        2 * (log2_cuw.max(log2_cuh) as usize - 2) + (log2_cuw != log2_cuh) as usize
+    } else {
+        0
+    }
+}
+
+/* decoded picture hash of every plane, in the first hash.size() bytes of the
+signature. samples are hashed as 16-bit little endian values: MD5 as in the
+reference software, CRC and checksum as defined for bit depths above 8 */
+pub(crate) fn evc_picbuf_signature(frame: &Frame<pel>, hash: PictureHash) -> [[u8; 16]; N_C] {
+    let mut signature = [[0u8; 16]; N_C];
+
+    let num_comp = if frame.chroma_sampling == ChromaSampling::Cs400 {
+        1
+    } else {
+        N_C
+    };
+    for (plane, sign) in frame.planes[..num_comp].iter().zip(signature.iter_mut()) {
+        let (w, h) = (plane.cfg.width, plane.cfg.height);
+        let region = plane.as_region();
+
+        match hash {
+            PictureHash::None => {}
+            PictureHash::MD5 => {
+                let mut ctx = md5::Context::new();
+                let mut row = Vec::with_capacity(w * 2);
+                for y in 0..h {
+                    row.clear();
+                    for &pix in &region[y][..w] {
+                        row.extend_from_slice(&pix.to_le_bytes());
+                    }
+                    ctx.consume(&row);
+                }
+                sign.copy_from_slice(&ctx.compute().0);
+            }
+            PictureHash::CRC => {
+                let mut crc = 0xFFFFu32;
+                let update = |byte: u8, crc: &mut u32| {
+                    for bit in (0..8).rev() {
+                        let msb = (*crc >> 15) & 1;
+                        *crc =
+                            (((*crc << 1) + ((byte as u32 >> bit) & 1)) & 0xFFFF) ^ (msb * 0x1021);
+                    }
+                };
+                for y in 0..h {
+                    for &pix in &region[y][..w] {
+                        update(pix as u8, &mut crc);
+                        update((pix >> 8) as u8, &mut crc);
+                    }
+                }
+                update(0, &mut crc);
+                update(0, &mut crc);
+                sign[..2].copy_from_slice(&(crc as u16).to_be_bytes());
+            }
+            PictureHash::Checksum => {
+                let mut sum = 0u32;
+                for y in 0..h {
+                    for (x, &pix) in region[y][..w].iter().enumerate() {
+                        let xor_mask = ((x & 0xFF) ^ (y & 0xFF) ^ (x >> 8) ^ (y >> 8)) as u32;
+                        sum = sum
+                            .wrapping_add((pix as u32 & 0xFF) ^ xor_mask)
+                            .wrapping_add((pix as u32 >> 8) ^ xor_mask);
+                    }
+                }
+                sign[..4].copy_from_slice(&sum.to_be_bytes());
+            }
+        }
+    }
+
+    signature
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn collocated_mv_clip() {
+        let (x, y) = (16, 32);
+        let (max_x, max_y) = (
+            64 + MV_CLIP_PAD_SIZE as i32 - 1,
+            64 + MV_CLIP_PAD_SIZE as i32 - 1,
+        );
+        let mut mv = [[-200, 10], [400, -300]];
+        evc_clip_mv_pic(x, y, max_x, max_y, &mut mv);
+        /* like the reference decoder, a motion past the left or the top
+        padding is set to the padding size off the block */
+        let pad = MV_CLIP_PAD_SIZE as i16;
+        assert_eq!(mv, [[pad - 16, 10], [max_x as i16 - 16, pad - 32]]);
+
+        let mut mv = [[-20, 30], [0, 0]];
+        evc_clip_mv_pic(x, y, max_x, max_y, &mut mv);
+        assert_eq!(mv, [[-20, 30], [0, 0]]);
+    }
+
+    #[test]
+    fn history_buffer_drops_oldest() {
+        let mut history = EvcHistoryBuffer::default();
+        for i in 0..ALLOWED_CHECKED_NUM + 2 {
+            history.update(&[[i as i16, 0], [0, -(i as i16)]], &[0, REFI_INVALID]);
+        }
+        assert_eq!(history.currCnt, ALLOWED_CHECKED_NUM);
+        assert_eq!(history.history_mv_table[0][REFP_0], [2, 0]);
+        assert_eq!(
+            history.history_mv_table[ALLOWED_CHECKED_NUM - 1][REFP_1],
+            [0, -(ALLOWED_CHECKED_NUM as i16 + 1)]
+        );
+
+        history.reset();
+        assert_eq!(history.currCnt, 0);
+    }
+
+    #[test]
+    fn affine_model_motion() {
+        /* a translation moves every sample alike */
+        let mv = [[20, -8]; VER_NUM];
+        for vertex_num in [2, 3] {
+            assert_eq!(evc_affine_mv_gradient(&mv, 16, 8, vertex_num), [0; 4]);
+            assert_eq!(evc_affine_center_mv(&mv, 16, 8, vertex_num), [20, -8]);
+        }
+
+        /* a zoom from the top-left corner, the 4-parameter model derives the
+        vertical gradient from the horizontal one */
+        let mv = [[0, 0], [16, 0], [0, 8]];
+        for vertex_num in [2, 3] {
+            assert_eq!(
+                evc_affine_mv_gradient(&mv, 16, 8, vertex_num),
+                [128, 0, 0, 128]
+            );
+            assert_eq!(evc_affine_center_mv(&mv, 16, 8, vertex_num), [8, 4]);
+        }
+
+        /* a rotation */
+        let mv = [[0, 0], [0, 16], [0, 0]];
+        assert_eq!(evc_affine_mv_gradient(&mv, 16, 16, 2), [0, 128, -128, 0]);
+        assert_eq!(evc_affine_center_mv(&mv, 16, 16, 2), [-8, 8]);
+    }
+
+    #[test]
+    fn mv_rounding_to_mvr() {
+        let mut mv = [5, -5];
+        evc_round_mv_to_mvr(&mut mv, 0);
+        assert_eq!(mv, [5, -5]);
+
+        /* halves are rounded away from zero */
+        for (mvr_idx, rounded) in [(1, [6, -6]), (2, [4, -4]), (3, [8, -8]), (4, [0, 0])] {
+            let mut mv = [5, -5];
+            evc_round_mv_to_mvr(&mut mv, mvr_idx);
+            assert_eq!(mv, rounded, "mvr_idx {}", mvr_idx);
+        }
+    }
+
+    #[test]
+    fn mmvd_idx() {
+        for group_idx in 0..MMVD_GRP_NUM {
+            for merge_idx in 0..MMVD_BASE_NUM {
+                for dist_idx in 0..MMVD_DIST_NUM {
+                    for dir_idx in 0..MMVD_DIR_NUM {
+                        let idx = evc_mmvd_idx(group_idx, merge_idx, dist_idx, dir_idx);
+                        assert_eq!(
+                            evc_mmvd_idx_split(idx),
+                            (group_idx, merge_idx, dist_idx, dir_idx)
+                        );
+                    }
+                }
+            }
+        }
+
+        assert_eq!(evc_mmvd_num_groups(false, 64, 64), 1);
+        assert_eq!(evc_mmvd_num_groups(true, 8, 4), 1);
+        assert_eq!(evc_mmvd_num_groups(true, 64, 64), MMVD_GRP_NUM);
+    }
+
+    #[test]
+    fn poc_lsb_wraparound() {
+        let sps = EvcSps {
+            log2_max_pic_order_cnt_lsb_minus4: 0,
+            ..Default::default()
+        };
+        let mut poc = EvcPoc {
+            prev_poc_val: 14,
+            ..Default::default()
+        };
+
+        /* the LSBs wrap forward past 16 */
+        evc_poc_lsb_derivation(&sps, 0, 2, &mut poc);
+        assert_eq!((poc.poc_val, poc.prev_poc_val), (18, 18));
+
+        /* and backward to a picture before the wrap, which is not a Tid0
+        picture and leaves the previous POC */
+        evc_poc_lsb_derivation(&sps, 1, 15, &mut poc);
+        assert_eq!((poc.poc_val, poc.prev_poc_val), (15, 18));
+
+        evc_poc_lsb_derivation(&sps, 0, 4, &mut poc);
+        assert_eq!((poc.poc_val, poc.prev_poc_val), (20, 20));
+    }
+}